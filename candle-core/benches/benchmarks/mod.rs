@@ -30,6 +30,7 @@ impl BenchDevice for Device {
                 #[cfg(not(feature = "metal"))]
                 panic!("Metal device without metal feature enabled: {:?}", device)
             }
+            Device::Wgpu(_) => self.synchronize(),
         }
     }
 
@@ -47,6 +48,7 @@ impl BenchDevice for Device {
             }
             Device::Cuda(_) => format!("cuda_{}", name.into()),
             Device::Metal(_) => format!("metal_{}", name.into()),
+            Device::Wgpu(_) => format!("wgpu_{}", name.into()),
         }
     }
 }
@@ -62,6 +64,8 @@ impl BenchDeviceHandler {
             devices.push(Device::new_metal(0)?);
         } else if cfg!(feature = "cuda") {
             devices.push(Device::new_cuda(0)?);
+        } else if cfg!(feature = "wgpu") {
+            devices.push(Device::new_wgpu(0)?);
         }
         devices.push(Device::Cpu);
         Ok(Self { devices })
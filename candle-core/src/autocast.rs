@@ -0,0 +1,114 @@
+//! Mixed precision through autocasting.
+//!
+//! Within an [`autocast`] block, the inputs of some ops such as matmul and convolutions are
+//! converted to a reduced precision dtype whereas the half precision inputs of ops that are more
+//! sensitive to precision, e.g. softmax, normalization layers or losses, get converted to f32.
+//! The conversions are tracked by the autograd graph so gradients flow back in the original
+//! dtype. The context is thread-local and blocks can be nested, the innermost block applies.
+//!
+//! ```rust
+//! use candle_core::{autocast, DType, Device, Tensor};
+//! let a = Tensor::ones((2, 3), DType::F32, &Device::Cpu)?;
+//! let b = Tensor::ones((3, 4), DType::F32, &Device::Cpu)?;
+//! let c = autocast::autocast(DType::F16, || a.matmul(&b))?;
+//! assert_eq!(c.dtype(), DType::F16);
+//! assert_eq!(a.matmul(&b)?.dtype(), DType::F32);
+//! # Ok::<(), candle_core::Error>(())
+//! ```
+use crate::{DType, Result, Tensor};
+use std::cell::RefCell;
+
+/// The ops that are affected by autocasting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AutocastOp {
+    Matmul,
+    Conv,
+    Softmax,
+    Norm,
+    Loss,
+}
+
+/// The configuration for an autocast block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutocastConfig {
+    /// The reduced precision dtype, typically `BF16` or `F16`.
+    pub dtype: DType,
+    /// The ops for which float inputs are converted to `dtype`.
+    pub reduced_ops: Vec<AutocastOp>,
+    /// The ops for which half precision inputs are converted to `F32`.
+    pub f32_ops: Vec<AutocastOp>,
+}
+
+impl AutocastConfig {
+    /// The default configuration, matmul and convolutions run in `dtype` whereas softmax,
+    /// normalization layers and losses run in f32.
+    pub fn new(dtype: DType) -> Self {
+        Self {
+            dtype,
+            reduced_ops: vec![AutocastOp::Matmul, AutocastOp::Conv],
+            f32_ops: vec![AutocastOp::Softmax, AutocastOp::Norm, AutocastOp::Loss],
+        }
+    }
+}
+
+thread_local! {
+    static AUTOCAST: RefCell<Vec<AutocastConfig>> = const { RefCell::new(Vec::new()) };
+}
+
+// Pops the innermost config when leaving a block, including when unwinding.
+struct PopOnDrop;
+
+impl Drop for PopOnDrop {
+    fn drop(&mut self) {
+        AUTOCAST.with(|a| a.borrow_mut().pop());
+    }
+}
+
+/// Runs `f` with autocasting to `dtype` enabled using the default configuration.
+pub fn autocast<F: FnOnce() -> R, R>(dtype: DType, f: F) -> R {
+    autocast_with(AutocastConfig::new(dtype), f)
+}
+
+/// Runs `f` with autocasting enabled using `config`. Passing a config with no ops can be used
+/// to disable autocasting within an outer block.
+pub fn autocast_with<F: FnOnce() -> R, R>(config: AutocastConfig, f: F) -> R {
+    AUTOCAST.with(|a| a.borrow_mut().push(config));
+    let _guard = PopOnDrop;
+    f()
+}
+
+/// The dtype that a float input of `op` with dtype `dtype` should be converted to within the
+/// current autocast block, `None` if no conversion is required.
+pub fn target_dtype(op: AutocastOp, dtype: DType) -> Option<DType> {
+    if !dtype.is_float() {
+        return None;
+    }
+    AUTOCAST.with(|a| {
+        let a = a.borrow();
+        let config = a.last()?;
+        let target = if config.reduced_ops.contains(&op) {
+            config.dtype
+        } else if config.f32_ops.contains(&op) && matches!(dtype, DType::BF16 | DType::F16) {
+            DType::F32
+        } else {
+            return None;
+        };
+        (target != dtype).then_some(target)
+    })
+}
+
+/// Converts `t` to the dtype to be used by `op` within the current autocast block.
+pub fn cast(t: &Tensor, op: AutocastOp) -> Result<Tensor> {
+    match target_dtype(op, t.dtype()) {
+        None => Ok(t.clone()),
+        Some(dtype) => t.to_dtype(dtype),
+    }
+}
+
+// Converts the two inputs of `op`, returns `None` when neither of them has to be converted.
+pub(crate) fn cast2(a: &Tensor, b: &Tensor, op: AutocastOp) -> Result<Option<(Tensor, Tensor)>> {
+    if target_dtype(op, a.dtype()).is_none() && target_dtype(op, b.dtype()).is_none() {
+        return Ok(None);
+    }
+    Ok(Some((cast(a, op)?, cast(b, op)?)))
+}
@@ -139,6 +139,13 @@ pub trait BackendDevice: Sized + std::fmt::Debug + Clone {
 
     fn storage_from_cpu_storage_owned(&self, _: CpuStorage) -> Result<Self::Storage>;
 
+    /// Copies the data to the device without waiting for the copy to complete, the operations
+    /// using the returned storage are ordered after the copy. This falls back to the
+    /// synchronous [`Self::storage_from_cpu_storage`] on backends without asynchronous copies.
+    fn storage_from_cpu_storage_async(&self, s: &CpuStorage) -> Result<Self::Storage> {
+        self.storage_from_cpu_storage(s)
+    }
+
     fn rand_uniform(&self, _: &Shape, _: DType, _: f64, _: f64) -> Result<Self::Storage>;
 
     fn rand_normal(&self, _: &Shape, _: DType, _: f64, _: f64) -> Result<Self::Storage>;
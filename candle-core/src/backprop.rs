@@ -1,7 +1,7 @@
 /// Methods for backpropagation of gradients.
 use crate::op::{BinaryOp, Op, ReduceOp, UnaryOp};
 use crate::{Error, Result, Tensor, TensorId};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 // arg has been reduced to node via reduce_dims, expand it back to arg.
 // This has to handle keepdims.
@@ -32,13 +32,16 @@ impl Tensor {
     /// elements having dependencies on the latter ones, e.g. the first element if any is the
     /// argument.
     /// This assumes that the op graph is a DAG.
-    fn sorted_nodes(&self) -> Vec<&Tensor> {
+    /// When `vars` is set, only the variables it contains are considered as tracking gradients so
+    /// the branches of the graph that cannot reach them are pruned.
+    fn sorted_nodes(&self, vars: Option<&HashSet<TensorId>>) -> Vec<&Tensor> {
         // The vec of sorted nodes is passed as an owned value rather than a mutable reference
         // to get around some lifetime limitations.
         fn walk<'a>(
             node: &'a Tensor,
             nodes: Vec<&'a Tensor>,
             already_seen: &mut HashMap<TensorId, bool>,
+            vars: Option<&HashSet<TensorId>>,
         ) -> (bool, Vec<&'a Tensor>) {
             if let Some(&tg) = already_seen.get(&node.id()) {
                 return (tg, nodes);
@@ -46,7 +49,7 @@ impl Tensor {
             let mut track_grad = false;
             let mut nodes = if node.is_variable() {
                 // Do not call recursively on the "leaf" nodes.
-                track_grad = true;
+                track_grad = vars.is_none_or(|vars| vars.contains(&node.id()));
                 nodes
             } else if node.dtype().is_int() {
                 nodes
@@ -56,11 +59,11 @@ impl Tensor {
                     | Op::ScatterAdd(t1, t2, t3, _)
                     | Op::CustomOp3(t1, t2, t3, _)
                     | Op::WhereCond(t1, t2, t3) => {
-                        let (tg, nodes) = walk(t1, nodes, already_seen);
+                        let (tg, nodes) = walk(t1, nodes, already_seen, vars);
                         track_grad |= tg;
-                        let (tg, nodes) = walk(t2, nodes, already_seen);
+                        let (tg, nodes) = walk(t2, nodes, already_seen, vars);
                         track_grad |= tg;
-                        let (tg, nodes) = walk(t3, nodes, already_seen);
+                        let (tg, nodes) = walk(t3, nodes, already_seen, vars);
                         track_grad |= tg;
                         nodes
                     }
@@ -90,22 +93,34 @@ impl Tensor {
                     | Op::IndexSelect(lhs, rhs, _)
                     | Op::Matmul(lhs, rhs)
                     | Op::SliceScatter0(lhs, rhs, _) => {
-                        let (tg, nodes) = walk(lhs, nodes, already_seen);
+                        let (tg, nodes) = walk(lhs, nodes, already_seen, vars);
                         track_grad |= tg;
-                        let (tg, nodes) = walk(rhs, nodes, already_seen);
+                        let (tg, nodes) = walk(rhs, nodes, already_seen, vars);
                         track_grad |= tg;
                         nodes
                     }
                     Op::Cat(args, _) => args.iter().fold(nodes, |nodes, arg| {
-                        let (tg, nodes) = walk(arg, nodes, already_seen);
+                        let (tg, nodes) = walk(arg, nodes, already_seen, vars);
                         track_grad |= tg;
                         nodes
                     }),
+                    Op::Checkpoint {
+                        inputs,
+                        vars: inner_vars,
+                        ..
+                    } => inputs
+                        .iter()
+                        .chain(inner_vars.iter())
+                        .fold(nodes, |nodes, arg| {
+                            let (tg, nodes) = walk(arg, nodes, already_seen, vars);
+                            track_grad |= tg;
+                            nodes
+                        }),
                     Op::Affine { arg, mul, .. } => {
                         if *mul == 0. {
                             nodes
                         } else {
-                            let (tg, nodes) = walk(arg, nodes, already_seen);
+                            let (tg, nodes) = walk(arg, nodes, already_seen, vars);
                             track_grad |= tg;
                             nodes
                         }
@@ -131,13 +146,13 @@ impl Tensor {
                     | Op::Elu(node, _)
                     | Op::Powf(node, _)
                     | Op::CustomOp1(node, _) => {
-                        let (tg, nodes) = walk(node, nodes, already_seen);
+                        let (tg, nodes) = walk(node, nodes, already_seen, vars);
                         track_grad |= tg;
                         nodes
                     }
                     Op::ToDType(node) => {
                         if node.dtype().is_float() {
-                            let (tg, nodes) = walk(node, nodes, already_seen);
+                            let (tg, nodes) = walk(node, nodes, already_seen, vars);
                             track_grad |= tg;
                             nodes
                         } else {
@@ -155,13 +170,46 @@ impl Tensor {
             }
             (track_grad, nodes)
         }
-        let (_tg, mut nodes) = walk(self, vec![], &mut HashMap::new());
+        let (_tg, mut nodes) = walk(self, vec![], &mut HashMap::new(), vars);
         nodes.reverse();
         nodes
     }
 
     pub fn backward(&self) -> Result<GradStore> {
-        let sorted_nodes = self.sorted_nodes();
+        let create_graph = CANDLE_GRAD_DO_NOT_DETACH.with(|b| *b);
+        self.backward_(create_graph, None)
+    }
+
+    /// Same as `backward` but the returned gradients keep track of the operations used to
+    /// compute them, so that they can be differentiated again. This can be used to get
+    /// higher-order derivatives, e.g. Hessian-vector products or gradient penalties.
+    ///
+    /// ```rust
+    /// use candle_core::{Device, Tensor, Var};
+    /// let x = Var::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    /// let y = (x.as_tensor() * x.as_tensor())?.sum_all()?;
+    /// let grads = y.backward_with_graph()?;
+    /// let grad_x = grads.get(&x).unwrap();
+    /// assert_eq!(grad_x.to_vec1::<f32>()?, [2., 4., 6.]);
+    /// let grads = grad_x.sum_all()?.backward()?;
+    /// assert_eq!(grads.get(&x).unwrap().to_vec1::<f32>()?, [2., 2., 2.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn backward_with_graph(&self) -> Result<GradStore> {
+        self.backward_(true, None)
+    }
+
+    /// Same as `backward` but only returns the gradients for `vars`. The parts of the graph that
+    /// do not lead to one of these variables are not traversed, which saves both compute and
+    /// memory when most of the model is frozen.
+    pub fn backward_for(&self, vars: &[&crate::Var]) -> Result<GradStore> {
+        let create_graph = CANDLE_GRAD_DO_NOT_DETACH.with(|b| *b);
+        let vars = vars.iter().map(|v| v.id()).collect::<HashSet<_>>();
+        self.backward_(create_graph, Some(&vars))
+    }
+
+    fn backward_(&self, create_graph: bool, vars: Option<&HashSet<TensorId>>) -> Result<GradStore> {
+        let sorted_nodes = self.sorted_nodes(vars);
         let mut grads = GradStore::new();
         grads.insert(self, self.ones_like()?.contiguous()?);
         for node in sorted_nodes.iter() {
@@ -174,10 +222,9 @@ impl Tensor {
             // https://github.com/huggingface/candle/issues/1241
             // Ideally, we would make these operations in place where possible to ensure that we
             // do not have to allocate too often. Here we just call `.detach` to avoid computing
-            // the backprop graph of the backprop itself. This would be an issue for second order
-            // derivatives but these are out of scope at the moment.
-            let do_not_detach = CANDLE_GRAD_DO_NOT_DETACH.with(|b| *b);
-            let grad = if do_not_detach { grad } else { grad.detach() };
+            // the backprop graph of the backprop itself unless this graph has been requested for
+            // higher-order derivatives.
+            let grad = if create_graph { grad } else { grad.detach() };
             if let Some(op) = node.op() {
                 match op {
                     Op::Binary(lhs, rhs, BinaryOp::Add) => {
@@ -660,6 +707,35 @@ impl Tensor {
                             *sum_grad = sum_grad.add(&arg_grad2)?
                         }
                     }
+                    Op::Checkpoint { inputs, vars, f } => {
+                        // Recompute the intermediate values, the float inputs are replaced by
+                        // fresh variables so that their gradients can be retrieved.
+                        let leaves = inputs
+                            .iter()
+                            .map(|t| {
+                                if t.dtype().is_float() {
+                                    t.detach().make_var()
+                                } else {
+                                    Ok(t.detach())
+                                }
+                            })
+                            .collect::<Result<Vec<_>>>()?;
+                        let ys = f(&leaves)?;
+                        let inner_grads =
+                            ys.mul(&grad)?.sum_all()?.backward_(create_graph, None)?;
+                        for (input, leaf) in inputs.iter().zip(leaves.iter()) {
+                            if let Some(input_grad) = inner_grads.get(leaf) {
+                                let sum_grad = grads.or_insert(input)?;
+                                *sum_grad = sum_grad.add(input_grad)?
+                            }
+                        }
+                        for var in vars.iter() {
+                            if let Some(var_grad) = inner_grads.get(var) {
+                                let sum_grad = grads.or_insert(var)?;
+                                *sum_grad = sum_grad.add(var_grad)?
+                            }
+                        }
+                    }
                     Op::CustomOp3(arg1, arg2, arg3, c) => {
                         let (arg_grad1, arg_grad2, arg_grad3) =
                             c.bwd(arg1, arg2, arg3, node, &grad)?;
@@ -708,6 +784,9 @@ impl Tensor {
                 };
             }
         }
+        if let Some(vars) = vars {
+            grads.0.retain(|id, _| vars.contains(id))
+        }
         Ok(grads)
     }
 }
@@ -727,6 +806,16 @@ impl GradStore {
         self.0.get(&id)
     }
 
+    /// Get a mutable reference to the gradient tensor corresponding to the given tensor id
+    pub fn get_id_mut(&mut self, id: TensorId) -> Option<&mut Tensor> {
+        self.0.get_mut(&id)
+    }
+
+    /// Get the ids of all the tensors that have a gradient in this store
+    pub fn get_ids(&self) -> impl Iterator<Item = &TensorId> {
+        self.0.keys()
+    }
+
     /// Get the gradient tensor associated with the given tensor
     pub fn get(&self, tensor: &Tensor) -> Option<&Tensor> {
         self.0.get(&tensor.id())
@@ -756,3 +845,34 @@ impl GradStore {
         Ok(grad)
     }
 }
+
+/// Runs `f` on `inputs` without keeping the intermediate values required for backpropagation,
+/// these get recomputed by running `f` again when computing the gradients. This trades compute
+/// for memory and is typically applied on each block of a deep model.
+///
+/// Gradients flow to the `inputs` and to the variables used by `f`. The function should be
+/// deterministic, e.g. dropout should not be used within `f` as the masks would differ between
+/// the two runs.
+pub fn checkpoint<F>(f: F, inputs: &[Tensor]) -> Result<Tensor>
+where
+    F: Fn(&[Tensor]) -> Result<Tensor> + Send + Sync + 'static,
+{
+    let detached: Vec<Tensor> = inputs.iter().map(|t| t.detach()).collect();
+    let ys = f(&detached)?;
+    // The only nodes that track gradients in this graph are the variables captured by `f`.
+    let vars: Vec<Tensor> = ys
+        .sorted_nodes(None)
+        .into_iter()
+        .filter(|t| t.is_variable())
+        .cloned()
+        .collect();
+    if vars.is_empty() && !inputs.iter().any(|t| t.track_op()) {
+        return Ok(ys.detach());
+    }
+    let op = crate::op::BackpropOp::from_op(Op::Checkpoint {
+        inputs: inputs.to_vec(),
+        vars,
+        f: std::sync::Arc::new(f),
+    });
+    Ok(ys.with_op(op))
+}
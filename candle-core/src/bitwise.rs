@@ -0,0 +1,204 @@
+//! Bitwise and shift operations on integer tensors.
+use crate::backend::BackendStorage;
+use crate::cpu_backend::{binary_map, unary_map};
+use crate::{
+    CpuStorage, CustomOp1, CustomOp2, DType, Device, Error, Layout, Result, Shape, Tensor,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BitwiseOp {
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+impl BitwiseOp {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::And => "bitwise_and",
+            Self::Or => "bitwise_or",
+            Self::Xor => "bitwise_xor",
+            Self::Shl => "shl",
+            Self::Shr => "shr",
+        }
+    }
+}
+
+macro_rules! bitwise_fn {
+    ($op:expr, $t:ty) => {
+        match $op {
+            BitwiseOp::And => |l: $t, r: $t| l & r,
+            BitwiseOp::Or => |l: $t, r: $t| l | r,
+            BitwiseOp::Xor => |l: $t, r: $t| l ^ r,
+            // Shifting by more than the number of bits results in zero.
+            BitwiseOp::Shl => |l: $t, r: $t| l.checked_shl(r as u32).unwrap_or(0),
+            BitwiseOp::Shr => |l: $t, r: $t| l.checked_shr(r as u32).unwrap_or(0),
+        }
+    };
+}
+
+impl CustomOp2 for BitwiseOp {
+    fn name(&self) -> &'static str {
+        BitwiseOp::name(self)
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        let storage = match (s1, s2) {
+            (CpuStorage::U8(lhs), CpuStorage::U8(rhs)) => {
+                CpuStorage::U8(binary_map(l1, l2, lhs, rhs, bitwise_fn!(self, u8)))
+            }
+            (CpuStorage::U32(lhs), CpuStorage::U32(rhs)) => {
+                CpuStorage::U32(binary_map(l1, l2, lhs, rhs, bitwise_fn!(self, u32)))
+            }
+            (CpuStorage::I64(lhs), CpuStorage::I64(rhs)) => {
+                CpuStorage::I64(binary_map(l1, l2, lhs, rhs, bitwise_fn!(self, i64)))
+            }
+            _ => Err(Error::UnsupportedDTypeForOp(s1.dtype(), self.name()).bt())?,
+        };
+        Ok((storage, l1.shape().clone()))
+    }
+}
+
+struct BitwiseNot;
+
+impl CustomOp1 for BitwiseNot {
+    fn name(&self) -> &'static str {
+        "bitwise_not"
+    }
+
+    fn cpu_fwd(&self, storage: &CpuStorage, layout: &Layout) -> Result<(CpuStorage, Shape)> {
+        let storage = match storage {
+            CpuStorage::U8(vs) => CpuStorage::U8(unary_map(vs, layout, |v| !v)),
+            CpuStorage::U32(vs) => CpuStorage::U32(unary_map(vs, layout, |v| !v)),
+            CpuStorage::I64(vs) => CpuStorage::I64(unary_map(vs, layout, |v| !v)),
+            _ => Err(Error::UnsupportedDTypeForOp(storage.dtype(), self.name()).bt())?,
+        };
+        Ok((storage, layout.shape().clone()))
+    }
+}
+
+impl Tensor {
+    fn bitwise_op(&self, rhs: &Self, op: BitwiseOp) -> Result<Self> {
+        if self.dtype() != rhs.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: rhs.dtype(),
+                op: op.name(),
+            }
+            .bt())?
+        }
+        if !self.dtype().is_int() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), op.name()).bt())?
+        }
+        let shape = self
+            .shape()
+            .broadcast_shape_binary_op(rhs.shape(), op.name())?;
+        let lhs = self.broadcast_as(&shape)?;
+        let rhs = rhs.broadcast_as(&shape)?;
+        // These ops only have a cpu implementation.
+        match self.device() {
+            Device::Cpu => lhs.apply_op2_no_bwd(&rhs, &op),
+            device => lhs
+                .to_device(&Device::Cpu)?
+                .apply_op2_no_bwd(&rhs.to_device(&Device::Cpu)?, &op)?
+                .to_device(device),
+        }
+    }
+
+    /// Element-wise bitwise and, the two tensors are broadcasted together. This is only supported
+    /// for integer dtypes.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[0b1100u8, 0b1010], &Device::Cpu)?;
+    /// let b = Tensor::new(&[0b1010u8, 0b0110], &Device::Cpu)?;
+    /// assert_eq!(a.bitwise_and(&b)?.to_vec1::<u8>()?, &[0b1000, 0b0010]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn bitwise_and(&self, rhs: &Self) -> Result<Self> {
+        self.bitwise_op(rhs, BitwiseOp::And)
+    }
+
+    /// Element-wise bitwise or, the two tensors are broadcasted together.
+    pub fn bitwise_or(&self, rhs: &Self) -> Result<Self> {
+        self.bitwise_op(rhs, BitwiseOp::Or)
+    }
+
+    /// Element-wise bitwise xor, the two tensors are broadcasted together.
+    pub fn bitwise_xor(&self, rhs: &Self) -> Result<Self> {
+        self.bitwise_op(rhs, BitwiseOp::Xor)
+    }
+
+    /// Element-wise left shift of `self` by `rhs` bits, the two tensors are broadcasted together.
+    /// Shifting by at least the number of bits of the dtype results in zero.
+    pub fn shl(&self, rhs: &Self) -> Result<Self> {
+        self.bitwise_op(rhs, BitwiseOp::Shl)
+    }
+
+    /// Element-wise right shift of `self` by `rhs` bits, the two tensors are broadcasted together.
+    /// This is an arithmetic shift for `i64`. Shifting by at least the number of bits of the dtype
+    /// results in zero.
+    pub fn shr(&self, rhs: &Self) -> Result<Self> {
+        self.bitwise_op(rhs, BitwiseOp::Shr)
+    }
+
+    fn bitwise_op_scalar(&self, rhs: u32, op: BitwiseOp) -> Result<Self> {
+        let rhs = match self.dtype() {
+            DType::U8 => match u8::try_from(rhs) {
+                Ok(rhs) => Tensor::new(rhs, self.device())?,
+                Err(_) => crate::bail!("{}: scalar {rhs} does not fit in u8", op.name()),
+            },
+            DType::U32 => Tensor::new(rhs, self.device())?,
+            DType::I64 => Tensor::new(rhs as i64, self.device())?,
+            dtype => Err(Error::UnsupportedDTypeForOp(dtype, op.name()).bt())?,
+        };
+        self.bitwise_op(&rhs, op)
+    }
+
+    /// Element-wise bitwise and with a scalar, an error is returned if the scalar does not fit in
+    /// the dtype of the tensor.
+    pub fn bitwise_and_scalar(&self, rhs: u32) -> Result<Self> {
+        self.bitwise_op_scalar(rhs, BitwiseOp::And)
+    }
+
+    /// Element-wise bitwise or with a scalar, an error is returned if the scalar does not fit in
+    /// the dtype of the tensor.
+    pub fn bitwise_or_scalar(&self, rhs: u32) -> Result<Self> {
+        self.bitwise_op_scalar(rhs, BitwiseOp::Or)
+    }
+
+    /// Element-wise bitwise xor with a scalar, an error is returned if the scalar does not fit in
+    /// the dtype of the tensor.
+    pub fn bitwise_xor_scalar(&self, rhs: u32) -> Result<Self> {
+        self.bitwise_op_scalar(rhs, BitwiseOp::Xor)
+    }
+
+    /// Element-wise bitwise not. This is only supported for integer dtypes.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[0u8, 0b1111_0000], &Device::Cpu)?;
+    /// assert_eq!(a.bitwise_not()?.to_vec1::<u8>()?, &[255, 0b0000_1111]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn bitwise_not(&self) -> Result<Self> {
+        if !self.dtype().is_int() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "bitwise_not").bt())?
+        }
+        match self.device() {
+            Device::Cpu => self.apply_op1_no_bwd(&BitwiseNot),
+            device => self
+                .to_device(&Device::Cpu)?
+                .apply_op1_no_bwd(&BitwiseNot)?
+                .to_device(device),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Complex tensors.
+//!
+//! Complex values are only supported on the cpu backend, they are stored as interleaved real and
+//! imaginary parts.
+use crate::op::BackpropOp;
+use crate::{CpuStorage, DType, Error, Result, Storage, Tensor, D};
+
+impl Tensor {
+    /// Creates a complex tensor from its real and imaginary parts.
+    ///
+    /// `f32` (as well as `f16` and `bf16`) parts result in a `c64` tensor, `f64` parts result in
+    /// a `c128` tensor. Both parts must have the same shape and dtype.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device, DType};
+    /// let re = Tensor::new(&[1f32, 2.], &Device::Cpu)?;
+    /// let im = Tensor::new(&[3f32, -4.], &Device::Cpu)?;
+    /// let c = Tensor::complex(&re, &im)?;
+    /// assert_eq!(c.dtype(), DType::C64);
+    /// assert_eq!(c.imag()?.to_vec1::<f32>()?, &[3., -4.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn complex(real: &Tensor, imag: &Tensor) -> Result<Tensor> {
+        let shape = real.same_shape_binary_op(imag, "complex")?.clone();
+        if real.dtype() != imag.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: real.dtype(),
+                rhs: imag.dtype(),
+                op: "complex",
+            }
+            .bt())?
+        }
+        let dtype = match real.dtype() {
+            DType::F64 => DType::C128,
+            DType::F32 | DType::F16 | DType::BF16 => DType::C64,
+            dtype => Err(Error::UnsupportedDTypeForOp(dtype, "complex").bt())?,
+        };
+        if !real.device().is_cpu() {
+            Err(Error::UnsupportedDTypeForOp(dtype, "complex").bt())?
+        }
+        let parts_dtype = if dtype == DType::C128 {
+            DType::F64
+        } else {
+            DType::F32
+        };
+        let real = real.to_dtype(parts_dtype)?;
+        let imag = imag.to_dtype(parts_dtype)?;
+        let interleaved = Tensor::stack(&[&real, &imag], D::Minus1)?.contiguous()?;
+        let (storage, layout) = interleaved.storage_and_layout();
+        let (o1, o2) = match layout.contiguous_offsets() {
+            Some(offsets) => offsets,
+            None => Err(Error::RequiresContiguous { op: "complex" }.bt())?,
+        };
+        let storage = match &*storage {
+            Storage::Cpu(CpuStorage::F32(vs)) => CpuStorage::C64(vs[o1..o2].to_vec()),
+            Storage::Cpu(CpuStorage::F64(vs)) => CpuStorage::C128(vs[o1..o2].to_vec()),
+            _ => Err(Error::UnsupportedDTypeForOp(dtype, "complex").bt())?,
+        };
+        crate::tensor::from_storage(Storage::Cpu(storage), shape, BackpropOp::none(), false)
+    }
+
+    /// Returns a real tensor with an additional trailing dimension of size two holding the real
+    /// and imaginary parts of a complex tensor.
+    fn complex_parts(&self, op: &'static str) -> Result<Tensor> {
+        let t = self.contiguous()?;
+        let (storage, layout) = t.storage_and_layout();
+        let (o1, o2) = match layout.contiguous_offsets() {
+            Some(offsets) => offsets,
+            None => Err(Error::RequiresContiguous { op }.bt())?,
+        };
+        let storage = match &*storage {
+            Storage::Cpu(CpuStorage::C64(vs)) => CpuStorage::F32(vs[2 * o1..2 * o2].to_vec()),
+            Storage::Cpu(CpuStorage::C128(vs)) => CpuStorage::F64(vs[2 * o1..2 * o2].to_vec()),
+            _ => Err(Error::UnsupportedDTypeForOp(self.dtype(), op).bt())?,
+        };
+        let mut dims = self.dims().to_vec();
+        dims.push(2);
+        crate::tensor::from_storage(Storage::Cpu(storage), dims, BackpropOp::none(), false)
+    }
+
+    /// The real part of a complex tensor, `c64` tensors result in `f32` values and `c128` tensors
+    /// in `f64` values.
+    pub fn real(&self) -> Result<Tensor> {
+        self.complex_parts("real")?.get_on_dim(D::Minus1, 0)
+    }
+
+    /// The imaginary part of a complex tensor.
+    pub fn imag(&self) -> Result<Tensor> {
+        self.complex_parts("imag")?.get_on_dim(D::Minus1, 1)
+    }
+
+    /// The complex conjugate of a complex tensor.
+    pub fn conj(&self) -> Result<Tensor> {
+        let parts = self.complex_parts("conj")?;
+        let real = parts.get_on_dim(D::Minus1, 0)?;
+        let imag = parts.get_on_dim(D::Minus1, 1)?.neg()?;
+        Tensor::complex(&real, &imag)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::autocast::AutocastOp;
 use crate::{op::BackpropOp, op::Op, Error, Result, Tensor};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -65,6 +66,31 @@ pub enum CudnnFwdAlgo {
     Count,
 }
 
+/// How the spatial dimensions of a convolution input get padded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaddingMode {
+    /// Pads both sides of each spatial dimension with the explicit `padding` value.
+    #[default]
+    Explicit,
+    /// Pads so that the output size is `ceil(input_size / stride)`, e.g. for a stride of 1 the
+    /// output has the same spatial size as the input. When the total padding for a dimension is
+    /// odd, the extra element is added at the end (bottom/right) as done by TensorFlow.
+    Same,
+}
+
+/// Returns the (start, end) padding used by [`PaddingMode::Same`] for a single dimension.
+pub fn same_padding(
+    i_size: usize,
+    k_size: usize,
+    stride: usize,
+    dilation: usize,
+) -> (usize, usize) {
+    let o_size = i_size.div_ceil(stride);
+    let needed =
+        (o_size.saturating_sub(1) * stride + dilation * (k_size - 1) + 1).saturating_sub(i_size);
+    (needed / 2, needed - needed / 2)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParamsConv2D {
     pub(crate) b_size: usize,
@@ -125,6 +151,75 @@ impl ParamsConvTranspose2D {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsConv3D {
+    pub(crate) b_size: usize,
+    pub(crate) i_d: usize,
+    pub(crate) i_h: usize,
+    pub(crate) i_w: usize,
+    pub(crate) k_d: usize,
+    pub(crate) k_h: usize,
+    pub(crate) k_w: usize,
+    pub(crate) c_out: usize,
+    pub(crate) c_in: usize,
+    pub(crate) padding: usize,
+    pub(crate) stride: usize,
+    pub(crate) dilation: usize,
+}
+
+impl ParamsConv3D {
+    fn out_size(&self, i: usize, k: usize) -> usize {
+        (i + 2 * self.padding - self.dilation * (k - 1) - 1) / self.stride + 1
+    }
+
+    pub(crate) fn out_d(&self) -> usize {
+        self.out_size(self.i_d, self.k_d)
+    }
+
+    pub(crate) fn out_h(&self) -> usize {
+        self.out_size(self.i_h, self.k_h)
+    }
+
+    pub(crate) fn out_w(&self) -> usize {
+        self.out_size(self.i_w, self.k_w)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamsConvTranspose3D {
+    pub(crate) b_size: usize,
+    pub(crate) i_d: usize,
+    pub(crate) i_h: usize,
+    pub(crate) i_w: usize,
+    pub(crate) k_d: usize,
+    pub(crate) k_h: usize,
+    pub(crate) k_w: usize,
+    pub(crate) c_out: usize,
+    pub(crate) c_in: usize,
+    pub(crate) padding: usize,
+    pub(crate) output_padding: usize,
+    pub(crate) stride: usize,
+    pub(crate) dilation: usize,
+}
+
+impl ParamsConvTranspose3D {
+    fn out_size(&self, i: usize, k: usize) -> usize {
+        (i - 1) * self.stride + self.dilation * (k - 1) + self.output_padding + 1 - 2 * self.padding
+    }
+
+    pub(crate) fn out_d(&self) -> usize {
+        self.out_size(self.i_d, self.k_d)
+    }
+
+    pub(crate) fn out_h(&self) -> usize {
+        self.out_size(self.i_h, self.k_h)
+    }
+
+    pub(crate) fn out_w(&self) -> usize {
+        self.out_size(self.i_w, self.k_w)
+    }
+}
+
 impl Tensor {
     fn conv1d_single_group(&self, kernel: &Self, params: &ParamsConv1D) -> Result<Self> {
         let storage =
@@ -138,7 +233,7 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        crate::tensor::from_storage(storage, out_dims, op, false)
     }
 
     /// Applies a 1D convolution over the input tensor.
@@ -150,6 +245,9 @@ impl Tensor {
         dilation: usize,
         groups: usize,
     ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv1d(&kernel, padding, stride, dilation, groups);
+        }
         let (c_out, c_in_k, k_size) = kernel.dims3()?;
         let (b_size, c_in, l_in) = self.dims3()?;
         if c_in != c_in_k * groups {
@@ -207,7 +305,7 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        crate::tensor::from_storage(storage, out_dims, op, false)
     }
 
     /// Applies a 1D transposed convolution over the input tensor.
@@ -220,6 +318,16 @@ impl Tensor {
         dilation: usize,
         groups: usize,
     ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv_transpose1d(
+                &kernel,
+                padding,
+                output_padding,
+                stride,
+                dilation,
+                groups,
+            );
+        }
         let (c_in_k, c_out, k_size) = kernel.dims3()?;
         let (b_size, c_in, l_in) = self.dims3()?;
         if c_in != c_in_k {
@@ -265,7 +373,7 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        crate::tensor::from_storage(storage, out_dims, op, false)
     }
 
     /// Applies a 2D convolution over the input tensor.
@@ -277,6 +385,9 @@ impl Tensor {
         dilation: usize,
         groups: usize,
     ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv2d(&kernel, padding, stride, dilation, groups);
+        }
         let (b_size, c_in, i_h, i_w) = self.dims4()?;
         let (c_out, c_in_k, k_h, k_w) = kernel.dims4()?;
         if c_in != c_in_k * groups {
@@ -311,6 +422,31 @@ impl Tensor {
         }
     }
 
+    /// Applies a 2D convolution over the input tensor using the given padding mode, `padding` is
+    /// only used with [`PaddingMode::Explicit`].
+    pub fn conv2d_with_mode(
+        &self,
+        kernel: &Self,
+        padding: usize,
+        mode: PaddingMode,
+        stride: usize,
+        dilation: usize,
+        groups: usize,
+    ) -> Result<Self> {
+        match mode {
+            PaddingMode::Explicit => self.conv2d(kernel, padding, stride, dilation, groups),
+            PaddingMode::Same => {
+                let (_b_size, _c_in, i_h, i_w) = self.dims4()?;
+                let (_c_out, _c_in_k, k_h, k_w) = kernel.dims4()?;
+                let (top, bottom) = same_padding(i_h, k_h, stride, dilation);
+                let (left, right) = same_padding(i_w, k_w, stride, dilation);
+                self.pad_with_zeros(2, top, bottom)?
+                    .pad_with_zeros(3, left, right)?
+                    .conv2d(kernel, 0, stride, dilation, groups)
+            }
+        }
+    }
+
     /// Applies a 2D transposed convolution over the input tensor.
     pub fn conv_transpose2d(
         &self,
@@ -320,11 +456,31 @@ impl Tensor {
         stride: usize,
         dilation: usize,
     ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv_transpose2d(&kernel, padding, output_padding, stride, dilation);
+        }
         let (b_size, c_in, i_h, i_w) = self.dims4()?;
         let (c_in_k, c_out, k_h, k_w) = kernel.dims4()?;
         if c_in != c_in_k {
             crate::bail!("in_channel mismatch between input ({c_in}) and kernel ({c_in_k})")
         }
+        if output_padding >= stride && output_padding >= dilation {
+            crate::bail!(
+                "conv_transpose2d: output_padding ({output_padding}) must be smaller than either stride ({stride}) or dilation ({dilation})"
+            )
+        }
+        for (i, k) in [(i_h, k_h), (i_w, k_w)] {
+            if i == 0
+                || k == 0
+                || (i - 1) * stride + dilation * (k - 1) + output_padding < 2 * padding
+            {
+                crate::bail!(
+                    "conv_transpose2d: empty output for input {:?}, kernel {:?} and padding {padding}",
+                    self.dims(),
+                    kernel.dims()
+                )
+            }
+        }
         let params = ParamsConvTranspose2D {
             b_size,
             i_h,
@@ -353,6 +509,184 @@ impl Tensor {
             dilation: params.dilation,
         });
         let out_dims = params.out_dims();
-        Ok(crate::tensor::from_storage(storage, out_dims, op, false))
+        crate::tensor::from_storage(storage, out_dims, op, false)
+    }
+
+    /// Applies a 3D convolution over the input tensor.
+    ///
+    /// The input has shape `(b, c_in, d, h, w)` and the kernel `(c_out, c_in / groups, k_d, k_h,
+    /// k_w)`. The convolution is computed by summing 2D convolutions over the depth of the
+    /// kernel, with the output depth folded in the batch dimension.
+    pub fn conv3d(
+        &self,
+        kernel: &Self,
+        padding: usize,
+        stride: usize,
+        dilation: usize,
+        groups: usize,
+    ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv3d(&kernel, padding, stride, dilation, groups);
+        }
+        let (b_size, c_in, i_d, i_h, i_w) = self.dims5()?;
+        let (c_out, c_in_k, k_d, k_h, k_w) = kernel.dims5()?;
+        if c_in != c_in_k * groups {
+            crate::bail!(
+                "in_channel mismatch between input ({c_in}, groups {groups}) and kernel ({c_in_k})"
+            )
+        }
+        if k_d == 0 || i_d + 2 * padding < dilation * (k_d - 1) + 1 {
+            crate::bail!("conv3d: the padded input depth is smaller than the kernel depth")
+        }
+        let params = ParamsConv3D {
+            b_size,
+            i_d,
+            i_h,
+            i_w,
+            k_d,
+            k_h,
+            k_w,
+            c_out,
+            c_in,
+            padding,
+            stride,
+            dilation,
+        };
+        let out_d = params.out_d();
+        let xs = self.pad_with_zeros(2, padding, padding)?;
+        let mut ys: Option<Tensor> = None;
+        for kd in 0..k_d {
+            // The input planes that are combined with the depth `kd` of the kernel.
+            let ids: Vec<u32> = (0..out_d)
+                .map(|od| (od * stride + kd * dilation) as u32)
+                .collect();
+            let ids = Tensor::from_vec(ids, out_d, self.device())?;
+            let xs = xs
+                .index_select(&ids, 2)?
+                .transpose(1, 2)?
+                .contiguous()?
+                .reshape((b_size * out_d, c_in, i_h, i_w))?;
+            let kernel = kernel.narrow(2, kd, 1)?.squeeze(2)?.contiguous()?;
+            let y = xs.conv2d(&kernel, padding, stride, dilation, groups)?;
+            ys = Some(match ys {
+                None => y,
+                Some(ys) => (ys + y)?,
+            })
+        }
+        let ys = ys.expect("the kernel depth is not zero");
+        ys.reshape((b_size, out_d, c_out, params.out_h(), params.out_w()))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    fn conv_transpose3d_single_group(
+        &self,
+        kernel: &Self,
+        params: &ParamsConvTranspose3D,
+    ) -> Result<Self> {
+        let &ParamsConvTranspose3D {
+            b_size,
+            i_d,
+            i_h,
+            i_w,
+            c_out,
+            padding,
+            output_padding,
+            stride,
+            dilation,
+            ..
+        } = params;
+        let out_d = params.out_d();
+        let (o_h, o_w) = (params.out_h(), params.out_w());
+        let xs =
+            self.transpose(1, 2)?
+                .contiguous()?
+                .reshape((b_size * i_d, params.c_in, i_h, i_w))?;
+        let mut ys = Tensor::zeros(
+            (b_size, out_d, c_out, o_h, o_w),
+            self.dtype(),
+            self.device(),
+        )?;
+        for kd in 0..params.k_d {
+            // Input plane `id` contributes to the output plane `id * stride + kd * dilation -
+            // padding` when it is within bounds.
+            let (src_ids, dst_ids): (Vec<u32>, Vec<u32>) = (0..i_d)
+                .filter_map(|id| {
+                    let od = (id * stride + kd * dilation).checked_sub(padding)?;
+                    (od < out_d).then_some((id as u32, od as u32))
+                })
+                .unzip();
+            if src_ids.is_empty() {
+                continue;
+            }
+            let n = src_ids.len();
+            let kernel = kernel.narrow(2, kd, 1)?.squeeze(2)?.contiguous()?;
+            let y = xs
+                .conv_transpose2d(&kernel, padding, output_padding, stride, dilation)?
+                .reshape((b_size, i_d, c_out, o_h, o_w))?;
+            let y = y.index_select(&Tensor::from_vec(src_ids, n, self.device())?, 1)?;
+            ys = ys.index_add(&Tensor::from_vec(dst_ids, n, self.device())?, &y, 1)?;
+        }
+        ys.transpose(1, 2)?.contiguous()
+    }
+
+    /// Applies a 3D transposed convolution over the input tensor.
+    ///
+    /// The input has shape `(b, c_in, d, h, w)` and the kernel `(c_in, c_out / groups, k_d, k_h,
+    /// k_w)`.
+    pub fn conv_transpose3d(
+        &self,
+        kernel: &Self,
+        padding: usize,
+        output_padding: usize,
+        stride: usize,
+        dilation: usize,
+        groups: usize,
+    ) -> Result<Self> {
+        if let Some((arg, kernel)) = crate::autocast::cast2(self, kernel, AutocastOp::Conv)? {
+            return arg.conv_transpose3d(
+                &kernel,
+                padding,
+                output_padding,
+                stride,
+                dilation,
+                groups,
+            );
+        }
+        let (b_size, c_in, i_d, i_h, i_w) = self.dims5()?;
+        let (c_in_k, c_out, k_d, k_h, k_w) = kernel.dims5()?;
+        if c_in != c_in_k {
+            crate::bail!("in_channel mismatch between input ({c_in}) and kernel ({c_in_k})")
+        }
+        if c_in % groups != 0 {
+            crate::bail!("in_channel {c_in} is not divisible by the number of groups")
+        }
+        let params = ParamsConvTranspose3D {
+            b_size,
+            i_d,
+            i_h,
+            i_w,
+            k_d,
+            k_h,
+            k_w,
+            c_out,
+            c_in: c_in / groups,
+            padding,
+            output_padding,
+            stride,
+            dilation,
+        };
+        if groups == 1 {
+            self.conv_transpose3d_single_group(kernel, &params)
+        } else {
+            let blocks = self.chunk(groups, 1)?;
+            let kernel = kernel.chunk(groups, 0)?;
+            let blocks = blocks
+                .iter()
+                .zip(&kernel)
+                .map(|(block, kernel)| block.conv_transpose3d_single_group(kernel, &params))
+                .collect::<Result<Vec<_>>>()?;
+            Tensor::cat(&blocks, 1)
+        }
     }
 }
@@ -135,6 +135,18 @@ impl Tensor {
                     f.write_i64::<LittleEndian>(v)?
                 }
             }
+            DType::C64 => {
+                let parts = Tensor::stack(&[vs.real()?, vs.imag()?], 1)?;
+                for v in parts.flatten_all()?.to_vec1::<f32>()? {
+                    f.write_f32::<LittleEndian>(v)?
+                }
+            }
+            DType::C128 => {
+                let parts = Tensor::stack(&[vs.real()?, vs.imag()?], 1)?;
+                for v in parts.flatten_all()?.to_vec1::<f64>()? {
+                    f.write_f64::<LittleEndian>(v)?
+                }
+            }
             DType::U8 => {
                 let vs = vs.to_vec1::<u8>()?;
                 f.write_all(&vs)?;
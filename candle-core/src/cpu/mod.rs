@@ -180,3 +180,40 @@ pub(crate) unsafe fn vec_dot_f16(a_row: *const f16, b_row: *const f16, c: *mut f
     }
     *c = sum;
 }
+
+/// Int8 matrix multiplication with int32 accumulation, computes `dst = lhs @ rhs.t()` where `lhs`
+/// has shape `(m, k)`, `rhs` has shape `(n, k)` and `dst` has shape `(m, n)`, all of them being
+/// stored in row major order. The rows of `dst` are computed in parallel.
+pub fn i8_matmul(
+    (m, n, k): (usize, usize, usize),
+    lhs: &[i8],
+    rhs: &[i8],
+    dst: &mut [i32],
+) -> crate::Result<()> {
+    use rayon::prelude::*;
+
+    if lhs.len() != m * k || rhs.len() != n * k || dst.len() != m * n {
+        crate::bail!(
+            "i8_matmul: unexpected sizes for {m}x{k} @ {n}x{k}: lhs {}, rhs {}, dst {}",
+            lhs.len(),
+            rhs.len(),
+            dst.len()
+        )
+    }
+    if k == 0 {
+        dst.fill(0);
+        return Ok(());
+    }
+    dst.par_chunks_mut(n.max(1))
+        .zip(lhs.par_chunks(k))
+        .for_each(|(dst_row, lhs_row)| {
+            for (d, rhs_row) in dst_row.iter_mut().zip(rhs.chunks_exact(k)) {
+                *d = lhs_row
+                    .iter()
+                    .zip(rhs_row.iter())
+                    .map(|(&a, &b)| a as i32 * b as i32)
+                    .sum();
+            }
+        });
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Kernels for complex tensors.
+//!
+//! Complex values are stored as interleaved real and imaginary parts, so element `i` of a layout
+//! corresponds to the values at index `2 * i` and `2 * i + 1` in the underlying storage.
+use super::MatMul;
+use crate::{DType, Error, Layout, Result};
+use num_traits::Float;
+
+/// Returns the layout of the underlying real storage, this adds a trailing dimension of size two
+/// holding the real and imaginary parts.
+pub(super) fn real_layout(l: &Layout) -> Layout {
+    let mut dims = l.dims().to_vec();
+    dims.push(2);
+    let mut stride: Vec<usize> = l.stride().iter().map(|s| 2 * s).collect();
+    stride.push(1);
+    Layout::new(dims.into(), stride, 2 * l.start_offset())
+}
+
+/// Returns the interleaved values in a contiguous buffer, applying `f` on each part.
+pub(super) fn unary_map<T: Copy, U: Copy, F: FnMut(T) -> U>(
+    vs: &[T],
+    layout: &Layout,
+    f: F,
+) -> Vec<U> {
+    super::unary_map(vs, &real_layout(layout), f)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn copy2d<T: Copy>(
+    src: &[T],
+    dst: &mut [T],
+    d1: usize,
+    d2: usize,
+    src_stride1: usize,
+    dst_stride1: usize,
+    src_offset: usize,
+    dst_offset: usize,
+) {
+    super::copy2d_(
+        src,
+        dst,
+        d1,
+        2 * d2,
+        2 * src_stride1,
+        2 * dst_stride1,
+        2 * src_offset,
+        2 * dst_offset,
+    )
+}
+
+fn binary_map<T: Copy, F: Fn((T, T), (T, T)) -> (T, T)>(
+    lhs_l: &Layout,
+    rhs_l: &Layout,
+    lhs: &[T],
+    rhs: &[T],
+    f: F,
+) -> Vec<T> {
+    let mut dst = Vec::with_capacity(2 * lhs_l.shape().elem_count());
+    for (i, j) in lhs_l.strided_index().zip(rhs_l.strided_index()) {
+        let (re, im) = f((lhs[2 * i], lhs[2 * i + 1]), (rhs[2 * j], rhs[2 * j + 1]));
+        dst.push(re);
+        dst.push(im);
+    }
+    dst
+}
+
+pub(super) fn binary_impl<T: Float>(
+    dtype: DType,
+    op: &'static str,
+    lhs_l: &Layout,
+    rhs_l: &Layout,
+    lhs: &[T],
+    rhs: &[T],
+) -> Result<Vec<T>> {
+    let data = match op {
+        "add" => binary_map(lhs_l, rhs_l, lhs, rhs, |(a, b), (c, d)| (a + c, b + d)),
+        "sub" => binary_map(lhs_l, rhs_l, lhs, rhs, |(a, b), (c, d)| (a - c, b - d)),
+        "mul" => binary_map(lhs_l, rhs_l, lhs, rhs, |(a, b), (c, d)| {
+            (a * c - b * d, a * d + b * c)
+        }),
+        "div" => binary_map(lhs_l, rhs_l, lhs, rhs, |(a, b), (c, d)| {
+            let den = c * c + d * d;
+            ((a * c + b * d) / den, (b * c - a * d) / den)
+        }),
+        op => Err(Error::UnsupportedDTypeForOp(dtype, op).bt())?,
+    };
+    Ok(data)
+}
+
+pub(super) fn unary_impl<T: Float>(
+    dtype: DType,
+    op: &'static str,
+    vs: &[T],
+    layout: &Layout,
+) -> Result<Vec<T>> {
+    match op {
+        "neg" => Ok(unary_map(vs, layout, |v| -v)),
+        op => Err(Error::UnsupportedDTypeForOp(dtype, op).bt()),
+    }
+}
+
+pub(super) fn matmul<T: Float>(
+    bmnk: (usize, usize, usize, usize),
+    lhs: &[T],
+    lhs_l: &Layout,
+    rhs: &[T],
+    rhs_l: &Layout,
+) -> Result<Vec<T>> {
+    let (b, m, n, k) = bmnk;
+    let (a_skip, b_skip) = MatMul(bmnk).ab_skip(lhs_l, rhs_l)?;
+    let lhs_stride = lhs_l.stride();
+    let rhs_stride = rhs_l.stride();
+    let rank = lhs_stride.len();
+    let (lhs_rs, lhs_cs) = (lhs_stride[rank - 2], lhs_stride[rank - 1]);
+    let (rhs_rs, rhs_cs) = (rhs_stride[rank - 2], rhs_stride[rank - 1]);
+    let lhs_o = lhs_l.start_offset();
+    let rhs_o = rhs_l.start_offset();
+    let mut dst = vec![T::zero(); 2 * b * m * n];
+    for step in 0..b {
+        for i in 0..m {
+            for j in 0..n {
+                let mut re = T::zero();
+                let mut im = T::zero();
+                for l in 0..k {
+                    let lhs_i = lhs_o + step * a_skip + i * lhs_rs + l * lhs_cs;
+                    let rhs_i = rhs_o + step * b_skip + l * rhs_rs + j * rhs_cs;
+                    let (a, b) = (lhs[2 * lhs_i], lhs[2 * lhs_i + 1]);
+                    let (c, d) = (rhs[2 * rhs_i], rhs[2 * rhs_i + 1]);
+                    re = re + a * c - b * d;
+                    im = im + a * d + b * c;
+                }
+                let dst_i = step * m * n + i * n + j;
+                dst[2 * dst_i] = re;
+                dst[2 * dst_i + 1] = im;
+            }
+        }
+    }
+    Ok(dst)
+}
@@ -4,6 +4,7 @@ use crate::{DType, Error, IntDType, Layout, Result, Shape, WithDType};
 use half::{bf16, f16};
 use rayon::prelude::*;
 
+mod complex;
 mod utils;
 pub use utils::{
     binary_map, binary_map_vec, unary_map, unary_map_vec, Map1, Map1Any, Map2, Map2U8,
@@ -13,6 +14,22 @@ const USE_IM2COL_CONV1D: bool = true;
 const USE_COL2IM_CONV1D_TR: bool = true;
 const USE_IM2COL_CONV2D: bool = true;
 
+// The rng used by rand_uniform and rand_normal once set_seed has been called, before that the
+// thread rng is used. There is a single cpu device so its state is shared by all the threads.
+static SEEDED_RNG: std::sync::Mutex<Option<rand::rngs::StdRng>> = std::sync::Mutex::new(None);
+
+pub(crate) fn with_rng<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut dyn rand::RngCore) -> R,
+{
+    // A poisoned lock only means that another thread panicked while sampling.
+    let mut rng = SEEDED_RNG.lock().unwrap_or_else(|e| e.into_inner());
+    match rng.as_mut() {
+        Some(rng) => f(rng),
+        None => f(&mut rand::thread_rng()),
+    }
+}
+
 // TODO: Maybe we should not implement [Clone] here and instead have an explicit allocator +
 // intercept the oom errors to avoid panicking and provide a proper error.
 #[derive(Debug, Clone)]
@@ -24,6 +41,9 @@ pub enum CpuStorage {
     F16(Vec<f16>),
     F32(Vec<f32>),
     F64(Vec<f64>),
+    // Complex values are stored as interleaved real and imaginary parts.
+    C64(Vec<f32>),
+    C128(Vec<f64>),
 }
 
 #[derive(Debug, Clone)]
@@ -209,6 +229,27 @@ impl Map1Any for ReduceIndex {
     }
 }
 
+// Slices up to this size are summed sequentially by the deterministic reduction, larger ones are
+// split in two halves that get summed in parallel and then added together.
+const TREE_REDUCE_LEAF_SIZE: usize = 64;
+const TREE_REDUCE_PAR_SIZE: usize = 1 << 14;
+
+/// Sum the elements of `xs` using a fixed tree-reduction order, the result does not depend on
+/// the number of threads that are used.
+fn tree_reduce_sum<T: WithDType>(xs: &[T]) -> T {
+    if xs.len() <= TREE_REDUCE_LEAF_SIZE {
+        xs.iter().fold(T::zero(), |acc, &x| acc + x)
+    } else {
+        let (lhs, rhs) = xs.split_at(xs.len() / 2);
+        if xs.len() >= TREE_REDUCE_PAR_SIZE {
+            let (lhs, rhs) = rayon::join(|| tree_reduce_sum(lhs), || tree_reduce_sum(rhs));
+            lhs + rhs
+        } else {
+            tree_reduce_sum(lhs) + tree_reduce_sum(rhs)
+        }
+    }
+}
+
 struct ReduceSum<'a> {
     dst_shape: &'a Shape,
     reduce_dims: &'a [usize],
@@ -222,6 +263,7 @@ impl<'a> ReduceSum<'a> {
         T: WithDType,
     {
         let mut dst = vec![start_elt; self.dst_shape.elem_count()];
+        let deterministic = crate::utils::deterministic_reductions();
         match src_l.contiguous_offsets() {
             Some((o1, o2)) => {
                 let src = &src[o1..o2];
@@ -240,6 +282,13 @@ impl<'a> ReduceSum<'a> {
                         .iter()
                         .map(|(u, _)| u)
                         .product::<usize>();
+                    if deterministic {
+                        for (dst_i, dst_v) in dst.iter_mut().enumerate() {
+                            let src_i = dst_i * reduce_sz;
+                            *dst_v = tree_reduce_sum(&src[src_i..src_i + reduce_sz]);
+                        }
+                        return Ok(dst);
+                    }
                     for (dst_i, dst_v) in dst.iter_mut().enumerate() {
                         let src_i = dst_i * reduce_sz;
                         unsafe {
@@ -252,6 +301,9 @@ impl<'a> ReduceSum<'a> {
                     }
                     return Ok(dst);
                 };
+                if deterministic {
+                    return Ok(self.tree_reduce(src.iter().copied(), dst));
+                }
                 for (unstr_index, &src) in src.iter().enumerate() {
                     let mut dst_index = unstr_index;
                     // Set the reduce_dims indexes to 0.
@@ -264,6 +316,10 @@ impl<'a> ReduceSum<'a> {
                 }
             }
             None => {
+                if deterministic {
+                    let src = src_l.strided_index().map(|src_index| src[src_index]);
+                    return Ok(self.tree_reduce(src, dst));
+                }
                 for (unstr_index, src_index) in src_l.strided_index().enumerate() {
                     let mut dst_index = unstr_index;
                     // Set the reduce_dims indexes to 0.
@@ -278,6 +334,36 @@ impl<'a> ReduceSum<'a> {
         }
         Ok(dst)
     }
+
+    /// Deterministic reduction for the layouts that are not handled by the fast path. The values,
+    /// given in logical order, are gathered per destination element so that each group is summed
+    /// in the same order as a contiguous reduction over the last dimensions would.
+    fn tree_reduce<T: WithDType>(&self, src: impl Iterator<Item = T>, mut dst: Vec<T>) -> Vec<T> {
+        let reduce_sz = self
+            .reduce_dims_and_stride
+            .iter()
+            .map(|(u, _)| u)
+            .product::<usize>();
+        if reduce_sz == 0 {
+            return dst;
+        }
+        let mut groups = vec![T::zero(); dst.len() * reduce_sz];
+        let mut group_lens = vec![0usize; dst.len()];
+        for (unstr_index, src) in src.enumerate() {
+            let mut dst_index = unstr_index;
+            // Set the reduce_dims indexes to 0.
+            for &(dim, stride) in self.reduce_dims_and_stride.iter() {
+                let (pre, post) = (dst_index / stride, dst_index % stride);
+                dst_index = (pre / dim) * stride + post;
+            }
+            groups[dst_index * reduce_sz + group_lens[dst_index]] = src;
+            group_lens[dst_index] += 1;
+        }
+        for (dst_v, group) in dst.iter_mut().zip(groups.chunks(reduce_sz)) {
+            *dst_v = tree_reduce_sum(group)
+        }
+        dst
+    }
 }
 
 impl<'a> Map1 for ReduceSum<'a> {
@@ -1622,6 +1708,28 @@ impl CpuStorage {
                     .concat();
                 Self::F64(storages)
             }
+            Self::C64(_) => {
+                let storages = storages
+                    .iter()
+                    .map(|s| match s {
+                        Self::C64(s) => Ok(s.as_slice()),
+                        _ => crate::bail!("dtype mismatch"),
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .concat();
+                Self::C64(storages)
+            }
+            Self::C128(_) => {
+                let storages = storages
+                    .iter()
+                    .map(|s| match s {
+                        Self::C128(s) => Ok(s.as_slice()),
+                        _ => crate::bail!("dtype mismatch"),
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .concat();
+                Self::C128(storages)
+            }
         };
         Ok(s)
     }
@@ -1639,6 +1747,8 @@ impl BackendStorage for CpuStorage {
             Self::F16(_) => DType::F16,
             Self::F32(_) => DType::F32,
             Self::F64(_) => DType::F64,
+            Self::C64(_) => DType::C64,
+            Self::C128(_) => DType::C128,
         }
     }
 
@@ -1841,6 +1951,25 @@ impl BackendStorage for CpuStorage {
                 let data = unary_map(storage, layout, |v| v);
                 Ok(Self::F64(data))
             }
+            (Self::C64(storage), DType::C64) => {
+                let data = complex::unary_map(storage, layout, |v| v);
+                Ok(Self::C64(data))
+            }
+            (Self::C64(storage), DType::C128) => {
+                let data = complex::unary_map(storage, layout, |v| v as f64);
+                Ok(Self::C128(data))
+            }
+            (Self::C128(storage), DType::C64) => {
+                let data = complex::unary_map(storage, layout, |v| v as f32);
+                Ok(Self::C64(data))
+            }
+            (Self::C128(storage), DType::C128) => {
+                let data = complex::unary_map(storage, layout, |v| v);
+                Ok(Self::C128(data))
+            }
+            (_, DType::C64 | DType::C128) | (Self::C64(_) | Self::C128(_), _) => {
+                Err(Error::UnsupportedDTypeForOp(self.dtype(), "to_dtype").bt())
+            }
         }
     }
 
@@ -1957,6 +2086,8 @@ impl BackendStorage for CpuStorage {
             Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
             Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
             Self::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
+            Self::C64(_) => Err(Error::UnsupportedDTypeForOp(DType::C64, "elu").bt()),
+            Self::C128(_) => Err(Error::UnsupportedDTypeForOp(DType::C128, "elu").bt()),
         }
     }
 
@@ -1982,6 +2113,8 @@ impl BackendStorage for CpuStorage {
             Self::U8(_) => Err(Error::UnsupportedDTypeForOp(DType::U8, "elu").bt()),
             Self::U32(_) => Err(Error::UnsupportedDTypeForOp(DType::U32, "elu").bt()),
             Self::I64(_) => Err(Error::UnsupportedDTypeForOp(DType::I64, "elu").bt()),
+            Self::C64(_) => Err(Error::UnsupportedDTypeForOp(DType::C64, "elu").bt()),
+            Self::C128(_) => Err(Error::UnsupportedDTypeForOp(DType::C128, "elu").bt()),
         }
     }
 
@@ -2035,6 +2168,14 @@ impl BackendStorage for CpuStorage {
                 let data = unary_map(storage, layout, B::i64);
                 Ok(Self::I64(data))
             }
+            Self::C64(storage) => {
+                let data = complex::unary_impl(DType::C64, B::NAME, storage, layout)?;
+                Ok(Self::C64(data))
+            }
+            Self::C128(storage) => {
+                let data = complex::unary_impl(DType::C128, B::NAME, storage, layout)?;
+                Ok(Self::C128(data))
+            }
         }
     }
 
@@ -2101,6 +2242,14 @@ impl BackendStorage for CpuStorage {
                 };
                 Ok(Self::U8(data))
             }
+            (Self::C64(lhs), Self::C64(rhs)) => {
+                let data = complex::binary_impl(DType::C64, B::NAME, lhs_l, rhs_l, lhs, rhs)?;
+                Ok(Self::C64(data))
+            }
+            (Self::C128(lhs), Self::C128(rhs)) => {
+                let data = complex::binary_impl(DType::C128, B::NAME, lhs_l, rhs_l, lhs, rhs)?;
+                Ok(Self::C128(data))
+            }
             _ => {
                 // This should be covered by the dtype check above.
                 Err(Error::DTypeMismatchBinaryOp {
@@ -2143,6 +2292,12 @@ impl BackendStorage for CpuStorage {
             (Self::F64(src), Self::F64(dst)) => {
                 copy2d_(src, dst, d1, d2, src_s, dst_s, src_o, dst_o)
             }
+            (Self::C64(src), Self::C64(dst)) => {
+                complex::copy2d(src, dst, d1, d2, src_s, dst_s, src_o, dst_o)
+            }
+            (Self::C128(src), Self::C128(dst)) => {
+                complex::copy2d(src, dst, d1, d2, src_s, dst_s, src_o, dst_o)
+            }
             (_, dst) => {
                 return Err(Error::DTypeMismatchBinaryOp {
                     lhs: self.dtype(),
@@ -2164,6 +2319,12 @@ impl BackendStorage for CpuStorage {
             (Self::F16(src), Self::F16(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
             (Self::F32(src), Self::F32(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
             (Self::F64(src), Self::F64(dst)) => copy_strided_src_(src, dst, dst_offset, src_l),
+            (Self::C64(src), Self::C64(dst)) => {
+                copy_strided_src_(src, dst, 2 * dst_offset, &complex::real_layout(src_l))
+            }
+            (Self::C128(src), Self::C128(dst)) => {
+                copy_strided_src_(src, dst, 2 * dst_offset, &complex::real_layout(src_l))
+            }
             (_, dst) => {
                 // This should be covered by the dtype check above.
                 return Err(Error::DTypeMismatchBinaryOp {
@@ -2430,7 +2591,17 @@ impl BackendStorage for CpuStorage {
         lhs_l: &Layout,
         rhs_l: &Layout,
     ) -> Result<Self> {
-        MatMul(bmnk).map(self, lhs_l, rhs, rhs_l)
+        match (self, rhs) {
+            (Self::C64(lhs), Self::C64(rhs)) => {
+                let data = complex::matmul(bmnk, lhs, lhs_l, rhs, rhs_l)?;
+                Ok(Self::C64(data))
+            }
+            (Self::C128(lhs), Self::C128(rhs)) => {
+                let data = complex::matmul(bmnk, lhs, lhs_l, rhs, rhs_l)?;
+                Ok(Self::C128(data))
+            }
+            _ => MatMul(bmnk).map(self, lhs_l, rhs, rhs_l),
+        }
     }
 
     fn device(&self) -> &Self::Device {
@@ -2473,34 +2644,35 @@ impl BackendDevice for CpuDevice {
         Ok(Self)
     }
 
-    fn set_seed(&self, _seed: u64) -> Result<()> {
-        crate::bail!("cannot seed the CPU rng with set_seed")
+    fn set_seed(&self, seed: u64) -> Result<()> {
+        use rand::SeedableRng;
+
+        let rng = rand::rngs::StdRng::seed_from_u64(seed);
+        *SEEDED_RNG.lock().unwrap_or_else(|e| e.into_inner()) = Some(rng);
+        Ok(())
     }
 
     fn rand_uniform(&self, shape: &Shape, dtype: DType, min: f64, max: f64) -> Result<CpuStorage> {
         use rand::prelude::*;
 
         let elem_count = shape.elem_count();
-        let mut rng = rand::thread_rng();
-        match dtype {
-            DType::U8 | DType::U32 | DType::I64 => {
+        with_rng(|rng| match dtype {
+            DType::U8 | DType::U32 | DType::I64 | DType::C64 | DType::C128 => {
                 Err(Error::UnsupportedDTypeForOp(dtype, "rand_uniform").bt())
             }
             DType::BF16 => {
                 let mut data = Vec::with_capacity(elem_count);
-                let uniform =
-                    rand::distributions::Uniform::new(bf16::from_f64(min), bf16::from_f64(max));
+                let uniform = rand::distributions::Uniform::new(min as f32, max as f32);
                 for _i in 0..elem_count {
-                    data.push(rng.sample::<bf16, _>(uniform))
+                    data.push(bf16::from_f32(rng.sample::<f32, _>(uniform)))
                 }
                 Ok(CpuStorage::BF16(data))
             }
             DType::F16 => {
                 let mut data = Vec::with_capacity(elem_count);
-                let uniform =
-                    rand::distributions::Uniform::new(f16::from_f64(min), f16::from_f64(max));
+                let uniform = rand::distributions::Uniform::new(min as f32, max as f32);
                 for _i in 0..elem_count {
-                    data.push(rng.sample::<f16, _>(uniform))
+                    data.push(f16::from_f32(rng.sample::<f32, _>(uniform)))
                 }
                 Ok(CpuStorage::F16(data))
             }
@@ -2520,33 +2692,32 @@ impl BackendDevice for CpuDevice {
                 }
                 Ok(CpuStorage::F64(data))
             }
-        }
+        })
     }
 
     fn rand_normal(&self, shape: &Shape, dtype: DType, mean: f64, std: f64) -> Result<CpuStorage> {
         use rand::prelude::*;
 
         let elem_count = shape.elem_count();
-        let mut rng = rand::thread_rng();
-        match dtype {
-            DType::U8 | DType::U32 | DType::I64 => {
+        with_rng(|mut rng| match dtype {
+            DType::U8 | DType::U32 | DType::I64 | DType::C64 | DType::C128 => {
                 Err(Error::UnsupportedDTypeForOp(dtype, "rand_normal").bt())
             }
             DType::BF16 => {
                 let mut data = Vec::with_capacity(elem_count);
-                let normal = rand_distr::Normal::new(bf16::from_f64(mean), bf16::from_f64(std))
-                    .map_err(Error::wrap)?;
+                let normal =
+                    rand_distr::Normal::new(mean as f32, std as f32).map_err(Error::wrap)?;
                 for _i in 0..elem_count {
-                    data.push(normal.sample(&mut rng))
+                    data.push(bf16::from_f32(normal.sample(&mut rng)))
                 }
                 Ok(CpuStorage::BF16(data))
             }
             DType::F16 => {
                 let mut data = Vec::with_capacity(elem_count);
-                let normal = rand_distr::Normal::new(f16::from_f64(mean), f16::from_f64(std))
-                    .map_err(Error::wrap)?;
+                let normal =
+                    rand_distr::Normal::new(mean as f32, std as f32).map_err(Error::wrap)?;
                 for _i in 0..elem_count {
-                    data.push(normal.sample(&mut rng))
+                    data.push(f16::from_f32(normal.sample(&mut rng)))
                 }
                 Ok(CpuStorage::F16(data))
             }
@@ -2567,7 +2738,7 @@ impl BackendDevice for CpuDevice {
                 }
                 Ok(CpuStorage::F64(data))
             }
-        }
+        })
     }
 
     #[allow(clippy::uninit_vec)]
@@ -2613,6 +2784,16 @@ impl BackendDevice for CpuDevice {
                 v.set_len(elem_count);
                 CpuStorage::F64(v)
             }
+            DType::C64 => {
+                let mut v = Vec::with_capacity(2 * elem_count);
+                v.set_len(2 * elem_count);
+                CpuStorage::C64(v)
+            }
+            DType::C128 => {
+                let mut v = Vec::with_capacity(2 * elem_count);
+                v.set_len(2 * elem_count);
+                CpuStorage::C128(v)
+            }
         };
         Ok(storage)
     }
@@ -2627,6 +2808,8 @@ impl BackendDevice for CpuDevice {
             DType::F16 => CpuStorage::F16(vec![f16::ONE; elem_count]),
             DType::F32 => CpuStorage::F32(vec![1f32; elem_count]),
             DType::F64 => CpuStorage::F64(vec![1f64; elem_count]),
+            DType::C64 => CpuStorage::C64([1f32, 0f32].repeat(elem_count)),
+            DType::C128 => CpuStorage::C128([1f64, 0f64].repeat(elem_count)),
         };
         Ok(storage)
     }
@@ -2641,6 +2824,8 @@ impl BackendDevice for CpuDevice {
             DType::F16 => CpuStorage::F16(vec![f16::ZERO; elem_count]),
             DType::F32 => CpuStorage::F32(vec![0f32; elem_count]),
             DType::F64 => CpuStorage::F64(vec![0f64; elem_count]),
+            DType::C64 => CpuStorage::C64(vec![0f32; 2 * elem_count]),
+            DType::C128 => CpuStorage::C128(vec![0f64; 2 * elem_count]),
         };
         Ok(storage)
     }
@@ -0,0 +1,177 @@
+//! Caching allocator for the cuda backend.
+//!
+//! Buffers released by dropped tensors are kept in free lists bucketed by their byte size and
+//! handed back on the next allocation of the same size, avoiding a `cudaMalloc`/`cudaFree` pair
+//! per tensor when the same shapes are allocated over and over, e.g. in autoregressive decoding.
+//! Buckets use the exact byte size of the released slice: a slice may come from an allocation
+//! that did not go through the cache so this is the only size known to be available.
+use std::collections::BTreeMap;
+
+/// The environment variable used to cap the number of bytes kept in the cache, caching is
+/// disabled when set to 0.
+pub const CACHE_LIMIT_ENV_VAR: &str = "CANDLE_CUDA_CACHE_LIMIT";
+
+/// Allocation statistics for the caching allocator of a cuda device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of buffers that had to be allocated on the device.
+    pub allocations: usize,
+    /// Number of allocations that were served from the cache.
+    pub hits: usize,
+    /// Number of buffers currently held in the free lists.
+    pub cached_buffers: usize,
+    /// Number of bytes currently held in the free lists.
+    pub cached_bytes: usize,
+}
+
+pub(crate) struct BufferCache<B> {
+    free: BTreeMap<usize, Vec<B>>,
+    limit: usize,
+    stats: CacheStats,
+}
+
+impl<B> BufferCache<B> {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            free: BTreeMap::new(),
+            limit,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Creates a cache using the limit from the `CANDLE_CUDA_CACHE_LIMIT` environment variable,
+    /// the cache is unbounded when the variable is not set.
+    pub(crate) fn from_env() -> Self {
+        let limit = match std::env::var(CACHE_LIMIT_ENV_VAR) {
+            Ok(v) => v.parse().unwrap_or(usize::MAX),
+            Err(_) => usize::MAX,
+        };
+        Self::new(limit)
+    }
+
+    /// Returns a cached buffer of `bytes` bytes if available, otherwise allocates a new one using
+    /// `alloc`. When the allocation fails, the cache is emptied and the allocation is retried.
+    pub(crate) fn alloc<E>(
+        &mut self,
+        bytes: usize,
+        mut alloc: impl FnMut(usize) -> Result<B, E>,
+    ) -> Result<B, E> {
+        if let Some(buffers) = self.free.get_mut(&bytes) {
+            if let Some(buffer) = buffers.pop() {
+                if buffers.is_empty() {
+                    self.free.remove(&bytes);
+                }
+                self.stats.hits += 1;
+                self.stats.cached_buffers -= 1;
+                self.stats.cached_bytes -= bytes;
+                return Ok(buffer);
+            }
+        }
+        let buffer = match alloc(bytes) {
+            Ok(buffer) => buffer,
+            Err(_) if self.stats.cached_buffers > 0 => {
+                self.clear();
+                alloc(bytes)?
+            }
+            Err(err) => return Err(err),
+        };
+        self.stats.allocations += 1;
+        Ok(buffer)
+    }
+
+    /// Adds a buffer of `bytes` bytes to the free lists. The buffer is returned when it would
+    /// exceed the cache limit so that the caller can free it.
+    pub(crate) fn release(&mut self, bytes: usize, buffer: B) -> Option<B> {
+        if self.stats.cached_bytes.saturating_add(bytes) > self.limit {
+            return Some(buffer);
+        }
+        self.free.entry(bytes).or_default().push(buffer);
+        self.stats.cached_buffers += 1;
+        self.stats.cached_bytes += bytes;
+        None
+    }
+
+    /// Drops all the cached buffers.
+    pub(crate) fn clear(&mut self) {
+        self.free.clear();
+        self.stats.cached_buffers = 0;
+        self.stats.cached_bytes = 0;
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alloc(counter: &mut usize) -> impl FnMut(usize) -> Result<Vec<u8>, ()> + '_ {
+        |bytes| {
+            *counter += 1;
+            Ok(vec![0u8; bytes])
+        }
+    }
+
+    #[test]
+    fn reuse() {
+        let mut cache = BufferCache::new(usize::MAX);
+        let mut n_allocs = 0;
+        for _ in 0..100 {
+            let a = cache.alloc(128, alloc(&mut n_allocs)).unwrap();
+            let b = cache.alloc(256, alloc(&mut n_allocs)).unwrap();
+            assert!(cache.release(128, a).is_none());
+            assert!(cache.release(256, b).is_none());
+        }
+        assert_eq!(n_allocs, 2);
+        let stats = cache.stats();
+        assert_eq!(stats.allocations, 2);
+        assert_eq!(stats.hits, 198);
+        assert_eq!(stats.cached_buffers, 2);
+        assert_eq!(stats.cached_bytes, 384);
+        // Buffers of a different size are never handed out.
+        let c = cache.alloc(64, alloc(&mut n_allocs)).unwrap();
+        assert_eq!(c.len(), 64);
+        assert_eq!(n_allocs, 3);
+        cache.clear();
+        assert_eq!(cache.stats().cached_bytes, 0);
+        cache.alloc(128, alloc(&mut n_allocs)).unwrap();
+        assert_eq!(n_allocs, 4);
+    }
+
+    #[test]
+    fn limit() {
+        let mut cache = BufferCache::new(200);
+        let a = cache.alloc(128, |b| Ok::<_, ()>(vec![0u8; b])).unwrap();
+        let b = cache.alloc(128, |b| Ok::<_, ()>(vec![0u8; b])).unwrap();
+        assert!(cache.release(128, a).is_none());
+        assert!(cache.release(128, b).is_some());
+        assert_eq!(cache.stats().cached_bytes, 128);
+
+        let mut cache = BufferCache::new(0);
+        let a = cache.alloc(128, |b| Ok::<_, ()>(vec![0u8; b])).unwrap();
+        assert!(cache.release(128, a).is_some());
+    }
+
+    #[test]
+    fn retry_after_clear() {
+        let mut cache = BufferCache::new(usize::MAX);
+        let a = cache.alloc(128, |b| Ok::<_, ()>(vec![0u8; b])).unwrap();
+        cache.release(128, a);
+        // Simulate an out of memory error that goes away once the cache has been emptied.
+        let mut n_calls = 0;
+        let b = cache.alloc(64, |b| {
+            n_calls += 1;
+            if n_calls == 1 {
+                Err(())
+            } else {
+                Ok(vec![0u8; b])
+            }
+        });
+        assert_eq!(b.unwrap().len(), 64);
+        assert_eq!(n_calls, 2);
+        assert_eq!(cache.stats().cached_buffers, 0);
+        assert!(cache.alloc(32, |_| Err::<Vec<u8>, _>(())).is_err());
+    }
+}
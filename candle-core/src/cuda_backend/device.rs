@@ -2,10 +2,11 @@ use crate::backend::BackendDevice;
 use crate::{CpuStorage, CpuStorageRef, DType, Layout, Result, Shape};
 pub use candle_kernels as kernels;
 pub use cudarc;
-use cudarc::driver::{CudaFunction, LaunchAsync, LaunchConfig};
+use cudarc::driver::{CudaFunction, CudaSlice, LaunchAsync, LaunchConfig};
 use half::{bf16, f16};
 use std::sync::{Arc, Mutex};
 
+use super::cache::{BufferCache, CacheStats};
 use super::{CudaError, CudaStorage, CudaStorageSlice, WrapErr};
 
 /// Unique identifier for cuda devices.
@@ -24,12 +25,117 @@ impl DeviceId {
 struct CudaRng(cudarc::curand::CudaRng);
 unsafe impl Send for CudaRng {}
 
+/// Page-locked host memory used to stage the asynchronous host to device copies.
+struct PinnedBuffer {
+    ptr: *mut std::ffi::c_void,
+    len: usize,
+}
+
+impl PinnedBuffer {
+    fn new(len: usize) -> Result<Self> {
+        let mut ptr = std::ptr::null_mut();
+        // SAFETY: ffi, the memory is released on drop.
+        unsafe { cudarc::driver::sys::cuMemAllocHost_v2(&mut ptr, len) }
+            .result()
+            .w()?;
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Drop for PinnedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: ffi, the pointer was allocated with cuMemAllocHost.
+        let _ = unsafe { cudarc::driver::sys::cuMemFreeHost(self.ptr) };
+    }
+}
+
+/// An event recorded on the default stream when a buffer is handed to the caching allocator.
+/// The kernels enqueued before the release may still be using the buffer, so work on other
+/// streams reusing the buffer has to wait for this event. Work on the default stream is already
+/// ordered after these kernels.
+struct ReleaseEvent {
+    event: cudarc::driver::sys::CUevent,
+    device: Arc<cudarc::driver::CudaDevice>,
+}
+unsafe impl Send for ReleaseEvent {}
+
+impl ReleaseEvent {
+    fn new(device: &Arc<cudarc::driver::CudaDevice>) -> Result<Self> {
+        use cudarc::driver::sys::CUevent_flags;
+        device.bind_to_thread().w()?;
+        let event =
+            cudarc::driver::result::event::create(CUevent_flags::CU_EVENT_DISABLE_TIMING).w()?;
+        Ok(Self {
+            event,
+            device: device.clone(),
+        })
+    }
+}
+
+impl Drop for ReleaseEvent {
+    fn drop(&mut self) {
+        if self.device.bind_to_thread().is_ok() {
+            // SAFETY: ffi, the event is not used anymore.
+            let _ = unsafe { cudarc::driver::result::event::destroy(self.event) };
+        }
+    }
+}
+
+/// A buffer held by the caching allocator, `released` is `None` for buffers that have just been
+/// allocated.
+struct CachedBuffer {
+    buffer: CudaSlice<u8>,
+    released: Option<ReleaseEvent>,
+}
+
+/// A stream dedicated to host to device copies so that they can overlap with the computations
+/// enqueued on the default stream, together with a pool of staging buffers. The buffers used by
+/// the enqueued copies are only reused once the copy stream has been synchronized.
+struct CopyStream {
+    stream: cudarc::driver::CudaStream,
+    free: Vec<PinnedBuffer>,
+    in_flight: Vec<PinnedBuffer>,
+}
+unsafe impl Send for CopyStream {}
+
+impl CopyStream {
+    fn synchronize(&mut self) -> Result<()> {
+        // SAFETY: ffi.
+        unsafe { cudarc::driver::result::stream::synchronize(self.stream.stream) }.w()?;
+        self.free.append(&mut self.in_flight);
+        Ok(())
+    }
+
+    fn acquire(&mut self, len: usize) -> Result<PinnedBuffer> {
+        let find = |free: &mut Vec<PinnedBuffer>| {
+            let idx = free.iter().position(|b| b.len >= len)?;
+            Some(free.swap_remove(idx))
+        };
+        if let Some(buffer) = find(&mut self.free) {
+            return Ok(buffer);
+        }
+        if !self.in_flight.is_empty() {
+            self.synchronize()?;
+            if let Some(buffer) = find(&mut self.free) {
+                return Ok(buffer);
+            }
+        }
+        // None of the pooled buffers is large enough, release them before allocating.
+        self.free.clear();
+        PinnedBuffer::new(len)
+    }
+}
+
 #[derive(Clone)]
 pub struct CudaDevice {
     id: DeviceId,
     device: Arc<cudarc::driver::CudaDevice>,
     pub(crate) blas: Arc<cudarc::cublas::CudaBlas>,
     curand: Arc<Mutex<CudaRng>>,
+    cache: Arc<Mutex<BufferCache<CachedBuffer>>>,
+    // The events of the buffers that have been taken out of the cache, reused on release.
+    release_events: Arc<Mutex<Vec<ReleaseEvent>>>,
+    copy_stream: Arc<Mutex<Option<CopyStream>>>,
 }
 
 impl std::fmt::Debug for CudaDevice {
@@ -55,6 +161,105 @@ impl CudaDevice {
         self.id
     }
 
+    /// Allocates an uninitialized slice, reusing a buffer from the caching allocator when one of
+    /// the same size is available.
+    ///
+    /// # Safety
+    /// The content of the returned slice is not initialized.
+    pub unsafe fn alloc<T: DeviceRepr>(
+        &self,
+        len: usize,
+    ) -> std::result::Result<CudaSlice<T>, DriverError> {
+        let bytes = len * std::mem::size_of::<T>();
+        if bytes == 0 {
+            return self.device.alloc::<T>(len);
+        }
+        let buffer = self.cache.lock().unwrap().alloc(bytes, |bytes| {
+            let buffer = self.device.alloc::<u8>(bytes)?;
+            Ok(CachedBuffer {
+                buffer,
+                released: None,
+            })
+        })?;
+        // The buffer is used on the default stream, after the kernels that used it before its
+        // release, so there is no need to wait for the release event.
+        self.recycle_release_event(buffer.released);
+        Ok(self.device.upgrade_device_ptr(buffer.buffer.leak(), len))
+    }
+
+    fn recycle_release_event(&self, event: Option<ReleaseEvent>) {
+        if let Some(event) = event {
+            self.release_events.lock().unwrap().push(event)
+        }
+    }
+
+    fn record_release_event(&self) -> Result<ReleaseEvent> {
+        let event = self.release_events.lock().unwrap().pop();
+        let event = match event {
+            Some(event) => event,
+            None => ReleaseEvent::new(&self.device)?,
+        };
+        // SAFETY: ffi.
+        unsafe { cudarc::driver::result::event::record(event.event, *self.device.cu_stream()) }
+            .w()?;
+        Ok(event)
+    }
+
+    /// Allocates a zeroed slice, reusing a buffer from the caching allocator when one of the same
+    /// size is available.
+    pub fn alloc_zeros<T: ValidAsZeroBits + DeviceRepr>(
+        &self,
+        len: usize,
+    ) -> std::result::Result<CudaSlice<T>, DriverError> {
+        // SAFETY: the slice is zeroed before being returned.
+        let mut slice = unsafe { self.alloc::<T>(len) }?;
+        self.device.memset_zeros(&mut slice)?;
+        Ok(slice)
+    }
+
+    fn release_slice<T>(&self, slice: CudaSlice<T>) {
+        let bytes = slice.len() * std::mem::size_of::<T>();
+        if bytes == 0 {
+            return;
+        }
+        // SAFETY: the slice owns at least `bytes` bytes starting at this pointer.
+        let buffer = unsafe { self.device.upgrade_device_ptr::<u8>(slice.leak(), bytes) };
+        // Without a release event the buffer could not be reused safely on the copy stream, so
+        // it is freed rather than cached.
+        let released = match self.record_release_event() {
+            Ok(event) => Some(event),
+            Err(_) => return,
+        };
+        let buffer = CachedBuffer { buffer, released };
+        // A buffer that does not fit in the cache is returned and freed here, after the lock
+        // has been released.
+        let _buffer = self.cache.lock().unwrap().release(bytes, buffer);
+    }
+
+    /// Hands the buffers of a storage that is not used anymore to the caching allocator.
+    pub(crate) fn release(&self, slice: CudaStorageSlice) {
+        match slice {
+            CudaStorageSlice::U8(s) => self.release_slice(s),
+            CudaStorageSlice::U32(s) => self.release_slice(s),
+            CudaStorageSlice::I64(s) => self.release_slice(s),
+            CudaStorageSlice::BF16(s) => self.release_slice(s),
+            CudaStorageSlice::F16(s) => self.release_slice(s),
+            CudaStorageSlice::F32(s) => self.release_slice(s),
+            CudaStorageSlice::F64(s) => self.release_slice(s),
+        }
+    }
+
+    /// Frees all the buffers held by the caching allocator.
+    pub fn empty_cache(&self) -> Result<()> {
+        self.cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    /// Returns the statistics of the caching allocator for this device.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
     fn const_impl(&self, v: f64, shape: &Shape, dtype: DType) -> Result<CudaStorage> {
         let elem_count = shape.elem_count();
         let cfg = LaunchConfig::for_num_elems(elem_count as u32);
@@ -115,6 +320,11 @@ impl CudaDevice {
                 unsafe { func.launch(cfg, params) }.w()?;
                 CudaStorageSlice::F64(data)
             }
+            DType::C64 | DType::C128 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "const_impl",
+            })
+            .w()?,
         };
         Ok(CudaStorage {
             slice,
@@ -122,6 +332,81 @@ impl CudaDevice {
         })
     }
 
+    /// Copies `src` to a new device buffer using the copy stream, `src` is first copied to a
+    /// pinned staging buffer so that this returns as soon as the copy has been enqueued. The
+    /// default stream waits for the copy, so the computations using the result are ordered
+    /// after it while the computations already enqueued can run concurrently.
+    fn htod_async_copy<T: cudarc::driver::DeviceRepr>(&self, src: &[T]) -> Result<CudaSlice<T>> {
+        use cudarc::driver::DevicePtr;
+
+        let len = std::mem::size_of_val(src);
+        if len == 0 {
+            // SAFETY: the slice is empty.
+            return unsafe { self.alloc::<T>(0) }.w();
+        }
+        self.device.bind_to_thread().w()?;
+        // The buffer is first used on the copy stream rather than on the default stream, so new
+        // buffers are allocated synchronously rather than in the default stream order.
+        let buffer = self.cache.lock().unwrap().alloc(len, |bytes| {
+            // SAFETY: ffi, the buffer is owned by the returned slice.
+            let buffer = unsafe {
+                let ptr = cudarc::driver::result::malloc_sync(bytes)?;
+                self.device.upgrade_device_ptr::<u8>(ptr, bytes)
+            };
+            Ok(CachedBuffer {
+                buffer,
+                released: None,
+            })
+        });
+        let CachedBuffer { buffer, released } = buffer.w()?;
+        // SAFETY: the buffer has `len` bytes, i.e. `src.len()` elements of type `T`.
+        let dst = unsafe {
+            self.device
+                .upgrade_device_ptr::<T>(buffer.leak(), src.len())
+        };
+        let mut copy_stream = self.copy_stream.lock().unwrap();
+        let copy_stream = match copy_stream.as_mut() {
+            Some(copy_stream) => copy_stream,
+            None => copy_stream.insert(CopyStream {
+                stream: self.device.fork_default_stream().w()?,
+                free: vec![],
+                in_flight: vec![],
+            }),
+        };
+        if let Some(released) = released {
+            // A cached buffer may still be used by the kernels that were enqueued on the default
+            // stream before it was released.
+            // SAFETY: ffi.
+            unsafe {
+                cudarc::driver::result::stream::wait_event(
+                    copy_stream.stream.stream,
+                    released.event,
+                    cudarc::driver::sys::CUevent_wait_flags::CU_EVENT_WAIT_DEFAULT,
+                )
+            }
+            .w()?;
+            self.recycle_release_event(Some(released));
+        }
+        let buffer = copy_stream.acquire(len)?;
+        // SAFETY: the buffer has at least `len` bytes and is not used by any pending copy.
+        let pinned = unsafe {
+            std::ptr::copy_nonoverlapping(src.as_ptr() as *const u8, buffer.ptr as *mut u8, len);
+            std::slice::from_raw_parts(buffer.ptr as *const T, src.len())
+        };
+        // SAFETY: ffi, the staging buffer is kept alive until the copy stream is synchronized.
+        unsafe {
+            cudarc::driver::result::memcpy_htod_async(
+                *dst.device_ptr(),
+                pinned,
+                copy_stream.stream.stream,
+            )
+        }
+        .w()?;
+        copy_stream.in_flight.push(buffer);
+        self.device.wait_for(&copy_stream.stream).w()?;
+        Ok(dst)
+    }
+
     pub fn get_or_load_func(&self, module_name: &str, ptx: &'static str) -> Result<CudaFunction> {
         if !self.has_func(module_name, module_name) {
             // Leaking the string here is a bit sad but we need a &'static str and this is only
@@ -156,6 +441,9 @@ impl BackendDevice for CudaDevice {
             device,
             blas: Arc::new(blas),
             curand: Arc::new(Mutex::new(CudaRng(curand))),
+            cache: Arc::new(Mutex::new(BufferCache::from_env())),
+            release_events: Arc::new(Mutex::new(vec![])),
+            copy_stream: Arc::new(Mutex::new(None)),
         })
     }
 
@@ -208,6 +496,11 @@ impl BackendDevice for CudaDevice {
                 let data = self.alloc_zeros::<f64>(elem_count).w()?;
                 CudaStorageSlice::F64(data)
             }
+            DType::C64 | DType::C128 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "zeros_impl",
+            })
+            .w()?,
         };
         Ok(CudaStorage {
             slice,
@@ -221,13 +514,17 @@ impl BackendDevice for CudaDevice {
         let slice = match dtype {
             // TODO: Add support for F16 and BF16 though this is likely to require some upstream
             // cudarc changes.
-            DType::U8 | DType::U32 | DType::I64 | DType::F16 | DType::BF16 => {
-                Err(CudaError::UnsupportedDtype {
-                    dtype,
-                    op: "rand_uniform",
-                })
-                .w()?
-            }
+            DType::U8
+            | DType::U32
+            | DType::I64
+            | DType::F16
+            | DType::BF16
+            | DType::C64
+            | DType::C128 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "rand_uniform",
+            })
+            .w()?,
             DType::F32 => {
                 let mut data = unsafe { self.alloc::<f32>(elem_count) }.w()?;
                 curand.0.fill_with_uniform(&mut data).w()?;
@@ -265,13 +562,17 @@ impl BackendDevice for CudaDevice {
             elem_count
         };
         let slice = match dtype {
-            DType::U8 | DType::U32 | DType::I64 | DType::F16 | DType::BF16 => {
-                Err(CudaError::UnsupportedDtype {
-                    dtype,
-                    op: "rand_normal",
-                })
-                .w()?
-            }
+            DType::U8
+            | DType::U32
+            | DType::I64
+            | DType::F16
+            | DType::BF16
+            | DType::C64
+            | DType::C128 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "rand_normal",
+            })
+            .w()?,
             DType::F32 => {
                 let mut data = unsafe { self.alloc::<f32>(elem_count_round) }.w()?;
                 curand
@@ -327,6 +628,11 @@ impl BackendDevice for CudaDevice {
                 let data = self.alloc::<f64>(elem_count).w()?;
                 CudaStorageSlice::F64(data)
             }
+            DType::C64 | DType::C128 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "alloc_uninit",
+            })
+            .w()?,
         };
         Ok(CudaStorage {
             slice,
@@ -401,6 +707,16 @@ impl BackendDevice for CudaDevice {
                 let data = self.htod_sync_copy(storage).w()?;
                 CudaStorageSlice::F64(data)
             }
+            CpuStorage::C64(_) => Err(CudaError::UnsupportedDtype {
+                dtype: DType::C64,
+                op: "storage_from_cpu_storage",
+            })
+            .w()?,
+            CpuStorage::C128(_) => Err(CudaError::UnsupportedDtype {
+                dtype: DType::C128,
+                op: "storage_from_cpu_storage",
+            })
+            .w()?,
         };
         Ok(CudaStorage {
             slice,
@@ -438,6 +754,35 @@ impl BackendDevice for CudaDevice {
                 let data = self.htod_copy(storage).w()?;
                 CudaStorageSlice::F64(data)
             }
+            CpuStorage::C64(_) => Err(CudaError::UnsupportedDtype {
+                dtype: DType::C64,
+                op: "storage_from_cpu_storage_owned",
+            })
+            .w()?,
+            CpuStorage::C128(_) => Err(CudaError::UnsupportedDtype {
+                dtype: DType::C128,
+                op: "storage_from_cpu_storage_owned",
+            })
+            .w()?,
+        };
+        Ok(CudaStorage {
+            slice,
+            device: self.clone(),
+        })
+    }
+
+    fn storage_from_cpu_storage_async(&self, storage: &CpuStorage) -> Result<CudaStorage> {
+        let slice = match storage {
+            CpuStorage::U8(storage) => CudaStorageSlice::U8(self.htod_async_copy(storage)?),
+            CpuStorage::U32(storage) => CudaStorageSlice::U32(self.htod_async_copy(storage)?),
+            CpuStorage::I64(storage) => CudaStorageSlice::I64(self.htod_async_copy(storage)?),
+            CpuStorage::BF16(storage) => CudaStorageSlice::BF16(self.htod_async_copy(storage)?),
+            CpuStorage::F16(storage) => CudaStorageSlice::F16(self.htod_async_copy(storage)?),
+            CpuStorage::F32(storage) => CudaStorageSlice::F32(self.htod_async_copy(storage)?),
+            CpuStorage::F64(storage) => CudaStorageSlice::F64(self.htod_async_copy(storage)?),
+            CpuStorage::C64(_) | CpuStorage::C128(_) => {
+                return self.storage_from_cpu_storage(storage)
+            }
         };
         Ok(CudaStorage {
             slice,
@@ -447,6 +792,10 @@ impl BackendDevice for CudaDevice {
 
     fn synchronize(&self) -> Result<()> {
         self.device.synchronize().map_err(crate::Error::wrap)?;
+        // All the copies have completed, the staging buffers can be reused.
+        if let Some(copy_stream) = self.copy_stream.lock().unwrap().as_mut() {
+            copy_stream.synchronize()?
+        }
         Ok(())
     }
 }
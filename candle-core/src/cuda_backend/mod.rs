@@ -9,11 +9,13 @@ use cudarc::driver::{
 };
 use half::{bf16, f16};
 
+mod cache;
 #[cfg(feature = "cudnn")]
 pub mod cudnn;
 mod device;
 mod error;
 mod utils;
+pub use cache::{CacheStats, CACHE_LIMIT_ENV_VAR};
 pub use device::{CudaDevice, DeviceId};
 pub use error::{CudaError, WrapErr};
 pub use utils::{Map1, Map1Any, Map2, Map2Any, Map2InPlace, Map3, S};
@@ -1040,6 +1042,18 @@ impl CudaStorage {
     }
 }
 
+impl Drop for CudaStorage {
+    fn drop(&mut self) {
+        // The buffers are handed to the caching allocator of the device rather than freed. The
+        // slice cannot be moved out of `self` so it is swapped with an empty one, if creating it
+        // fails the buffers are freed as usual.
+        if let Ok(empty) = self.device.null::<u8>() {
+            let slice = std::mem::replace(&mut self.slice, CudaStorageSlice::U8(empty));
+            self.device.release(slice)
+        }
+    }
+}
+
 fn gemm_config<T>(
     alpha: T,
     beta: T,
@@ -1180,6 +1194,13 @@ impl BackendStorage for CudaStorage {
         };
         let inp = &inp;
 
+        if dtype.is_complex() {
+            Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "to_dtype",
+            })
+            .w()?
+        }
         let kernel_name = format!("cast_{}_{}", self.dtype().as_str(), dtype.as_str());
         let func = dev.get_or_load_func(&kernel_name, kernels::CAST)?;
         let slice = match dtype {
@@ -1225,6 +1246,7 @@ impl BackendStorage for CudaStorage {
                 unsafe { func.launch(cfg, params) }.w()?;
                 CudaStorageSlice::F64(out)
             }
+            DType::C64 | DType::C128 => unreachable!(),
         };
         Ok(Self {
             slice,
@@ -1,6 +1,6 @@
 use crate::op::{BackpropOp, Op};
 use crate::tensor::from_storage;
-use crate::{CpuStorage, CudaStorage, Layout, MetalStorage, Result, Shape, Tensor};
+use crate::{CpuStorage, CudaStorage, Layout, MetalStorage, Result, Shape, Tensor, WgpuStorage};
 use std::sync::Arc;
 
 /// Unary ops that can be defined in user-land.
@@ -32,6 +32,14 @@ pub trait CustomOp1 {
         ))
     }
 
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(&self, _storage: &WgpuStorage, _layout: &Layout) -> Result<(WgpuStorage, Shape)> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
+
     /// This function takes as argument the argument `arg` used in the forward pass, the result
     /// produced by the forward operation `res` and the gradient of the result `grad_res`.
     /// The function should return the gradient of the argument.
@@ -81,6 +89,20 @@ pub trait CustomOp2 {
         ))
     }
 
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(
+        &self,
+        _: &WgpuStorage,
+        _: &Layout,
+        _: &WgpuStorage,
+        _: &Layout,
+    ) -> Result<(WgpuStorage, Shape)> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
+
     fn bwd(
         &self,
         _arg1: &Tensor,
@@ -139,6 +161,22 @@ pub trait CustomOp3 {
         ))
     }
 
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(
+        &self,
+        _: &WgpuStorage,
+        _: &Layout,
+        _: &WgpuStorage,
+        _: &Layout,
+        _: &WgpuStorage,
+        _: &Layout,
+    ) -> Result<(WgpuStorage, Shape)> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
+
     fn bwd(
         &self,
         _arg1: &Tensor,
@@ -155,7 +193,7 @@ impl Tensor {
     /// Applies a unary custom op without backward support
     pub fn apply_op1_no_bwd<C: CustomOp1>(&self, c: &C) -> Result<Self> {
         let (storage, shape) = self.storage().apply_op1(self.layout(), c)?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        from_storage(storage, shape, BackpropOp::none(), false)
     }
 
     /// Applies a binary custom op without backward support
@@ -163,7 +201,7 @@ impl Tensor {
         let (storage, shape) =
             self.storage()
                 .apply_op2(self.layout(), &rhs.storage(), rhs.layout(), c)?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        from_storage(storage, shape, BackpropOp::none(), false)
     }
 
     /// Applies a ternary custom op without backward support
@@ -176,7 +214,7 @@ impl Tensor {
             t3.layout(),
             c,
         )?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), false))
+        from_storage(storage, shape, BackpropOp::none(), false)
     }
 
     /// Applies a unary custom op.
@@ -185,7 +223,7 @@ impl Tensor {
             .storage()
             .apply_op1(self.layout(), c.as_ref().as_ref())?;
         let op = BackpropOp::new1(self, |s| Op::CustomOp1(s, c.clone()));
-        Ok(from_storage(storage, shape, op, false))
+        from_storage(storage, shape, op, false)
     }
 
     pub fn apply_op1<C: 'static + CustomOp1 + Send + Sync>(&self, c: C) -> Result<Self> {
@@ -205,7 +243,7 @@ impl Tensor {
             c.as_ref().as_ref(),
         )?;
         let op = BackpropOp::new2(self, rhs, |t1, t2| Op::CustomOp2(t1, t2, c.clone()));
-        Ok(from_storage(storage, shape, op, false))
+        from_storage(storage, shape, op, false)
     }
 
     pub fn apply_op2<C: 'static + CustomOp2 + Send + Sync>(&self, r: &Self, c: C) -> Result<Self> {
@@ -230,7 +268,7 @@ impl Tensor {
         let op = BackpropOp::new3(self, t2, t3, |t1, t2, t3| {
             Op::CustomOp3(t1, t2, t3, c.clone())
         });
-        Ok(from_storage(storage, shape, op, false))
+        from_storage(storage, shape, op, false)
     }
 
     pub fn apply_op3<C: 'static + CustomOp3 + Send + Sync>(
@@ -270,6 +308,14 @@ pub trait InplaceOp1 {
             format!("no metal implementation for {}", self.name()).into(),
         ))
     }
+
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(&self, _storage: &mut WgpuStorage, _layout: &Layout) -> Result<()> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
 }
 
 pub trait InplaceOp2 {
@@ -301,6 +347,14 @@ pub trait InplaceOp2 {
             format!("no metal implementation for {}", self.name()).into(),
         ))
     }
+
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(&self, _: &mut WgpuStorage, _: &Layout, _: &WgpuStorage, _: &Layout) -> Result<()> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
 }
 
 pub trait InplaceOp3 {
@@ -349,6 +403,22 @@ pub trait InplaceOp3 {
             format!("no metal implementation for {}", self.name()).into(),
         ))
     }
+
+    /// The forward pass, as run on a wgpu device. Note that the storage can use arbitrary strides,
+    /// offsets etc so the associated layout should be used to access it.
+    fn wgpu_fwd(
+        &self,
+        _: &mut WgpuStorage,
+        _: &Layout,
+        _: &WgpuStorage,
+        _: &Layout,
+        _: &WgpuStorage,
+        _: &Layout,
+    ) -> Result<()> {
+        Err(crate::Error::Wgpu(
+            format!("no wgpu implementation for {}", self.name()).into(),
+        ))
+    }
 }
 
 impl Tensor {
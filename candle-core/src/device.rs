@@ -9,6 +9,7 @@ pub enum DeviceLocation {
     Cpu,
     Cuda { gpu_id: usize },
     Metal { gpu_id: usize },
+    Wgpu { gpu_id: usize },
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +17,7 @@ pub enum Device {
     Cpu,
     Cuda(crate::CudaDevice),
     Metal(crate::MetalDevice),
+    Wgpu(crate::WgpuDevice),
 }
 
 pub trait NdArray {
@@ -134,11 +136,21 @@ impl Device {
         Ok(Self::Metal(crate::MetalDevice::new(ordinal)?))
     }
 
+    /// Creates a device using the `ordinal`-th adapter reported by wgpu, this can be a Vulkan,
+    /// Metal, DX12 or OpenGL adapter depending on the platform.
+    pub fn new_wgpu(ordinal: usize) -> Result<Self> {
+        Ok(Self::Wgpu(crate::WgpuDevice::new(ordinal)?))
+    }
+
+    /// Seeds the random number generator used by `Tensor::rand` and `Tensor::randn` on this
+    /// device. There is a single cpu device so on cpu the seeded generator is shared by all the
+    /// threads of the process.
     pub fn set_seed(&self, seed: u64) -> Result<()> {
         match self {
             Self::Cpu => CpuDevice.set_seed(seed),
             Self::Cuda(c) => c.set_seed(seed),
             Self::Metal(m) => m.set_seed(seed),
+            Self::Wgpu(m) => m.set_seed(seed),
         }
     }
 
@@ -147,6 +159,7 @@ impl Device {
             (Self::Cpu, Self::Cpu) => true,
             (Self::Cuda(lhs), Self::Cuda(rhs)) => lhs.same_device(rhs),
             (Self::Metal(lhs), Self::Metal(rhs)) => lhs.same_device(rhs),
+            (Self::Wgpu(lhs), Self::Wgpu(rhs)) => lhs.same_device(rhs),
             _ => false,
         }
     }
@@ -156,6 +169,7 @@ impl Device {
             Self::Cpu => DeviceLocation::Cpu,
             Self::Cuda(device) => device.location(),
             Device::Metal(device) => device.location(),
+            Device::Wgpu(device) => device.location(),
         }
     }
 
@@ -171,6 +185,10 @@ impl Device {
         matches!(self, Self::Metal(_))
     }
 
+    pub fn is_wgpu(&self) -> bool {
+        matches!(self, Self::Wgpu(_))
+    }
+
     pub fn cuda_if_available(ordinal: usize) -> Result<Self> {
         if crate::utils::cuda_is_available() {
             Self::new_cuda(ordinal)
@@ -205,6 +223,10 @@ impl Device {
                 let storage = device.rand_uniform(shape, dtype, lo, up)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.rand_uniform(shape, dtype, lo, up)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -243,6 +265,10 @@ impl Device {
                 let storage = device.rand_normal(shape, dtype, mean, std)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.rand_normal(shape, dtype, mean, std)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -269,6 +295,10 @@ impl Device {
                 let storage = device.ones_impl(shape, dtype)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.ones_impl(shape, dtype)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -286,6 +316,10 @@ impl Device {
                 let storage = device.zeros_impl(shape, dtype)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.zeros_impl(shape, dtype)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -303,6 +337,10 @@ impl Device {
                 let storage = device.alloc_uninit(shape, dtype)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.alloc_uninit(shape, dtype)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -317,6 +355,10 @@ impl Device {
                 let storage = device.storage_from_slice(data)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = device.storage_from_slice(data)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -333,6 +375,11 @@ impl Device {
                 let storage = device.storage_from_cpu_storage_owned(storage)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = array.to_cpu_storage();
+                let storage = device.storage_from_cpu_storage_owned(storage)?;
+                Ok(Storage::Wgpu(storage))
+            }
         }
     }
 
@@ -349,6 +396,20 @@ impl Device {
                 let storage = device.storage_from_cpu_storage_owned(storage)?;
                 Ok(Storage::Metal(storage))
             }
+            Device::Wgpu(device) => {
+                let storage = S::to_cpu_storage_owned(data);
+                let storage = device.storage_from_cpu_storage_owned(storage)?;
+                Ok(Storage::Wgpu(storage))
+            }
+        }
+    }
+
+    /// Frees the device buffers kept around by the cuda caching allocator, this is a no-op on
+    /// the other devices.
+    pub fn empty_cache(&self) -> Result<()> {
+        match self {
+            Self::Cpu | Self::Metal(_) | Self::Wgpu(_) => Ok(()),
+            Self::Cuda(d) => d.empty_cache(),
         }
     }
 
@@ -357,6 +418,7 @@ impl Device {
             Self::Cpu => Ok(()),
             Self::Cuda(d) => d.synchronize(),
             Self::Metal(d) => d.synchronize(),
+            Self::Wgpu(d) => d.synchronize(),
         }
     }
 }
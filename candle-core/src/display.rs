@@ -17,6 +17,9 @@ impl Tensor {
             crate::DeviceLocation::Metal { gpu_id } => {
                 format!(", metal:{}", gpu_id)
             }
+            crate::DeviceLocation::Wgpu { gpu_id } => {
+                format!(", wgpu:{}", gpu_id)
+            }
         };
 
         write!(f, "Tensor[")?;
@@ -48,6 +51,45 @@ impl Tensor {
         }
         write!(f, "; {}{}]", self.dtype().as_str(), device_str)
     }
+
+    fn fmt_complex_dt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let device_str = match self.device().location() {
+            crate::DeviceLocation::Cpu => "".to_owned(),
+            crate::DeviceLocation::Cuda { gpu_id } => {
+                format!(", cuda:{}", gpu_id)
+            }
+            crate::DeviceLocation::Metal { gpu_id } => {
+                format!(", metal:{}", gpu_id)
+            }
+            crate::DeviceLocation::Wgpu { gpu_id } => {
+                format!(", wgpu:{}", gpu_id)
+            }
+        };
+
+        write!(f, "Tensor[")?;
+        match self.dims() {
+            [] | [_] if self.elem_count() < 10 => {
+                if let Ok(vs) = complex_values(self) {
+                    for (i, (re, im)) in vs.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{re}{im:+}i")?;
+                    }
+                }
+            }
+            dims => {
+                write!(f, "dims ")?;
+                for (i, d) in dims.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{d}")?;
+                }
+            }
+        }
+        write!(f, "; {}{}]", self.dtype().as_str(), device_str)
+    }
 }
 
 impl std::fmt::Debug for Tensor {
@@ -60,6 +102,7 @@ impl std::fmt::Debug for Tensor {
             DType::F16 => self.fmt_dt::<f16>(f),
             DType::F32 => self.fmt_dt::<f32>(f),
             DType::F64 => self.fmt_dt::<f64>(f),
+            DType::C64 | DType::C128 => self.fmt_complex_dt(f),
         }
     }
 }
@@ -164,13 +207,16 @@ impl std::fmt::Write for FmtSize {
 }
 
 trait TensorFormatter {
-    type Elem: WithDType;
+    type Elem: Copy;
 
     fn fmt<T: std::fmt::Write>(&self, v: Self::Elem, max_w: usize, f: &mut T) -> std::fmt::Result;
 
+    /// Returns the elements of the tensor in row-major order.
+    fn values(&self, t: &Tensor) -> Result<Vec<Self::Elem>>;
+
     fn max_width(&self, to_display: &Tensor) -> usize {
         let mut max_width = 1;
-        if let Ok(vs) = to_display.flatten_all().and_then(|t| t.to_vec1()) {
+        if let Ok(vs) = self.values(to_display) {
             for &v in vs.iter() {
                 let mut fmt_size = FmtSize::new();
                 let _res = self.fmt(v, 1, &mut fmt_size);
@@ -202,15 +248,12 @@ trait TensorFormatter {
         write!(f, "[")?;
         match dims {
             [] => {
-                if let Ok(v) = t.to_scalar::<Self::Elem>() {
+                if let Some(&v) = self.values(t).ok().as_ref().and_then(|vs| vs.first()) {
                     self.fmt(v, max_w, f)?
                 }
             }
             [v] if summarize && *v > 2 * edge_items => {
-                if let Ok(vs) = t
-                    .narrow(0, 0, edge_items)
-                    .and_then(|t| t.to_vec1::<Self::Elem>())
-                {
+                if let Ok(vs) = t.narrow(0, 0, edge_items).and_then(|t| self.values(&t)) {
                     for v in vs.into_iter() {
                         self.fmt(v, max_w, f)?;
                         write!(f, ", ")?;
@@ -219,7 +262,7 @@ trait TensorFormatter {
                 write!(f, "...")?;
                 if let Ok(vs) = t
                     .narrow(0, v - edge_items, edge_items)
-                    .and_then(|t| t.to_vec1::<Self::Elem>())
+                    .and_then(|t| self.values(&t))
                 {
                     for v in vs.into_iter() {
                         write!(f, ", ")?;
@@ -229,7 +272,7 @@ trait TensorFormatter {
             }
             [_] => {
                 let elements_per_line = usize::max(1, po.line_width / (max_w + 2));
-                if let Ok(vs) = t.to_vec1::<Self::Elem>() {
+                if let Ok(vs) = self.values(t) {
                     for (i, v) in vs.into_iter().enumerate() {
                         if i > 0 {
                             if i % elements_per_line == 0 {
@@ -356,6 +399,10 @@ where
 {
     type Elem = S;
 
+    fn values(&self, t: &Tensor) -> Result<Vec<S>> {
+        t.flatten_all()?.to_vec1()
+    }
+
     fn fmt<T: std::fmt::Write>(&self, v: Self::Elem, max_w: usize, f: &mut T) -> std::fmt::Result {
         if self.sci_mode {
             write!(
@@ -401,11 +448,48 @@ where
 {
     type Elem = S;
 
+    fn values(&self, t: &Tensor) -> Result<Vec<S>> {
+        t.flatten_all()?.to_vec1()
+    }
+
     fn fmt<T: std::fmt::Write>(&self, v: Self::Elem, max_w: usize, f: &mut T) -> std::fmt::Result {
         write!(f, "{v:max_w$}")
     }
 }
 
+/// Complex values are printed as `a+bi`, the real and imaginary parts use the same precision.
+struct ComplexFormatter {
+    precision: usize,
+}
+
+impl TensorFormatter for ComplexFormatter {
+    type Elem = (f64, f64);
+
+    fn values(&self, t: &Tensor) -> Result<Vec<(f64, f64)>> {
+        complex_values(t)
+    }
+
+    fn fmt<T: std::fmt::Write>(&self, v: Self::Elem, max_w: usize, f: &mut T) -> std::fmt::Result {
+        let (re, im) = v;
+        let v = format!("{re:.prec$}{im:+.prec$}i", prec = self.precision);
+        write!(f, "{v:>max_w$}")
+    }
+}
+
+fn complex_values(t: &Tensor) -> Result<Vec<(f64, f64)>> {
+    let re = t
+        .real()?
+        .to_dtype(DType::F64)?
+        .flatten_all()?
+        .to_vec1::<f64>()?;
+    let im = t
+        .imag()?
+        .to_dtype(DType::F64)?
+        .flatten_all()?
+        .to_vec1::<f64>()?;
+    Ok(re.into_iter().zip(im).collect())
+}
+
 fn get_summarized_data(t: &Tensor, edge_items: usize) -> Result<Tensor> {
     let dims = t.dims();
     if dims.is_empty() {
@@ -497,6 +581,14 @@ impl std::fmt::Display for Tensor {
                     writeln!(f)?;
                 }
             }
+            DType::C64 | DType::C128 => {
+                let tf = ComplexFormatter {
+                    precision: po.precision,
+                };
+                let max_w = tf.max_width(&to_display);
+                tf.fmt_tensor(self, 1, max_w, summarize, &po, f)?;
+                writeln!(f)?;
+            }
         };
 
         let device_str = match self.device().location() {
@@ -507,6 +599,9 @@ impl std::fmt::Display for Tensor {
             crate::DeviceLocation::Metal { gpu_id } => {
                 format!(", metal:{}", gpu_id)
             }
+            crate::DeviceLocation::Wgpu { gpu_id } => {
+                format!(", wgpu:{}", gpu_id)
+            }
         };
 
         write!(
@@ -20,6 +20,21 @@ pub enum DType {
     F32,
     // Floating-point using double precision (64 bits).
     F64,
+    // Complex number using single precision for the real and imaginary parts (64 bits).
+    C64,
+    // Complex number using double precision for the real and imaginary parts (128 bits).
+    C128,
+}
+
+/// How values get rounded when converting to a lower precision dtype.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum RoundingMode {
+    // Round to the nearest representable value.
+    #[default]
+    Nearest,
+    // Round up or down randomly with a probability proportional to the distance to the two
+    // closest representable values, so that the rounding is unbiased in expectation.
+    Stochastic,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -44,6 +59,8 @@ impl std::str::FromStr for DType {
             "f16" => Ok(Self::F16),
             "f32" => Ok(Self::F32),
             "f64" => Ok(Self::F64),
+            "c64" => Ok(Self::C64),
+            "c128" => Ok(Self::C128),
             _ => Err(DTypeParseError(s.to_string())),
         }
     }
@@ -60,6 +77,8 @@ impl DType {
             Self::F16 => "f16",
             Self::F32 => "f32",
             Self::F64 => "f64",
+            Self::C64 => "c64",
+            Self::C128 => "c128",
         }
     }
 
@@ -73,6 +92,8 @@ impl DType {
             Self::F16 => 2,
             Self::F32 => 4,
             Self::F64 => 8,
+            Self::C64 => 8,
+            Self::C128 => 16,
         }
     }
 
@@ -80,6 +101,7 @@ impl DType {
         match self {
             Self::U8 | Self::U32 | Self::I64 => true,
             Self::BF16 | Self::F16 | Self::F32 | Self::F64 => false,
+            Self::C64 | Self::C128 => false,
         }
     }
 
@@ -87,8 +109,13 @@ impl DType {
         match self {
             Self::U8 | Self::U32 | Self::I64 => false,
             Self::BF16 | Self::F16 | Self::F32 | Self::F64 => true,
+            Self::C64 | Self::C128 => false,
         }
     }
+
+    pub fn is_complex(&self) -> bool {
+        matches!(self, Self::C64 | Self::C128)
+    }
 }
 
 pub trait WithDType:
@@ -0,0 +1,389 @@
+//! Einstein summation over tensors.
+//!
+//! The contraction is evaluated by folding the operands pairwise from left to right, each pair
+//! being reduced to a single batched `matmul`. No attempt is made at finding an optimal
+//! contraction path.
+use crate::{bail, Result, Tensor};
+use std::collections::HashMap;
+
+// Labels used for the dimensions covered by an ellipsis are taken from the unicode private use
+// area so that they cannot clash with user provided labels.
+const ELLIPSIS_LABEL_START: u32 = 0xE000;
+
+fn is_ellipsis_label(c: char) -> bool {
+    c as u32 >= ELLIPSIS_LABEL_START
+}
+
+fn ellipsis_labels(len: usize, max_len: usize) -> Vec<char> {
+    (max_len - len..max_len)
+        .map(|i| char::from_u32(ELLIPSIS_LABEL_START + i as u32).unwrap())
+        .collect()
+}
+
+#[derive(Debug)]
+struct Term {
+    labels: Vec<char>,
+    // Position of the ellipsis within `labels` if any.
+    ellipsis: Option<usize>,
+}
+
+impl Term {
+    fn parse(term: &str, equation: &str) -> Result<Self> {
+        let mut labels = vec![];
+        let mut ellipsis = None;
+        let mut chars = term.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {
+                    if chars.next() != Some('.') || chars.next() != Some('.') {
+                        bail!("einsum: invalid ellipsis in '{equation}'")
+                    }
+                    if ellipsis.is_some() {
+                        bail!("einsum: more than one ellipsis in term '{term}' of '{equation}'")
+                    }
+                    ellipsis = Some(labels.len())
+                }
+                c if c.is_ascii_alphabetic() => labels.push(c),
+                c if c.is_whitespace() => {}
+                c => bail!("einsum: invalid character '{c}' in '{equation}'"),
+            }
+        }
+        Ok(Self { labels, ellipsis })
+    }
+
+    /// Returns the labels for all the dimensions, the ellipsis being expanded to cover the
+    /// `ellipsis_len` dimensions that are not explicitly labeled.
+    fn expand(&self, ellipsis_len: usize, max_ellipsis_len: usize) -> Vec<char> {
+        match self.ellipsis {
+            None => self.labels.clone(),
+            Some(pos) => {
+                let mut labels = self.labels[..pos].to_vec();
+                labels.extend(ellipsis_labels(ellipsis_len, max_ellipsis_len));
+                labels.extend_from_slice(&self.labels[pos..]);
+                labels
+            }
+        }
+    }
+}
+
+/// A tensor together with the label of each of its dimensions.
+struct Operand {
+    tensor: Tensor,
+    labels: Vec<char>,
+}
+
+impl Operand {
+    /// Replaces repeated labels by the corresponding diagonal, e.g. `ii` becomes `i`.
+    fn take_diagonals(mut self) -> Result<Self> {
+        loop {
+            let repeated = self.labels.iter().enumerate().find_map(|(i, l)| {
+                self.labels[i + 1..]
+                    .iter()
+                    .position(|l2| l2 == l)
+                    .map(|j| (i, i + 1 + j))
+            });
+            let (p, q) = match repeated {
+                None => return Ok(self),
+                Some(pq) => pq,
+            };
+            let dims = self.tensor.dims();
+            let n = dims[p];
+            if dims[q] != n {
+                bail!(
+                    "einsum: dimension mismatch for repeated index '{}', {} <> {}",
+                    self.labels[p],
+                    n,
+                    dims[q]
+                )
+            }
+            let mut perm: Vec<usize> = (0..dims.len()).filter(|&d| d != p && d != q).collect();
+            let mut labels: Vec<char> = perm.iter().map(|&d| self.labels[d]).collect();
+            let mut new_dims: Vec<usize> = perm.iter().map(|&d| dims[d]).collect();
+            labels.push(self.labels[p]);
+            new_dims.push(n * n);
+            perm.push(p);
+            perm.push(q);
+            let ids =
+                Tensor::arange_step(0u32, (n * n) as u32, (n + 1) as u32, self.tensor.device())?;
+            let tensor = self.tensor.permute(perm)?.reshape(new_dims)?;
+            let last_dim = tensor.rank() - 1;
+            self.tensor = tensor.index_select(&ids, last_dim)?;
+            self.labels = labels;
+        }
+    }
+
+    /// Sums over all the dimensions which label does not satisfy `keep`.
+    fn sum_out<F: Fn(char) -> bool>(self, keep: F) -> Result<Self> {
+        let mut sum_dims = vec![];
+        let mut labels = vec![];
+        for (i, &l) in self.labels.iter().enumerate() {
+            if keep(l) {
+                labels.push(l)
+            } else {
+                sum_dims.push(i)
+            }
+        }
+        if sum_dims.is_empty() {
+            return Ok(self);
+        }
+        let tensor = self.tensor.sum(sum_dims)?;
+        Ok(Self { tensor, labels })
+    }
+
+    fn size(&self, label: char) -> Option<usize> {
+        self.labels
+            .iter()
+            .position(|&l| l == label)
+            .map(|i| self.tensor.dims()[i])
+    }
+
+    /// Permutes the dimensions so as to follow `labels`, broadcasting them to `sizes`, and
+    /// returns the result reshaped to `reshape_to`.
+    fn arrange(
+        &self,
+        labels: &[char],
+        sizes: &[usize],
+        reshape_to: (usize, usize, usize),
+    ) -> Result<Tensor> {
+        let perm: Vec<usize> = labels
+            .iter()
+            .map(|l| self.labels.iter().position(|l2| l2 == l).unwrap())
+            .collect();
+        let tensor = if self.labels.is_empty() {
+            self.tensor.clone()
+        } else {
+            self.tensor.permute(perm)?
+        };
+        let tensor = if tensor.dims() == sizes {
+            tensor
+        } else {
+            tensor.broadcast_as(sizes)?
+        };
+        tensor.reshape(reshape_to)
+    }
+
+    /// Contracts two operands together, only the labels for which `keep` returns true are
+    /// preserved.
+    fn contract<F: Fn(char) -> bool>(self, rhs: Self, keep: F) -> Result<Self> {
+        let lhs_labels = self.labels.clone();
+        let rhs_labels = rhs.labels.clone();
+        let lhs = self.sum_out(|l| keep(l) || rhs_labels.contains(&l))?;
+        let rhs = rhs.sum_out(|l| keep(l) || lhs_labels.contains(&l))?;
+
+        let mut batch = vec![];
+        let mut contracted = vec![];
+        let mut left = vec![];
+        for &l in lhs.labels.iter() {
+            if rhs.labels.contains(&l) {
+                if keep(l) {
+                    batch.push(l)
+                } else {
+                    contracted.push(l)
+                }
+            } else {
+                left.push(l)
+            }
+        }
+        let right: Vec<char> = rhs
+            .labels
+            .iter()
+            .filter(|l| !lhs.labels.contains(l))
+            .copied()
+            .collect();
+
+        // Shared dimensions may have a size of one on one side when covered by an ellipsis.
+        let size = |l: char| {
+            let s1 = lhs.size(l).unwrap_or(1);
+            let s2 = rhs.size(l).unwrap_or(1);
+            usize::max(s1, s2)
+        };
+        let batch_sizes: Vec<usize> = batch.iter().map(|&l| size(l)).collect();
+        let contracted_sizes: Vec<usize> = contracted.iter().map(|&l| size(l)).collect();
+        let left_sizes: Vec<usize> = left.iter().map(|&l| size(l)).collect();
+        let right_sizes: Vec<usize> = right.iter().map(|&l| size(l)).collect();
+        let b = batch_sizes.iter().product::<usize>();
+        let k = contracted_sizes.iter().product::<usize>();
+        let m = left_sizes.iter().product::<usize>();
+        let n = right_sizes.iter().product::<usize>();
+
+        let lhs_t = lhs.arrange(
+            &[batch.as_slice(), &left, &contracted].concat(),
+            &[batch_sizes.as_slice(), &left_sizes, &contracted_sizes].concat(),
+            (b, m, k),
+        )?;
+        let rhs_t = rhs.arrange(
+            &[batch.as_slice(), &contracted, &right].concat(),
+            &[batch_sizes.as_slice(), &contracted_sizes, &right_sizes].concat(),
+            (b, k, n),
+        )?;
+        let tensor = lhs_t.matmul(&rhs_t)?;
+        let tensor = tensor.reshape([batch_sizes, left_sizes, right_sizes].concat())?;
+        let labels = [batch, left, right].concat();
+        Ok(Self { tensor, labels })
+    }
+}
+
+impl Tensor {
+    /// Evaluates the Einstein summation convention on the operands.
+    ///
+    /// The equation uses a single letter label for each dimension of the operands, e.g.
+    /// `"ij,jk->ik"` for a matrix multiplication. The supported features are:
+    /// - An ellipsis `...` standing for all the dimensions that are not explicitly labeled, these
+    ///   dimensions are broadcasted between operands.
+    /// - Repeated labels within an operand, the diagonal along these dimensions is used.
+    /// - An explicit output after `->`. When the output is omitted, it is made of the ellipsis
+    ///   dimensions followed by the labels that appear exactly once, in alphabetical order.
+    ///
+    /// Dimensions that do not appear in the output are summed over.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::arange(0f32, 6f32, &Device::Cpu)?.reshape((2, 3))?;
+    /// let b = Tensor::arange(0f32, 12f32, &Device::Cpu)?.reshape((3, 4))?;
+    /// let c = Tensor::einsum("ij,jk->ik", &[&a, &b])?;
+    /// assert_eq!(c.to_vec2::<f32>()?, a.matmul(&b)?.to_vec2::<f32>()?);
+    /// let tr = Tensor::einsum("ij->ji", &[&a])?;
+    /// assert_eq!(tr.dims(), &[3, 2]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn einsum<A: AsRef<Tensor>>(equation: &str, operands: &[A]) -> Result<Self> {
+        if operands.is_empty() {
+            Err(crate::Error::OpRequiresAtLeastOneTensor { op: "einsum" }.bt())?
+        }
+        let (inputs, output) = match equation.split_once("->") {
+            None => (equation, None),
+            Some((inputs, output)) => (inputs, Some(output)),
+        };
+        let inputs = inputs
+            .split(',')
+            .map(|t| Term::parse(t, equation))
+            .collect::<Result<Vec<_>>>()?;
+        if inputs.len() != operands.len() {
+            bail!(
+                "einsum: '{equation}' expects {} operands, got {}",
+                inputs.len(),
+                operands.len()
+            )
+        }
+
+        // Expand the ellipsis of each operand and check the ranks.
+        let mut ellipsis_lens = Vec::with_capacity(inputs.len());
+        for (term, operand) in inputs.iter().zip(operands.iter()) {
+            let rank = operand.as_ref().rank();
+            let n_labels = term.labels.len();
+            let ellipsis_len = match term.ellipsis {
+                None if rank == n_labels => 0,
+                Some(_) if rank >= n_labels => rank - n_labels,
+                _ => bail!(
+                    "einsum: operand of shape {:?} does not match the term {:?} of '{equation}'",
+                    operand.as_ref().shape(),
+                    term.labels.iter().collect::<String>()
+                ),
+            };
+            ellipsis_lens.push(ellipsis_len)
+        }
+        let max_ellipsis_len = ellipsis_lens.iter().copied().max().unwrap_or(0);
+        let operands = inputs
+            .iter()
+            .zip(operands.iter())
+            .zip(ellipsis_lens.iter())
+            .map(|((term, operand), &ellipsis_len)| Operand {
+                tensor: operand.as_ref().clone(),
+                labels: term.expand(ellipsis_len, max_ellipsis_len),
+            })
+            .collect::<Vec<_>>();
+
+        // Check that the dimensions associated with each label are consistent.
+        let mut sizes: HashMap<char, usize> = HashMap::new();
+        for operand in operands.iter() {
+            for (&label, &size) in operand.labels.iter().zip(operand.tensor.dims()) {
+                match sizes.get(&label) {
+                    None => {
+                        sizes.insert(label, size);
+                    }
+                    Some(&prev) if prev == size => {}
+                    Some(&prev) if is_ellipsis_label(label) && (prev == 1 || size == 1) => {
+                        sizes.insert(label, usize::max(prev, size));
+                    }
+                    Some(&prev) => {
+                        if is_ellipsis_label(label) {
+                            bail!("einsum: cannot broadcast ellipsis dimensions {prev} <> {size}")
+                        } else {
+                            bail!(
+                                "einsum: dimension mismatch for index '{label}', {prev} <> {size}"
+                            )
+                        }
+                    }
+                }
+            }
+        }
+
+        let output = match output {
+            Some(output) => {
+                let term = Term::parse(output, equation)?;
+                let labels = term.expand(max_ellipsis_len, max_ellipsis_len);
+                for (i, l) in labels.iter().enumerate() {
+                    if labels[..i].contains(l) {
+                        bail!("einsum: output index '{l}' appears more than once")
+                    }
+                    if !sizes.contains_key(l) {
+                        bail!("einsum: output index '{l}' does not appear in the inputs")
+                    }
+                }
+                labels
+            }
+            None => {
+                let mut counts: HashMap<char, usize> = HashMap::new();
+                for term in inputs.iter() {
+                    for &l in term.labels.iter() {
+                        *counts.entry(l).or_default() += 1
+                    }
+                }
+                let mut labels: Vec<char> = counts
+                    .into_iter()
+                    .filter_map(|(l, c)| if c == 1 { Some(l) } else { None })
+                    .collect();
+                labels.sort();
+                [ellipsis_labels(max_ellipsis_len, max_ellipsis_len), labels].concat()
+            }
+        };
+
+        let mut operands = operands
+            .into_iter()
+            .map(|o| o.take_diagonals())
+            .collect::<Result<Vec<_>>>()?
+            .into_iter();
+        // Safe to unwrap as operands is not empty.
+        let mut acc = operands.next().unwrap();
+        let rest: Vec<Vec<char>> = operands
+            .as_slice()
+            .iter()
+            .map(|o| o.labels.clone())
+            .collect();
+        for (idx, rhs) in operands.enumerate() {
+            let keep = |l: char| {
+                output.contains(&l) || rest[idx + 1..].iter().any(|labels| labels.contains(&l))
+            };
+            acc = acc.contract(rhs, keep)?;
+        }
+        let acc = acc.sum_out(|l| output.contains(&l))?;
+
+        // Dimensions covered by an ellipsis may still have to be broadcasted, e.g. for a single
+        // operand with a size one dimension.
+        let out_sizes: Vec<usize> = output.iter().map(|l| sizes[l]).collect();
+        let perm: Vec<usize> = output
+            .iter()
+            .map(|l| acc.labels.iter().position(|l2| l2 == l).unwrap())
+            .collect();
+        let tensor = if perm.is_empty() {
+            acc.tensor
+        } else {
+            acc.tensor.permute(perm)?
+        };
+        if tensor.dims() == out_sizes {
+            Ok(tensor)
+        } else {
+            tensor.broadcast_as(out_sizes)?.contiguous()
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{DType, DeviceLocation, Layout, MetalError, Shape};
+use crate::{DType, DeviceLocation, Layout, MetalError, Shape, WgpuError};
 
 #[derive(Debug, Clone)]
 pub struct MatMulUnexpectedStriding {
@@ -92,6 +92,9 @@ pub enum Error {
     #[error("empty tensor for {op}")]
     EmptyTensor { op: &'static str },
 
+    #[error("{op} expects a tensor with a single element, got shape {shape:?}")]
+    NotASingleElement { op: &'static str, shape: Shape },
+
     // === Device Errors ===
     #[error("device mismatch in {op}, lhs: {lhs:?}, rhs: {rhs:?}")]
     DeviceMismatchBinaryOp {
@@ -155,6 +158,9 @@ pub enum Error {
     #[error("the candle crate has not been built with metal support")]
     NotCompiledWithMetalSupport,
 
+    #[error("the candle crate has not been built with wgpu support")]
+    NotCompiledWithWgpuSupport,
+
     #[error("cannot find tensor {path}")]
     CannotFindTensor { path: String },
 
@@ -165,6 +171,9 @@ pub enum Error {
     #[error("Metal error {0}")]
     Metal(#[from] MetalError),
 
+    #[error("wgpu error {0}")]
+    Wgpu(#[from] WgpuError),
+
     #[error(transparent)]
     TryFromIntError(#[from] core::num::TryFromIntError),
 
@@ -201,6 +210,14 @@ pub enum Error {
         path: std::path::PathBuf,
     },
 
+    /// Adding the names of the operands to a shape mismatch, see `Tensor::with_name`.
+    #[error("{inner}, lhs name: {}, rhs name: {}", fmt_name(.lhs), fmt_name(.rhs))]
+    WithTensorNames {
+        inner: Box<Self>,
+        lhs: Option<String>,
+        rhs: Option<String>,
+    },
+
     #[error("{inner}\n{backtrace}")]
     WithBacktrace {
         inner: Box<Self>,
@@ -214,6 +231,10 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+fn fmt_name(name: &Option<String>) -> &str {
+    name.as_deref().unwrap_or("<unnamed>")
+}
+
 impl Error {
     pub fn wrap(err: impl std::error::Error + Send + Sync + 'static) -> Self {
         Self::Wrapped(Box::new(err)).bt()
@@ -239,6 +260,26 @@ impl Error {
         }
     }
 
+    /// Attaches the tensor names to a shape mismatch error, other errors and errors where neither
+    /// operand is named are returned unchanged.
+    pub(crate) fn with_tensor_names(self, lhs: Option<&str>, rhs: Option<&str>) -> Self {
+        if lhs.is_none() && rhs.is_none() {
+            return self;
+        }
+        match self {
+            Self::WithBacktrace { inner, backtrace } => Self::WithBacktrace {
+                inner: Box::new(inner.with_tensor_names(lhs, rhs)),
+                backtrace,
+            },
+            err @ Self::ShapeMismatchBinaryOp { .. } => Self::WithTensorNames {
+                inner: Box::new(err),
+                lhs: lhs.map(|s| s.to_string()),
+                rhs: rhs.map(|s| s.to_string()),
+            },
+            err => err,
+        }
+    }
+
     pub fn with_path<P: AsRef<std::path::Path>>(self, p: P) -> Self {
         Self::WithPath {
             inner: Box::new(self),
@@ -0,0 +1,300 @@
+//! Fast Fourier transforms.
+//!
+//! The transforms are computed on the cpu using double precision. Power-of-two sizes use an
+//! iterative radix-2 algorithm, other sizes go through Bluestein's algorithm so that all sizes
+//! run in `O(n log n)`. Complex outputs use the `c64` dtype for `f32`, `f16` and `bf16` inputs
+//! and `c128` for `f64` inputs.
+use crate::shape::Dim;
+use crate::{DType, Device, Error, Result, Tensor};
+
+// Unnormalized in-place transform for power-of-two sizes.
+fn radix2(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1. } else { -1. };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2. * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (s, c) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let xr = re[b] * c - im[b] * s;
+                let xi = re[b] * s + im[b] * c;
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Unnormalized in-place transform for arbitrary sizes using Bluestein's algorithm, the transform
+// is expressed as a convolution that is computed with power-of-two transforms.
+fn bluestein(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    let m = (2 * n - 1).next_power_of_two();
+    let sign = if inverse { 1. } else { -1. };
+    // The chirp exp(sign * i * pi * k^2 / n), k^2 is reduced modulo 2n to preserve precision.
+    let chirp: Vec<(f64, f64)> = (0..n)
+        .map(|k| {
+            let k2 = (k * k) % (2 * n);
+            let angle = sign * std::f64::consts::PI * k2 as f64 / n as f64;
+            let (s, c) = angle.sin_cos();
+            (c, s)
+        })
+        .collect();
+    let mut a_re = vec![0f64; m];
+    let mut a_im = vec![0f64; m];
+    for (k, &(c, s)) in chirp.iter().enumerate() {
+        a_re[k] = re[k] * c - im[k] * s;
+        a_im[k] = re[k] * s + im[k] * c;
+    }
+    let mut b_re = vec![0f64; m];
+    let mut b_im = vec![0f64; m];
+    for (k, &(c, s)) in chirp.iter().enumerate() {
+        b_re[k] = c;
+        b_im[k] = -s;
+        if k > 0 {
+            b_re[m - k] = c;
+            b_im[m - k] = -s;
+        }
+    }
+    radix2(&mut a_re, &mut a_im, false);
+    radix2(&mut b_re, &mut b_im, false);
+    let b = b_re.iter().zip(b_im.iter());
+    for ((a_re, a_im), (b_re, b_im)) in a_re.iter_mut().zip(a_im.iter_mut()).zip(b) {
+        (*a_re, *a_im) = (*a_re * b_re - *a_im * b_im, *a_re * b_im + *a_im * b_re);
+    }
+    radix2(&mut a_re, &mut a_im, true);
+    for (k, &(c, s)) in chirp.iter().enumerate() {
+        let (r, i) = (a_re[k] / m as f64, a_im[k] / m as f64);
+        re[k] = r * c - i * s;
+        im[k] = r * s + i * c;
+    }
+}
+
+fn transform(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    if n.is_power_of_two() {
+        radix2(re, im, inverse)
+    } else if n > 1 {
+        bluestein(re, im, inverse)
+    }
+}
+
+/// The real and imaginary parts of `t` as `f64` values, with `dim` moved to the last position.
+fn parts(t: &Tensor, dim: usize) -> Result<(Vec<f64>, Vec<f64>)> {
+    let t = t.transpose(dim, t.rank() - 1)?;
+    if t.dtype().is_complex() {
+        let re = t.real()?.to_dtype(DType::F64)?.flatten_all()?.to_vec1()?;
+        let im = t.imag()?.to_dtype(DType::F64)?.flatten_all()?.to_vec1()?;
+        Ok((re, im))
+    } else {
+        let re: Vec<f64> = t.to_dtype(DType::F64)?.flatten_all()?.to_vec1()?;
+        let im = vec![0f64; re.len()];
+        Ok((re, im))
+    }
+}
+
+/// Builds a tensor from values where `dim` is the last dimension, `dims` are the dimensions of
+/// the input tensor and `out_len` the size of `dim` in the output.
+fn from_parts(
+    re: Vec<f64>,
+    im: Option<Vec<f64>>,
+    dims: &[usize],
+    dim: usize,
+    out_len: usize,
+    dtype: DType,
+) -> Result<Tensor> {
+    let mut out_dims = dims.to_vec();
+    out_dims.swap(dim, dims.len() - 1);
+    *out_dims.last_mut().unwrap() = out_len;
+    let real_dtype = match dtype {
+        DType::F64 | DType::C128 => DType::F64,
+        _ => DType::F32,
+    };
+    let re = Tensor::from_vec(re, out_dims.as_slice(), &Device::Cpu)?.to_dtype(real_dtype)?;
+    let t = match im {
+        None => re,
+        Some(im) => {
+            let im =
+                Tensor::from_vec(im, out_dims.as_slice(), &Device::Cpu)?.to_dtype(real_dtype)?;
+            Tensor::complex(&re, &im)?
+        }
+    };
+    t.transpose(dim, dims.len() - 1)
+}
+
+fn check_dtype(t: &Tensor, op: &'static str, complex_only: bool) -> Result<()> {
+    match t.dtype() {
+        DType::C64 | DType::C128 => Ok(()),
+        DType::F16 | DType::BF16 | DType::F32 | DType::F64 if !complex_only => Ok(()),
+        dtype => Err(Error::UnsupportedDTypeForOp(dtype, op).bt()),
+    }
+}
+
+fn fft_<D: Dim>(t: &Tensor, dim: D, inverse: bool, op: &'static str) -> Result<Tensor> {
+    check_dtype(t, op, false)?;
+    let dim = dim.to_index(t.shape(), op)?;
+    let n = t.dim(dim)?;
+    let (mut re, mut im) = parts(t, dim)?;
+    if n > 0 {
+        for (re, im) in re.chunks_exact_mut(n).zip(im.chunks_exact_mut(n)) {
+            transform(re, im, inverse);
+            if inverse {
+                re.iter_mut().for_each(|v| *v /= n as f64);
+                im.iter_mut().for_each(|v| *v /= n as f64);
+            }
+        }
+    }
+    from_parts(re, Some(im), t.dims(), dim, n, t.dtype())
+}
+
+/// The discrete Fourier transform of `t` along dimension `dim`.
+///
+/// Real inputs are treated as complex values with a zero imaginary part, the result is always a
+/// complex tensor with the same shape as the input.
+pub fn fft<D: Dim>(t: &Tensor, dim: D) -> Result<Tensor> {
+    fft_(t, dim, false, "fft")
+}
+
+/// The inverse of [`fft`], the result is normalized by `1/n` where `n` is the size of `dim`.
+pub fn ifft<D: Dim>(t: &Tensor, dim: D) -> Result<Tensor> {
+    fft_(t, dim, true, "ifft")
+}
+
+/// The discrete Fourier transform of a real tensor along dimension `dim`.
+///
+/// As the transform of a real signal is Hermitian symmetric, only the `n / 2 + 1` non-negative
+/// frequencies are returned where `n` is the size of `dim`.
+///
+/// ```rust
+/// use candle_core::{fft, Tensor, Device};
+/// let t = Tensor::new(&[1f32, 2., 3., 4.], &Device::Cpu)?;
+/// let f = fft::rfft(&t, 0)?;
+/// assert_eq!(f.dims(), &[3]);
+/// assert_eq!(f.real()?.to_vec1::<f32>()?, &[10., -2., -2.]);
+/// assert_eq!(f.imag()?.to_vec1::<f32>()?, &[0., 2., 0.]);
+/// # Ok::<(), candle_core::Error>(())
+/// ```
+pub fn rfft<D: Dim>(t: &Tensor, dim: D) -> Result<Tensor> {
+    if t.dtype().is_complex() {
+        Err(Error::UnsupportedDTypeForOp(t.dtype(), "rfft").bt())?
+    }
+    check_dtype(t, "rfft", false)?;
+    let dim = dim.to_index(t.shape(), "rfft")?;
+    let n = t.dim(dim)?;
+    if n == 0 {
+        crate::bail!("rfft: cannot transform an empty dimension")
+    }
+    let out_len = n / 2 + 1;
+    let (mut re, mut im) = parts(t, dim)?;
+    let mut out_re = Vec::with_capacity(re.len() / n * out_len);
+    let mut out_im = Vec::with_capacity(re.len() / n * out_len);
+    for (re, im) in re.chunks_exact_mut(n).zip(im.chunks_exact_mut(n)) {
+        transform(re, im, false);
+        out_re.extend_from_slice(&re[..out_len]);
+        out_im.extend_from_slice(&im[..out_len]);
+    }
+    let dtype = if t.dtype() == DType::F64 {
+        DType::C128
+    } else {
+        DType::C64
+    };
+    from_parts(out_re, Some(out_im), t.dims(), dim, out_len, dtype)
+}
+
+/// The inverse of [`rfft`], this returns a real tensor.
+///
+/// `n` is the size of `dim` in the output signal, it defaults to `2 * (m - 1)` where `m` is the
+/// size of `dim` in the input. It has to be provided for odd sized signals.
+pub fn irfft<D: Dim>(t: &Tensor, dim: D, n: Option<usize>) -> Result<Tensor> {
+    check_dtype(t, "irfft", true)?;
+    let dim = dim.to_index(t.shape(), "irfft")?;
+    let m = t.dim(dim)?;
+    let n = match n {
+        Some(n) => n,
+        None if m > 0 => 2 * (m - 1),
+        None => crate::bail!("irfft: cannot infer the output size from an empty input"),
+    };
+    if n == 0 {
+        crate::bail!("irfft: invalid output size {n}")
+    }
+    let (re, im) = parts(t, dim)?;
+    let rows = re.len().checked_div(m).unwrap_or(0);
+    let mut out = Vec::with_capacity(rows * n);
+    let mut full_re = vec![0f64; n];
+    let mut full_im = vec![0f64; n];
+    for row in 0..rows {
+        let (re, im) = (&re[row * m..(row + 1) * m], &im[row * m..(row + 1) * m]);
+        // Rebuild the full spectrum using the Hermitian symmetry, frequencies that are not part
+        // of the input are set to zero.
+        for (k, (dst_re, dst_im)) in full_re.iter_mut().zip(full_im.iter_mut()).enumerate() {
+            let (r, i) = if k <= n / 2 {
+                if k < m {
+                    (re[k], im[k])
+                } else {
+                    (0., 0.)
+                }
+            } else if n - k < m {
+                (re[n - k], -im[n - k])
+            } else {
+                (0., 0.)
+            };
+            *dst_re = r;
+            *dst_im = i;
+        }
+        transform(&mut full_re, &mut full_im, true);
+        out.extend(full_re.iter().map(|v| v / n as f64))
+    }
+    from_parts(out, None, t.dims(), dim, n, t.dtype())
+}
+
+/// Short-time Fourier transform of a signal along its last dimension.
+///
+/// The signal is split in frames of `n_fft` values separated by `hop_length` values, each frame
+/// is multiplied by `window` which must be a 1d tensor of size `n_fft` and the [`rfft`] of the
+/// windowed frames is returned. The signal is not padded so the last incomplete frame is
+/// discarded.
+///
+/// For an input of shape `(.., len)`, the output has shape `(.., n_frames, n_fft / 2 + 1)` with
+/// `n_frames = 1 + (len - n_fft) / hop_length`.
+pub fn stft(t: &Tensor, n_fft: usize, hop_length: usize, window: &Tensor) -> Result<Tensor> {
+    if window.dims() != [n_fft] {
+        Err(Error::ShapeMismatchBinaryOp {
+            lhs: window.shape().clone(),
+            rhs: n_fft.into(),
+            op: "stft",
+        }
+        .bt())?
+    }
+    if hop_length == 0 {
+        crate::bail!("stft: hop_length must be positive")
+    }
+    let len = t.dim(crate::D::Minus1)?;
+    if n_fft == 0 || len < n_fft {
+        crate::bail!("stft: signal of length {len} is too short for n_fft {n_fft}")
+    }
+    let n_frames = 1 + (len - n_fft) / hop_length;
+    let frames = (0..n_frames)
+        .map(|i| t.narrow(crate::D::Minus1, i * hop_length, n_fft))
+        .collect::<Result<Vec<_>>>()?;
+    let frames = Tensor::stack(&frames, t.rank() - 1)?;
+    let frames = frames.broadcast_mul(&window.to_dtype(frames.dtype())?)?;
+    rfft(&frames, crate::D::Minus1)
+}
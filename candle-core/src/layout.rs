@@ -99,6 +99,39 @@ impl Layout {
         })
     }
 
+    /// A view with the windows of size `size` along `dim` taken every `step` elements, the
+    /// windows are indexed by `dim` and a new trailing dimension indexes the window elements.
+    /// The windows can overlap so the resulting layout may refer to the same elements multiple
+    /// times.
+    pub fn unfold(&self, dim: usize, size: usize, step: usize) -> Result<Self> {
+        let dims = self.shape().dims();
+        if dim >= dims.len() {
+            Err(Error::DimOutOfRange {
+                shape: self.shape().clone(),
+                dim: dim as i32,
+                op: "unfold",
+            }
+            .bt())?
+        }
+        if size > dims[dim] || step == 0 {
+            crate::bail!(
+                "unfold: invalid size {size} or step {step} for dim {dim} of shape {:?}",
+                self.shape
+            )
+        }
+        let mut dims = dims.to_vec();
+        let mut stride = self.stride.clone();
+        dims[dim] = (dims[dim] - size) / step + 1;
+        dims.push(size);
+        stride.push(stride[dim]);
+        stride[dim] *= step;
+        Ok(Self {
+            shape: Shape::from(dims),
+            stride,
+            start_offset: self.start_offset,
+        })
+    }
+
     pub fn transpose(&self, dim1: usize, dim2: usize) -> Result<Self> {
         let rank = self.shape.rank();
         if rank <= dim1 || rank <= dim2 {
@@ -35,8 +35,11 @@
 
 #[cfg(feature = "accelerate")]
 mod accelerate;
+pub mod autocast;
 pub mod backend;
 pub mod backprop;
+mod bitwise;
+mod complex;
 pub mod conv;
 mod convert;
 pub mod cpu;
@@ -49,9 +52,13 @@ pub mod display;
 mod dtype;
 pub mod dummy_cuda_backend;
 mod dummy_metal_backend;
+mod dummy_wgpu_backend;
+mod einsum;
 pub mod error;
+pub mod fft;
 mod indexer;
 pub mod layout;
+mod linalg;
 #[cfg(feature = "metal")]
 pub mod metal_backend;
 #[cfg(feature = "mkl")]
@@ -62,30 +69,36 @@ pub mod pickle;
 pub mod quantized;
 pub mod safetensors;
 pub mod scalar;
+mod scatter;
 pub mod shape;
 mod sort;
 mod storage;
 mod strided_index;
 mod tensor;
 mod tensor_cat;
+mod tensor_inplace;
 pub mod test_utils;
 pub mod utils;
 mod variable;
+#[cfg(feature = "wgpu")]
+pub mod wgpu_backend;
 
 #[cfg(feature = "cudnn")]
 pub use cuda_backend::cudnn;
 
+pub use backprop::checkpoint;
 pub use cpu_backend::{CpuStorage, CpuStorageRef};
 pub use custom_op::{CustomOp1, CustomOp2, CustomOp3, InplaceOp1, InplaceOp2, InplaceOp3};
 pub use device::{Device, DeviceLocation, NdArray};
-pub use dtype::{DType, DTypeParseError, FloatDType, IntDType, WithDType};
+pub use dtype::{DType, DTypeParseError, FloatDType, IntDType, RoundingMode, WithDType};
 pub use error::{Error, Result};
 pub use indexer::IndexOp;
 pub use layout::Layout;
 pub use shape::{Shape, D};
 pub use storage::Storage;
 pub use strided_index::{StridedBlocks, StridedIndex};
-pub use tensor::{Tensor, TensorId};
+pub use tensor::{PadMode, Tensor, TensorId};
+pub use utils::{check_nan, set_check_nan};
 pub use variable::Var;
 
 #[cfg(feature = "cuda")]
@@ -102,6 +115,12 @@ pub use metal_backend::{MetalDevice, MetalError, MetalStorage};
 #[cfg(not(feature = "metal"))]
 pub use dummy_metal_backend::{MetalDevice, MetalError, MetalStorage};
 
+#[cfg(feature = "wgpu")]
+pub use wgpu_backend::{WgpuDevice, WgpuError, WgpuStorage};
+
+#[cfg(not(feature = "wgpu"))]
+pub use dummy_wgpu_backend::{WgpuDevice, WgpuError, WgpuStorage};
+
 #[cfg(feature = "mkl")]
 extern crate intel_mkl_src;
 
@@ -0,0 +1,361 @@
+//! Linear algebra operations on batches of matrices.
+//!
+//! These operations are computed on the cpu using `f64` values, tensors stored on other devices
+//! are copied to the cpu and the results are copied back. The inputs must use the `f32` or `f64`
+//! dtype and the matrices are stored in the last two dimensions, all the leading dimensions are
+//! batch dimensions.
+use crate::{DType, Device, Error, Result, Tensor};
+
+/// The matrices stored in `t` as a flat vector of `f64` values, together with the number of
+/// rows and columns of each matrix.
+fn matrices(t: &Tensor, op: &'static str) -> Result<(Vec<f64>, usize, usize)> {
+    match t.dtype() {
+        DType::F32 | DType::F64 => {}
+        dtype => Err(Error::UnsupportedDTypeForOp(dtype, op).bt())?,
+    }
+    let (m, n) = match t.dims() {
+        [.., m, n] => (*m, *n),
+        _ => Err(Error::UnexpectedNumberOfDims {
+            expected: 2,
+            got: t.rank(),
+            shape: t.shape().clone(),
+        }
+        .bt())?,
+    };
+    let vs = t
+        .to_device(&Device::Cpu)?
+        .to_dtype(DType::F64)?
+        .flatten_all()?
+        .to_vec1::<f64>()?;
+    Ok((vs, m, n))
+}
+
+/// Builds a tensor with the same dtype and device as `like` from `f64` values.
+fn from_matrices(vs: Vec<f64>, dims: &[usize], like: &Tensor) -> Result<Tensor> {
+    Tensor::from_vec(vs, dims, &Device::Cpu)?
+        .to_dtype(like.dtype())?
+        .to_device(like.device())
+}
+
+/// A LU decomposition with partial pivoting of a square matrix, the unit lower triangular and
+/// the upper triangular factors are stored in the same buffer.
+struct Lu {
+    lu: Vec<f64>,
+    perm: Vec<usize>,
+    n: usize,
+}
+
+impl Lu {
+    fn new(a: &[f64], n: usize, op: &'static str) -> Result<Self> {
+        let mut lu = a.to_vec();
+        let mut perm: Vec<usize> = (0..n).collect();
+        // Pivots below this threshold are considered to be zero.
+        let max_abs = lu.iter().fold(0f64, |acc, v| acc.max(v.abs()));
+        let eps = f64::EPSILON * n as f64 * max_abs;
+        for k in 0..n {
+            let (pivot_row, pivot) = (k..n)
+                .map(|i| (i, lu[i * n + k].abs()))
+                .fold((k, -1.), |acc, v| if v.1 > acc.1 { v } else { acc });
+            if pivot <= eps {
+                crate::bail!("{op}: the matrix is singular")
+            }
+            if pivot_row != k {
+                for j in 0..n {
+                    lu.swap(k * n + j, pivot_row * n + j)
+                }
+                perm.swap(k, pivot_row)
+            }
+            let diag = lu[k * n + k];
+            for i in k + 1..n {
+                let factor = lu[i * n + k] / diag;
+                lu[i * n + k] = factor;
+                for j in k + 1..n {
+                    lu[i * n + j] -= factor * lu[k * n + j]
+                }
+            }
+        }
+        Ok(Self { lu, perm, n })
+    }
+
+    /// Solves `A X = B` where `b` is a `n x k` matrix.
+    fn solve(&self, b: &[f64], k: usize) -> Vec<f64> {
+        let n = self.n;
+        let lu = &self.lu;
+        let mut x = vec![0f64; n * k];
+        for (i, &p) in self.perm.iter().enumerate() {
+            x[i * k..(i + 1) * k].copy_from_slice(&b[p * k..(p + 1) * k])
+        }
+        for c in 0..k {
+            for i in 0..n {
+                let mut v = x[i * k + c];
+                for j in 0..i {
+                    v -= lu[i * n + j] * x[j * k + c]
+                }
+                x[i * k + c] = v
+            }
+            for i in (0..n).rev() {
+                let mut v = x[i * k + c];
+                for j in i + 1..n {
+                    v -= lu[i * n + j] * x[j * k + c]
+                }
+                x[i * k + c] = v / lu[i * n + i]
+            }
+        }
+        x
+    }
+}
+
+// Maximum number of sweeps for the one-sided Jacobi algorithm, convergence is usually reached
+// after a handful of sweeps.
+const SVD_MAX_SWEEPS: usize = 64;
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+// Applies a Givens rotation to columns `p` and `q`, with `p < q`.
+fn rotate(cols: &mut [Vec<f64>], p: usize, q: usize, c: f64, s: f64) {
+    let (lhs, rhs) = cols.split_at_mut(q);
+    for (x, y) in lhs[p].iter_mut().zip(rhs[0].iter_mut()) {
+        let (xv, yv) = (*x, *y);
+        *x = c * xv - s * yv;
+        *y = s * xv + c * yv;
+    }
+}
+
+// Adds unit vectors orthogonal to `cols` until there are `target` columns of size `dim`.
+fn complete_basis(cols: &mut Vec<Vec<f64>>, dim: usize, target: usize) {
+    while cols.len() < target {
+        let mut best: Option<(f64, Vec<f64>)> = None;
+        for e in 0..dim {
+            let mut x = vec![0f64; dim];
+            x[e] = 1.;
+            // Orthogonalize twice for numerical stability.
+            for _ in 0..2 {
+                for c in cols.iter() {
+                    let d = dot(c, &x);
+                    x.iter_mut().zip(c.iter()).for_each(|(x, c)| *x -= d * c)
+                }
+            }
+            let norm = dot(&x, &x).sqrt();
+            let is_better = match &best {
+                None => true,
+                Some((best_norm, _)) => norm > *best_norm,
+            };
+            if is_better {
+                best = Some((norm, x))
+            }
+        }
+        match best {
+            Some((norm, x)) => cols.push(x.iter().map(|x| x / norm).collect()),
+            None => break,
+        }
+    }
+}
+
+/// One-sided Jacobi SVD of a `m x n` matrix with `m >= n`. This returns `u` as a `m x u_cols`
+/// matrix, the `n` singular values in descending order and `v` as a `n x n` matrix.
+fn svd_tall(a: &[f64], m: usize, n: usize, u_cols: usize) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+    let mut cols: Vec<Vec<f64>> = (0..n)
+        .map(|j| (0..m).map(|i| a[i * n + j]).collect())
+        .collect();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|j| (0..n).map(|i| if i == j { 1. } else { 0. }).collect())
+        .collect();
+    for _sweep in 0..SVD_MAX_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in p + 1..n {
+                let alpha = dot(&cols[p], &cols[p]);
+                let beta = dot(&cols[q], &cols[q]);
+                let gamma = dot(&cols[p], &cols[q]);
+                if gamma == 0. || gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2. * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1. + zeta * zeta).sqrt());
+                let c = 1. / (1. + t * t).sqrt();
+                let s = c * t;
+                rotate(&mut cols, p, q, c, s);
+                rotate(&mut v, p, q, c, s);
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    let norms: Vec<f64> = cols.iter().map(|c| dot(c, c).sqrt()).collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+    let s: Vec<f64> = order.iter().map(|&j| norms[j]).collect();
+    let tol = s.first().copied().unwrap_or(0.) * m as f64 * f64::EPSILON;
+    let mut u: Vec<Vec<f64>> = order
+        .iter()
+        .filter(|&&j| norms[j] > tol)
+        .map(|&j| cols[j].iter().map(|v| v / norms[j]).collect())
+        .collect();
+    complete_basis(&mut u, m, u_cols);
+    let u = (0..m * u_cols).map(|i| u[i % u_cols][i / u_cols]).collect();
+    let v = (0..n * n).map(|i| v[order[i % n]][i / n]).collect();
+    (u, s, v)
+}
+
+impl Tensor {
+    /// The inverse of a square matrix, or of each matrix for a batch of square matrices.
+    ///
+    /// The inverse is computed using a LU decomposition with partial pivoting and an error is
+    /// returned if a matrix is singular.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[2f64, 1.], [1., 1.]], &Device::Cpu)?;
+    /// let inv = a.inverse()?;
+    /// assert_eq!(inv.to_vec2::<f64>()?, &[[1., -1.], [-1., 2.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn inverse(&self) -> Result<Tensor> {
+        let (vs, m, n) = matrices(self, "inverse")?;
+        if m != n {
+            crate::bail!("inverse: expected square matrices, got {:?}", self.shape())
+        }
+        let mut eye = vec![0f64; n * n];
+        for i in 0..n {
+            eye[i * n + i] = 1.
+        }
+        let mut dst = Vec::with_capacity(vs.len());
+        if n > 0 {
+            for a in vs.chunks_exact(n * n) {
+                let lu = Lu::new(a, n, "inverse")?;
+                dst.extend(lu.solve(&eye, n))
+            }
+        }
+        from_matrices(dst, self.dims(), self)
+    }
+
+    /// Solves the linear system `A X = B` where `A` is `self`, a square matrix, and `B` is `rhs`.
+    ///
+    /// `rhs` can either be a matrix with the same number of rows as `A` or a vector, in which
+    /// case the result is a vector too. The leading batch dimensions are broadcasted. An error is
+    /// returned if `A` is singular.
+    pub fn solve(&self, rhs: &Tensor) -> Result<Tensor> {
+        if rhs.rank() == 1 {
+            return self.solve(&rhs.unsqueeze(1)?)?.squeeze(crate::D::Minus1);
+        }
+        if self.dtype() != rhs.dtype() {
+            Err(Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: rhs.dtype(),
+                op: "solve",
+            }
+            .bt())?
+        }
+        let (l_shape, r_shape) = self.shape().broadcast_shape_matmul(rhs.shape())?;
+        let (vs, m, n) = matrices(&self.broadcast_as(&l_shape)?, "solve")?;
+        if m != n {
+            crate::bail!("solve: expected square matrices, got {:?}", self.shape())
+        }
+        let (bs, _, k) = matrices(&rhs.broadcast_as(&r_shape)?, "solve")?;
+        let mut dst = Vec::with_capacity(bs.len());
+        if n > 0 && k > 0 {
+            for (a, b) in vs.chunks_exact(n * n).zip(bs.chunks_exact(n * k)) {
+                let lu = Lu::new(a, n, "solve")?;
+                dst.extend(lu.solve(b, k))
+            }
+        }
+        from_matrices(dst, r_shape.dims(), self)
+    }
+
+    /// The Cholesky decomposition of a symmetric positive-definite matrix, or of each matrix for
+    /// a batch of matrices.
+    ///
+    /// This returns the lower triangular factor `L` such that `A = L L^T`, or the upper
+    /// triangular factor `U = L^T` such that `A = U^T U` if `upper` is `true`. Only the lower
+    /// triangular part of the input is used. An error is returned if a matrix is not positive
+    /// definite.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[4f64, 2.], [2., 5.]], &Device::Cpu)?;
+    /// let l = a.cholesky(false)?;
+    /// assert_eq!(l.to_vec2::<f64>()?, &[[2., 0.], [1., 2.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn cholesky(&self, upper: bool) -> Result<Tensor> {
+        let (vs, m, n) = matrices(self, "cholesky")?;
+        if m != n {
+            crate::bail!("cholesky: expected square matrices, got {:?}", self.shape())
+        }
+        let mut dst = vec![0f64; vs.len()];
+        if n > 0 {
+            for (a, l) in vs.chunks_exact(n * n).zip(dst.chunks_exact_mut(n * n)) {
+                for j in 0..n {
+                    let mut diag = a[j * n + j];
+                    for k in 0..j {
+                        diag -= l[j * n + k] * l[j * n + k]
+                    }
+                    if diag.is_nan() || diag <= 0. {
+                        crate::bail!("cholesky: the matrix is not positive definite")
+                    }
+                    let diag = diag.sqrt();
+                    l[j * n + j] = diag;
+                    for i in j + 1..n {
+                        let mut v = a[i * n + j];
+                        for k in 0..j {
+                            v -= l[i * n + k] * l[j * n + k]
+                        }
+                        l[i * n + j] = v / diag
+                    }
+                }
+                if upper {
+                    for i in 0..n {
+                        for j in i + 1..n {
+                            l.swap(i * n + j, j * n + i)
+                        }
+                    }
+                }
+            }
+        }
+        from_matrices(dst, self.dims(), self)
+    }
+
+    /// The singular value decomposition of a matrix, or of each matrix for a batch of matrices.
+    ///
+    /// For a `m x n` matrix `A`, this returns `(U, S, V)` such that `A = U diag(S) V^T`. The
+    /// singular values `S` have size `k = min(m, n)` and are sorted in descending order. If
+    /// `full_matrices` is `true`, `U` and `V` are square orthogonal matrices of size `m x m` and
+    /// `n x n`, otherwise only the first `k` columns are returned so `U` has shape `m x k` and
+    /// `V` has shape `n x k`. The decomposition uses the one-sided Jacobi algorithm.
+    pub fn svd(&self, full_matrices: bool) -> Result<(Tensor, Tensor, Tensor)> {
+        let (vs, m, n) = matrices(self, "svd")?;
+        if m == 0 || n == 0 {
+            crate::bail!("svd: empty matrices are not supported {:?}", self.shape())
+        }
+        let k = m.min(n);
+        let (u_cols, v_cols) = if full_matrices { (m, n) } else { (k, k) };
+        let batch = vs.len() / (m * n);
+        let mut u_vs = Vec::with_capacity(batch * m * u_cols);
+        let mut s_vs = Vec::with_capacity(batch * k);
+        let mut v_vs = Vec::with_capacity(batch * n * v_cols);
+        for a in vs.chunks_exact(m * n) {
+            if m >= n {
+                let (u, s, v) = svd_tall(a, m, n, u_cols);
+                u_vs.extend(u);
+                s_vs.extend(s);
+                v_vs.extend(v);
+            } else {
+                // Decompose the transposed matrix A^T = U' S V'^T so that A = V' S U'^T.
+                let at: Vec<f64> = (0..m * n).map(|i| a[(i % m) * n + i / m]).collect();
+                let (u, s, v) = svd_tall(&at, n, m, v_cols);
+                u_vs.extend(v);
+                s_vs.extend(s);
+                v_vs.extend(u);
+            }
+        }
+        let batch_dims = &self.dims()[..self.rank() - 2];
+        let u = from_matrices(u_vs, &[batch_dims, &[m, u_cols]].concat(), self)?;
+        let s = from_matrices(s_vs, &[batch_dims, &[k]].concat(), self)?;
+        let v = from_matrices(v_vs, &[batch_dims, &[n, v_cols]].concat(), self)?;
+        Ok((u, s, v))
+    }
+}
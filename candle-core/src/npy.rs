@@ -72,6 +72,18 @@ impl Header {
         Shape::from(self.shape.as_slice())
     }
 
+    // Reads the array data that follows the header, arrays stored in fortran order get transposed
+    // so that the returned tensor has the expected shape.
+    fn read_array<R: Read>(&self, reader: &mut R) -> Result<Tensor> {
+        if !self.fortran_order || self.shape.len() < 2 {
+            return Tensor::from_reader(self.shape(), self.descr, reader);
+        }
+        let rank = self.shape.len();
+        let dims = self.shape.iter().rev().copied().collect::<Vec<_>>();
+        let tensor = Tensor::from_reader(dims.into(), self.descr, reader)?;
+        tensor.permute((0..rank).rev().collect::<Vec<_>>())
+    }
+
     fn to_string(&self) -> Result<String> {
         let fortran_order = if self.fortran_order { "True" } else { "False" };
         let mut shape = self
@@ -88,6 +100,8 @@ impl Header {
             DType::I64 => "i8",
             DType::U32 => "u4",
             DType::U8 => "u1",
+            DType::C64 => "c8",
+            DType::C128 => "c16",
         };
         if !shape.is_empty() {
             shape.push(',')
@@ -167,8 +181,9 @@ impl Header {
                     "B" | "u1" => DType::U8,
                     "I" | "u4" => DType::U32,
                     "?" | "b1" => DType::U8,
-                    // "F" | "F4" => DType::C64,
-                    // "D" | "F8" => DType::C128,
+                    "F" | "c8" => DType::C64,
+                    "D" | "c16" => DType::C128,
+                    "O" => return Err(Error::Npy("object arrays are not supported".to_string())),
                     descr => return Err(Error::Npy(format!("unrecognized descr {descr}"))),
                 }
             }
@@ -239,6 +254,21 @@ impl Tensor {
                 reader.read_i64_into::<LittleEndian>(&mut data_t)?;
                 Tensor::from_vec(data_t, shape, &Device::Cpu)
             }
+            DType::C64 | DType::C128 => {
+                // The real and imaginary parts are interleaved.
+                let mut dims = shape.dims().to_vec();
+                dims.push(2);
+                let parts_dtype = if dtype == DType::C64 {
+                    DType::F32
+                } else {
+                    DType::F64
+                };
+                let parts = Self::from_reader(dims.into(), parts_dtype, reader)?;
+                Tensor::complex(
+                    &parts.get_on_dim(crate::D::Minus1, 0)?,
+                    &parts.get_on_dim(crate::D::Minus1, 1)?,
+                )
+            }
         }
     }
 
@@ -247,10 +277,7 @@ impl Tensor {
         let mut reader = File::open(path.as_ref())?;
         let header = read_header(&mut reader)?;
         let header = Header::parse(&header)?;
-        if header.fortran_order {
-            return Err(Error::Npy("fortran order not supported".to_string()));
-        }
-        Self::from_reader(header.shape(), header.descr, &mut reader)
+        header.read_array(&mut reader)
     }
 
     /// Reads a npz file and returns the stored multi-dimensional arrays together with their names.
@@ -266,10 +293,7 @@ impl Tensor {
             };
             let header = read_header(&mut reader)?;
             let header = Header::parse(&header)?;
-            if header.fortran_order {
-                return Err(Error::Npy("fortran order not supported".to_string()));
-            }
-            let s = Self::from_reader(header.shape(), header.descr, &mut reader)?;
+            let s = header.read_array(&mut reader)?;
             result.push((name, s))
         }
         Ok(result)
@@ -290,10 +314,7 @@ impl Tensor {
             };
             let header = read_header(&mut reader)?;
             let header = Header::parse(&header)?;
-            if header.fortran_order {
-                return Err(Error::Npy("fortran order not supported".to_string()));
-            }
-            let s = Self::from_reader(header.shape(), header.descr, &mut reader)?;
+            let s = header.read_array(&mut reader)?;
             result.push(s)
         }
         Ok(result)
@@ -302,9 +323,14 @@ impl Tensor {
     fn write<T: Write>(&self, f: &mut T) -> Result<()> {
         f.write_all(NPY_MAGIC_STRING)?;
         f.write_all(&[1u8, 0u8])?;
+        // Tensors with a fortran contiguous layout, e.g. transposed matrixes, are written in
+        // fortran order so that the data does not have to be re-arranged.
+        let rank = self.rank();
+        let fortran_order =
+            rank > 1 && !self.is_contiguous() && self.layout().is_fortran_contiguous();
         let header = Header {
             descr: self.dtype(),
-            fortran_order: false,
+            fortran_order,
             shape: self.dims().to_vec(),
         };
         let mut header = header.to_string()?;
@@ -315,7 +341,12 @@ impl Tensor {
         header.push('\n');
         f.write_all(&[(header.len() % 256) as u8, (header.len() / 256) as u8])?;
         f.write_all(header.as_bytes())?;
-        self.write_bytes(f)
+        if fortran_order {
+            self.permute((0..rank).rev().collect::<Vec<_>>())?
+                .write_bytes(f)
+        } else {
+            self.write_bytes(f)
+        }
     }
 
     /// Writes a multi-dimensional array in the npy format.
@@ -341,6 +372,17 @@ impl Tensor {
     }
 }
 
+/// Reads all the arrays stored in a npz file, the arrays are indexed by name.
+pub fn read_npz<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Tensor>> {
+    Ok(Tensor::read_npz(path)?.into_iter().collect())
+}
+
+/// Writes some named arrays to a npz file, the arrays can then be loaded in Python with
+/// `np.load`.
+pub fn write_npz<P: AsRef<Path>>(path: P, ts: &[(&str, &Tensor)]) -> Result<()> {
+    Tensor::write_npz(ts, path)
+}
+
 /// Lazy tensor loader.
 pub struct NpzTensors {
     index_per_name: HashMap<String, usize>,
@@ -399,10 +441,7 @@ impl NpzTensors {
         let mut reader = zip.by_index(index)?;
         let header = read_header(&mut reader)?;
         let header = Header::parse(&header)?;
-        if header.fortran_order {
-            return Err(Error::Npy("fortran order not supported".to_string()));
-        }
-        let tensor = Tensor::from_reader(header.shape(), header.descr, &mut reader)?;
+        let tensor = header.read_array(&mut reader)?;
         Ok(Some(tensor))
     }
 }
@@ -446,5 +485,8 @@ mod tests {
             h.to_string().unwrap(),
             "{'descr': '<u4', 'fortran_order': False, 'shape': (), }"
         );
+
+        let h = "{'descr': '|O', 'fortran_order': False, 'shape': (3,), }";
+        assert!(Header::parse(h).is_err());
     }
 }
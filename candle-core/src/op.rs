@@ -177,8 +177,101 @@ pub enum Op {
         Tensor,
         std::sync::Arc<Box<dyn crate::CustomOp3 + Send + Sync>>,
     ),
+    // The intermediate values are not kept, `f` is run again on `inputs` during backprop.
+    // `vars` are the variables captured by `f`.
+    Checkpoint {
+        inputs: Vec<Tensor>,
+        vars: Vec<Tensor>,
+        f: std::sync::Arc<CheckpointFn>,
+    },
 }
 
+impl Op {
+    /// A short name for the op, used in error messages.
+    pub(crate) fn name(&self) -> String {
+        match self {
+            Self::Binary(_, _, op) => format!("{op:?}").to_lowercase(),
+            Self::Unary(_, op) => format!("{op:?}").to_lowercase(),
+            Self::Cmp(_, _) => "cmp".to_string(),
+            Self::Reduce(_, op, _) => op.name().to_string(),
+            Self::Matmul(_, _) => "matmul".to_string(),
+            Self::Gather(_, _, _) => "gather".to_string(),
+            Self::ScatterAdd(_, _, _, _) => "scatter-add".to_string(),
+            Self::IndexSelect(_, _, _) => "index-select".to_string(),
+            Self::IndexAdd(_, _, _, _) => "index-add".to_string(),
+            Self::WhereCond(_, _, _) => "where-cond".to_string(),
+            Self::Conv1D { .. } => "conv1d".to_string(),
+            Self::ConvTranspose1D { .. } => "conv-transpose1d".to_string(),
+            Self::Conv2D { .. } => "conv2d".to_string(),
+            Self::ConvTranspose2D { .. } => "conv-transpose2d".to_string(),
+            Self::AvgPool2D { .. } => "avg-pool2d".to_string(),
+            Self::MaxPool2D { .. } => "max-pool2d".to_string(),
+            Self::UpsampleNearest1D { .. } => "upsample-nearest1d".to_string(),
+            Self::UpsampleNearest2D { .. } => "upsample-nearest2d".to_string(),
+            Self::Cat(_, _) => "cat".to_string(),
+            Self::Affine { .. } => "affine".to_string(),
+            Self::ToDType(_) => "to-dtype".to_string(),
+            Self::Copy(_) => "copy".to_string(),
+            Self::Broadcast(_) => "broadcast".to_string(),
+            Self::Narrow(_, _, _, _) => "narrow".to_string(),
+            Self::SliceScatter0(_, _, _) => "slice-scatter".to_string(),
+            Self::Reshape(_) => "reshape".to_string(),
+            Self::ToDevice(_) => "to-device".to_string(),
+            Self::Transpose(_, _, _) => "transpose".to_string(),
+            Self::Permute(_, _) => "permute".to_string(),
+            Self::Elu(_, _) => "elu".to_string(),
+            Self::Powf(_, _) => "powf".to_string(),
+            Self::CustomOp1(_, c) => c.name().to_string(),
+            Self::CustomOp2(_, _, c) => c.name().to_string(),
+            Self::CustomOp3(_, _, _, c) => c.name().to_string(),
+            Self::Checkpoint { .. } => "checkpoint".to_string(),
+        }
+    }
+
+    /// The tensors that this op takes as inputs.
+    pub(crate) fn args(&self) -> Vec<&Tensor> {
+        match self {
+            Self::IndexAdd(t1, t2, t3, _)
+            | Self::ScatterAdd(t1, t2, t3, _)
+            | Self::CustomOp3(t1, t2, t3, _)
+            | Self::WhereCond(t1, t2, t3) => vec![t1, t2, t3],
+            Self::Conv1D { arg, kernel, .. }
+            | Self::ConvTranspose1D { arg, kernel, .. }
+            | Self::Conv2D { arg, kernel, .. }
+            | Self::ConvTranspose2D { arg, kernel, .. } => vec![arg, kernel],
+            Self::Binary(t1, t2, _)
+            | Self::Matmul(t1, t2)
+            | Self::Gather(t1, t2, _)
+            | Self::IndexSelect(t1, t2, _)
+            | Self::SliceScatter0(t1, t2, _)
+            | Self::CustomOp2(t1, t2, _) => vec![t1, t2],
+            Self::Cat(args, _) => args.iter().collect(),
+            Self::Checkpoint { inputs, .. } => inputs.iter().collect(),
+            Self::AvgPool2D { arg, .. }
+            | Self::MaxPool2D { arg, .. }
+            | Self::UpsampleNearest1D { arg, .. }
+            | Self::UpsampleNearest2D { arg, .. }
+            | Self::Affine { arg, .. }
+            | Self::Unary(arg, _)
+            | Self::Cmp(arg, _)
+            | Self::Reduce(arg, _, _)
+            | Self::ToDType(arg)
+            | Self::Copy(arg)
+            | Self::Broadcast(arg)
+            | Self::Narrow(arg, _, _, _)
+            | Self::Reshape(arg)
+            | Self::ToDevice(arg)
+            | Self::Transpose(arg, _, _)
+            | Self::Permute(arg, _)
+            | Self::Elu(arg, _)
+            | Self::Powf(arg, _)
+            | Self::CustomOp1(arg, _) => vec![arg],
+        }
+    }
+}
+
+pub(crate) type CheckpointFn = dyn Fn(&[Tensor]) -> crate::Result<Tensor> + Send + Sync;
+
 pub trait UnaryOpT {
     const NAME: &'static str;
     const KERNEL: &'static str;
@@ -916,6 +1009,10 @@ impl BackpropOp {
         Self(op)
     }
 
+    pub(crate) fn from_op(op: Op) -> Self {
+        Self(Some(op))
+    }
+
     pub(crate) fn is_none(&self) -> bool {
         self.0.is_none()
     }
@@ -220,7 +220,10 @@ impl Object {
             Object::Class {
                 module_name,
                 class_name,
-            } if module_name == "torch._utils" && class_name == "_rebuild_parameter" => {
+            } if module_name == "torch._utils"
+                && (class_name == "_rebuild_parameter"
+                    || class_name == "_rebuild_parameter_with_state") =>
+            {
                 let mut args = args.tuple()?;
                 args.remove(0).reduce()?
             }
@@ -620,6 +623,7 @@ fn rebuild_args(args: Object) -> Result<(Layout, DType, String, usize)> {
         "BFloat16Storage" => DType::BF16,
         "ByteStorage" => DType::U8,
         "LongStorage" => DType::I64,
+        "BoolStorage" => DType::U8,
         other => {
             crate::bail!("unsupported storage type {other}")
         }
@@ -628,6 +632,51 @@ fn rebuild_args(args: Object) -> Result<(Layout, DType, String, usize)> {
     Ok((layout, dtype, path, storage_size))
 }
 
+// Walks the nested dicts, lists and tuples in `obj` and collects the tensors that they contain,
+// the names of the nested values are prefixed by their parent names, e.g. `encoder.layers.0.w`.
+fn collect_tensor_infos(
+    obj: Object,
+    prefix: &str,
+    dir_name: &std::path::Path,
+    tensor_infos: &mut Vec<TensorInfo>,
+) {
+    let join = |name: &str| {
+        if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}.{name}")
+        }
+    };
+    let key_values = match obj {
+        Object::Dict(key_values) => key_values
+            .into_iter()
+            .filter_map(|(k, v)| match k {
+                Object::Unicode(k) => Some((join(&k), v)),
+                Object::Int(k) => Some((join(&k.to_string()), v)),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        Object::List(values) | Object::Tuple(values) => values
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (join(&i.to_string()), v))
+            .collect(),
+        _ => return,
+    };
+    for (name, value) in key_values.into_iter() {
+        match value {
+            Object::Dict(_) | Object::List(_) | Object::Tuple(_) => {
+                collect_tensor_infos(value, &name, dir_name, tensor_infos)
+            }
+            value => match value.into_tensor_info(Object::Unicode(name), dir_name) {
+                Ok(Some(tensor_info)) => tensor_infos.push(tensor_info),
+                Ok(None) => {}
+                Err(err) => eprintln!("skipping: {err:?}"),
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TensorInfo {
     pub name: String,
@@ -700,17 +749,9 @@ pub fn read_pth_tensor_info<P: AsRef<std::path::Path>>(
             obj
         };
 
-        // If the object is a dict, then we can extract the tensor info from it.
-        // NOTE: We are assuming that the `obj` is state_dict by this stage.
-        if let Object::Dict(key_values) = obj {
-            for (name, value) in key_values.into_iter() {
-                match value.into_tensor_info(name, &dir_name) {
-                    Ok(Some(tensor_info)) => tensor_infos.push(tensor_info),
-                    Ok(None) => {}
-                    Err(err) => eprintln!("skipping: {err:?}"),
-                }
-            }
-        }
+        // NOTE: We are assuming that the `obj` is state_dict by this stage, nested dicts and
+        // lists get flattened with their names joined by dots.
+        collect_tensor_infos(obj, "", &dir_name, &mut tensor_infos);
     }
     Ok(tensor_infos)
 }
@@ -751,13 +792,29 @@ impl PthTensors {
         let is_fortran_contiguous = tensor_info.layout.is_fortran_contiguous();
         let rank = tensor_info.layout.shape().rank();
 
-        // Reading the data is a bit tricky as it can be strided, for now only support the basic
-        // case and when the tensor is fortran contiguous.
+        // Reading the data is a bit tricky as it can be strided, the contiguous and fortran
+        // contiguous cases are read directly. For other strided views, the whole storage is read
+        // and the elements are gathered following the layout.
         if !tensor_info.layout.is_contiguous() && !is_fortran_contiguous {
-            crate::bail!(
-                "cannot retrieve non-contiguous tensors {:?}",
-                tensor_info.layout
-            )
+            let storage = Tensor::from_reader(
+                tensor_info.storage_size.into(),
+                tensor_info.dtype,
+                &mut reader,
+            )?;
+            let indexes = tensor_info
+                .layout
+                .strided_index()
+                .map(|i| i as i64)
+                .collect::<Vec<_>>();
+            let indexes = Tensor::from_vec(
+                indexes,
+                tensor_info.layout.shape().elem_count(),
+                &crate::Device::Cpu,
+            )?;
+            let tensor = storage
+                .index_select(&indexes, 0)?
+                .reshape(tensor_info.layout.shape())?;
+            return Ok(Some(tensor));
         }
         let start_offset = tensor_info.layout.start_offset();
         if start_offset > 0 {
@@ -1,5 +1,7 @@
+use super::iq_grids::{IQ2XXS_GRID, IQ3S_GRID, KSIGNS_IQ2XS};
 use super::k_quants::{
-    BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K,
+    BlockIQ2XXS, BlockIQ3S, BlockIQ4NL, BlockIQ4XS, BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0,
+    BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, KVALUES_IQ4NL, QK4_NL, QK8_0, QK_K,
 };
 use crate::Result;
 use byteorder::{ByteOrder, LittleEndian};
@@ -665,3 +667,152 @@ pub(crate) fn vec_dot_q8k_q8k(n: usize, xs: &[BlockQ8K], ys: &[BlockQ8K]) -> Res
         Ok(hsum_float_8(acc))
     }
 }
+
+// Maps 16 packed IQ4_NL grid indexes to their int8 grid values, the low nibbles end up in the
+// lower half of the result and the high nibbles in the upper half.
+#[inline(always)]
+unsafe fn iq4_nl_lookup(values: __m128i, qs: *const u8) -> __m256i {
+    let m4 = _mm_set1_epi8(0xF);
+    let q4bits = _mm_loadu_si128(qs as *const __m128i);
+    let lo = _mm_shuffle_epi8(values, _mm_and_si128(q4bits, m4));
+    let hi = _mm_shuffle_epi8(values, _mm_and_si128(_mm_srli_epi16(q4bits, 4), m4));
+    _mm256_insertf128_si256::<1>(_mm256_castsi128_si256(lo), hi)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4_nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> Result<f32> {
+    if n % QK4_NL != 0 {
+        crate::bail!("vec_dot_iq4_nl_q8_0: {n} is not divisible by {QK4_NL}")
+    }
+    unsafe {
+        let values = _mm_loadu_si128(KVALUES_IQ4NL.as_ptr() as *const __m128i);
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d.to_f32());
+            let bx = iq4_nl_lookup(values, x.qs.as_ptr());
+            let by = _mm256_loadu_si256(y.qs.as_ptr() as *const __m256i);
+            let q = mul_sum_i8_pairs_float(bx, by);
+            acc = _mm256_fmadd_ps(d, q, acc);
+        }
+        Ok(hsum_float_8(acc))
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4_xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq4_xs_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let values = _mm_loadu_si128(KVALUES_IQ4NL.as_ptr() as *const __m128i);
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for ib in 0..QK_K / 32 {
+                let bx = iq4_nl_lookup(values, x.qs.as_ptr().add(16 * ib));
+                let by = _mm256_loadu_si256(y.qs.as_ptr().add(32 * ib) as *const __m256i);
+                // Q8K values can be -128, so the sign is moved to the grid values rather than to
+                // the activations, |-128| is then read as 128 by the unsigned operand.
+                let p16 = _mm256_maddubs_epi16(_mm256_sign_epi8(by, by), _mm256_sign_epi8(bx, by));
+                let ls = _mm256_set1_epi16(x.scale(ib) as i16);
+                sumi = _mm256_add_epi32(sumi, _mm256_madd_epi16(p16, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        Ok(hsum_float_8(acc))
+    }
+}
+
+// Expands each bit of a sign byte to an int8 lane holding -1 when the bit is set and 1 otherwise,
+// so that the signs can be applied with _mm256_sign_epi8.
+const SIGNS_EPI8: [u64; 256] = {
+    let mut signs = [0u64; 256];
+    let mut s = 0;
+    while s < 256 {
+        let mut j = 0;
+        while j < 8 {
+            let lane: u64 = if s & (1 << j) != 0 { 0xFF } else { 0x01 };
+            signs[s] |= lane << (8 * j);
+            j += 1;
+        }
+        s += 1;
+    }
+    signs
+};
+
+// The dot product between 32 signed grid values and 32 Q8K values, scaled by `ls`. As for IQ4_XS
+// the sign is moved to the grid values as the Q8K values can be -128.
+#[inline(always)]
+unsafe fn iq_grid_dot(grid: __m256i, signs: __m256i, q8: *const i8, ls: i32) -> __m256i {
+    let bx = _mm256_sign_epi8(grid, signs);
+    let by = _mm256_loadu_si256(q8 as *const __m256i);
+    let p16 = _mm256_maddubs_epi16(_mm256_sign_epi8(by, by), _mm256_sign_epi8(bx, by));
+    _mm256_madd_epi16(p16, _mm256_set1_epi16(ls as i16))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2_xxs_q8k(n: usize, xs: &[BlockIQ2XXS], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq2_xxs_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for ib in 0..QK_K / 32 {
+                let (aux0, aux1) = x.aux32(ib);
+                let grid = |l: usize| IQ2XXS_GRID[((aux0 >> (8 * l)) & 0xFF) as usize] as i64;
+                let signs = |l: usize| {
+                    SIGNS_EPI8[KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize] as usize]
+                };
+                let grid = _mm256_set_epi64x(grid(3), grid(2), grid(1), grid(0));
+                let signs = _mm256_set_epi64x(
+                    signs(3) as i64,
+                    signs(2) as i64,
+                    signs(1) as i64,
+                    signs(0) as i64,
+                );
+                let ls = 2 * (aux1 >> 28) as i32 + 1;
+                let q8 = y.qs.as_ptr().add(32 * ib);
+                sumi = _mm256_add_epi32(sumi, iq_grid_dot(grid, signs, q8, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        Ok(0.125 * hsum_float_8(acc))
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq3_s_q8k(n: usize, xs: &[BlockIQ3S], ys: &[BlockQ8K]) -> Result<f32> {
+    if n % QK_K != 0 {
+        crate::bail!("vec_dot_iq3_s_q8k: {n} is not divisible by {QK_K}")
+    }
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for ib in 0..QK_K / 32 {
+                let grid = |k: usize| IQ3S_GRID[x.grid_index(ib, k)] as i32;
+                let signs = |l: usize| SIGNS_EPI8[x.signs[4 * ib + l] as usize] as i64;
+                let grid = _mm256_set_epi32(
+                    grid(7),
+                    grid(6),
+                    grid(5),
+                    grid(4),
+                    grid(3),
+                    grid(2),
+                    grid(1),
+                    grid(0),
+                );
+                let signs = _mm256_set_epi64x(signs(3), signs(2), signs(1), signs(0));
+                let q8 = y.qs.as_ptr().add(32 * ib);
+                sumi = _mm256_add_epi32(sumi, iq_grid_dot(grid, signs, q8, x.scale(ib)));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        Ok(hsum_float_8(acc))
+    }
+}
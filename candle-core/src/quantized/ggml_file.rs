@@ -130,6 +130,7 @@ fn from_raw_data<T: super::GgmlType + Send + Sync + 'static>(
         Device::Cpu => QStorage::Cpu(Box::new(data.to_vec())),
         Device::Metal(metal) => super::metal::load_quantized(metal, data)?,
         Device::Cuda(cuda) => super::cuda::load_quantized(cuda, data)?,
+        Device::Wgpu(_) => crate::bail!("quantized tensors are not supported on wgpu"),
     };
     super::QTensor::new(data, dims)
 }
@@ -183,6 +184,18 @@ pub fn qtensor_from_ggml(
         GgmlDType::Q6K => {
             from_raw_data::<k_quants::BlockQ6K>(raw_data, size_in_bytes, dims, device)
         }
+        GgmlDType::IQ4NL => {
+            from_raw_data::<k_quants::BlockIQ4NL>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4XS => {
+            from_raw_data::<k_quants::BlockIQ4XS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2XXS => {
+            from_raw_data::<k_quants::BlockIQ2XXS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ3S => {
+            from_raw_data::<k_quants::BlockIQ3S>(raw_data, size_in_bytes, dims, device)
+        }
         _ => crate::bail!("quantized type {ggml_dtype:?} is not supported yet"),
     }
 }
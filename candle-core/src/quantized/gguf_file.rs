@@ -139,7 +139,7 @@ pub enum ValueType {
     Array,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     U8(u8),
     I8(i8),
@@ -156,6 +156,36 @@ pub enum Value {
     Array(Vec<Value>),
 }
 
+macro_rules! value_from {
+    ($ty:ty, $variant:ident) => {
+        impl From<$ty> for Value {
+            fn from(v: $ty) -> Self {
+                Self::$variant(v)
+            }
+        }
+    };
+}
+
+value_from!(u8, U8);
+value_from!(i8, I8);
+value_from!(u16, U16);
+value_from!(i16, I16);
+value_from!(u32, U32);
+value_from!(i32, I32);
+value_from!(u64, U64);
+value_from!(i64, I64);
+value_from!(f32, F32);
+value_from!(f64, F64);
+value_from!(bool, Bool);
+value_from!(String, String);
+value_from!(Vec<Value>, Array);
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Self::String(v.to_string())
+    }
+}
+
 impl Value {
     pub fn value_type(&self) -> ValueType {
         match self {
@@ -467,6 +497,16 @@ impl Content {
         })
     }
 
+    /// The key-value pairs from the metadata section, e.g. `general.architecture`.
+    pub fn metadata(&self) -> &HashMap<String, Value> {
+        &self.metadata
+    }
+
+    /// The tensor table, tensor infos are indexed by name.
+    pub fn tensor_infos(&self) -> &HashMap<String, TensorInfo> {
+        &self.tensor_infos
+    }
+
     pub fn tensor<R: std::io::Seek + std::io::Read>(
         &self,
         reader: &mut R,
@@ -537,3 +577,84 @@ pub fn write<W: std::io::Seek + std::io::Write>(
     }
     Ok(())
 }
+
+/// A builder for gguf files, metadata and tensors are accumulated and then written in the order
+/// in which they were added.
+///
+/// ```rust
+/// use candle_core::quantized::{gguf_file::GgufWriter, GgmlDType, QTensor};
+/// use candle_core::{Device, Tensor};
+/// # fn main() -> candle_core::Result<()> {
+/// let t = Tensor::zeros((4, 32), candle_core::DType::F32, &Device::Cpu)?;
+/// let qt = QTensor::quantize(&t, GgmlDType::Q8_0)?;
+/// let mut writer = GgufWriter::new();
+/// writer
+///     .add_metadata("general.architecture", "llama")
+///     .add_metadata("llama.context_length", 2048u32)
+///     .add_tensor("weight", &qt);
+/// let mut buffer = std::io::Cursor::new(vec![]);
+/// writer.write(&mut buffer)?;
+/// # Ok(()) }
+/// ```
+#[derive(Default)]
+pub struct GgufWriter<'a> {
+    metadata: Vec<(String, Value)>,
+    tensors: Vec<(String, &'a QTensor)>,
+}
+
+impl<'a> GgufWriter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a metadata key-value pair, the value replaces the previous one if `key` has already
+    /// been set.
+    pub fn add_metadata<K: Into<String>, V: Into<Value>>(&mut self, key: K, value: V) -> &mut Self {
+        let key = key.into();
+        let value = value.into();
+        match self.metadata.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.metadata.push((key, value)),
+        }
+        self
+    }
+
+    /// Adds a tensor, the tensor replaces the previous one if `name` has already been used.
+    pub fn add_tensor<N: Into<String>>(&mut self, name: N, tensor: &'a QTensor) -> &mut Self {
+        let name = name.into();
+        match self.tensors.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, t)) => *t = tensor,
+            None => self.tensors.push((name, tensor)),
+        }
+        self
+    }
+
+    /// Writes the metadata and tensors using the gguf format.
+    pub fn write<W: std::io::Seek + std::io::Write>(&self, w: &mut W) -> Result<()> {
+        // The tensor data is always aligned on the default alignment when writing.
+        match self.metadata.iter().find(|(k, _)| k == "general.alignment") {
+            None => {}
+            Some((_, v)) if v.to_u64().ok() == Some(DEFAULT_ALIGNMENT) => {}
+            Some((_, v)) => crate::bail!("unsupported general.alignment {v:?}"),
+        }
+        let metadata = self
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>();
+        let tensors = self
+            .tensors
+            .iter()
+            .map(|(n, t)| (n.as_str(), *t))
+            .collect::<Vec<_>>();
+        write(w, &metadata, &tensors)
+    }
+
+    /// Writes the metadata and tensors to a new gguf file.
+    pub fn write_file<P: AsRef<std::path::Path>>(&self, p: P) -> Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(p.as_ref())?);
+        self.write(&mut file)?;
+        std::io::Write::flush(&mut file)?;
+        Ok(())
+    }
+}
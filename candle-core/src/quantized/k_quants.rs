@@ -1,3 +1,4 @@
+use super::iq_grids::{IQ2XXS_GRID, IQ3S_GRID, KMASK_IQ2XS, KSIGNS_IQ2XS};
 use super::utils::{
     get_scale_min_k4, group_for_dequantization, group_for_quantization, make_q3_quants,
     make_qkx1_quants, make_qx_quants, nearest_int,
@@ -18,6 +19,7 @@ pub const QK5_0: usize = 32;
 pub const QK5_1: usize = 32;
 pub const QK8_0: usize = 32;
 pub const QK8_1: usize = 32;
+pub const QK4_NL: usize = 32;
 
 pub trait GgmlType: Sized + Clone + Send + Sync {
     const DTYPE: GgmlDType;
@@ -154,6 +156,48 @@ pub struct BlockQ8K {
 }
 const _: () = assert!(4 + QK_K + QK_K / 16 * 2 == std::mem::size_of::<BlockQ8K>());
 
+// The non-linear grid used by the IQ4_NL and IQ4_XS quantizations, kvalues_iq4nl in llama.cpp.
+pub(crate) const KVALUES_IQ4NL: [i8; 16] = [
+    -127, -104, -83, -65, -49, -35, -22, -10, 1, 13, 25, 38, 53, 69, 89, 113,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4NL {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK4_NL / 2],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ4NL>() == 18);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4XS {
+    pub(crate) d: f16,
+    pub(crate) scales_h: u16,
+    pub(crate) scales_l: [u8; QK_K / 64],
+    pub(crate) qs: [u8; QK_K / 2],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ4XS>() == 136);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2XXS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u16; QK_K / 8],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ2XXS>() == 66);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ3S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) qh: [u8; QK_K / 32],
+    pub(crate) signs: [u8; QK_K / 8],
+    pub(crate) scales: [u8; QK_K / 64],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ3S>() == 110);
+
 impl GgmlType for BlockQ4_0 {
     const DTYPE: GgmlDType = GgmlDType::Q4_0;
     const BLCK_SIZE: usize = QK4_0;
@@ -1882,6 +1926,588 @@ pub fn matmul<T: GgmlType>(
     Ok(())
 }
 
+// Returns the index of the grid value that is the closest to `x`, `values` has to be sorted.
+fn best_index_int8(values: &[i8], x: f32) -> usize {
+    let n = values.len();
+    if x <= values[0] as f32 {
+        return 0;
+    }
+    if x >= values[n - 1] as f32 {
+        return n - 1;
+    }
+    let (mut ml, mut mu) = (0, n - 1);
+    while mu - ml > 1 {
+        let mav = (ml + mu) / 2;
+        if x < values[mav] as f32 {
+            mu = mav
+        } else {
+            ml = mav
+        }
+    }
+    if x - (values[mu - 1] as f32) < (values[mu] as f32) - x {
+        mu - 1
+    } else {
+        mu
+    }
+}
+
+// Finds the scale for a block of 32 values on the IQ4_NL grid, the importance weights are the
+// squared values as done in llama.cpp when no importance matrix is provided, the candidate
+// scales are the same as in quantize_row_iq4_nl_impl.
+fn iq4_nl_block_scale(xs: &[f32], ntry: i32) -> f32 {
+    let mut amax = 0f32;
+    let mut max = 0f32;
+    for &x in xs.iter() {
+        if amax < x.abs() {
+            amax = x.abs();
+            max = x;
+        }
+    }
+    if amax < 1e-15 {
+        return 0.;
+    }
+    let v0 = KVALUES_IQ4NL[0] as f32;
+    let sums = |id: f32| {
+        let mut sumqx = 0f32;
+        let mut sumq2 = 0f32;
+        for &x in xs.iter() {
+            let q = KVALUES_IQ4NL[best_index_int8(&KVALUES_IQ4NL, id * x)] as f32;
+            let w = x * x;
+            sumqx += w * q * x;
+            sumq2 += w * q * q;
+        }
+        (sumqx, sumq2)
+    };
+    let (sumqx, sumq2) = sums(-v0 / max);
+    let mut d = if sumq2 > 0. { sumqx / sumq2 } else { -max / v0 };
+    let mut best = d * sumqx;
+    for itry in -ntry..=ntry {
+        let (sumqx, sumq2) = sums((itry as f32 + v0) / max);
+        if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+            d = sumqx / sumq2;
+            best = d * sumqx;
+        }
+    }
+    d
+}
+
+// Packs the grid indexes for 32 values using the scale `d`, the first 16 values are stored in
+// the low nibbles and the last 16 values in the high nibbles.
+fn iq4_nl_pack(xs: &[f32], d: f32, qs: &mut [u8]) {
+    let id = if d != 0. { 1. / d } else { 0. };
+    for j in 0..16 {
+        let l0 = best_index_int8(&KVALUES_IQ4NL, id * xs[j]) as u8;
+        let l1 = best_index_int8(&KVALUES_IQ4NL, id * xs[j + 16]) as u8;
+        qs[j] = l0 | (l1 << 4)
+    }
+}
+
+// The dot product between 32 values packed as IQ4_NL grid indexes and 32 int8 values.
+fn iq4_nl_dot(qs: &[u8], q8: &[i8]) -> i32 {
+    let mut sumi = 0i32;
+    for j in 0..16 {
+        sumi += KVALUES_IQ4NL[(qs[j] & 0xF) as usize] as i32 * q8[j] as i32;
+        sumi += KVALUES_IQ4NL[(qs[j] >> 4) as usize] as i32 * q8[j + 16] as i32;
+    }
+    sumi
+}
+
+impl GgmlType for BlockIQ4NL {
+    const DTYPE: GgmlDType = GgmlDType::IQ4NL;
+    const BLCK_SIZE: usize = QK4_NL;
+    type VecDotType = BlockQ8_0;
+
+    // dequantize_row_iq4_nl
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        for (block, ys) in group_for_dequantization(xs, ys)? {
+            let d = block.d.to_f32();
+            for (j, &q) in block.qs.iter().enumerate() {
+                ys[j] = d * KVALUES_IQ4NL[(q & 0xF) as usize] as f32;
+                ys[j + QK4_NL / 2] = d * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+            }
+        }
+        Ok(())
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        for (block, xs) in group_for_quantization(xs, ys)? {
+            let d = iq4_nl_block_scale(xs, 7);
+            block.d = f16::from_f32(d);
+            iq4_nl_pack(xs, d, &mut block.qs);
+        }
+        Ok(())
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq4_nl_q8_0(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    // ggml_vec_dot_iq4_nl_q8_0
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK4_NL != 0 {
+            crate::bail!("vec_dot_iq4_nl_q8_0: {n} is not divisible by {QK4_NL}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d.to_f32();
+            sumf += d * iq4_nl_dot(&x.qs, &y.qs) as f32
+        }
+        Ok(sumf)
+    }
+}
+
+impl BlockIQ4XS {
+    // The 6 bits scale for the 32 values sub-block `ib`, the 4 low bits are stored in
+    // `scales_l` and the 2 high bits in `scales_h`.
+    pub(crate) fn scale(&self, ib: usize) -> i32 {
+        let ls_l = (self.scales_l[ib / 2] >> (4 * (ib % 2))) & 0xF;
+        let ls_h = (self.scales_h >> (2 * ib)) & 3;
+        (ls_l as i32 | ((ls_h as i32) << 4)) - 32
+    }
+}
+
+impl GgmlType for BlockIQ4XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ4XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    // dequantize_row_iq4_xs
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        for (block, ys) in group_for_dequantization(xs, ys)? {
+            let d = block.d.to_f32();
+            for ib in 0..QK_K / 32 {
+                let dl = d * block.scale(ib) as f32;
+                let qs = &block.qs[16 * ib..16 * (ib + 1)];
+                let ys = &mut ys[32 * ib..32 * (ib + 1)];
+                for (j, &q) in qs.iter().enumerate() {
+                    ys[j] = dl * KVALUES_IQ4NL[(q & 0xF) as usize] as f32;
+                    ys[j + 16] = dl * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // quantize_row_iq4_xs
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        for (block, xs) in group_for_quantization(xs, ys)? {
+            let mut scales = [0f32; QK_K / 32];
+            let mut max_scale = 0f32;
+            let mut amax_scale = 0f32;
+            for (ib, scale) in scales.iter_mut().enumerate() {
+                *scale = iq4_nl_block_scale(&xs[32 * ib..32 * (ib + 1)], 7);
+                if scale.abs() > amax_scale {
+                    amax_scale = scale.abs();
+                    max_scale = *scale;
+                }
+            }
+            let d = -max_scale / 32.;
+            let id = if d != 0. { 1. / d } else { 0. };
+            block.d = f16::from_f32(d);
+            block.scales_h = 0;
+            block.scales_l = [0; QK_K / 64];
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(id * scale).clamp(-32, 31);
+                let dl = block.d.to_f32() * l as f32;
+                let xs = &xs[32 * ib..32 * (ib + 1)];
+                iq4_nl_pack(xs, dl, &mut block.qs[16 * ib..16 * (ib + 1)]);
+                let l = (l + 32) as u8;
+                block.scales_l[ib / 2] |= (l & 0xF) << (4 * (ib % 2));
+                block.scales_h |= ((l >> 4) as u16) << (2 * ib);
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq4_xs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    // ggml_vec_dot_iq4_xs_q8_K
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq4_xs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d4d8 = x.d.to_f32() * y.d;
+            for ib in 0..QK_K / 32 {
+                let sumi = iq4_nl_dot(&x.qs[16 * ib..], &y.qs[32 * ib..]);
+                sumf += d4d8 * (x.scale(ib) * sumi) as f32
+            }
+        }
+        Ok(sumf)
+    }
+}
+
+impl BlockIQ2XXS {
+    // The two 32 bits words for the 32 values sub-block `ib`: the first one holds the four grid
+    // indexes, the second one the four 7 bits sign indexes and the 4 bits scale.
+    pub(crate) fn aux32(&self, ib: usize) -> (u32, u32) {
+        let qs = &self.qs[4 * ib..4 * (ib + 1)];
+        let aux0 = qs[0] as u32 | ((qs[1] as u32) << 16);
+        let aux1 = qs[2] as u32 | ((qs[3] as u32) << 16);
+        (aux0, aux1)
+    }
+}
+
+impl GgmlType for BlockIQ2XXS {
+    const DTYPE: GgmlDType = GgmlDType::IQ2XXS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    // dequantize_row_iq2_xxs
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        for (block, ys) in group_for_dequantization(xs, ys)? {
+            let d = block.d.to_f32();
+            for (ib, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let (aux0, aux1) = block.aux32(ib);
+                let db = d * (0.5 + (aux1 >> 28) as f32) * 0.25;
+                for (l, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 0xFF) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    for j in 0..8 {
+                        let y = db * grid[j] as f32;
+                        ys[j] = if signs & KMASK_IQ2XS[j] != 0 { -y } else { y };
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn from_float(_xs: &[f32], _ys: &mut [Self]) -> Result<()> {
+        // llama.cpp only quantizes to this type using an importance matrix.
+        crate::bail!("quantization to iq2_xxs requires an importance matrix which is not supported")
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq2_xxs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    // ggml_vec_dot_iq2_xxs_q8_K
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq2_xxs_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let mut bsum = 0i32;
+            for ib in 0..QK_K / 32 {
+                let (aux0, aux1) = x.aux32(ib);
+                let ls = 2 * (aux1 >> 28) as i32 + 1;
+                let mut sumi = 0i32;
+                for l in 0..4 {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 0xFF) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    let q8 = &y.qs[32 * ib + 8 * l..32 * ib + 8 * (l + 1)];
+                    for j in 0..8 {
+                        let p = grid[j] as i32 * q8[j] as i32;
+                        sumi += if signs & KMASK_IQ2XS[j] != 0 { -p } else { p };
+                    }
+                }
+                bsum += sumi * ls;
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(0.125 * sumf)
+    }
+}
+
+impl BlockIQ3S {
+    // The odd scale for the 32 values sub-block `ib`.
+    pub(crate) fn scale(&self, ib: usize) -> i32 {
+        1 + 2 * ((self.scales[ib / 2] >> (4 * (ib % 2))) & 0xF) as i32
+    }
+
+    // The 9 bits grid index for the values 4 * k to 4 * k + 3 of the 32 values sub-block `ib`.
+    pub(crate) fn grid_index(&self, ib: usize, k: usize) -> usize {
+        self.qs[8 * ib + k] as usize | ((((self.qh[ib] >> k) & 1) as usize) << 8)
+    }
+}
+
+// The grid used when quantizing to IQ3_S: `map` returns the index in IQ3S_GRID for each of the
+// 4096 points with coordinates in 0..8, or -1 for points that are not on the grid. For these,
+// `neighbours` holds the closest grid points. This plays the same role as iq3xs_init_impl.
+struct Iq3sIndex {
+    map: Vec<i16>,
+    neighbours: Vec<Vec<u16>>,
+}
+
+// Off-grid points are searched over the grid points at the 24 smallest distances.
+const IQ3S_NUM_NEIGHBOURS: usize = 24;
+
+fn iq3s_index() -> &'static Iq3sIndex {
+    static INDEX: std::sync::OnceLock<Iq3sIndex> = std::sync::OnceLock::new();
+    INDEX.get_or_init(|| {
+        let point = |u: usize| -> [i32; 4] { std::array::from_fn(|i| (u >> (3 * i)) as i32 & 7) };
+        let mut map = vec![-1i16; 4096];
+        for (index, g) in IQ3S_GRID.iter().enumerate() {
+            let u = g
+                .to_le_bytes()
+                .iter()
+                .enumerate()
+                .fold(0, |u, (i, &g)| u | (((g as usize - 1) / 2) << (3 * i)));
+            map[u] = index as i16
+        }
+        let neighbours = (0..4096)
+            .map(|u| {
+                if map[u] >= 0 {
+                    return vec![];
+                }
+                let pos = point(u);
+                let mut dist2 = IQ3S_GRID
+                    .iter()
+                    .enumerate()
+                    .map(|(index, g)| {
+                        let d2: i32 = g
+                            .to_le_bytes()
+                            .iter()
+                            .zip(pos.iter())
+                            .map(|(&g, &l)| (g as i32 - (2 * l + 1)).pow(2))
+                            .sum();
+                        (d2, index as u16)
+                    })
+                    .collect::<Vec<_>>();
+                dist2.sort();
+                let mut n_dists = 0;
+                let mut last_d2 = -1;
+                dist2
+                    .into_iter()
+                    .take_while(|&(d2, _)| {
+                        if d2 != last_d2 {
+                            n_dists += 1;
+                            last_d2 = d2
+                        }
+                        n_dists <= IQ3S_NUM_NEIGHBOURS
+                    })
+                    .map(|(_, index)| index)
+                    .collect()
+            })
+            .collect();
+        Iq3sIndex { map, neighbours }
+    })
+}
+
+// Writes to `ls` the coordinates of the grid point used for the four values `xs` with the scale
+// `d` and returns whether the rounded values were on the grid. Otherwise the closest neighbour
+// is used as in iq3_find_best_neighbour2.
+fn iq3s_grid_point(xs: &[f32], ws: &[f32], d: f32, ls: &mut [i8]) -> bool {
+    let index = iq3s_index();
+    let id = 1. / d;
+    let mut u = 0;
+    for (i, &x) in xs.iter().enumerate() {
+        let l = nearest_int(0.5 * (id * x - 1.)).clamp(0, 7);
+        u |= (l as usize) << (3 * i);
+    }
+    if index.map[u] >= 0 {
+        for (i, l) in ls.iter_mut().enumerate() {
+            *l = ((u >> (3 * i)) & 7) as i8
+        }
+        return true;
+    }
+    let mut best = (f32::INFINITY, 0);
+    for &grid_index in index.neighbours[u].iter() {
+        let g = IQ3S_GRID[grid_index as usize].to_le_bytes();
+        let mut d2 = 0f32;
+        for i in 0..4 {
+            let diff = d * g[i] as f32 - xs[i];
+            d2 += ws[i] * diff * diff;
+        }
+        if d2 < best.0 {
+            best = (d2, grid_index as usize)
+        }
+    }
+    let g = IQ3S_GRID[best.1].to_le_bytes();
+    for (l, &g) in ls.iter_mut().zip(g.iter()) {
+        *l = ((g - 1) / 2) as i8
+    }
+    false
+}
+
+impl GgmlType for BlockIQ3S {
+    const DTYPE: GgmlDType = GgmlDType::IQ3S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    // dequantize_row_iq3_s
+    fn to_float(xs: &[Self], ys: &mut [f32]) -> Result<()> {
+        for (block, ys) in group_for_dequantization(xs, ys)? {
+            let d = block.d.to_f32();
+            for (ib, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let db = d * block.scale(ib) as f32;
+                let signs = &block.signs[4 * ib..4 * (ib + 1)];
+                for (k, ys) in ys.chunks_exact_mut(4).enumerate() {
+                    let grid = IQ3S_GRID[block.grid_index(ib, k)].to_le_bytes();
+                    let signs = signs[k / 2];
+                    for j in 0..4 {
+                        let y = db * grid[j] as f32;
+                        let mask = KMASK_IQ2XS[4 * (k % 2) + j];
+                        ys[j] = if signs & mask != 0 { -y } else { y };
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // quantize_row_iq3_s_ref, the squared values are used as weights as done in llama.cpp when
+    // no importance matrix is provided.
+    fn from_float(xs: &[f32], ys: &mut [Self]) -> Result<()> {
+        const KMAXQ: i32 = 8;
+        for (block, xs) in group_for_quantization(xs, ys)? {
+            block.d = f16::from_f32(0.);
+            block.qs = [0; QK_K / 4];
+            block.qh = [0; QK_K / 32];
+            block.signs = [0; QK_K / 8];
+            block.scales = [0; QK_K / 64];
+            let mut scales = [0f32; QK_K / 32];
+            let mut max_scale = 0f32;
+            for (ib, xs) in xs.chunks_exact(32).enumerate() {
+                let weight: [f32; 32] = std::array::from_fn(|i| xs[i] * xs[i]);
+                let waux: [f32; 32] = std::array::from_fn(|i| weight[i].sqrt());
+                let xval: [f32; 32] = std::array::from_fn(|i| xs[i].abs());
+                let mut block_signs = [0u8; 4];
+                for (i, x) in xs.iter().enumerate() {
+                    if *x < 0. {
+                        block_signs[i / 8] |= 1 << (i % 8)
+                    }
+                }
+                let max = xval.iter().fold(0f32, |m, &x| m.max(x));
+                if max == 0. {
+                    continue;
+                }
+                let mut l = [0i8; 32];
+                let mut laux = [0i8; 32];
+                let mut is_on_grid = [false; 8];
+                let mut best = 0f32;
+                let mut scale = max / (2 * KMAXQ - 1) as f32;
+                let sums = |l: &[i8]| {
+                    let (mut sumqx, mut sumq2) = (0f32, 0f32);
+                    for i in 0..32 {
+                        let q = (2 * l[i] + 1) as f32;
+                        sumqx += weight[i] * xval[i] * q;
+                        sumq2 += weight[i] * q * q;
+                    }
+                    (sumqx, sumq2)
+                };
+                for is in -9..=9 {
+                    let id = ((2 * KMAXQ - 1) as f32 + is as f32 * 0.2) / max;
+                    let this_scale = 1. / id;
+                    let is_on_grid_aux: [bool; 8] = std::array::from_fn(|k| {
+                        let r = 4 * k..4 * (k + 1);
+                        iq3s_grid_point(
+                            &xval[r.clone()],
+                            &waux[r.clone()],
+                            this_scale,
+                            &mut laux[r],
+                        )
+                    });
+                    let (sumqx, sumq2) = sums(&laux);
+                    if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+                        scale = sumqx / sumq2;
+                        best = scale * sumqx;
+                        l = laux;
+                        is_on_grid = is_on_grid_aux;
+                    }
+                }
+                if is_on_grid.iter().any(|&on_grid| !on_grid) && scale > 0. {
+                    for k in 0..8 {
+                        let r = 4 * k..4 * (k + 1);
+                        iq3s_grid_point(&xval[r.clone()], &waux[r.clone()], scale, &mut l[r]);
+                    }
+                    let (sumqx, sumq2) = sums(&l);
+                    if sumq2 > 0. {
+                        scale = sumqx / sumq2
+                    }
+                }
+                if scale < 0. {
+                    scale = -scale;
+                    for s in block_signs.iter_mut() {
+                        *s = !*s
+                    }
+                }
+                let index = iq3s_index();
+                for k in 0..8 {
+                    let u = (0..4).fold(0, |u, i| u | ((l[4 * k + i] as usize) << (3 * i)));
+                    let grid_index = index.map[u];
+                    if grid_index < 0 {
+                        crate::bail!(
+                            "iq3_s quantization produced a point {u} that is not on the grid"
+                        )
+                    }
+                    block.qs[8 * ib + k] = (grid_index & 255) as u8;
+                    block.qh[ib] |= ((grid_index >> 8) as u8) << k;
+                }
+                block.signs[4 * ib..4 * (ib + 1)].copy_from_slice(&block_signs);
+                scales[ib] = scale;
+                max_scale = max_scale.max(scale);
+            }
+            if max_scale == 0. {
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d * 1.033);
+            let id = 1. / d;
+            for (ib, scales) in scales.chunks_exact(2).enumerate() {
+                let l1 = nearest_int(0.5 * (id * scales[0] - 1.)).clamp(0, 15) as u8;
+                let l2 = nearest_int(0.5 * (id * scales[1] - 1.)).clamp(0, 15) as u8;
+                block.scales[ib] = l1 | (l2 << 4);
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        #[cfg(target_feature = "avx")]
+        return super::avx::vec_dot_iq3_s_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    // ggml_vec_dot_iq3_s_q8_K
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> Result<f32> {
+        if n % QK_K != 0 {
+            crate::bail!("vec_dot_iq3_s_q8k: {n} is not divisible by {QK_K}")
+        }
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = x.d.to_f32() * y.d;
+            let mut bsum = 0i32;
+            for ib in 0..QK_K / 32 {
+                let mut sumi = 0i32;
+                for k in 0..8 {
+                    let grid = IQ3S_GRID[x.grid_index(ib, k)].to_le_bytes();
+                    let signs = x.signs[4 * ib + k / 2];
+                    let q8 = &y.qs[32 * ib + 4 * k..32 * ib + 4 * (k + 1)];
+                    for j in 0..4 {
+                        let p = grid[j] as i32 * q8[j] as i32;
+                        let mask = KMASK_IQ2XS[4 * (k % 2) + j];
+                        sumi += if signs & mask != 0 { -p } else { p };
+                    }
+                }
+                bsum += sumi * x.scale(ib);
+            }
+            sumf += d * bsum as f32;
+        }
+        Ok(sumf)
+    }
+}
+
 impl GgmlType for f32 {
     const DTYPE: GgmlDType = GgmlDType::F32;
     const BLCK_SIZE: usize = 1;
@@ -103,6 +103,22 @@ impl QMetalStorage {
                 let vec: Vec<crate::quantized::BlockQ8K> = read_to_vec(&buffer, block_len);
                 crate::quantized::BlockQ8K::to_float(&vec, &mut out)?;
             }
+            GgmlDType::IQ4NL => {
+                let vec: Vec<crate::quantized::BlockIQ4NL> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4NL::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ4XS => {
+                let vec: Vec<crate::quantized::BlockIQ4XS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4XS::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ2XXS => {
+                let vec: Vec<crate::quantized::BlockIQ2XXS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ2XXS::to_float(&vec, &mut out)?;
+            }
+            GgmlDType::IQ3S => {
+                let vec: Vec<crate::quantized::BlockIQ3S> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ3S::to_float(&vec, &mut out)?;
+            }
         }
 
         let buffer = self.device.new_buffer_with_data(&out)?;
@@ -173,7 +189,7 @@ impl QMetalStorage {
                 device.device(),
                 &command_buffer,
                 device.kernels(),
-                self.dtype.into(),
+                self.dtype.try_into()?,
                 (1, 1, n, k),
                 storage.buffer(),
                 (layout.start_offset() + batch_id * k) * storage.dtype().size_in_bytes(),
@@ -208,9 +224,11 @@ fn read_to_vec<T: Clone>(buffer: &Buffer, n: usize) -> Vec<T> {
     slice.to_vec()
 }
 
-impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
-    fn from(value: GgmlDType) -> Self {
-        match value {
+impl TryFrom<GgmlDType> for candle_metal_kernels::GgmlDType {
+    type Error = crate::Error;
+
+    fn try_from(value: GgmlDType) -> Result<Self> {
+        let dtype = match value {
             GgmlDType::Q4_0 => candle_metal_kernels::GgmlDType::Q4_0,
             GgmlDType::Q4_1 => candle_metal_kernels::GgmlDType::Q4_1,
             GgmlDType::Q5_0 => candle_metal_kernels::GgmlDType::Q5_0,
@@ -225,6 +243,10 @@ impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
             GgmlDType::Q8K => candle_metal_kernels::GgmlDType::Q8K,
             GgmlDType::F16 => candle_metal_kernels::GgmlDType::F16,
             GgmlDType::F32 => candle_metal_kernels::GgmlDType::F32,
-        }
+            GgmlDType::IQ4NL | GgmlDType::IQ4XS | GgmlDType::IQ2XXS | GgmlDType::IQ3S => {
+                crate::bail!("quantized matmul is not supported for {value:?} on metal")
+            }
+        };
+        Ok(dtype)
     }
 }
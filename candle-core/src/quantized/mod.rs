@@ -8,6 +8,7 @@ mod dummy_cuda;
 mod dummy_metal;
 pub mod ggml_file;
 pub mod gguf_file;
+mod iq_grids;
 pub mod k_quants;
 #[cfg(feature = "metal")]
 pub mod metal;
@@ -51,6 +52,7 @@ impl Device {
                 let storage = cuda::QCudaStorage::zeros(cuda, elem_count, dtype)?;
                 Ok(QStorage::Cuda(storage))
             }
+            Device::Wgpu(_) => crate::bail!("quantized tensors are not supported on wgpu"),
         }
     }
 }
@@ -145,6 +147,10 @@ pub enum GgmlDType {
     Q5K,
     Q6K,
     Q8K,
+    IQ4NL,
+    IQ4XS,
+    IQ2XXS,
+    IQ3S,
 }
 
 impl GgmlDType {
@@ -164,6 +170,13 @@ impl GgmlDType {
             13 => Self::Q5K,
             14 => Self::Q6K,
             15 => Self::Q8K,
+            16 => Self::IQ2XXS,
+            20 => Self::IQ4NL,
+            21 => Self::IQ3S,
+            23 => Self::IQ4XS,
+            17 | 18 | 19 | 22 | 29 => {
+                crate::bail!("unsupported dtype for tensor {u}, only the IQ2_XXS, IQ3_S, IQ4_NL and IQ4_XS iq quantizations are supported")
+            }
             _ => crate::bail!("unknown dtype for tensor {u}"),
         };
         Ok(dtype)
@@ -185,6 +198,10 @@ impl GgmlDType {
             Self::Q5K => 13,
             Self::Q6K => 14,
             Self::Q8K => 15,
+            Self::IQ4NL => 20,
+            Self::IQ4XS => 23,
+            Self::IQ2XXS => 16,
+            Self::IQ3S => 21,
         }
     }
 
@@ -205,6 +222,19 @@ impl GgmlDType {
             Self::Q5K => Box::new(vec![BlockQ5K::zeros(); elem_count / BlockQ5K::BLCK_SIZE]),
             Self::Q6K => Box::new(vec![BlockQ6K::zeros(); elem_count / BlockQ6K::BLCK_SIZE]),
             Self::Q8K => Box::new(vec![BlockQ8K::zeros(); elem_count / BlockQ8K::BLCK_SIZE]),
+            Self::IQ4NL => Box::new(vec![
+                BlockIQ4NL::zeros();
+                elem_count / BlockIQ4NL::BLCK_SIZE
+            ]),
+            Self::IQ4XS => Box::new(vec![
+                BlockIQ4XS::zeros();
+                elem_count / BlockIQ4XS::BLCK_SIZE
+            ]),
+            Self::IQ2XXS => Box::new(vec![
+                BlockIQ2XXS::zeros();
+                elem_count / BlockIQ2XXS::BLCK_SIZE
+            ]),
+            Self::IQ3S => Box::new(vec![BlockIQ3S::zeros(); elem_count / BlockIQ3S::BLCK_SIZE]),
         }
     }
     /// The type size for blocks in bytes.
@@ -226,6 +256,10 @@ impl GgmlDType {
             Self::Q5K => std::mem::size_of::<BlockQ5K>(),
             Self::Q6K => std::mem::size_of::<BlockQ6K>(),
             Self::Q8K => std::mem::size_of::<BlockQ8K>(),
+            Self::IQ4NL => std::mem::size_of::<BlockIQ4NL>(),
+            Self::IQ4XS => std::mem::size_of::<BlockIQ4XS>(),
+            Self::IQ2XXS => std::mem::size_of::<BlockIQ2XXS>(),
+            Self::IQ3S => std::mem::size_of::<BlockIQ3S>(),
         }
     }
 
@@ -240,7 +274,16 @@ impl GgmlDType {
             Self::Q5_1 => k_quants::QK5_1,
             Self::Q8_0 => k_quants::QK8_0,
             Self::Q8_1 => k_quants::QK8_1,
-            Self::Q2K | Self::Q3K | Self::Q4K | Self::Q5K | Self::Q6K | Self::Q8K => k_quants::QK_K,
+            Self::IQ4NL => k_quants::QK4_NL,
+            Self::Q2K
+            | Self::Q3K
+            | Self::Q4K
+            | Self::Q5K
+            | Self::Q6K
+            | Self::Q8K
+            | Self::IQ4XS
+            | Self::IQ2XXS
+            | Self::IQ3S => k_quants::QK_K,
         }
     }
 }
@@ -321,11 +364,21 @@ impl QTensor {
         Ok(Self { storage, shape })
     }
 
+    /// Quantizes a tensor using the block format `dtype`, the block scales and quantized values
+    /// are computed in the same way as the reference quantization functions from llama.cpp.
+    /// The last dimension of the tensor has to be a multiple of the block size.
     pub fn quantize(src: &Tensor, dtype: GgmlDType) -> Result<Self> {
         let shape = src.shape();
         let block_size = dtype.block_size();
         check_shape(shape, block_size)?;
-        let src = src.to_dtype(crate::DType::F32)?.flatten_all()?;
+        // The whole storage gets quantized so f32 tensors are copied as they may be views on a
+        // larger storage, `to_dtype` already returns a new storage for other dtypes.
+        let src = if src.dtype() == crate::DType::F32 {
+            src.force_contiguous()?
+        } else {
+            src.to_dtype(crate::DType::F32)?
+        };
+        let src = src.flatten_all()?;
         let elem_count = shape.elem_count();
         if elem_count % block_size != 0 {
             crate::bail!(
@@ -360,7 +413,7 @@ impl QTensor {
     pub fn dequantize(&self, device: &Device) -> Result<Tensor> {
         let storage = self.storage.dequantize(self.shape.elem_count())?;
         let none = crate::op::BackpropOp::none();
-        crate::tensor::from_storage(storage, self.shape.clone(), none, false).to_device(device)
+        crate::tensor::from_storage(storage, self.shape.clone(), none, false)?.to_device(device)
     }
 
     pub fn dequantize_f16(&self, device: &Device) -> Result<Tensor> {
@@ -370,7 +423,7 @@ impl QTensor {
             QStorage::Cuda(s) => {
                 let s = s.dequantize_f16(self.shape.elem_count())?;
                 let none = crate::op::BackpropOp::none();
-                crate::tensor::from_storage(Storage::Cuda(s), self.shape.clone(), none, false)
+                crate::tensor::from_storage(Storage::Cuda(s), self.shape.clone(), none, false)?
                     .to_device(device)
             }
             _ => {
@@ -5,16 +5,20 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::path::Path;
 
-impl From<DType> for st::Dtype {
-    fn from(value: DType) -> Self {
+impl TryFrom<DType> for st::Dtype {
+    type Error = Error;
+    fn try_from(value: DType) -> Result<Self> {
         match value {
-            DType::U8 => st::Dtype::U8,
-            DType::U32 => st::Dtype::U32,
-            DType::I64 => st::Dtype::I64,
-            DType::BF16 => st::Dtype::BF16,
-            DType::F16 => st::Dtype::F16,
-            DType::F32 => st::Dtype::F32,
-            DType::F64 => st::Dtype::F64,
+            DType::U8 => Ok(st::Dtype::U8),
+            DType::U32 => Ok(st::Dtype::U32),
+            DType::I64 => Ok(st::Dtype::I64),
+            DType::BF16 => Ok(st::Dtype::BF16),
+            DType::F16 => Ok(st::Dtype::F16),
+            DType::F32 => Ok(st::Dtype::F32),
+            DType::F64 => Ok(st::Dtype::F64),
+            dtype @ (DType::C64 | DType::C128) => {
+                Err(Error::UnsupportedDTypeForOp(dtype, "safetensors").bt())
+            }
         }
     }
 }
@@ -37,7 +41,11 @@ impl TryFrom<st::Dtype> for DType {
 
 impl st::View for Tensor {
     fn dtype(&self) -> st::Dtype {
-        self.dtype().into()
+        // `save` and `serialize` return an error for complex dtypes before serializing, this
+        // only panics when serializing complex tensors directly with the safetensors crate.
+        self.dtype()
+            .try_into()
+            .expect("complex dtypes cannot be serialized to safetensors")
     }
     fn shape(&self) -> &[usize] {
         self.shape().dims()
@@ -58,7 +66,12 @@ impl st::View for Tensor {
 
 impl st::View for &Tensor {
     fn dtype(&self) -> st::Dtype {
-        (*self).dtype().into()
+        // `save` and `serialize` return an error for complex dtypes before serializing, this
+        // only panics when serializing complex tensors directly with the safetensors crate.
+        (*self)
+            .dtype()
+            .try_into()
+            .expect("complex dtypes cannot be serialized to safetensors")
     }
     fn shape(&self) -> &[usize] {
         self.dims()
@@ -77,8 +90,17 @@ impl st::View for &Tensor {
     }
 }
 
+// Returns an error if some of the tensors cannot be stored in the safetensors format.
+fn check_dtypes<'a>(tensors: impl IntoIterator<Item = &'a Tensor>) -> Result<()> {
+    for tensor in tensors {
+        st::Dtype::try_from(tensor.dtype())?;
+    }
+    Ok(())
+}
+
 impl Tensor {
     pub fn save_safetensors<P: AsRef<Path>>(&self, name: &str, filename: P) -> Result<()> {
+        check_dtypes([self])?;
         let data = [(name, self.clone())];
         Ok(st::serialize_to_file(data, &None, filename.as_ref())?)
     }
@@ -192,6 +214,9 @@ impl Tensor {
             DType::F16 => convert_slice::<half::f16>(data, shape, device),
             DType::F32 => convert_slice::<f32>(data, shape, device),
             DType::F64 => convert_slice::<f64>(data, shape, device),
+            DType::C64 | DType::C128 => {
+                Err(Error::UnsupportedDTypeForOp(dtype, "from_raw_buffer").bt())
+            }
         }
     }
 }
@@ -228,6 +253,9 @@ fn convert_back(tensor: &Tensor) -> Result<Vec<u8>> {
         DType::BF16 => Ok(convert_back_::<half::bf16>(tensor.to_vec1()?)),
         DType::F32 => Ok(convert_back_::<f32>(tensor.to_vec1()?)),
         DType::F64 => Ok(convert_back_::<f64>(tensor.to_vec1()?)),
+        dtype @ (DType::C64 | DType::C128) => {
+            Err(Error::UnsupportedDTypeForOp(dtype, "safetensors").bt())
+        }
     }
 }
 
@@ -248,12 +276,80 @@ pub fn save<K: AsRef<str> + Ord + std::fmt::Display, P: AsRef<Path>>(
     tensors: &HashMap<K, Tensor>,
     filename: P,
 ) -> Result<()> {
+    check_dtypes(tensors.values())?;
     Ok(st::serialize_to_file(tensors, &None, filename.as_ref())?)
 }
 
+/// Serializes some tensors to a buffer in the safetensors format, complex tensors are not
+/// supported by the format and result in an error.
+pub fn serialize<'a, K: AsRef<str> + Ord + std::fmt::Display + 'a>(
+    tensors: impl IntoIterator<Item = (K, &'a Tensor)>,
+) -> Result<Vec<u8>> {
+    let tensors = tensors.into_iter().collect::<Vec<_>>();
+    check_dtypes(tensors.iter().map(|(_, tensor)| *tensor))?;
+    Ok(st::serialize(tensors, &None)?)
+}
+
 #[derive(yoke::Yokeable)]
 struct SafeTensors_<'a>(SafeTensors<'a>);
 
+/// Reads a sharded safetensors index file, e.g. `model.safetensors.index.json`, returning the
+/// list of shard files, resolved relative to the index directory, and the mapping from tensor
+/// names to indexes in this list.
+fn read_index<P: AsRef<Path>>(
+    index_path: P,
+) -> Result<(Vec<std::path::PathBuf>, HashMap<String, usize>)> {
+    let index_path = index_path.as_ref();
+    let index = std::fs::read(index_path).map_err(|e| Error::from(e).with_path(index_path))?;
+    let index: serde_json::Value =
+        serde_json::from_slice(&index).map_err(|e| Error::msg(e).with_path(index_path))?;
+    let weight_map = match index.get("weight_map") {
+        Some(serde_json::Value::Object(weight_map)) => weight_map,
+        _ => Err(Error::Msg("no weight_map in index".to_string()).with_path(index_path))?,
+    };
+    let dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = vec![];
+    let mut file_indexes = HashMap::new();
+    let mut routing = HashMap::new();
+    for (name, file) in weight_map.iter() {
+        let file = match file.as_str() {
+            Some(file) => file,
+            None => Err(
+                Error::Msg(format!("unexpected shard for {name} in index: {file}"))
+                    .with_path(index_path),
+            )?,
+        };
+        let index = *file_indexes.entry(file.to_string()).or_insert_with(|| {
+            files.push(dir.join(file));
+            files.len() - 1
+        });
+        routing.insert(name.to_string(), index);
+    }
+    Ok((files, routing))
+}
+
+/// Loads all the tensors referenced by a sharded safetensors index file, e.g.
+/// `model.safetensors.index.json`. The shard paths are relative to the directory containing the
+/// index. An error is returned if a shard is missing or does not contain the tensors that the
+/// index maps to it.
+pub fn load_sharded<P: AsRef<Path>>(
+    index_path: P,
+    device: &Device,
+) -> Result<HashMap<String, Tensor>> {
+    let (files, routing) = read_index(index_path)?;
+    let mut shards = files
+        .iter()
+        .map(|p| load(p, device).map_err(|e| e.with_path(p)))
+        .collect::<Result<Vec<_>>>()?;
+    routing
+        .into_iter()
+        .map(|(name, index)| match shards[index].remove(&name) {
+            Some(tensor) => Ok((name, tensor)),
+            None => Err(Error::CannotFindTensor { path: name }.with_path(&files[index])),
+        })
+        .collect()
+}
+
 pub struct MmapedSafetensors {
     safetensors: Vec<yoke::Yoke<SafeTensors_<'static>, memmap2::Mmap>>,
     routing: Option<HashMap<String, usize>>,
@@ -320,6 +416,30 @@ impl MmapedSafetensors {
         })
     }
 
+    /// Memory maps the shards referenced by a sharded safetensors index file, e.g.
+    /// `model.safetensors.index.json`, tensor names are resolved to shards using the index.
+    ///
+    /// An error is returned if a shard is missing or does not contain the tensors that the index
+    /// maps to it.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn from_index<P: AsRef<Path>>(index_path: P) -> Result<Self> {
+        let (files, routing) = read_index(index_path)?;
+        let mut st = Self::multi(&files)?;
+        for (name, &index) in routing.iter() {
+            if st.safetensors[index].get().0.tensor(name).is_err() {
+                Err(Error::CannotFindTensor {
+                    path: name.to_string(),
+                }
+                .with_path(&files[index]))?
+            }
+        }
+        st.routing = Some(routing);
+        Ok(st)
+    }
+
     pub fn load(&self, name: &str, dev: &Device) -> Result<Tensor> {
         self.get(name)?.load(dev)
     }
@@ -403,6 +523,93 @@ impl BufferedSafetensors {
     }
 }
 
+/// Reads the tensors of a safetensors file on demand. Only the header is read when creating the
+/// reader, the data for a tensor is read when loading it by seeking to its byte range. Contrary
+/// to [`MmapedSafetensors`] this does not require memory mapping the file which can be costly,
+/// e.g. on network filesystems, and the data for unused tensors is never read.
+pub struct SafeTensorsReader<R> {
+    reader: std::sync::Mutex<R>,
+    metadata: st::Metadata,
+    names: Vec<String>,
+    // The offset of the tensor data, i.e. the size of the header.
+    data_start: u64,
+}
+
+impl SafeTensorsReader<std::fs::File> {
+    /// Opens a safetensors file and reads its header.
+    pub fn from_file<P: AsRef<Path>>(p: P) -> Result<Self> {
+        let p = p.as_ref();
+        let file = std::fs::File::open(p).map_err(|e| Error::from(e).with_path(p))?;
+        Self::new(file).map_err(|e| e.with_path(p))
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek> SafeTensorsReader<R> {
+    /// Creates a reader from a source in the safetensors format, this reads the header from the
+    /// current position of `reader`.
+    pub fn new(mut reader: R) -> Result<Self> {
+        // Same limit as the one used by the safetensors crate.
+        const MAX_HEADER_SIZE: u64 = 100_000_000;
+        let start = reader.stream_position()?;
+        let mut header_size = [0u8; 8];
+        reader.read_exact(&mut header_size)?;
+        let header_size = u64::from_le_bytes(header_size);
+        if header_size > MAX_HEADER_SIZE {
+            Err(safetensors::SafeTensorError::HeaderTooLarge)?
+        }
+        let mut header = vec![0u8; header_size as usize];
+        reader.read_exact(&mut header)?;
+        let metadata: st::Metadata = serde_json::from_slice(&header)
+            .map_err(|_| safetensors::SafeTensorError::InvalidHeaderDeserialization)?;
+        let mut names: Vec<String> = metadata.tensors().into_keys().collect();
+        names.sort();
+        Ok(Self {
+            reader: std::sync::Mutex::new(reader),
+            metadata,
+            names,
+            data_start: start + 8 + header_size,
+        })
+    }
+
+    /// The names of the tensors in the file, sorted alphabetically.
+    pub fn tensor_names(&self) -> Vec<&str> {
+        self.names.iter().map(|name| name.as_str()).collect()
+    }
+
+    /// The dtype, shape and data offsets of a tensor, the offsets are relative to the end of the
+    /// header.
+    pub fn tensor_info(&self, name: &str) -> Result<&st::TensorInfo> {
+        self.metadata.info(name).ok_or_else(|| {
+            Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt()
+        })
+    }
+
+    /// Reads the data for tensor `name` and loads it on `dev`.
+    pub fn load(&self, name: &str, dev: &Device) -> Result<Tensor> {
+        let info = self.tensor_info(name)?;
+        let (start, end) = info.data_offsets;
+        if end < start {
+            Err(safetensors::SafeTensorError::InvalidOffset(
+                name.to_string(),
+            ))?
+        }
+        let mut data = vec![0u8; end - start];
+        {
+            let mut reader = self
+                .reader
+                .lock()
+                .map_err(|_| Error::Msg(format!("poisoned safetensors reader for {name}")))?;
+            reader.seek(std::io::SeekFrom::Start(self.data_start + start as u64))?;
+            reader.read_exact(&mut data)?;
+        }
+        let view = st::TensorView::new(info.dtype, info.shape.clone(), &data)?;
+        convert(&view, dev)
+    }
+}
+
 pub struct MmapedFile {
     path: std::path::PathBuf,
     inner: memmap2::Mmap,
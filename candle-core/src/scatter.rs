@@ -0,0 +1,272 @@
+//! Scatter operations that overwrite or reduce the destination values.
+use crate::backend::BackendStorage;
+use crate::cpu_backend::Map2;
+use crate::{CpuStorage, CustomOp3, Device, Error, Layout, Result, Shape, Tensor, WithDType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScatterReduce {
+    None,
+    Max,
+    Min,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Scatter {
+    dim: usize,
+    reduce: ScatterReduce,
+}
+
+impl Scatter {
+    fn name(&self) -> &'static str {
+        match self.reduce {
+            ScatterReduce::None => "scatter",
+            ScatterReduce::Max => "scatter-max",
+            ScatterReduce::Min => "scatter-min",
+        }
+    }
+}
+
+struct ScatterMap<'a> {
+    scatter: &'a Scatter,
+    ids: &'a [usize],
+    ids_l: &'a Layout,
+}
+
+impl<'a> Map2 for ScatterMap<'a> {
+    const OP: &'static str = "scatter";
+    fn f<T: WithDType>(&self, v1: &[T], l1: &Layout, src: &[T], src_l: &Layout) -> Result<Vec<T>> {
+        let mut dst = Vec::with_capacity(l1.shape().elem_count());
+        for index in l1.strided_index() {
+            dst.push(v1[index])
+        }
+        let src = match src_l.contiguous_offsets() {
+            None => Err(Error::RequiresContiguous {
+                op: self.scatter.name(),
+            }
+            .bt())?,
+            Some((o1, o2)) => &src[o1..o2],
+        };
+
+        let dim = self.scatter.dim;
+        let ids_dims = self.ids_l.dims();
+        let dst_dims = l1.dims();
+        let dst_dim_len = dst_dims[dim];
+        let dst_right_len: usize = dst_dims[dim + 1..].iter().product();
+
+        let ids_left_len: usize = ids_dims[..dim].iter().product();
+        let ids_dim_len = ids_dims[dim];
+        let ids_right_len: usize = ids_dims[dim + 1..].iter().product();
+        for left_i in 0..ids_left_len {
+            let start_ids_idx = left_i * ids_right_len * ids_dim_len;
+            let start_dst_idx = left_i * dst_right_len * dst_dim_len;
+            for i in 0..ids_dim_len {
+                let start_ids_idx = start_ids_idx + i * ids_right_len;
+                for right_i in 0..dst_right_len {
+                    let ids_idx = start_ids_idx + right_i;
+                    let index = self.ids[ids_idx];
+                    if index >= dst_dim_len {
+                        Err(Error::InvalidIndex {
+                            index,
+                            size: dst_dim_len,
+                            op: self.scatter.name(),
+                        }
+                        .bt())?
+                    }
+                    let dst_idx = start_dst_idx + index * dst_right_len + right_i;
+                    let v = src[ids_idx];
+                    match self.scatter.reduce {
+                        ScatterReduce::None => dst[dst_idx] = v,
+                        ScatterReduce::Max => {
+                            if v > dst[dst_idx] {
+                                dst[dst_idx] = v
+                            }
+                        }
+                        ScatterReduce::Min => {
+                            if v < dst[dst_idx] {
+                                dst[dst_idx] = v
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(dst)
+    }
+}
+
+impl CustomOp3 for Scatter {
+    fn name(&self) -> &'static str {
+        Scatter::name(self)
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        ids: &CpuStorage,
+        ids_l: &Layout,
+        s3: &CpuStorage,
+        l3: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        let (o1, o2) = match ids_l.contiguous_offsets() {
+            Some(offsets) => offsets,
+            None => Err(Error::RequiresContiguous { op: self.name() }.bt())?,
+        };
+        let ids: Vec<usize> = match ids {
+            CpuStorage::U8(ids) => ids[o1..o2].iter().map(|&v| v as usize).collect(),
+            CpuStorage::U32(ids) => ids[o1..o2].iter().map(|&v| v as usize).collect(),
+            CpuStorage::I64(ids) => ids[o1..o2].iter().map(|&v| v as usize).collect(),
+            _ => Err(Error::UnsupportedDTypeForOp(ids.dtype(), self.name()).bt())?,
+        };
+        let map = ScatterMap {
+            scatter: self,
+            ids: &ids,
+            ids_l,
+        };
+        let storage = map.map(s1, l1, s3, l3)?;
+        Ok((storage, l1.shape().clone()))
+    }
+}
+
+impl Tensor {
+    fn scatter_(&self, indexes: &Self, source: &Self, dim: usize, op: Scatter) -> Result<Self> {
+        let source_dims = source.dims();
+        let self_dims = self.dims();
+        let mismatch = source_dims.len() != self_dims.len()
+            || self_dims
+                .iter()
+                .zip(source_dims.iter())
+                .enumerate()
+                .any(|(i, (&d1, &d2))| i != dim && d1 != d2);
+        if mismatch {
+            Err(Error::ShapeMismatchBinaryOp {
+                op: op.name(),
+                lhs: self.shape().clone(),
+                rhs: source.shape().clone(),
+            }
+            .bt())?
+        }
+        if indexes.dims() != source.dims() {
+            Err(Error::ShapeMismatchBinaryOp {
+                op: op.name(),
+                lhs: indexes.shape().clone(),
+                rhs: source.shape().clone(),
+            }
+            .bt())?
+        }
+        let indexes = indexes.contiguous()?;
+        let source = source.contiguous()?;
+        // These ops only have a cpu implementation.
+        match self.device() {
+            Device::Cpu => self.apply_op3_no_bwd(&indexes, &source, &op),
+            device => self
+                .to_device(&Device::Cpu)?
+                .apply_op3_no_bwd(
+                    &indexes.to_device(&Device::Cpu)?,
+                    &source.to_device(&Device::Cpu)?,
+                    &op,
+                )?
+                .to_device(device),
+        }
+    }
+
+    /// Writes the values of `source` into a copy of `self` at the positions given by `indexes`
+    /// along dimension `dim`. The shape constraints are the same as for `scatter_add`, when
+    /// multiple values are written to the same position the last one in row-major order is kept.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::zeros((2, 3), candle_core::DType::F32, &Device::Cpu)?;
+    /// let ids = Tensor::new(&[[2u32, 0], [1, 1]], &Device::Cpu)?;
+    /// let src = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    /// let t = t.scatter(1, &ids, &src)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[2., 0., 1.], [0., 4., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn scatter<D: crate::shape::Dim>(
+        &self,
+        dim: D,
+        indexes: &Self,
+        source: &Self,
+    ) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "scatter")?;
+        let op = Scatter {
+            dim,
+            reduce: ScatterReduce::None,
+        };
+        self.scatter_(indexes, source, dim, op)
+    }
+
+    /// Similar to `scatter` but each position of the result holds the maximum of the value from
+    /// `self` and of all the `source` values written to this position.
+    pub fn scatter_max<D: crate::shape::Dim>(
+        &self,
+        dim: D,
+        indexes: &Self,
+        source: &Self,
+    ) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "scatter-max")?;
+        let op = Scatter {
+            dim,
+            reduce: ScatterReduce::Max,
+        };
+        self.scatter_(indexes, source, dim, op)
+    }
+
+    /// Similar to `scatter` but each position of the result holds the minimum of the value from
+    /// `self` and of all the `source` values written to this position.
+    pub fn scatter_min<D: crate::shape::Dim>(
+        &self,
+        dim: D,
+        indexes: &Self,
+        source: &Self,
+    ) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "scatter-min")?;
+        let op = Scatter {
+            dim,
+            reduce: ScatterReduce::Min,
+        };
+        self.scatter_(indexes, source, dim, op)
+    }
+
+    /// Copies the slices of `source` into a copy of `self`, the slice at position `i` along
+    /// `dim` in `source` is written at position `indexes[i]` along `dim` in the result. This is
+    /// the overwriting counterpart of `index_add`, when an index is repeated the last slice is
+    /// kept.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::zeros((3, 2), candle_core::DType::F32, &Device::Cpu)?;
+    /// let ids = Tensor::new(&[2u32, 0], &Device::Cpu)?;
+    /// let src = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    /// let t = t.index_copy(&ids, &src, 0)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[3., 4.], [0., 0.], [1., 2.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn index_copy<D: crate::shape::Dim>(
+        &self,
+        indexes: &Self,
+        source: &Self,
+        dim: D,
+    ) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "index-copy")?;
+        let indexes_len = indexes.dims1()?;
+        if source.dim(dim)? != indexes_len {
+            Err(Error::ShapeMismatchBinaryOp {
+                op: "index-copy (ids, source)",
+                lhs: indexes.shape().clone(),
+                rhs: source.shape().clone(),
+            }
+            .bt())?
+        }
+        // Expand the 1d indexes so that they select whole slices of `source`.
+        let mut dims = vec![1; source.rank()];
+        dims[dim] = indexes_len;
+        let indexes = indexes.reshape(dims)?.broadcast_as(source.shape())?;
+        let op = Scatter {
+            dim,
+            reduce: ScatterReduce::None,
+        };
+        self.scatter_(&indexes, source, dim, op)
+    }
+}
@@ -251,7 +251,12 @@ impl Shape {
         let (m, lhs_k) = (lhs_dims[lhs_dims.len() - 2], lhs_dims[lhs_dims.len() - 1]);
         let (rhs_k, n) = (rhs_dims[rhs_dims.len() - 2], rhs_dims[rhs_dims.len() - 1]);
         if lhs_k != rhs_k {
-            crate::bail!("different inner dimensions in broadcast matmul {lhs:?} {rhs:?}")
+            Err(Error::ShapeMismatchBinaryOp {
+                lhs: lhs.clone(),
+                rhs: rhs.clone(),
+                op: "broadcast_matmul",
+            }
+            .bt())?
         }
 
         let lhs_b = Self::from(&lhs_dims[..lhs_dims.len() - 2]);
@@ -8,7 +8,11 @@ struct ArgSort {
 }
 
 impl ArgSort {
-    fn asort<T: crate::WithDType>(&self, vs: &[T], layout: &crate::Layout) -> Vec<u32> {
+    fn asort<T: crate::WithDType>(&self, vs: &[T], layout: &crate::Layout) -> Result<Vec<u32>> {
+        let vs = match layout.contiguous_offsets() {
+            Some((o1, o2)) => &vs[o1..o2],
+            None => Err(crate::Error::RequiresContiguous { op: "argsort" }.bt())?,
+        };
         #[allow(clippy::uninit_vec)]
         // Safety: indexes are set later in the parallelized section.
         let mut sort_indexes = unsafe {
@@ -48,7 +52,7 @@ impl ArgSort {
                     })
                 });
         }
-        sort_indexes
+        Ok(sort_indexes)
     }
 }
 
@@ -63,13 +67,19 @@ impl crate::CustomOp1 for ArgSort {
         layout: &crate::Layout,
     ) -> Result<(crate::CpuStorage, crate::Shape)> {
         let sort_indexes = match storage {
-            crate::CpuStorage::U8(vs) => self.asort(vs, layout),
-            crate::CpuStorage::U32(vs) => self.asort(vs, layout),
-            crate::CpuStorage::I64(vs) => self.asort(vs, layout),
-            crate::CpuStorage::BF16(vs) => self.asort(vs, layout),
-            crate::CpuStorage::F16(vs) => self.asort(vs, layout),
-            crate::CpuStorage::F32(vs) => self.asort(vs, layout),
-            crate::CpuStorage::F64(vs) => self.asort(vs, layout),
+            crate::CpuStorage::U8(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::U32(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::I64(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::BF16(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::F16(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::F32(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::F64(vs) => self.asort(vs, layout)?,
+            crate::CpuStorage::C64(_) => {
+                Err(crate::Error::UnsupportedDTypeForOp(crate::DType::C64, "argsort").bt())?
+            }
+            crate::CpuStorage::C128(_) => {
+                Err(crate::Error::UnsupportedDTypeForOp(crate::DType::C128, "argsort").bt())?
+            }
         };
         let sort_indexes = crate::CpuStorage::U32(sort_indexes);
         Ok((sort_indexes, layout.shape().into()))
@@ -149,6 +159,7 @@ impl crate::CustomOp1 for ArgSort {
                     DType::U8 => "asort_asc_u8",
                     DType::U32 => "asort_asc_u32",
                     DType::I64 => "asort_asc_i64",
+                    dtype => crate::bail!("unsupported dtype for argsort {dtype:?}"),
                 }
             } else {
                 match storage.dtype() {
@@ -159,6 +170,7 @@ impl crate::CustomOp1 for ArgSort {
                     DType::U8 => "asort_desc_u8",
                     DType::U32 => "asort_desc_u32",
                     DType::I64 => "asort_desc_i64",
+                    dtype => crate::bail!("unsupported dtype for argsort {dtype:?}"),
                 }
             }
         };
@@ -200,6 +212,78 @@ fn next_power_of_2(x: usize) -> usize {
     n
 }
 
+#[derive(Debug, Clone, Copy)]
+struct TopK {
+    k: usize,
+    largest: bool,
+    last_dim: usize,
+}
+
+impl TopK {
+    fn topk<T: crate::WithDType>(&self, vs: &[T], layout: &crate::Layout) -> Result<Vec<u32>> {
+        let vs = match layout.contiguous_offsets() {
+            Some((o1, o2)) => &vs[o1..o2],
+            None => Err(crate::Error::RequiresContiguous { op: "topk" }.bt())?,
+        };
+        let mut dst = vec![0u32; vs.len() / self.last_dim * self.k];
+        dst.par_chunks_exact_mut(self.k)
+            .zip(vs.par_chunks_exact(self.last_dim))
+            .for_each(|(dst, vs)| {
+                // Ties are broken using the index so that this matches a stable sort. NaN values
+                // are ordered using the total order on f64.
+                let cmp = |&i: &u32, &j: &u32| {
+                    let (a, b) = (vs[i as usize], vs[j as usize]);
+                    let ord = a
+                        .partial_cmp(&b)
+                        .unwrap_or_else(|| a.to_f64().total_cmp(&b.to_f64()));
+                    let ord = if self.largest { ord.reverse() } else { ord };
+                    ord.then(i.cmp(&j))
+                };
+                let mut indexes: Vec<u32> = (0..self.last_dim as u32).collect();
+                if self.k < self.last_dim {
+                    indexes.select_nth_unstable_by(self.k - 1, cmp);
+                }
+                let indexes = &mut indexes[..self.k];
+                indexes.sort_unstable_by(cmp);
+                dst.copy_from_slice(indexes)
+            });
+        Ok(dst)
+    }
+}
+
+impl crate::CustomOp1 for TopK {
+    fn name(&self) -> &'static str {
+        "topk"
+    }
+
+    fn cpu_fwd(
+        &self,
+        storage: &crate::CpuStorage,
+        layout: &crate::Layout,
+    ) -> Result<(crate::CpuStorage, crate::Shape)> {
+        let indexes = match storage {
+            crate::CpuStorage::U8(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::U32(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::I64(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::BF16(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::F16(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::F32(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::F64(vs) => self.topk(vs, layout)?,
+            crate::CpuStorage::C64(_) => {
+                Err(crate::Error::UnsupportedDTypeForOp(crate::DType::C64, "topk").bt())?
+            }
+            crate::CpuStorage::C128(_) => {
+                Err(crate::Error::UnsupportedDTypeForOp(crate::DType::C128, "topk").bt())?
+            }
+        };
+        let mut dims = layout.dims().to_vec();
+        if let Some(last) = dims.last_mut() {
+            *last = self.k
+        }
+        Ok((crate::CpuStorage::U32(indexes), dims.into()))
+    }
+}
+
 impl Tensor {
     /// Returns the indices that sort the tensor along the last dimension.
     ///
@@ -236,4 +320,420 @@ impl Tensor {
         let sorted = self.gather(&asort, crate::D::Minus1)?;
         Ok((sorted, asort))
     }
+
+    /// Returns the indices that sort the tensor along dimension `dim`, the indices use the `u32`
+    /// dtype.
+    ///
+    /// If `descending` is `true`, sorting is performed in descending order. The tensor does not
+    /// have to be contiguous. On the cpu the sort is stable so ties keep their original order.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3f32, 1., 2.], [1., 2., 1.]], &Device::Cpu)?;
+    /// let indices = t.argsort(1, false)?;
+    /// assert_eq!(indices.to_vec2::<u32>()?, &[[1, 2, 0], [0, 2, 1]]);
+    /// let indices = t.argsort(0, true)?;
+    /// assert_eq!(indices.to_vec2::<u32>()?, &[[0, 1, 0], [1, 0, 1]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn argsort<D: crate::shape::Dim>(&self, dim: D, descending: bool) -> Result<Tensor> {
+        let dim = dim.to_index(self.shape(), "argsort")?;
+        let last_dim = self.rank() - 1;
+        if dim == last_dim {
+            self.contiguous()?.arg_sort_last_dim(!descending)
+        } else {
+            self.transpose(dim, last_dim)?
+                .contiguous()?
+                .arg_sort_last_dim(!descending)?
+                .transpose(dim, last_dim)?
+                .contiguous()
+        }
+    }
+
+    /// Sorts the tensor along dimension `dim`, returns the sorted tensor together with the
+    /// indices of the sorted values in the original tensor.
+    ///
+    /// If `descending` is `true`, sorting is performed in descending order. The tensor does not
+    /// have to be contiguous. On the cpu the sort is stable so ties keep their original order.
+    pub fn sort<D: crate::shape::Dim>(&self, dim: D, descending: bool) -> Result<(Tensor, Tensor)> {
+        let dim = dim.to_index(self.shape(), "sort")?;
+        let indices = self.argsort(dim, descending)?;
+        let sorted = self.contiguous()?.gather(&indices, dim)?;
+        Ok((sorted, indices))
+    }
+
+    fn topk_<D: crate::shape::Dim>(
+        &self,
+        k: usize,
+        dim: D,
+        largest: bool,
+        op: &'static str,
+    ) -> Result<(Tensor, Tensor)> {
+        let dim = dim.to_index(self.shape(), op)?;
+        let size = self.dim(dim)?;
+        if k > size {
+            crate::bail!("{op}: k ({k}) is larger than the size of dim {dim} ({size})")
+        }
+        if k == 0 || !self.device().is_cpu() {
+            // The partial selection is only implemented on the cpu.
+            let (sorted, indices) = self.sort(dim, largest)?;
+            return Ok((sorted.narrow(dim, 0, k)?, indices.narrow(dim, 0, k)?));
+        }
+        let last_dim = self.rank() - 1;
+        let op = TopK {
+            k,
+            largest,
+            last_dim: size,
+        };
+        let indices = if dim == last_dim {
+            self.contiguous()?.apply_op1_no_bwd(&op)?
+        } else {
+            self.transpose(dim, last_dim)?
+                .contiguous()?
+                .apply_op1_no_bwd(&op)?
+                .transpose(dim, last_dim)?
+                .contiguous()?
+        };
+        let values = self.contiguous()?.gather(&indices, dim)?;
+        Ok((values, indices))
+    }
+
+    /// Returns the `k` largest values along dimension `dim` together with their indices, the
+    /// indices use the `u32` dtype.
+    ///
+    /// The values are returned in descending order, ties are ordered by increasing index. On the
+    /// cpu this uses a partial selection rather than sorting the whole dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3f32, 1., 4., 1., 5.], [9., 2., 6., 5., 3.]], &Device::Cpu)?;
+    /// let (values, indices) = t.topk(2, 1)?;
+    /// assert_eq!(values.to_vec2::<f32>()?, &[[5., 4.], [9., 6.]]);
+    /// assert_eq!(indices.to_vec2::<u32>()?, &[[4, 2], [0, 2]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn topk<D: crate::shape::Dim>(&self, k: usize, dim: D) -> Result<(Tensor, Tensor)> {
+        self.topk_(k, dim, true, "topk")
+    }
+
+    /// Returns the `k` smallest values along dimension `dim` together with their indices, the
+    /// values are returned in ascending order.
+    pub fn topk_smallest<D: crate::shape::Dim>(
+        &self,
+        k: usize,
+        dim: D,
+    ) -> Result<(Tensor, Tensor)> {
+        self.topk_(k, dim, false, "topk_smallest")
+    }
+
+    /// Returns the `u32` indices at which `values` would have to be inserted in `self` to keep
+    /// it sorted, `self` being sorted in ascending order along its last dimension.
+    ///
+    /// When `right` is false, the returned index is the first one where the element of `self` is
+    /// greater or equal to the value, when `right` is true the first one where it is strictly
+    /// greater. A 1d `self` is used for all the values, otherwise the leading dimensions of `self`
+    /// and `values` are broadcasted together.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let sorted = Tensor::new(&[1f32, 2., 2., 3.], &Device::Cpu)?;
+    /// let values = Tensor::new(&[2f32, 0., 5.], &Device::Cpu)?;
+    /// assert_eq!(sorted.searchsorted(&values, false)?.to_vec1::<u32>()?, &[1, 0, 4]);
+    /// assert_eq!(sorted.searchsorted(&values, true)?.to_vec1::<u32>()?, &[3, 0, 4]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn searchsorted(&self, values: &Tensor, right: bool) -> Result<Tensor> {
+        if self.dtype() != values.dtype() {
+            Err(crate::Error::DTypeMismatchBinaryOp {
+                lhs: self.dtype(),
+                rhs: values.dtype(),
+                op: "searchsorted",
+            }
+            .bt())?
+        }
+        let n = match self.dims() {
+            [] => crate::bail!("searchsorted: the sorted tensor must have at least one dim"),
+            dims => dims[dims.len() - 1],
+        };
+        let (sorted, vs, out_shape) = if self.rank() == 1 {
+            let m = values.elem_count();
+            let vs = values.reshape((1, m))?;
+            (self.unsqueeze(0)?, vs, values.shape().clone())
+        } else {
+            if values.rank() == 0 {
+                crate::bail!("searchsorted: values must have at least one dim")
+            }
+            let (s_batch, v_batch) = (&self.dims()[..self.rank() - 1], values.dims());
+            let (v_batch, m) = v_batch.split_at(v_batch.len() - 1);
+            let batch = crate::Shape::from(s_batch)
+                .broadcast_shape_binary_op(&crate::Shape::from(v_batch), "searchsorted")?;
+            let sorted = self.broadcast_as([batch.dims(), &[n]].concat())?;
+            let out_shape = crate::Shape::from([batch.dims(), m].concat());
+            let vs = values.broadcast_as(&out_shape)?;
+            let b = batch.elem_count();
+            (sorted.reshape((b, n))?, vs.reshape((b, m[0]))?, out_shape)
+        };
+        // The search is done on the host using f64 values.
+        let sorted = sorted.to_dtype(crate::DType::F64)?.to_vec2::<f64>()?;
+        let vs = vs.to_dtype(crate::DType::F64)?.to_vec2::<f64>()?;
+        let indexes: Vec<u32> = sorted
+            .iter()
+            .zip(vs.iter())
+            .flat_map(|(sorted, vs)| {
+                vs.iter().map(move |&v| {
+                    let index = if right {
+                        sorted.partition_point(|&s| s <= v)
+                    } else {
+                        sorted.partition_point(|&s| s < v)
+                    };
+                    index as u32
+                })
+            })
+            .collect();
+        Tensor::from_vec(indexes, out_shape, values.device())
+    }
+
+    fn unique_<T: crate::WithDType>(&self, sorted: bool) -> Result<(Tensor, Vec<u32>, Vec<u32>)> {
+        let vs = self.flatten_all()?.to_vec1::<T>()?;
+        // A stable sort keeps the first occurrence of each value at the start of its group.
+        let mut order: Vec<usize> = (0..vs.len()).collect();
+        order.sort_by(|&i, &j| {
+            vs[i]
+                .partial_cmp(&vs[j])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        // Groups of equal values, as (first index in the input, number of elements).
+        let mut groups: Vec<(usize, u32)> = vec![];
+        let mut group_of = vec![0u32; vs.len()];
+        for (pos, &i) in order.iter().enumerate() {
+            match groups.last_mut() {
+                Some((_, count)) if pos > 0 && vs[order[pos - 1]] == vs[i] => *count += 1,
+                _ => groups.push((i, 1)),
+            }
+            group_of[i] = (groups.len() - 1) as u32
+        }
+        if !sorted {
+            // Reorder the groups by first occurrence and remap the inverse indices.
+            let mut perm: Vec<usize> = (0..groups.len()).collect();
+            perm.sort_by_key(|&g| groups[g].0);
+            let mut rank = vec![0u32; groups.len()];
+            for (r, &g) in perm.iter().enumerate() {
+                rank[g] = r as u32
+            }
+            groups = perm.iter().map(|&g| groups[g]).collect();
+            group_of.iter_mut().for_each(|g| *g = rank[*g as usize]);
+        }
+        let values: Vec<T> = groups.iter().map(|&(i, _)| vs[i]).collect();
+        let counts = groups.iter().map(|&(_, c)| c).collect();
+        let values = Tensor::from_vec(values, groups.len(), self.device())?;
+        Ok((values, group_of, counts))
+    }
+
+    fn unique_all(&self, sorted: bool) -> Result<(Tensor, Vec<u32>, Vec<u32>)> {
+        use crate::DType;
+        match self.dtype() {
+            DType::U8 => self.unique_::<u8>(sorted),
+            DType::U32 => self.unique_::<u32>(sorted),
+            DType::I64 => self.unique_::<i64>(sorted),
+            DType::BF16 => self.unique_::<half::bf16>(sorted),
+            DType::F16 => self.unique_::<half::f16>(sorted),
+            DType::F32 => self.unique_::<f32>(sorted),
+            DType::F64 => self.unique_::<f64>(sorted),
+            dtype => Err(crate::Error::UnsupportedDTypeForOp(dtype, "unique").bt()),
+        }
+    }
+
+    /// Returns the unique elements of the tensor as a 1d tensor, floats are compared using exact
+    /// equality. The values are in ascending order if `sorted` is true, and in order of first
+    /// occurrence otherwise.
+    ///
+    /// The computation happens on the host as the output size depends on the data.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[3u32, 1, 3, 2, 1], &Device::Cpu)?;
+    /// assert_eq!(t.unique(true)?.to_vec1::<u32>()?, &[1, 2, 3]);
+    /// assert_eq!(t.unique(false)?.to_vec1::<u32>()?, &[3, 1, 2]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn unique(&self, sorted: bool) -> Result<Tensor> {
+        let (values, _, _) = self.unique_all(sorted)?;
+        Ok(values)
+    }
+
+    /// Returns the sorted unique elements of the tensor, optionally together with the inverse
+    /// indices and the number of occurrences of each unique value.
+    ///
+    /// The inverse indices have the same shape as `self` and contain the position of each element
+    /// in the unique values, the counts have one element per unique value. Both use the `u32`
+    /// dtype.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[3u32, 1, 3, 2, 1], &Device::Cpu)?;
+    /// let (values, inverse, counts) = t.unique_with(true, true)?;
+    /// assert_eq!(values.to_vec1::<u32>()?, &[1, 2, 3]);
+    /// assert_eq!(inverse.unwrap().to_vec1::<u32>()?, &[2, 0, 2, 1, 0]);
+    /// assert_eq!(counts.unwrap().to_vec1::<u32>()?, &[2, 1, 2]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn unique_with(
+        &self,
+        return_inverse: bool,
+        return_counts: bool,
+    ) -> Result<(Tensor, Option<Tensor>, Option<Tensor>)> {
+        let (values, inverse, counts) = self.unique_all(true)?;
+        let inverse = if return_inverse {
+            Some(Tensor::from_vec(inverse, self.shape(), self.device())?)
+        } else {
+            None
+        };
+        let counts = if return_counts {
+            let n = counts.len();
+            Some(Tensor::from_vec(counts, n, self.device())?)
+        } else {
+            None
+        };
+        Ok((values, inverse, counts))
+    }
+
+    fn median_<D: crate::shape::Dim>(&self, dim: D, keepdim: bool) -> Result<(Tensor, Tensor)> {
+        let dim = dim.to_index(self.shape(), "median")?;
+        let n = self.dim(dim)?;
+        if n == 0 {
+            crate::bail!("median: cannot compute the median of an empty dim {dim}")
+        }
+        let (sorted, indices) = self.sort(dim, false)?;
+        let values = sorted.narrow(dim, (n - 1) / 2, 1)?;
+        let indices = indices.narrow(dim, (n - 1) / 2, 1)?;
+        if keepdim {
+            Ok((values, indices))
+        } else {
+            Ok((values.squeeze(dim)?, indices.squeeze(dim)?))
+        }
+    }
+
+    /// Returns the median of the values along dimension `dim` together with its index, the
+    /// indices use the `u32` dtype. For an even number of elements this is the lower of the two
+    /// middle values. The reduced dimension is kept with a size of 1.
+    pub fn median_keepdim<D: crate::shape::Dim>(&self, dim: D) -> Result<(Tensor, Tensor)> {
+        self.median_(dim, true)
+    }
+
+    /// Similar to `median_keepdim` but the target dimension is squeezed.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3f32, 1., 2.], [4., 1., 3.]], &Device::Cpu)?;
+    /// let (values, indices) = t.median(1)?;
+    /// assert_eq!(values.to_vec1::<f32>()?, &[2., 3.]);
+    /// assert_eq!(indices.to_vec1::<u32>()?, &[2, 2]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn median<D: crate::shape::Dim>(&self, dim: D) -> Result<(Tensor, Tensor)> {
+        self.median_(dim, false)
+    }
+
+    /// Returns the `q`-th quantile of the values along dimension `dim`, `q` has to be between 0
+    /// and 1. When the quantile falls between two values, the result is linearly interpolated
+    /// between them. The target dimension is squeezed, this requires a float dtype.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[4f32, 1., 3., 2.]], &Device::Cpu)?;
+    /// assert_eq!(t.quantile(0.5, 1)?.to_vec1::<f32>()?, &[2.5]);
+    /// assert_eq!(t.quantile(1., 1)?.to_vec1::<f32>()?, &[4.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn quantile<D: crate::shape::Dim>(&self, q: f64, dim: D) -> Result<Tensor> {
+        let dim = dim.to_index(self.shape(), "quantile")?;
+        if !self.dtype().is_float() {
+            Err(crate::Error::UnsupportedDTypeForOp(self.dtype(), "quantile").bt())?
+        }
+        if !(0.0..=1.0).contains(&q) {
+            crate::bail!("quantile: q has to be between 0 and 1, got {q}")
+        }
+        let n = self.dim(dim)?;
+        if n == 0 {
+            crate::bail!("quantile: cannot compute a quantile of an empty dim {dim}")
+        }
+        let (sorted, _) = self.sort(dim, false)?;
+        let pos = q * (n - 1) as f64;
+        let lo = pos.floor() as usize;
+        let hi = usize::min(lo + 1, n - 1);
+        let frac = pos - lo as f64;
+        let lo = sorted.narrow(dim, lo, 1)?.squeeze(dim)?;
+        let hi = sorted.narrow(dim, hi, 1)?.squeeze(dim)?;
+        &lo + ((hi - &lo)? * frac)?
+    }
+
+    fn mode_<T: crate::WithDType>(&self) -> Result<(Tensor, Tensor)> {
+        // `self` is contiguous and the target dimension is the last one.
+        let n = self.dim(crate::D::Minus1)?;
+        let vs = self.flatten_all()?.to_vec1::<T>()?;
+        let mut values = Vec::with_capacity(vs.len() / n);
+        let mut indices = Vec::with_capacity(vs.len() / n);
+        for row in vs.chunks(n) {
+            let mut order: Vec<usize> = (0..n).collect();
+            order.sort_by(|&i, &j| {
+                row[i]
+                    .partial_cmp(&row[j])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            // Track the longest run of equal values, the stable sort puts the first occurrence
+            // at the start of each run and ties between runs go to the smallest value.
+            let (mut best, mut best_len, mut start) = (0, 0, 0);
+            for pos in 1..=n {
+                if pos == n || row[order[pos]] != row[order[start]] {
+                    if pos - start > best_len {
+                        (best, best_len) = (order[start], pos - start)
+                    }
+                    start = pos
+                }
+            }
+            values.push(row[best]);
+            indices.push(best as u32);
+        }
+        let mut dims = self.dims().to_vec();
+        dims.pop();
+        let values = Tensor::from_vec(values, dims.as_slice(), self.device())?;
+        let indices = Tensor::from_vec(indices, dims, self.device())?;
+        Ok((values, indices))
+    }
+
+    /// Returns the most frequent value along dimension `dim` together with the index of its first
+    /// occurrence, the indices use the `u32` dtype. When several values are equally frequent,
+    /// the smallest one is returned. The target dimension is squeezed.
+    ///
+    /// The computation happens on the host.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3u32, 1, 3, 1, 2], [5, 4, 4, 5, 4]], &Device::Cpu)?;
+    /// let (values, indices) = t.mode(1)?;
+    /// assert_eq!(values.to_vec1::<u32>()?, &[1, 4]);
+    /// assert_eq!(indices.to_vec1::<u32>()?, &[1, 1]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn mode<D: crate::shape::Dim>(&self, dim: D) -> Result<(Tensor, Tensor)> {
+        use crate::DType;
+        let dim = dim.to_index(self.shape(), "mode")?;
+        if self.dim(dim)? == 0 {
+            crate::bail!("mode: cannot compute the mode of an empty dim {dim}")
+        }
+        // Move the target dimension last while keeping the other dimensions in order.
+        let mut perm: Vec<usize> = (0..self.rank()).filter(|&d| d != dim).collect();
+        perm.push(dim);
+        let t = self.permute(perm)?.contiguous()?;
+        match self.dtype() {
+            DType::U8 => t.mode_::<u8>(),
+            DType::U32 => t.mode_::<u32>(),
+            DType::I64 => t.mode_::<i64>(),
+            DType::BF16 => t.mode_::<half::bf16>(),
+            DType::F16 => t.mode_::<half::f16>(),
+            DType::F32 => t.mode_::<f32>(),
+            DType::F64 => t.mode_::<f64>(),
+            dtype => Err(crate::Error::UnsupportedDTypeForOp(dtype, "mode").bt()),
+        }
+    }
 }
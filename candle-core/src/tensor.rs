@@ -3,12 +3,12 @@
 use crate::backend::{BackendDevice, BackendStorage};
 use crate::op::{BackpropOp, BinaryOp, CmpOp, Op, ReduceOp, UnaryOp};
 use crate::scalar::TensorOrScalar;
-use crate::shape::{Dim, Dims};
+use crate::shape::{Dim, Dims, D};
 use crate::{bail, storage::Storage, DType, Device, Error, Layout, Result, Shape};
 use std::sync::{Arc, RwLock};
 
-/// Unique identifier for tensors.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+/// Unique identifier for tensors, ids are allocated in increasing order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TensorId(usize);
 
 impl TensorId {
@@ -40,6 +40,8 @@ pub struct Tensor_ {
     is_variable: bool,
     dtype: DType,
     device: Device,
+    // The name is only used to make error messages more helpful.
+    name: Option<Arc<str>>,
 }
 
 impl AsRef<Tensor> for Tensor {
@@ -86,7 +88,7 @@ macro_rules! unary_op {
                 .storage()
                 .unary_impl::<crate::op::$op_name>(self.layout())?;
             let op = BackpropOp::new1(self, |s| Op::Unary(s, UnaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            from_storage(storage, shape.clone(), op, false)
         }
     };
 }
@@ -104,7 +106,7 @@ macro_rules! binary_op {
                 rhs.layout(),
             )?;
             let op = BackpropOp::new2(self, rhs, |t1, t2| Op::Binary(t1, t2, BinaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            from_storage(storage, shape.clone(), op, false)
         }
     };
 }
@@ -129,7 +131,7 @@ macro_rules! binary_op_scalar {
                 rhs.layout(),
             )?;
             let op = BackpropOp::new2(self, &rhs, |t1, t2| Op::Binary(t1, t2, BinaryOp::$op_name));
-            Ok(from_storage(storage, shape.clone(), op, false))
+            from_storage(storage, shape.clone(), op, false)
         }
     };
 }
@@ -140,7 +142,8 @@ macro_rules! broadcast_binary_op {
             let lhs = self;
             let shape = lhs
                 .shape()
-                .broadcast_shape_binary_op(rhs.shape(), stringify!($fn_name))?;
+                .broadcast_shape_binary_op(rhs.shape(), stringify!($fn_name))
+                .map_err(|e| e.with_tensor_names(lhs.name(), rhs.name()))?;
             let l_broadcast = shape != *lhs.shape();
             let r_broadcast = shape != *rhs.shape();
             match (l_broadcast, r_broadcast) {
@@ -155,13 +158,33 @@ macro_rules! broadcast_binary_op {
     };
 }
 
+/// The values used when padding a tensor with [`Tensor::pad`], this follows the modes of
+/// PyTorch's `F.pad`. The examples show the padding of `[1, 2, 3, 4]` with two elements on each
+/// side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadMode {
+    /// Pads with a constant value, `[v, v, 1, 2, 3, 4, v, v]`.
+    Constant(f64),
+    /// Reflects the values without repeating the border, `[3, 2, 1, 2, 3, 4, 3, 2]`. The padding
+    /// has to be smaller than the dimension size.
+    Reflect,
+    /// Repeats the border values, `[1, 1, 1, 2, 3, 4, 4, 4]`.
+    Replicate,
+    /// Wraps around the values, `[3, 4, 1, 2, 3, 4, 1, 2]`. The padding cannot exceed the
+    /// dimension size.
+    Circular,
+}
+
 /// Creates a fresh tensor structure based on a storage and a shape, this uses contiguous strides.
+///
+/// When nan checking is enabled, see `set_check_nan`, the tensors resulting from ops that
+/// are tracked in the autograd graph are checked for nan and inf values.
 pub(crate) fn from_storage<S: Into<Shape>>(
     storage: Storage,
     shape: S,
     op: BackpropOp,
     is_variable: bool,
-) -> Tensor {
+) -> Result<Tensor> {
     let dtype = storage.dtype();
     let device = storage.device();
     let tensor_ = Tensor_ {
@@ -172,8 +195,25 @@ pub(crate) fn from_storage<S: Into<Shape>>(
         is_variable,
         dtype,
         device,
+        name: None,
     };
-    Tensor(Arc::new(tensor_))
+    let tensor = Tensor(Arc::new(tensor_));
+    if crate::utils::check_nan() {
+        if let Some(op) = tensor.op() {
+            if tensor.has_nan()? || tensor.has_inf()? {
+                let shapes = op
+                    .args()
+                    .iter()
+                    .map(|t| t.dims().to_vec())
+                    .collect::<Vec<_>>();
+                bail!(
+                    "nan or inf in the output of {}, input shapes {shapes:?}",
+                    op.name()
+                )
+            }
+        }
+    }
+    Ok(tensor)
 }
 
 impl Tensor {
@@ -186,7 +226,7 @@ impl Tensor {
         let none = BackpropOp::none();
         let shape = shape.into();
         let storage = device.ones(&shape, dtype)?;
-        Ok(from_storage(storage, shape, none, is_variable))
+        from_storage(storage, shape, none, is_variable)
     }
 
     /// Creates a new tensor filled with ones.
@@ -226,7 +266,7 @@ impl Tensor {
         let none = BackpropOp::none();
         let shape = shape.into();
         let storage = device.zeros(&shape, dtype)?;
-        Ok(from_storage(storage, shape, none, is_variable))
+        from_storage(storage, shape, none, is_variable)
     }
 
     /// Creates a new tensor filled with zeros.
@@ -266,7 +306,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_uniform(lo, up, &s)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        from_storage(storage, s, none, is_variable)
     }
 
     pub(crate) fn rand_f64_impl<S: Into<Shape>>(
@@ -280,7 +320,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_uniform_f64(lo, up, &s, dtype)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        from_storage(storage, s, none, is_variable)
     }
 
     /// Creates a new tensor initialized with values sampled uniformly between `lo` and `up`.
@@ -307,7 +347,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_normal(mean, std, &s)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        from_storage(storage, s, none, is_variable)
     }
 
     pub(crate) fn randn_f64_impl<S: Into<Shape>>(
@@ -321,7 +361,7 @@ impl Tensor {
         let s = s.into();
         let storage = device.rand_normal_f64(mean, std, &s, dtype)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, s, none, is_variable))
+        from_storage(storage, s, none, is_variable)
     }
 
     pub fn randn_like(&self, mean: f64, stdev: f64) -> Result<Self> {
@@ -359,7 +399,7 @@ impl Tensor {
         }
         let storage = device.storage(array)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, shape, none, is_variable))
+        from_storage(storage, shape, none, is_variable)
     }
 
     /// Creates a new tensor on the specified device using the content and shape of the input.
@@ -435,7 +475,7 @@ impl Tensor {
         }
         let storage = device.storage_owned(data)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, shape, none, is_variable))
+        from_storage(storage, shape, none, is_variable)
     }
 
     /// Creates a new tensor initialized with values from the input vector. The number of elements
@@ -464,21 +504,22 @@ impl Tensor {
         }
         let storage = device.storage_from_slice(array)?;
         let none = BackpropOp::none();
-        Ok(from_storage(storage, shape, none, false))
+        from_storage(storage, shape, none, false)
     }
 
     pub(crate) fn same_shape_binary_op(&self, rhs: &Self, op: &'static str) -> Result<&Shape> {
-        let lhs = self.shape();
-        let rhs = rhs.shape();
-        if lhs != rhs {
+        let lhs_shape = self.shape();
+        let rhs_shape = rhs.shape();
+        if lhs_shape != rhs_shape {
             Err(Error::ShapeMismatchBinaryOp {
-                lhs: lhs.clone(),
-                rhs: rhs.clone(),
+                lhs: lhs_shape.clone(),
+                rhs: rhs_shape.clone(),
                 op,
             }
-            .bt())
+            .bt()
+            .with_tensor_names(self.name(), rhs.name()))
         } else {
-            Ok(lhs)
+            Ok(lhs_shape)
         }
     }
 
@@ -538,17 +579,33 @@ impl Tensor {
         (self * mult)?.round()? * (1f64 / mult)
     }
 
-    /// Retrieves the single scalar value hold in the tensor. If the tensor contains multiple
-    /// dimensions, an error is returned instead.
+    /// Retrieves the single scalar value hold in the tensor. The tensor can have any shape as
+    /// long as it contains exactly one element, e.g. `()`, `(1,)` or `(1, 1)`. An error is
+    /// returned if this is not the case or if `S` does not match the tensor dtype.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3f32]], &Device::Cpu)?;
+    /// assert_eq!(t.to_scalar::<f32>()?, 3.);
+    /// assert!(t.to_scalar::<f64>().is_err());
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
     pub fn to_scalar<S: crate::WithDType>(&self) -> Result<S> {
-        if self.rank() != 0 {
-            Err(Error::UnexpectedNumberOfDims {
-                expected: 0,
-                got: self.rank(),
+        if self.elem_count() != 1 {
+            Err(Error::NotASingleElement {
+                op: "to_scalar",
                 shape: self.shape().clone(),
             }
             .bt())?
         }
+        if self.dtype() != S::DTYPE {
+            Err(Error::UnexpectedDType {
+                msg: "to_scalar: unexpected dtype, use to_f64 to convert",
+                expected: S::DTYPE,
+                got: self.dtype(),
+            }
+            .bt())?
+        }
         let from_cpu_storage = |cpu_storage: &crate::CpuStorage| {
             let data = S::cpu_storage_as_slice(cpu_storage)?;
             Ok::<_, Error>(data[self.layout().start_offset()])
@@ -557,6 +614,7 @@ impl Tensor {
             Storage::Cpu(cpu_storage) => from_cpu_storage(cpu_storage),
             Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
             Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Wgpu(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
         }
     }
 
@@ -565,6 +623,31 @@ impl Tensor {
         self.to_scalar::<S>()
     }
 
+    /// Retrieves the single value hold in the tensor converted to `f64` whatever the tensor
+    /// dtype. As for `to_scalar`, the tensor must contain exactly one element.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let loss = Tensor::new(&[0.5f32], &Device::Cpu)?;
+    /// assert_eq!(loss.to_f64()?, 0.5);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn to_f64(&self) -> Result<f64> {
+        use crate::WithDType;
+        match self.dtype() {
+            DType::U8 => Ok(self.to_scalar::<u8>()?.to_f64()),
+            DType::U32 => Ok(self.to_scalar::<u32>()?.to_f64()),
+            DType::I64 => Ok(self.to_scalar::<i64>()?.to_f64()),
+            DType::BF16 => Ok(self.to_scalar::<half::bf16>()?.to_f64()),
+            DType::F16 => Ok(self.to_scalar::<half::f16>()?.to_f64()),
+            DType::F32 => Ok(self.to_scalar::<f32>()?.to_f64()),
+            DType::F64 => self.to_scalar::<f64>(),
+            dtype @ (DType::C64 | DType::C128) => {
+                Err(Error::UnsupportedDTypeForOp(dtype, "to_f64").bt())
+            }
+        }
+    }
+
     /// Repeat this tensor along the specified dimensions.
     pub fn repeat<S: Into<Shape>>(&self, shape: S) -> Result<Tensor> {
         // Similar to PyTorch, we extend the number of dimensions of self if needed.
@@ -584,6 +667,77 @@ impl Tensor {
         Ok(inp)
     }
 
+    /// Repeat each element of this tensor along dimension `dim`, the copies of an element being
+    /// contiguous in the result. This is `repeat_interleave` in PyTorch.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[1u32, 2], [3, 4]], &Device::Cpu)?;
+    /// let t = t.repeat_interleave(2, 0)?;
+    /// assert_eq!(t.to_vec2::<u32>()?, &[[1, 2], [1, 2], [3, 4], [3, 4]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn repeat_interleave<D: Dim>(&self, repeats: usize, dim: D) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "repeat_interleave")?;
+        if repeats == 1 {
+            return Ok(self.clone());
+        }
+        let mut dims = self.dims().to_vec();
+        dims[dim] *= repeats;
+        // Using cat rather than a broadcast avoids going through a strided copy.
+        let t = self.unsqueeze(dim + 1)?;
+        Tensor::cat(&vec![&t; repeats], dim + 1)?.reshape(dims)
+    }
+
+    /// Repeat each element of this tensor along dimension `dim` a variable number of times.
+    ///
+    /// `repeats` is a 1d tensor of integers with one count per index of `dim`, or a single
+    /// element in which case the same count is used for all the indexes. The size of `dim` in
+    /// the result is the sum of the counts.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[1u32, 2, 3], &Device::Cpu)?;
+    /// let repeats = Tensor::new(&[2u32, 0, 1], &Device::Cpu)?;
+    /// let t = t.repeat_interleave_tensor(&repeats, 0)?;
+    /// assert_eq!(t.to_vec1::<u32>()?, &[1, 1, 3]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn repeat_interleave_tensor<D: Dim>(&self, repeats: &Self, dim: D) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "repeat_interleave")?;
+        let dim_size = self.dim(dim)?;
+        if !repeats.dtype().is_int() {
+            Err(Error::UnsupportedDTypeForOp(
+                repeats.dtype(),
+                "repeat_interleave",
+            ))?
+        }
+        let repeats = repeats
+            .flatten_all()?
+            .to_dtype(DType::I64)?
+            .to_vec1::<i64>()?;
+        let repeats = if repeats.len() == 1 {
+            vec![repeats[0]; dim_size]
+        } else if repeats.len() == dim_size {
+            repeats
+        } else {
+            bail!(
+                "repeat_interleave: expected {dim_size} repeats for dim {dim}, got {}",
+                repeats.len()
+            )
+        };
+        let mut ids = Vec::with_capacity(repeats.iter().map(|&r| r.max(0) as usize).sum());
+        for (i, &r) in repeats.iter().enumerate() {
+            if r < 0 {
+                bail!("repeat_interleave: repeats have to be non-negative, got {r}")
+            }
+            ids.extend(std::iter::repeat_n(i as u32, r as usize))
+        }
+        let ids_len = ids.len();
+        let ids = Tensor::from_vec(ids, ids_len, self.device())?;
+        self.contiguous()?.index_select(&ids, dim)
+    }
+
     /// Creates grids of coordinates specified by the 1D inputs.
     ///
     /// # Arguments
@@ -668,7 +822,7 @@ impl Tensor {
         }
         let storage = self.storage().affine(self.layout(), mul, add)?;
         let op = BackpropOp::new1(self, |arg| Op::Affine { arg, mul, add });
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     /// Applies the Exponential Linear Unit (ELU) function on each element of the input tensor.
@@ -678,7 +832,7 @@ impl Tensor {
         }
         let storage = self.storage().elu(self.layout(), alpha)?;
         let op = BackpropOp::new1(self, |t| Op::Elu(t, alpha));
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     /// Raise the tensor to some float exponent `e`.
@@ -688,7 +842,7 @@ impl Tensor {
         }
         let storage = self.storage().powf(self.layout(), e)?;
         let op = BackpropOp::new1(self, |t| Op::Powf(t, e));
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     pub(crate) fn check_dim(&self, dim: usize, op: &'static str) -> Result<()> {
@@ -730,6 +884,38 @@ impl Tensor {
         }
     }
 
+    /// Split a tensor into consecutive parts along `dim`, part `i` having size `sizes[i]` on this
+    /// dimension. The sizes must sum to the size of `dim`, the returned tensors are views on the
+    /// input tensor.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let qkv = Tensor::arange(0u32, 8, &Device::Cpu)?.reshape((1, 8))?;
+    /// let parts = qkv.split(&[4, 2, 2], 1)?;
+    /// assert_eq!(parts[0].to_vec2::<u32>()?, &[[0, 1, 2, 3]]);
+    /// assert_eq!(parts[1].to_vec2::<u32>()?, &[[4, 5]]);
+    /// assert_eq!(parts[2].to_vec2::<u32>()?, &[[6, 7]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn split<D: Dim>(&self, sizes: &[usize], dim: D) -> Result<Vec<Self>> {
+        let dim = dim.to_index(self.shape(), "split")?;
+        let size = self.dim(dim)?;
+        let total: usize = sizes.iter().sum();
+        if total != size {
+            bail!(
+                "split sizes {sizes:?} sum to {total} but dim {dim} of {:?} has size {size}",
+                self.shape()
+            )
+        }
+        let mut start = 0;
+        let mut tensors = Vec::with_capacity(sizes.len());
+        for &len in sizes.iter() {
+            tensors.push(self.narrow(dim, start, len)?);
+            start += len
+        }
+        Ok(tensors)
+    }
+
     /// Returns a new tensor that is a narrowed version of the input, the dimension `dim`
     /// ranges from `start` to `start + len`.
     pub fn narrow<D: Dim>(&self, dim: D, start: usize, len: usize) -> Result<Self> {
@@ -766,6 +952,7 @@ impl Tensor {
                 is_variable: false,
                 dtype: self.dtype,
                 device: self.device.clone(),
+                name: self.name.clone(),
             };
             Ok(Tensor(Arc::new(tensor_)))
         }
@@ -804,7 +991,7 @@ impl Tensor {
             }
             ReduceOp::ArgMin | ReduceOp::ArgMax => BackpropOp::none(),
         };
-        let res = from_storage(storage, dims, op, false);
+        let res = from_storage(storage, dims, op, false)?;
         if keepdim {
             Ok(res)
         } else {
@@ -822,7 +1009,7 @@ impl Tensor {
             dims[sum_dim] = 1
         }
         let op = BackpropOp::new1(self, |a| Op::Reduce(a, ReduceOp::Sum, dims.to_vec()));
-        let sum = from_storage(storage, dims, op, false);
+        let sum = from_storage(storage, dims, op, false)?;
         if keepdim {
             Ok(sum)
         } else {
@@ -843,13 +1030,16 @@ impl Tensor {
     /// assert_eq!(tensor.to_vec2::<f32>()?, &[[2., 3.], [4., 5.], [0., 1.]]);
     /// # Ok::<(), candle_core::Error>(())
     /// ```
-    pub fn roll<D>(&self, shift: i32, dim: D) -> Result<Self>
+    pub fn roll<D>(&self, shift: i64, dim: D) -> Result<Self>
     where
         D: Dim + Clone,
     {
         let dim = dim.to_index(self.shape(), "roll")?;
         let dim_size = self.dim(dim)?;
-        let shift = shift.rem_euclid(dim_size as i32) as usize;
+        if dim_size == 0 {
+            return Ok(self.clone());
+        }
+        let shift = shift.rem_euclid(dim_size as i64) as usize;
         if shift == 0 {
             Ok(self.clone())
         } else {
@@ -859,6 +1049,55 @@ impl Tensor {
         }
     }
 
+    /// Roll the tensor input along multiple dimensions, `shifts[i]` being applied to `dims[i]`.
+    ///
+    /// ```rust
+    /// # use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// let tensor = tensor.roll_dims(&[1, -1], &[0, 1])?;
+    /// assert_eq!(tensor.to_vec2::<f32>()?, &[[4., 5., 3.], [1., 2., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn roll_dims(&self, shifts: &[i64], dims: &[usize]) -> Result<Self> {
+        if shifts.len() != dims.len() {
+            bail!(
+                "roll: shifts and dims must have the same length, got {} and {}",
+                shifts.len(),
+                dims.len()
+            )
+        }
+        let mut t = self.clone();
+        for (&shift, &dim) in shifts.iter().zip(dims.iter()) {
+            t = t.roll(shift, dim)?
+        }
+        Ok(t)
+    }
+
+    /// Reverse the order of the elements along the given dimensions.
+    ///
+    /// ```rust
+    /// # use candle_core::{Tensor, Device};
+    /// let tensor = Tensor::new(&[[0f32, 1., 2.], [3., 4., 5.]], &Device::Cpu)?;
+    /// assert_eq!(tensor.flip(&[1])?.to_vec2::<f32>()?, &[[2., 1., 0.], [5., 4., 3.]]);
+    /// assert_eq!(tensor.flip(&[0, 1])?.to_vec2::<f32>()?, &[[5., 4., 3.], [2., 1., 0.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn flip(&self, dims: &[usize]) -> Result<Self> {
+        let dims = dims.to_indexes(self.shape(), "flip")?;
+        // index_select requires a contiguous input, its output is always contiguous.
+        let mut t = self.contiguous()?;
+        for dim in dims {
+            let dim_size = t.dim(dim)?;
+            if dim_size <= 1 {
+                continue;
+            }
+            let ids: Vec<u32> = (0..dim_size as u32).rev().collect();
+            let ids = Tensor::from_vec(ids, dim_size, t.device())?;
+            t = t.index_select(&ids, dim)?
+        }
+        Ok(t)
+    }
+
     /// Returns the sum of all elements in the input tensor. The sum is performed over all the
     /// input dimensions.
     ///
@@ -992,7 +1231,7 @@ impl Tensor {
             .storage()
             .cmp(op, &rhs.storage(), self.layout(), rhs.layout())?;
         let op = BackpropOp::new1(self, |a| Op::Cmp(a, op));
-        Ok(from_storage(storage, shape.dims(), op, false))
+        from_storage(storage, shape.dims(), op, false)
     }
 
     /// Element-wise equality.
@@ -1034,6 +1273,38 @@ impl Tensor {
         self.maximum(min)?.minimum(max)
     }
 
+    /// Clamp the tensor values to be between the element-wise bounds `min` and `max`, the three
+    /// tensors are broadcasted together.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[-2f32, 0.5, 3.], [1., -1., 0.]], &Device::Cpu)?;
+    /// let min = Tensor::new(&[-1f32, 0., 1.], &Device::Cpu)?;
+    /// let max = Tensor::new(&[[1f32], [0.5]], &Device::Cpu)?;
+    /// let t = t.clamp_tensor(&min, &max)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[-1., 0.5, 1.], [0.5, 0., 0.5]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn clamp_tensor(&self, min: &Self, max: &Self) -> Result<Self> {
+        self.broadcast_maximum(min)?.broadcast_minimum(max)
+    }
+
+    /// Linear interpolation between `self` and `end`, computing `self + weight * (end - self)`.
+    /// The three tensors are broadcasted together so `weight` can be a scalar tensor.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let start = Tensor::new(&[0f32, 1., 2.], &Device::Cpu)?;
+    /// let end = Tensor::new(&[4f32, 3., 2.], &Device::Cpu)?;
+    /// let weight = Tensor::new(0.25f32, &Device::Cpu)?;
+    /// let t = start.lerp(&end, &weight)?;
+    /// assert_eq!(t.to_vec1::<f32>()?, &[1., 1.5, 2.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn lerp(&self, end: &Self, weight: &Self) -> Result<Self> {
+        self.broadcast_add(&end.broadcast_sub(self)?.broadcast_mul(weight)?)
+    }
+
     /// Interpolate the input tensor to the `target_size` size, taking the value of the nearest element.
     ///
     /// The input tensor should have three dimensions, `(batch, channels, l)`, the returned
@@ -1044,7 +1315,7 @@ impl Tensor {
         let storage = self
             .storage()
             .upsample_nearest1d(self.layout(), target_size)?;
-        Ok(from_storage(storage, (n, c, target_size), op, false))
+        from_storage(storage, (n, c, target_size), op, false)
     }
 
     /// Alias for `interpolate1d`.
@@ -1067,7 +1338,7 @@ impl Tensor {
         let storage = self
             .storage()
             .upsample_nearest2d(self.layout(), target_h, target_w)?;
-        Ok(from_storage(storage, (n, c, target_h, target_w), op, false))
+        from_storage(storage, (n, c, target_h, target_w), op, false)
     }
 
     /// Alias for `interpolate2d`.
@@ -1075,6 +1346,75 @@ impl Tensor {
         self.interpolate2d(target_h, target_w)
     }
 
+    // Linear interpolation along `dim` so that this dimension has size `target_size`.
+    fn interpolate_linear_dim(
+        &self,
+        dim: usize,
+        target_size: usize,
+        align_corners: bool,
+    ) -> Result<Self> {
+        let size = self.dim(dim)?;
+        if size == target_size && !align_corners {
+            return Ok(self.clone());
+        }
+        if size == 0 || target_size == 0 {
+            bail!("cannot interpolate from size {size} to {target_size}")
+        }
+        let mut ids0 = Vec::with_capacity(target_size);
+        let mut ids1 = Vec::with_capacity(target_size);
+        let mut weights = Vec::with_capacity(target_size);
+        for i in 0..target_size {
+            // https://pytorch.org/docs/stable/generated/torch.nn.Upsample.html
+            let src = if align_corners {
+                if target_size > 1 {
+                    i as f64 * (size - 1) as f64 / (target_size - 1) as f64
+                } else {
+                    0.
+                }
+            } else {
+                ((i as f64 + 0.5) * size as f64 / target_size as f64 - 0.5).max(0.)
+            };
+            let i0 = usize::min(src.floor() as usize, size - 1);
+            ids0.push(i0 as u32);
+            ids1.push(usize::min(i0 + 1, size - 1) as u32);
+            weights.push((src - i0 as f64) as f32);
+        }
+        let mut dims = vec![1; self.rank()];
+        dims[dim] = target_size;
+        let device = self.device();
+        let weights = Tensor::from_vec(weights, dims, device)?.to_dtype(self.dtype())?;
+        let xs = self.contiguous()?;
+        let x0 = xs.index_select(&Tensor::new(ids0, device)?, dim)?;
+        let x1 = xs.index_select(&Tensor::new(ids1, device)?, dim)?;
+        x0.lerp(&x1, &weights)
+    }
+
+    /// Interpolate the input tensor to the `(target_h, target_w)` size using bilinear
+    /// interpolation.
+    ///
+    /// The input tensor should have four dimensions, `(batch, channels, h, w)`, the returned
+    /// tensor also has four dimensions, `(batch, channels, target_h, target_w)`. When
+    /// `align_corners` is true, the corner pixels of the input and output are aligned, otherwise
+    /// the pixels are considered as squares and their centers are aligned as in PyTorch.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[[[1f32, 2.], [3., 4.]]]], &Device::Cpu)?;
+    /// let t = t.upsample_bilinear2d(2, 4, false)?.squeeze(0)?.squeeze(0)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[1., 1.25, 1.75, 2.], [3., 3.25, 3.75, 4.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn upsample_bilinear2d(
+        &self,
+        target_h: usize,
+        target_w: usize,
+        align_corners: bool,
+    ) -> Result<Self> {
+        let (_n, _c, _h, _w) = self.dims4()?;
+        self.interpolate_linear_dim(2, target_h, align_corners)?
+            .interpolate_linear_dim(3, target_w, align_corners)
+    }
+
     /// 2D average pooling over an input tensor with multiple channels.
     ///
     /// The input tensor should have four dimensions, `(batch, channels, h, w)`, the returned
@@ -1110,7 +1450,7 @@ impl Tensor {
         let storage = self
             .storage()
             .avg_pool2d(self.layout(), kernel_size, stride)?;
-        Ok(from_storage(storage, (n, c, h_out, w_out), op, false))
+        from_storage(storage, (n, c, h_out, w_out), op, false)
     }
 
     /// 2D max pooling over an input tensor with multiple channels.
@@ -1148,7 +1488,224 @@ impl Tensor {
         let storage = self
             .storage()
             .max_pool2d(self.layout(), kernel_size, stride)?;
-        Ok(from_storage(storage, (n, c, h_out, w_out), op, false))
+        from_storage(storage, (n, c, h_out, w_out), op, false)
+    }
+
+    // Returns one tensor of shape `(batch, channels, h_out, w_out)` per kernel position, holding
+    // the input values that this kernel position covers for each output location.
+    fn pool2d_windows(
+        &self,
+        kernel_size: (usize, usize),
+        stride: (usize, usize),
+        dilation: (usize, usize),
+    ) -> Result<Vec<Self>> {
+        let (_n, _c, h, w) = self.dims4()?;
+        let (k_h, k_w) = kernel_size;
+        let (s_h, s_w) = stride;
+        let (d_h, d_w) = dilation;
+        if k_h == 0 || k_w == 0 || s_h == 0 || s_w == 0 || d_h == 0 || d_w == 0 {
+            bail!("kernel-size {kernel_size:?}, stride {stride:?} and dilation {dilation:?} should be positive")
+        }
+        let eff_h = d_h * (k_h - 1) + 1;
+        let eff_w = d_w * (k_w - 1) + 1;
+        if h < eff_h || w < eff_w {
+            bail!("dilated kernel-size ({eff_h}, {eff_w}) is larger than the padded input size {h},{w}")
+        }
+        let h_out = (h - eff_h) / s_h + 1;
+        let w_out = (w - eff_w) / s_w + 1;
+        let xs = self.contiguous()?;
+        let mut windows = Vec::with_capacity(k_h * k_w);
+        for m in 0..k_h {
+            let start = (m * d_h) as u32;
+            let ids =
+                Tensor::arange_step(start, start + (h_out * s_h) as u32, s_h as u32, xs.device())?;
+            let rows = xs.index_select(&ids, 2)?;
+            for n in 0..k_w {
+                let start = (n * d_w) as u32;
+                let ids = Tensor::arange_step(
+                    start,
+                    start + (w_out * s_w) as u32,
+                    s_w as u32,
+                    xs.device(),
+                )?;
+                windows.push(rows.index_select(&ids, 3)?)
+            }
+        }
+        Ok(windows)
+    }
+
+    fn check_pool_padding(kernel_size: (usize, usize), padding: (usize, usize)) -> Result<()> {
+        if padding.0 > kernel_size.0 / 2 || padding.1 > kernel_size.1 / 2 {
+            bail!("padding {padding:?} should be at most half of the kernel-size {kernel_size:?}")
+        }
+        Ok(())
+    }
+
+    /// Same as `avg_pool2d_with_stride` but also supports `padding` and `dilation`.
+    ///
+    /// The input is zero-padded by `padding` on both sides of the two last dimensions. When
+    /// `count_include_pad` is `false`, the padded positions are excluded from the number of
+    /// elements used to compute each average.
+    pub fn avg_pool2d_with_params<T: crate::ToUsize2>(
+        &self,
+        kernel_size: T,
+        stride: T,
+        padding: T,
+        dilation: T,
+        count_include_pad: bool,
+    ) -> Result<Self> {
+        let kernel_size = kernel_size.to_usize2();
+        let stride = stride.to_usize2();
+        let padding = padding.to_usize2();
+        let dilation = dilation.to_usize2();
+        if padding == (0, 0) && dilation == (1, 1) {
+            return self.avg_pool2d_with_stride(kernel_size, stride);
+        }
+        Self::check_pool_padding(kernel_size, padding)?;
+        let xs = self
+            .pad_with_zeros(2, padding.0, padding.0)?
+            .pad_with_zeros(3, padding.1, padding.1)?;
+        let windows = xs.pool2d_windows(kernel_size, stride, dilation)?;
+        let sum = Tensor::stack(&windows, 0)?.sum(0)?;
+        if count_include_pad || padding == (0, 0) {
+            sum / (kernel_size.0 * kernel_size.1) as f64
+        } else {
+            let (_n, _c, h, w) = self.dims4()?;
+            let counts = Tensor::ones((1, 1, h, w), self.dtype(), self.device())?
+                .pad_with_zeros(2, padding.0, padding.0)?
+                .pad_with_zeros(3, padding.1, padding.1)?
+                .pool2d_windows(kernel_size, stride, dilation)?;
+            let counts = Tensor::stack(&counts, 0)?.sum(0)?;
+            sum.broadcast_div(&counts)
+        }
+    }
+
+    /// Same as `max_pool2d_with_stride` but also supports `padding` and `dilation`.
+    ///
+    /// The input is padded with `-inf` by `padding` on both sides of the two last dimensions so
+    /// that the padded positions never contribute to the maximum.
+    pub fn max_pool2d_with_params<T: crate::ToUsize2>(
+        &self,
+        kernel_size: T,
+        stride: T,
+        padding: T,
+        dilation: T,
+    ) -> Result<Self> {
+        let kernel_size = kernel_size.to_usize2();
+        let stride = stride.to_usize2();
+        let padding = padding.to_usize2();
+        let dilation = dilation.to_usize2();
+        if padding == (0, 0) && dilation == (1, 1) {
+            return self.max_pool2d_with_stride(kernel_size, stride);
+        }
+        Self::check_pool_padding(kernel_size, padding)?;
+        let pad = |xs: &Self, dim: usize, p: usize| -> Result<Self> {
+            if p == 0 {
+                return Ok(xs.clone());
+            }
+            let mut dims = xs.dims().to_vec();
+            dims[dim] = p;
+            let neg_inf =
+                Tensor::full(f32::NEG_INFINITY, dims, xs.device())?.to_dtype(xs.dtype())?;
+            Tensor::cat(&[&neg_inf, xs, &neg_inf], dim)
+        };
+        let xs = pad(self, 2, padding.0)?;
+        let xs = pad(&xs, 3, padding.1)?;
+        let mut windows = xs
+            .pool2d_windows(kernel_size, stride, dilation)?
+            .into_iter();
+        let mut max = match windows.next() {
+            None => bail!("empty kernel in max_pool2d"),
+            Some(window) => window,
+        };
+        for window in windows {
+            max = max.maximum(&window)?
+        }
+        Ok(max)
+    }
+
+    /// 1D average pooling over an input tensor with multiple channels.
+    ///
+    /// The input tensor should have three dimensions, `(batch, channels, l)`, the returned tensor
+    /// has three dimensions `(batch, channels, l')`. The pooling uses a `stride` equal to the
+    /// kernel size.
+    pub fn avg_pool1d(&self, kernel_size: usize) -> Result<Self> {
+        self.avg_pool1d_with_params(kernel_size, kernel_size, 0, 1, true)
+    }
+
+    /// Same as `avg_pool1d` but with custom `stride`, `padding` and `dilation`, see
+    /// `avg_pool2d_with_params` for details.
+    pub fn avg_pool1d_with_params(
+        &self,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+        dilation: usize,
+        count_include_pad: bool,
+    ) -> Result<Self> {
+        let (_n, _c, _l) = self.dims3()?;
+        self.unsqueeze(2)?
+            .avg_pool2d_with_params(
+                (1, kernel_size),
+                (1, stride),
+                (0, padding),
+                (1, dilation),
+                count_include_pad,
+            )?
+            .squeeze(2)
+    }
+
+    /// 1D max pooling over an input tensor with multiple channels.
+    ///
+    /// The input tensor should have three dimensions, `(batch, channels, l)`, the returned tensor
+    /// has three dimensions `(batch, channels, l')`. The pooling uses a `stride` equal to the
+    /// kernel size.
+    pub fn max_pool1d(&self, kernel_size: usize) -> Result<Self> {
+        self.max_pool1d_with_params(kernel_size, kernel_size, 0, 1)
+    }
+
+    /// Same as `max_pool1d` but with custom `stride`, `padding` and `dilation`, see
+    /// `max_pool2d_with_params` for details.
+    pub fn max_pool1d_with_params(
+        &self,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+        dilation: usize,
+    ) -> Result<Self> {
+        let (_n, _c, _l) = self.dims3()?;
+        self.unsqueeze(2)?
+            .max_pool2d_with_params((1, kernel_size), (1, stride), (0, padding), (1, dilation))?
+            .squeeze(2)
+    }
+
+    /// 2D adaptive average pooling, the two last dimensions of the `(batch, channels, h, w)`
+    /// input are pooled so that the output has shape `(batch, channels, out_h, out_w)`.
+    ///
+    /// Output position `i` along a dimension of size `l` averages the input range
+    /// `floor(i * l / out)..ceil((i + 1) * l / out)`, so windows can overlap when `l` is not a
+    /// multiple of `out`.
+    pub fn adaptive_avg_pool2d<T: crate::ToUsize2>(&self, output_size: T) -> Result<Self> {
+        let (out_h, out_w) = output_size.to_usize2();
+        let (_n, _c, h, w) = self.dims4()?;
+        if out_h == 0 || out_w == 0 {
+            bail!("adaptive_avg_pool2d: output size ({out_h}, {out_w}) should be positive")
+        }
+        let pool_dim = |xs: &Self, dim: usize, l: usize, out: usize| -> Result<Self> {
+            if l == out {
+                return Ok(xs.clone());
+            }
+            let slices = (0..out)
+                .map(|i| {
+                    let start = i * l / out;
+                    let end = ((i + 1) * l).div_ceil(out);
+                    xs.narrow(dim, start, end - start)?.mean_keepdim(dim)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Tensor::cat(&slices, dim)
+        };
+        let xs = pool_dim(self, 2, h, out_h)?;
+        pool_dim(&xs, 3, w, out_w)
     }
 
     /// Returns the matrix-multiplication of the input tensor with the other provided tensor.
@@ -1160,6 +1717,10 @@ impl Tensor {
     ///
     /// The resulting tensor has dimensions `b1, b2, ..., bi, m, n`.
     pub fn matmul(&self, rhs: &Self) -> Result<Self> {
+        use crate::autocast::{cast2, AutocastOp};
+        if let Some((lhs, rhs)) = cast2(self, rhs, AutocastOp::Matmul)? {
+            return lhs.matmul(&rhs);
+        }
         let a_dims = self.shape().dims();
         let b_dims = rhs.shape().dims();
 
@@ -1171,7 +1732,8 @@ impl Tensor {
                 rhs: rhs.shape().clone(),
                 op: "matmul",
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), rhs.name()))?
         }
 
         let m = a_dims[dim - 2];
@@ -1191,7 +1753,8 @@ impl Tensor {
                 rhs: rhs.shape().clone(),
                 op: "matmul",
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), rhs.name()))?
         }
 
         let storage = self.storage().matmul(
@@ -1201,7 +1764,7 @@ impl Tensor {
             rhs.layout(),
         )?;
         let op = BackpropOp::new2(self, rhs, Op::Matmul);
-        Ok(from_storage(storage, c_shape, op, false))
+        from_storage(storage, c_shape, op, false)
     }
 
     /// Matrix-multiplication with broadcasting support.
@@ -1211,7 +1774,10 @@ impl Tensor {
     /// shape `(l, k, m)`, the output will have shape `(j, l, n, m)`.
     pub fn broadcast_matmul(&self, rhs: &Self) -> Result<Self> {
         let lhs = self;
-        let (l_shape, r_shape) = lhs.shape().broadcast_shape_matmul(rhs.shape())?;
+        let (l_shape, r_shape) = lhs
+            .shape()
+            .broadcast_shape_matmul(rhs.shape())
+            .map_err(|e| e.with_tensor_names(lhs.name(), rhs.name()))?;
         let l_broadcast = l_shape != *lhs.shape();
         let r_broadcast = r_shape != *rhs.shape();
         // TODO: Avoid concretising the broadcasted matrixes via contiguous.
@@ -1226,6 +1792,55 @@ impl Tensor {
         }
     }
 
+    /// Outer product of two 1d tensors, if `self` has `m` elements and `rhs` has `n` elements the
+    /// result has shape `(m, n)`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    /// let b = Tensor::new(&[1f32, -1.], &Device::Cpu)?;
+    /// let t = a.outer(&b)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[1., -1.], [2., -2.], [3., -3.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn outer(&self, rhs: &Self) -> Result<Self> {
+        let m = self.dims1()?;
+        let n = rhs.dims1()?;
+        self.reshape((m, 1))?.broadcast_mul(&rhs.reshape((1, n))?)
+    }
+
+    /// Kronecker product over the last two dimensions, the leading dimensions are broadcasted
+    /// together. If `self` has shape `(.., m, n)` and `rhs` has shape `(.., p, q)`, the result
+    /// has shape `(.., m * p, n * q)`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[[1f32, 2.], [3., 4.]], &Device::Cpu)?;
+    /// let b = Tensor::new(&[[0f32, 1.]], &Device::Cpu)?;
+    /// let t = a.kron(&b)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[0., 1., 0., 2.], [0., 3., 0., 4.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn kron(&self, rhs: &Self) -> Result<Self> {
+        if self.rank() < 2 || rhs.rank() < 2 {
+            bail!(
+                "kron expects tensors with at least two dims, got {:?} and {:?}",
+                self.shape(),
+                rhs.shape()
+            )
+        }
+        let (m, n) = (self.dim(D::Minus2)?, self.dim(D::Minus1)?);
+        let (p, q) = (rhs.dim(D::Minus2)?, rhs.dim(D::Minus1)?);
+        let mut l_dims = self.dims()[..self.rank() - 2].to_vec();
+        l_dims.extend([m, 1, n, 1]);
+        let mut r_dims = rhs.dims()[..rhs.rank() - 2].to_vec();
+        r_dims.extend([1, p, 1, q]);
+        let t = self.reshape(l_dims)?.broadcast_mul(&rhs.reshape(r_dims)?)?;
+        let mut dims = t.dims()[..t.rank() - 4].to_vec();
+        dims.extend([m * p, n * q]);
+        t.reshape(dims)
+    }
+
     /// Returns a tensor with the same shape as the input tensor, the values are taken from
     /// `on_true` if the input tensor value is not zero, and `on_false` at the positions where the
     /// input tensor is equal to zero.
@@ -1240,7 +1855,69 @@ impl Tensor {
             on_false.layout(),
         )?;
         let op = BackpropOp::new3(self, on_true, on_false, Op::WhereCond);
-        Ok(from_storage(storage, shape, op, false))
+        from_storage(storage, shape, op, false)
+    }
+
+    // The flattened indexes of the non-zero elements, these are computed on the host as the
+    // number of elements is data dependent.
+    fn nonzero_flat_indexes(&self) -> Result<Vec<u32>> {
+        let mask = self
+            .ne(0u8)?
+            .flatten_all()?
+            .to_device(&Device::Cpu)?
+            .to_vec1::<u8>()?;
+        let indexes = mask
+            .iter()
+            .enumerate()
+            .filter(|(_, &m)| m != 0)
+            .map(|(i, _)| i as u32)
+            .collect();
+        Ok(indexes)
+    }
+
+    /// Returns the coordinates of the non-zero elements as a `(n, rank)` tensor of type `u32`,
+    /// where `n` is the number of non-zero elements. The coordinates are in row-major order.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[0f32, 1.], [2., 0.]], &Device::Cpu)?;
+    /// assert_eq!(t.nonzero()?.to_vec2::<u32>()?, &[[0, 1], [1, 0]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn nonzero(&self) -> Result<Self> {
+        let indexes = self.nonzero_flat_indexes()?;
+        let dims = self.dims();
+        let rank = dims.len();
+        let n = indexes.len();
+        let mut coords = vec![0u32; n * rank];
+        for (coord, &index) in coords.chunks_exact_mut(rank.max(1)).zip(indexes.iter()) {
+            let mut index = index as usize;
+            for (c, &d) in coord.iter_mut().zip(dims.iter()).rev() {
+                *c = (index % d) as u32;
+                index /= d;
+            }
+        }
+        Tensor::from_vec(coords, (n, rank), self.device())
+    }
+
+    /// Returns a 1d tensor with the elements of `self` for which `mask` is non-zero. The mask is
+    /// broadcasted to the shape of `self`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[0f32, 1.], [2., 3.]], &Device::Cpu)?;
+    /// let mask = t.gt(1.5)?;
+    /// assert_eq!(t.masked_select(&mask)?.to_vec1::<f32>()?, &[2., 3.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn masked_select(&self, mask: &Self) -> Result<Self> {
+        let indexes = mask.broadcast_as(self.shape())?.nonzero_flat_indexes()?;
+        if indexes.is_empty() {
+            return Tensor::zeros(0, self.dtype(), self.device());
+        }
+        let n = indexes.len();
+        let indexes = Tensor::from_vec(indexes, n, self.device())?;
+        self.flatten_all()?.index_select(&indexes, 0)
     }
 
     /// Returns a tensor with the values from the `self` tensor at the index corresponding to the
@@ -1269,7 +1946,8 @@ impl Tensor {
                 rhs: ids.shape().clone(),
                 op: "embedding",
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), ids.name()))?
         }
         self.index_select(ids, 0)
     }
@@ -1296,7 +1974,8 @@ impl Tensor {
                 lhs: self.shape().clone(),
                 rhs: source.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), source.name()))?
         }
         if indexes.dims() != source.dims() {
             Err(Error::ShapeMismatchBinaryOp {
@@ -1304,7 +1983,8 @@ impl Tensor {
                 lhs: indexes.shape().clone(),
                 rhs: source.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(indexes.name(), source.name()))?
         }
         let storage = self.storage().scatter_add(
             self.layout(),
@@ -1317,7 +1997,7 @@ impl Tensor {
         let op = BackpropOp::new3(self, indexes, source, |t1, t2, t3| {
             Op::ScatterAdd(t1, t2, t3, dim)
         });
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     /// Embeds the values of the `src` tensor into the `self` tensor on the specified dimension.
@@ -1377,7 +2057,8 @@ impl Tensor {
                 lhs: self.shape().clone(),
                 rhs: src.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), src.name()))?
         }
         let mut storage = unsafe { self.device().alloc_uninit(self.shape(), self.dtype())? };
         self.storage()
@@ -1386,7 +2067,7 @@ impl Tensor {
         src.storage()
             .copy_strided_src(&mut storage, offset, src.layout())?;
         let op = BackpropOp::new2(self, src, |t1, t2| Op::SliceScatter0(t1, t2, start));
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     /// Accumulate element from `source` at indexes `indexes` and add them to `self`.
@@ -1412,7 +2093,8 @@ impl Tensor {
                 lhs: self.shape().clone(),
                 rhs: source.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), source.name()))?
         }
         // The number of element in indexes must match the dimension on which the add is
         // performed on the source tensor (and the index values from `indexes` are taken from
@@ -1424,7 +2106,8 @@ impl Tensor {
                 lhs: indexes.shape().clone(),
                 rhs: source.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(indexes.name(), source.name()))?
         }
         let storage = self.storage().index_add(
             self.layout(),
@@ -1437,7 +2120,7 @@ impl Tensor {
         let op = BackpropOp::new3(self, indexes, source, |t1, t2, t3| {
             Op::IndexAdd(t1, t2, t3, dim)
         });
-        Ok(from_storage(storage, self.shape(), op, false))
+        from_storage(storage, self.shape(), op, false)
     }
 
     /// Gather values across the target dimension.
@@ -1473,13 +2156,54 @@ impl Tensor {
                 lhs: self.shape().clone(),
                 rhs: indexes.shape().clone(),
             }
-            .bt())?
+            .bt()
+            .with_tensor_names(self.name(), indexes.name()))?
         }
         let storage =
             self.storage()
                 .gather(self.layout(), &indexes.storage(), indexes.layout(), dim)?;
         let op = BackpropOp::new2(self, indexes, |t1, t2| Op::Gather(t1, t2, dim));
-        Ok(from_storage(storage, indexes.shape(), op, false))
+        from_storage(storage, indexes.shape(), op, false)
+    }
+
+    /// Gather values across the target dimension, similar to `gather` except that `self` and
+    /// `indexes` are broadcasted together on all the dimensions other than `dim`.
+    ///
+    /// This can be used to select the values matching the indexes returned by `arg_sort` or
+    /// `argmax_keepdim`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[[3f32, 1., 4.], [1., 5., 9.]], &Device::Cpu)?;
+    /// let idx = t.argmax_keepdim(1)?;
+    /// let t = t.take_along_dim(&idx, 1)?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[4.], [9.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn take_along_dim<D: Dim>(&self, indexes: &Self, dim: D) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "take-along-dim")?;
+        if self.rank() != indexes.rank() {
+            Err(Error::ShapeMismatchBinaryOp {
+                op: "take-along-dim",
+                lhs: self.shape().clone(),
+                rhs: indexes.shape().clone(),
+            }
+            .bt()
+            .with_tensor_names(self.name(), indexes.name()))?
+        }
+        let mut self_dims = self.dims().to_vec();
+        let mut indexes_dims = indexes.dims().to_vec();
+        self_dims[dim] = 1;
+        indexes_dims[dim] = 1;
+        let shape = Shape::from(self_dims)
+            .broadcast_shape_binary_op(&Shape::from(indexes_dims), "take-along-dim")?;
+        let mut self_dims = shape.dims().to_vec();
+        let mut indexes_dims = shape.dims().to_vec();
+        self_dims[dim] = self.dim(dim)?;
+        indexes_dims[dim] = indexes.dim(dim)?;
+        let src = self.broadcast_as(self_dims)?.contiguous()?;
+        let indexes = indexes.broadcast_as(indexes_dims)?.contiguous()?;
+        src.gather(&indexes, dim)
     }
 
     /// Select values for the input tensor at the target indexes across the specified dimension.
@@ -1498,7 +2222,8 @@ impl Tensor {
                 rhs: indexes.shape().clone(),
                 op: "index-select",
             }
-            .bt())?,
+            .bt()
+            .with_tensor_names(self.name(), indexes.name()))?,
         };
         let storage = self.storage().index_select(
             &indexes.storage(),
@@ -1509,7 +2234,7 @@ impl Tensor {
         let mut dims = self.dims().to_vec();
         dims[dim] = indexes_len;
         let op = BackpropOp::new2(self, indexes, |t1, t2| Op::IndexSelect(t1, t2, dim));
-        Ok(from_storage(storage, dims, op, false))
+        from_storage(storage, dims, op, false)
     }
 
     /// Returns an iterator over position of the elements in the storage when ranging over the
@@ -1548,6 +2273,7 @@ impl Tensor {
             Storage::Cpu(storage) => from_cpu_storage(storage),
             Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
             Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Wgpu(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
         }
     }
 
@@ -1579,6 +2305,7 @@ impl Tensor {
             Storage::Cpu(storage) => from_cpu_storage(storage),
             Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
             Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Wgpu(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
         }
     }
 
@@ -1620,6 +2347,7 @@ impl Tensor {
             Storage::Cpu(storage) => from_cpu_storage(storage),
             Storage::Cuda(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
             Storage::Metal(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
+            Storage::Wgpu(storage) => from_cpu_storage(&storage.to_cpu_storage()?),
         }
     }
 
@@ -1674,13 +2402,48 @@ impl Tensor {
         self.id
     }
 
+    /// Returns a tensor sharing the storage, the gradient tracking and the id of this tensor with
+    /// `name` attached to it. The name is reported in shape mismatch errors involving the tensor
+    /// and is inherited by the views created from it, e.g. via `t` or `narrow`. Tensors retrieved
+    /// through a `VarBuilder` are named after their path.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, DType, Device};
+    /// let w = Tensor::zeros((3, 4), DType::F32, &Device::Cpu)?.with_name("proj.weight");
+    /// let x = Tensor::zeros((2, 5), DType::F32, &Device::Cpu)?;
+    /// let err = x.matmul(&w.t()?).unwrap_err().to_string();
+    /// assert!(err.contains("rhs name: proj.weight"));
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn with_name(&self, name: &str) -> Tensor {
+        let tensor_ = Tensor_ {
+            id: self.id,
+            storage: self.storage.clone(),
+            layout: self.layout.clone(),
+            op: self.op.clone(),
+            is_variable: self.is_variable,
+            dtype: self.dtype,
+            device: self.device.clone(),
+            name: Some(name.into()),
+        };
+        Tensor(Arc::new(tensor_))
+    }
+
+    /// The name attached to this tensor with `with_name` if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     /// Whether this tensor is a variable or not. A variable is a tensor for which gradient is
     /// tracked and on which backpropagation can be performed.
     pub fn is_variable(&self) -> bool {
         self.is_variable
     }
 
-    pub(crate) fn op(&self) -> &Option<Op> {
+    /// The operation that produced this tensor, this is only recorded when one of the arguments
+    /// of the operation is tracked, i.e. is a variable or has itself been produced by a tracked
+    /// operation. This can be used to walk the computation graph, e.g. to export it.
+    pub fn op(&self) -> &Option<Op> {
         &self.op
     }
 
@@ -1841,6 +2604,7 @@ impl Tensor {
             is_variable: false,
             dtype: self.dtype,
             device: self.device.clone(),
+            name: self.name.clone(),
         };
         Ok(Tensor(Arc::new(tensor_)))
     }
@@ -1877,6 +2641,7 @@ impl Tensor {
             is_variable: false,
             dtype: self.dtype,
             device: self.device.clone(),
+            name: self.name.clone(),
         };
         Ok(Tensor(Arc::new(tensor_)))
     }
@@ -1903,6 +2668,7 @@ impl Tensor {
             is_variable: false,
             dtype: self.dtype,
             device: self.device.clone(),
+            name: self.name.clone(),
         };
         Ok(Tensor(Arc::new(tensor_)))
     }
@@ -1923,11 +2689,72 @@ impl Tensor {
                 is_variable: false,
                 dtype: self.dtype,
                 device: self.device.clone(),
+                name: self.name.clone(),
             };
             Tensor(Arc::new(tensor_))
         }
     }
 
+    /// Returns true if the tensor contains some nan values, this is always false for integer
+    /// dtypes.
+    pub fn has_nan(&self) -> Result<bool> {
+        if self.dtype().is_complex() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "has_nan").bt())?
+        }
+        if !self.dtype().is_float() || self.elem_count() == 0 {
+            return Ok(false);
+        }
+        let t = self.detach();
+        let nan = t.ne(&t)?.flatten_all()?.max(0)?.to_scalar::<u8>()?;
+        Ok(nan != 0)
+    }
+
+    /// Returns true if the tensor contains some positive or negative infinite values, this is
+    /// always false for integer dtypes.
+    pub fn has_inf(&self) -> Result<bool> {
+        if self.dtype().is_complex() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "has_inf").bt())?
+        }
+        if !self.dtype().is_float() || self.elem_count() == 0 {
+            return Ok(false);
+        }
+        let inf = self.detach().abs()?.eq(f64::INFINITY)?;
+        let inf = inf.flatten_all()?.max(0)?.to_scalar::<u8>()?;
+        Ok(inf != 0)
+    }
+
+    /// Returns a new tensor sharing the storage and layout of the current one but with `op` as
+    /// the operation used to compute it.
+    pub(crate) fn with_op(&self, op: BackpropOp) -> Tensor {
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: self.storage.clone(),
+            layout: self.layout.clone(),
+            op,
+            is_variable: false,
+            dtype: self.dtype,
+            device: self.device.clone(),
+            name: self.name.clone(),
+        };
+        Tensor(Arc::new(tensor_))
+    }
+
+    /// Returns the storage of this tensor for in-place modifications. This only succeeds when
+    /// the modification cannot be observed elsewhere: the tensor node and its storage are not
+    /// shared, the data is contiguous and starts at the beginning of the storage, and the tensor
+    /// is not part of a computation graph.
+    pub(crate) fn unique_storage_mut(&mut self) -> Option<&mut Storage> {
+        let tensor_ = Arc::get_mut(&mut self.0)?;
+        if tensor_.op.is_some()
+            || tensor_.is_variable
+            || !tensor_.layout.is_contiguous()
+            || tensor_.layout.start_offset() != 0
+        {
+            return None;
+        }
+        Arc::get_mut(&mut tensor_.storage)?.get_mut().ok()
+    }
+
     /// If the target device is the same as the tensor device, only a shallow copy is performed.
     pub fn to_device(&self, device: &Device) -> Result<Tensor> {
         if self.device().same_device(device) {
@@ -1940,8 +2767,12 @@ impl Tensor {
                 (Storage::Cpu(storage), Device::Metal(metal)) => {
                     Storage::Metal(metal.storage_from_cpu_storage(storage)?)
                 }
+                (Storage::Cpu(storage), Device::Wgpu(wgpu)) => {
+                    Storage::Wgpu(wgpu.storage_from_cpu_storage(storage)?)
+                }
                 (Storage::Cuda(storage), Device::Cpu) => Storage::Cpu(storage.to_cpu_storage()?),
                 (Storage::Metal(storage), Device::Cpu) => Storage::Cpu(storage.to_cpu_storage()?),
+                (Storage::Wgpu(storage), Device::Cpu) => Storage::Cpu(storage.to_cpu_storage()?),
                 (Storage::Cuda(storage), Device::Cuda(cuda)) => {
                     // TODO: Avoid passing through the cpu storage here, especially if the gpu ids
                     // are the same.
@@ -1962,11 +2793,47 @@ impl Tensor {
                 is_variable: false,
                 dtype: self.dtype,
                 device: device.clone(),
+                name: self.name.clone(),
             };
             Ok(Tensor(Arc::new(tensor_)))
         }
     }
 
+    /// Similar to [`Self::to_device`] but the copies from the cpu to a cuda device do not wait
+    /// for the data to be transferred. The data is staged in pinned memory and copied on a
+    /// dedicated stream so that the transfer can overlap with the computations already enqueued,
+    /// e.g. to prefetch the next batch of a training loop. The operations using the returned
+    /// tensor are ordered after the copy, [`Device::synchronize`] waits for all the copies to
+    /// complete. The other transfers are synchronous.
+    ///
+    /// The pinned staging buffers are pooled per device. A buffer used by an enqueued copy is only
+    /// recycled when [`Device::synchronize`] is called, or when no pooled buffer is large enough
+    /// for a new copy: in this case the call blocks until all the pending copies have completed.
+    /// Calling `synchronize` regularly, e.g. once per training step, avoids these stalls and keeps
+    /// the pinned memory bounded by the size of the copies enqueued in between. The device
+    /// buffers receiving the data come from the caching allocator of the device.
+    pub fn to_device_async(&self, device: &Device) -> Result<Tensor> {
+        let cuda = match device {
+            Device::Cuda(cuda) if self.device().is_cpu() => cuda,
+            _ => return self.to_device(device),
+        };
+        let storage = match &*self.storage() {
+            Storage::Cpu(storage) => Storage::Cuda(cuda.storage_from_cpu_storage_async(storage)?),
+            _ => bail!("unexpected storage for a cpu tensor"),
+        };
+        let tensor_ = Tensor_ {
+            id: TensorId::new(),
+            storage: Arc::new(RwLock::new(storage)),
+            layout: self.layout.clone(),
+            op: BackpropOp::new1(self, Op::ToDevice),
+            is_variable: false,
+            dtype: self.dtype,
+            device: device.clone(),
+            name: self.name.clone(),
+        };
+        Ok(Tensor(Arc::new(tensor_)))
+    }
+
     /// Returns a new tensor duplicating data from the original tensor. New dimensions are inserted
     /// on the left.
     pub fn broadcast_left<S: Into<Shape>>(&self, left_shape: S) -> Result<Self> {
@@ -1992,6 +2859,7 @@ impl Tensor {
             is_variable: false,
             dtype: self.dtype,
             device: self.device.clone(),
+            name: self.name.clone(),
         };
         Ok(Tensor(Arc::new(tensor_)))
     }
@@ -2018,7 +2886,43 @@ impl Tensor {
             let shape = self.shape();
             let storage = self.storage().to_dtype(self.layout(), dtype)?;
             let op = BackpropOp::new1(self, Op::ToDType);
-            Ok(from_storage(storage, shape.clone(), op, false))
+            from_storage(storage, shape.clone(), op, false)
+        }
+    }
+
+    /// Same as `to_dtype` but using a specific rounding mode. Stochastic rounding is only
+    /// supported for f32 to bf16 conversions, it uses the cpu random number generator so that it
+    /// can be seeded via `Device::set_seed` on the cpu device, and the conversion is done on the
+    /// host.
+    pub fn to_dtype_with(&self, dtype: DType, rounding: crate::RoundingMode) -> Result<Self> {
+        if self.dtype() == dtype {
+            return Ok(self.clone());
+        }
+        match (rounding, self.dtype(), dtype) {
+            (crate::RoundingMode::Nearest, _, _) => self.to_dtype(dtype),
+            (crate::RoundingMode::Stochastic, DType::F32, DType::BF16) => {
+                let xs = self.flatten_all()?.to_vec1::<f32>()?;
+                let data = crate::cpu_backend::with_rng(|rng| {
+                    xs.iter()
+                        .map(|&x| {
+                            if x.is_finite() {
+                                // Adding random low bits before truncating rounds the magnitude
+                                // up with a probability proportional to the truncated residual.
+                                let bits = x.to_bits().wrapping_add(rng.next_u32() & 0xffff);
+                                half::bf16::from_bits((bits >> 16) as u16)
+                            } else {
+                                half::bf16::from_f32(x)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                });
+                let storage = self.device().storage_owned(data)?;
+                let op = BackpropOp::new1(self, Op::ToDType);
+                from_storage(storage, self.shape().clone(), op, false)
+            }
+            (crate::RoundingMode::Stochastic, src, dst) => {
+                bail!("stochastic rounding is not supported from {src:?} to {dst:?}")
+            }
         }
     }
 
@@ -2033,7 +2937,7 @@ impl Tensor {
             self.storage()
                 .copy_strided_src(&mut storage, 0, self.layout())?;
             let op = BackpropOp::new1(self, Op::Copy);
-            Ok(from_storage(storage, shape.clone(), op, false))
+            from_storage(storage, shape.clone(), op, false)
         }
     }
 
@@ -2044,7 +2948,7 @@ impl Tensor {
         self.storage()
             .copy_strided_src(&mut storage, 0, self.layout())?;
         let op = BackpropOp::new1(self, Op::Copy);
-        Ok(from_storage(storage, shape.clone(), op, false))
+        from_storage(storage, shape.clone(), op, false)
     }
 
     /// Create a variable based on the values currently stored in a tensor. The storage is always
@@ -2054,7 +2958,7 @@ impl Tensor {
         let mut storage = unsafe { self.device().alloc_uninit(&shape, self.dtype())? };
         self.storage()
             .copy_strided_src(&mut storage, 0, self.layout())?;
-        Ok(from_storage(storage, shape, BackpropOp::none(), true))
+        from_storage(storage, shape, BackpropOp::none(), true)
     }
 
     /// Reshape returns a tensor with the target shape provided that the number of elements of the
@@ -2089,7 +2993,8 @@ impl Tensor {
                 rhs: shape,
                 op: "reshape",
             }
-            .bt());
+            .bt()
+            .with_tensor_names(self.name(), None));
         }
         let op = BackpropOp::new1(self, Op::Reshape);
         if self.is_contiguous() {
@@ -2101,13 +3006,14 @@ impl Tensor {
                 is_variable: false,
                 dtype: self.dtype,
                 device: self.device.clone(),
+                name: self.name.clone(),
             };
             Ok(Tensor(Arc::new(tensor_)))
         } else {
             let mut storage = unsafe { self.device().alloc_uninit(&shape, self.dtype())? };
             self.storage()
                 .copy_strided_src(&mut storage, 0, self.layout())?;
-            Ok(from_storage(storage, shape, op, false))
+            from_storage(storage, shape, op, false)
         }
     }
 
@@ -2142,6 +3048,7 @@ impl Tensor {
                 is_variable: false,
                 dtype: self.dtype,
                 device: self.device.clone(),
+                name: self.name.clone(),
             };
             Ok(Tensor(Arc::new(tensor_)))
         } else {
@@ -2180,6 +3087,7 @@ impl Tensor {
             is_variable: false,
             dtype: self.dtype,
             device: self.device.clone(),
+            name: self.name.clone(),
         };
         Ok(Tensor(Arc::new(tensor_)))
     }
@@ -2280,6 +3188,127 @@ impl Tensor {
         }
     }
 
+    /// Pad the input tensor along dimension `dim` using the padding `mode`. This adds `left`
+    /// elements before the input tensor values and `right` elements after, see [`PadMode`] for
+    /// the values that are used.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device, PadMode};
+    /// let t = Tensor::new(&[1f32, 2., 3., 4.], &Device::Cpu)?;
+    /// let p = t.pad(0, 2, 1, PadMode::Reflect)?;
+    /// assert_eq!(p.to_vec1::<f32>()?, [3., 2., 1., 2., 3., 4., 3.]);
+    /// let p = t.pad(0, 2, 1, PadMode::Circular)?;
+    /// assert_eq!(p.to_vec1::<f32>()?, [3., 4., 1., 2., 3., 4., 1.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn pad<D: Dim>(&self, dim: D, left: usize, right: usize, mode: PadMode) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "pad")?;
+        if left == 0 && right == 0 {
+            return Ok(self.clone());
+        }
+        let size = self.dim(dim)?;
+        match mode {
+            PadMode::Constant(v) => {
+                let mut dims = self.dims().to_vec();
+                dims[dim] = left;
+                let l = Tensor::zeros(dims.as_slice(), self.dtype, self.device())?.affine(1., v)?;
+                dims[dim] = right;
+                let r = Tensor::zeros(dims.as_slice(), self.dtype, self.device())?.affine(1., v)?;
+                Tensor::cat(&[&l, self, &r], dim)
+            }
+            PadMode::Reflect if left >= size || right >= size => {
+                bail!(
+                    "reflect padding ({left}, {right}) should be smaller than the dim size {size}"
+                )
+            }
+            PadMode::Circular if left > size || right > size => {
+                bail!("circular padding ({left}, {right}) should not exceed the dim size {size}")
+            }
+            PadMode::Replicate if size == 0 => {
+                bail!("cannot use replicate padding on an empty dimension")
+            }
+            PadMode::Reflect | PadMode::Replicate | PadMode::Circular => {
+                let n = size as i64;
+                let ids = (-(left as i64)..n + right as i64)
+                    .map(|p| {
+                        let p = match mode {
+                            PadMode::Reflect if p < 0 => -p,
+                            PadMode::Reflect if p >= n => 2 * (n - 1) - p,
+                            PadMode::Replicate => p.clamp(0, n - 1),
+                            PadMode::Circular => p.rem_euclid(n),
+                            _ => p,
+                        };
+                        p as u32
+                    })
+                    .collect::<Vec<_>>();
+                let ids = Tensor::new(ids, self.device())?;
+                self.index_select(&ids, dim)
+            }
+        }
+    }
+
+    /// Returns the sliding windows of size `size` taken every `step` elements along `dim`. The
+    /// windows are indexed by `dim` and a new trailing dimension of size `size` indexes the
+    /// elements within each window, as in PyTorch.
+    ///
+    /// The result is a strided view of the input when it does not track gradients, otherwise the
+    /// windows are materialized.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::arange(0f32, 7f32, &Device::Cpu)?;
+    /// let w = t.unfold(0, 3, 2)?;
+    /// assert_eq!(w.to_vec2::<f32>()?, [[0., 1., 2.], [2., 3., 4.], [4., 5., 6.]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn unfold<D: Dim>(&self, dim: D, size: usize, step: usize) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "unfold")?;
+        let layout = self.layout().unfold(dim, size, step)?;
+        if !self.track_op() {
+            let tensor_ = Tensor_ {
+                id: TensorId::new(),
+                storage: self.storage.clone(),
+                layout,
+                op: BackpropOp::none(),
+                is_variable: false,
+                dtype: self.dtype,
+                device: self.device.clone(),
+                name: self.name.clone(),
+            };
+            return Ok(Tensor(Arc::new(tensor_)));
+        }
+        // Gather the windows with a single index_select so that gradients can flow back.
+        let n_windows = layout.dims()[dim];
+        let ids = (0..n_windows)
+            .flat_map(|w| (0..size).map(move |k| (w * step + k) as u32))
+            .collect::<Vec<_>>();
+        let ids = Tensor::new(ids, self.device())?;
+        let mut dims = self.dims().to_vec();
+        dims[dim] = n_windows;
+        dims.insert(dim + 1, size);
+        let windows = self.index_select(&ids, dim)?.reshape(dims)?;
+        let mut perm = (0..windows.rank()).collect::<Vec<_>>();
+        let size_dim = perm.remove(dim + 1);
+        perm.push(size_dim);
+        windows.permute(perm)
+    }
+
+    /// Extracts the sliding `(kh, kw)` patches of a `(b, c, h, w)` tensor using a stride of
+    /// `(sh, sw)`, similar to PyTorch's `F.unfold` without padding or dilation. The result has
+    /// shape `(b, c * kh * kw, oh * ow)` where `oh` and `ow` are the number of patches along the
+    /// height and width, this is the layout used by im2col based convolutions.
+    pub fn unfold2d(&self, kernel: (usize, usize), stride: (usize, usize)) -> Result<Self> {
+        let (b, c, _h, _w) = self.dims4()?;
+        let (kh, kw) = kernel;
+        let (sh, sw) = stride;
+        // (b, c, oh, ow, kh, kw)
+        let patches = self.unfold(2, kh, sh)?.unfold(3, kw, sw)?;
+        let (oh, ow) = (patches.dim(2)?, patches.dim(3)?);
+        patches
+            .permute((0, 1, 4, 5, 2, 3))?
+            .reshape((b, c * kh * kw, oh * ow))
+    }
+
     /// Run the `forward` method of `m` on `self`.
     pub fn apply<M: crate::Module>(&self, m: &M) -> Result<Self> {
         m.forward(self)
@@ -2360,6 +3389,141 @@ impl Tensor {
         t1.eq(&t2)?.to_dtype(dtype)
     }
 
+    // Returns a (m, n) u8 mask that is set for the elements on or below (resp. above) the
+    // `diagonal`-th diagonal.
+    fn tri_mask(m: usize, n: usize, diagonal: i64, lower: bool, device: &Device) -> Result<Self> {
+        let rows = Tensor::arange(0u32, m as u32, device)?.reshape((m, 1))?;
+        let cols = Tensor::arange(0u32, n as u32, device)?.reshape((1, n))?;
+        // Shift whichever side keeps the indexes non-negative.
+        let (rows, cols) = if diagonal >= 0 {
+            ((rows + diagonal as f64)?, cols)
+        } else {
+            (rows, (cols + (-diagonal) as f64)?)
+        };
+        let rows = rows.broadcast_as((m, n))?;
+        let cols = cols.broadcast_as((m, n))?;
+        if lower {
+            cols.le(&rows)
+        } else {
+            cols.ge(&rows)
+        }
+    }
+
+    fn tri(&self, diagonal: i64, lower: bool, op: &'static str) -> Result<Self> {
+        if self.rank() < 2 {
+            bail!(
+                "{op} expects a tensor with at least 2 dims, got {:?}",
+                self.shape()
+            )
+        }
+        let (m, n) = (self.dims()[self.rank() - 2], self.dims()[self.rank() - 1]);
+        let mask = Self::tri_mask(m, n, diagonal, lower, self.device())?;
+        mask.broadcast_as(self.shape())?
+            .where_cond(self, &self.zeros_like()?)
+    }
+
+    /// Returns a copy of the tensor where the elements above the `diagonal`-th diagonal of the
+    /// last two dimensions are set to zero.
+    ///
+    /// `diagonal = 0` is the main diagonal, positive values refer to diagonals above it and
+    /// negative values to diagonals below it.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::ones((3, 3), candle_core::DType::U8, &Device::Cpu)?;
+    /// let t = t.tril(-1)?;
+    /// assert_eq!(t.to_vec2::<u8>()?, &[[0, 0, 0], [1, 0, 0], [1, 1, 0]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn tril(&self, diagonal: i64) -> Result<Self> {
+        self.tri(diagonal, true, "tril")
+    }
+
+    /// Returns a copy of the tensor where the elements below the `diagonal`-th diagonal of the
+    /// last two dimensions are set to zero.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::ones((3, 3), candle_core::DType::U8, &Device::Cpu)?;
+    /// let t = t.triu(1)?;
+    /// assert_eq!(t.to_vec2::<u8>()?, &[[0, 1, 1], [0, 0, 1], [0, 0, 0]]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn triu(&self, diagonal: i64) -> Result<Self> {
+        self.tri(diagonal, false, "triu")
+    }
+
+    /// Returns the `offset`-th diagonal with respect to dimensions `dim1` and `dim2`.
+    ///
+    /// These two dimensions are removed and the diagonal is appended as the last dimension of
+    /// the result. A positive `offset` refers to a diagonal above the main one, a negative
+    /// `offset` to a diagonal below it.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::arange(0u32, 6, &Device::Cpu)?.reshape((2, 3))?;
+    /// assert_eq!(t.diagonal(0, 0, 1)?.to_vec1::<u32>()?, &[0, 4]);
+    /// assert_eq!(t.diagonal(1, 0, 1)?.to_vec1::<u32>()?, &[1, 5]);
+    /// assert_eq!(t.diagonal(-1, 0, 1)?.to_vec1::<u32>()?, &[3]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn diagonal<D1: Dim, D2: Dim>(&self, offset: i64, dim1: D1, dim2: D2) -> Result<Self> {
+        let dim1 = dim1.to_index(self.shape(), "diagonal")?;
+        let dim2 = dim2.to_index(self.shape(), "diagonal")?;
+        if dim1 == dim2 {
+            bail!("diagonal: dim1 and dim2 have to be different, got {dim1}")
+        }
+        let mut perm: Vec<usize> = (0..self.rank())
+            .filter(|&d| d != dim1 && d != dim2)
+            .collect();
+        perm.push(dim1);
+        perm.push(dim2);
+        let t = self.permute(perm)?;
+        let rank = t.rank();
+        let (n1, n2) = (t.dims()[rank - 2], t.dims()[rank - 1]);
+        let (start1, start2) = if offset >= 0 {
+            (0, offset as usize)
+        } else {
+            ((-offset) as usize, 0)
+        };
+        let len = n1.saturating_sub(start1).min(n2.saturating_sub(start2));
+        let mut dims = t.dims()[..rank - 2].to_vec();
+        if len == 0 {
+            dims.push(0);
+            return Tensor::zeros(dims, self.dtype(), self.device());
+        }
+        let t = t
+            .narrow(rank - 2, start1, len)?
+            .narrow(rank - 1, start2, len)?
+            .flatten_from(rank - 2)?;
+        let ids = Tensor::arange_step(0u32, (len * len) as u32, (len + 1) as u32, self.device())?;
+        t.index_select(&ids, rank - 2)
+    }
+
+    /// Builds a square matrix with the elements of a 1d tensor on its diagonal, or extracts the
+    /// main diagonal of a 2d tensor.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[1f32, 2.], &Device::Cpu)?;
+    /// let t = t.diag()?;
+    /// assert_eq!(t.to_vec2::<f32>()?, &[[1., 0.], [0., 2.]]);
+    /// assert_eq!(t.diag()?.to_vec1::<f32>()?, &[1., 2.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn diag(&self) -> Result<Self> {
+        match self.rank() {
+            1 => {
+                let n = self.elem_count();
+                let mask = Self::eye(n, DType::U8, self.device())?;
+                let t = self.unsqueeze(0)?.broadcast_as((n, n))?;
+                mask.where_cond(&t, &Tensor::zeros((n, n), self.dtype(), self.device())?)
+            }
+            2 => self.diagonal(0, 0, 1),
+            rank => bail!("diag expects a 1d or 2d tensor, got rank {rank}"),
+        }
+    }
+
     /// Returns the cumulative sum of elements of the input tensor summed over the specified
     /// dimension.
     ///
@@ -2382,6 +3546,209 @@ impl Tensor {
         }
     }
 
+    // Inclusive scan along `dim` for an associative operation `f(prev, cur)`, this uses
+    // log2(n) steps where each element is combined with the one `offset` positions before it.
+    fn scan<F>(ts: &[Self], dim: usize, f: F) -> Result<Vec<Self>>
+    where
+        F: Fn(&[Self], &[Self]) -> Result<Vec<Self>>,
+    {
+        let mut ts = ts.to_vec();
+        let n = ts[0].dim(dim)?;
+        let mut offset = 1;
+        while offset < n {
+            let prev = ts
+                .iter()
+                .map(|t| t.narrow(dim, 0, n - offset))
+                .collect::<Result<Vec<_>>>()?;
+            let cur = ts
+                .iter()
+                .map(|t| t.narrow(dim, offset, n - offset))
+                .collect::<Result<Vec<_>>>()?;
+            let combined = f(&prev, &cur)?;
+            ts = ts
+                .iter()
+                .zip(combined.iter())
+                .map(|(t, c)| Tensor::cat(&[&t.narrow(dim, 0, offset)?, c], dim))
+                .collect::<Result<Vec<_>>>()?;
+            offset *= 2;
+        }
+        Ok(ts)
+    }
+
+    /// Returns the cumulative product of elements of the input tensor over the specified
+    /// dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[1f32, 2., 3., 4.], &Device::Cpu)?;
+    /// assert_eq!(t.cumprod(0)?.to_vec1::<f32>()?, &[1., 2., 6., 24.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn cumprod<D: Dim>(&self, dim: D) -> Result<Self> {
+        let dim = dim.to_index(self.shape(), "cumprod")?;
+        let ts = Self::scan(std::slice::from_ref(self), dim, |prev, cur| {
+            Ok(vec![(&prev[0] * &cur[0])?])
+        })?;
+        Ok(ts[0].clone())
+    }
+
+    fn cum_extremum(&self, dim: usize, max: bool) -> Result<(Self, Self)> {
+        let n = self.dim(dim)?;
+        let mut ids_shape = vec![1; self.rank()];
+        ids_shape[dim] = n;
+        let ids = Tensor::arange(0u32, n as u32, self.device())?
+            .reshape(ids_shape)?
+            .broadcast_as(self.shape())?
+            .contiguous()?;
+        let ts = Self::scan(&[self.clone(), ids], dim, |prev, cur| {
+            // On ties, the most recent index is kept.
+            let take_prev = if max {
+                prev[0].gt(&cur[0])?
+            } else {
+                prev[0].lt(&cur[0])?
+            };
+            let values = take_prev.where_cond(&prev[0], &cur[0])?;
+            let ids = take_prev.where_cond(&prev[1], &cur[1])?;
+            Ok(vec![values, ids])
+        })?;
+        Ok((ts[0].clone(), ts[1].clone()))
+    }
+
+    /// Returns the cumulative maximum of elements of the input tensor over the specified
+    /// dimension, together with the `u32` indexes of these maximums along this dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[1f32, 3., 2., 4.], &Device::Cpu)?;
+    /// let (values, indexes) = t.cummax(0)?;
+    /// assert_eq!(values.to_vec1::<f32>()?, &[1., 3., 3., 4.]);
+    /// assert_eq!(indexes.to_vec1::<u32>()?, &[0, 1, 1, 3]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn cummax<D: Dim>(&self, dim: D) -> Result<(Self, Self)> {
+        let dim = dim.to_index(self.shape(), "cummax")?;
+        self.cum_extremum(dim, true)
+    }
+
+    /// Returns the cumulative minimum of elements of the input tensor over the specified
+    /// dimension, together with the `u32` indexes of these minimums along this dimension.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[3f32, 1., 2., 0.], &Device::Cpu)?;
+    /// let (values, indexes) = t.cummin(0)?;
+    /// assert_eq!(values.to_vec1::<f32>()?, &[3., 1., 1., 0.]);
+    /// assert_eq!(indexes.to_vec1::<u32>()?, &[0, 1, 1, 3]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn cummin<D: Dim>(&self, dim: D) -> Result<(Self, Self)> {
+        let dim = dim.to_index(self.shape(), "cummin")?;
+        self.cum_extremum(dim, false)
+    }
+
+    /// Counts the number of occurrences of each value in a 1d tensor of non-negative integers.
+    ///
+    /// The result has `max(self) + 1` elements, or `minlength` if this is larger. When `weights`
+    /// is specified, the weights are summed rather than counting occurrences and the result uses
+    /// the dtype of `weights`, otherwise it uses `u32`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[1u32, 3, 1, 0], &Device::Cpu)?;
+    /// assert_eq!(t.bincount(None, 0)?.to_vec1::<u32>()?, &[1, 2, 0, 1]);
+    /// assert_eq!(t.bincount(None, 6)?.to_vec1::<u32>()?, &[1, 2, 0, 1, 0, 0]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn bincount(&self, weights: Option<&Self>, minlength: usize) -> Result<Self> {
+        if !self.dtype().is_int() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "bincount"))?
+        }
+        let values = self.to_dtype(DType::I64)?.to_vec1::<i64>()?;
+        let mut len = minlength;
+        for &v in values.iter() {
+            if v < 0 {
+                bail!("bincount: values have to be non-negative, got {v}")
+            }
+            len = usize::max(len, v as usize + 1)
+        }
+        match weights {
+            None => {
+                let mut counts = vec![0u32; len];
+                for &v in values.iter() {
+                    counts[v as usize] += 1
+                }
+                Tensor::from_vec(counts, len, self.device())
+            }
+            Some(weights) => {
+                self.same_shape_binary_op(weights, "bincount")?;
+                let ws = weights.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+                let mut sums = vec![0f64; len];
+                for (&v, &w) in values.iter().zip(ws.iter()) {
+                    sums[v as usize] += w
+                }
+                Tensor::from_vec(sums, len, weights.device())?.to_dtype(weights.dtype())
+            }
+        }
+    }
+
+    /// Computes a histogram of the values of a float tensor using `bins` bins of equal width.
+    ///
+    /// The bins span `range` if specified, values outside of this range being ignored, and the
+    /// minimum and maximum values of the tensor otherwise. All the bins are half-open except the
+    /// last one which also includes its right edge. This returns the `u32` counts for each bin
+    /// together with the `bins + 1` bin edges.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let t = Tensor::new(&[0f32, 0.5, 1.5, 2., 3.], &Device::Cpu)?;
+    /// let (counts, edges) = t.histogram(2, Some((0., 2.)))?;
+    /// assert_eq!(counts.to_vec1::<u32>()?, &[2, 2]);
+    /// assert_eq!(edges.to_vec1::<f32>()?, &[0., 1., 2.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn histogram(&self, bins: usize, range: Option<(f64, f64)>) -> Result<(Self, Self)> {
+        if !self.dtype().is_float() {
+            Err(Error::UnsupportedDTypeForOp(self.dtype(), "histogram"))?
+        }
+        if bins == 0 {
+            bail!("histogram: the number of bins has to be positive")
+        }
+        let values = self.flatten_all()?.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+        let (lo, hi) = match range {
+            Some((lo, hi)) => (lo, hi),
+            None => values
+                .iter()
+                .filter(|v| !v.is_nan())
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &v| {
+                    (lo.min(v), hi.max(v))
+                }),
+        };
+        let (lo, hi) = if lo > hi {
+            // Only reachable with an empty tensor when no range is given.
+            if range.is_some() {
+                bail!("histogram: invalid range ({lo}, {hi})")
+            }
+            (0., 1.)
+        } else if lo == hi {
+            (lo - 0.5, hi + 0.5)
+        } else {
+            (lo, hi)
+        };
+        let mut counts = vec![0u32; bins];
+        for &v in values.iter() {
+            if v.is_nan() || v < lo || v > hi {
+                continue;
+            }
+            let bin = ((v - lo) / (hi - lo) * bins as f64) as usize;
+            counts[bin.min(bins - 1)] += 1
+        }
+        let width = (hi - lo) / bins as f64;
+        let edges: Vec<f64> = (0..=bins).map(|i| lo + i as f64 * width).collect();
+        let counts = Tensor::from_vec(counts, bins, self.device())?;
+        let edges = Tensor::from_vec(edges, bins + 1, self.device())?.to_dtype(self.dtype())?;
+        Ok((counts, edges))
+    }
+
     /// Returns a copy of `self` where the values within `ranges` have been replaced with the
     /// content of `src`.
     pub fn slice_assign<D: std::ops::RangeBounds<usize>>(
@@ -2438,11 +3805,57 @@ impl Tensor {
         mask.where_cond(/* on_true= */ &src, /* on_false= */ self)
     }
 
-    /// Returns log(sum(exp(tensor), dim)).
+    /// Returns log(sum(exp(tensor), dim)), the reduced dimensions are kept with a size of 1.
+    ///
+    /// The maximum over the reduced dimensions is subtracted before taking the exponential so
+    /// that large inputs do not overflow.
+    pub fn log_sum_exp_keepdim<D: Dims>(&self, sum_dims: D) -> Result<Self> {
+        let sum_dims = sum_dims.to_indexes(self.shape(), "log-sum-exp")?;
+        let mut max = self.clone();
+        for &dim in sum_dims.iter() {
+            max = max.max_keepdim(dim)?
+        }
+        // Avoid nan values when the max is infinite, e.g. when all the values are -inf.
+        let finite = max.abs()?.lt(f64::INFINITY)?;
+        let max = finite.where_cond(&max, &max.zeros_like()?)?;
+        let sum = self.broadcast_sub(&max)?.exp()?.sum_keepdim(sum_dims)?;
+        sum.log()? + max
+    }
+
+    /// Returns log(sum(exp(tensor), dim)), see `log_sum_exp_keepdim`. The reduced dimensions
+    /// are squeezed.
     pub fn log_sum_exp<D: Dims>(&self, sum_dims: D) -> Result<Self> {
-        let exp = self.exp()?;
-        let sum = exp.sum(sum_dims)?;
-        sum.log()
+        let sum_dims = sum_dims.to_indexes(self.shape(), "log-sum-exp")?;
+        let lse = self.log_sum_exp_keepdim(sum_dims.as_slice())?;
+        let dims: Vec<usize> = self
+            .dims()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !sum_dims.contains(i))
+            .map(|(_, &d)| d)
+            .collect();
+        lse.reshape(dims)
+    }
+
+    /// Element-wise log(exp(self) + exp(rhs)), computed as `max + log(1 + exp(min - max))` to
+    /// avoid overflows.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let a = Tensor::new(&[0f32, 1000.], &Device::Cpu)?;
+    /// let b = Tensor::new(&[0f32, 1000.], &Device::Cpu)?;
+    /// let t = a.logaddexp(&b)?.to_vec1::<f32>()?;
+    /// assert!((t[0] - 2f32.ln()).abs() < 1e-6);
+    /// assert!((t[1] - 1000. - 2f32.ln()).abs() < 1e-3);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn logaddexp(&self, rhs: &Self) -> Result<Self> {
+        let max = self.maximum(rhs)?;
+        let min = self.minimum(rhs)?;
+        let finite = max.abs()?.lt(f64::INFINITY)?;
+        let max_ = finite.where_cond(&max, &max.zeros_like()?)?;
+        let log = ((min - &max_)?.exp()? + (&max - &max_)?.exp()?)?.log()?;
+        log + max_
     }
 
     /// Pointwise pow operation.
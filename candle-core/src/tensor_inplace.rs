@@ -0,0 +1,127 @@
+//! In-place variants of some elementwise operations.
+//!
+//! These reuse the tensor buffer when the result cannot be observed from any other tensor, this
+//! is only done for cpu tensors that are not part of a computation graph. Otherwise, e.g. when
+//! the storage is shared with a view or a clone or when the tensor is needed for backprop, the
+//! out-of-place operation is used and the tensor is replaced by the result.
+use crate::op::{Add, BinaryOpT, Div, Mul, Sub};
+use crate::{storage::Storage, CpuStorage, Layout, Result, Tensor, WithDType};
+
+fn binary_map_inplace<T: Copy>(lhs: &mut [T], rhs: &[T], rhs_l: &Layout, f: fn(T, T) -> T) {
+    match rhs_l.contiguous_offsets() {
+        Some((o1, o2)) => lhs
+            .iter_mut()
+            .zip(rhs[o1..o2].iter())
+            .for_each(|(l, &r)| *l = f(*l, r)),
+        None => lhs
+            .iter_mut()
+            .zip(rhs_l.strided_index())
+            .for_each(|(l, i)| *l = f(*l, rhs[i])),
+    }
+}
+
+fn affine_inplace<T: WithDType>(vs: &mut [T], mul: f64, add: f64) {
+    let mul = T::from_f64(mul);
+    let add = T::from_f64(add);
+    vs.iter_mut().for_each(|v| *v = *v * mul + add)
+}
+
+macro_rules! inplace_binary_op {
+    ($fn_name:ident, $op:ident, $out_of_place:ident) => {
+        /// In-place version of
+        #[doc = concat!("[`Tensor::", stringify!($out_of_place), "`],")]
+        /// both tensors must have the same shape.
+        pub fn $fn_name(&mut self, rhs: &Self) -> Result<()> {
+            if !self.binary_inplace::<$op>(rhs) {
+                *self = self.$out_of_place(rhs)?
+            }
+            Ok(())
+        }
+    };
+}
+
+impl Tensor {
+    // Returns false if the operation could not be applied in place.
+    fn binary_inplace<B: BinaryOpT>(&mut self, rhs: &Self) -> bool {
+        if self.shape() != rhs.shape() || self.dtype() != rhs.dtype() {
+            return false;
+        }
+        let el_count = self.elem_count();
+        let rhs_storage = rhs.storage();
+        let rhs_l = rhs.layout();
+        let lhs = match self.unique_storage_mut() {
+            Some(Storage::Cpu(lhs)) => lhs,
+            _ => return false,
+        };
+        let rhs = match &*rhs_storage {
+            Storage::Cpu(rhs) => rhs,
+            _ => return false,
+        };
+        match (lhs, rhs) {
+            (CpuStorage::U8(l), CpuStorage::U8(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::u8)
+            }
+            (CpuStorage::U32(l), CpuStorage::U32(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::u32)
+            }
+            (CpuStorage::I64(l), CpuStorage::I64(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::i64)
+            }
+            (CpuStorage::BF16(l), CpuStorage::BF16(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::bf16)
+            }
+            (CpuStorage::F16(l), CpuStorage::F16(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::f16)
+            }
+            (CpuStorage::F32(l), CpuStorage::F32(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::f32)
+            }
+            (CpuStorage::F64(l), CpuStorage::F64(r)) => {
+                binary_map_inplace(&mut l[..el_count], r, rhs_l, B::f64)
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    inplace_binary_op!(add_, Add, add);
+    inplace_binary_op!(sub_, Sub, sub);
+    inplace_binary_op!(mul_, Mul, mul);
+    inplace_binary_op!(div_, Div, div);
+
+    /// In-place version of [`Tensor::affine`], computes `self * mul + add`.
+    ///
+    /// ```rust
+    /// use candle_core::{Tensor, Device};
+    /// let mut a = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    /// a.affine_(2., 1.)?;
+    /// assert_eq!(a.to_vec1::<f32>()?, [3., 5., 7.]);
+    /// # Ok::<(), candle_core::Error>(())
+    /// ```
+    pub fn affine_(&mut self, mul: f64, add: f64) -> Result<()> {
+        let el_count = self.elem_count();
+        match self.unique_storage_mut() {
+            Some(Storage::Cpu(CpuStorage::U8(vs))) => affine_inplace(&mut vs[..el_count], mul, add),
+            Some(Storage::Cpu(CpuStorage::U32(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            Some(Storage::Cpu(CpuStorage::I64(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            Some(Storage::Cpu(CpuStorage::BF16(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            Some(Storage::Cpu(CpuStorage::F16(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            Some(Storage::Cpu(CpuStorage::F32(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            Some(Storage::Cpu(CpuStorage::F64(vs))) => {
+                affine_inplace(&mut vs[..el_count], mul, add)
+            }
+            _ => *self = self.affine(mul, add)?,
+        }
+        Ok(())
+    }
+}
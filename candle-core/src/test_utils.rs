@@ -1,3 +1,5 @@
+#[cfg(feature = "test-utils")]
+use crate::{bail, DType};
 use crate::{Result, Tensor};
 
 #[macro_export]
@@ -60,3 +62,85 @@ pub fn to_vec3_round(t: &Tensor, digits: i32) -> Result<Vec<Vec<Vec<f32>>>> {
         .collect();
     Ok(t)
 }
+
+// The largest absolute and relative errors between two tensors, together with the flat index
+// of the element that exceeds the tolerance by the largest margin if any.
+#[cfg(feature = "test-utils")]
+struct CloseReport {
+    max_abs: f64,
+    max_rel: f64,
+    worst: Option<(usize, f64, f64)>,
+}
+
+#[cfg(feature = "test-utils")]
+fn close_report(a: &Tensor, b: &Tensor, rtol: f64, atol: f64) -> Result<CloseReport> {
+    if a.shape() != b.shape() {
+        bail!("shape mismatch {:?} <> {:?}", a.shape(), b.shape())
+    }
+    if a.dtype() != b.dtype() {
+        bail!("dtype mismatch {:?} <> {:?}", a.dtype(), b.dtype())
+    }
+    let a = a.flatten_all()?.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+    let b = b.flatten_all()?.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+    let mut report = CloseReport {
+        max_abs: 0.,
+        max_rel: 0.,
+        worst: None,
+    };
+    let mut worst_excess = 0f64;
+    for (i, (&a, &b)) in a.iter().zip(b.iter()).enumerate() {
+        let (abs, rel, excess) = if a.is_finite() && b.is_finite() {
+            let abs = (a - b).abs();
+            let rel = if abs == 0. { 0. } else { abs / b.abs() };
+            (abs, rel, abs - (atol + rtol * b.abs()))
+        } else if a == b {
+            // Infinite values are only close to the same infinity.
+            (0., 0., 0.)
+        } else {
+            // Nan values are never close, including to another nan.
+            (f64::INFINITY, f64::INFINITY, f64::INFINITY)
+        };
+        report.max_abs = report.max_abs.max(abs);
+        report.max_rel = report.max_rel.max(rel);
+        if excess > worst_excess {
+            worst_excess = excess;
+            report.worst = Some((i, a, b));
+        }
+    }
+    Ok(report)
+}
+
+/// Returns true if `a` and `b` have the same shape and dtype and if all their elements satisfy
+/// `|a - b| <= atol + rtol * |b|`. Infinite values are only close to the same infinity and nan
+/// values are never considered close.
+#[cfg(feature = "test-utils")]
+pub fn allclose(a: &Tensor, b: &Tensor, rtol: f64, atol: f64) -> Result<bool> {
+    if a.shape() != b.shape() || a.dtype() != b.dtype() {
+        return Ok(false);
+    }
+    Ok(close_report(a, b, rtol, atol)?.worst.is_none())
+}
+
+/// Panics if `a` and `b` are not close as per [`allclose`], the panic message contains the
+/// largest absolute and relative errors as well as the index and values of the element that
+/// exceeds the tolerance the most.
+#[cfg(feature = "test-utils")]
+#[track_caller]
+pub fn assert_close(a: &Tensor, b: &Tensor, rtol: f64, atol: f64) {
+    let report = match close_report(a, b, rtol, atol) {
+        Ok(report) => report,
+        Err(err) => panic!("tensors are not close: {err}"),
+    };
+    if let Some((index, va, vb)) = report.worst {
+        let mut index = index;
+        let mut nd_index = vec![0; a.rank()];
+        for (i, &d) in a.dims().iter().enumerate().rev() {
+            nd_index[i] = index % d;
+            index /= d;
+        }
+        panic!(
+            "tensors are not close (rtol={rtol}, atol={atol}): max abs error {:e}, max rel error {:e}, worst element at {nd_index:?}: {va} <> {vb}",
+            report.max_abs, report.max_rel,
+        )
+    }
+}
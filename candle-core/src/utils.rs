@@ -1,4 +1,8 @@
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DETERMINISTIC_REDUCTIONS: AtomicBool = AtomicBool::new(false);
+static CHECK_NAN: AtomicBool = AtomicBool::new(false);
 
 pub fn get_num_threads() -> usize {
     // Respond to the same environment variable as rayon.
@@ -11,6 +15,37 @@ pub fn get_num_threads() -> usize {
     }
 }
 
+/// Whether the cpu sum reductions, and so the mean ones, use a fixed tree-reduction order so that
+/// the results are bitwise reproducible and do not depend on the layout of the reduced tensor.
+/// This can be enabled with [`set_deterministic_reductions`] or by setting the
+/// `CANDLE_DETERMINISTIC_REDUCTIONS` environment variable. The other backends are not affected.
+pub fn deterministic_reductions() -> bool {
+    static FROM_ENV: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    let from_env =
+        *FROM_ENV.get_or_init(|| match std::env::var("CANDLE_DETERMINISTIC_REDUCTIONS") {
+            Ok(s) => !s.is_empty() && s != "0",
+            Err(_) => false,
+        });
+    from_env || DETERMINISTIC_REDUCTIONS.load(Ordering::Relaxed)
+}
+
+pub fn set_deterministic_reductions(b: bool) {
+    DETERMINISTIC_REDUCTIONS.store(b, Ordering::Relaxed)
+}
+
+/// Whether the outputs of the ops tracked in the autograd graph get checked for nan and inf
+/// values, see [`set_check_nan`].
+pub fn check_nan() -> bool {
+    CHECK_NAN.load(Ordering::Relaxed)
+}
+
+/// Enables or disables the nan and inf checks for the whole process. When enabled, an op that is
+/// tracked in the autograd graph and produces a nan or inf value returns an error with the op
+/// name and the shapes of its inputs. This is slow and is only intended for debugging.
+pub fn set_check_nan(b: bool) {
+    CHECK_NAN.store(b, Ordering::Relaxed)
+}
+
 pub fn has_accelerate() -> bool {
     cfg!(feature = "accelerate")
 }
@@ -27,6 +62,10 @@ pub fn metal_is_available() -> bool {
     cfg!(feature = "metal")
 }
 
+pub fn wgpu_is_available() -> bool {
+    cfg!(feature = "wgpu")
+}
+
 pub fn with_avx() -> bool {
     cfg!(target_feature = "avx")
 }
@@ -0,0 +1,234 @@
+use crate::Result;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use wgpu::util::DeviceExt;
+
+use super::kernels::WORKGROUP_SIZE;
+use super::WgpuError;
+
+/// Unique identifier for wgpu devices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceId(usize);
+
+impl DeviceId {
+    pub(crate) fn new() -> Self {
+        use std::sync::atomic;
+        static COUNTER: atomic::AtomicUsize = atomic::AtomicUsize::new(1);
+        Self(COUNTER.fetch_add(1, atomic::Ordering::Relaxed))
+    }
+}
+
+type Pipelines = Arc<Mutex<HashMap<String, Arc<wgpu::ComputePipeline>>>>;
+
+#[derive(Clone)]
+pub struct WgpuDevice {
+    /// Unique identifier, two devices created from the same adapter do not share their queue so
+    /// they are not considered to be the same device.
+    pub(crate) id: DeviceId,
+    /// The index of the adapter this device was created from.
+    pub(crate) ordinal: usize,
+    pub(crate) device: Arc<wgpu::Device>,
+    /// Single queue for the whole device, submissions are executed in order.
+    pub(crate) queue: Arc<wgpu::Queue>,
+    /// The compiled pipelines indexed by the kernel name, the kernels are compiled on first use.
+    pub(crate) pipelines: Pipelines,
+    /// Random numbers are generated on the host and then copied to the device.
+    pub(crate) rng: Arc<Mutex<rand::rngs::StdRng>>,
+}
+
+impl std::fmt::Debug for WgpuDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WgpuDevice({:?})", self.id)
+    }
+}
+
+impl WgpuDevice {
+    /// Creates a device from a wgpu adapter. This is the entry point on the web where adapters
+    /// can only be requested asynchronously.
+    pub async fn from_adapter(adapter: &wgpu::Adapter, ordinal: usize) -> Result<Self> {
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("candle"),
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+            memory_hints: wgpu::MemoryHints::Performance,
+        };
+        let (device, queue) = adapter
+            .request_device(&descriptor, None)
+            .await
+            .map_err(|e| WgpuError::Message(format!("cannot create device: {e}")))?;
+        Ok(Self {
+            id: DeviceId::new(),
+            ordinal,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            pipelines: Arc::new(Mutex::new(HashMap::new())),
+            rng: Arc::new(Mutex::new(rand::rngs::StdRng::from_entropy())),
+        })
+    }
+
+    pub fn id(&self) -> DeviceId {
+        self.id
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub(crate) fn create_buffer(&self, size_in_bytes: usize) -> Arc<wgpu::Buffer> {
+        // Empty bindings are not allowed so always allocate at least one element.
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size_in_bytes.max(4) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Arc::new(buffer)
+    }
+
+    pub(crate) fn create_buffer_init(&self, data: &[u8]) -> Arc<wgpu::Buffer> {
+        if data.is_empty() {
+            return self.create_buffer(0);
+        }
+        let buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: data,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+            });
+        Arc::new(buffer)
+    }
+
+    fn pipeline(
+        &self,
+        name: &str,
+        source: impl FnOnce() -> String,
+    ) -> Result<Arc<wgpu::ComputePipeline>> {
+        let mut pipelines = self.pipelines.lock().map_err(WgpuError::from)?;
+        if let Some(pipeline) = pipelines.get(name) {
+            return Ok(pipeline.clone());
+        }
+        let module = self
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(name),
+                source: wgpu::ShaderSource::Wgsl(source().into()),
+            });
+        let pipeline = self
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(name),
+                layout: None,
+                module: &module,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            });
+        let pipeline = Arc::new(pipeline);
+        pipelines.insert(name.to_string(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Runs the kernel `name` with one thread per element for `numel` elements. The first buffer
+    /// is the destination, `params` gets bound after it and the inputs follow.
+    pub(crate) fn dispatch(
+        &self,
+        name: &str,
+        source: impl FnOnce() -> String,
+        dst: &wgpu::Buffer,
+        inputs: &[&wgpu::Buffer],
+        params: &[u32],
+        numel: usize,
+    ) -> Result<()> {
+        if numel == 0 {
+            return Ok(());
+        }
+        let pipeline = self.pipeline(name, source)?;
+        let params: Vec<u8> = params.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let params = self.create_buffer_init(&params);
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: dst.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: params.as_entire_binding(),
+            },
+        ];
+        for (idx, input) in inputs.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: idx as u32 + 2,
+                resource: input.as_entire_binding(),
+            })
+        }
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+        let groups = numel.div_ceil(WORKGROUP_SIZE);
+        let max_groups = self.device.limits().max_compute_workgroups_per_dimension as usize;
+        let groups_x = groups.min(max_groups);
+        let groups_y = groups.div_ceil(groups_x);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some(name) });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some(name),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(groups_x as u32, groups_y as u32, 1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+        Ok(())
+    }
+
+    /// Copies the content of a buffer back to the host, this blocks until all the previously
+    /// submitted work has completed.
+    pub(crate) fn read_buffer(
+        &self,
+        buffer: &wgpu::Buffer,
+        size_in_bytes: usize,
+    ) -> Result<Vec<u8>> {
+        if size_in_bytes == 0 {
+            return Ok(vec![]);
+        }
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size: size_in_bytes as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size_in_bytes as u64);
+        self.queue.submit(Some(encoder.finish()));
+        let slice = staging.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |r| {
+            let _ = sender.send(r);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .map_err(|e| WgpuError::Message(e.to_string()))?
+            .map_err(|e| WgpuError::Message(e.to_string()))?;
+        let data = slice.get_mapped_range().to_vec();
+        staging.unmap();
+        Ok(data)
+    }
+}
@@ -0,0 +1,338 @@
+//! WGSL sources for the compute shaders used by the wgpu backend.
+//!
+//! The shaders are generated on the fly for each op and dtype and compiled once per device. All
+//! the kernels use one thread per output element, binding 0 is the destination buffer, binding 1
+//! holds the `u32` parameters and the following bindings are the inputs. For elementwise kernels
+//! the parameters are laid out as follows:
+//! `[numel, rank, p0, p1, dims[rank], (offset, strides[rank]) for each input]`
+//! where `p0` and `p1` are op specific scalars, e.g. the bits of the `f32` values used by affine.
+use crate::{DType, Layout, Shape};
+
+pub(crate) const WORKGROUP_SIZE: usize = 64;
+
+/// The WGSL type used to store a dtype, `U8` values are widened to `u32` as WGSL has no 8 bits
+/// integer type.
+pub(crate) fn wgsl_type(dtype: DType) -> Option<&'static str> {
+    match dtype {
+        DType::F32 => Some("f32"),
+        DType::U32 | DType::U8 => Some("u32"),
+        _ => None,
+    }
+}
+
+const STRIDED_INDEX: &str = r"
+fn strided_index(i: u32, input: u32) -> u32 {
+    let rank = params[1];
+    let base = 4u + rank + input * (rank + 1u);
+    var idx = i;
+    var res = params[base];
+    for (var d = rank; d > 0u; d = d - 1u) {
+        let dim = params[3u + d];
+        res = res + (idx % dim) * params[base + d];
+        idx = idx / dim;
+    }
+    return res;
+}
+";
+
+// Abramowitz and Stegun formula 7.1.26, the maximum error is 1.5e-7.
+const ERF: &str = r"
+fn erf_f32(v: f32) -> f32 {
+    let x = abs(v);
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let p = ((((1.061405429 * t - 1.453152027) * t + 1.421413741) * t - 0.284496736) * t
+        + 0.254829592) * t;
+    return sign(v) * (1.0 - p * exp(-x * x));
+}
+";
+
+/// Builds a full shader from the destination type, the inputs as `(name, type)` pairs, some
+/// helper functions and the body of the main function which has access to the thread index `i`.
+pub(crate) fn shader(dst: &str, inputs: &[(&str, &str)], helpers: &str, body: &str) -> String {
+    let mut src = format!(
+        "@group(0) @binding(0) var<storage, read_write> dst: array<{dst}>;\n\
+         @group(0) @binding(1) var<storage, read> params: array<u32>;\n"
+    );
+    for (idx, (name, ty)) in inputs.iter().enumerate() {
+        src.push_str(&format!(
+            "@group(0) @binding({}) var<storage, read> {name}: array<{ty}>;\n",
+            idx + 2
+        ));
+    }
+    src.push_str(helpers);
+    src.push_str(&format!(
+        "
+@compute @workgroup_size({WORKGROUP_SIZE})
+fn main(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {{
+    let i = gid.x + gid.y * nwg.x * {WORKGROUP_SIZE}u;
+    if (i >= params[0]) {{
+        return;
+    }}
+    {body}
+}}
+"
+    ));
+    src
+}
+
+/// Shader for the elementwise kernels, `inputs` are indexed through their layouts and the values
+/// are available as `x0`, `x1`, ... in `expr`.
+pub(crate) fn elementwise(dst: &str, inputs: &[(&str, &str)], expr: &str) -> String {
+    let helpers = if expr.contains("erf_f32") {
+        format!("{STRIDED_INDEX}{ERF}")
+    } else {
+        STRIDED_INDEX.to_string()
+    };
+    let mut body = String::new();
+    for (idx, (name, _)) in inputs.iter().enumerate() {
+        body.push_str(&format!(
+            "let x{idx} = {name}[strided_index(i, {idx}u)];\n    "
+        ));
+    }
+    body.push_str(&format!(
+        "let p0 = bitcast<f32>(params[2]);\n    \
+         let p1 = bitcast<f32>(params[3]);\n    \
+         dst[i] = {expr};"
+    ));
+    shader(dst, inputs, &helpers, &body)
+}
+
+/// The parameters for the elementwise kernels, see the module documentation for the layout.
+pub(crate) fn elementwise_params(shape: &Shape, layouts: &[&Layout], p0: u32, p1: u32) -> Vec<u32> {
+    let dims = shape.dims();
+    let mut params = vec![shape.elem_count() as u32, dims.len() as u32, p0, p1];
+    params.extend(dims.iter().map(|&d| d as u32));
+    for layout in layouts {
+        params.push(layout.start_offset() as u32);
+        params.extend(layout.stride().iter().map(|&s| s as u32));
+    }
+    params
+}
+
+/// The WGSL expression for a unary op applied to `x0`, only float inputs are supported.
+pub(crate) fn unary_expr(kernel: &str) -> Option<&'static str> {
+    let expr = match kernel {
+        "uexp" => "exp(x0)",
+        "ulog" => "log(x0)",
+        "usin" => "sin(x0)",
+        "ucos" => "cos(x0)",
+        "utanh" => "tanh(x0)",
+        "uneg" => "-x0",
+        "urecip" => "1.0 / x0",
+        "usqr" => "x0 * x0",
+        "usqrt" => "sqrt(x0)",
+        "uabs" => "abs(x0)",
+        "uceil" => "ceil(x0)",
+        "ufloor" => "floor(x0)",
+        // Rounds half-way cases away from zero like f32::round.
+        "uround" => "sign(x0) * floor(abs(x0) + 0.5)",
+        "usign" => "sign(x0)",
+        "urelu" => "max(x0, 0.0)",
+        "usilu" => "x0 / (1.0 + exp(-x0))",
+        "ugelu" => "0.5 * x0 * (1.0 + tanh(0.7978845608 * (x0 + 0.044715 * x0 * x0 * x0)))",
+        "uerf" => "erf_f32(x0)",
+        "ugelu_erf" => "0.5 * x0 * (1.0 + erf_f32(x0 * 0.7071067811865476))",
+        _ => return None,
+    };
+    Some(expr)
+}
+
+/// The WGSL expression for a binary op applied to `x0` and `x1`.
+pub(crate) fn binary_expr(kernel: &str) -> Option<&'static str> {
+    let expr = match kernel {
+        "badd" => "x0 + x1",
+        "bsub" => "x0 - x1",
+        "bmul" => "x0 * x1",
+        "bdiv" => "x0 / x1",
+        "bminimum" => "min(x0, x1)",
+        "bmaximum" => "max(x0, x1)",
+        _ => return None,
+    };
+    Some(expr)
+}
+
+/// The WGSL expression converting `x0` between two supported dtypes.
+pub(crate) fn cast_expr(src: DType, dst: DType) -> &'static str {
+    match (src, dst) {
+        (DType::F32, DType::U32) => "u32(x0)",
+        (DType::F32, DType::U8) => "min(u32(x0), 255u)",
+        (DType::U32 | DType::U8, DType::F32) => "f32(x0)",
+        (DType::U32, DType::U8) => "x0 & 255u",
+        _ => "x0",
+    }
+}
+
+/// Reduction over some dimensions, the parameters are
+/// `[numel, rank, reduce_count, 0, dims[rank], offset, strides[rank], reduced[rank]]`.
+pub(crate) fn reduce(dst: &str, src: &str, op: crate::op::ReduceOp) -> String {
+    use crate::op::ReduceOp;
+    let (update, result) = match op {
+        ReduceOp::Sum => ("acc = acc + v;", "acc"),
+        ReduceOp::Min => ("if (j == 0u || v < acc) { acc = v; }", "acc"),
+        ReduceOp::Max => ("if (j == 0u || v > acc) { acc = v; }", "acc"),
+        ReduceOp::ArgMin => (
+            "if (j == 0u || v < acc) { acc = v; acc_idx = j; }",
+            "acc_idx",
+        ),
+        ReduceOp::ArgMax => (
+            "if (j == 0u || v > acc) { acc = v; acc_idx = j; }",
+            "acc_idx",
+        ),
+    };
+    let body = format!(
+        "let rank = params[1];
+    let base = 4u + rank;
+    var idx = i;
+    var start = params[base];
+    for (var d = rank; d > 0u; d = d - 1u) {{
+        if (params[base + rank + d] == 0u) {{
+            let dim = params[3u + d];
+            start = start + (idx % dim) * params[base + d];
+            idx = idx / dim;
+        }}
+    }}
+    var acc = {src}(0);
+    var acc_idx = 0u;
+    for (var j = 0u; j < params[2]; j = j + 1u) {{
+        var jdx = j;
+        var s = start;
+        for (var d = rank; d > 0u; d = d - 1u) {{
+            if (params[base + rank + d] == 1u) {{
+                let dim = params[3u + d];
+                s = s + (jdx % dim) * params[base + d];
+                jdx = jdx / dim;
+            }}
+        }}
+        let v = src[s];
+        {update}
+    }}
+    dst[i] = {result};"
+    );
+    shader(dst, &[("src", src)], "", &body)
+}
+
+/// Batched matmul, the parameters are
+/// `[numel, m, n, k, lhs_offset, lhs_batch_stride, lhs_stride_m, lhs_stride_k, rhs_offset,
+/// rhs_batch_stride, rhs_stride_k, rhs_stride_n]`.
+pub(crate) fn matmul(ty: &str) -> String {
+    let body = format!(
+        "let m = params[1];
+    let n = params[2];
+    let b = i / (m * n);
+    let row = (i % (m * n)) / n;
+    let col = i % n;
+    let l = params[4] + b * params[5] + row * params[6];
+    let r = params[8] + b * params[9] + col * params[11];
+    var acc = {ty}(0);
+    for (var k = 0u; k < params[3]; k = k + 1u) {{
+        acc = acc + lhs[l + k * params[7]] * rhs[r + k * params[10]];
+    }}
+    dst[i] = acc;"
+    );
+    shader(ty, &[("lhs", ty), ("rhs", ty)], "", &body)
+}
+
+/// Softmax over the last dimension of a contiguous input, one thread per row. The parameters
+/// are `[rows, dim, offset]`.
+pub(crate) fn softmax_last_dim() -> String {
+    let body = "let dim = params[1];
+    let start = params[2] + i * dim;
+    var mx = src[start];
+    for (var j = 1u; j < dim; j = j + 1u) {
+        mx = max(mx, src[start + j]);
+    }
+    var sum = 0.0;
+    for (var j = 0u; j < dim; j = j + 1u) {
+        let e = exp(src[start + j] - mx);
+        dst[i * dim + j] = e;
+        sum = sum + e;
+    }
+    for (var j = 0u; j < dim; j = j + 1u) {
+        dst[i * dim + j] = dst[i * dim + j] / sum;
+    }";
+    shader("f32", &[("src", "f32")], "", body)
+}
+
+/// Selects slices along a dimension of a contiguous input, the parameters are
+/// `[numel, left, src_dim, ids_dim, right, src_offset, ids_offset]`.
+pub(crate) fn index_select(ty: &str) -> String {
+    let body = "let right = params[4];
+    let ids_dim = params[3];
+    let r = i % right;
+    let j = (i / right) % ids_dim;
+    let l = i / (right * ids_dim);
+    let id = ids[params[6] + j];
+    dst[i] = src[params[5] + (l * params[2] + id) * right + r];";
+    shader(ty, &[("src", ty), ("ids", "u32")], "", body)
+}
+
+/// Gathers values along a dimension of a contiguous input, the parameters are the same as for
+/// [`index_select`] with `ids` having the same shape as the output.
+pub(crate) fn gather(ty: &str) -> String {
+    let body = "let right = params[4];
+    let ids_dim = params[3];
+    let r = i % right;
+    let l = i / (right * ids_dim);
+    let id = ids[params[6] + i];
+    dst[i] = src[params[5] + (l * params[2] + id) * right + r];";
+    shader(ty, &[("src", ty), ("ids", "u32")], "", body)
+}
+
+/// Adds the source slices selected by `ids` to the base tensor, each thread loops over the
+/// indexes rather than using atomics. The parameters are
+/// `[numel, left, dst_dim, ids_dim, right, base_offset, ids_offset, src_offset]`.
+pub(crate) fn index_add(ty: &str) -> String {
+    let body = "let right = params[4];
+    let dst_dim = params[2];
+    let ids_dim = params[3];
+    let r = i % right;
+    let d = (i / right) % dst_dim;
+    let l = i / (right * dst_dim);
+    var acc = base[params[5] + i];
+    for (var j = 0u; j < ids_dim; j = j + 1u) {
+        if (ids[params[6] + j] == d) {
+            acc = acc + src[params[7] + (l * ids_dim + j) * right + r];
+        }
+    }
+    dst[i] = acc;";
+    shader(ty, &[("base", ty), ("ids", "u32"), ("src", ty)], "", body)
+}
+
+/// Same as [`index_add`] but with `ids` having the same shape as the source.
+pub(crate) fn scatter_add(ty: &str) -> String {
+    let body = "let right = params[4];
+    let dst_dim = params[2];
+    let src_dim = params[3];
+    let r = i % right;
+    let d = (i / right) % dst_dim;
+    let l = i / (right * dst_dim);
+    var acc = base[params[5] + i];
+    for (var j = 0u; j < src_dim; j = j + 1u) {
+        let s = (l * src_dim + j) * right + r;
+        if (ids[params[6] + s] == d) {
+            acc = acc + src[params[7] + s];
+        }
+    }
+    dst[i] = acc;";
+    shader(ty, &[("base", ty), ("ids", "u32"), ("src", ty)], "", body)
+}
+
+/// Copies a strided source to a contiguous destination starting at `p0`, the parameters are
+/// the elementwise ones.
+pub(crate) fn copy_strided(ty: &str) -> String {
+    shader(
+        ty,
+        &[("src", ty)],
+        STRIDED_INDEX,
+        "dst[params[2] + i] = src[strided_index(i, 0u)];",
+    )
+}
+
+/// 2D copy, the parameters are `[numel, d2, src_stride1, dst_stride1, src_offset, dst_offset]`.
+pub(crate) fn copy2d(ty: &str) -> String {
+    let body = "let d2 = params[1];
+    let r = i / d2;
+    let c = i % d2;
+    dst[params[5] + r * params[3] + c] = src[params[4] + r * params[2] + c];";
+    shader(ty, &[("src", ty)], "", body)
+}
@@ -0,0 +1,757 @@
+//! A backend running compute shaders through [wgpu](https://wgpu.rs), this makes it possible
+//! to use Vulkan, Metal, DX12, OpenGL or WebGPU adapters. It is enabled with the `wgpu` feature.
+//!
+//! The backend lives in `candle-core` next to the cuda and metal ones rather than in a separate
+//! crate: `Device` and `Storage` are enums dispatching statically to each backend, there is no
+//! extension point that would let another crate register a device. Behind the feature flag it
+//! does not add any dependency to the default build.
+//!
+//! Only the `F32`, `U32` and `U8` dtypes are supported, `U8` values are stored as `u32` on the
+//! device. The supported ops are:
+//! - unary ops on `F32`: `exp`, `log`, `sin`, `cos`, `tanh`, `neg`, `recip`, `sqr`, `sqrt`,
+//!   `abs`, `ceil`, `floor`, `round`, `sign`, `relu`, `silu`, `gelu`, `gelu_erf`, `erf`, as well
+//!   as `affine`, `powf` and `elu`.
+//! - binary ops: `add`, `sub`, `mul`, `div`, `minimum`, `maximum`, the comparisons and
+//!   `where_cond`.
+//! - dtype conversions between the supported dtypes.
+//! - reductions: `sum`, `min`, `max`, `argmin`, `argmax`.
+//! - `matmul` (batched), `softmax_last_dim` through `candle_nn::ops::softmax_last_dim`.
+//! - indexing: `index_select`, `gather`, `scatter_add`, `index_add`, and the copies used by
+//!   `contiguous`, `cat` and `slice_assign`.
+//! - `rand` and `randn` for `F32`, the values are sampled on the host using the device rng.
+//!
+//! The other ops, in particular `conv1d`, `conv2d`, their transposed versions, `avg_pool2d`,
+//! `max_pool2d` and the `upsample_nearest` ops, return an error saying that they are not
+//! supported on wgpu. Custom ops have to provide a `wgpu_fwd` implementation.
+//!
+//! Reading a tensor back to the host blocks until the device is idle, on the web this is not
+//! possible so the device has to be created with [`WgpuDevice::from_adapter`] and the data
+//! should be retrieved from the underlying buffers asynchronously.
+use crate::backend::{BackendDevice, BackendStorage};
+use crate::conv::{ParamsConv1D, ParamsConv2D, ParamsConvTranspose1D, ParamsConvTranspose2D};
+use crate::op::{BinaryOpT, CmpOp, ReduceOp, UnaryOpT};
+use crate::{CpuStorage, DType, Error, Layout, Result, Shape};
+use std::sync::{Arc, PoisonError};
+
+mod device;
+mod kernels;
+pub use device::{DeviceId, WgpuDevice};
+
+/// wgpu related errors
+#[derive(thiserror::Error, Debug)]
+pub enum WgpuError {
+    #[error("{0}")]
+    Message(String),
+    #[error("lock poisoned: {0}")]
+    LockError(String),
+}
+
+impl From<String> for WgpuError {
+    fn from(e: String) -> Self {
+        WgpuError::Message(e)
+    }
+}
+
+impl<T> From<PoisonError<T>> for WgpuError {
+    fn from(p: PoisonError<T>) -> Self {
+        WgpuError::LockError(p.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WgpuStorage {
+    /// The actual buffer containing the data, all the supported dtypes use 4 bytes per element.
+    buffer: Arc<wgpu::Buffer>,
+    /// a reference to the device owning this buffer
+    device: WgpuDevice,
+    /// The count of allocated elements in the buffer
+    count: usize,
+    /// The dtype is kept since buffers are untyped.
+    dtype: DType,
+}
+
+fn wgsl_type(dtype: DType, op: &'static str) -> Result<&'static str> {
+    match kernels::wgsl_type(dtype) {
+        Some(ty) => Ok(ty),
+        None => Err(crate::Error::UnsupportedDTypeForOp(dtype, op).bt()),
+    }
+}
+
+fn unsupported<T>(op: &'static str) -> Result<T> {
+    Err(WgpuError::Message(format!("{op} is not supported on wgpu")).into())
+}
+
+impl WgpuStorage {
+    pub fn new(buffer: Arc<wgpu::Buffer>, device: WgpuDevice, count: usize, dtype: DType) -> Self {
+        Self {
+            buffer,
+            device,
+            count,
+            dtype,
+        }
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    fn alloc(device: &WgpuDevice, count: usize, dtype: DType, op: &'static str) -> Result<Self> {
+        wgsl_type(dtype, op)?;
+        let buffer = device.create_buffer(count * 4);
+        Ok(Self::new(buffer, device.clone(), count, dtype))
+    }
+
+    /// Returns a storage where the data described by `layout` is contiguous together with the
+    /// offset at which the data starts.
+    fn contiguous(&self, layout: &Layout) -> Result<(Self, usize)> {
+        if layout.is_contiguous() {
+            return Ok((self.clone(), layout.start_offset()));
+        }
+        let mut dst = Self::alloc(
+            &self.device,
+            layout.shape().elem_count(),
+            self.dtype,
+            "copy",
+        )?;
+        self.copy_strided_src(&mut dst, 0, layout)?;
+        Ok((dst, 0))
+    }
+
+    /// The index tensors are read as `u32` on the device.
+    fn ids(&self, layout: &Layout, op: &'static str) -> Result<(Self, usize)> {
+        match self.dtype {
+            DType::U32 | DType::U8 => self.contiguous(layout),
+            dtype => Err(crate::Error::UnsupportedDTypeForOp(dtype, op).bt()),
+        }
+    }
+
+    fn elementwise(
+        &self,
+        name: &str,
+        layout: &Layout,
+        expr: &str,
+        p0: f32,
+        p1: f32,
+    ) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "elementwise")?;
+        let shape = layout.shape();
+        let dst = Self::alloc(&self.device, shape.elem_count(), self.dtype, "elementwise")?;
+        let params = kernels::elementwise_params(shape, &[layout], p0.to_bits(), p1.to_bits());
+        self.device.dispatch(
+            &format!("{name}_{ty}"),
+            || kernels::elementwise(ty, &[("src", ty)], expr),
+            &dst.buffer,
+            &[&self.buffer],
+            &params,
+            shape.elem_count(),
+        )?;
+        Ok(dst)
+    }
+
+    /// Softmax over the last dimension, this is used by the custom op in `candle-nn`.
+    pub fn softmax_last_dim(&self, layout: &Layout) -> Result<Self> {
+        if self.dtype != DType::F32 {
+            Err(crate::Error::UnsupportedDTypeForOp(self.dtype, "softmax").bt())?
+        }
+        let (src, offset) = self.contiguous(layout)?;
+        let shape = layout.shape();
+        let dim = shape.dims().last().copied().unwrap_or(1);
+        let rows = shape.elem_count().checked_div(dim).unwrap_or(0);
+        let dst = Self::alloc(&self.device, shape.elem_count(), self.dtype, "softmax")?;
+        self.device.dispatch(
+            "softmax_last_dim_f32",
+            kernels::softmax_last_dim,
+            &dst.buffer,
+            &[&src.buffer],
+            &[rows as u32, dim as u32, offset as u32],
+            rows,
+        )?;
+        Ok(dst)
+    }
+
+    fn to_vec<T: Copy>(&self, f: impl Fn([u8; 4]) -> T) -> Result<Vec<T>> {
+        let data = self.device.read_buffer(&self.buffer, self.count * 4)?;
+        Ok(data
+            .chunks_exact(4)
+            .map(|c| f([c[0], c[1], c[2], c[3]]))
+            .collect())
+    }
+}
+
+impl BackendStorage for WgpuStorage {
+    type Device = WgpuDevice;
+
+    fn try_clone(&self, _: &Layout) -> Result<Self> {
+        Ok(self.clone())
+    }
+
+    fn dtype(&self) -> DType {
+        self.dtype
+    }
+
+    fn device(&self) -> &Self::Device {
+        &self.device
+    }
+
+    fn to_cpu_storage(&self) -> Result<CpuStorage> {
+        match self.dtype {
+            DType::F32 => Ok(CpuStorage::F32(self.to_vec(f32::from_le_bytes)?)),
+            DType::U32 => Ok(CpuStorage::U32(self.to_vec(u32::from_le_bytes)?)),
+            DType::U8 => Ok(CpuStorage::U8(
+                self.to_vec(|b| u32::from_le_bytes(b) as u8)?,
+            )),
+            dtype => Err(crate::Error::UnsupportedDTypeForOp(dtype, "to_cpu_storage").bt()),
+        }
+    }
+
+    fn affine(&self, layout: &Layout, mul: f64, add: f64) -> Result<Self> {
+        let expr = match self.dtype {
+            DType::F32 => "x0 * p0 + p1",
+            _ => "u32(f32(x0) * p0 + p1)",
+        };
+        self.elementwise("affine", layout, expr, mul as f32, add as f32)
+    }
+
+    fn powf(&self, layout: &Layout, e: f64) -> Result<Self> {
+        if self.dtype != DType::F32 {
+            Err(crate::Error::UnsupportedDTypeForOp(self.dtype, "powf").bt())?
+        }
+        self.elementwise("powf", layout, "pow(x0, p0)", e as f32, 0.)
+    }
+
+    fn elu(&self, layout: &Layout, alpha: f64) -> Result<Self> {
+        if self.dtype != DType::F32 {
+            Err(crate::Error::UnsupportedDTypeForOp(self.dtype, "elu").bt())?
+        }
+        let expr = "select(p0 * (exp(x0) - 1.0), x0, x0 >= 0.0)";
+        self.elementwise("elu", layout, expr, alpha as f32, 0.)
+    }
+
+    fn reduce_op(&self, op: ReduceOp, layout: &Layout, sum_dims: &[usize]) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, op.name())?;
+        let src_dims = layout.dims();
+        let mut dst_dims = src_dims.to_vec();
+        let mut reduced = vec![0u32; src_dims.len()];
+        let mut reduce_count = 1;
+        for &dim in sum_dims {
+            dst_dims[dim] = 1;
+            reduced[dim] = 1;
+            reduce_count *= src_dims[dim];
+        }
+        let dst_el = Shape::from(dst_dims).elem_count();
+        let (dtype, dst_ty) = match op {
+            ReduceOp::ArgMin | ReduceOp::ArgMax => (DType::U32, "u32"),
+            ReduceOp::Sum | ReduceOp::Min | ReduceOp::Max => (self.dtype, ty),
+        };
+        let dst = Self::alloc(&self.device, dst_el, dtype, op.name())?;
+        let mut params = vec![dst_el as u32, src_dims.len() as u32, reduce_count as u32, 0];
+        params.extend(src_dims.iter().map(|&d| d as u32));
+        params.push(layout.start_offset() as u32);
+        params.extend(layout.stride().iter().map(|&s| s as u32));
+        params.extend(reduced);
+        self.device.dispatch(
+            &format!("reduce_{}_{ty}", op.name()),
+            || kernels::reduce(dst_ty, ty, op),
+            &dst.buffer,
+            &[&self.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn cmp(&self, op: CmpOp, rhs: &Self, lhs_l: &Layout, rhs_l: &Layout) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "cmp")?;
+        let shape = lhs_l.shape();
+        let (name, op_str) = match op {
+            CmpOp::Eq => ("eq", "=="),
+            CmpOp::Ne => ("ne", "!="),
+            CmpOp::Le => ("le", "<="),
+            CmpOp::Ge => ("ge", ">="),
+            CmpOp::Lt => ("lt", "<"),
+            CmpOp::Gt => ("gt", ">"),
+        };
+        let dst = Self::alloc(&self.device, shape.elem_count(), DType::U8, "cmp")?;
+        let params = kernels::elementwise_params(shape, &[lhs_l, rhs_l], 0, 0);
+        let expr = format!("select(0u, 1u, x0 {op_str} x1)");
+        self.device.dispatch(
+            &format!("cmp_{name}_{ty}"),
+            || kernels::elementwise("u32", &[("lhs", ty), ("rhs", ty)], &expr),
+            &dst.buffer,
+            &[&self.buffer, &rhs.buffer],
+            &params,
+            shape.elem_count(),
+        )?;
+        Ok(dst)
+    }
+
+    fn to_dtype(&self, layout: &Layout, dtype: DType) -> Result<Self> {
+        let src_ty = wgsl_type(self.dtype, "to_dtype")?;
+        let dst_ty = wgsl_type(dtype, "to_dtype")?;
+        let shape = layout.shape();
+        let dst = Self::alloc(&self.device, shape.elem_count(), dtype, "to_dtype")?;
+        let params = kernels::elementwise_params(shape, &[layout], 0, 0);
+        let expr = kernels::cast_expr(self.dtype, dtype);
+        self.device.dispatch(
+            &format!("cast_{}_{}", self.dtype.as_str(), dtype.as_str()),
+            || kernels::elementwise(dst_ty, &[("src", src_ty)], expr),
+            &dst.buffer,
+            &[&self.buffer],
+            &params,
+            shape.elem_count(),
+        )?;
+        Ok(dst)
+    }
+
+    fn unary_impl<B: UnaryOpT>(&self, layout: &Layout) -> Result<Self> {
+        let expr = match (self.dtype, kernels::unary_expr(B::KERNEL)) {
+            (DType::F32, Some(expr)) => expr,
+            (dtype, _) => Err(crate::Error::UnsupportedDTypeForOp(dtype, B::NAME).bt())?,
+        };
+        self.elementwise(B::KERNEL, layout, expr, 0., 0.)
+    }
+
+    fn binary_impl<B: BinaryOpT>(
+        &self,
+        rhs: &Self,
+        lhs_l: &Layout,
+        rhs_l: &Layout,
+    ) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, B::NAME)?;
+        let expr = match kernels::binary_expr(B::KERNEL) {
+            Some(expr) => expr,
+            None => return unsupported(B::NAME),
+        };
+        let shape = lhs_l.shape();
+        let dst = Self::alloc(&self.device, shape.elem_count(), self.dtype, B::NAME)?;
+        let params = kernels::elementwise_params(shape, &[lhs_l, rhs_l], 0, 0);
+        self.device.dispatch(
+            &format!("{}_{ty}", B::KERNEL),
+            || kernels::elementwise(ty, &[("lhs", ty), ("rhs", ty)], expr),
+            &dst.buffer,
+            &[&self.buffer, &rhs.buffer],
+            &params,
+            shape.elem_count(),
+        )?;
+        Ok(dst)
+    }
+
+    fn where_cond(
+        &self,
+        layout: &Layout,
+        t: &Self,
+        t_l: &Layout,
+        f: &Self,
+        f_l: &Layout,
+    ) -> Result<Self> {
+        wgsl_type(self.dtype, "where")?;
+        let ty = wgsl_type(t.dtype, "where")?;
+        let shape = t_l.shape();
+        let dst = Self::alloc(&self.device, shape.elem_count(), t.dtype, "where")?;
+        let params = kernels::elementwise_params(shape, &[layout, t_l, f_l], 0, 0);
+        let inputs = [("cond", "u32"), ("on_true", ty), ("on_false", ty)];
+        self.device.dispatch(
+            &format!("where_{ty}"),
+            || kernels::elementwise(ty, &inputs, "select(x2, x1, x0 != 0u)"),
+            &dst.buffer,
+            &[&self.buffer, &t.buffer, &f.buffer],
+            &params,
+            shape.elem_count(),
+        )?;
+        Ok(dst)
+    }
+
+    fn conv1d(&self, _: &Layout, _: &Self, _: &Layout, _: &ParamsConv1D) -> Result<Self> {
+        unsupported("conv1d")
+    }
+
+    fn conv_transpose1d(
+        &self,
+        _: &Layout,
+        _: &Self,
+        _: &Layout,
+        _: &ParamsConvTranspose1D,
+    ) -> Result<Self> {
+        unsupported("conv-transpose1d")
+    }
+
+    fn conv2d(&self, _: &Layout, _: &Self, _: &Layout, _: &ParamsConv2D) -> Result<Self> {
+        unsupported("conv2d")
+    }
+
+    fn conv_transpose2d(
+        &self,
+        _: &Layout,
+        _: &Self,
+        _: &Layout,
+        _: &ParamsConvTranspose2D,
+    ) -> Result<Self> {
+        unsupported("conv-transpose2d")
+    }
+
+    fn avg_pool2d(&self, _: &Layout, _: (usize, usize), _: (usize, usize)) -> Result<Self> {
+        unsupported("avg-pool2d")
+    }
+
+    fn max_pool2d(&self, _: &Layout, _: (usize, usize), _: (usize, usize)) -> Result<Self> {
+        unsupported("max-pool2d")
+    }
+
+    fn upsample_nearest1d(&self, _: &Layout, _: usize) -> Result<Self> {
+        unsupported("upsample-nearest1d")
+    }
+
+    fn upsample_nearest2d(&self, _: &Layout, _: usize, _: usize) -> Result<Self> {
+        unsupported("upsample-nearest2d")
+    }
+
+    fn gather(&self, l: &Layout, ids: &Self, ids_l: &Layout, dim: usize) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "gather")?;
+        let (src, src_offset) = self.contiguous(l)?;
+        let (ids, ids_offset) = ids.ids(ids_l, "gather")?;
+        let dims = l.dims();
+        let ids_dims = ids_l.dims();
+        let left: usize = dims[..dim].iter().product();
+        let right: usize = dims[dim + 1..].iter().product();
+        let dst_el = ids_l.shape().elem_count();
+        let dst = Self::alloc(&self.device, dst_el, self.dtype, "gather")?;
+        let params = [
+            dst_el,
+            left,
+            dims[dim],
+            ids_dims[dim],
+            right,
+            src_offset,
+            ids_offset,
+        ]
+        .map(|v| v as u32);
+        self.device.dispatch(
+            &format!("gather_{ty}"),
+            || kernels::gather(ty),
+            &dst.buffer,
+            &[&src.buffer, &ids.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn scatter_add(
+        &self,
+        l: &Layout,
+        ids: &Self,
+        ids_l: &Layout,
+        src: &Self,
+        src_l: &Layout,
+        dim: usize,
+    ) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "scatter-add")?;
+        let (base, base_offset) = self.contiguous(l)?;
+        let (ids, ids_offset) = ids.ids(ids_l, "scatter-add")?;
+        let (src, src_offset) = src.contiguous(src_l)?;
+        let dims = l.dims();
+        let left: usize = dims[..dim].iter().product();
+        let right: usize = dims[dim + 1..].iter().product();
+        let dst_el = l.shape().elem_count();
+        let dst = Self::alloc(&self.device, dst_el, self.dtype, "scatter-add")?;
+        let params = [
+            dst_el,
+            left,
+            dims[dim],
+            src_l.dims()[dim],
+            right,
+            base_offset,
+            ids_offset,
+            src_offset,
+        ]
+        .map(|v| v as u32);
+        self.device.dispatch(
+            &format!("scatter_add_{ty}"),
+            || kernels::scatter_add(ty),
+            &dst.buffer,
+            &[&base.buffer, &ids.buffer, &src.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn index_select(&self, ids: &Self, l: &Layout, ids_l: &Layout, dim: usize) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "index-select")?;
+        let (src, src_offset) = self.contiguous(l)?;
+        let (ids, ids_offset) = ids.ids(ids_l, "index-select")?;
+        let dims = l.dims();
+        let left: usize = dims[..dim].iter().product();
+        let right: usize = dims[dim + 1..].iter().product();
+        let ids_el = ids_l.shape().elem_count();
+        let dst_el = left * ids_el * right;
+        let dst = Self::alloc(&self.device, dst_el, self.dtype, "index-select")?;
+        let params = [
+            dst_el, left, dims[dim], ids_el, right, src_offset, ids_offset,
+        ]
+        .map(|v| v as u32);
+        self.device.dispatch(
+            &format!("index_select_{ty}"),
+            || kernels::index_select(ty),
+            &dst.buffer,
+            &[&src.buffer, &ids.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn index_add(
+        &self,
+        l: &Layout,
+        ids: &Self,
+        ids_l: &Layout,
+        src: &Self,
+        src_l: &Layout,
+        dim: usize,
+    ) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "index-add")?;
+        let (base, base_offset) = self.contiguous(l)?;
+        let (ids, ids_offset) = ids.ids(ids_l, "index-add")?;
+        let (src, src_offset) = src.contiguous(src_l)?;
+        let dims = l.dims();
+        let left: usize = dims[..dim].iter().product();
+        let right: usize = dims[dim + 1..].iter().product();
+        let dst_el = l.shape().elem_count();
+        let dst = Self::alloc(&self.device, dst_el, self.dtype, "index-add")?;
+        let params = [
+            dst_el,
+            left,
+            dims[dim],
+            ids_l.shape().elem_count(),
+            right,
+            base_offset,
+            ids_offset,
+            src_offset,
+        ]
+        .map(|v| v as u32);
+        self.device.dispatch(
+            &format!("index_add_{ty}"),
+            || kernels::index_add(ty),
+            &dst.buffer,
+            &[&base.buffer, &ids.buffer, &src.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn matmul(
+        &self,
+        rhs: &Self,
+        (b, m, n, k): (usize, usize, usize, usize),
+        lhs_l: &Layout,
+        rhs_l: &Layout,
+    ) -> Result<Self> {
+        let ty = wgsl_type(self.dtype, "matmul")?;
+        let striding_error = |msg| {
+            crate::Error::MatMulUnexpectedStriding(Box::new(
+                crate::error::MatMulUnexpectedStriding {
+                    lhs_l: lhs_l.clone(),
+                    rhs_l: rhs_l.clone(),
+                    bmnk: (b, m, n, k),
+                    msg,
+                },
+            ))
+            .bt()
+        };
+        let lhs_stride = lhs_l.stride();
+        let rhs_stride = rhs_l.stride();
+        let rank = lhs_stride.len();
+        let a_skip: usize = match lhs_stride[..rank - 2] {
+            [s1, stride] if s1 == stride * lhs_l.dims()[1] => stride,
+            [_, stride] if lhs_l.dims()[0] == 1 => stride,
+            [stride, _] if lhs_l.dims()[1] == 1 => stride,
+            [stride] => stride,
+            [] => m * k,
+            _ => Err(striding_error("non-contiguous lhs"))?,
+        };
+        let b_skip: usize = match rhs_stride[..rank - 2] {
+            [s1, stride] if s1 == stride * rhs_l.dims()[1] => stride,
+            [_, stride] if rhs_l.dims()[0] == 1 => stride,
+            [stride, _] if rhs_l.dims()[1] == 1 => stride,
+            [stride] => stride,
+            [] => n * k,
+            _ => Err(striding_error("non-contiguous rhs"))?,
+        };
+        let dst_el = b * m * n;
+        let dst = Self::alloc(&self.device, dst_el, self.dtype, "matmul")?;
+        let params = [
+            dst_el,
+            m,
+            n,
+            k,
+            lhs_l.start_offset(),
+            a_skip,
+            lhs_stride[rank - 2],
+            lhs_stride[rank - 1],
+            rhs_l.start_offset(),
+            b_skip,
+            rhs_stride[rank - 2],
+            rhs_stride[rank - 1],
+        ]
+        .map(|v| v as u32);
+        self.device.dispatch(
+            &format!("matmul_{ty}"),
+            || kernels::matmul(ty),
+            &dst.buffer,
+            &[&self.buffer, &rhs.buffer],
+            &params,
+            dst_el,
+        )?;
+        Ok(dst)
+    }
+
+    fn copy_strided_src(&self, dst: &mut Self, dst_offset: usize, src_l: &Layout) -> Result<()> {
+        let ty = wgsl_type(self.dtype, "copy")?;
+        let shape = src_l.shape();
+        let params = kernels::elementwise_params(shape, &[src_l], dst_offset as u32, 0);
+        self.device.dispatch(
+            &format!("copy_strided_{ty}"),
+            || kernels::copy_strided(ty),
+            &dst.buffer,
+            &[&self.buffer],
+            &params,
+            shape.elem_count(),
+        )
+    }
+
+    fn copy2d(
+        &self,
+        dst: &mut Self,
+        d1: usize,
+        d2: usize,
+        src_s: usize,
+        dst_s: usize,
+        src_o: usize,
+        dst_o: usize,
+    ) -> Result<()> {
+        let ty = wgsl_type(self.dtype, "copy2d")?;
+        let params = [d1 * d2, d2, src_s, dst_s, src_o, dst_o].map(|v| v as u32);
+        self.device.dispatch(
+            &format!("copy2d_{ty}"),
+            || kernels::copy2d(ty),
+            &dst.buffer,
+            &[&self.buffer],
+            &params,
+            d1 * d2,
+        )
+    }
+}
+
+impl BackendDevice for WgpuDevice {
+    type Storage = WgpuStorage;
+
+    fn new(ordinal: usize) -> Result<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = match instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .into_iter()
+            .nth(ordinal)
+        {
+            Some(adapter) => adapter,
+            None => Err(WgpuError::Message(format!("no wgpu adapter for {ordinal}")))?,
+        };
+        pollster::block_on(Self::from_adapter(&adapter, ordinal))
+    }
+
+    fn location(&self) -> crate::DeviceLocation {
+        crate::DeviceLocation::Wgpu {
+            gpu_id: self.ordinal,
+        }
+    }
+
+    fn same_device(&self, rhs: &Self) -> bool {
+        self.id == rhs.id
+    }
+
+    fn zeros_impl(&self, shape: &Shape, dtype: DType) -> Result<WgpuStorage> {
+        // wgpu zero-initializes the buffers.
+        WgpuStorage::alloc(self, shape.elem_count(), dtype, "zeros")
+    }
+
+    fn ones_impl(&self, shape: &Shape, dtype: DType) -> Result<WgpuStorage> {
+        let el = shape.elem_count();
+        let storage = match dtype {
+            DType::F32 => CpuStorage::F32(vec![1f32; el]),
+            DType::U32 => CpuStorage::U32(vec![1u32; el]),
+            DType::U8 => CpuStorage::U8(vec![1u8; el]),
+            dtype => Err(crate::Error::UnsupportedDTypeForOp(dtype, "ones").bt())?,
+        };
+        self.storage_from_cpu_storage_owned(storage)
+    }
+
+    unsafe fn alloc_uninit(&self, shape: &Shape, dtype: DType) -> Result<WgpuStorage> {
+        WgpuStorage::alloc(self, shape.elem_count(), dtype, "alloc")
+    }
+
+    fn storage_from_slice<T: crate::WithDType>(&self, s: &[T]) -> Result<Self::Storage> {
+        self.storage_from_cpu_storage(&T::to_cpu_storage(s))
+    }
+
+    fn storage_from_cpu_storage(&self, storage: &CpuStorage) -> Result<Self::Storage> {
+        let (data, count): (Vec<u8>, usize) = match storage {
+            CpuStorage::F32(s) => (s.iter().flat_map(|v| v.to_le_bytes()).collect(), s.len()),
+            CpuStorage::U32(s) => (s.iter().flat_map(|v| v.to_le_bytes()).collect(), s.len()),
+            CpuStorage::U8(s) => (
+                s.iter().flat_map(|&v| (v as u32).to_le_bytes()).collect(),
+                s.len(),
+            ),
+            s => Err(crate::Error::UnsupportedDTypeForOp(s.dtype(), "to_device").bt())?,
+        };
+        let buffer = self.create_buffer_init(&data);
+        Ok(WgpuStorage::new(
+            buffer,
+            self.clone(),
+            count,
+            storage.dtype(),
+        ))
+    }
+
+    fn storage_from_cpu_storage_owned(&self, storage: CpuStorage) -> Result<Self::Storage> {
+        self.storage_from_cpu_storage(&storage)
+    }
+
+    fn rand_uniform(&self, shape: &Shape, dtype: DType, lo: f64, up: f64) -> Result<WgpuStorage> {
+        use rand::prelude::*;
+        if dtype != DType::F32 {
+            Err(crate::Error::UnsupportedDTypeForOp(dtype, "rand_uniform").bt())?
+        }
+        let mut rng = self.rng.lock().map_err(WgpuError::from)?;
+        let uniform = rand::distributions::Uniform::new(lo as f32, up as f32);
+        let data = (0..shape.elem_count())
+            .map(|_| rng.sample::<f32, _>(uniform))
+            .collect::<Vec<_>>();
+        self.storage_from_cpu_storage_owned(CpuStorage::F32(data))
+    }
+
+    fn rand_normal(&self, shape: &Shape, dtype: DType, mean: f64, std: f64) -> Result<WgpuStorage> {
+        use rand::prelude::*;
+        if dtype != DType::F32 {
+            Err(crate::Error::UnsupportedDTypeForOp(dtype, "rand_normal").bt())?
+        }
+        let mut rng = self.rng.lock().map_err(WgpuError::from)?;
+        let normal = rand_distr::Normal::new(mean as f32, std as f32).map_err(Error::wrap)?;
+        let data = (0..shape.elem_count())
+            .map(|_| normal.sample(&mut *rng))
+            .collect::<Vec<_>>();
+        self.storage_from_cpu_storage_owned(CpuStorage::F32(data))
+    }
+
+    fn set_seed(&self, seed: u64) -> Result<()> {
+        use rand::SeedableRng;
+        let mut rng = self.rng.lock().map_err(WgpuError::from)?;
+        *rng = rand::rngs::StdRng::seed_from_u64(seed);
+        Ok(())
+    }
+
+    fn synchronize(&self) -> Result<()> {
+        self.device.poll(wgpu::Maintain::Wait);
+        Ok(())
+    }
+}
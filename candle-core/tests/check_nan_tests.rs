@@ -0,0 +1,29 @@
+// The nan checks are enabled for the whole process so they are tested in their own binary to
+// avoid interfering with the other tests.
+use candle_core::{Device, Result, Tensor, Var};
+
+#[test]
+fn check_nan() -> Result<()> {
+    let device = &Device::Cpu;
+    let x = Var::new(&[0f32, 1., 2.], device)?;
+    let y = Tensor::new(&[0f32, 2., 4.], device)?;
+    // The checks are disabled by default.
+    let z = x.div(&y)?;
+    assert!(z.has_nan()?);
+    assert!(!z.has_inf()?);
+    assert!(x.recip()?.has_inf()?);
+
+    candle_core::set_check_nan(true);
+    let err = x.div(&y).unwrap_err().to_string();
+    assert!(err.contains("nan or inf in the output of div"), "{err}");
+    assert!(err.contains("[[3], [3]]"), "{err}");
+    let err = x.recip().unwrap_err().to_string();
+    assert!(err.contains("recip"), "{err}");
+    // Ops on tensors that do not track gradients are not checked.
+    assert!(x.detach().div(&y)?.has_nan()?);
+    let z = (x.as_tensor() + 1.)?.sqr()?;
+    candle_core::set_check_nan(false);
+    assert_eq!(z.to_vec1::<f32>()?, [1., 4., 9.]);
+    assert!(x.div(&y).is_ok());
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use candle_core::{test_device, test_utils, Device, IndexOp, Tensor};
+use candle_core::{test_device, test_utils, DType, Device, IndexOp, Tensor};
 
 /* This test is based on the following script.
 import torch
@@ -322,6 +322,36 @@ fn conv2d_smaller(dev: &Device) -> Result<()> {
     Ok(())
 }
 
+fn conv2d_same(dev: &Device) -> Result<()> {
+    use candle_core::conv::{same_padding, PaddingMode};
+    assert_eq!(same_padding(5, 3, 1, 1), (1, 1));
+    assert_eq!(same_padding(5, 4, 1, 1), (1, 2));
+    assert_eq!(same_padding(6, 3, 2, 1), (0, 1));
+    assert_eq!(same_padding(5, 3, 1, 2), (2, 2));
+
+    let t = Tensor::randn(0f32, 1., (2, 3, 5, 6), dev)?;
+    for k_size in [1, 2, 3, 4, 5] {
+        let w = Tensor::randn(0f32, 1., (4, 3, k_size, k_size), dev)?;
+        let res = t.conv2d_with_mode(&w, 0, PaddingMode::Same, 1, 1, 1)?;
+        assert_eq!(res.dims(), [2, 4, 5, 6], "kernel size {k_size}");
+        let res = t.conv2d_with_mode(&w, 0, PaddingMode::Same, 2, 1, 1)?;
+        assert_eq!(res.dims(), [2, 4, 3, 3], "kernel size {k_size}");
+    }
+
+    // With an even kernel, the extra padding goes to the bottom/right.
+    let t = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?.reshape((1, 1, 2, 2))?;
+    let w = Tensor::ones((1, 1, 2, 2), DType::F32, dev)?;
+    let res = t.conv2d_with_mode(&w, 0, PaddingMode::Same, 1, 1, 1)?;
+    assert_eq!(
+        res.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[10., 6.], [7., 4.]]
+    );
+    // The explicit mode uses the padding argument.
+    let res = t.conv2d_with_mode(&w, 1, PaddingMode::Explicit, 1, 1, 1)?;
+    assert_eq!(res.dims(), [1, 1, 3, 3]);
+    Ok(())
+}
+
 /* This test is based on the following script.
 import torch
 torch.manual_seed(4242)
@@ -733,6 +763,193 @@ fn conv2d_grad(dev: &Device) -> Result<()> {
     Ok(())
 }
 
+fn conv3d_small(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 27., dev)?.reshape((1, 1, 3, 3, 3))?;
+    let w = Tensor::ones((1, 1, 2, 2, 2), DType::F32, dev)?;
+    let res = t.conv3d(&w, 0, 1, 1, 1)?;
+    assert_eq!(res.dims(), [1, 1, 2, 2, 2]);
+    // Each output is the sum of a 2x2x2 cube of the input.
+    assert_eq!(
+        res.flatten_all()?.to_vec1::<f32>()?,
+        [52., 60., 76., 84., 124., 132., 148., 156.]
+    );
+    let res = t.conv3d(&w, 1, 2, 1, 1)?;
+    assert_eq!(res.dims(), [1, 1, 2, 2, 2]);
+    assert_eq!(
+        res.flatten_all()?.to_vec1::<f32>()?,
+        [0., 3., 9., 24., 27., 60., 72., 156.]
+    );
+
+    // The output size is (i + 2 * padding - dilation * (k - 1) - 1) / stride + 1.
+    let t = Tensor::zeros((2, 4, 7, 6, 5), DType::F32, dev)?;
+    let w = Tensor::zeros((6, 2, 3, 2, 2), DType::F32, dev)?;
+    assert_eq!(t.conv3d(&w, 1, 2, 2, 2)?.dims(), [2, 6, 3, 3, 3]);
+    assert_eq!(t.conv3d(&w, 0, 1, 3, 2)?.dims(), [2, 6, 1, 3, 2]);
+    assert!(t.conv3d(&w, 0, 1, 1, 1).is_err());
+    // The output size is (i - 1) * stride + dilation * (k - 1) + output_padding + 1 - 2 * padding.
+    let w = Tensor::zeros((4, 3, 3, 2, 2), DType::F32, dev)?;
+    assert_eq!(
+        t.conv_transpose3d(&w, 1, 1, 2, 2, 2)?.dims(),
+        [2, 6, 16, 12, 10]
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn conv3d_reference(
+    t: &[f32],
+    (b_size, c_in, i_d, i_h, i_w): (usize, usize, usize, usize, usize),
+    w: &[f32],
+    (c_out, k_c, k_d, k_h, k_w): (usize, usize, usize, usize, usize),
+    padding: usize,
+    stride: usize,
+    dilation: usize,
+    groups: usize,
+) -> Vec<f32> {
+    let out = |i: usize, k: usize| (i + 2 * padding - dilation * (k - 1) - 1) / stride + 1;
+    let (o_d, o_h, o_w) = (out(i_d, k_d), out(i_h, k_h), out(i_w, k_w));
+    let mut res = vec![0f32; b_size * c_out * o_d * o_h * o_w];
+    let c_out_g = c_out / groups;
+    for b in 0..b_size {
+        for oc in 0..c_out {
+            let g = oc / c_out_g;
+            for od in 0..o_d {
+                for oh in 0..o_h {
+                    for ow in 0..o_w {
+                        let mut sum = 0f32;
+                        for kc in 0..k_c {
+                            let ic = g * k_c + kc;
+                            for kd in 0..k_d {
+                                for kh in 0..k_h {
+                                    for kw in 0..k_w {
+                                        let id = (od * stride + kd * dilation) as isize
+                                            - padding as isize;
+                                        let ih = (oh * stride + kh * dilation) as isize
+                                            - padding as isize;
+                                        let iw = (ow * stride + kw * dilation) as isize
+                                            - padding as isize;
+                                        if id < 0
+                                            || ih < 0
+                                            || iw < 0
+                                            || id >= i_d as isize
+                                            || ih >= i_h as isize
+                                            || iw >= i_w as isize
+                                        {
+                                            continue;
+                                        }
+                                        let (id, ih, iw) = (id as usize, ih as usize, iw as usize);
+                                        let t_idx =
+                                            (((b * c_in + ic) * i_d + id) * i_h + ih) * i_w + iw;
+                                        let w_idx =
+                                            (((oc * k_c + kc) * k_d + kd) * k_h + kh) * k_w + kw;
+                                        sum += t[t_idx] * w[w_idx]
+                                    }
+                                }
+                            }
+                        }
+                        res[(((b * c_out + oc) * o_d + od) * o_h + oh) * o_w + ow] = sum
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+fn conv3d(dev: &Device) -> Result<()> {
+    let t_dims = (2, 4, 5, 4, 6);
+    let t = Tensor::randn(0f32, 1., t_dims, &Device::Cpu)?;
+    let t_vec = t.flatten_all()?.to_vec1::<f32>()?;
+    for (w_dims, padding, stride, dilation, groups) in [
+        ((3, 4, 2, 3, 2), 0, 1, 1, 1),
+        ((3, 4, 3, 2, 3), 1, 2, 1, 1),
+        ((2, 4, 2, 2, 2), 2, 1, 2, 1),
+        ((4, 2, 2, 3, 3), 1, 1, 1, 2),
+    ] {
+        let w = Tensor::randn(0f32, 1., w_dims, &Device::Cpu)?;
+        let w_vec = w.flatten_all()?.to_vec1::<f32>()?;
+        let expected = conv3d_reference(
+            &t_vec, t_dims, &w_vec, w_dims, padding, stride, dilation, groups,
+        );
+        let res =
+            t.to_device(dev)?
+                .conv3d(&w.to_device(dev)?, padding, stride, dilation, groups)?;
+        let res = res.flatten_all()?.to_vec1::<f32>()?;
+        assert_eq!(res.len(), expected.len());
+        for (r, e) in res.iter().zip(expected.iter()) {
+            assert!((r - e).abs() < 1e-4, "{r} {e}")
+        }
+    }
+    Ok(())
+}
+
+fn conv_transpose3d(dev: &Device) -> Result<()> {
+    // The transposed convolution is the adjoint of the convolution:
+    // <conv3d(x, w), y> = <x, conv_transpose3d(y, w)>
+    for (padding, stride, dilation, groups) in [(0, 1, 1, 1), (1, 2, 1, 1), (1, 1, 2, 2)] {
+        let x = Tensor::randn(0f32, 1., (2, 4, 5, 6, 5), dev)?;
+        let w = Tensor::randn(0f32, 1., (6, 4 / groups, 2, 3, 2), dev)?;
+        let y = x.conv3d(&w, padding, stride, dilation, groups)?;
+        let y2 = Tensor::randn(0f32, 1., y.shape(), dev)?;
+        // Output padding recovers the input size lost in the strided convolution.
+        let (o_d, o_h, o_w) = (y.dim(2)?, y.dim(3)?, y.dim(4)?);
+        let full = |o: usize, k: usize| (o - 1) * stride + dilation * (k - 1) + 1 - 2 * padding;
+        let output_padding = 5 - full(o_d, 2);
+        assert_eq!(6 - full(o_h, 3), output_padding);
+        assert_eq!(5 - full(o_w, 2), output_padding);
+        let x2 = y2.conv_transpose3d(&w, padding, output_padding, stride, dilation, groups)?;
+        assert_eq!(x2.dims(), x.dims());
+        let lhs = (y * &y2)?.sum_all()?.to_vec0::<f32>()?;
+        let rhs = (x * x2)?.sum_all()?.to_vec0::<f32>()?;
+        assert!((lhs - rhs).abs() < 1e-3 * lhs.abs().max(1.), "{lhs} {rhs}");
+    }
+    // A single input voxel spreads the kernel in the output.
+    let x = Tensor::ones((1, 1, 1, 1, 1), DType::F32, dev)?;
+    let w = Tensor::arange(0f32, 8., dev)?.reshape((1, 1, 2, 2, 2))?;
+    let res = x.conv_transpose3d(&w, 0, 0, 1, 1, 1)?;
+    assert_eq!(
+        res.flatten_all()?.to_vec1::<f32>()?,
+        [0., 1., 2., 3., 4., 5., 6., 7.]
+    );
+    Ok(())
+}
+
+fn conv_transpose2d_configs(dev: &Device) -> Result<()> {
+    // Compare with the cpu implementation on random inputs, for various strides, paddings,
+    // output paddings and dilations. The input is strided to also exercise this case.
+    let t = Tensor::randn(0f32, 1., (2, 5, 3, 4), dev)?.transpose(2, 3)?;
+    let t_cpu = t.to_device(&Device::Cpu)?;
+    for (k_h, k_w, stride, padding, output_padding, dilation) in [
+        (3, 3, 1, 0, 0, 1),
+        (3, 2, 2, 1, 1, 1),
+        (2, 3, 2, 0, 1, 2),
+        (4, 4, 3, 2, 2, 1),
+        (3, 5, 3, 1, 2, 3),
+    ] {
+        let w = Tensor::randn(0f32, 1., (5, 3, k_h, k_w), dev)?;
+        let w_cpu = w.to_device(&Device::Cpu)?;
+        let res = t.conv_transpose2d(&w, padding, output_padding, stride, dilation)?;
+        let out_size = |i: usize, k: usize| {
+            (i - 1) * stride - 2 * padding + dilation * (k - 1) + output_padding + 1
+        };
+        assert_eq!(res.dims(), [2, 3, out_size(4, k_h), out_size(3, k_w)]);
+        let res_cpu = t_cpu.conv_transpose2d(&w_cpu, padding, output_padding, stride, dilation)?;
+        let diff = (res.to_device(&Device::Cpu)? - res_cpu)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?
+            .to_vec0::<f32>()?;
+        assert!(diff < 1e-4, "{diff}");
+    }
+    // The output padding has to be smaller than either the stride or the dilation.
+    let w = Tensor::randn(0f32, 1., (5, 3, 3, 3), dev)?;
+    assert!(t.conv_transpose2d(&w, 0, 2, 2, 1).is_err());
+    assert!(t.conv_transpose2d(&w, 0, 2, 2, 3).is_ok());
+    // The padding cannot remove the whole output.
+    assert!(t.conv_transpose2d(&w, 3, 0, 1, 1).is_err());
+    Ok(())
+}
+
 test_device!(conv1d, conv1d_cpu, conv1d_gpu, conv1d_metal);
 test_device!(
     conv1d_small,
@@ -759,9 +976,34 @@ test_device!(
     conv2d_smaller_gpu,
     conv2d_smaller_metal
 );
+test_device!(
+    conv2d_same,
+    conv2d_same_cpu,
+    conv2d_same_gpu,
+    conv2d_same_metal
+);
 test_device!(
     conv2d_grad,
     conv2d_grad_cpu,
     conv2d_grad_gpu,
     conv2_grad_metal
 );
+test_device!(
+    conv3d_small,
+    conv3d_small_cpu,
+    conv3d_small_gpu,
+    conv3d_small_metal
+);
+test_device!(conv3d, conv3d_cpu, conv3d_gpu, conv3d_metal);
+test_device!(
+    conv_transpose2d_configs,
+    conv_transpose2d_configs_cpu,
+    conv_transpose2d_configs_gpu,
+    conv_transpose2d_configs_metal
+);
+test_device!(
+    conv_transpose3d,
+    conv_transpose3d_cpu,
+    conv_transpose3d_gpu,
+    conv_transpose3d_metal
+);
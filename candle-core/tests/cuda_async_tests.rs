@@ -0,0 +1,73 @@
+#![cfg(feature = "cuda")]
+use candle_core::cuda::cudarc::driver::sys::{cuStreamQuery, CUresult};
+use candle_core::{Device, Result, Tensor};
+
+// Returns whether all the work enqueued on the default stream of the device has completed.
+fn default_stream_idle(device: &Device) -> bool {
+    let cuda = match device {
+        Device::Cuda(cuda) => cuda.cuda_device(),
+        _ => unreachable!(),
+    };
+    // SAFETY: ffi, the stream is owned by the device which outlives this call.
+    let status = unsafe { cuStreamQuery(*cuda.cu_stream()) };
+    assert!(
+        status == CUresult::CUDA_SUCCESS || status == CUresult::CUDA_ERROR_NOT_READY,
+        "{status:?}"
+    );
+    status == CUresult::CUDA_SUCCESS
+}
+
+// Enqueues enough work on the default stream to keep it busy for a while.
+fn busy_compute(a: &Tensor) -> Result<Tensor> {
+    let mut b = a.clone();
+    for _ in 0..50 {
+        b = (b.matmul(a)? * 1e-3)?;
+    }
+    Ok(b)
+}
+
+#[test]
+fn to_device_async_overlaps_compute() -> Result<()> {
+    let device = Device::new_cuda(0)?;
+    let cpu = &Device::Cpu;
+    let xs = Tensor::arange(0f32, (1 << 20) as f32, cpu)?;
+    // Load the kernels, create the copy stream and allocate a staging buffer beforehand.
+    let a = Tensor::randn(0f32, 1., (1024, 1024), &device)?;
+    busy_compute(&a)?;
+    xs.to_device_async(&device)?;
+    device.synchronize()?;
+
+    // The asynchronous copy is enqueued without waiting for the computations already running on
+    // the default stream.
+    let b = busy_compute(&a)?;
+    let ys = xs.to_device_async(&device)?;
+    assert!(!default_stream_idle(&device));
+    // The computations using the copy are ordered after it.
+    let doubled = (&ys * 2.)?;
+    device.synchronize()?;
+    assert!(default_stream_idle(&device));
+    assert_eq!(ys.to_vec1::<f32>()?, xs.to_vec1::<f32>()?);
+    assert_eq!(doubled.to_vec1::<f32>()?, (&xs * 2.)?.to_vec1::<f32>()?);
+
+    // Whereas the synchronous copy waits for the default stream to be idle.
+    let c = busy_compute(&a)?;
+    let zs = xs.to_device(&device)?;
+    assert!(default_stream_idle(&device));
+    assert_eq!(zs.to_vec1::<f32>()?, xs.to_vec1::<f32>()?);
+    assert_eq!(b.dims(), c.dims());
+
+    // The destination buffers are recycled through the caching allocator, a cached buffer is
+    // only overwritten once the kernels enqueued before its release have completed.
+    let cuda = match &device {
+        Device::Cuda(cuda) => cuda.clone(),
+        _ => unreachable!(),
+    };
+    drop((ys, zs));
+    let before = cuda.cache_stats();
+    let ys = xs.to_device_async(&device)?;
+    let stats = cuda.cache_stats();
+    assert_eq!(stats.allocations, before.allocations, "{stats:?}");
+    assert!(stats.hits > before.hits, "{stats:?}");
+    assert_eq!(ys.to_vec1::<f32>()?, xs.to_vec1::<f32>()?);
+    Ok(())
+}
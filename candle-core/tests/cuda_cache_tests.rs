@@ -0,0 +1,43 @@
+#![cfg(feature = "cuda")]
+use candle_core::{DType, Device, Result, Tensor};
+
+#[test]
+fn caching_allocator() -> Result<()> {
+    let device = Device::new_cuda(0)?;
+    let cuda = match &device {
+        Device::Cuda(cuda) => cuda.clone(),
+        _ => unreachable!(),
+    };
+    device.empty_cache()?;
+    let before = cuda.cache_stats();
+    for _ in 0..100 {
+        let t = Tensor::zeros((32, 64), DType::F32, &device)?;
+        let t = ((t + 1.)? * 2.)?;
+        assert_eq!(t.sum_all()?.to_vec0::<f32>()?, 4096.);
+    }
+    let stats = cuda.cache_stats();
+    // Without the cache, each iteration would result in at least four allocations.
+    let allocations = stats.allocations - before.allocations;
+    assert!(allocations <= 8, "{stats:?}");
+    assert!(stats.hits - before.hits >= 300, "{stats:?}");
+    assert!(stats.cached_bytes > 0);
+
+    // Buffers shared with views or clones are recycled once the last of them is dropped.
+    device.empty_cache()?;
+    let t = Tensor::zeros((32, 64), DType::F32, &device)?;
+    let view = t.narrow(0, 4, 8)?;
+    let clone = t.clone();
+    drop(t);
+    drop(clone);
+    assert_eq!(cuda.cache_stats().cached_buffers, 0);
+    drop(view);
+    let stats = cuda.cache_stats();
+    assert_eq!(stats.cached_buffers, 1);
+    assert_eq!(stats.cached_bytes, 32 * 64 * 4);
+
+    device.empty_cache()?;
+    let stats = cuda.cache_stats();
+    assert_eq!(stats.cached_buffers, 0);
+    assert_eq!(stats.cached_bytes, 0);
+    Ok(())
+}
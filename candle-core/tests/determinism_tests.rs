@@ -0,0 +1,71 @@
+// The cpu rng and the deterministic reduction flag are shared by the whole process, these tests
+// run in their own binary so that the other tests cannot draw from the seeded rng.
+use candle_core::{DType, Device, Result, Tensor};
+
+#[test]
+fn seeded_rand() -> Result<()> {
+    let dev = &Device::Cpu;
+    dev.set_seed(299792458)?;
+    let u1 = Tensor::rand(0f32, 1f32, 100, dev)?.to_vec1::<f32>()?;
+    let n1 = Tensor::randn(0f64, 1f64, (4, 25), dev)?.to_vec2::<f64>()?;
+    dev.set_seed(299792458)?;
+    let u2 = Tensor::rand(0f32, 1f32, 100, dev)?.to_vec1::<f32>()?;
+    let n2 = Tensor::randn(0f64, 1f64, (4, 25), dev)?.to_vec2::<f64>()?;
+    assert_eq!(u1, u2);
+    assert_eq!(n1, n2);
+    dev.set_seed(42)?;
+    let u3 = Tensor::rand(0f32, 1f32, 100, dev)?.to_vec1::<f32>()?;
+    assert_ne!(u1, u3);
+    Ok(())
+}
+
+#[test]
+fn deterministic_sum() -> Result<()> {
+    let dev = &Device::Cpu;
+    candle_core::utils::set_deterministic_reductions(true);
+    let n = 1 << 20;
+    // The rng is shared by the whole process, use deterministic values to not interfere with
+    // the seeded_rand test.
+    let t = (Tensor::arange(0u32, n as u32, dev)?.to_dtype(DType::F32)? * 0.37)?.sin()?;
+    // Shuffle the values using a fixed permutation, 7919 being coprime with n.
+    let perm = (0..n as u32)
+        .map(|i| (i as u64 * 7919 % n as u64) as u32)
+        .collect::<Vec<_>>();
+    let perm = Tensor::new(perm, dev)?;
+    let t = t.index_select(&perm, 0)?;
+    let sum = t.sum_all()?.to_scalar::<f32>()?;
+    let mean = t.mean_all()?.to_scalar::<f32>()?;
+    for _ in 0..5 {
+        assert_eq!(t.sum_all()?.to_scalar::<f32>()?.to_bits(), sum.to_bits());
+        assert_eq!(t.mean_all()?.to_scalar::<f32>()?.to_bits(), mean.to_bits());
+    }
+    let t = t.reshape((16, n / 16))?;
+    let sums = t.sum(1)?.to_vec1::<f32>()?;
+    for _ in 0..5 {
+        assert_eq!(t.sum(1)?.to_vec1::<f32>()?, sums);
+    }
+    Ok(())
+}
+
+#[test]
+fn deterministic_sum_non_last_dims() -> Result<()> {
+    let dev = &Device::Cpu;
+    candle_core::utils::set_deterministic_reductions(true);
+    let t = (Tensor::arange(0f32, 64. * 4096., dev)?.reshape((64, 4096))? * 0.37)?.sin()?;
+    // Reducing over the first dimension, with a contiguous or a strided layout, sums the values
+    // in the same order as reducing the last dimension of the transposed tensor.
+    let expected = t.t()?.contiguous()?.sum(1)?.to_vec1::<f32>()?;
+    assert_eq!(t.sum(0)?.to_vec1::<f32>()?, expected);
+    assert_eq!(t.t()?.sum(1)?.to_vec1::<f32>()?, expected);
+    for _ in 0..5 {
+        assert_eq!(t.sum(0)?.to_vec1::<f32>()?, expected);
+    }
+    let t = t.reshape((4, 16, 4096))?;
+    let expected = t
+        .permute((0, 2, 1))?
+        .contiguous()?
+        .sum(2)?
+        .to_vec2::<f32>()?;
+    assert_eq!(t.sum(1)?.to_vec2::<f32>()?, expected);
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use candle_core::{fft, test_utils, DType, Device, Result, Tensor};
+
+// Naive O(n^2) reference implementation returning the real and imaginary parts.
+fn dft(re: &[f64], im: &[f64], inverse: bool) -> (Vec<f64>, Vec<f64>) {
+    let n = re.len();
+    let sign = if inverse { 1. } else { -1. };
+    let scale = if inverse { 1. / n as f64 } else { 1. };
+    (0..n)
+        .map(|k| {
+            let (mut r, mut i) = (0., 0.);
+            for (j, (re, im)) in re.iter().zip(im.iter()).enumerate() {
+                let angle = sign * 2. * std::f64::consts::PI * ((j * k) % n) as f64 / n as f64;
+                let (s, c) = angle.sin_cos();
+                r += re * c - im * s;
+                i += re * s + im * c;
+            }
+            (r * scale, i * scale)
+        })
+        .unzip()
+}
+
+fn signal(n: usize, seed: usize) -> Vec<f64> {
+    (0..n).map(|i| ((i * 7 + seed) % 11) as f64 - 4.5).collect()
+}
+
+fn tensor(vs: &[f64]) -> Result<Tensor> {
+    Tensor::new(vs, &Device::Cpu)
+}
+
+// The real and imaginary parts of a complex tensor, flattened.
+fn parts(t: &Tensor) -> Result<(Tensor, Tensor)> {
+    Ok((t.real()?.flatten_all()?, t.imag()?.flatten_all()?))
+}
+
+#[test]
+fn fft_ifft() -> Result<()> {
+    for n in 1..=17 {
+        let (re, im) = (signal(n, 3), signal(n, 5));
+        let t = Tensor::complex(
+            &Tensor::new(re.as_slice(), &Device::Cpu)?,
+            &Tensor::new(im.as_slice(), &Device::Cpu)?,
+        )?;
+        let f = fft::fft(&t, 0)?;
+        assert_eq!(f.dtype(), DType::C128);
+        let (f_re, f_im) = parts(&f)?;
+        let (expected_re, expected_im) = dft(&re, &im, false);
+        test_utils::assert_close(&f_re, &tensor(&expected_re)?, 0., 1e-8);
+        test_utils::assert_close(&f_im, &tensor(&expected_im)?, 0., 1e-8);
+
+        let i = fft::ifft(&f, 0)?;
+        let (i_re, i_im) = parts(&i)?;
+        test_utils::assert_close(&i_re, &tensor(&re)?, 0., 1e-8);
+        test_utils::assert_close(&i_im, &tensor(&im)?, 0., 1e-8);
+    }
+    Ok(())
+}
+
+#[test]
+fn fft_dim() -> Result<()> {
+    let t = Tensor::new(signal(15, 1).as_slice(), &Device::Cpu)?.reshape((3, 5))?;
+    let f = fft::fft(&t, 0)?;
+    assert_eq!(f.dims(), [3, 5]);
+    let f = f.t()?.contiguous()?;
+    let t = t.t()?.contiguous()?;
+    for i in 0..5 {
+        let re = t.get(i)?.to_vec1::<f64>()?;
+        let (expected_re, expected_im) = dft(&re, &[0.; 3], false);
+        let (f_re, f_im) = parts(&f.get(i)?)?;
+        test_utils::assert_close(&f_re, &tensor(&expected_re)?, 0., 1e-8);
+        test_utils::assert_close(&f_im, &tensor(&expected_im)?, 0., 1e-8);
+    }
+    Ok(())
+}
+
+#[test]
+fn rfft_irfft() -> Result<()> {
+    for n in 1..=17 {
+        let re = signal(n, 2);
+        let t = Tensor::new(re.as_slice(), &Device::Cpu)?;
+        let f = fft::rfft(&t, 0)?;
+        assert_eq!(f.dims(), [n / 2 + 1]);
+        let (f_re, f_im) = parts(&f)?;
+        let (expected_re, expected_im) = dft(&re, &vec![0.; n], false);
+        test_utils::assert_close(&f_re, &tensor(&expected_re[..n / 2 + 1])?, 0., 1e-8);
+        test_utils::assert_close(&f_im, &tensor(&expected_im[..n / 2 + 1])?, 0., 1e-8);
+
+        let i = fft::irfft(&f, 0, Some(n))?;
+        assert_eq!(i.dtype(), DType::F64);
+        test_utils::assert_close(&i, &tensor(&re)?, 0., 1e-8);
+    }
+
+    let t = Tensor::new(&[[1f32, 2., 3., 4.], [0., 1., 0., -1.]], &Device::Cpu)?;
+    let f = fft::rfft(&t, 1)?;
+    assert_eq!(f.dtype(), DType::C64);
+    assert_eq!(
+        test_utils::to_vec2_round(&f.real()?, 4)?,
+        [[10., -2., -2.], [0., 0., 0.]]
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(&f.imag()?, 4)?,
+        [[0., 2., 0.], [0., -2., 0.]]
+    );
+    let i = fft::irfft(&f, 1, None)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&i, 4)?,
+        [[1., 2., 3., 4.], [0., 1., 0., -1.]]
+    );
+    Ok(())
+}
+
+#[test]
+fn stft() -> Result<()> {
+    let samples = signal(20, 4);
+    let t = Tensor::new(samples.as_slice(), &Device::Cpu)?;
+    let window = Tensor::new(&[0.5f64, 1., 1., 0.5], &Device::Cpu)?;
+    let s = fft::stft(&t, 4, 3, &window)?;
+    assert_eq!(s.dims(), [6, 3]);
+    for frame in 0..6 {
+        let windowed: Vec<f64> = samples[3 * frame..3 * frame + 4]
+            .iter()
+            .zip([0.5, 1., 1., 0.5])
+            .map(|(v, w)| v * w)
+            .collect();
+        let (expected_re, expected_im) = dft(&windowed, &[0.; 4], false);
+        let (re, im) = parts(&s.get(frame)?)?;
+        test_utils::assert_close(&re, &tensor(&expected_re[..3])?, 0., 1e-8);
+        test_utils::assert_close(&im, &tensor(&expected_im[..3])?, 0., 1e-8);
+    }
+    assert!(fft::stft(&t, 5, 3, &window).is_err());
+    Ok(())
+}
@@ -1,6 +1,6 @@
 #![allow(clippy::approx_constant)]
 use anyhow::{Context, Result};
-use candle_core::{test_device, test_utils, Device, Shape, Tensor, Var};
+use candle_core::{test_device, test_utils, CpuStorage, Device, Shape, Tensor, Var};
 
 fn simple_grad(device: &Device) -> Result<()> {
     let x = Var::new(&[3f32, 1., 4.], device)?;
@@ -505,6 +505,182 @@ fn binary_grad(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn second_order_grad(device: &Device) -> Result<()> {
+    let x = Var::new(&[-2f32, 1., 3.], device)?;
+    let x = x.as_tensor();
+    let y = (x * x)?.mul(x)?;
+    let grads = y.backward_with_graph()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    // dy/dx = 3.x^2
+    assert_eq!(grad_x.to_vec1::<f32>()?, [12., 3., 27.]);
+    let grads = grad_x.backward()?;
+    let grad2_x = grads.get(x).context("no grad for grad_x")?;
+    // d2y/dx2 = 6.x
+    assert_eq!(grad2_x.to_vec1::<f32>()?, [-12., 6., 18.]);
+
+    // Gradient penalty through a matmul and some activations, the penalty is the squared norm
+    // of dy/dx and gets differentiated with respect to w.
+    let w = Var::new(&[[1f32, 0., 2.], [0.5, -1., 0.]], device)?;
+    let x = Var::new(&[[0.1f32], [0.2], [0.3]], device)?;
+    let (w, x) = (w.as_tensor(), x.as_tensor());
+    let h = w.matmul(x)?.relu()?;
+    let y = (h.broadcast_add(&x.sum_all()?)?.tanh()?.sum_all()? + x.sum_all()?.exp()?)?;
+    let grads = y.backward_with_graph()?;
+    let grad_x = grads.get(x).context("no grad for x")?;
+    let penalty = grad_x.sqr()?.sum_all()?;
+    let grads = penalty.backward()?;
+    let grad_w = grads.get(w).context("no grad for w")?;
+    assert_eq!(grad_w.dims(), [2, 3]);
+    // Compare with finite differences on the penalty as a function of w[0, 0].
+    let penalty_at = |w00: f32| -> Result<f32> {
+        let w = Var::new(&[[w00, 0., 2.], [0.5, -1., 0.]], device)?;
+        let w = w.as_tensor();
+        let x = Var::new(&[[0.1f32], [0.2], [0.3]], device)?;
+        let x = x.as_tensor();
+        let h = w.matmul(x)?.relu()?;
+        let y = (h.broadcast_add(&x.sum_all()?)?.tanh()?.sum_all()? + x.sum_all()?.exp()?)?;
+        let grads = y.backward()?;
+        let grad_x = grads.get(x).context("no grad for x")?;
+        Ok(grad_x.sqr()?.sum_all()?.to_scalar::<f32>()?)
+    };
+    let eps = 1e-2;
+    let fd = (penalty_at(1. + eps)? - penalty_at(1. - eps)?) / (2. * eps);
+    let g = grad_w.to_vec2::<f32>()?[0][0];
+    assert!((fd - g).abs() < 1e-3, "{fd} {g}");
+    Ok(())
+}
+
+fn checkpoint_grad(device: &Device) -> Result<()> {
+    let w1 = Var::new(&[[0.5f32, -1.0, 0.3], [0.2, 0.8, -0.6]], device)?;
+    let b1 = Var::new(&[0.1f32, -0.2], device)?;
+    let w2 = Var::new(&[[1.5f32, -0.7]], device)?;
+    let b2 = Var::new(&[0.05f32], device)?;
+    let xs = Var::new(&[[1f32, 2., 3.], [-1., 0.5, 2.]], device)?;
+    let mlp = {
+        let (w1, b1, w2, b2) = (w1.clone(), b1.clone(), w2.clone(), b2.clone());
+        move |xs: &[Tensor]| -> candle_core::Result<Tensor> {
+            let h = xs[0].matmul(&w1.t()?)?.broadcast_add(&b1)?.gelu()?;
+            h.matmul(&w2.t()?)?.broadcast_add(&b2)
+        }
+    };
+    let expected = mlp(&[xs.as_tensor().clone()])?;
+    let ys = candle_core::checkpoint(mlp, &[xs.as_tensor().clone()])?;
+    assert_eq!(
+        test_utils::to_vec2_round(&ys, 4)?,
+        test_utils::to_vec2_round(&expected, 4)?
+    );
+    let loss = ys.sqr()?.sum_all()?;
+    let expected_loss = expected.sqr()?.sum_all()?;
+    let grads = loss.backward()?;
+    let expected_grads = expected_loss.backward()?;
+    for var in [&w1, &b1, &w2, &b2, &xs] {
+        let grad = grads.get(var).context("no grad")?.flatten_all()?;
+        let expected = expected_grads.get(var).context("no grad")?.flatten_all()?;
+        assert_eq!(
+            test_utils::to_vec1_round(&grad, 4)?,
+            test_utils::to_vec1_round(&expected, 4)?
+        );
+    }
+    Ok(())
+}
+
+// Identity op counting how many times its backward pass runs.
+struct CountBwd(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+impl candle_core::CustomOp1 for CountBwd {
+    fn name(&self) -> &'static str {
+        "count-bwd"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s: &CpuStorage,
+        l: &candle_core::Layout,
+    ) -> candle_core::Result<(CpuStorage, Shape)> {
+        match s {
+            CpuStorage::F32(s) => {
+                let s = candle_core::cpu_backend::unary_map(s, l, |v| v);
+                Ok((CpuStorage::F32(s), l.shape().clone()))
+            }
+            _ => candle_core::bail!("count-bwd only supports f32"),
+        }
+    }
+
+    fn bwd(
+        &self,
+        _arg: &Tensor,
+        _res: &Tensor,
+        grad_res: &Tensor,
+    ) -> candle_core::Result<Option<Tensor>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(Some(grad_res.clone()))
+    }
+}
+
+#[test]
+fn backward_for() -> Result<()> {
+    use std::sync::atomic::Ordering;
+    let device = &Device::Cpu;
+    let count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let base = Var::new(&[[0.5f32, -1.0, 0.3], [0.2, 0.8, -0.6]], device)?;
+    let lora_a = Var::new(&[[0.1f32, 0.2, -0.3]], device)?;
+    let lora_b = Var::new(&[[0.4f32], [-0.5]], device)?;
+    let xs = Tensor::new(&[[1f32, 2., 3.], [-1., 0.5, 2.]], device)?;
+    let frozen = base.apply_op1(CountBwd(count.clone()))?;
+    let ys = (xs.matmul(&frozen.t()?)? + xs.matmul(&lora_a.t()?)?.matmul(&lora_b.t()?)?)?;
+    let loss = ys.sqr()?.sum_all()?;
+
+    let grads = loss.backward()?;
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    let partial_grads = loss.backward_for(&[&lora_a, &lora_b])?;
+    // The frozen branch is not traversed.
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert!(partial_grads.get(&base).is_none());
+    assert!(partial_grads.get(&xs).is_none());
+    for var in [&lora_a, &lora_b] {
+        let grad = partial_grads.get(var).context("no grad")?;
+        let expected = grads.get(var).context("no grad")?;
+        assert_eq!(grad.to_vec2::<f32>()?, expected.to_vec2::<f32>()?);
+    }
+    Ok(())
+}
+
+#[test]
+fn autocast() -> Result<()> {
+    use candle_core::autocast::{autocast, autocast_with, AutocastConfig};
+    use candle_core::DType;
+    let device = &Device::Cpu;
+    let a = Var::new(&[[1f32, 2., 3.], [4., 5., 6.]], device)?;
+    let b = Var::new(&[[0.5f32, -1.], [2., 0.25], [-3., 1.5]], device)?;
+    let c = autocast(DType::F16, || {
+        let c = a.matmul(&b)?;
+        assert_eq!(c.dtype(), DType::F16);
+        // Nested blocks apply the innermost configuration.
+        let d = autocast_with(AutocastConfig::new(DType::F64), || a.matmul(&b))?;
+        assert_eq!(d.dtype(), DType::F64);
+        let no_ops = AutocastConfig {
+            reduced_ops: vec![],
+            f32_ops: vec![],
+            ..AutocastConfig::new(DType::F16)
+        };
+        let e = autocast_with(no_ops, || a.matmul(&b))?;
+        assert_eq!(e.dtype(), DType::F32);
+        assert_eq!(a.matmul(&b)?.dtype(), DType::F16);
+        Ok::<_, candle_core::Error>(c)
+    })?;
+    assert_eq!(a.matmul(&b)?.dtype(), DType::F32);
+
+    let grads = c.to_dtype(DType::F32)?.sum_all()?.backward()?;
+    let grad_a = grads.get(&a).context("no grad for a")?;
+    let grad_b = grads.get(&b).context("no grad for b")?;
+    assert_eq!(grad_a.dtype(), DType::F32);
+    assert_eq!(grad_b.dtype(), DType::F32);
+    let grads = a.matmul(&b)?.sum_all()?.backward()?;
+    test_utils::assert_close(grad_a, grads.get(&a).context("no grad for a")?, 1e-2, 1e-2);
+    test_utils::assert_close(grad_b, grads.get(&b).context("no grad for b")?, 1e-2, 1e-2);
+    Ok(())
+}
+
 test_device!(
     simple_grad,
     simple_grad_cpu,
@@ -531,3 +707,15 @@ test_device!(
     binary_grad_gpu,
     binary_grad_metal
 );
+test_device!(
+    second_order_grad,
+    second_order_grad_cpu,
+    second_order_grad_gpu,
+    second_order_grad_metal
+);
+test_device!(
+    checkpoint_grad,
+    checkpoint_grad_cpu,
+    checkpoint_grad_gpu,
+    checkpoint_grad_metal
+);
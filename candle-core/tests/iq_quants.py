@@ -0,0 +1,120 @@
+# Generates iq_quants.safetensors, random IQ blocks along with their dequantized values as
+# computed by the ggml dequantization kernels.
+#
+# The kernels and lookup tables are extracted from ggml-metal.metal in llama.cpp, e.g.
+# ggml/src/ggml-metal/ggml-metal.metal, and compiled as plain C so this only requires a C
+# compiler:
+#   python iq_quants.py path/to/ggml-metal.metal
+import json
+import os
+import random
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+metal = open(sys.argv[1]).read()
+
+# (name, number of blocks, block size in bytes, values per block, dequantize calls per block)
+TYPES = [
+    ("iq4_nl", 4, 18, 32, 2),
+    ("iq4_xs", 2, 136, 256, 16),
+    ("iq2_xxs", 2, 66, 256, 16),
+    ("iq3_s", 2, 110, 256, 16),
+]
+
+
+def extract(pattern):
+    m = re.search(pattern, metal, re.S)
+    assert m is not None, pattern
+    return m.group(0)
+
+
+def table(name):
+    return extract(r"GGML_TABLE_BEGIN\(\w+, " + name + r", \d+\).*?GGML_TABLE_END\(\)")
+
+
+def dequantize_fn(name):
+    body = extract(r"void dequantize_" + name + r"\(.*?\n}\n")
+    return body.replace("thread type4x4 & reg", "float reg[4][4]")
+
+
+c_src = "\n".join(
+    [
+        "#include <assert.h>",
+        "#include <stdint.h>",
+        "#include <stdio.h>",
+        "typedef _Float16 half;",
+        "#define device",
+        "#define thread",
+        "#define constant",
+        "#define constexpr",
+        "#define select(a, b, c) ((c) ? (b) : (a))",
+        "#define QK_K 256",
+        "#define QK4_NL 32",
+        "#define IQ3S_N_SCALE QK_K/64",
+        extract(r"typedef struct {[^}]*} block_iq2_xxs;"),
+        extract(r"typedef struct {[^}]*} block_iq3_s;"),
+        extract(r"typedef struct {[^}]*} block_iq4_nl;"),
+        extract(r"typedef struct {[^}]*} block_iq4_xs;"),
+        "#define GGML_TABLE_BEGIN(type, name, size) static const type name[size] = {",
+        "#define GGML_TABLE_END() };",
+        table("kmask_iq2xs"),
+        table("ksigns_iq2xs"),
+        table("iq2xxs_grid"),
+        table("iq3s_grid"),
+        extract(r"constexpr constant static float kvalues_iq4nl_f\[16\] = {[^}]*};"),
+    ]
+    + [dequantize_fn(name) for (name, _, _, _, _) in TYPES]
+)
+c_src += "\nint main(void) {\n    float reg[4][4];\n"
+for name, n_blocks, _, _, nl in TYPES:
+    c_src += f"""    for (int i = 0; i < {n_blocks}; ++i) {{
+        block_{name} xb;
+        if (fread(&xb, sizeof(xb), 1, stdin) != 1) return 1;
+        for (short il = 0; il < {nl}; ++il) {{
+            dequantize_{name}(&xb, il, reg);
+            fwrite(reg, sizeof(reg), 1, stdout);
+        }}
+    }}
+"""
+c_src += "    return 0;\n}\n"
+
+rng = random.Random(299792458)
+blocks = {}
+for name, n_blocks, block_size, _, _ in TYPES:
+    data = b""
+    for _ in range(n_blocks):
+        # Use a finite scale, all the other bit patterns are valid quantized values.
+        d = struct.pack("<e", rng.uniform(-0.05, 0.05))
+        data += d + bytes(rng.randrange(256) for _ in range(block_size - 2))
+    blocks[name] = data
+
+with tempfile.TemporaryDirectory() as tmp:
+    c_file = os.path.join(tmp, "iq_quants.c")
+    exe = os.path.join(tmp, "iq_quants")
+    with open(c_file, "w") as f:
+        f.write(c_src)
+    subprocess.run(["cc", "-O1", "-ffp-contract=off", "-o", exe, c_file], check=True)
+    stdin = b"".join(blocks[name] for (name, _, _, _, _) in TYPES)
+    dequantized = subprocess.run([exe], input=stdin, capture_output=True, check=True).stdout
+
+tensors = {}
+offset = 0
+for name, n_blocks, block_size, n_values, _ in TYPES:
+    values = dequantized[offset : offset + 4 * n_blocks * n_values]
+    offset += len(values)
+    tensors[name] = ("U8", [n_blocks * block_size], blocks[name])
+    tensors[name + "_dequantized"] = ("F32", [n_blocks * n_values], values)
+assert offset == len(dequantized)
+
+# Write the safetensors file by hand to avoid depending on the safetensors package.
+header, data = {}, b""
+for name, (dtype, shape, values) in tensors.items():
+    header[name] = {"dtype": dtype, "shape": shape, "data_offsets": [len(data), len(data) + len(values)]}
+    data += values
+header = json.dumps(header, separators=(",", ":")).encode()
+header += b" " * (-len(header) % 8)
+with open("iq_quants.safetensors", "wb") as f:
+    f.write(struct.pack("<Q", len(header)) + header + data)
@@ -0,0 +1,175 @@
+use candle_core::{test_device, test_utils, DType, Device, Result, Tensor, D};
+
+fn inverse(device: &Device) -> Result<()> {
+    let a = Tensor::new(&[[4f32, 7., 2.], [3., 6., 1.], [2., 5., 3.]], device)?;
+    let inv = a.inverse()?;
+    assert_eq!(inv.dtype(), DType::F32);
+    assert_eq!(
+        test_utils::to_vec2_round(&a.matmul(&inv)?, 4)?,
+        [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(&inv.inverse()?, 4)?,
+        a.to_vec2::<f32>()?
+    );
+
+    // Batched inverse, the second matrix requires pivoting.
+    let a = Tensor::new(&[[[2f64, 1.], [1., 1.]], [[0., 1.], [2., 0.]]], device)?;
+    let inv = a.inverse()?;
+    assert_eq!(
+        inv.to_vec3::<f64>()?,
+        [[[1., -1.], [-1., 2.]], [[0., 0.5], [1., 0.]]]
+    );
+
+    let singular = Tensor::new(&[[1f32, 2.], [2., 4.]], device)?;
+    assert!(singular.inverse().is_err());
+    let non_square = Tensor::new(&[[1f32, 2., 3.], [2., 4., 5.]], device)?;
+    assert!(non_square.inverse().is_err());
+    Ok(())
+}
+
+fn solve(device: &Device) -> Result<()> {
+    let a = Tensor::new(&[[4f32, 7., 2.], [3., 6., 1.], [2., 5., 3.]], device)?;
+    let b = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 6.]], device)?;
+    let x = a.solve(&b)?;
+    assert_eq!(x.dims(), [3, 2]);
+    assert_eq!(
+        test_utils::to_vec2_round(&x, 4)?,
+        test_utils::to_vec2_round(&a.inverse()?.matmul(&b)?, 4)?
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(&a.matmul(&x)?, 4)?,
+        b.to_vec2::<f32>()?
+    );
+
+    // Vector right-hand side.
+    let v = Tensor::new(&[1f32, 3., 5.], device)?;
+    let x = a.solve(&v)?;
+    assert_eq!(x.dims(), [3]);
+    assert_eq!(
+        test_utils::to_vec1_round(&x, 4)?,
+        test_utils::to_vec2_round(&a.solve(&b)?, 4)?
+            .iter()
+            .map(|r| r[0])
+            .collect::<Vec<_>>()
+    );
+
+    // The batch dimensions of the right-hand side get broadcasted.
+    let bs = Tensor::stack(&[&b, &(&b * 2.)?], 0)?;
+    let xs = a.solve(&bs)?;
+    assert_eq!(xs.dims(), [2, 3, 2]);
+    assert_eq!(
+        test_utils::to_vec3_round(&xs, 4)?,
+        test_utils::to_vec3_round(&a.inverse()?.broadcast_matmul(&bs)?, 4)?
+    );
+    Ok(())
+}
+
+fn cholesky(device: &Device) -> Result<()> {
+    let a = Tensor::new(
+        &[[4f32, 12., -16.], [12., 37., -43.], [-16., -43., 98.]],
+        device,
+    )?;
+    let l = a.cholesky(false)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&l, 4)?,
+        [[2., 0., 0.], [6., 1., 0.], [-8., 5., 3.]]
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(&l.matmul(&l.t()?)?, 4)?,
+        a.to_vec2::<f32>()?
+    );
+    let u = a.cholesky(true)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&u, 4)?,
+        [[2., 6., -8.], [0., 1., 5.], [0., 0., 3.]]
+    );
+    assert_eq!(
+        test_utils::to_vec2_round(&u.t()?.matmul(&u)?, 4)?,
+        a.to_vec2::<f32>()?
+    );
+
+    // Batched decomposition of random spd matrices.
+    let m = Tensor::randn(0f32, 1f32, (3, 4, 4), device)?;
+    let eye = Tensor::eye(4, DType::F32, device)?;
+    let a = m.matmul(&m.t()?)?.broadcast_add(&eye)?;
+    let l = a.cholesky(false)?;
+    let diff = (l.matmul(&l.t()?)? - &a)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_vec0::<f32>()?;
+    assert!(diff < 1e-4, "{diff}");
+
+    let not_pd = Tensor::new(&[[1f32, 2.], [2., 1.]], device)?;
+    assert!(not_pd.cholesky(false).is_err());
+    Ok(())
+}
+
+fn max_abs_diff(lhs: &Tensor, rhs: &Tensor) -> Result<f32> {
+    (lhs - rhs)?.abs()?.flatten_all()?.max(0)?.to_vec0::<f32>()
+}
+
+fn check_svd(a: &Tensor, full_matrices: bool) -> Result<()> {
+    let (m, n) = a.dims2()?;
+    let k = m.min(n);
+    let (u, s, v) = a.svd(full_matrices)?;
+    let (u_cols, v_cols) = if full_matrices { (m, n) } else { (k, k) };
+    assert_eq!(u.dims(), [m, u_cols]);
+    assert_eq!(s.dims(), [k]);
+    assert_eq!(v.dims(), [n, v_cols]);
+    let s_vec = s.to_vec1::<f32>()?;
+    assert!(s_vec.windows(2).all(|w| w[0] >= w[1]), "{s_vec:?}");
+    // U diag(S) V^T reconstructs the input.
+    let u_k = u.narrow(1, 0, k)?;
+    let v_k = v.narrow(1, 0, k)?;
+    let rec = u_k.broadcast_mul(&s.unsqueeze(0)?)?.matmul(&v_k.t()?)?;
+    assert!(max_abs_diff(&rec, a)? < 1e-4);
+    // U and V have orthonormal columns.
+    let eye = Tensor::eye(u_cols, DType::F32, a.device())?;
+    assert!(max_abs_diff(&u.t()?.matmul(&u)?, &eye)? < 1e-4);
+    let eye = Tensor::eye(v_cols, DType::F32, a.device())?;
+    assert!(max_abs_diff(&v.t()?.matmul(&v)?, &eye)? < 1e-4);
+    Ok(())
+}
+
+fn svd(device: &Device) -> Result<()> {
+    let a = Tensor::new(&[[3f32, 0.], [0., -2.]], device)?;
+    let (u, s, v) = a.svd(false)?;
+    assert_eq!(test_utils::to_vec1_round(&s, 4)?, [3., 2.]);
+    assert_eq!(
+        test_utils::to_vec2_round(&u.matmul(&v.t()?)?, 4)?,
+        [[1., 0.], [0., -1.]]
+    );
+
+    let tall = Tensor::randn(0f32, 1f32, (5, 3), device)?;
+    let wide = Tensor::randn(0f32, 1f32, (3, 5), device)?;
+    // A rank one matrix.
+    let singular = Tensor::new(
+        &[[1f32, 2., 3.], [2., 4., 6.], [3., 6., 9.], [1., 2., 3.]],
+        device,
+    )?;
+    for a in [&tall, &wide, &singular, &singular.t()?] {
+        check_svd(a, false)?;
+        check_svd(a, true)?;
+    }
+    let (_, s, _) = singular.svd(false)?;
+    assert_eq!(test_utils::to_vec1_round(&s, 4)?, [14.4914, 0., 0.]);
+
+    // Batched decomposition.
+    let a = Tensor::randn(0f32, 1f32, (2, 3, 4, 2), device)?;
+    let (u, s, v) = a.svd(false)?;
+    assert_eq!(u.dims(), [2, 3, 4, 2]);
+    assert_eq!(s.dims(), [2, 3, 2]);
+    assert_eq!(v.dims(), [2, 3, 2, 2]);
+    let rec = u
+        .broadcast_mul(&s.unsqueeze(D::Minus2)?)?
+        .matmul(&v.transpose(D::Minus2, D::Minus1)?)?;
+    assert!(max_abs_diff(&rec, &a)? < 1e-4);
+    Ok(())
+}
+
+test_device!(inverse, inverse_cpu, inverse_gpu, inverse_metal);
+test_device!(solve, solve_cpu, solve_gpu, solve_metal);
+test_device!(cholesky, cholesky_cpu, cholesky_gpu, cholesky_metal);
+test_device!(svd, svd_cpu, svd_gpu, svd_metal);
@@ -95,6 +95,43 @@ fn mm_layout(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn kron_outer(device: &Device) -> Result<()> {
+    let a = Tensor::new(&[[1f32, 2.], [3., 4.]], device)?;
+    let b = Tensor::new(&[[0f32, 5.], [6., 7.]], device)?;
+    assert_eq!(
+        a.kron(&b)?.to_vec2::<f32>()?,
+        &[
+            [0., 5., 0., 10.],
+            [6., 7., 12., 14.],
+            [0., 15., 0., 20.],
+            [18., 21., 24., 28.]
+        ]
+    );
+    // Non-square inputs and batch broadcasting.
+    let c = Tensor::new(&[[1f32, -1., 2.]], device)?;
+    assert_eq!(
+        a.kron(&c)?.to_vec2::<f32>()?,
+        &[[1., -1., 2., 2., -2., 4.], [3., -3., 6., 4., -4., 8.]]
+    );
+    let batch = Tensor::stack(&[&a, &(&a * 2.)?, &(&a * 3.)?], 0)?;
+    let out = batch.kron(&b)?;
+    assert_eq!(out.dims(), &[3, 4, 4]);
+    for idx in 0..3 {
+        let expected = (a.kron(&b)? * (idx + 1) as f64)?;
+        assert_eq!(out.i(idx)?.to_vec2::<f32>()?, expected.to_vec2::<f32>()?);
+    }
+    assert!(Tensor::new(&[1f32, 2.], device)?.kron(&b).is_err());
+
+    let u = Tensor::new(&[1f32, 2., 3.], device)?;
+    let v = Tensor::new(&[4f32, 5.], device)?;
+    assert_eq!(
+        u.outer(&v)?.to_vec2::<f32>()?,
+        &[[4., 5.], [8., 10.], [12., 15.]]
+    );
+    assert!(a.outer(&v).is_err());
+    Ok(())
+}
+
 test_device!(matmul, matmul_cpu, matmul_gpu, matmul_metal);
 test_device!(
     broadcast_matmul,
@@ -104,3 +141,4 @@ test_device!(
 );
 test_device!(squeeze_mm, squeeze_mm_cpu, squeeze_mm_gpu, squeeze_mm_metal);
 test_device!(mm_layout, mm_layout_cpu, mm_layout_gpu, mm_layout_metal);
+test_device!(kron_outer, kron_outer_cpu, kron_outer_gpu, kron_outer_metal);
@@ -101,6 +101,115 @@ fn upsample_nearest2d(dev: &Device) -> Result<()> {
     Ok(())
 }
 
+fn upsample_bilinear2d(dev: &Device) -> Result<()> {
+    let t = Tensor::new(&[[[[1f32, 2.], [3., 4.]]]], dev)?;
+    let upsampled = t.upsample_bilinear2d(4, 4, false)?.i(0)?.i(0)?;
+    assert_eq!(
+        upsampled.to_vec2::<f32>()?,
+        [
+            [1.0, 1.25, 1.75, 2.0],
+            [1.5, 1.75, 2.25, 2.5],
+            [2.5, 2.75, 3.25, 3.5],
+            [3.0, 3.25, 3.75, 4.0]
+        ]
+    );
+    let upsampled = t.upsample_bilinear2d(4, 4, true)?.i(0)?.i(0)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&upsampled, 4)?,
+        [
+            [1.0, 1.3333, 1.6667, 2.0],
+            [1.6667, 2.0, 2.3333, 2.6667],
+            [2.3333, 2.6667, 3.0, 3.3333],
+            [3.0, 3.3333, 3.6667, 4.0]
+        ]
+    );
+    // Same size without align_corners is the identity, downsampling averages neighbours.
+    let t = Tensor::arange(0f32, 16f32, dev)?.reshape((1, 1, 4, 4))?;
+    let same = t.upsample_bilinear2d(4, 4, false)?;
+    assert_eq!(
+        same.flatten_all()?.to_vec1::<f32>()?,
+        t.flatten_all()?.to_vec1::<f32>()?
+    );
+    let down = t.upsample_bilinear2d(2, 2, false)?.i(0)?.i(0)?;
+    assert_eq!(down.to_vec2::<f32>()?, [[2.5, 4.5], [10.5, 12.5]]);
+    Ok(())
+}
+
+fn max_pool2d_padding_dilation(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 16f32, dev)?.reshape((1, 1, 4, 4))?;
+    // The padded positions use -inf so the result is negative even if all inputs are.
+    let pool = (&t - 20.)?.max_pool2d_with_params(3, 2, 1, 1)?;
+    assert_eq!(
+        pool.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[-15., -13.], [-7., -5.]]
+    );
+    let pool = t.max_pool2d_with_params(2, 1, 0, 2)?;
+    assert_eq!(
+        pool.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[10., 11.], [14., 15.]]
+    );
+    let pool = t.max_pool2d_with_params(2, 2, 0, 1)?;
+    assert_eq!(
+        pool.flatten_all()?.to_vec1::<f32>()?,
+        t.max_pool2d(2)?.flatten_all()?.to_vec1::<f32>()?
+    );
+    assert!(t.max_pool2d_with_params(2, 2, 2, 1).is_err());
+    Ok(())
+}
+
+fn avg_pool2d_padding_dilation(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 16f32, dev)?.reshape((1, 1, 4, 4))?;
+    let pool = t.avg_pool2d_with_params(3, 2, 1, 1, true)?;
+    assert_eq!(
+        test_utils::to_vec2_round(&pool.squeeze(0)?.squeeze(0)?, 4)?,
+        [[1.1111, 2.6667], [5.6667, 10.0]]
+    );
+    let pool = t.avg_pool2d_with_params(3, 2, 1, 1, false)?;
+    assert_eq!(
+        pool.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[2.5, 4.], [8.5, 10.]]
+    );
+    let pool = t.avg_pool2d_with_params(2, 1, 0, 2, false)?;
+    assert_eq!(
+        pool.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[5., 6.], [9., 10.]]
+    );
+    Ok(())
+}
+
+fn pool1d(dev: &Device) -> Result<()> {
+    let t = Tensor::new(&[[[1f32, 3., 2., 5., 4., 0.]]], dev)?;
+    assert_eq!(t.max_pool1d(2)?.to_vec3::<f32>()?, [[[3., 5., 4.]]]);
+    assert_eq!(t.avg_pool1d(2)?.to_vec3::<f32>()?, [[[2., 3.5, 2.]]]);
+    let pool = t.max_pool1d_with_params(3, 2, 1, 1)?;
+    assert_eq!(pool.to_vec3::<f32>()?, [[[3., 5., 5.]]]);
+    let pool = t.max_pool1d_with_params(2, 1, 0, 3)?;
+    assert_eq!(pool.to_vec3::<f32>()?, [[[5., 4., 2.]]]);
+    let pool = t.avg_pool1d_with_params(3, 2, 1, 1, false)?;
+    assert_eq!(test_utils::to_vec3_round(&pool, 4)?, [[[2., 3.3333, 3.]]]);
+    let pool = t.avg_pool1d_with_params(3, 2, 1, 1, true)?;
+    assert_eq!(
+        test_utils::to_vec3_round(&pool, 4)?,
+        [[[1.3333, 3.3333, 3.]]]
+    );
+    Ok(())
+}
+
+fn adaptive_avg_pool2d(dev: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 35f32, dev)?.reshape((1, 1, 5, 7))?;
+    // The sizes are not divisible so the windows overlap.
+    let pool = t.adaptive_avg_pool2d((3, 2))?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(pool.to_vec2::<f32>()?, [[5., 8.], [15.5, 18.5], [26., 29.]]);
+    let pool = t.adaptive_avg_pool2d(1)?.flatten_all()?;
+    assert_eq!(pool.to_vec1::<f32>()?, [17.]);
+    let pool = t.adaptive_avg_pool2d((5, 7))?;
+    assert_eq!(
+        pool.flatten_all()?.to_vec1::<f32>()?,
+        t.flatten_all()?.to_vec1::<f32>()?
+    );
+    Ok(())
+}
+
 test_device!(avg_pool2d, avg_pool2d_cpu, avg_pool2d_gpu, avg_pool2d_metal);
 test_device!(
     avg_pool2d_pytorch,
@@ -115,3 +224,28 @@ test_device!(
     upsample_nearest2d_gpu,
     upsample_nearest2d_metal
 );
+test_device!(
+    max_pool2d_padding_dilation,
+    max_pool2d_padding_dilation_cpu,
+    max_pool2d_padding_dilation_gpu,
+    max_pool2d_padding_dilation_metal
+);
+test_device!(
+    avg_pool2d_padding_dilation,
+    avg_pool2d_padding_dilation_cpu,
+    avg_pool2d_padding_dilation_gpu,
+    avg_pool2d_padding_dilation_metal
+);
+test_device!(pool1d, pool1d_cpu, pool1d_gpu, pool1d_metal);
+test_device!(
+    adaptive_avg_pool2d,
+    adaptive_avg_pool2d_cpu,
+    adaptive_avg_pool2d_gpu,
+    adaptive_avg_pool2d_metal
+);
+test_device!(
+    upsample_bilinear2d,
+    upsample_bilinear2d_cpu,
+    upsample_bilinear2d_gpu,
+    upsample_bilinear2d_metal
+);
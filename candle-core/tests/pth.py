@@ -35,3 +35,22 @@ print("Tensor stride:", tensor_fortran.stride())  # Stride will reflect the Fort
 torch.save({"tensor_fortran": tensor_fortran}, 'fortran_tensor_3d.pth')
 
 print("3D Tensor saved with Fortran layout.")
+
+############################################################################################################
+# Write non-contiguous views sharing the same storage.
+x = torch.arange(12, dtype=torch.float32).reshape(3, 4)
+torch.save(
+    {"base": x, "slice": x[:, 1:3], "step": x[::2, ::2], "transposed": x.t()[1:, :2]},
+    "strided_view.pt",
+)
+
+############################################################################################################
+# Write a nested state dict using parameters.
+encoder = torch.nn.Linear(3, 2)
+with torch.no_grad():
+    encoder.weight.copy_(torch.tensor([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]))
+    encoder.bias.copy_(torch.tensor([7.0, 8.0]))
+state_dict = OrderedDict()
+state_dict["encoder"] = OrderedDict(encoder.named_parameters())
+state_dict["layers"] = [torch.tensor([1, 2, 3]), torch.tensor([4, 5])]
+torch.save({"model": state_dict, "epoch": 3}, "nested_state_dict.pt")
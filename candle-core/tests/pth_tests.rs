@@ -29,3 +29,51 @@ fn test_pth_fortran_congiguous() {
         ]
     );
 }
+
+#[test]
+fn test_pth_strided_view() {
+    let tensors = candle_core::pickle::PthTensors::new("tests/strided_view.pt", None).unwrap();
+    let get = |name| {
+        tensors
+            .get(name)
+            .unwrap()
+            .unwrap()
+            .to_vec2::<f32>()
+            .unwrap()
+    };
+    assert_eq!(
+        get("base"),
+        [[0., 1., 2., 3.], [4., 5., 6., 7.], [8., 9., 10., 11.]]
+    );
+    assert_eq!(get("slice"), [[1., 2.], [5., 6.], [9., 10.]]);
+    assert_eq!(get("step"), [[0., 2.], [8., 10.]]);
+    assert_eq!(get("transposed"), [[1., 5.], [2., 6.], [3., 7.]]);
+}
+
+#[test]
+fn test_pth_nested_state_dict() {
+    let tensors = candle_core::pickle::read_all("tests/nested_state_dict.pt").unwrap();
+    let mut names = tensors.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "model.encoder.bias",
+            "model.encoder.weight",
+            "model.layers.0",
+            "model.layers.1"
+        ]
+    );
+
+    let tensors =
+        candle_core::pickle::PthTensors::new("tests/nested_state_dict.pt", Some("model")).unwrap();
+    let weight = tensors.get("encoder.weight").unwrap().unwrap();
+    assert_eq!(
+        weight.to_vec2::<f32>().unwrap(),
+        [[1., 2., 3.], [4., 5., 6.]]
+    );
+    let bias = tensors.get("encoder.bias").unwrap().unwrap();
+    assert_eq!(bias.to_vec1::<f32>().unwrap(), [7., 8.]);
+    let layer = tensors.get("layers.1").unwrap().unwrap();
+    assert_eq!(layer.to_vec1::<i64>().unwrap(), [4, 5]);
+}
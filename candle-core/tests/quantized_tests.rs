@@ -101,6 +101,7 @@ fn quantized_matmul(device: &Device) -> Result<()> {
                 [341876.0, 994283.0, 1655709.0, 2301518.0]
             ]
         ),
+        Device::Wgpu(_) => unreachable!("quantized tensors are not supported on wgpu"),
     }
     test_matmul(device, (1, 3, 4, 256), GgmlDType::Q4_0)?;
     Ok(())
@@ -165,6 +166,7 @@ fn quantized_matmul_neg(device: &Device) -> Result<()> {
                 [-196472.0, 63012.0, 324585.0, 587902.0]
             ]
         ),
+        Device::Wgpu(_) => unreachable!("quantized tensors are not supported on wgpu"),
     }
     let lhs2 = Tensor::stack(&[&lhs, &lhs], 0)?;
     let res2 = matmul.forward(&lhs2)?;
@@ -799,6 +801,9 @@ fn ggml_reference_matmul_error(dtype: GgmlDType) -> Result<f32> {
 
         // Not from the ggml repo.
         GgmlDType::Q8K => 0.00065,
+        GgmlDType::IQ4NL => 0.0028,
+        GgmlDType::IQ4XS => 0.0022,
+        GgmlDType::IQ3S => 0.0046,
         _ => bail!("No GGML results for quantization type {dtype:?}",),
     };
     Ok(err)
@@ -1149,3 +1154,281 @@ fn quantized_matmul_q8k() -> Result<()> {
     ggml_matmul_error_test::<BlockQ8K>()?;
     Ok(())
 }
+
+#[test]
+fn gguf_write_read() -> Result<()> {
+    use quantized::gguf_file::{Content, GgufWriter, Value};
+
+    let dev = &Device::Cpu;
+    let t = Tensor::arange(0f32, 256f32, dev)?.reshape((4, 64))?;
+    let q4 = quantized::QTensor::quantize(&t, GgmlDType::Q4_0)?;
+    let q8 = quantized::QTensor::quantize(&t.affine(0.5, -1.)?, GgmlDType::Q8_0)?;
+    let q4k = quantized::QTensor::quantize(
+        &Tensor::arange(0f32, 512f32, dev)?.reshape((2, 256))?,
+        GgmlDType::Q4K,
+    )?;
+    let f32s = quantized::QTensor::quantize(&Tensor::new(&[1f32, -2., 3.5], dev)?, GgmlDType::F32)?;
+
+    let mut writer = GgufWriter::new();
+    writer
+        .add_metadata("general.architecture", "llama")
+        .add_metadata("llama.context_length", 4096u32)
+        .add_metadata("llama.rope.freq_base", 10000f32)
+        .add_metadata("llama.rope.scaling.enabled", false)
+        .add_metadata(
+            "tokenizer.ggml.tokens",
+            vec![Value::from("<s>"), Value::from("</s>")],
+        )
+        .add_tensor("q4", &q4)
+        .add_tensor("q8", &q8)
+        .add_tensor("q4k", &q4k)
+        .add_tensor("f32", &f32s);
+    let mut buffer = std::io::Cursor::new(vec![]);
+    writer.write(&mut buffer)?;
+
+    buffer.set_position(0);
+    let content = Content::read(&mut buffer)?;
+    let metadata = content.metadata();
+    assert_eq!(metadata.len(), 5);
+    assert_eq!(metadata["general.architecture"].to_string()?, "llama");
+    assert_eq!(metadata["llama.context_length"].to_u32()?, 4096);
+    assert_eq!(metadata["llama.rope.freq_base"].to_f32()?, 10000.);
+    assert!(!metadata["llama.rope.scaling.enabled"].to_bool()?);
+    assert_eq!(
+        metadata["tokenizer.ggml.tokens"],
+        Value::Array(vec![Value::from("<s>"), Value::from("</s>")])
+    );
+    assert_eq!(content.tensor_infos().len(), 4);
+    for (name, expected) in [("q4", &q4), ("q8", &q8), ("q4k", &q4k), ("f32", &f32s)] {
+        let qt = content.tensor(&mut buffer, name, dev)?;
+        assert_eq!(qt.dtype(), expected.dtype());
+        assert_eq!(qt.shape(), expected.shape());
+        assert_eq!(qt.data()?, expected.data()?);
+    }
+
+    // Only the default alignment is supported when writing.
+    writer.add_metadata("general.alignment", 64u32);
+    assert!(writer.write(&mut std::io::Cursor::new(vec![])).is_err());
+    Ok(())
+}
+
+// The IQ4_NL grid, kvalues_iq4nl in llama.cpp.
+const KVALUES_IQ4NL: [f32; 16] = [
+    -127., -104., -83., -65., -49., -35., -22., -10., 1., 13., 25., 38., 53., 69., 89., 113.,
+];
+
+#[test]
+fn dequantize_iq4_nl() -> Result<()> {
+    // A single block with d = 0.5, the low nibbles go through the grid in increasing order and
+    // the high nibbles in decreasing order.
+    let mut raw = vec![0x00, 0x38];
+    raw.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    let qt =
+        quantized::ggml_file::qtensor_from_ggml(GgmlDType::IQ4NL, &raw, vec![32], &Device::Cpu)?;
+    let dst = qt.dequantize(&Device::Cpu)?.to_vec1::<f32>()?;
+    assert_eq!(
+        dst[..16],
+        [
+            -63.5, -52., -41.5, -32.5, -24.5, -17.5, -11., -5., 0.5, 6.5, 12.5, 19., 26.5, 34.5,
+            44.5, 56.5
+        ]
+    );
+    assert_eq!(
+        dst[16..],
+        [
+            56.5, 44.5, 34.5, 26.5, 19., 12.5, 6.5, 0.5, -5., -11., -17.5, -24.5, -32.5, -41.5,
+            -52., -63.5
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn dequantize_iq4_xs() -> Result<()> {
+    // A single super-block with d = 0.25, the 6 bits sub-block scales are
+    // [32, 33, 31, 40, 0, 63, 16, 48], i.e. [0, 1, -1, 8, -32, 31, -16, 16] after removing
+    // the offset.
+    let mut raw = vec![0x00, 0x34, 0x9A, 0xDC, 0x10, 0x8F, 0xF0, 0x00];
+    for _ib in 0..8 {
+        raw.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    }
+    assert_eq!(raw.len(), 136);
+    let qt =
+        quantized::ggml_file::qtensor_from_ggml(GgmlDType::IQ4XS, &raw, vec![256], &Device::Cpu)?;
+    let dst = qt.dequantize(&Device::Cpu)?.to_vec1::<f32>()?;
+    let scales = [0., 1., -1., 8., -32., 31., -16., 16.];
+    for (ib, scale) in scales.iter().enumerate() {
+        let dl = 0.25 * scale;
+        for j in 0..16 {
+            assert_eq!(dst[32 * ib + j], dl * KVALUES_IQ4NL[j]);
+            assert_eq!(dst[32 * ib + j + 16], dl * KVALUES_IQ4NL[15 - j]);
+        }
+    }
+    assert_eq!(dst[32..35], [-31.75, -26., -20.75]);
+    assert_eq!(dst[128..131], [1016., 832., 664.]);
+    Ok(())
+}
+
+#[test]
+fn quantize_iq4() -> Result<()> {
+    for dtype in [GgmlDType::IQ4NL, GgmlDType::IQ4XS] {
+        let src = get_test_vector2(0.5, 1024, &Device::Cpu)?;
+        let quant = quantized::QTensor::quantize(&src, dtype)?;
+        let dst = quant.dequantize(&Device::Cpu)?;
+        compare_with_error(
+            dst.to_vec1::<f32>()?.as_slice(),
+            src.to_vec1::<f32>()?.as_slice(),
+            0.05,
+        );
+        ggml_quantization_error_test(dtype, &Device::Cpu, GGML_MAX_QUANTIZATION_TOTAL_ERROR)?;
+    }
+    ggml_matmul_error_test::<k_quants::BlockIQ4NL>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ4XS>()?;
+    Ok(())
+}
+
+#[test]
+fn dequantize_iq_reference() -> Result<()> {
+    // Random blocks along with their values dequantized by the ggml kernels, see iq_quants.py.
+    let dev = &Device::Cpu;
+    let tensors = candle_core::safetensors::load("tests/iq_quants.safetensors", dev)?;
+    for (name, dtype) in [
+        ("iq4_nl", GgmlDType::IQ4NL),
+        ("iq4_xs", GgmlDType::IQ4XS),
+        ("iq2_xxs", GgmlDType::IQ2XXS),
+        ("iq3_s", GgmlDType::IQ3S),
+    ] {
+        let raw = tensors[name].to_vec1::<u8>()?;
+        let expected = tensors[&format!("{name}_dequantized")].to_vec1::<f32>()?;
+        let qt = quantized::ggml_file::qtensor_from_ggml(dtype, &raw, vec![expected.len()], dev)?;
+        let dst = qt.dequantize(dev)?.to_vec1::<f32>()?;
+        assert_eq!(dst, expected, "{name}");
+    }
+    Ok(())
+}
+
+// Checks the vec-dot of the blocks from the reference file against the dot product of the
+// dequantized values.
+fn iq_vec_dot_test<T: GgmlType>(name: &str) -> Result<()> {
+    let dev = &Device::Cpu;
+    let tensors = candle_core::safetensors::load("tests/iq_quants.safetensors", dev)?;
+    let raw = tensors[name].to_vec1::<u8>()?;
+    let xs_f32 = tensors[&format!("{name}_dequantized")].to_vec1::<f32>()?;
+    let n = xs_f32.len();
+    let mut xs = vec![T::zeros(); n / T::BLCK_SIZE];
+    assert_eq!(raw.len(), xs.len() * std::mem::size_of::<T>());
+    unsafe { std::ptr::copy_nonoverlapping(raw.as_ptr(), xs.as_mut_ptr() as *mut u8, raw.len()) };
+
+    let ys_f32 = (0..n).map(|i| (i as f32 * 0.37).sin()).collect::<Vec<_>>();
+    let mut ys = vec![T::VecDotType::zeros(); n / T::VecDotType::BLCK_SIZE];
+    T::VecDotType::from_float(&ys_f32, &mut ys)?;
+    let mut ys_f32 = vec![0f32; n];
+    T::VecDotType::to_float(&ys, &mut ys_f32)?;
+
+    let result = T::vec_dot(n, &xs, &ys)?;
+    let result_unopt = T::vec_dot_unopt(n, &xs, &ys)?;
+    let expected = vec_dot_reference(&xs_f32, &ys_f32);
+    let tolerance = 1e-5
+        * xs_f32
+            .iter()
+            .zip(ys_f32.iter())
+            .map(|(x, y)| (x * y).abs())
+            .sum::<f32>();
+    assert!(
+        (result - expected).abs() <= tolerance,
+        "{name}: {result} {expected}"
+    );
+    assert!(
+        (result_unopt - expected).abs() <= tolerance,
+        "{name}: {result_unopt} {expected}"
+    );
+    Ok(())
+}
+
+#[test]
+fn vec_dot_iq() -> Result<()> {
+    iq_vec_dot_test::<k_quants::BlockIQ4NL>("iq4_nl")?;
+    iq_vec_dot_test::<k_quants::BlockIQ4XS>("iq4_xs")?;
+    iq_vec_dot_test::<k_quants::BlockIQ2XXS>("iq2_xxs")?;
+    iq_vec_dot_test::<k_quants::BlockIQ3S>("iq3_s")?;
+    Ok(())
+}
+
+#[test]
+fn quantize_iq3_s() -> Result<()> {
+    // The IQ3_S grid has few points with close coordinates so slowly varying values as returned
+    // by get_test_vector2 are poorly approximated, only the ggml error bounds are checked.
+    ggml_quantization_error_test(
+        GgmlDType::IQ3S,
+        &Device::Cpu,
+        GGML_MAX_QUANTIZATION_TOTAL_ERROR_3BITS,
+    )?;
+    ggml_matmul_error_test::<k_quants::BlockIQ3S>()?;
+
+    // As in llama.cpp, quantizing to IQ2_XXS requires an importance matrix.
+    let src = Tensor::zeros(256, DType::F32, &Device::Cpu)?;
+    assert!(quantized::QTensor::quantize(&src, GgmlDType::IQ2XXS).is_err());
+    Ok(())
+}
+
+#[test]
+fn quantize_reference_block() -> Result<()> {
+    // The expected blocks match the output of the llama.cpp reference quantization functions,
+    // quantize_row_q{4_0,5_0,8_0}_ref, for the input (j - 16) / 4.
+    let dev = &Device::Cpu;
+    let src = Tensor::arange(-16f32, 16f32, dev)?.affine(0.25, 0.)?;
+
+    let q4_0 = quantized::QTensor::quantize(&src, GgmlDType::Q4_0)?;
+    let mut expected = vec![0x00, 0x38];
+    expected.extend([
+        128u8, 145, 145, 162, 162, 179, 179, 196, 196, 213, 213, 230, 230, 247, 247, 248,
+    ]);
+    assert_eq!(q4_0.data()?.to_vec(), expected);
+
+    let q5_0 = quantized::QTensor::quantize(&src, GgmlDType::Q5_0)?;
+    let mut expected = vec![0x00, 0x34, 0x00, 0x00, 0xFF, 0xFF];
+    expected.extend((0..16u8).map(|j| j | (j << 4)));
+    assert_eq!(q5_0.data()?.to_vec(), expected);
+
+    let q8_0 = quantized::QTensor::quantize(&src, GgmlDType::Q8_0)?;
+    let mut expected = vec![0x08, 0x28];
+    expected.extend(
+        [
+            -127i8, -119, -111, -103, -95, -87, -79, -71, -64, -56, -48, -40, -32, -24, -16, -8, 0,
+            8, 16, 24, 32, 40, 48, 56, 64, 71, 79, 87, 95, 103, 111, 119,
+        ]
+        .map(|v| v as u8),
+    );
+    assert_eq!(q8_0.data()?.to_vec(), expected);
+
+    // Round trips stay within the expected error bounds.
+    let src = get_test_vector2(1.0, 1024, dev)?;
+    for (dtype, max_err) in [
+        (GgmlDType::Q4_0, 0.07),
+        (GgmlDType::Q5_0, 0.035),
+        (GgmlDType::Q8_0, 0.005),
+        (GgmlDType::Q4K, 0.07),
+    ] {
+        let dst = quantized::QTensor::quantize(&src, dtype)?.dequantize(dev)?;
+        compare_with_error(
+            dst.to_vec1::<f32>()?.as_slice(),
+            src.to_vec1::<f32>()?.as_slice(),
+            max_err,
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn quantize_view() -> Result<()> {
+    // Quantizing a view only uses the elements from the view.
+    let dev = &Device::Cpu;
+    let t = Tensor::arange(0f32, 512f32, dev)?.reshape((4, 128))?;
+    for view in [t.narrow(0, 1, 2)?, t.narrow(0, 0, 2)?, t.narrow(1, 32, 64)?] {
+        let q = quantized::QTensor::quantize(&view, GgmlDType::Q8_0)?;
+        let expected = quantized::QTensor::quantize(&view.force_contiguous()?, GgmlDType::Q8_0)?;
+        assert_eq!(q.shape(), view.shape());
+        assert_eq!(q.data()?, expected.data()?);
+    }
+    Ok(())
+}
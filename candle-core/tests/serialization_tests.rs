@@ -49,6 +49,47 @@ fn npz() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn npz_roundtrip() -> Result<()> {
+    let dev = &candle_core::Device::Cpu;
+    let tmp_file = TmpFile::create("npz");
+    let f32s = Tensor::arange(0f32, 6f32, dev)?.reshape((2, 3))?;
+    let f64s = Tensor::new(&[1.5f64, -2.5], dev)?;
+    let i64s = Tensor::new(&[[-1i64], [2], [-3]], dev)?;
+    let u8s = Tensor::new(&[3u8, 1, 4, 1, 5], dev)?;
+    // A fortran contiguous tensor gets written in fortran order.
+    let transposed = Tensor::arange(0u32, 12u32, dev)?.reshape((3, 4))?.t()?;
+    let scalar = Tensor::new(42f32, dev)?;
+    candle_core::npy::write_npz(
+        &tmp_file,
+        &[
+            ("f32s", &f32s),
+            ("f64s", &f64s),
+            ("i64s", &i64s),
+            ("u8s", &u8s),
+            ("transposed", &transposed),
+            ("scalar", &scalar),
+        ],
+    )?;
+    let ts = candle_core::npy::read_npz(&tmp_file)?;
+    assert_eq!(ts.len(), 6);
+    assert_eq!(ts["f32s"].to_vec2::<f32>()?, f32s.to_vec2::<f32>()?);
+    assert_eq!(ts["f64s"].to_vec1::<f64>()?, [1.5, -2.5]);
+    assert_eq!(ts["i64s"].to_vec2::<i64>()?, [[-1], [2], [-3]]);
+    assert_eq!(ts["u8s"].to_vec1::<u8>()?, [3, 1, 4, 1, 5]);
+    assert_eq!(ts["transposed"].dims(), [4, 3]);
+    assert_eq!(
+        ts["transposed"].to_vec2::<u32>()?,
+        transposed.to_vec2::<u32>()?
+    );
+    assert_eq!(ts["scalar"].to_vec0::<f32>()?, 42.);
+
+    let npz = candle_core::npy::NpzTensors::new(&tmp_file)?;
+    let t = npz.get("transposed")?.unwrap();
+    assert_eq!(t.to_vec2::<u32>()?, transposed.to_vec2::<u32>()?);
+    Ok(())
+}
+
 #[test]
 fn safetensors() -> Result<()> {
     use candle_core::safetensors::Load;
@@ -62,10 +103,136 @@ fn safetensors() -> Result<()> {
     let diff = (&t - t2)?.abs()?.sum_all()?.to_vec0::<f32>()?;
     assert_eq!(diff, 0f32);
     // Load from bytes.
-    let bytes = std::fs::read(tmp_file)?;
+    let bytes = std::fs::read(&tmp_file)?;
     let st = candle_core::safetensors::SliceSafetensors::new(&bytes)?;
     let t2 = st.get("t").unwrap().load(&candle_core::Device::Cpu);
     let diff = (&t - t2)?.abs()?.sum_all()?.to_vec0::<f32>()?;
     assert_eq!(diff, 0f32);
+
+    // Complex tensors are not supported by the safetensors format.
+    let c = Tensor::complex(&t, &t)?;
+    assert!(c.save_safetensors("c", &tmp_file).is_err());
+    let ts = std::collections::HashMap::from([("t", t), ("c", c)]);
+    assert!(candle_core::safetensors::save(&ts, &tmp_file).is_err());
+    assert!(candle_core::safetensors::serialize([("c", &ts["c"])]).is_err());
+    let bytes = candle_core::safetensors::serialize([("t", &ts["t"])])?;
+    let t2 = candle_core::safetensors::load_buffer(&bytes, &candle_core::Device::Cpu)?;
+    assert_eq!(t2["t"].to_vec1::<f32>()?, ts["t"].to_vec1::<f32>()?);
+    Ok(())
+}
+
+// A reader that keeps track of the number of bytes that have been read.
+struct CountingReader<R> {
+    inner: R,
+    count: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n, std::sync::atomic::Ordering::SeqCst);
+        Ok(n)
+    }
+}
+
+impl<R: std::io::Seek> std::io::Seek for CountingReader<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn safetensors_reader() -> Result<()> {
+    use std::sync::atomic::Ordering;
+
+    let dev = &candle_core::Device::Cpu;
+    let tmp_file = TmpFile::create("st_reader");
+    let a = Tensor::arange(0f32, 1000f32, dev)?.reshape((10, 100))?;
+    let b = Tensor::arange(0u32, 6u32, dev)?.reshape((2, 3))?;
+    let c = Tensor::arange(0i64, 2000i64, dev)?;
+    let ts = std::collections::HashMap::from([("a", a), ("b", b.clone()), ("c", c)]);
+    candle_core::safetensors::save(&ts, &tmp_file)?;
+    let file_len = std::fs::metadata(&tmp_file)?.len() as usize;
+
+    let count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let reader = CountingReader {
+        inner: std::fs::File::open(&tmp_file)?,
+        count: count.clone(),
+    };
+    let st = candle_core::safetensors::SafeTensorsReader::new(reader)?;
+    let header_len = count.load(Ordering::SeqCst);
+    // Only the header has been read, the data section is 4000 + 24 + 16000 bytes.
+    assert_eq!(header_len, file_len - 20024);
+    assert_eq!(st.tensor_names(), ["a", "b", "c"]);
+    let info = st.tensor_info("b")?;
+    assert_eq!(info.shape, [2, 3]);
+    assert_eq!(info.dtype, safetensors::Dtype::U32);
+
+    // Loading `b` only reads its 24 bytes.
+    let b2 = st.load("b", dev)?;
+    assert_eq!(count.load(Ordering::SeqCst) - header_len, 24);
+    assert_eq!(b2.to_vec2::<u32>()?, b.to_vec2::<u32>()?);
+    assert!(st.load("d", dev).is_err());
+
+    let st = candle_core::safetensors::SafeTensorsReader::from_file(&tmp_file)?;
+    let a = st.load("a", dev)?;
+    assert_eq!(a.dims(), [10, 100]);
+    assert_eq!(a.sum_all()?.to_vec0::<f32>()?, 499500.);
+    Ok(())
+}
+
+#[test]
+fn safetensors_sharded() -> Result<()> {
+    let dev = &candle_core::Device::Cpu;
+    let dir = std::env::temp_dir().join(format!("candle-sharded-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let a = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?;
+    let b = Tensor::new(&[5u32, 6, 7], dev)?;
+    let c = Tensor::new(&[8f32], dev)?;
+    let shard1 = std::collections::HashMap::from([("a", a.clone()), ("b", b.clone())]);
+    let shard2 = std::collections::HashMap::from([("c", c.clone())]);
+    candle_core::safetensors::save(&shard1, dir.join("model-00001-of-00002.safetensors"))?;
+    candle_core::safetensors::save(&shard2, dir.join("model-00002-of-00002.safetensors"))?;
+    let index = dir.join("model.safetensors.index.json");
+    std::fs::write(
+        &index,
+        r#"{
+  "metadata": {"total_size": 32},
+  "weight_map": {
+    "a": "model-00001-of-00002.safetensors",
+    "b": "model-00001-of-00002.safetensors",
+    "c": "model-00002-of-00002.safetensors"
+  }
+}"#,
+    )?;
+
+    let ts = candle_core::safetensors::load_sharded(&index, dev)?;
+    assert_eq!(ts.len(), 3);
+    assert_eq!(ts["a"].to_vec2::<f32>()?, [[1., 2.], [3., 4.]]);
+    assert_eq!(ts["b"].to_vec1::<u32>()?, [5, 6, 7]);
+    assert_eq!(ts["c"].to_vec1::<f32>()?, [8.]);
+
+    let st = unsafe { candle_core::safetensors::MmapedSafetensors::from_index(&index)? };
+    assert_eq!(st.load("b", dev)?.to_vec1::<u32>()?, [5, 6, 7]);
+    assert_eq!(st.load("c", dev)?.to_vec1::<f32>()?, [8.]);
+    assert!(st.load("d", dev).is_err());
+
+    // The index maps a tensor to a shard that does not contain it.
+    std::fs::write(
+        &index,
+        r#"{"weight_map": {"a": "model-00001-of-00002.safetensors", "d": "model-00002-of-00002.safetensors"}}"#,
+    )?;
+    assert!(candle_core::safetensors::load_sharded(&index, dev).is_err());
+    assert!(unsafe { candle_core::safetensors::MmapedSafetensors::from_index(&index) }.is_err());
+
+    // The index references a missing shard.
+    std::fs::write(
+        &index,
+        r#"{"weight_map": {"a": "model-00001-of-00003.safetensors"}}"#,
+    )?;
+    assert!(candle_core::safetensors::load_sharded(&index, dev).is_err());
+    assert!(unsafe { candle_core::safetensors::MmapedSafetensors::from_index(&index) }.is_err());
+
+    std::fs::remove_dir_all(&dir)?;
     Ok(())
 }
@@ -96,6 +96,58 @@ fn clamp(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn clamp_tensor_lerp(device: &Device) -> Result<()> {
+    let data = [[3f32, -1., 4., 1.5, 5.], [2., 1., -7., 8., 0.5]];
+    let mins = [0f32, -2., 1., 2., 0.];
+    let maxs = [4f32, 1.];
+    let tensor = Tensor::new(&data, device)?;
+    let min = Tensor::new(&mins, device)?;
+    let max = Tensor::new(&maxs, device)?.reshape((2, 1))?;
+    let clamped = tensor.clamp_tensor(&min, &max)?;
+    let expected: Vec<Vec<f32>> = data
+        .iter()
+        .zip(maxs.iter())
+        .map(|(row, max)| {
+            row.iter()
+                .zip(mins.iter())
+                .map(|(v, min)| v.max(*min).min(*max))
+                .collect()
+        })
+        .collect();
+    assert_eq!(clamped.to_vec2::<f32>()?, expected);
+    assert!(tensor
+        .clamp_tensor(&Tensor::new(&[0f32, 1.], device)?, &max)
+        .is_err());
+
+    let start = [1f64, -2., 3., 0.5];
+    let end = [[2f64, 2., -3., 0.5], [0., 1., 1., 4.]];
+    let weights = [0.5f64, 0.25, 1., -0.5];
+    let lerp = Tensor::new(&start, device)?
+        .lerp(&Tensor::new(&end, device)?, &Tensor::new(&weights, device)?)?;
+    let expected: Vec<Vec<f64>> = end
+        .iter()
+        .map(|row| {
+            row.iter()
+                .zip(start.iter().zip(weights.iter()))
+                .map(|(e, (s, w))| s + w * (e - s))
+                .collect()
+        })
+        .collect();
+    assert_eq!(lerp.to_vec2::<f64>()?, expected);
+    // A scalar weight is broadcasted over all the elements.
+    let lerp = Tensor::new(&start, device)?.lerp(
+        &Tensor::new(&end[0], device)?,
+        &Tensor::new(0.75f64, device)?,
+    )?;
+    let expected: Vec<f64> = start
+        .iter()
+        .zip(end[0].iter())
+        .map(|(s, e)| s + 0.75 * (e - s))
+        .collect();
+    assert_eq!(lerp.to_vec1::<f64>()?, expected);
+    Ok(())
+}
+
 fn asort(device: &Device) -> Result<()> {
     let data = &[[3f32, 1., 4., 1.1, 5.], [2.1, 1., 7., 8., 2.]];
     let tensor = Tensor::new(data, device)?;
@@ -130,6 +182,61 @@ fn asort(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn sort_dim(device: &Device) -> Result<()> {
+    let data = &[
+        [[3f32, 1.], [1., 5.], [2., 4.]],
+        [[0., 9.], [7., 8.], [5., 6.]],
+    ];
+    let tensor = Tensor::new(data, device)?;
+    let (sorted, indexes) = tensor.sort(1, false)?;
+    assert_eq!(
+        indexes.to_vec3::<u32>()?,
+        [[[1, 0], [2, 2], [0, 1]], [[0, 2], [2, 1], [1, 0]]]
+    );
+    assert_eq!(
+        sorted.to_vec3::<f32>()?,
+        [
+            [[1., 1.], [2., 4.], [3., 5.]],
+            [[0., 6.], [5., 8.], [7., 9.]]
+        ]
+    );
+    assert_eq!(
+        tensor.gather(&indexes, 1)?.to_vec3::<f32>()?,
+        sorted.to_vec3::<f32>()?
+    );
+    let (sorted, indexes) = tensor.sort(1, true)?;
+    assert_eq!(
+        indexes.to_vec3::<u32>()?,
+        [[[0, 1], [2, 2], [1, 0]], [[1, 0], [2, 1], [0, 2]]]
+    );
+    assert_eq!(
+        sorted.to_vec3::<f32>()?,
+        [
+            [[3., 5.], [2., 4.], [1., 1.]],
+            [[7., 9.], [5., 8.], [0., 6.]]
+        ]
+    );
+    assert_eq!(
+        tensor.gather(&indexes, 1)?.to_vec3::<f32>()?,
+        sorted.to_vec3::<f32>()?
+    );
+    // Sorting a non-contiguous tensor.
+    let (sorted_t, indexes_t) = tensor.transpose(1, 2)?.sort(D::Minus1, true)?;
+    assert_eq!(
+        sorted_t.transpose(1, 2)?.to_vec3::<f32>()?,
+        sorted.to_vec3::<f32>()?
+    );
+    assert_eq!(
+        indexes_t.transpose(1, 2)?.to_vec3::<u32>()?,
+        indexes.to_vec3::<u32>()?
+    );
+    assert_eq!(
+        tensor.argsort(1, true)?.to_vec3::<u32>()?,
+        indexes.to_vec3::<u32>()?
+    );
+    Ok(())
+}
+
 fn unary_op(device: &Device) -> Result<()> {
     let data = &[[-3f32, 1., 4., -0.1, 0.5], [2.7, -1.8, -0.28, 1.8, 2.8]];
     let tensor = Tensor::new(data, device)?;
@@ -655,6 +762,29 @@ fn narrow(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn split(device: &Device) -> Result<()> {
+    let tensor = Tensor::arange(0f32, 14., device)?.reshape((2, 7))?;
+    let parts = tensor.split(&[4, 1, 2], 1)?;
+    assert_eq!(parts.len(), 3);
+    assert_eq!(
+        parts[0].to_vec2::<f32>()?,
+        &[[0., 1., 2., 3.], [7., 8., 9., 10.]]
+    );
+    assert_eq!(parts[1].to_vec2::<f32>()?, &[[4.], [11.]]);
+    assert_eq!(parts[2].to_vec2::<f32>()?, &[[5., 6.], [12., 13.]]);
+    let parts = tensor.split(&[0, 2], D::Minus2)?;
+    assert_eq!(parts[0].dims(), &[0, 7]);
+    assert_eq!(parts[1].to_vec2::<f32>()?, tensor.to_vec2::<f32>()?);
+
+    let err = tensor.split(&[4, 2], 1).unwrap_err().to_string();
+    assert!(
+        err.starts_with("split sizes [4, 2] sum to 6 but dim 1 of [2, 7] has size 7"),
+        "{err}"
+    );
+    assert!(tensor.split(&[7], 2).is_err());
+    Ok(())
+}
+
 fn broadcast(device: &Device) -> Result<()> {
     let data = &[3f32, 1., 4.];
     let tensor = Tensor::new(data, device)?;
@@ -893,6 +1023,43 @@ fn index_add(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn index_copy(device: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 12f32, device)?.reshape((4, 3))?;
+    let init = Tensor::full(-1f32, (4, 2), device)?;
+    // Repeated indexes keep the last slice, index_add accumulates them.
+    let ids = Tensor::new(&[1u32, 0u32, 1u32], device)?;
+    let hs = init.index_copy(&ids, &t, 1)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[[1.0, 2.0], [4.0, 5.0], [7.0, 8.0], [10.0, 11.0]],
+    );
+    let hs = init.index_add(&ids, &t, 1)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[[0.0, 1.0], [3.0, 7.0], [6.0, 13.0], [9.0, 19.0]],
+    );
+
+    let init = Tensor::zeros((6, 3), DType::F32, device)?;
+    let ids = Tensor::new(&[5i64, 0, 2, 0], device)?;
+    let hs = init.index_copy(&ids, &t, 0)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [9.0, 10.0, 11.0],
+            [0.0, 0.0, 0.0],
+            [6.0, 7.0, 8.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 2.0]
+        ]
+    );
+    assert!(init.index_copy(&ids.narrow(0, 0, 3)?, &t, 0).is_err());
+    assert!(init
+        .index_copy(&Tensor::new(&[6u32, 0, 1, 2], device)?, &t, 0)
+        .is_err());
+    Ok(())
+}
+
 fn slice_scatter(device: &Device) -> Result<()> {
     let t = Tensor::arange(0f32, 12f32, device)?.reshape((4, 3))?;
     assert_eq!(
@@ -975,6 +1142,86 @@ fn scatter_add(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn scatter_max_min(device: &Device) -> Result<()> {
+    let t = Tensor::arange(0f32, 12f32, device)?.reshape((4, 3))?;
+    let ids = Tensor::new(&[[0u32, 1, 2], [3, 4, 0], [3, 3, 1], [2, 0, 4]], device)?;
+    let init = Tensor::full(5f32, (4, 5), device)?;
+    // Overlapping indexes keep the last written value.
+    let hs = init.scatter(1, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [0.0, 1.0, 2.0, 5.0, 5.0],
+            [5.0, 5.0, 5.0, 3.0, 4.0],
+            [5.0, 8.0, 5.0, 7.0, 5.0],
+            [10.0, 5.0, 9.0, 5.0, 11.0]
+        ]
+    );
+    let hs = init.scatter_max(1, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [5.0, 5.0, 5.0, 5.0, 5.0],
+            [5.0, 5.0, 5.0, 5.0, 5.0],
+            [5.0, 8.0, 5.0, 7.0, 5.0],
+            [10.0, 5.0, 9.0, 5.0, 11.0]
+        ]
+    );
+    let hs = init.scatter_min(1, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [0.0, 1.0, 2.0, 5.0, 5.0],
+            [5.0, 5.0, 5.0, 3.0, 4.0],
+            [5.0, 5.0, 5.0, 5.0, 5.0],
+            [5.0, 5.0, 5.0, 5.0, 5.0]
+        ]
+    );
+
+    let init = Tensor::zeros((5, 3), DType::F32, device)?;
+    let hs = init.scatter(0, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [0.0, 10.0, 5.0],
+            [0.0, 1.0, 8.0],
+            [9.0, 0.0, 2.0],
+            [6.0, 7.0, 0.0],
+            [0.0, 4.0, 11.0]
+        ]
+    );
+    let init = Tensor::full(100f32, (5, 3), device)?;
+    let hs = init.scatter_min(0, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [0.0, 10.0, 5.0],
+            [100.0, 1.0, 8.0],
+            [9.0, 100.0, 2.0],
+            [3.0, 7.0, 100.0],
+            [100.0, 4.0, 11.0]
+        ]
+    );
+    let hs = init.neg()?.scatter_max(0, &ids, &t)?;
+    assert_eq!(
+        hs.to_vec2::<f32>()?,
+        &[
+            [0.0, 10.0, 5.0],
+            [-100.0, 1.0, 8.0],
+            [9.0, -100.0, 2.0],
+            [6.0, 7.0, -100.0],
+            [-100.0, 4.0, 11.0]
+        ]
+    );
+
+    // Out of range indexes and mismatched shapes are errors.
+    let init = Tensor::zeros((4, 4), DType::F32, device)?;
+    assert!(init.scatter(1, &ids, &t).is_err());
+    assert!(init.scatter(0, &ids, &t).is_err());
+    assert!(init.scatter_max(1, &ids.narrow(1, 0, 2)?, &t).is_err());
+    Ok(())
+}
+
 fn gather(device: &Device) -> Result<()> {
     let ids = Tensor::new(&[[0u32], [2u32], [1u32], [0u32]], device)?;
     let t = Tensor::arange(0f32, 12f32, device)?.reshape((4, 3))?;
@@ -1007,6 +1254,90 @@ fn gather(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn index_select_gather_random(device: &Device) -> Result<()> {
+    // Compare with the cpu results on a random embedding table, and on a transposed view of this
+    // table for all the dims.
+    let vec = |t: &Tensor| t.flatten_all()?.to_dtype(DType::F32)?.to_vec1::<f32>();
+    let table = Tensor::randn(0f32, 1., (50, 16), device)?;
+    for dtype in [DType::F32, DType::F16] {
+        let table = table.to_dtype(dtype)?;
+        let table_cpu = table.to_device(&Device::Cpu)?;
+        let ids: Vec<u32> = (0..20).map(|i| (i * 37 + 11) % 50).collect();
+        let ids_cpu = Tensor::new(ids.as_slice(), &Device::Cpu)?;
+        let embs = table.index_select(&ids_cpu.to_device(device)?, 0)?;
+        assert_eq!(vec(&embs)?, vec(&table_cpu.index_select(&ids_cpu, 0)?)?);
+
+        // Only the metal backend supports strided inputs for these ops.
+        let t = table.reshape((5, 10, 16))?.transpose(0, 2)?;
+        let t = if device.is_metal() {
+            t
+        } else {
+            t.contiguous()?
+        };
+        let t_cpu = table_cpu
+            .reshape((5, 10, 16))?
+            .transpose(0, 2)?
+            .contiguous()?;
+        for dim in 0..3 {
+            let dim_size = t.dim(dim)?;
+            let ids: Vec<u32> = (0..7).map(|i| (i * 5 + 3) % dim_size as u32).collect();
+            let ids_cpu = Tensor::new(ids.as_slice(), &Device::Cpu)?;
+            let ys = t.index_select(&ids_cpu.to_device(device)?, dim)?;
+            assert_eq!(vec(&ys)?, vec(&t_cpu.index_select(&ids_cpu, dim)?)?);
+
+            let mut ids_dims = t.dims().to_vec();
+            ids_dims[dim] = 3;
+            let n: usize = ids_dims.iter().product();
+            let ids: Vec<u32> = (0..n)
+                .map(|i| (i * 7 + 3) as u32 % dim_size as u32)
+                .collect();
+            let ids_cpu = Tensor::from_vec(ids, ids_dims, &Device::Cpu)?;
+            let ys = t.gather(&ids_cpu.to_device(device)?, dim)?;
+            assert_eq!(vec(&ys)?, vec(&t_cpu.gather(&ids_cpu, dim)?)?);
+        }
+    }
+    Ok(())
+}
+
+fn take_along_dim(device: &Device) -> Result<()> {
+    let data = &[[[3f32, 1., 4.], [1., 5., 9.]], [[2., 6., 5.], [3., 5., 8.]]];
+    let t = Tensor::new(data, device)?;
+    for dim in 0..3 {
+        let (sorted, _) = t.sort(dim, false)?;
+        let indexes = t.argsort(dim, false)?;
+        assert_eq!(
+            t.take_along_dim(&indexes, dim)?.to_vec3::<f32>()?,
+            sorted.to_vec3::<f32>()?
+        );
+    }
+    let indexes = t.argmax_keepdim(2)?;
+    assert_eq!(
+        t.take_along_dim(&indexes, 2)?.to_vec3::<f32>()?,
+        [[[4.], [9.]], [[6.], [8.]]]
+    );
+    // The indexes are broadcasted on the non-gather dimensions.
+    let indexes = Tensor::new(&[[[2u32, 0]]], device)?;
+    assert_eq!(
+        t.take_along_dim(&indexes, 2)?.to_vec3::<f32>()?,
+        [[[4., 3.], [9., 1.]], [[5., 2.], [8., 3.]]]
+    );
+    // So is the input tensor.
+    let t = Tensor::new(&[[1f32, 2., 3.]], device)?;
+    let indexes = Tensor::new(&[[2u32], [0]], device)?;
+    assert_eq!(
+        t.take_along_dim(&indexes, 1)?.to_vec2::<f32>()?,
+        [[3.], [1.]]
+    );
+    assert!(t.take_along_dim(&Tensor::new(&[0u32], device)?, 1).is_err());
+    assert!(t
+        .take_along_dim(
+            &Tensor::new(&[[0u32], [1], [2]], device)?.reshape((1, 3, 1))?,
+            1
+        )
+        .is_err());
+    Ok(())
+}
+
 fn broadcasting(device: &Device) -> Result<()> {
     let t1 = Tensor::arange(0f32, 24f32, device)?.reshape((4, 2, 3))?;
     let t2 = Tensor::new(&[100f32, 200f32], device)?;
@@ -1162,6 +1493,100 @@ fn zero_dim(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn to_device_async(device: &Device) -> Result<()> {
+    let cpu = &Device::Cpu;
+    // Some computations enqueued before the copies.
+    let a = Tensor::randn(0f32, 1., (64, 64), device)?;
+    let mut b = a.clone();
+    for _ in 0..10 {
+        b = b.matmul(&a)?.tanh()?;
+    }
+    // Enough copies with various sizes to reuse the staging buffers, including transposed ones.
+    let mut copies = vec![];
+    for i in 0..8 {
+        let n = 10 * (i % 3 + 1);
+        let xs = Tensor::arange(i as f32, (i + 6 * n) as f32, cpu)?.reshape((6, ()))?;
+        let xs = if i % 2 == 0 { xs.t()? } else { xs };
+        copies.push((xs.to_device_async(device)?, xs));
+    }
+    // The copies can be used directly, the computations are ordered after them.
+    let sum = copies[3].0.sum_all()?.to_device_async(cpu)?;
+    assert_eq!(
+        sum.to_vec0::<f32>()?,
+        copies[3].1.sum_all()?.to_vec0::<f32>()?
+    );
+    let u8s = Tensor::new(&[1u8, 2, 3], cpu)?.to_device_async(device)?;
+    let u32s = Tensor::new(&[4u32, 5], cpu)?.to_device_async(device)?;
+    let i64s = Tensor::new(&[-6i64], cpu)?.to_device_async(device)?;
+    let f16s = Tensor::new(&[1f32, 0.5], cpu)?
+        .to_dtype(DType::F16)?
+        .to_device_async(device)?;
+    let empty = Tensor::zeros(0, DType::F32, cpu)?.to_device_async(device)?;
+    device.synchronize()?;
+    for (ys, xs) in copies {
+        assert!(ys.device().same_device(device));
+        assert_eq!(ys.dims(), xs.dims());
+        assert_eq!(ys.to_vec2::<f32>()?, xs.to_vec2::<f32>()?);
+    }
+    assert_eq!(u8s.to_vec1::<u8>()?, [1, 2, 3]);
+    assert_eq!(u32s.to_vec1::<u32>()?, [4, 5]);
+    assert_eq!(i64s.to_vec1::<i64>()?, [-6]);
+    assert_eq!(f16s.to_dtype(DType::F32)?.to_vec1::<f32>()?, [1., 0.5]);
+    assert_eq!(empty.dims(), [0]);
+    assert_eq!(b.dims(), [64, 64]);
+    Ok(())
+}
+
+fn einsum(device: &Device) -> Result<()> {
+    let a = Tensor::arange(0f32, 6f32, device)?.reshape((2, 3))?;
+    let b = Tensor::arange(0f32, 12f32, device)?.reshape((3, 4))?;
+    let c = Tensor::einsum("ij,jk->ik", &[&a, &b])?;
+    assert_eq!(
+        c.to_vec2::<f32>()?,
+        [[20.0, 23.0, 26.0, 29.0], [56.0, 68.0, 80.0, 92.0]]
+    );
+    let c = Tensor::einsum("ij,jk", &[&a, &b])?;
+    assert_eq!(
+        c.to_vec2::<f32>()?,
+        [[20.0, 23.0, 26.0, 29.0], [56.0, 68.0, 80.0, 92.0]]
+    );
+
+    let a = Tensor::arange(0f32, 24f32, device)?.reshape((2, 3, 4))?;
+    let b = Tensor::arange(0f32, 40f32, device)?.reshape((2, 4, 5))?;
+    let c = Tensor::einsum("bij,bjk->bik", &[&a, &b])?;
+    assert_eq!(c.dims(), &[2, 3, 5]);
+    assert_eq!(c.to_vec3::<f32>()?, a.matmul(&b)?.to_vec3::<f32>()?);
+    let c = Tensor::einsum("...ij,...jk->...ik", &[&a, &b])?;
+    assert_eq!(c.to_vec3::<f32>()?, a.matmul(&b)?.to_vec3::<f32>()?);
+
+    let a = Tensor::arange(0f32, 6f32, device)?.reshape((2, 3))?;
+    let t = Tensor::einsum("ij->ji", &[&a])?;
+    assert_eq!(t.to_vec2::<f32>()?, [[0.0, 3.0], [1.0, 4.0], [2.0, 5.0]]);
+    let s = Tensor::einsum("ij->i", &[&a])?;
+    assert_eq!(s.to_vec1::<f32>()?, [3.0, 12.0]);
+
+    let m = Tensor::arange(0f32, 9f32, device)?.reshape((3, 3))?;
+    let tr = Tensor::einsum("ii->", &[&m])?;
+    assert_eq!(tr.to_vec0::<f32>()?, 12.0);
+    let d = Tensor::einsum("ii->i", &[&m])?;
+    assert_eq!(d.to_vec1::<f32>()?, [0.0, 4.0, 8.0]);
+
+    let x = Tensor::new(&[1f32, 2., 3.], device)?;
+    let y = Tensor::new(&[4f32, 5.], device)?;
+    let o = Tensor::einsum("i,j->ij", &[&x, &y])?;
+    assert_eq!(o.to_vec2::<f32>()?, [[4.0, 5.0], [8.0, 10.0], [12.0, 15.0]]);
+    let dot = Tensor::einsum("i,i->", &[&x, &x])?;
+    assert_eq!(dot.to_vec0::<f32>()?, 14.0);
+
+    // Mismatched dimensions for a shared index.
+    assert!(Tensor::einsum("ij,jk->ik", &[&a, &a]).is_err());
+    // Wrong number of operands.
+    assert!(Tensor::einsum("ij,jk->ik", &[&a]).is_err());
+    // Unknown output index.
+    assert!(Tensor::einsum("ij->k", &[&a]).is_err());
+    Ok(())
+}
+
 test_device!(zeros, zeros_cpu, zeros_gpu, zeros_metal);
 test_device!(ones, ones_cpu, ones_gpu, ones_metal);
 test_device!(full, full_cpu, full_gpu, full_metal);
@@ -1169,6 +1594,7 @@ test_device!(arange, arange_cpu, arange_gpu, arange_metal);
 test_device!(add_mul, add_mul_cpu, add_mul_gpu, add_mul_metal);
 test_device!(tensor_2d, tensor_2d_cpu, tensor_2d_gpu, tensor_2d_metal);
 test_device!(narrow, narrow_cpu, narrow_gpu, narrow_metal);
+test_device!(split, split_cpu, split_gpu, split_metal);
 test_device!(broadcast, broadcast_cpu, broadcast_gpu, broadcast_metal);
 test_device!(slice_set, ss_cpu, ss_gpu, ss_metal);
 test_device!(cat, cat_cpu, cat_gpu, cat_metal);
@@ -1195,7 +1621,26 @@ test_device!(
     index_select_metal
 );
 test_device!(index_add, index_add_cpu, index_add_gpu, index_add_metal);
+test_device!(index_copy, index_copy_cpu, index_copy_gpu, index_copy_metal);
+test_device!(
+    scatter_max_min,
+    scatter_max_min_cpu,
+    scatter_max_min_gpu,
+    scatter_max_min_metal
+);
 test_device!(gather, gather_cpu, gather_gpu, gather_metal);
+test_device!(
+    index_select_gather_random,
+    index_select_gather_random_cpu,
+    index_select_gather_random_gpu,
+    index_select_gather_random_metal
+);
+test_device!(
+    take_along_dim,
+    take_along_dim_cpu,
+    take_along_dim_gpu,
+    take_along_dim_metal
+);
 test_device!(
     scatter_add,
     scatter_add_cpu,
@@ -1210,9 +1655,71 @@ test_device!(
 );
 test_device!(randn, randn_cpu, randn_gpu, randn_metal);
 test_device!(clamp, clamp_cpu, clamp_gpu, clamp_metal);
+test_device!(
+    clamp_tensor_lerp,
+    clamp_tensor_lerp_cpu,
+    clamp_tensor_lerp_gpu,
+    clamp_tensor_lerp_metal
+);
 test_device!(asort, asort_cpu, asort_gpu, asort_metal);
+test_device!(sort_dim, sort_dim_cpu, sort_dim_gpu, sort_dim_metal);
+test_device!(topk, topk_cpu, topk_gpu, topk_metal);
+test_device!(
+    repeat_interleave,
+    repeat_interleave_cpu,
+    repeat_interleave_gpu,
+    repeat_interleave_metal
+);
+test_device!(roll_flip, roll_flip_cpu, roll_flip_gpu, roll_flip_metal);
+test_device!(
+    nonzero_masked_select,
+    nonzero_masked_select_cpu,
+    nonzero_masked_select_gpu,
+    nonzero_masked_select_metal
+);
+test_device!(
+    cumprod_cummax_cummin,
+    cumprod_cummax_cummin_cpu,
+    cumprod_cummax_cummin_gpu,
+    cumprod_cummax_cummin_metal
+);
+test_device!(
+    searchsorted,
+    searchsorted_cpu,
+    searchsorted_gpu,
+    searchsorted_metal
+);
+test_device!(
+    bincount_histogram,
+    bincount_histogram_cpu,
+    bincount_histogram_gpu,
+    bincount_histogram_metal
+);
+test_device!(bitwise, bitwise_cpu, bitwise_gpu, bitwise_metal);
+test_device!(unique, unique_cpu, unique_gpu, unique_metal);
+test_device!(
+    median_quantile_mode,
+    median_quantile_mode_cpu,
+    median_quantile_mode_gpu,
+    median_quantile_mode_metal
+);
+test_device!(
+    diag_tril_triu,
+    diag_tril_triu_cpu,
+    diag_tril_triu_gpu,
+    diag_tril_triu_metal
+);
 test_device!(var, var_cpu, var_gpu, var_metal);
 test_device!(zero_dim, zero_dim_cpu, zero_dim_gpu, zero_dim_metal);
+test_device!(pad, pad_cpu, pad_gpu, pad_metal);
+test_device!(unfold, unfold_cpu, unfold_gpu, unfold_metal);
+test_device!(einsum, einsum_cpu, einsum_gpu, einsum_metal);
+test_device!(
+    to_device_async,
+    to_device_async_cpu,
+    to_device_async_gpu,
+    to_device_async_metal
+);
 
 // There was originally a bug on the CPU implementation for randn
 // https://github.com/huggingface/candle/issues/381
@@ -1225,6 +1732,91 @@ fn randn_hasneg() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn stochastic_rounding() -> Result<()> {
+    use candle_core::RoundingMode;
+    let dev = &Device::Cpu;
+    dev.set_seed(1337)?;
+    // The bf16 ulp around 1 is 2^-7 so this value is not representable.
+    let v = 1f32 + 2f32.powi(-10);
+    let t = Tensor::full(v, 100_000, dev)?;
+    let nearest = t.to_dtype_with(DType::BF16, RoundingMode::Nearest)?;
+    let nearest = nearest
+        .to_dtype(DType::F32)?
+        .mean_all()?
+        .to_scalar::<f32>()?;
+    assert_eq!(nearest, 1.);
+    let rounded = t.to_dtype_with(DType::BF16, RoundingMode::Stochastic)?;
+    let values = rounded.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+    assert!(values.iter().all(|&x| x == 1. || x == 1. + 2f32.powi(-7)));
+    let mean = values.iter().map(|&x| x as f64).sum::<f64>() / values.len() as f64;
+    assert!((mean - v as f64).abs() < 1e-4, "{mean} {v}");
+    let neg = t
+        .neg()?
+        .to_dtype_with(DType::BF16, RoundingMode::Stochastic)?;
+    let neg = neg.to_dtype(DType::F32)?.mean_all()?.to_scalar::<f32>()?;
+    assert!((neg + v).abs() < 1e-4, "{neg} {v}");
+    Ok(())
+}
+
+fn unfold(device: &Device) -> Result<()> {
+    let xs = (0..20).map(|i| ((i * 7) % 11) as f32).collect::<Vec<_>>();
+    let t = Tensor::new(xs.as_slice(), device)?.reshape((2, 10))?;
+    for (size, step) in [(3, 1), (4, 3), (2, 5), (10, 1)] {
+        let windows = t.unfold(1, size, step)?;
+        let n = (10 - size) / step + 1;
+        assert_eq!(windows.dims(), [2, n, size]);
+        let expected = xs
+            .chunks(10)
+            .map(|row| {
+                (0..n)
+                    .map(|w| row[w * step..w * step + size].iter().sum::<f32>())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(windows.sum(2)?.to_vec2::<f32>()?, expected);
+        // The materialized windows used when tracking gradients match the strided view.
+        let var = candle_core::Var::from_tensor(&t)?;
+        let var_windows = var.unfold(1, size, step)?;
+        assert_eq!(var_windows.to_vec3::<f32>()?, windows.to_vec3::<f32>()?);
+    }
+    assert!(t.unfold(1, 11, 1).is_err());
+    assert!(t.unfold(1, 2, 0).is_err());
+
+    // im2col patches multiplied by the flattened kernel match conv2d.
+    let t = Tensor::arange(0f32, 2. * 3. * 5. * 6., device)?.reshape((2, 3, 5, 6))?;
+    let t = t.sin()?;
+    let k = Tensor::arange(0f32, 4. * 3. * 2. * 3., device)?.reshape((4, 3, 2, 3))?;
+    let k = k.cos()?;
+    let patches = t.unfold2d((2, 3), (2, 1))?;
+    assert_eq!(patches.dims(), [2, 18, 2 * 4]);
+    let res = k
+        .reshape((4, 18))?
+        .broadcast_matmul(&patches)?
+        .reshape((2, 4, 2, 4))?;
+    let conv = t
+        .conv2d(&k, 0, 1, 1, 1)?
+        .index_select(&Tensor::new(&[0u32, 2], device)?, 2)?;
+    let diff = (res - conv)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_vec0::<f32>()?;
+    assert!(diff < 1e-4, "{diff}");
+    Ok(())
+}
+
+#[test]
+fn unfold_grad() -> Result<()> {
+    let var = candle_core::Var::new(&[1f32, 2., 3., 4., 5.], &Device::Cpu)?;
+    let windows = var.unfold(0, 3, 1)?;
+    let grads = windows.sum_all()?.backward()?;
+    let grad = grads.get(&var).unwrap();
+    // Each element gets a gradient equal to the number of windows it belongs to.
+    assert_eq!(grad.to_vec1::<f32>()?, [1., 2., 3., 2., 1.]);
+    Ok(())
+}
+
 #[test]
 fn pad_with_same() -> Result<()> {
     let t = Tensor::arange(1f32, 5f32, &Device::Cpu)?.reshape((2, 2))?;
@@ -1241,6 +1833,58 @@ fn pad_with_same() -> Result<()> {
     Ok(())
 }
 
+fn pad(device: &Device) -> Result<()> {
+    use candle_core::PadMode;
+    let t = Tensor::arange(1f32, 5f32, device)?;
+    let pad = |mode| -> Result<Vec<f32>> { t.pad(0, 2, 3, mode)?.to_vec1::<f32>() };
+    assert_eq!(
+        pad(PadMode::Constant(-1.))?,
+        [-1., -1., 1., 2., 3., 4., -1., -1., -1.]
+    );
+    assert_eq!(pad(PadMode::Reflect)?, [3., 2., 1., 2., 3., 4., 3., 2., 1.]);
+    assert_eq!(
+        pad(PadMode::Replicate)?,
+        [1., 1., 1., 2., 3., 4., 4., 4., 4.]
+    );
+    assert_eq!(
+        pad(PadMode::Circular)?,
+        [3., 4., 1., 2., 3., 4., 1., 2., 3.]
+    );
+    assert_eq!(
+        t.pad(0, 0, 1, PadMode::Constant(0.))?.to_vec1::<f32>()?,
+        t.pad_with_zeros(0, 0, 1)?.to_vec1::<f32>()?
+    );
+    assert!(t.pad(0, 4, 0, PadMode::Reflect).is_err());
+    assert!(t.pad(0, 0, 5, PadMode::Circular).is_err());
+
+    // Padding both dims of a matrix with successive calls.
+    let t = Tensor::arange(1f32, 7f32, device)?.reshape((2, 3))?;
+    let p = t
+        .pad(1, 1, 1, PadMode::Reflect)?
+        .pad(0, 1, 0, PadMode::Reflect)?;
+    assert_eq!(
+        p.to_vec2::<f32>()?,
+        [
+            [5., 4., 5., 6., 5.],
+            [2., 1., 2., 3., 2.],
+            [5., 4., 5., 6., 5.]
+        ]
+    );
+    let p = t
+        .pad(1, 2, 0, PadMode::Circular)?
+        .pad(0, 1, 1, PadMode::Replicate)?;
+    assert_eq!(
+        p.to_vec2::<f32>()?,
+        [
+            [2., 3., 1., 2., 3.],
+            [2., 3., 1., 2., 3.],
+            [5., 6., 4., 5., 6.],
+            [5., 6., 4., 5., 6.]
+        ]
+    );
+    Ok(())
+}
+
 #[test]
 fn i64_abs() -> Result<()> {
     let t = Tensor::new(&[-42i64, 1337], &Device::Cpu)?;
@@ -1284,6 +1928,456 @@ fn tril_triu_eye() -> Result<()> {
     Ok(())
 }
 
+fn diag_tril_triu(device: &Device) -> Result<()> {
+    // Causal mask combined with a sliding window of size 2, built on u8.
+    let ones = Tensor::ones((4, 4), DType::U8, device)?;
+    let mask = ones.tril(0)?.triu(-1)?;
+    assert_eq!(
+        mask.to_vec2::<u8>()?,
+        [[1, 0, 0, 0], [1, 1, 0, 0], [0, 1, 1, 0], [0, 0, 1, 1]]
+    );
+    let t = Tensor::arange(1f32, 13., device)?.reshape((3, 4))?;
+    assert_eq!(
+        t.tril(1)?.to_vec2::<f32>()?,
+        [[1., 2., 0., 0.], [5., 6., 7., 0.], [9., 10., 11., 12.]]
+    );
+    assert_eq!(
+        t.tril(-2)?.to_vec2::<f32>()?,
+        [[0., 0., 0., 0.], [0., 0., 0., 0.], [9., 0., 0., 0.]]
+    );
+    assert_eq!(
+        t.triu(2)?.to_vec2::<f32>()?,
+        [[0., 0., 3., 4.], [0., 0., 0., 8.], [0., 0., 0., 0.]]
+    );
+    assert_eq!(
+        t.triu(-1)?.to_vec2::<f32>()?,
+        [[1., 2., 3., 4.], [5., 6., 7., 8.], [0., 10., 11., 12.]]
+    );
+    // The masks are applied on the last two dims.
+    let b = Tensor::stack(&[&t, &(&t * 2.)?], 0)?;
+    assert_eq!(
+        b.triu(1)?.get(1)?.to_vec2::<f32>()?,
+        [[0., 4., 6., 8.], [0., 0., 14., 16.], [0., 0., 0., 24.]]
+    );
+
+    assert_eq!(t.diagonal(0, 0, 1)?.to_vec1::<f32>()?, [1., 6., 11.]);
+    assert_eq!(t.diagonal(2, 0, 1)?.to_vec1::<f32>()?, [3., 8.]);
+    assert_eq!(t.diagonal(-1, 0, 1)?.to_vec1::<f32>()?, [5., 10.]);
+    assert_eq!(t.diagonal(1, 1, 0)?.to_vec1::<f32>()?, [5., 10.]);
+    assert_eq!(t.diagonal(4, 0, 1)?.dims(), [0]);
+    let d = b.diagonal(1, 1, 2)?;
+    assert_eq!(d.to_vec2::<f32>()?, [[2., 7., 12.], [4., 14., 24.]]);
+    let d = b.diagonal(0, 0, 2)?;
+    assert_eq!(d.to_vec2::<f32>()?, [[1., 4.], [5., 12.], [9., 20.]]);
+
+    let v = Tensor::new(&[1u32, 2, 3], device)?;
+    let m = v.diag()?;
+    assert_eq!(m.to_vec2::<u32>()?, [[1, 0, 0], [0, 2, 0], [0, 0, 3]]);
+    assert_eq!(m.diag()?.to_vec1::<u32>()?, [1, 2, 3]);
+    assert!(b.diag().is_err());
+    Ok(())
+}
+
+fn repeat_interleave(device: &Device) -> Result<()> {
+    // Expanding the kv heads for grouped-query attention, (b, n_kv_heads, seq_len, head_dim).
+    let kv = Tensor::arange(0f32, 24., device)?.reshape((2, 2, 3, 2))?;
+    let n_rep = 3;
+    let expanded = kv.repeat_interleave(n_rep, 1)?;
+    assert_eq!(expanded.dims(), [2, 6, 3, 2]);
+    for h in 0..6 {
+        assert_eq!(
+            expanded.i((.., h))?.to_vec3::<f32>()?,
+            kv.i((.., h / n_rep))?.to_vec3::<f32>()?
+        );
+    }
+    // Same as the broadcast based implementation.
+    let (b, n_kv, seq_len, head_dim) = kv.dims4()?;
+    let reference = kv
+        .unsqueeze(2)?
+        .broadcast_as((b, n_kv, n_rep, seq_len, head_dim))?
+        .reshape((b, n_kv * n_rep, seq_len, head_dim))?;
+    assert_eq!(
+        expanded.flatten_all()?.to_vec1::<f32>()?,
+        reference.flatten_all()?.to_vec1::<f32>()?
+    );
+    let t = Tensor::new(&[[1u8, 2], [3, 4]], device)?;
+    assert_eq!(
+        t.repeat_interleave(2, 1)?.to_vec2::<u8>()?,
+        [[1, 1, 2, 2], [3, 3, 4, 4]]
+    );
+    assert_eq!(
+        t.repeat_interleave(1, 0)?.to_vec2::<u8>()?,
+        [[1, 2], [3, 4]]
+    );
+
+    // Variable number of repeats, checked against a manual reference.
+    let t = Tensor::arange(0f32, 12., device)?.reshape((3, 4))?;
+    let counts = [1usize, 0, 3, 2];
+    let repeats = Tensor::new(&[1i64, 0, 3, 2], device)?;
+    let r = t.repeat_interleave_tensor(&repeats, 1)?;
+    assert_eq!(r.dims(), [3, 6]);
+    let mut columns = vec![];
+    for (i, &c) in counts.iter().enumerate() {
+        for _ in 0..c {
+            columns.push(t.narrow(1, i, 1)?)
+        }
+    }
+    let reference = Tensor::cat(&columns, 1)?;
+    assert_eq!(r.to_vec2::<f32>()?, reference.to_vec2::<f32>()?);
+    assert_eq!(
+        r.to_vec2::<f32>()?,
+        [
+            [0., 2., 2., 2., 3., 3.],
+            [4., 6., 6., 6., 7., 7.],
+            [8., 10., 10., 10., 11., 11.]
+        ]
+    );
+    let repeats = Tensor::new(&[2u32], device)?;
+    assert_eq!(
+        t.repeat_interleave_tensor(&repeats, 0)?.to_vec2::<f32>()?,
+        t.repeat_interleave(2, 0)?.to_vec2::<f32>()?
+    );
+    assert_eq!(
+        t.t()?
+            .repeat_interleave_tensor(&repeats, 0)?
+            .to_vec2::<f32>()?,
+        t.t()?.repeat_interleave(2, 0)?.to_vec2::<f32>()?
+    );
+    let repeats = Tensor::new(&[1u32, 2], device)?;
+    assert!(t.repeat_interleave_tensor(&repeats, 1).is_err());
+    let repeats = Tensor::new(&[1i64, -1, 1], device)?;
+    assert!(t.repeat_interleave_tensor(&repeats, 0).is_err());
+    Ok(())
+}
+
+fn roll_flip(device: &Device) -> Result<()> {
+    let t = Tensor::arange(0u32, 5, device)?;
+    assert_eq!(t.roll(2, 0)?.to_vec1::<u32>()?, [3, 4, 0, 1, 2]);
+    assert_eq!(t.roll(-2, 0)?.to_vec1::<u32>()?, [2, 3, 4, 0, 1]);
+    // Shifts larger than the dimension wrap around.
+    assert_eq!(t.roll(12, 0)?.to_vec1::<u32>()?, [3, 4, 0, 1, 2]);
+    assert_eq!(t.roll(-7, 0)?.to_vec1::<u32>()?, [2, 3, 4, 0, 1]);
+    assert_eq!(t.roll(5, 0)?.to_vec1::<u32>()?, [0, 1, 2, 3, 4]);
+
+    // Non-contiguous input.
+    let t = Tensor::arange(0f32, 6., device)?.reshape((2, 3))?.t()?;
+    assert_eq!(
+        t.roll(1, 0)?.to_vec2::<f32>()?,
+        [[2., 5.], [0., 3.], [1., 4.]]
+    );
+    assert_eq!(
+        t.roll_dims(&[1, -3], &[0, 1])?.to_vec2::<f32>()?,
+        [[5., 2.], [3., 0.], [4., 1.]]
+    );
+    assert!(t.roll_dims(&[1], &[0, 1]).is_err());
+
+    assert_eq!(
+        t.flip(&[0])?.to_vec2::<f32>()?,
+        [[2., 5.], [1., 4.], [0., 3.]]
+    );
+    assert_eq!(
+        t.flip(&[1])?.to_vec2::<f32>()?,
+        [[3., 0.], [4., 1.], [5., 2.]]
+    );
+    assert_eq!(
+        t.flip(&[0, 1])?.to_vec2::<f32>()?,
+        [[5., 2.], [4., 1.], [3., 0.]]
+    );
+    let t = Tensor::randn(0f32, 1f32, (2, 3, 4), device)?;
+    let flipped = t.flip(&[0, 2])?;
+    assert_eq!(flipped.dtype(), DType::F32);
+    assert_eq!(
+        flipped.flip(&[2, 0])?.to_vec3::<f32>()?,
+        t.to_vec3::<f32>()?
+    );
+    assert!(t.flip(&[1, 1]).is_err());
+    Ok(())
+}
+
+fn nonzero_masked_select(device: &Device) -> Result<()> {
+    let t = Tensor::new(&[[0f32, 1., 0.], [2., 0., -3.]], device)?;
+    let nz = t.nonzero()?;
+    assert_eq!(nz.dtype(), DType::U32);
+    assert_eq!(nz.to_vec2::<u32>()?, [[0, 1], [1, 0], [1, 2]]);
+    let t3 = Tensor::new(&[[[0u8, 1], [0, 0]], [[1, 0], [0, 1]]], device)?;
+    assert_eq!(
+        t3.nonzero()?.to_vec2::<u32>()?,
+        [[0, 0, 1], [1, 0, 0], [1, 1, 1]]
+    );
+    let zeros = Tensor::zeros((2, 3), DType::F32, device)?;
+    assert_eq!(zeros.nonzero()?.dims(), [0, 2]);
+
+    let mask = t.lt(0.5)?;
+    assert_eq!(t.masked_select(&mask)?.to_vec1::<f32>()?, [0., 0., 0., -3.]);
+    let mask = Tensor::new(&[1u8, 0, 1], device)?;
+    assert_eq!(t.masked_select(&mask)?.to_vec1::<f32>()?, [0., 0., 2., -3.]);
+    let empty = t.masked_select(&zeros)?;
+    assert_eq!(empty.dims(), [0]);
+    assert_eq!(empty.dtype(), DType::F32);
+    Ok(())
+}
+
+// Manual inclusive prefix scan along the rows (dim 0) or the columns (dim 1).
+fn prefix_scan(vs: &[Vec<f32>], dim: usize, f: impl Fn(f32, f32) -> f32) -> Vec<Vec<f32>> {
+    let mut vs = vs.to_vec();
+    for i in 0..vs.len() {
+        for j in 0..vs[i].len() {
+            if dim == 0 && i > 0 {
+                vs[i][j] = f(vs[i - 1][j], vs[i][j])
+            } else if dim == 1 && j > 0 {
+                vs[i][j] = f(vs[i][j - 1], vs[i][j])
+            }
+        }
+    }
+    vs
+}
+
+fn cumprod_cummax_cummin(device: &Device) -> Result<()> {
+    let vs = vec![
+        vec![1f32, -2., 3., 0.5, 2.],
+        vec![2., 1., -1., 4., 1.],
+        vec![0.5, 3., 2., -2., 3.],
+    ];
+    let t = Tensor::new(vs.clone(), device)?;
+    for dim in [0, 1] {
+        assert_eq!(
+            t.cumprod(dim)?.to_vec2::<f32>()?,
+            prefix_scan(&vs, dim, |a, b| a * b)
+        );
+        let (max, _) = t.cummax(dim)?;
+        assert_eq!(max.to_vec2::<f32>()?, prefix_scan(&vs, dim, f32::max));
+        let (min, _) = t.cummin(dim)?;
+        assert_eq!(min.to_vec2::<f32>()?, prefix_scan(&vs, dim, f32::min));
+    }
+    let (_, ids) = t.cummax(1)?;
+    assert_eq!(
+        ids.to_vec2::<u32>()?,
+        [[0, 0, 2, 2, 2], [0, 0, 0, 3, 3], [0, 1, 1, 1, 4]]
+    );
+    let (_, ids) = t.cummin(0)?;
+    assert_eq!(
+        ids.to_vec2::<u32>()?,
+        [[0, 0, 0, 0, 0], [0, 0, 1, 0, 1], [2, 0, 1, 2, 1]]
+    );
+    // On ties the last index is returned, as in PyTorch.
+    let t = Tensor::new(&[2u32, 2, 1, 2], device)?;
+    let (max, ids) = t.cummax(0)?;
+    assert_eq!(max.to_vec1::<u32>()?, [2, 2, 2, 2]);
+    assert_eq!(ids.to_vec1::<u32>()?, [0, 1, 1, 3]);
+    Ok(())
+}
+
+fn searchsorted(device: &Device) -> Result<()> {
+    let sorted = Tensor::new(&[1f32, 3., 3., 3., 5., 7.], device)?;
+    let values = Tensor::new(&[[0f32, 1., 3.], [4., 7., 8.]], device)?;
+    let left = sorted.searchsorted(&values, false)?;
+    assert_eq!(left.dtype(), DType::U32);
+    assert_eq!(left.to_vec2::<u32>()?, [[0, 0, 1], [4, 5, 6]]);
+    let right = sorted.searchsorted(&values, true)?;
+    assert_eq!(right.to_vec2::<u32>()?, [[0, 1, 4], [4, 6, 6]]);
+
+    // Batched search, the values are broadcasted over the batch dimension of sorted.
+    let sorted = Tensor::new(&[[1u32, 2, 2, 4], [0, 0, 5, 5]], device)?;
+    let values = Tensor::new(&[0u32, 2, 5], device)?;
+    assert_eq!(
+        sorted.searchsorted(&values, false)?.to_vec2::<u32>()?,
+        [[0, 1, 4], [0, 2, 2]]
+    );
+    assert_eq!(
+        sorted.searchsorted(&values, true)?.to_vec2::<u32>()?,
+        [[0, 3, 4], [2, 2, 4]]
+    );
+    let values = Tensor::new(&[[2u32], [0]], device)?;
+    assert_eq!(
+        Tensor::searchsorted(&sorted, &values, true)?.to_vec2::<u32>()?,
+        [[3], [2]]
+    );
+    let values = Tensor::new(&[1f32], device)?;
+    assert!(sorted.searchsorted(&values, true).is_err());
+    Ok(())
+}
+
+fn bincount_histogram(device: &Device) -> Result<()> {
+    let t = Tensor::new(&[2u8, 0, 2, 5, 2, 1, 0], device)?;
+    let counts = t.bincount(None, 0)?;
+    assert_eq!(counts.dtype(), DType::U32);
+    assert_eq!(counts.to_vec1::<u32>()?, [2, 1, 3, 0, 0, 1]);
+    assert_eq!(
+        t.bincount(None, 8)?.to_vec1::<u32>()?,
+        [2, 1, 3, 0, 0, 1, 0, 0]
+    );
+    let weights = Tensor::new(&[0.5f32, 1., 0.25, 2., 1., 3., 1.5], device)?;
+    let sums = t.bincount(Some(&weights), 0)?;
+    assert_eq!(sums.dtype(), DType::F32);
+    assert_eq!(sums.to_vec1::<f32>()?, [2.5, 3., 1.75, 0., 0., 2.]);
+    let t = Tensor::new(&[1i64, -1], device)?;
+    assert!(t.bincount(None, 0).is_err());
+    assert!(weights.bincount(None, 0).is_err());
+
+    let t = Tensor::arange(0f32, 100., device)?;
+    let (counts, edges) = t.histogram(5, Some((0., 100.)))?;
+    assert_eq!(counts.to_vec1::<u32>()?, [20, 20, 20, 20, 20]);
+    assert_eq!(edges.to_vec1::<f32>()?, [0., 20., 40., 60., 80., 100.]);
+    // Out of range values are dropped, the last bin includes its right edge.
+    let (counts, _) = t.histogram(4, Some((20., 60.)))?;
+    assert_eq!(counts.to_vec1::<u32>()?, [10, 10, 10, 11]);
+    // Without a range, the bins span the min and max values and the max goes in the last bin.
+    let t = Tensor::new(&[1f64, 2., 2., 3., 5.], device)?;
+    let (counts, edges) = t.histogram(4, None)?;
+    assert_eq!(counts.to_vec1::<u32>()?, [1, 2, 1, 1]);
+    assert_eq!(edges.to_vec1::<f64>()?, [1., 2., 3., 4., 5.]);
+    assert!(Tensor::new(&[1u32, 2], device)?.histogram(2, None).is_err());
+    Ok(())
+}
+
+fn bitwise(device: &Device) -> Result<()> {
+    let a = Tensor::new(&[[0b1100u8, 0b1010], [0b0101, 0b1111]], device)?;
+    let b = Tensor::new(&[0b1010u8, 0b0110], device)?;
+    assert_eq!(
+        a.bitwise_and(&b)?.to_vec2::<u8>()?,
+        [[0b1000, 0b0010], [0b0000, 0b0110]]
+    );
+    assert_eq!(
+        a.bitwise_or(&b)?.to_vec2::<u8>()?,
+        [[0b1110, 0b1110], [0b1111, 0b1111]]
+    );
+    assert_eq!(
+        a.bitwise_xor(&b)?.to_vec2::<u8>()?,
+        [[0b0110, 0b1100], [0b1111, 0b1001]]
+    );
+    assert_eq!(a.bitwise_not()?.to_vec2::<u8>()?, [[243, 245], [250, 240]]);
+    assert_eq!(
+        a.bitwise_and_scalar(0b0011)?.to_vec2::<u8>()?,
+        [[0b0000, 0b0010], [0b0001, 0b0011]]
+    );
+    assert_eq!(
+        a.bitwise_or_scalar(255)?.to_vec2::<u8>()?,
+        [[255, 255], [255, 255]]
+    );
+    // Scalars that do not fit in the tensor dtype are rejected rather than truncated.
+    assert!(a.bitwise_and_scalar(256).is_err());
+
+    let t = Tensor::new(&[1u32, 3, 0xffff_ffff], device)?;
+    let shifts = Tensor::new(&[[4u32], [32]], device)?;
+    assert_eq!(
+        t.shl(&shifts)?.to_vec2::<u32>()?,
+        [[16, 48, 0xffff_fff0], [0, 0, 0]]
+    );
+    assert_eq!(
+        t.shr(&shifts)?.to_vec2::<u32>()?,
+        [[0, 0, 0x0fff_ffff], [0, 0, 0]]
+    );
+    let t = Tensor::new(&[-16i64, 16], device)?;
+    let shifts = Tensor::new(&[2i64], device)?;
+    assert_eq!(t.shr(&shifts)?.to_vec1::<i64>()?, [-4, 4]);
+    assert_eq!(t.shl(&shifts)?.to_vec1::<i64>()?, [-64, 64]);
+    assert_eq!(t.bitwise_or_scalar(1)?.to_vec1::<i64>()?, [-15, 17]);
+    assert_eq!(t.bitwise_xor_scalar(0)?.to_vec1::<i64>()?, [-16, 16]);
+    assert_eq!(
+        t.bitwise_and_scalar(u32::MAX)?.to_vec1::<i64>()?,
+        [0xffff_fff0, 16]
+    );
+    assert_eq!(t.bitwise_not()?.to_vec1::<i64>()?, [15, -17]);
+
+    let f = Tensor::new(&[1f32, 2.], device)?;
+    assert!(f.bitwise_and(&f).is_err());
+    assert!(f.bitwise_not().is_err());
+    assert!(f.shl(&f).is_err());
+    assert!(f.bitwise_and_scalar(1).is_err());
+    // Mixing dtypes is an error too.
+    let u = Tensor::new(&[1u32, 2], device)?;
+    assert!(u.bitwise_or(&u.to_dtype(DType::I64)?).is_err());
+    // Incompatible shapes cannot be broadcasted.
+    assert!(u.bitwise_xor(&Tensor::new(&[1u32, 2, 3], device)?).is_err());
+    Ok(())
+}
+
+fn unique(device: &Device) -> Result<()> {
+    let t = Tensor::new(&[[5i64, -2, 5], [7, -2, 5], [0, 7, 5]], device)?;
+    assert_eq!(t.unique(true)?.to_vec1::<i64>()?, [-2, 0, 5, 7]);
+    assert_eq!(t.unique(false)?.to_vec1::<i64>()?, [5, -2, 7, 0]);
+    let (values, inverse, counts) = t.unique_with(true, true)?;
+    assert_eq!(values.to_vec1::<i64>()?, [-2, 0, 5, 7]);
+    let inverse = inverse.unwrap();
+    assert_eq!(inverse.dims(), [3, 3]);
+    assert_eq!(inverse.to_vec2::<u32>()?, [[2, 0, 2], [3, 0, 2], [1, 3, 2]]);
+    assert_eq!(counts.unwrap().to_vec1::<u32>()?, [2, 1, 4, 2]);
+    // The inverse indices map back to the original tensor.
+    assert_eq!(
+        values
+            .index_select(&inverse.flatten_all()?, 0)?
+            .reshape((3, 3))?
+            .to_vec2::<i64>()?,
+        t.to_vec2::<i64>()?
+    );
+    let (_, inverse, counts) = t.unique_with(false, true)?;
+    assert!(inverse.is_none());
+    assert!(counts.is_some());
+
+    let t = Tensor::new(&[1.5f32, -0.5, 1.5, 2.0], device)?;
+    assert_eq!(t.unique(true)?.to_vec1::<f32>()?, [-0.5, 1.5, 2.0]);
+    let (_, _, counts) = t.unique_with(false, true)?;
+    assert_eq!(counts.unwrap().to_vec1::<u32>()?, [1, 2, 1]);
+
+    let t = Tensor::zeros(0, DType::U32, device)?;
+    let (values, inverse, counts) = t.unique_with(true, true)?;
+    assert_eq!(values.dims(), [0]);
+    assert_eq!(inverse.unwrap().dims(), [0]);
+    assert_eq!(counts.unwrap().dims(), [0]);
+    assert_eq!(t.unique(false)?.dims(), [0]);
+    Ok(())
+}
+
+fn median_quantile_mode(device: &Device) -> Result<()> {
+    // Odd and even lengths, the median is the lower middle value of the sorted values.
+    let rows = [vec![3f32, 9., 1., 7., 5.], vec![2f32, 8., 4., 6., 0., 10.]];
+    for row in rows.iter() {
+        let t = Tensor::new(row.as_slice(), device)?.reshape((1, row.len()))?;
+        let mut sorted = row.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = sorted[(row.len() - 1) / 2];
+        let (values, indices) = t.median(1)?;
+        assert_eq!(values.to_vec1::<f32>()?, [median]);
+        let index = indices.to_vec1::<u32>()?[0] as usize;
+        assert_eq!(row[index], median);
+        let (values, indices) = t.median_keepdim(1)?;
+        assert_eq!(values.to_vec2::<f32>()?, [[median]]);
+        assert_eq!(indices.dims(), [1, 1]);
+
+        for q in [0., 0.1, 0.25, 0.5, 0.9, 1.] {
+            let pos = q * (row.len() - 1) as f64;
+            let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+            let frac = (pos - lo as f64) as f32;
+            let expected = sorted[lo] + (sorted[hi] - sorted[lo]) * frac;
+            let quantile = t.quantile(q, 1)?.to_vec1::<f32>()?;
+            assert!(
+                (quantile[0] - expected).abs() < 1e-5,
+                "{q} {quantile:?} {expected}"
+            );
+        }
+    }
+    let t = Tensor::new(&[[3f64, 1.], [4., 1.], [5., 9.]], device)?;
+    let (values, indices) = t.median(0)?;
+    assert_eq!(values.to_vec1::<f64>()?, [4., 1.]);
+    assert_eq!(indices.to_vec1::<u32>()?, [1, 1]);
+    assert_eq!(t.quantile(0.75, 0)?.to_vec1::<f64>()?, [4.5, 5.]);
+    assert!(t.quantile(1.5, 0).is_err());
+    assert!(Tensor::new(&[1u32, 2], device)?.quantile(0.5, 0).is_err());
+
+    let t = Tensor::new(&[[1i64, 3, 3, 2, 1], [7, 7, 7, 0, 0]], device)?;
+    let (values, indices) = t.mode(1)?;
+    assert_eq!(values.to_vec1::<i64>()?, [1, 7]);
+    assert_eq!(indices.to_vec1::<u32>()?, [0, 0]);
+    let t = Tensor::new(&[[[2f32, 5.], [2., 4.]], [[1., 5.], [2., 4.]]], device)?;
+    let (values, indices) = t.mode(0)?;
+    assert_eq!(values.to_vec2::<f32>()?, [[1., 5.], [2., 4.]]);
+    assert_eq!(indices.to_vec2::<u32>()?, [[1, 0], [0, 0]]);
+    let (values, indices) = t.mode(1)?;
+    assert_eq!(values.to_vec2::<f32>()?, [[2., 4.], [1., 4.]]);
+    assert_eq!(indices.to_vec2::<u32>()?, [[0, 1], [0, 1]]);
+    Ok(())
+}
+
 #[test]
 fn cumsum() -> Result<()> {
     let t = &[3f32, 1., 4., 1., 5.];
@@ -1311,26 +2405,53 @@ fn cumsum() -> Result<()> {
     Ok(())
 }
 
-/// A helper function for floating point comparison. Both a and b must be 1D Tensor and contains the same amount of data.
-/// Assertion passes if the difference of all pairs of a and b is smaller than epsilon.
-fn assert_close(a: &Tensor, b: &Tensor, epsilon: f64) -> Result<()> {
-    let a_vec: Vec<f64> = a.to_vec1()?;
-    let b_vec: Vec<f64> = b.to_vec1()?;
-
-    assert_eq!(a_vec.len(), b_vec.len());
-    for (a, b) in a_vec.iter().zip(b_vec.iter()) {
-        assert!((a - b).abs() < epsilon);
-    }
-    Ok(())
-}
-
 #[test]
 fn log_sum_exp() -> Result<()> {
     let input = Tensor::new(&[[1f64, 2., 3.], [4., 5., 6.]], &Device::Cpu)?;
     let output = input.log_sum_exp(D::Minus1)?;
     // The expectations obtained from pytorch.
     let expected = Tensor::new(&[3.4076, 6.4076], &Device::Cpu)?;
-    assert_close(&output, &expected, 0.00001)?;
+    test_utils::assert_close(&output, &expected, 0., 1e-5);
+    let output = input.log_sum_exp_keepdim(0)?;
+    assert_eq!(output.dims(), [1, 3]);
+    let expected = Tensor::new(&[4.0486, 5.0486, 6.0486], &Device::Cpu)?;
+    test_utils::assert_close(&output.squeeze(0)?, &expected, 0., 1e-4);
+    let output = input.log_sum_exp((0, 1))?;
+    assert_eq!(output.dims(), [0usize; 0]);
+    let expected = Tensor::new(6.4561f64, &Device::Cpu)?;
+    test_utils::assert_close(&output, &expected, 0., 1e-4);
+
+    // Large inputs overflow with the naive implementation but not with the max-shift.
+    let input = Tensor::new(&[[1e30f32, 1e30], [-1e30, 0.]], &Device::Cpu)?;
+    let naive = input.exp()?.sum(1)?.log()?.to_vec1::<f32>()?;
+    assert!(naive[0].is_infinite());
+    let output = input.log_sum_exp(1)?.to_vec1::<f32>()?;
+    assert_eq!(output, [1e30, 0.]);
+    let input = Tensor::new(&[[f32::NEG_INFINITY, f32::NEG_INFINITY]], &Device::Cpu)?;
+    let output = input.log_sum_exp(1)?.to_vec1::<f32>()?;
+    assert_eq!(output, [f32::NEG_INFINITY]);
+    for dtype in [DType::F16, DType::BF16, DType::F64] {
+        let input = Tensor::new(&[[3f32, 3.]], &Device::Cpu)?.to_dtype(dtype)?;
+        let output = input
+            .log_sum_exp(1)?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>()?;
+        assert!((output[0] - 3. - 2f32.ln()).abs() < 1e-2);
+    }
+
+    let lhs = Tensor::new(&[0f64, 1e3, -1e3, f64::NEG_INFINITY, 2.], &Device::Cpu)?;
+    let rhs = Tensor::new(&[0f64, 1e3, 5., f64::NEG_INFINITY, 3.], &Device::Cpu)?;
+    let output = lhs.logaddexp(&rhs)?.to_vec1::<f64>()?;
+    let expected = [
+        2f64.ln(),
+        1e3 + 2f64.ln(),
+        5.,
+        f64::NEG_INFINITY,
+        (2f64.exp() + 3f64.exp()).ln(),
+    ];
+    for (o, e) in output.iter().zip(expected.iter()) {
+        assert!(o == e || (o - e).abs() < 1e-9, "{output:?} {expected:?}");
+    }
     Ok(())
 }
 
@@ -1345,3 +2466,282 @@ fn pow() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn complex() -> Result<()> {
+    let device = &Device::Cpu;
+    let re = Tensor::new(&[[1f32, 3.], [0., 2.]], device)?;
+    let im = Tensor::new(&[[2f32, 0.], [-1., -1.]], device)?;
+    let a = Tensor::complex(&re, &im)?;
+    assert_eq!(a.dtype(), DType::C64);
+    assert_eq!(a.dims(), [2, 2]);
+    assert_eq!(a.real()?.to_vec2::<f32>()?, [[1., 3.], [0., 2.]]);
+    assert_eq!(a.imag()?.to_vec2::<f32>()?, [[2., 0.], [-1., -1.]]);
+    assert_eq!(a.t()?.real()?.to_vec2::<f32>()?, [[1., 0.], [3., 2.]]);
+    assert_eq!(a.conj()?.imag()?.to_vec2::<f32>()?, [[-2., 0.], [1., 1.]]);
+
+    let b = Tensor::complex(
+        &Tensor::new(&[[1f32, 0.], [2., -1.]], device)?,
+        &Tensor::new(&[[0f32, 1.], [1., 0.]], device)?,
+    )?;
+    // (1+2i)(1) + 3(2+i) = 7+5i, (1+2i)(i) + 3(-1) = -5+i
+    // (-i)(1) + (2-i)(2+i) = 5-i, (-i)(i) + (2-i)(-1) = -1+i
+    let c = a.matmul(&b)?;
+    assert_eq!(c.real()?.to_vec2::<f32>()?, [[7., -5.], [5., -1.]]);
+    assert_eq!(c.imag()?.to_vec2::<f32>()?, [[5., 1.], [-1., 1.]]);
+
+    let s = (&a + &b)?;
+    assert_eq!(s.real()?.to_vec2::<f32>()?, [[2., 3.], [2., 1.]]);
+    assert_eq!(s.imag()?.to_vec2::<f32>()?, [[2., 1.], [0., -1.]]);
+    let p = (&a * &b)?;
+    assert_eq!(p.real()?.to_vec2::<f32>()?, [[1., 0.], [1., -2.]]);
+    assert_eq!(p.imag()?.to_vec2::<f32>()?, [[2., 3.], [-2., 1.]]);
+    let n = a.neg()?;
+    assert_eq!(n.real()?.to_vec2::<f32>()?, [[-1., -3.], [0., -2.]]);
+    assert_eq!(n.imag()?.to_vec2::<f32>()?, [[-2., 0.], [1., 1.]]);
+
+    let c = Tensor::complex(&re.to_dtype(DType::F64)?, &im.to_dtype(DType::F64)?)?;
+    assert_eq!(c.dtype(), DType::C128);
+    let c = c.to_dtype(DType::C64)?;
+    assert_eq!(c.real()?.to_vec2::<f32>()?, [[1., 3.], [0., 2.]]);
+    assert_eq!(c.imag()?.to_vec2::<f32>()?, [[2., 0.], [-1., -1.]]);
+
+    let t = Tensor::complex(
+        &Tensor::new(&[1f32, 2.], device)?,
+        &Tensor::new(&[3f32, -4.], device)?,
+    )?;
+    assert_eq!(
+        format!("{t}"),
+        "[1.0000+3.0000i, 2.0000-4.0000i]\nTensor[[2], c64]"
+    );
+    // Large complex tensors are summarized like the other dtypes.
+    let t = Tensor::complex(
+        &Tensor::arange(0f32, 2000., device)?,
+        &Tensor::ones(2000, DType::F32, device)?,
+    )?;
+    assert_eq!(
+        format!("{t}"),
+        "[   0.0000+1.0000i,    1.0000+1.0000i,    2.0000+1.0000i, ..., 1997.0000+1.0000i, 1998.0000+1.0000i, 1999.0000+1.0000i]\nTensor[[2000], c64]"
+    );
+    assert!(a.to_vec2::<f32>().is_err());
+    assert!(re.real().is_err());
+    Ok(())
+}
+
+fn topk(device: &Device) -> Result<()> {
+    let t = Tensor::new(&[[3f32, 1., 4., 1., 5.], [9., 2., 6., 5., 3.]], device)?;
+    let (values, indexes) = t.topk(3, 1)?;
+    assert_eq!(values.to_vec2::<f32>()?, [[5., 4., 3.], [9., 6., 5.]]);
+    assert_eq!(indexes.to_vec2::<u32>()?, [[4, 2, 0], [0, 2, 3]]);
+    let (values, indexes) = t.topk_smallest(2, D::Minus1)?;
+    assert_eq!(values.to_vec2::<f32>()?, [[1., 1.], [2., 3.]]);
+    assert_eq!(indexes.to_vec2::<u32>()?, [[1, 3], [1, 4]]);
+    let (values, indexes) = t.topk(1, 0)?;
+    assert_eq!(values.to_vec2::<f32>()?, [[9., 2., 6., 5., 5.]]);
+    assert_eq!(indexes.to_vec2::<u32>()?, [[1, 1, 1, 1, 0]]);
+    assert!(t.topk(6, 1).is_err());
+    Ok(())
+}
+
+#[test]
+fn topk_random() -> Result<()> {
+    for &(n, k) in [(7, 3), (100, 10), (32768, 50), (32768, 32768)].iter() {
+        let t = Tensor::randn(0f32, 1f32, (3, n), &Device::Cpu)?;
+        let (sorted, sorted_indexes) = t.sort(1, true)?;
+        let (values, indexes) = t.topk(k, 1)?;
+        assert_eq!(values.dims(), [3, k]);
+        assert_eq!(
+            values.to_vec2::<f32>()?,
+            sorted.narrow(1, 0, k)?.to_vec2::<f32>()?
+        );
+        assert_eq!(
+            indexes.to_vec2::<u32>()?,
+            sorted_indexes.narrow(1, 0, k)?.to_vec2::<u32>()?
+        );
+        let (sorted, _) = t.sort(1, false)?;
+        let (values, _) = t.t()?.topk_smallest(k, 0)?;
+        assert_eq!(
+            values.t()?.to_vec2::<f32>()?,
+            sorted.narrow(1, 0, k)?.to_vec2::<f32>()?
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn sort_stable() -> Result<()> {
+    let t = Tensor::new(&[1u32, 0, 1, 0, 1], &Device::Cpu)?;
+    assert_eq!(t.argsort(0, false)?.to_vec1::<u32>()?, [1, 3, 0, 2, 4]);
+    assert_eq!(t.argsort(0, true)?.to_vec1::<u32>()?, [0, 2, 4, 1, 3]);
+    let (sorted, _) = t.sort(0, true)?;
+    assert_eq!(sorted.to_vec1::<u32>()?, [1, 1, 1, 0, 0]);
+    Ok(())
+}
+
+#[test]
+fn inplace_ops() -> Result<()> {
+    let dev = &Device::Cpu;
+    let rhs = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?;
+    let mut t = Tensor::new(&[[10f32, 20.], [30., 40.]], dev)?;
+    let id = t.id();
+    t.add_(&rhs)?;
+    t.mul_(&rhs.t()?)?;
+    t.sub_(&rhs)?;
+    t.div_(&Tensor::new(&[[2f32, 2.], [2., 2.]], dev)?)?;
+    t.affine_(2., 1.)?;
+    // The buffer is reused so the tensor keeps its id.
+    assert_eq!(t.id(), id);
+    assert_eq!(t.to_vec2::<f32>()?, [[11., 65.], [64., 173.]]);
+
+    // Clones and views sharing the storage are not modified.
+    let alias = t.clone();
+    let view = t.narrow(0, 1, 1)?;
+    t.add_(&rhs)?;
+    assert_ne!(t.id(), id);
+    assert_eq!(alias.to_vec2::<f32>()?, [[11., 65.], [64., 173.]]);
+    assert_eq!(view.to_vec2::<f32>()?, [[64., 173.]]);
+    assert_eq!(t.to_vec2::<f32>()?, [[12., 67.], [67., 177.]]);
+    let mut view = view;
+    view.affine_(0., 1.)?;
+    assert_eq!(view.to_vec2::<f32>()?, [[1., 1.]]);
+    assert_eq!(alias.to_vec2::<f32>()?, [[11., 65.], [64., 173.]]);
+
+    let mut u = Tensor::new(&[1u32, 2, 3], dev)?;
+    u.mul_(&Tensor::new(&[3u32, 2, 1], dev)?)?;
+    assert_eq!(u.to_vec1::<u32>()?, [3, 4, 3]);
+    assert!(u.add_(&Tensor::new(&[1u32, 2], dev)?).is_err());
+    assert!(u.add_(&Tensor::new(&[1f32, 2., 3.], dev)?).is_err());
+    Ok(())
+}
+
+#[test]
+fn inplace_ops_backprop() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = candle_core::Var::new(&[1f32, 2., 3.], dev)?;
+    let c = Tensor::new(&[1f32, 1., 2.], dev)?;
+    // y = exp(x) * c + c, the exp output is needed for backprop and must not be modified.
+    let e = x.exp()?;
+    let mut y = e.clone();
+    y.mul_(&c)?;
+    y.add_(&c)?;
+    let grads = y.sum_all()?.backward()?;
+    let expected = (x.exp()? * &c)?;
+    assert_eq!(
+        test_utils::to_vec1_round(grads.get(&x).unwrap(), 4)?,
+        test_utils::to_vec1_round(&expected, 4)?
+    );
+    assert_eq!(
+        test_utils::to_vec1_round(&e, 4)?,
+        test_utils::to_vec1_round(&x.exp()?, 4)?
+    );
+    Ok(())
+}
+
+#[test]
+fn to_scalar() -> Result<()> {
+    let device = &Device::Cpu;
+    assert_eq!(Tensor::new(2.5f32, device)?.to_scalar::<f32>()?, 2.5);
+    assert_eq!(Tensor::new(&[7u32], device)?.to_scalar::<u32>()?, 7);
+    assert_eq!(Tensor::new(&[[-3i64]], device)?.to_scalar::<i64>()?, -3);
+    // The single element of a view is read at the right offset.
+    let t = Tensor::arange(0f32, 6., device)?.reshape((2, 3))?;
+    assert_eq!(t.i((1.., 2..))?.to_scalar::<f32>()?, 5.);
+    assert_eq!(t.sum_all()?.to_vec0::<f32>()?, 15.);
+
+    let err = t.to_scalar::<f32>().unwrap_err().to_string();
+    assert!(
+        err.starts_with("to_scalar expects a tensor with a single element, got shape [2, 3]"),
+        "{err}"
+    );
+    let err = Tensor::zeros(0, DType::F32, device)?
+        .to_scalar::<f32>()
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("got shape [0]"), "{err}");
+    let err = Tensor::new(&[1f32], device)?
+        .to_scalar::<f64>()
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.starts_with(
+            "to_scalar: unexpected dtype, use to_f64 to convert, expected: F64, got: F32"
+        ),
+        "{err}"
+    );
+
+    assert_eq!(Tensor::new(&[[0.25f32]], device)?.to_f64()?, 0.25);
+    assert_eq!(Tensor::new(3u8, device)?.to_f64()?, 3.);
+    assert_eq!(Tensor::new(&[-2i64], device)?.to_f64()?, -2.);
+    let t = Tensor::new(1.5f32, device)?;
+    assert_eq!(t.to_dtype(DType::BF16)?.to_f64()?, 1.5);
+    assert_eq!(t.to_dtype(DType::F16)?.to_f64()?, 1.5);
+    assert!(Tensor::new(&[1f32, 2.], device)?.to_f64().is_err());
+    Ok(())
+}
+
+#[test]
+fn shape_mismatch_names() -> Result<()> {
+    let device = &Device::Cpu;
+    let lhs = Tensor::zeros((2, 3), DType::F32, device)?;
+    let rhs = Tensor::zeros((4, 5), DType::F32, device)?;
+    let err = lhs.matmul(&rhs).unwrap_err().to_string();
+    assert!(
+        err.starts_with("shape mismatch in matmul, lhs: [2, 3], rhs: [4, 5]"),
+        "{err}"
+    );
+
+    let lhs = lhs.with_name("x");
+    let named_rhs = rhs.with_name("w");
+    assert_eq!(named_rhs.name(), Some("w"));
+    // Naming returns a new handle on the same tensor, the original one is left unnamed.
+    assert_eq!(rhs.name(), None);
+    assert_eq!(named_rhs.id(), rhs.id());
+    let rhs = named_rhs;
+    let err = lhs.matmul(&rhs).unwrap_err().to_string();
+    assert!(
+        err.starts_with(
+            "shape mismatch in matmul, lhs: [2, 3], rhs: [4, 5], lhs name: x, rhs name: w"
+        ),
+        "{err}"
+    );
+    // Views inherit the name, results of computations do not.
+    let err = lhs.matmul(&rhs.t()?).unwrap_err().to_string();
+    assert!(
+        err.contains("rhs: [5, 4], lhs name: x, rhs name: w"),
+        "{err}"
+    );
+    let err = lhs
+        .broadcast_matmul(&rhs.unsqueeze(0)?)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.starts_with("shape mismatch in broadcast_matmul, lhs: [2, 3], rhs: [1, 4, 5], lhs name: x, rhs name: w"),
+        "{err}"
+    );
+    let err = lhs.broadcast_add(&rhs).unwrap_err().to_string();
+    assert!(
+        err.starts_with(
+            "shape mismatch in broadcast_add, lhs: [2, 3], rhs: [4, 5], lhs name: x, rhs name: w"
+        ),
+        "{err}"
+    );
+    let err = (&lhs + 1.)?.add(&rhs).unwrap_err().to_string();
+    assert!(
+        err.starts_with(
+            "shape mismatch in add, lhs: [2, 3], rhs: [4, 5], lhs name: <unnamed>, rhs name: w"
+        ),
+        "{err}"
+    );
+    // The underlying error is still a plain shape mismatch.
+    match lhs.add(&rhs).unwrap_err() {
+        candle_core::Error::WithTensorNames { inner, .. } => {
+            assert!(matches!(
+                *inner,
+                candle_core::Error::ShapeMismatchBinaryOp { .. }
+            ))
+        }
+        candle_core::Error::WithBacktrace { .. } => {}
+        err => panic!("unexpected error {err:?}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use candle_core::test_utils::{allclose, assert_close};
+use candle_core::{DType, Device, Result, Tensor};
+
+#[test]
+fn allclose_pass() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?;
+    let b = (&a + 1e-6)?;
+    assert!(allclose(&a, &b, 0., 1e-5)?);
+    assert!(allclose(&a, &b, 1e-5, 0.)?);
+    assert!(!allclose(&a, &b, 0., 1e-7)?);
+    assert!(!allclose(&a, &a.to_dtype(DType::F64)?, 1., 1.)?);
+    assert!(!allclose(&a, &a.reshape(4)?, 1., 1.)?);
+    let inf = Tensor::new(&[f32::INFINITY, 1.], dev)?;
+    assert!(allclose(&inf, &inf, 0., 0.)?);
+    let nan = Tensor::new(&[f32::NAN, 1.], dev)?;
+    assert!(!allclose(&nan, &nan, 1., 1.)?);
+    assert_close(&a, &b, 1e-4, 1e-4);
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "worst element at [1, 0]: 3.5 <> 3")]
+fn assert_close_fail() {
+    let dev = &Device::Cpu;
+    let a = Tensor::new(&[[1f32, 2.], [3.5, 4.]], dev).unwrap();
+    let b = Tensor::new(&[[1.1f32, 2.], [3., 4.]], dev).unwrap();
+    assert_close(&a, &b, 0., 0.2)
+}
+
+#[test]
+#[should_panic(expected = "shape mismatch")]
+fn assert_close_shape_mismatch() {
+    let a = Tensor::zeros((2, 3), DType::F32, &Device::Cpu).unwrap();
+    let b = Tensor::zeros((3, 2), DType::F32, &Device::Cpu).unwrap();
+    assert_close(&a, &b, 0., 0.)
+}
+
+#[test]
+fn allclose_non_finite() -> Result<()> {
+    let dev = &Device::Cpu;
+    let t = |vs: &[f32]| Tensor::new(vs, dev);
+    let (inf, neg_inf) = (f32::INFINITY, f32::NEG_INFINITY);
+    assert!(allclose(
+        &t(&[inf, neg_inf])?,
+        &t(&[inf, neg_inf])?,
+        0.,
+        0.
+    )?);
+    // Large tolerances do not make mismatched infinities close.
+    assert!(!allclose(&t(&[inf])?, &t(&[neg_inf])?, 1e3, 1e3)?);
+    assert!(!allclose(&t(&[1.])?, &t(&[inf])?, 1e3, 1e3)?);
+    assert!(!allclose(&t(&[inf])?, &t(&[1.])?, 1e3, 1e3)?);
+    assert!(!allclose(&t(&[f32::NAN])?, &t(&[inf])?, 1e3, 1e3)?);
+    assert!(!allclose(&t(&[1.])?, &t(&[f32::NAN])?, 1e3, 1e3)?);
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "worst element at [1]: inf <> -inf")]
+fn assert_close_mismatched_infinities() {
+    let a = Tensor::new(&[1f32, f32::INFINITY], &Device::Cpu).unwrap();
+    let b = Tensor::new(&[1f32, f32::NEG_INFINITY], &Device::Cpu).unwrap();
+    assert_close(&a, &b, 1., 1.)
+}
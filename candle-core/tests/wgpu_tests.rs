@@ -0,0 +1,169 @@
+#![cfg(feature = "wgpu")]
+use candle_core::{test_utils, DType, Device, IndexOp, Result, Tensor, D};
+
+/// Returns `None` when no wgpu adapter is available on the machine running the tests.
+fn wgpu_device() -> Option<Device> {
+    Device::new_wgpu(0).ok()
+}
+
+fn to_vec_cpu(t: &Tensor) -> Result<Vec<f32>> {
+    t.to_device(&Device::Cpu)?.flatten_all()?.to_vec1::<f32>()
+}
+
+fn assert_same(wgpu: &Tensor, cpu: &Tensor) -> Result<()> {
+    assert_eq!(wgpu.dims(), cpu.dims());
+    let (wgpu, cpu) = (to_vec_cpu(wgpu)?, to_vec_cpu(cpu)?);
+    for (w, c) in wgpu.iter().zip(cpu.iter()) {
+        assert!((w - c).abs() <= 1e-4 * (1. + c.abs()), "{wgpu:?} {cpu:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn wgpu_roundtrip() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let t = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], &device)?;
+    assert_eq!(t.to_vec2::<f32>()?, [[1., 2., 3.], [4., 5., 6.]]);
+    let t = Tensor::new(&[3u8, 1, 4], &device)?;
+    assert_eq!(t.to_vec1::<u8>()?, [3, 1, 4]);
+    let t = Tensor::ones((2, 2), DType::U32, &device)?;
+    assert_eq!(t.to_vec2::<u32>()?, [[1, 1], [1, 1]]);
+    let t = Tensor::zeros(3, DType::F32, &device)?;
+    assert_eq!(t.to_vec1::<f32>()?, [0., 0., 0.]);
+    Ok(())
+}
+
+#[test]
+fn wgpu_elementwise() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let cpu = Tensor::randn(0f32, 1., (3, 4, 5), &Device::Cpu)?;
+    let rhs = Tensor::randn(0f32, 1., (4, 1), &Device::Cpu)?;
+    let xs = cpu.to_device(&device)?;
+    let ys = rhs.to_device(&device)?;
+    assert_same(&xs.exp()?, &cpu.exp()?)?;
+    assert_same(&xs.tanh()?, &cpu.tanh()?)?;
+    assert_same(&xs.gelu_erf()?, &cpu.gelu_erf()?)?;
+    assert_same(&xs.affine(2., -1.)?, &cpu.affine(2., -1.)?)?;
+    assert_same(&xs.broadcast_mul(&ys)?, &cpu.broadcast_mul(&rhs)?)?;
+    assert_same(&xs.broadcast_sub(&ys)?, &cpu.broadcast_sub(&rhs)?)?;
+    assert_same(&xs.t()?.contiguous()?, &cpu.t()?.contiguous()?)?;
+    assert_same(&xs.narrow(2, 1, 3)?.sqr()?, &cpu.narrow(2, 1, 3)?.sqr()?)?;
+    let cond = xs.ge(0f64)?;
+    assert_eq!(
+        cond.to_device(&Device::Cpu)?.to_vec3::<u8>()?,
+        cpu.ge(0f64)?.to_vec3::<u8>()?
+    );
+    assert_same(&cond.where_cond(&xs, &xs.zeros_like()?)?, &cpu.relu()?)?;
+    Ok(())
+}
+
+#[test]
+fn wgpu_reduce() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let cpu = Tensor::randn(0f32, 1., (2, 3, 7), &Device::Cpu)?;
+    let xs = cpu.to_device(&device)?;
+    assert_same(&xs.sum_keepdim(D::Minus1)?, &cpu.sum_keepdim(D::Minus1)?)?;
+    assert_same(&xs.sum_keepdim(1)?, &cpu.sum_keepdim(1)?)?;
+    assert_same(&xs.max_keepdim(0)?, &cpu.max_keepdim(0)?)?;
+    assert_same(&xs.min_keepdim(2)?, &cpu.min_keepdim(2)?)?;
+    assert_eq!(
+        xs.argmax_keepdim(D::Minus1)?.to_vec3::<u32>()?,
+        cpu.argmax_keepdim(D::Minus1)?.to_vec3::<u32>()?
+    );
+    Ok(())
+}
+
+#[test]
+fn wgpu_matmul() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let lhs = Tensor::randn(0f32, 1., (2, 5, 7), &Device::Cpu)?;
+    let rhs = Tensor::randn(0f32, 1., (2, 7, 3), &Device::Cpu)?;
+    let lhs_w = lhs.to_device(&device)?;
+    let rhs_w = rhs.to_device(&device)?;
+    assert_same(&lhs_w.matmul(&rhs_w)?, &lhs.matmul(&rhs)?)?;
+    let rhs_t = rhs.transpose(1, 2)?.contiguous()?;
+    let rhs_tw = rhs_t.to_device(&device)?;
+    assert_same(
+        &lhs_w.matmul(&rhs_tw.transpose(1, 2)?)?,
+        &lhs.matmul(&rhs_t.transpose(1, 2)?)?,
+    )?;
+    Ok(())
+}
+
+#[test]
+fn wgpu_indexing() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let cpu = Tensor::arange(0f32, 12., &Device::Cpu)?.reshape((4, 3))?;
+    let xs = cpu.to_device(&device)?;
+    let ids = Tensor::new(&[2u32, 0, 2], &device)?;
+    assert_eq!(
+        xs.index_select(&ids, 0)?.to_vec2::<f32>()?,
+        [[6., 7., 8.], [0., 1., 2.], [6., 7., 8.]]
+    );
+    assert_eq!(
+        xs.index_select(&ids, 1)?.to_vec2::<f32>()?,
+        [[2., 0., 2.], [5., 3., 5.], [8., 6., 8.], [11., 9., 11.]]
+    );
+    let ids = Tensor::new(&[[0u32], [2], [1], [0]], &device)?;
+    assert_eq!(
+        xs.gather(&ids, 1)?.to_vec2::<f32>()?,
+        [[0.], [5.], [7.], [9.]]
+    );
+    let init = Tensor::ones((4, 2), DType::F32, &device)?;
+    let ids = Tensor::new(&[1u32, 1, 0], &device)?;
+    assert_eq!(
+        init.index_add(&ids, &xs, 1)?.to_vec2::<f32>()?,
+        [[3., 2.], [6., 8.], [9., 14.], [12., 20.]]
+    );
+    assert_eq!(xs.i((1.., 1))?.to_vec1::<f32>()?, [4., 7., 10.]);
+    let cat = Tensor::cat(&[&xs, &xs.affine(-1., 0.)?], 1)?;
+    assert_eq!(
+        cat.to_vec2::<f32>()?,
+        Tensor::cat(&[&cpu, &cpu.affine(-1., 0.)?], 1)?.to_vec2::<f32>()?
+    );
+    Ok(())
+}
+
+#[test]
+fn wgpu_to_dtype() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let xs = Tensor::new(&[1.7f32, 0., 3.2], &device)?;
+    assert_eq!(xs.to_dtype(DType::U32)?.to_vec1::<u32>()?, [1, 0, 3]);
+    assert_eq!(xs.to_dtype(DType::U8)?.to_vec1::<u8>()?, [1, 0, 3]);
+    let xs = Tensor::new(&[4u32, 2], &device)?;
+    assert_eq!(xs.to_dtype(DType::F32)?.to_vec1::<f32>()?, [4., 2.]);
+    assert!(xs.to_dtype(DType::F64).is_err());
+    let xs = Tensor::new(&[1f32, 4., 9.], &device)?;
+    assert_eq!(test_utils::to_vec1_round(&xs.sqrt()?, 4)?, [1., 2., 3.]);
+    Ok(())
+}
+
+#[test]
+fn wgpu_unsupported_ops() -> Result<()> {
+    let Some(device) = wgpu_device() else {
+        return Ok(());
+    };
+    let t = Tensor::ones((1, 2, 4, 4), DType::F32, &device)?;
+    let k = Tensor::ones((3, 2, 2, 2), DType::F32, &device)?;
+    for err in [
+        t.conv2d(&k, 0, 1, 1, 1).unwrap_err(),
+        t.max_pool2d(2).unwrap_err(),
+        t.avg_pool2d(2).unwrap_err(),
+        t.upsample_nearest2d(8, 8).unwrap_err(),
+    ] {
+        assert!(err.to_string().contains("not supported on wgpu"), "{err}");
+    }
+    Ok(())
+}
@@ -7,7 +7,8 @@ extern crate accelerate_src;
 use anyhow::{Error as E, Result};
 use clap::Parser;
 
-use candle_transformers::models::gemma::{Config, Model};
+use candle_transformers::models::gemma::{Config, Model as BModel};
+use candle_transformers::models::quantized_gemma::Model as QModel;
 
 use candle::{DType, Device, Tensor};
 use candle_examples::token_output_stream::TokenOutputStream;
@@ -40,6 +41,20 @@ enum Which {
     CodeInstruct7B,
 }
 
+enum Model {
+    B(BModel),
+    Q(QModel),
+}
+
+impl Model {
+    fn forward(&mut self, xs: &Tensor, pos: usize) -> candle::Result<Tensor> {
+        match self {
+            Self::B(m) => m.forward(xs, pos),
+            Self::Q(m) => m.forward(xs, pos),
+        }
+    }
+}
+
 struct TextGeneration {
     model: Model,
     device: Device,
@@ -196,6 +211,10 @@ struct Args {
 
     #[arg(long)]
     use_flash_attn: bool,
+
+    /// Use a quantized gguf model, the gguf file has to be passed via --weight-files.
+    #[arg(long)]
+    quantized: bool,
 }
 
 fn main() -> Result<()> {
@@ -259,7 +278,12 @@ fn main() -> Result<()> {
             .split(',')
             .map(std::path::PathBuf::from)
             .collect::<Vec<_>>(),
-        None => candle_examples::hub_load_safetensors(&repo, "model.safetensors.index.json")?,
+        None => {
+            if args.quantized {
+                anyhow::bail!("--weight-files is required for quantized models")
+            }
+            candle_examples::hub_load_safetensors(&repo, "model.safetensors.index.json")?
+        }
     };
     println!("retrieved the files in {:?}", start.elapsed());
     let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
@@ -272,8 +296,16 @@ fn main() -> Result<()> {
     } else {
         DType::F32
     };
-    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
-    let model = Model::new(args.use_flash_attn, &config, vb)?;
+    let model = if args.quantized {
+        let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
+            &filenames[0],
+            &device,
+        )?;
+        Model::Q(QModel::new(&config, vb.pp("model"))?)
+    } else {
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
+        Model::B(BModel::new(args.use_flash_attn, &config, vb)?)
+    };
 
     println!("loaded the model in {:?}", start.elapsed());
 
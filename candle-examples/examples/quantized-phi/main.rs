@@ -93,6 +93,10 @@ struct Args {
 
     #[arg(long)]
     use_flash_attn: bool,
+
+    /// Store the kv cache as int8, only supported for phi-3.
+    #[arg(long)]
+    quantized_kv_cache: bool,
 }
 
 impl Args {
@@ -216,12 +220,13 @@ fn main() -> anyhow::Result<()> {
         );
         match args.which {
             Which::Phi2 => Model::Phi2(Phi2::from_gguf(model, &mut file, &device)?),
-            Which::Phi3 => Model::Phi3(Phi3::from_gguf(
-                args.use_flash_attn,
-                model,
-                &mut file,
-                &device,
-            )?),
+            Which::Phi3 => {
+                let mut model = Phi3::from_gguf(args.use_flash_attn, model, &mut file, &device)?;
+                if args.quantized_kv_cache {
+                    model.use_quantized_kv_cache()
+                }
+                Model::Phi3(model)
+            }
             Which::Phi3b => Model::Phi3b(Phi3b::from_gguf(model, &mut file, &device)?),
         }
     };
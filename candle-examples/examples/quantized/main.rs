@@ -255,6 +255,10 @@ struct Args {
     /// Use the slower dmmv cuda kernel.
     #[arg(long)]
     force_dmmv: bool,
+
+    /// Store the kv cache as int8.
+    #[arg(long)]
+    quantized_kv_cache: bool,
 }
 
 impl Args {
@@ -471,6 +475,9 @@ fn main() -> anyhow::Result<()> {
             ModelWeights::from_ggml(model, args.gqa.unwrap_or(default_gqa))?
         }
     };
+    if args.quantized_kv_cache {
+        model.use_quantized_kv_cache()
+    }
     println!("model built");
 
     let tokenizer = args.tokenizer()?;
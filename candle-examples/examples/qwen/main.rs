@@ -7,6 +7,7 @@ extern crate accelerate_src;
 use anyhow::{Error as E, Result};
 use clap::Parser;
 
+use candle_transformers::models::quantized_qwen2::Model as QModel;
 use candle_transformers::models::qwen2::{Config as ConfigBase, ModelForCausalLM as ModelBase};
 use candle_transformers::models::qwen2_moe::{Config as ConfigMoe, Model as ModelMoe};
 
@@ -20,6 +21,7 @@ use tokenizers::Tokenizer;
 enum Model {
     Base(ModelBase),
     Moe(ModelMoe),
+    Quantized(QModel),
 }
 
 impl Model {
@@ -27,6 +29,7 @@ impl Model {
         match self {
             Self::Moe(ref mut m) => m.forward(xs, s),
             Self::Base(ref mut m) => m.forward(xs, s),
+            Self::Quantized(ref mut m) => m.forward(xs, s),
         }
     }
 }
@@ -209,6 +212,10 @@ struct Args {
 
     #[arg(long, default_value = "0.5b")]
     model: WhichModel,
+
+    /// Use a quantized gguf model, the gguf file has to be passed via --weight-files.
+    #[arg(long)]
+    quantized: bool,
 }
 
 fn main() -> Result<()> {
@@ -272,6 +279,7 @@ fn main() -> Result<()> {
             .split(',')
             .map(std::path::PathBuf::from)
             .collect::<Vec<_>>(),
+        None if args.quantized => anyhow::bail!("--weight-files is required for quantized models"),
         None => match args.model {
             WhichModel::W0_5b | WhichModel::W2_0_5b | WhichModel::W2_1_5b | WhichModel::W1_8b => {
                 vec![repo.get("model.safetensors")?]
@@ -298,13 +306,25 @@ fn main() -> Result<()> {
     } else {
         DType::F32
     };
-    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
     let model = match args.model {
+        WhichModel::MoeA27b if args.quantized => {
+            anyhow::bail!("no quantized version of the MoE model is available")
+        }
+        _ if args.quantized => {
+            let config: ConfigBase = serde_json::from_slice(&std::fs::read(config_file)?)?;
+            let vb = candle_transformers::quantized_var_builder::VarBuilder::from_gguf(
+                &filenames[0],
+                &device,
+            )?;
+            Model::Quantized(QModel::new(&config, vb)?)
+        }
         WhichModel::MoeA27b => {
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
             let config: ConfigMoe = serde_json::from_slice(&std::fs::read(config_file)?)?;
             Model::Moe(ModelMoe::new(&config, vb)?)
         }
         _ => {
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(&filenames, dtype, &device)? };
             let config: ConfigBase = serde_json::from_slice(&std::fs::read(config_file)?)?;
             Model::Base(ModelBase::new(&config, vb)?)
         }
@@ -10,12 +10,14 @@ use anyhow::{Error as E, Result};
 use candle::{DType, Device, IndexOp, Module, Tensor, D};
 use clap::Parser;
 use stable_diffusion::vae::AutoEncoderKL;
+use stable_diffusion::{img2img, prompt_weighting};
 use tokenizers::Tokenizer;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The prompt to be used for image generation.
+    /// The prompt to be used for image generation, `(word:1.3)` and `[word]` can be used to
+    /// increase or decrease the weight of some words.
     #[arg(
         long,
         default_value = "A very realistic photo of a rusty robot walking on a sandy beach"
@@ -102,6 +104,11 @@ struct Args {
     #[arg(long, default_value_t = 0.8)]
     img2img_strength: f64,
 
+    /// A mask image for inpainting, the white regions are generated and the black regions are
+    /// kept from the img2img image.
+    #[arg(long, value_name = "FILE")]
+    inpaint_mask: Option<String>,
+
     /// The seed to use when generating random samples.
     #[arg(long)]
     seed: Option<u64>,
@@ -293,6 +300,49 @@ fn save_image(
     Ok(())
 }
 
+// Tokenizes a prompt using the `(word:1.3)` emphasis syntax, returns the tokens including the
+// start and end of text tokens together with their weights.
+fn tokenize_weighted(tokenizer: &Tokenizer, prompt: &str) -> Result<(Vec<u32>, Vec<f64>)> {
+    let vocab = tokenizer.get_vocab(true);
+    let special = |token: &str| match vocab.get(token) {
+        Some(id) => Ok(*id),
+        None => anyhow::bail!("cannot find {token} in the tokenizer vocabulary"),
+    };
+    let (bos, eos) = (special("<|startoftext|>")?, special("<|endoftext|>")?);
+    let (tokens, weights) = prompt_weighting::tokenize_weighted(prompt, |text| {
+        let tokens = tokenizer
+            .encode(text, false)
+            .map_err(|e| candle::Error::Msg(e.to_string()))?;
+        Ok(tokens.get_ids().to_vec())
+    })?;
+    let tokens = [vec![bos], tokens, vec![eos]].concat();
+    let weights = [vec![1.0], weights, vec![1.0]].concat();
+    Ok((tokens, weights))
+}
+
+enum TextModel {
+    Clip(stable_diffusion::clip::ClipTextTransformer),
+    // SDXL uses the penultimate hidden states of both text encoders and the pooled output of
+    // the second one.
+    ClipPenultimate(stable_diffusion::clip::ClipTextTransformer),
+    ClipWithProjection(stable_diffusion::clip::ClipTextModelWithProjection),
+}
+
+impl TextModel {
+    // Returns the token embeddings and the pooled embeddings if any.
+    fn encode(&self, tokens: &Tensor) -> Result<(Tensor, Option<Tensor>)> {
+        let res = match self {
+            Self::Clip(m) => (m.forward(tokens)?, None),
+            Self::ClipPenultimate(m) => (m.forward_with_penultimate(tokens)?.0, None),
+            Self::ClipWithProjection(m) => {
+                let (embeddings, pooled) = m.forward(tokens)?;
+                (embeddings, Some(pooled))
+            }
+        };
+        Ok(res)
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn text_embeddings(
     prompt: &str,
@@ -306,7 +356,7 @@ fn text_embeddings(
     dtype: DType,
     use_guide_scale: bool,
     first: bool,
-) -> Result<Tensor> {
+) -> Result<(Tensor, Option<Tensor>)> {
     let tokenizer_file = if first {
         ModelFile::Tokenizer
     } else {
@@ -319,11 +369,7 @@ fn text_embeddings(
         None => *tokenizer.get_vocab(true).get("<|endoftext|>").unwrap(),
     };
     println!("Running with prompt \"{prompt}\".");
-    let mut tokens = tokenizer
-        .encode(prompt, true)
-        .map_err(E::msg)?
-        .get_ids()
-        .to_vec();
+    let (mut tokens, weights) = tokenize_weighted(&tokenizer, prompt)?;
     if tokens.len() > sd_config.clip.max_position_embeddings {
         anyhow::bail!(
             "the prompt is too long, {} > max-tokens ({})",
@@ -348,16 +394,36 @@ fn text_embeddings(
     } else {
         sd_config.clip2.as_ref().unwrap()
     };
-    let text_model =
-        stable_diffusion::build_clip_transformer(clip_config, clip_weights, device, DType::F32)?;
-    let text_embeddings = text_model.forward(&tokens)?;
+    let text_model = if sd_config.clip2.is_none() {
+        let model = stable_diffusion::build_clip_transformer(
+            clip_config,
+            clip_weights,
+            device,
+            DType::F32,
+        )?;
+        TextModel::Clip(model)
+    } else if first {
+        let model = stable_diffusion::build_clip_transformer(
+            clip_config,
+            clip_weights,
+            device,
+            DType::F32,
+        )?;
+        TextModel::ClipPenultimate(model)
+    } else {
+        let model = stable_diffusion::build_clip_transformer_with_projection(
+            clip_config,
+            clip_weights,
+            device,
+            DType::F32,
+        )?;
+        TextModel::ClipWithProjection(model)
+    };
+    let (text_embeddings, pooled) = text_model.encode(&tokens)?;
+    let text_embeddings = prompt_weighting::apply_prompt_weights(&text_embeddings, &weights)?;
 
     let text_embeddings = if use_guide_scale {
-        let mut uncond_tokens = tokenizer
-            .encode(uncond_prompt, true)
-            .map_err(E::msg)?
-            .get_ids()
-            .to_vec();
+        let (mut uncond_tokens, uncond_weights) = tokenize_weighted(&tokenizer, uncond_prompt)?;
         if uncond_tokens.len() > sd_config.clip.max_position_embeddings {
             anyhow::bail!(
                 "the negative prompt is too long, {} > max-tokens ({})",
@@ -370,11 +436,25 @@ fn text_embeddings(
         }
 
         let uncond_tokens = Tensor::new(uncond_tokens.as_slice(), device)?.unsqueeze(0)?;
-        let uncond_embeddings = text_model.forward(&uncond_tokens)?;
-
-        Tensor::cat(&[uncond_embeddings, text_embeddings], 0)?.to_dtype(dtype)?
+        let (uncond_embeddings, uncond_pooled) = text_model.encode(&uncond_tokens)?;
+        let uncond_embeddings =
+            prompt_weighting::apply_prompt_weights(&uncond_embeddings, &uncond_weights)?;
+
+        let text_embeddings =
+            Tensor::cat(&[uncond_embeddings, text_embeddings], 0)?.to_dtype(dtype)?;
+        let pooled = match (uncond_pooled, pooled) {
+            (Some(uncond_pooled), Some(pooled)) => {
+                Some(Tensor::cat(&[uncond_pooled, pooled], 0)?.to_dtype(dtype)?)
+            }
+            _ => None,
+        };
+        (text_embeddings, pooled)
     } else {
-        text_embeddings.to_dtype(dtype)?
+        let pooled = match pooled {
+            Some(pooled) => Some(pooled.to_dtype(dtype)?),
+            None => None,
+        };
+        (text_embeddings.to_dtype(dtype)?, pooled)
     };
     Ok(text_embeddings)
 }
@@ -399,6 +479,24 @@ fn image_preprocess<T: AsRef<std::path::Path>>(path: T) -> anyhow::Result<Tensor
     Ok(img)
 }
 
+fn mask_preprocess<T: AsRef<std::path::Path>>(
+    path: T,
+    height: usize,
+    width: usize,
+) -> anyhow::Result<Tensor> {
+    let img = image::ImageReader::open(path)?.decode()?;
+    let img = img.resize_exact(
+        width as u32,
+        height as u32,
+        image::imageops::FilterType::Nearest,
+    );
+    let img = img.to_luma8().into_raw();
+    let mask = Tensor::from_vec(img, (1, 1, height, width), &Device::Cpu)?
+        .to_dtype(DType::F32)?
+        .affine(1. / 255., 0.)?;
+    Ok(mask)
+}
+
 fn run(args: Args) -> Result<()> {
     use tracing_chrome::ChromeLayerBuilder;
     use tracing_subscriber::prelude::*;
@@ -425,6 +523,7 @@ fn run(args: Args) -> Result<()> {
         use_flash_attn,
         img2img,
         img2img_strength,
+        inpaint_mask,
         seed,
         ..
     } = args;
@@ -432,6 +531,9 @@ fn run(args: Args) -> Result<()> {
     if !(0. ..=1.).contains(&img2img_strength) {
         anyhow::bail!("img2img-strength should be between 0 and 1, got {img2img_strength}")
     }
+    if inpaint_mask.is_some() && img2img.is_none() {
+        anyhow::bail!("inpainting requires an initial image to be set with --img2img")
+    }
 
     let _guard = if tracing {
         let (chrome_layer, guard) = ChromeLayerBuilder::new().build();
@@ -507,30 +609,38 @@ fn run(args: Args) -> Result<()> {
         })
         .collect::<Result<Vec<_>>>()?;
 
+    let (text_embeddings, pooled): (Vec<_>, Vec<_>) = text_embeddings.into_iter().unzip();
     let text_embeddings = Tensor::cat(&text_embeddings, D::Minus1)?;
     let text_embeddings = text_embeddings.repeat((bsize, 1, 1))?;
     println!("{text_embeddings:?}");
+    // The pooled text embeddings and the size conditioning used by SDXL.
+    let added_cond = match pooled.into_iter().flatten().next() {
+        None => None,
+        Some(pooled) => {
+            let pooled = pooled.repeat((bsize, 1))?;
+            let size = (sd_config.height, sd_config.width);
+            let time_ids = stable_diffusion::sdxl::add_time_ids(size, (0, 0), size, &device)?
+                .to_dtype(dtype)?
+                .repeat((pooled.dim(0)?, 1))?;
+            Some((pooled, time_ids))
+        }
+    };
 
     println!("Building the autoencoder.");
     let vae_weights = ModelFile::Vae.get(vae_weights, sd_version, use_f16)?;
     let vae = sd_config.build_vae(vae_weights, &device, dtype)?;
-    let init_latent_dist = match &img2img {
+    let init_image = match &img2img {
         None => None,
         Some(image) => {
             let image = image_preprocess(image)?.to_device(&device)?;
-            Some(vae.encode(&image)?)
+            let init_latent_dist = vae.encode(&image)?;
+            Some((image.dims4()?, init_latent_dist))
         }
     };
     println!("Building the unet.");
     let unet_weights = ModelFile::Unet.get(unet_weights, sd_version, use_f16)?;
     let unet = sd_config.build_unet(unet_weights, &device, 4, use_flash_attn, dtype)?;
 
-    let t_start = if img2img.is_some() {
-        n_steps - (n_steps as f64 * img2img_strength) as usize
-    } else {
-        0
-    };
-
     let vae_scale = match sd_version {
         StableDiffusionVersion::V1_5
         | StableDiffusionVersion::V2_1
@@ -540,15 +650,27 @@ fn run(args: Args) -> Result<()> {
 
     for idx in 0..num_samples {
         let timesteps = scheduler.timesteps();
-        let latents = match &init_latent_dist {
-            Some(init_latent_dist) => {
-                let latents = (init_latent_dist.sample()? * vae_scale)?.to_device(&device)?;
-                if t_start < timesteps.len() {
-                    let noise = latents.randn_like(0f64, 1f64)?;
-                    scheduler.add_noise(&latents, noise, timesteps[t_start])?
-                } else {
-                    latents
-                }
+        let (latents, t_start, inpaint) = match &init_image {
+            Some(((_, _, h, w), init_latent_dist)) => {
+                let init_latents = (init_latent_dist.sample()? * vae_scale)?;
+                let noise = init_latents.randn_like(0f64, 1f64)?;
+                let (latents, t_start) =
+                    img2img::img2img_latents(&*scheduler, &init_latents, &noise, img2img_strength)?;
+                let inpaint = match &inpaint_mask {
+                    None => None,
+                    Some(mask) => {
+                        let (_, _, latent_h, latent_w) = init_latents.dims4()?;
+                        let mask = mask_preprocess(mask, *h, *w)?.to_device(&device)?;
+                        let mask = img2img::latent_mask(&mask, latent_h, latent_w)?;
+                        let inpaint = img2img::Inpaint::new(
+                            mask,
+                            init_latents.to_dtype(dtype)?,
+                            noise.to_dtype(dtype)?,
+                        )?;
+                        Some(inpaint)
+                    }
+                };
+                (latents, t_start, inpaint)
             }
             None => {
                 let latents = Tensor::randn(
@@ -558,7 +680,7 @@ fn run(args: Args) -> Result<()> {
                     &device,
                 )?;
                 // scale the initial noise by the standard deviation required by the scheduler
-                (latents * scheduler.init_noise_sigma())?
+                ((latents * scheduler.init_noise_sigma())?, 0, None)
             }
         };
         let mut latents = latents.to_dtype(dtype)?;
@@ -576,8 +698,16 @@ fn run(args: Args) -> Result<()> {
             };
 
             let latent_model_input = scheduler.scale_model_input(latent_model_input, timestep)?;
-            let noise_pred =
-                unet.forward(&latent_model_input, timestep as f64, &text_embeddings)?;
+            let noise_pred = unet.forward_with_added_cond(
+                &latent_model_input,
+                timestep as f64,
+                &text_embeddings,
+                added_cond
+                    .as_ref()
+                    .map(|(pooled, time_ids)| (pooled, time_ids)),
+                None,
+                None,
+            )?;
 
             let noise_pred = if use_guide_scale {
                 let noise_pred = noise_pred.chunk(2, 0)?;
@@ -589,6 +719,10 @@ fn run(args: Args) -> Result<()> {
             };
 
             latents = scheduler.step(&noise_pred, timestep, &latents)?;
+            if let Some(inpaint) = &inpaint {
+                let next_timestep = timesteps.get(timestep_index + 1).copied();
+                latents = inpaint.blend(&*scheduler, &latents, next_timestep)?;
+            }
             let dt = start_time.elapsed().as_secs_f32();
             println!("step {}/{n_steps} done, {:.2}s", timestep_index + 1, dt);
 
@@ -20,7 +20,8 @@ use tokenizers::Tokenizer;
 mod multilingual;
 mod pcm_decode;
 
-use candle_transformers::models::whisper::{self as m, audio, Config};
+use candle_transformers::models::whisper::{self as m, audio, timestamps, Config};
+use m::transcribe::{transcribe_long, DecodingResult, Segment, TranscribeOptions, WindowDecoder};
 
 pub enum Model {
     Normal(m::model::Whisper),
@@ -55,6 +56,18 @@ impl Model {
         }
     }
 
+    pub fn decoder_forward_with_cross_attentions(
+        &mut self,
+        x: &Tensor,
+        xa: &Tensor,
+        flush: bool,
+    ) -> candle::Result<(Tensor, Vec<Tensor>)> {
+        match self {
+            Self::Normal(m) => m.decoder.forward_with_cross_attentions(x, xa, flush),
+            Self::Quantized(m) => m.decoder.forward_with_cross_attentions(x, xa, flush),
+        }
+    }
+
     pub fn decoder_final_linear(&self, x: &Tensor) -> candle::Result<Tensor> {
         match self {
             Self::Normal(m) => m.decoder.final_linear(x),
@@ -63,30 +76,12 @@ impl Model {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct DecodingResult {
-    tokens: Vec<u32>,
-    text: String,
-    avg_logprob: f64,
-    no_speech_prob: f64,
-    temperature: f64,
-    compression_ratio: f64,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Clone)]
-struct Segment {
-    start: f64,
-    duration: f64,
-    dr: DecodingResult,
-}
-
 struct Decoder {
     model: Model,
     rng: rand::rngs::StdRng,
     task: Option<Task>,
     timestamps: bool,
+    word_timestamps: bool,
     verbose: bool,
     tokenizer: Tokenizer,
     suppress_tokens: Tensor,
@@ -96,6 +91,7 @@ struct Decoder {
     eot_token: u32,
     no_speech_token: u32,
     no_timestamps_token: u32,
+    sot_prev_token: u32,
     language_token: Option<u32>,
 }
 
@@ -109,6 +105,7 @@ impl Decoder {
         language_token: Option<u32>,
         task: Option<Task>,
         timestamps: bool,
+        word_timestamps: bool,
         verbose: bool,
     ) -> Result<Self> {
         let no_timestamps_token = token_id(&tokenizer, m::NO_TIMESTAMPS_TOKEN)?;
@@ -127,6 +124,7 @@ impl Decoder {
             .collect();
         let suppress_tokens = Tensor::new(suppress_tokens.as_slice(), device)?;
         let sot_token = token_id(&tokenizer, m::SOT_TOKEN)?;
+        let sot_prev_token = token_id(&tokenizer, m::SOT_PREV_TOKEN)?;
         let transcribe_token = token_id(&tokenizer, m::TRANSCRIBE_TOKEN)?;
         let translate_token = token_id(&tokenizer, m::TRANSLATE_TOKEN)?;
         let eot_token = token_id(&tokenizer, m::EOT_TOKEN)?;
@@ -143,6 +141,7 @@ impl Decoder {
             tokenizer,
             task,
             timestamps,
+            word_timestamps,
             verbose,
             suppress_tokens,
             sot_token,
@@ -152,18 +151,12 @@ impl Decoder {
             no_speech_token,
             language_token,
             no_timestamps_token,
+            sot_prev_token,
         })
     }
 
-    fn decode(&mut self, mel: &Tensor, t: f64) -> Result<DecodingResult> {
-        let model = &mut self.model;
-        let audio_features = model.encoder_forward(mel, true)?;
-        if self.verbose {
-            println!("audio features: {:?}", audio_features.dims());
-        }
-        let sample_len = model.config().max_target_positions / 2;
-        let mut sum_logprob = 0f64;
-        let mut no_speech_prob = f64::NAN;
+    /// The special tokens that start the transcription, these come after the prompt if any.
+    fn sot_sequence(&self) -> Vec<u32> {
         let mut tokens = vec![self.sot_token];
         if let Some(language_token) = self.language_token {
             tokens.push(language_token);
@@ -175,6 +168,109 @@ impl Decoder {
         if !self.timestamps {
             tokens.push(self.no_timestamps_token);
         }
+        tokens
+    }
+
+    /// Aligns the text tokens of a segment with the audio window it was decoded from using the
+    /// cross-attention weights.
+    fn word_timings(
+        &mut self,
+        mel: &Tensor,
+        text_tokens: &[u32],
+    ) -> Result<Vec<timestamps::WordTiming>> {
+        if text_tokens.is_empty() {
+            return Ok(vec![]);
+        }
+        // Split the tokens on spaces, a new word starts with each token that begins with a space.
+        let mut words: Vec<(String, Vec<u32>)> = vec![];
+        for &token in text_tokens.iter() {
+            let text = self.tokenizer.decode(&[token], false).map_err(E::msg)?;
+            match words.last_mut() {
+                Some((word, word_tokens)) if !text.starts_with(' ') => {
+                    word.push_str(&text);
+                    word_tokens.push(token)
+                }
+                _ => words.push((text, vec![token])),
+            }
+        }
+        let mut tokens = self.sot_sequence();
+        let text_start = tokens.len();
+        tokens.extend_from_slice(text_tokens);
+        tokens.push(self.eot_token);
+        let (_, _, num_frames) = mel.dims3()?;
+        let model = &mut self.model;
+        let audio_features = model.encoder_forward(mel, true)?;
+        let tokens_t = Tensor::new(tokens.as_slice(), mel.device())?.unsqueeze(0)?;
+        let (_, cross_attentions) =
+            model.decoder_forward_with_cross_attentions(&tokens_t, &audio_features, true)?;
+        let heads = timestamps::default_alignment_heads(model.config());
+        let matrix = timestamps::alignment_matrix(&cross_attentions, &heads, num_frames, 7)?;
+        // The rows predicting the text tokens and the end of text token.
+        let matrix = matrix.narrow(0, text_start - 1, text_tokens.len() + 1)?;
+        Ok(timestamps::word_timings(&matrix, &words)?)
+    }
+
+    fn run(&mut self, mel: &Tensor) -> Result<Vec<Segment>> {
+        let (_, _, content_frames) = mel.dims3()?;
+        let start = std::time::Instant::now();
+        // The timestamp tokens come right after the notimestamps token.
+        let options = TranscribeOptions::new(self.no_timestamps_token + 1, self.model.config());
+        let segments = transcribe_long(self, mel, &options)?;
+        for segment in segments.iter() {
+            let text_tokens: Vec<u32> = segment
+                .tokens
+                .iter()
+                .copied()
+                .filter(|&token| token < self.eot_token)
+                .collect();
+            let text = self.tokenizer.decode(&text_tokens, true).map_err(E::msg)?;
+            println!("{:.1}s -- {:.1}s: {}", segment.start, segment.end, text);
+            if self.word_timestamps {
+                let window_frames = usize::min(content_frames - segment.seek, m::N_FRAMES);
+                let mel_window = mel.narrow(2, segment.seek, window_frames)?;
+                let offset = (segment.seek * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+                for word in self.word_timings(&mel_window, &text_tokens)? {
+                    println!(
+                        "  {:.2}s-{:.2}s: {}",
+                        offset + word.start,
+                        offset + word.end,
+                        word.word.trim(),
+                    )
+                }
+            }
+            if self.verbose {
+                println!("{segment:?}");
+            }
+        }
+        if self.verbose {
+            println!(
+                "transcribed {} segments in {:?}",
+                segments.len(),
+                start.elapsed()
+            );
+        }
+        Ok(segments)
+    }
+}
+
+impl WindowDecoder for Decoder {
+    fn decode(&mut self, mel: &Tensor, prompt: &[u32], t: f64) -> candle::Result<DecodingResult> {
+        let audio_features = self.model.encoder_forward(mel, true)?;
+        if self.verbose {
+            println!("audio features: {:?}", audio_features.dims());
+        }
+        let mut tokens = vec![];
+        if !prompt.is_empty() {
+            tokens.push(self.sot_prev_token);
+            tokens.extend_from_slice(prompt);
+        }
+        let sot_index = tokens.len();
+        tokens.extend(self.sot_sequence());
+        let sample_begin = tokens.len();
+        let model = &mut self.model;
+        let sample_len = model.config().max_target_positions / 2;
+        let mut sum_logprob = 0f64;
+        let mut no_speech_prob = f64::NAN;
         for i in 0..sample_len {
             let tokens_t = Tensor::new(tokens.as_slice(), mel.device())?;
 
@@ -183,10 +279,13 @@ impl Decoder {
             let tokens_t = tokens_t.unsqueeze(0)?;
             let ys = model.decoder_forward(&tokens_t, &audio_features, i == 0)?;
 
-            // Extract the no speech probability on the first iteration by looking at the first
-            // token logits and the probability for the according token.
+            // Extract the no speech probability on the first iteration by looking at the logits
+            // for the sot token and the probability for the according token.
             if i == 0 {
-                let logits = model.decoder_final_linear(&ys.i(..1)?)?.i(0)?.i(0)?;
+                let logits = model
+                    .decoder_final_linear(&ys.i((..1, sot_index..sot_index + 1))?)?
+                    .i(0)?
+                    .i(0)?;
                 no_speech_prob = softmax(&logits, 0)?
                     .i(self.no_speech_token as usize)?
                     .to_scalar::<f32>()? as f64;
@@ -208,7 +307,8 @@ impl Decoder {
             let next_token = if t > 0f64 {
                 let prs = softmax(&(&logits / t)?, 0)?;
                 let logits_v: Vec<f32> = prs.to_vec1()?;
-                let distr = rand::distributions::WeightedIndex::new(&logits_v)?;
+                let distr = rand::distributions::WeightedIndex::new(&logits_v)
+                    .map_err(candle::Error::wrap)?;
                 distr.sample(&mut self.rng) as u32
             } else {
                 let logits_v: Vec<f32> = logits.to_vec1()?;
@@ -228,116 +328,20 @@ impl Decoder {
             }
             sum_logprob += prob.ln();
         }
-        let text = self.tokenizer.decode(&tokens, true).map_err(E::msg)?;
-        let avg_logprob = sum_logprob / tokens.len() as f64;
+        let mut tokens = tokens.split_off(sample_begin);
+        if tokens.last() == Some(&self.eot_token) {
+            tokens.pop();
+        }
+        let avg_logprob = sum_logprob / (tokens.len() + 1) as f64;
 
         Ok(DecodingResult {
             tokens,
-            text,
             avg_logprob,
             no_speech_prob,
             temperature: t,
             compression_ratio: f64::NAN,
         })
     }
-
-    fn decode_with_fallback(&mut self, segment: &Tensor) -> Result<DecodingResult> {
-        for (i, &t) in m::TEMPERATURES.iter().enumerate() {
-            let dr: Result<DecodingResult> = self.decode(segment, t);
-            if i == m::TEMPERATURES.len() - 1 {
-                return dr;
-            }
-            // On errors, we try again with a different temperature.
-            match dr {
-                Ok(dr) => {
-                    let needs_fallback = dr.compression_ratio > m::COMPRESSION_RATIO_THRESHOLD
-                        || dr.avg_logprob < m::LOGPROB_THRESHOLD;
-                    if !needs_fallback || dr.no_speech_prob > m::NO_SPEECH_THRESHOLD {
-                        return Ok(dr);
-                    }
-                }
-                Err(err) => {
-                    println!("Error running at {t}: {err}")
-                }
-            }
-        }
-        unreachable!()
-    }
-
-    fn run(&mut self, mel: &Tensor) -> Result<Vec<Segment>> {
-        let (_, _, content_frames) = mel.dims3()?;
-        let mut seek = 0;
-        let mut segments = vec![];
-        while seek < content_frames {
-            let start = std::time::Instant::now();
-            let time_offset = (seek * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-            let segment_size = usize::min(content_frames - seek, m::N_FRAMES);
-            let mel_segment = mel.narrow(2, seek, segment_size)?;
-            let segment_duration = (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-            let dr = self.decode_with_fallback(&mel_segment)?;
-            seek += segment_size;
-            if dr.no_speech_prob > m::NO_SPEECH_THRESHOLD && dr.avg_logprob < m::LOGPROB_THRESHOLD {
-                println!("no speech detected, skipping {seek} {dr:?}");
-                continue;
-            }
-            let segment = Segment {
-                start: time_offset,
-                duration: segment_duration,
-                dr,
-            };
-            if self.timestamps {
-                println!(
-                    "{:.1}s -- {:.1}s",
-                    segment.start,
-                    segment.start + segment.duration,
-                );
-                let mut tokens_to_decode = vec![];
-                let mut prev_timestamp_s = 0f32;
-                for &token in segment.dr.tokens.iter() {
-                    if token == self.sot_token || token == self.eot_token {
-                        continue;
-                    }
-                    // The no_timestamp_token is the last before the timestamp ones.
-                    if token > self.no_timestamps_token {
-                        let timestamp_s = (token - self.no_timestamps_token + 1) as f32 / 50.;
-                        if !tokens_to_decode.is_empty() {
-                            let text = self
-                                .tokenizer
-                                .decode(&tokens_to_decode, true)
-                                .map_err(E::msg)?;
-                            println!("  {:.1}s-{:.1}s: {}", prev_timestamp_s, timestamp_s, text);
-                            tokens_to_decode.clear()
-                        }
-                        prev_timestamp_s = timestamp_s;
-                    } else {
-                        tokens_to_decode.push(token)
-                    }
-                }
-                if !tokens_to_decode.is_empty() {
-                    let text = self
-                        .tokenizer
-                        .decode(&tokens_to_decode, true)
-                        .map_err(E::msg)?;
-                    if !text.is_empty() {
-                        println!("  {:.1}s-...: {}", prev_timestamp_s, text);
-                    }
-                    tokens_to_decode.clear()
-                }
-            } else {
-                println!(
-                    "{:.1}s -- {:.1}s: {}",
-                    segment.start,
-                    segment.start + segment.duration,
-                    segment.dr.text,
-                )
-            }
-            if self.verbose {
-                println!("{seek}: {segment:?}, in {:?}", start.elapsed());
-            }
-            segments.push(segment)
-        }
-        Ok(segments)
-    }
 }
 
 pub fn token_id(tokenizer: &Tokenizer, token: &str) -> candle::Result<u32> {
@@ -461,10 +465,16 @@ struct Args {
     #[arg(long)]
     task: Option<Task>,
 
-    /// Timestamps mode, this is not fully implemented yet.
+    /// Timestamps mode, the predicted timestamps are used to split the transcription into
+    /// segments and to advance the 30s decoding window.
     #[arg(long)]
     timestamps: bool,
 
+    /// Print the start and end times of each word, using the cross-attention weights to align
+    /// the text with the audio. This is not compatible with the timestamps mode.
+    #[arg(long, conflicts_with = "timestamps")]
+    word_timestamps: bool,
+
     /// Print the full DecodingResult structure rather than just the text.
     #[arg(long)]
     verbose: bool,
@@ -586,6 +596,7 @@ fn main() -> Result<()> {
         language_token,
         args.task,
         args.timestamps,
+        args.word_timestamps,
         args.verbose,
     )?;
     dc.run(&mel)?;
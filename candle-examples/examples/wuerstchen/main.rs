@@ -267,7 +267,7 @@ fn run(args: Args) -> Result<()> {
         // https://huggingface.co/warp-ai/wuerstchen-prior/blob/main/prior/config.json
         let latent_height = (height as f64 / RESOLUTION_MULTIPLE).ceil() as usize;
         let latent_width = (width as f64 / RESOLUTION_MULTIPLE).ceil() as usize;
-        let mut latents = Tensor::randn(
+        let latents = Tensor::randn(
             0f32,
             1f32,
             (b_size, PRIOR_CIN, latent_height, latent_width),
@@ -291,23 +291,24 @@ fn run(args: Args) -> Result<()> {
             )?
         };
         let prior_scheduler = wuerstchen::ddpm::DDPMWScheduler::new(60, Default::default())?;
-        let timesteps = prior_scheduler.timesteps();
-        let timesteps = &timesteps[..timesteps.len() - 1];
+        let n_steps = prior_scheduler.timesteps().len() - 1;
         println!("prior denoising");
-        for (index, &t) in timesteps.iter().enumerate() {
+        let mut index = 0;
+        let latents = prior_scheduler.denoise(latents, |latents, t| {
             let start_time = std::time::Instant::now();
-            let latent_model_input = Tensor::cat(&[&latents, &latents], 0)?;
+            let latent_model_input = Tensor::cat(&[latents, latents], 0)?;
             let ratio = (Tensor::ones(2, DType::F32, &device)? * t)?;
             let noise_pred = prior.forward(&latent_model_input, &ratio, &prior_text_embeddings)?;
             let noise_pred = noise_pred.chunk(2, 0)?;
             let (noise_pred_text, noise_pred_uncond) = (&noise_pred[0], &noise_pred[1]);
             let noise_pred = (noise_pred_uncond
                 + ((noise_pred_text - noise_pred_uncond)? * PRIOR_GUIDANCE_SCALE)?)?;
-            latents = prior_scheduler.step(&noise_pred, t, &latents)?;
+            index += 1;
             let dt = start_time.elapsed().as_secs_f32();
-            println!("step {}/{} done, {:.2}s", index + 1, timesteps.len(), dt);
-        }
-        ((latents * 42.)? - 1.)?
+            println!("step {index}/{n_steps} done, {dt:.2}s");
+            Ok(noise_pred)
+        })?;
+        prior_scheduler.unscale_latents(&latents)?
     };
 
     println!("Building the vqgan.");
@@ -344,7 +345,7 @@ fn run(args: Args) -> Result<()> {
         let latent_height = (image_embeddings.dim(2)? as f64 * LATENT_DIM_SCALE) as usize;
         let latent_width = (image_embeddings.dim(3)? as f64 * LATENT_DIM_SCALE) as usize;
 
-        let mut latents = Tensor::randn(
+        let latents = Tensor::randn(
             0f32,
             1f32,
             (b_size, DECODER_CIN, latent_height, latent_width),
@@ -353,17 +354,18 @@ fn run(args: Args) -> Result<()> {
 
         println!("diffusion process with prior {image_embeddings:?}");
         let scheduler = wuerstchen::ddpm::DDPMWScheduler::new(12, Default::default())?;
-        let timesteps = scheduler.timesteps();
-        let timesteps = &timesteps[..timesteps.len() - 1];
-        for (index, &t) in timesteps.iter().enumerate() {
+        let n_steps = scheduler.timesteps().len() - 1;
+        let mut index = 0;
+        let latents = scheduler.denoise(latents, |latents, t| {
             let start_time = std::time::Instant::now();
             let ratio = (Tensor::ones(1, DType::F32, &device)? * t)?;
             let noise_pred =
-                decoder.forward(&latents, &ratio, &image_embeddings, Some(&text_embeddings))?;
-            latents = scheduler.step(&noise_pred, t, &latents)?;
+                decoder.forward(latents, &ratio, &image_embeddings, Some(&text_embeddings))?;
+            index += 1;
             let dt = start_time.elapsed().as_secs_f32();
-            println!("step {}/{} done, {:.2}s", index + 1, timesteps.len(), dt);
-        }
+            println!("step {index}/{n_steps} done, {dt:.2}s");
+            Ok(noise_pred)
+        })?;
         println!(
             "Generating the final image for sample {}/{}.",
             idx + 1,
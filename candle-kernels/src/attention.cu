@@ -0,0 +1,188 @@
+#include "cuda_utils.cuh"
+#include <cmath>
+#include <stdint.h>
+
+#define WARP_SIZE 32
+// Number of queries processed by a block, each query is handled by a warp.
+#define ATTN_BLOCK_Q 8
+// Number of keys and values loaded in shared memory at once.
+#define ATTN_BLOCK_KV 16
+// Each lane of a warp accumulates ATTN_MAX_HEAD_DIM / WARP_SIZE output values.
+#define ATTN_MAX_HEAD_DIM 256
+#define ATTN_PER_LANE (ATTN_MAX_HEAD_DIM / WARP_SIZE)
+
+static __device__ __forceinline__ float attn_warp_reduce_sum(float x) {
+#pragma unroll
+    for (int mask = 16; mask > 0; mask >>= 1) {
+        x += __shfl_xor_sync(0xffffffff, x, mask, 32);
+    }
+    return x;
+}
+
+// Fused scaled dot-product attention, the keys and values are processed by tiles using an online
+// softmax so that the attention scores are never materialized, see the flash-attention paper:
+// https://arxiv.org/abs/2205.14135
+// q, k, v, and dst are contiguous with the (batch * heads, seq_len, head_dim) layout.
+// info contains n_heads, seq_len, kv_len, head_dim, has_mask, and the mask strides for the
+// batch, head, query, and key dimensions (0 for broadcasted dimensions).
+// When causal_offset is not negative, query i only attends to the keys j <= i + causal_offset.
+template <typename T>
+__device__ void fused_attention(
+    const T *q,
+    const T *k,
+    const T *v,
+    const T *mask,
+    T *dst,
+    const size_t *info,
+    const float scale,
+    const int64_t causal_offset
+) {
+    extern __shared__ float attn_smem[];
+    const size_t n_heads = info[0];
+    const size_t seq_len = info[1];
+    const size_t kv_len = info[2];
+    const size_t head_dim = info[3];
+    const bool has_mask = info[4] != 0;
+    const size_t *mask_strides = info + 5;
+
+    const size_t bh = blockIdx.y;
+    const size_t lane = threadIdx.x;
+    const size_t row = blockIdx.x * ATTN_BLOCK_Q + threadIdx.y;
+    const bool active = row < seq_len;
+    const size_t tid = threadIdx.y * WARP_SIZE + threadIdx.x;
+    const size_t n_threads = WARP_SIZE * ATTN_BLOCK_Q;
+
+    float *k_tile = attn_smem;
+    float *v_tile = attn_smem + ATTN_BLOCK_KV * head_dim;
+
+    q += bh * seq_len * head_dim;
+    k += bh * kv_len * head_dim;
+    v += bh * kv_len * head_dim;
+    dst += bh * seq_len * head_dim;
+    const T *mask_row = mask;
+    if (has_mask) {
+        mask_row += (bh / n_heads) * mask_strides[0] + (bh % n_heads) * mask_strides[1]
+            + row * mask_strides[2];
+    }
+
+    float q_reg[ATTN_PER_LANE];
+    float acc[ATTN_PER_LANE];
+#pragma unroll
+    for (int c = 0; c < ATTN_PER_LANE; ++c) {
+        const size_t d = lane + c * WARP_SIZE;
+        q_reg[c] = (active && d < head_dim) ? static_cast<float>(q[row * head_dim + d]) * scale : 0.0f;
+        acc[c] = 0.0f;
+    }
+    // Running maximum and sum of the exponentials for the query of this warp.
+    float m = -INFINITY;
+    float l = 0.0f;
+
+    // The keys after kv_end are masked for all the queries of this block.
+    size_t kv_end = kv_len;
+    if (causal_offset >= 0) {
+        const size_t last_row = min(blockIdx.x * ATTN_BLOCK_Q + ATTN_BLOCK_Q, seq_len) - 1;
+        kv_end = min(kv_len, last_row + causal_offset + 1);
+    }
+
+    for (size_t kv_start = 0; kv_start < kv_end; kv_start += ATTN_BLOCK_KV) {
+        const size_t tile_len = min((size_t)ATTN_BLOCK_KV, kv_end - kv_start);
+        // Wait for the previous tile to be consumed before overwriting it.
+        __syncthreads();
+        for (size_t idx = tid; idx < tile_len * head_dim; idx += n_threads) {
+            k_tile[idx] = static_cast<float>(k[kv_start * head_dim + idx]);
+            v_tile[idx] = static_cast<float>(v[kv_start * head_dim + idx]);
+        }
+        __syncthreads();
+        if (!active) {
+            continue;
+        }
+
+        float s[ATTN_BLOCK_KV];
+        float tile_max = -INFINITY;
+#pragma unroll
+        for (int jj = 0; jj < ATTN_BLOCK_KV; ++jj) {
+            if (jj < tile_len) {
+                const size_t j = kv_start + jj;
+                float sj = 0.0f;
+#pragma unroll
+                for (int c = 0; c < ATTN_PER_LANE; ++c) {
+                    const size_t d = lane + c * WARP_SIZE;
+                    if (d < head_dim) {
+                        sj += q_reg[c] * k_tile[jj * head_dim + d];
+                    }
+                }
+                sj = attn_warp_reduce_sum(sj);
+                if (causal_offset >= 0 && j > row + causal_offset) {
+                    sj = -INFINITY;
+                } else if (has_mask) {
+                    sj += static_cast<float>(mask_row[j * mask_strides[3]]);
+                }
+                s[jj] = sj;
+                tile_max = fmaxf(tile_max, sj);
+            }
+        }
+
+        const float m_new = fmaxf(m, tile_max);
+        if (m_new == -INFINITY) {
+            // All the scores so far are masked.
+            continue;
+        }
+        // Rescale the previous accumulators to the new maximum.
+        const float correction = expf(m - m_new);
+        l *= correction;
+#pragma unroll
+        for (int c = 0; c < ATTN_PER_LANE; ++c) {
+            acc[c] *= correction;
+        }
+#pragma unroll
+        for (int jj = 0; jj < ATTN_BLOCK_KV; ++jj) {
+            if (jj < tile_len) {
+                const float p = expf(s[jj] - m_new);
+                l += p;
+#pragma unroll
+                for (int c = 0; c < ATTN_PER_LANE; ++c) {
+                    const size_t d = lane + c * WARP_SIZE;
+                    if (d < head_dim) {
+                        acc[c] += p * v_tile[jj * head_dim + d];
+                    }
+                }
+            }
+        }
+        m = m_new;
+    }
+
+    if (!active) {
+        return;
+    }
+#pragma unroll
+    for (int c = 0; c < ATTN_PER_LANE; ++c) {
+        const size_t d = lane + c * WARP_SIZE;
+        if (d < head_dim) {
+            // Fully masked rows result in nans, as with the softmax over the materialized scores.
+            dst[row * head_dim + d] = static_cast<T>(acc[c] / l);
+        }
+    }
+}
+
+#define FUSED_ATTENTION_OP(TYPENAME, FN_NAME) \
+  extern "C" __global__ void FN_NAME( \
+      const TYPENAME *q, \
+      const TYPENAME *k, \
+      const TYPENAME *v, \
+      const TYPENAME *mask, \
+      TYPENAME *dst, \
+      const size_t *info, \
+      const float scale, \
+      const int64_t causal_offset) { \
+    fused_attention<TYPENAME>(q, k, v, mask, dst, info, scale, causal_offset); \
+  } \
+
+#if __CUDA_ARCH__ >= 800
+FUSED_ATTENTION_OP(__nv_bfloat16, fused_attention_bf16)
+#endif
+
+#if __CUDA_ARCH__ >= 530
+FUSED_ATTENTION_OP(__half, fused_attention_f16)
+#endif
+
+FUSED_ATTENTION_OP(float, fused_attention_f32)
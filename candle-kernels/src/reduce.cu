@@ -279,6 +279,56 @@ __device__ void rope_thd(
     dst[i2] = src[i1] * s + src[i2] * c;
 }
 
+// Rotates the queries and the keys with a single launch, the first bh_q * td / 2 threads handle
+// the queries and the remaining ones the keys. The rotated keys are written after the queries in
+// dst.
+template <typename T>
+__device__ void rope_qk(
+    const T * q,
+    const T * k,
+    const T * cos,
+    const T * sin,
+    T * dst,
+    const uint32_t bh_q,
+    const uint32_t bh_k,
+    const uint32_t td,
+    const uint32_t d,
+    const uint32_t interleaved
+) {
+    const uint32_t idx = blockIdx.x * blockDim.x + threadIdx.x;
+    const uint32_t n_q = bh_q * td / 2;
+    if (idx >= n_q + bh_k * td / 2) return;
+
+    const T * src = q;
+    uint32_t i = idx;
+    if (idx >= n_q) {
+        src = k;
+        dst += bh_q * td;
+        i -= n_q;
+    }
+    uint32_t i1, i2, i_cs;
+    if (interleaved) {
+        i1 = 2 * i;
+        i2 = i1 + 1;
+        i_cs = i % (td / 2);
+    } else {
+        uint32_t i_bh = i / (td / 2);
+        uint32_t i_td = i - (td / 2) * i_bh;
+        uint32_t i_t = i_td / (d / 2);
+        uint32_t i_d = i_td - (d / 2) * i_t;
+        i1 = i_bh * td + i_t * d + i_d;
+        i2 = i1 + d / 2;
+        i_cs = i_t * (d / 2) + i_d;
+    }
+    T c = cos[i_cs];
+    T s = sin[i_cs];
+    T x1 = src[i1];
+    T x2 = src[i2];
+
+    dst[i1] = x1 * c - x2 * s;
+    dst[i2] = x1 * s + x2 * c;
+}
+
 template <typename T>
 __device__ void
 fast_max(const size_t src_numel, const size_t el_to_sum_per_block,
@@ -541,7 +591,7 @@ fast_argmax(const size_t src_numel, const size_t el_to_sum_per_block,
     layernorm<TYPENAME>(src, dst, alpha, beta, n_cols, eps);                   \
   }                                                                            \
 
-#define ROPE_OP(TYPENAME, FN_NAME, FN_NAME_I, FN_NAME_THD) \
+#define ROPE_OP(TYPENAME, FN_NAME, FN_NAME_I, FN_NAME_THD, FN_NAME_QK) \
   extern "C" __global__ void FN_NAME_I( \
       const TYPENAME *src, \
       const TYPENAME *cos, \
@@ -572,12 +622,25 @@ fast_argmax(const size_t src_numel, const size_t el_to_sum_per_block,
       const uint32_t d) { \
     rope_thd<TYPENAME>(src, cos, sin, dst, b, t, h, d); \
   } \
+  extern "C" __global__ void FN_NAME_QK( \
+      const TYPENAME *q, \
+      const TYPENAME *k, \
+      const TYPENAME *cos, \
+      const TYPENAME *sin, \
+      TYPENAME *dst, \
+      const uint32_t bh_q, \
+      const uint32_t bh_k, \
+      const uint32_t td, \
+      const uint32_t d, \
+      const uint32_t interleaved) { \
+    rope_qk<TYPENAME>(q, k, cos, sin, dst, bh_q, bh_k, td, d, interleaved); \
+  } \
 
 #if __CUDA_ARCH__ >= 800
 SOFTMAX_OP(__nv_bfloat16, float, softmax_bf16)
 RMSNORM_OP(__nv_bfloat16, rmsnorm_bf16)
 LAYERNORM_OP(__nv_bfloat16, layernorm_bf16)
-ROPE_OP(__nv_bfloat16, rope_bf16, rope_i_bf16, rope_thd_bf16)
+ROPE_OP(__nv_bfloat16, rope_bf16, rope_i_bf16, rope_thd_bf16, rope_qk_bf16)
 SUM_OP(__nv_bfloat16, sum_bf16)
 FAST_OP(__nv_bfloat16, fast_min_bf16, fast_max_bf16, fast_argmin_bf16, fast_argmax_bf16, fast_sum_bf16)
 #endif
@@ -586,7 +649,7 @@ FAST_OP(__nv_bfloat16, fast_min_bf16, fast_max_bf16, fast_argmin_bf16, fast_argm
 SOFTMAX_OP(__half, float, softmax_f16)
 RMSNORM_OP(__half, rmsnorm_f16)
 LAYERNORM_OP(__half, layernorm_f16)
-ROPE_OP(__half, rope_f16, rope_i_f16, rope_thd_f16)
+ROPE_OP(__half, rope_f16, rope_i_f16, rope_thd_f16, rope_qk_f16)
 SUM_OP(__half, sum_f16)
 FAST_OP(__half, fast_min_f16, fast_max_f16, fast_argmin_f16, fast_argmax_f16, fast_sum_f16)
 #endif
@@ -600,8 +663,8 @@ RMSNORM_OP(float, rmsnorm_f32)
 RMSNORM_OP(double, rmsnorm_f64)
 LAYERNORM_OP(float, layernorm_f32)
 LAYERNORM_OP(double, layernorm_f64)
-ROPE_OP(float, rope_f32, rope_i_f32, rope_thd_f32)
-ROPE_OP(double, rope_f64, rope_i_f64, rope_thd_f64)
+ROPE_OP(float, rope_f32, rope_i_f32, rope_thd_f32, rope_qk_f32)
+ROPE_OP(double, rope_f64, rope_i_f64, rope_thd_f64, rope_qk_f64)
 
 FAST_OP(float, fast_min_f32, fast_max_f32, fast_argmin_f32, fast_argmax_f32, fast_sum_f32)
 FAST_OP(double, fast_min_f64, fast_max_f64, fast_argmin_f64, fast_argmax_f64, fast_sum_f64)
@@ -24,6 +24,7 @@ METAL_FUNC void index(
     constant size_t &right_size, 
     constant size_t &ids_size,
     constant bool &contiguous,
+    constant size_t &num_dims,
     constant size_t *src_dims,
     constant size_t *src_strides,
     const device TYPENAME *input,
@@ -44,7 +45,7 @@ METAL_FUNC void index(
     // No need to check for zero we're only allowing unsized. 
     */ 
     const size_t src_i = left_rank_i * src_dim_size * right_size + input_i * right_size + right_rank_i; 
-    const size_t strided_src_i = contiguous ? src_i : get_strided_index(src_i, src_dim_size, src_dims, src_strides);
+    const size_t strided_src_i = contiguous ? src_i : get_strided_index(src_i, num_dims, src_dims, src_strides);
     output[tid] = input[strided_src_i];
 }
 
@@ -56,6 +57,7 @@ kernel void NAME( \
     constant size_t &right_size, \
     constant size_t &ids_size, \
     constant bool &contiguous, \
+    constant size_t &num_dims, \
     constant size_t *src_dims, \
     constant size_t *src_strides, \
     const device TYPENAME *input, \
@@ -63,7 +65,7 @@ kernel void NAME( \
     device TYPENAME *output, \
     uint tid [[ thread_position_in_grid ]] \
 ) { \
-    index<TYPENAME, INDEX_TYPENAME>(dst_size, left_size, src_dim_size, right_size, ids_size, contiguous, src_dims, src_strides, input, input_ids, output, tid); \
+    index<TYPENAME, INDEX_TYPENAME>(dst_size, left_size, src_dim_size, right_size, ids_size, contiguous, num_dims, src_dims, src_strides, input, input_ids, output, tid); \
 }
 
 
@@ -74,6 +76,10 @@ METAL_FUNC void gather(
     constant size_t &src_dim_size, 
     constant size_t &right_size, 
     constant size_t &ids_size, 
+    constant bool &contiguous,
+    constant size_t &num_dims,
+    constant size_t *src_dims,
+    constant size_t *src_strides,
     const device TYPENAME *input, 
     const device INDEX_TYPENAME *input_ids, 
     device TYPENAME *output, 
@@ -86,7 +92,8 @@ METAL_FUNC void gather(
     const size_t right_rank_i = tid % right_size; 
     const size_t left_rank_i = tid / right_size / ids_size; 
     const size_t src_i = (left_rank_i * src_dim_size + input_i) * right_size + right_rank_i; 
-    output[tid] = input[src_i]; 
+    const size_t strided_src_i = contiguous ? src_i : get_strided_index(src_i, num_dims, src_dims, src_strides);
+    output[tid] = input[strided_src_i]; 
 }
 
 # define GATHER_OP(NAME, INDEX_TYPENAME, TYPENAME) \
@@ -96,12 +103,16 @@ kernel void NAME( \
     constant size_t &src_dim_size, \
     constant size_t &right_size, \
     constant size_t &ids_size, \
+    constant bool &contiguous, \
+    constant size_t &num_dims, \
+    constant size_t *src_dims, \
+    constant size_t *src_strides, \
     const device TYPENAME *input, \
     const device INDEX_TYPENAME *input_ids, \
     device TYPENAME *output, \
     uint tid [[ thread_position_in_grid ]] \
 ) { \
-    gather<TYPENAME, INDEX_TYPENAME>(dst_size, left_size, src_dim_size, right_size, ids_size, input, input_ids, output, tid); \
+    gather<TYPENAME, INDEX_TYPENAME>(dst_size, left_size, src_dim_size, right_size, ids_size, contiguous, num_dims, src_dims, src_strides, input, input_ids, output, tid); \
 }
 
 template<typename TYPENAME, typename INDEX_TYPENAME>
@@ -205,12 +216,24 @@ INDEX_OP(is_u8_f16, uint8_t, half)
 INDEX_OP(is_u8_bf16, uint8_t, bfloat)
 #endif
 
+GATHER_OP(gather_i64_f32, int64_t, float)
+GATHER_OP(gather_i64_f16, int64_t, half)
+#if defined(__HAVE_BFLOAT__)
+GATHER_OP(gather_i64_bf16, int64_t, bfloat)
+#endif
+
 GATHER_OP(gather_u32_f32, uint, float)
 GATHER_OP(gather_u32_f16, uint, half)
 #if defined(__HAVE_BFLOAT__)
 GATHER_OP(gather_u32_bf16, uint, bfloat)
 #endif
 
+GATHER_OP(gather_u8_f32, uint8_t, float)
+GATHER_OP(gather_u8_f16, uint8_t, half)
+#if defined(__HAVE_BFLOAT__)
+GATHER_OP(gather_u8_bf16, uint8_t, bfloat)
+#endif
+
 SCATTER_ADD_OP(sa_u32_f32, uint32_t, float)
 SCATTER_ADD_OP(sa_u8_f32, uint8_t, float)
 SCATTER_ADD_OP(sa_i64_f32, int64_t, float)
@@ -1204,6 +1204,7 @@ pub fn call_index_select(
             right_size,
             ids_size,
             contiguous,
+            src_dims.len(),
             src_dims,
             src_strides,
             &input,
@@ -1231,6 +1232,9 @@ pub fn call_gather(
     shape: &[usize],
     ids_size: usize,
     dim: usize,
+    contiguous: bool,
+    src_dims: &[usize],
+    src_strides: &[usize],
     input: BufferOffset,
     ids: BufferOffset,
     output: &Buffer,
@@ -1254,6 +1258,10 @@ pub fn call_gather(
             src_dim_size,
             right_size,
             ids_size,
+            contiguous,
+            src_dims.len(),
+            src_dims,
+            src_strides,
             &input,
             &ids,
             output
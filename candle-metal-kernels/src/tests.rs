@@ -637,6 +637,24 @@ fn index_select_strided() {
     assert_eq!(result, vec![0.0, 4.0]);
 }
 
+#[test]
+fn index_select_strided_3d() {
+    // A transposed view of a (2, 3, 4) tensor, the indexed dim size differs from the rank.
+    let embedding = (0..24).map(|x| x as f32).collect::<Vec<_>>();
+    let shape = [4, 3, 2];
+    let stride = [1, 4, 12];
+    let ids = [2u32, 0];
+    let dim = 1;
+    let result = run_index_select_strided(&embedding, &shape, &stride, &ids, dim, "is_u32_f32");
+    let expected: Vec<f32> = (0..4)
+        .flat_map(|a| {
+            ids.iter()
+                .flat_map(move |&k| (0..2).map(move |c| (a + 4 * k as usize + 12 * c) as f32))
+        })
+        .collect();
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn index_select_f16() {
     let embedding: Vec<_> = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0]
@@ -784,6 +802,90 @@ fn run_index_select_strided<T: Clone, I: Clone + std::fmt::Debug>(
     read_to_vec(&dst_buffer, dst_el)
 }
 
+#[test]
+fn gather() {
+    let input = (0..12).map(|x| x as f32).collect::<Vec<_>>();
+    let shape = [4, 3];
+    let stride = [3, 1];
+    let ids = [0u32, 2, 1, 0];
+    let result = run_gather(&input, &shape, &stride, &ids, 1, 1, "gather_u32_f32");
+    assert_eq!(result, vec![0.0, 5.0, 7.0, 9.0]);
+
+    let ids = [0u32, 2, 0, 0, 1, 1];
+    let result = run_gather(&input, &shape, &stride, &ids, 2, 0, "gather_u32_f32");
+    assert_eq!(result, vec![0.0, 7.0, 2.0, 0.0, 4.0, 5.0]);
+}
+
+#[test]
+fn gather_strided() {
+    // A transposed view of a (2, 3, 4) tensor, gathering along the middle dim.
+    let input = (0..24).map(|x| x as f32).collect::<Vec<_>>();
+    let shape = [4, 3, 2];
+    let stride = [1, 4, 12];
+    let ids: Vec<u32> = (0..16).map(|i| (i * 7 % 3) as u32).collect();
+    let result = run_gather(&input, &shape, &stride, &ids, 2, 1, "gather_u32_f32");
+    let expected: Vec<f32> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, &k)| {
+            let (a, c) = (i / 4, i % 2);
+            (a + 4 * k as usize + 12 * c) as f32
+        })
+        .collect();
+    assert_eq!(result, expected);
+
+    let input: Vec<_> = input.into_iter().map(f16::from_f32).collect();
+    let ids: Vec<i64> = ids.iter().map(|&i| i as i64).collect();
+    let result = run_gather(&input, &shape, &stride, &ids, 2, 1, "gather_i64_f16");
+    assert_eq!(approx_f16(result, 0), expected);
+}
+
+fn run_gather<T: Clone, I: Clone + std::fmt::Debug>(
+    input: &[T],
+    shape: &[usize],
+    stride: &[usize],
+    ids: &[I],
+    ids_dim_size: usize,
+    dim: usize,
+    name: &'static str,
+) -> Vec<T> {
+    let device = Device::system_default().expect("no device found");
+
+    let command_queue = device.new_command_queue();
+    let command_buffer = command_queue.new_command_buffer();
+    let input_buffer = new_buffer(&device, input);
+    let ids_buffer = new_buffer(&device, ids);
+    let dst_buffer = new_buffer(&device, &vec![0.0f32; ids.len()]);
+
+    let mut contiguous_stride = vec![1; shape.len()];
+    for d in (1..shape.len()).rev() {
+        contiguous_stride[d - 1] = contiguous_stride[d] * shape[d];
+    }
+    let contiguous = contiguous_stride == stride;
+    let kernels = Kernels::new();
+    call_gather(
+        &device,
+        command_buffer,
+        &kernels,
+        name,
+        shape,
+        ids_dim_size,
+        dim,
+        contiguous,
+        shape,
+        stride,
+        BufferOffset::zero_offset(&input_buffer),
+        BufferOffset::zero_offset(&ids_buffer),
+        &dst_buffer,
+    )
+    .unwrap();
+
+    command_buffer.commit();
+    command_buffer.wait_until_completed();
+
+    read_to_vec(&dst_buffer, ids.len())
+}
+
 #[test]
 fn cos_f16() {
     let v: Vec<f16> = [1.0f32, 2.0, 3.0]
@@ -1,4 +1,8 @@
 mod benchmarks;
 
 use criterion::criterion_main;
-criterion_main!(benchmarks::layer_norm::benches, benchmarks::conv::benches);
+criterion_main!(
+    benchmarks::layer_norm::benches,
+    benchmarks::rms_norm::benches,
+    benchmarks::conv::benches
+);
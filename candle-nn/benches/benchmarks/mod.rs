@@ -1,5 +1,6 @@
 pub(crate) mod conv;
 pub(crate) mod layer_norm;
+pub(crate) mod rms_norm;
 
 use candle::{Device, Result};
 
@@ -25,6 +26,7 @@ impl BenchDevice for Device {
                 #[cfg(not(feature = "metal"))]
                 panic!("Metal device without metal feature enabled: {:?}", device)
             }
+            Device::Wgpu(_) => self.synchronize(),
         }
     }
 
@@ -42,6 +44,7 @@ impl BenchDevice for Device {
             }
             Device::Cuda(_) => format!("cuda_{}", name.into()),
             Device::Metal(_) => format!("metal_{}", name.into()),
+            Device::Wgpu(_) => format!("wgpu_{}", name.into()),
         }
     }
 }
@@ -0,0 +1,55 @@
+use crate::benchmarks::{BenchDevice, BenchDeviceHandler};
+use candle::{DType, Device, Tensor};
+use criterion::{black_box, criterion_group, Criterion};
+use std::time::Instant;
+
+fn run_fused(input: &Tensor, weight: &Tensor) {
+    let _ = candle_nn::ops::rms_norm(input, weight, 1e-5);
+}
+
+fn run_slow(input: &Tensor, weight: &Tensor) {
+    let _ = candle_nn::ops::rms_norm_slow(input, weight, 1e-5);
+}
+
+// A (batch * seq_len, hidden_size) input as found in llama models.
+const M: usize = 1024;
+const K: usize = 4096;
+
+fn run_rms_norm_benchmark(
+    c: &mut Criterion,
+    device: &Device,
+    dtype: DType,
+    name: &str,
+    f: fn(&Tensor, &Tensor),
+) {
+    let weight = Tensor::arange(0.0, K as f32, device)
+        .unwrap()
+        .to_dtype(dtype)
+        .unwrap();
+    let input = Tensor::ones((M, K), dtype, device).unwrap();
+
+    let mut group = c.benchmark_group(device.bench_name(name));
+    group.bench_function("iter", move |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            for _i in 0..iters {
+                f(black_box(&input), black_box(&weight));
+            }
+            device.sync().unwrap();
+            start.elapsed()
+        })
+    });
+    group.finish();
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let device = BenchDeviceHandler::new().unwrap();
+    for d in device.devices {
+        run_rms_norm_benchmark(c, &d, DType::F32, "rms_norm_f32", run_fused);
+        run_rms_norm_benchmark(c, &d, DType::F32, "rms_norm_slow_f32", run_slow);
+        run_rms_norm_benchmark(c, &d, DType::BF16, "rms_norm_bf16", run_fused);
+        run_rms_norm_benchmark(c, &d, DType::BF16, "rms_norm_slow_bf16", run_slow);
+    }
+}
+
+criterion_group!(benches, criterion_benchmark);
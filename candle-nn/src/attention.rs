@@ -0,0 +1,192 @@
+//! Multi-head attention.
+//!
+//! The inputs use a batch-first layout, `(batch, seq_len, dim)`. The queries, keys and values
+//! are projected, split into heads, combined with scaled dot-product attention and the heads
+//! are then merged back before applying the output projection.
+use candle::{Module, Result, Tensor, D};
+
+use crate::kv_cache::KvCache;
+use crate::Linear;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MultiheadAttentionConfig {
+    pub num_heads: usize,
+    /// The dimension of the keys, defaults to `embed_dim`. This is typically set for
+    /// cross-attention.
+    pub kdim: Option<usize>,
+    /// The dimension of the values, defaults to `embed_dim`.
+    pub vdim: Option<usize>,
+    /// Whether the input and output projections have a bias.
+    pub bias: bool,
+    /// Use a single `in_proj_weight`/`in_proj_bias` pair for the query, key and value
+    /// projections as done in PyTorch, rather than the separate `q_proj`, `k_proj` and `v_proj`
+    /// linear layers. This requires the keys and values to have `embed_dim` dimensions.
+    pub fused_qkv: bool,
+}
+
+impl Default for MultiheadAttentionConfig {
+    fn default() -> Self {
+        Self {
+            num_heads: 1,
+            kdim: None,
+            vdim: None,
+            bias: true,
+            fused_qkv: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum InProj {
+    Fused(Linear),
+    Separate { q: Linear, k: Linear, v: Linear },
+}
+
+#[derive(Clone, Debug)]
+pub struct MultiheadAttention {
+    in_proj: InProj,
+    out_proj: Linear,
+    embed_dim: usize,
+    num_heads: usize,
+    head_dim: usize,
+}
+
+impl MultiheadAttention {
+    pub fn embed_dim(&self) -> usize {
+        self.embed_dim
+    }
+
+    pub fn num_heads(&self) -> usize {
+        self.num_heads
+    }
+
+    fn project(&self, query: &Tensor, key: &Tensor, value: &Tensor) -> Result<[Tensor; 3]> {
+        match &self.in_proj {
+            InProj::Separate { q, k, v } => {
+                Ok([q.forward(query)?, k.forward(key)?, v.forward(value)?])
+            }
+            InProj::Fused(in_proj) => {
+                if query.id() == key.id() && key.id() == value.id() {
+                    let qkv = in_proj.forward(query)?;
+                    let e = self.embed_dim;
+                    return Ok([
+                        qkv.narrow(D::Minus1, 0, e)?,
+                        qkv.narrow(D::Minus1, e, e)?,
+                        qkv.narrow(D::Minus1, 2 * e, e)?,
+                    ]);
+                }
+                let proj = |xs: &Tensor, i: usize| -> Result<Tensor> {
+                    let e = self.embed_dim;
+                    let w = in_proj.weight().narrow(0, i * e, e)?;
+                    let b = match in_proj.bias() {
+                        None => None,
+                        Some(b) => Some(b.narrow(0, i * e, e)?),
+                    };
+                    Linear::new(w, b).forward(xs)
+                };
+                Ok([proj(query, 0)?, proj(key, 1)?, proj(value, 2)?])
+            }
+        }
+    }
+
+    // (b, seq_len, embed_dim) -> (b, num_heads, seq_len, head_dim)
+    fn split_heads(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = xs.dims3()?;
+        xs.reshape((b_sz, seq_len, self.num_heads, self.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()
+    }
+
+    /// Applies the attention, `query` has shape `(batch, seq_len, embed_dim)`, `key` and
+    /// `value` have shape `(batch, kv_len, kdim)` and `(batch, kv_len, vdim)`. For
+    /// self-attention, the same tensor can be used for all three.
+    ///
+    /// `attn_mask` is an additive mask that is broadcasted to
+    /// `(batch, num_heads, seq_len, kv_len)`, e.g. filled with `0` and `-inf`.
+    pub fn forward(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        attn_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        self.forward_with_kv_cache(query, key, value, attn_mask, None)
+    }
+
+    /// Same as `forward` but the projected keys and values are appended to `kv_cache` and the
+    /// attention is computed over all the cached positions, the cache should be created with
+    /// `KvCache::new(2, max_seq_len)`, or `KvCache::new_quantized(2, max_seq_len)` to store the
+    /// keys and values as int8. In this case `kv_len` in the mask shape is the total number of
+    /// cached positions.
+    pub fn forward_with_kv_cache(
+        &self,
+        query: &Tensor,
+        key: &Tensor,
+        value: &Tensor,
+        attn_mask: Option<&Tensor>,
+        kv_cache: Option<&mut KvCache>,
+    ) -> Result<Tensor> {
+        let (b_sz, seq_len, _) = query.dims3()?;
+        let [q, k, v] = self.project(query, key, value)?;
+        let q = self.split_heads(&q)?;
+        let k = self.split_heads(&k)?;
+        let v = self.split_heads(&v)?;
+        let (k, v) = match kv_cache {
+            None => (k, v),
+            Some(kv_cache) => kv_cache.append(&k, &v)?,
+        };
+        let ys = crate::ops::scaled_dot_product_attention(&q, &k, &v, attn_mask, None, false)?;
+        let ys = ys
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, self.embed_dim))?;
+        self.out_proj.forward(&ys)
+    }
+}
+
+/// Creates or initializes a multi-head attention layer.
+///
+/// The weights are stored under `q_proj`, `k_proj`, `v_proj` and `out_proj`, or under
+/// `in_proj_weight`, `in_proj_bias` and `out_proj` when `fused_qkv` is set.
+pub fn multihead_attention(
+    embed_dim: usize,
+    config: MultiheadAttentionConfig,
+    vb: crate::VarBuilder,
+) -> Result<MultiheadAttention> {
+    let num_heads = config.num_heads;
+    if num_heads == 0 || embed_dim % num_heads != 0 {
+        candle::bail!("embed_dim {embed_dim} should be divisible by num_heads {num_heads}")
+    }
+    let kdim = config.kdim.unwrap_or(embed_dim);
+    let vdim = config.vdim.unwrap_or(embed_dim);
+    let in_proj = if config.fused_qkv {
+        if kdim != embed_dim || vdim != embed_dim {
+            candle::bail!(
+                "fused qkv projections require kdim {kdim} and vdim {vdim} to be equal to embed_dim {embed_dim}"
+            )
+        }
+        let ws = vb.get_with_hints(
+            (3 * embed_dim, embed_dim),
+            "in_proj_weight",
+            crate::init::DEFAULT_KAIMING_NORMAL,
+        )?;
+        let bs = if config.bias {
+            Some(vb.get_with_hints(3 * embed_dim, "in_proj_bias", crate::Init::Const(0.))?)
+        } else {
+            None
+        };
+        InProj::Fused(Linear::new(ws, bs))
+    } else {
+        let q = crate::linear_b(embed_dim, embed_dim, config.bias, vb.pp("q_proj"))?;
+        let k = crate::linear_b(kdim, embed_dim, config.bias, vb.pp("k_proj"))?;
+        let v = crate::linear_b(vdim, embed_dim, config.bias, vb.pp("v_proj"))?;
+        InProj::Separate { q, k, v }
+    };
+    let out_proj = crate::linear_b(embed_dim, embed_dim, config.bias, vb.pp("out_proj"))?;
+    Ok(MultiheadAttention {
+        in_proj,
+        out_proj,
+        embed_dim,
+        num_heads,
+        head_dim: embed_dim / num_heads,
+    })
+}
@@ -0,0 +1,103 @@
+//! Exponential moving average of the weights of a model.
+//!
+//! The `Ema` structure holds a shadow copy of the variables of a `VarMap`, this copy is updated
+//! after each optimizer step and can be swapped in for evaluation, which is common when training
+//! diffusion models or GANs.
+//!
+//! ```rust
+//! use candle::{DType, Device};
+//! let var_map = candle_nn::VarMap::new();
+//! let w = var_map.get(2, "w", candle_nn::Init::Const(1.), DType::F32, &Device::Cpu)?;
+//! let mut ema = candle_nn::Ema::new(&var_map, false)?;
+//! var_map.data().lock().unwrap()["w"].set(&w.zeros_like()?)?;
+//! ema.update(0.9)?;
+//! assert_eq!(ema.get("w").unwrap().to_vec1::<f32>()?, [0.9, 0.9]);
+//! # Ok::<(), candle::Error>(())
+//! ```
+use crate::VarMap;
+use candle::{Result, Tensor};
+use std::collections::HashMap;
+
+/// Tracks an exponential moving average of the variables in a `VarMap`.
+pub struct Ema {
+    var_map: VarMap,
+    shadow: HashMap<String, Tensor>,
+    num_updates: usize,
+    use_warmup: bool,
+}
+
+impl Ema {
+    /// Creates a shadow copy of the variables currently in `var_map`.
+    ///
+    /// When `use_warmup` is true, the decay used for the first updates is lowered to
+    /// `(1 + n) / (10 + n)` where `n` is the number of updates so far. This avoids the average
+    /// being biased towards the initial weights at the beginning of training.
+    pub fn new(var_map: &VarMap, use_warmup: bool) -> Result<Self> {
+        let shadow = var_map
+            .data()
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, var)| Ok((name.clone(), var.as_tensor().detach().copy()?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        Ok(Self {
+            var_map: var_map.clone(),
+            shadow,
+            num_updates: 0,
+            use_warmup,
+        })
+    }
+
+    /// The decay that will be used by the next update when `decay` is requested.
+    pub fn effective_decay(&self, decay: f64) -> f64 {
+        if self.use_warmup {
+            let n = self.num_updates as f64;
+            decay.min((1. + n) / (10. + n))
+        } else {
+            decay
+        }
+    }
+
+    /// Updates the average with the current values of the variables, this should typically be
+    /// called after each optimizer step.
+    ///
+    /// `ema = decay * ema + (1 - decay) * param`
+    pub fn update(&mut self, decay: f64) -> Result<()> {
+        let decay = self.effective_decay(decay);
+        let var_map = self.var_map.data().lock().unwrap();
+        for (name, var) in var_map.iter() {
+            let param = var.as_tensor().detach();
+            let ema = match self.shadow.get(name) {
+                // Variables added to the map after the creation of the average start from their
+                // current value.
+                None => param.copy()?,
+                Some(ema) => (ema.affine(decay, 0.)? + param.affine(1. - decay, 0.)?)?,
+            };
+            self.shadow.insert(name.clone(), ema);
+        }
+        self.num_updates += 1;
+        Ok(())
+    }
+
+    /// Sets the variables of `var_map` to the averaged values, variables that are not tracked
+    /// are left unchanged.
+    pub fn copy_to(&self, var_map: &VarMap) -> Result<()> {
+        let var_map = var_map.data().lock().unwrap();
+        for (name, var) in var_map.iter() {
+            if let Some(ema) = self.shadow.get(name) {
+                var.set(ema)?
+            }
+        }
+        Ok(())
+    }
+
+    /// The averaged value for the variable `name`.
+    pub fn get(&self, name: &str) -> Option<&Tensor> {
+        self.shadow.get(name)
+    }
+
+    /// The number of updates applied so far.
+    pub fn num_updates(&self) -> usize {
+        self.num_updates
+    }
+}
@@ -5,6 +5,7 @@ use candle::{Result, Tensor};
 pub struct Embedding {
     embeddings: Tensor,
     hidden_size: usize,
+    padding_idx: Option<usize>,
 }
 
 impl Embedding {
@@ -12,9 +13,39 @@ impl Embedding {
         Self {
             embeddings,
             hidden_size,
+            padding_idx: None,
         }
     }
 
+    /// Creates an embedding layer where the lookups of `padding_idx` always return zeros, the
+    /// gradient for this row of the embeddings is also zero.
+    pub fn new_with_padding_idx(
+        embeddings: Tensor,
+        hidden_size: usize,
+        padding_idx: Option<usize>,
+    ) -> Result<Self> {
+        if let Some(padding_idx) = padding_idx {
+            let num_embeddings = embeddings.dim(0)?;
+            if padding_idx >= num_embeddings {
+                candle::bail!(
+                    "padding_idx {padding_idx} is out of range for {num_embeddings} embeddings"
+                )
+            }
+        }
+        Ok(Self {
+            embeddings,
+            hidden_size,
+            padding_idx,
+        })
+    }
+
+    /// Creates an embedding layer from an existing `(num_embeddings, hidden_size)` weight
+    /// matrix, e.g. to tie the embeddings with the weights of an output projection.
+    pub fn from_weights(embeddings: Tensor) -> Result<Self> {
+        let (_num_embeddings, hidden_size) = embeddings.dims2()?;
+        Ok(Self::new(embeddings, hidden_size))
+    }
+
     pub fn embeddings(&self) -> &Tensor {
         &self.embeddings
     }
@@ -23,6 +54,10 @@ impl Embedding {
     pub fn hidden_size(&self) -> usize {
         self.hidden_size
     }
+
+    pub fn padding_idx(&self) -> Option<usize> {
+        self.padding_idx
+    }
 }
 
 impl crate::Module for Embedding {
@@ -31,6 +66,17 @@ impl crate::Module for Embedding {
         final_dims.push(self.hidden_size);
         let indexes = indexes.flatten_all()?;
         let values = self.embeddings.index_select(&indexes, 0)?;
+        let values = match self.padding_idx {
+            None => values,
+            Some(padding_idx) => {
+                // Masking the values also masks the gradient of the padding row.
+                let mask = indexes
+                    .ne(padding_idx as f64)?
+                    .to_dtype(values.dtype())?
+                    .unsqueeze(1)?;
+                values.broadcast_mul(&mask)?
+            }
+        };
         let values = values.reshape(final_dims)?;
         Ok(values)
     }
@@ -47,3 +93,28 @@ pub fn embedding(in_size: usize, out_size: usize, vb: crate::VarBuilder) -> Resu
     )?;
     Ok(Embedding::new(embeddings, out_size))
 }
+
+/// Same as `embedding` but the `padding_idx` row of the embeddings is set to zero and is never
+/// updated, see `Embedding::new_with_padding_idx`.
+pub fn embedding_with_padding_idx(
+    in_size: usize,
+    out_size: usize,
+    padding_idx: Option<usize>,
+    vb: crate::VarBuilder,
+) -> Result<Embedding> {
+    let embeddings = vb.get_with_hints(
+        (in_size, out_size),
+        "weight",
+        crate::Init::Randn {
+            mean: 0.,
+            stdev: 1.,
+        },
+    )?;
+    let embedding = Embedding::new_with_padding_idx(embeddings, out_size, padding_idx)?;
+    if let Some(padding_idx) = padding_idx {
+        let embeddings = embedding.embeddings();
+        let zeros = Tensor::zeros((1, out_size), embeddings.dtype(), embeddings.device())?;
+        embeddings.slice_set(&zeros, 0, padding_idx)?;
+    }
+    Ok(embedding)
+}
@@ -0,0 +1,170 @@
+//! Instance Normalization.
+//!
+//! This layer applies Instance Normalization as described in [`Instance Normalization`]. The
+//! input is expected to have four dimensions `(batch, channels, h, w)`, each channel of each
+//! sample is normalized independently using the mean and variance over the spatial dimensions.
+//!
+//! When `track_running_stats` is set, the running statistics are updated in training mode and
+//! used for normalization in evaluation mode, otherwise the instance statistics are always used.
+//!
+//! [`Instance Normalization`]: https://arxiv.org/abs/1607.08022
+use candle::{DType, Result, Tensor, Var};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InstanceNormConfig {
+    pub eps: f64,
+    /// Controls exponential moving average of running stats. Defaults to 0.1
+    ///
+    /// `running_stat * (1.0 - momentum) + stat * momentum`.
+    pub momentum: f64,
+    /// Whether to use a learnable per-channel weight and bias.
+    pub affine: bool,
+    pub track_running_stats: bool,
+}
+
+impl Default for InstanceNormConfig {
+    fn default() -> Self {
+        Self {
+            eps: 1e-5,
+            momentum: 0.1,
+            affine: false,
+            track_running_stats: false,
+        }
+    }
+}
+
+impl From<f64> for InstanceNormConfig {
+    fn from(eps: f64) -> Self {
+        Self {
+            eps,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InstanceNorm2d {
+    running_stats: Option<(Var, Var)>,
+    weight_and_bias: Option<(Tensor, Tensor)>,
+    num_features: usize,
+    eps: f64,
+    momentum: f64,
+}
+
+impl InstanceNorm2d {
+    pub fn running_mean(&self) -> Option<&Tensor> {
+        self.running_stats.as_ref().map(|(m, _)| m.as_tensor())
+    }
+
+    pub fn running_var(&self) -> Option<&Tensor> {
+        self.running_stats.as_ref().map(|(_, v)| v.as_tensor())
+    }
+
+    pub fn weight_and_bias(&self) -> Option<(&Tensor, &Tensor)> {
+        self.weight_and_bias.as_ref().map(|v| (&v.0, &v.1))
+    }
+
+    /// Normalizes using the instance statistics and updates the running statistics if they are
+    /// tracked. The `Module` implementation uses the running statistics when tracked.
+    pub fn forward_train(&self, x: &Tensor) -> Result<Tensor> {
+        self.forward_(x, true)
+    }
+
+    fn normalize(&self, x: &Tensor, mean: &Tensor, var: &Tensor) -> Result<Tensor> {
+        let x = x
+            .broadcast_sub(mean)?
+            .broadcast_div(&(var + self.eps)?.sqrt()?)?;
+        match &self.weight_and_bias {
+            None => Ok(x),
+            Some((weight, bias)) => {
+                let weight = weight.reshape((1, (), 1))?;
+                let bias = bias.reshape((1, (), 1))?;
+                x.broadcast_mul(&weight)?.broadcast_add(&bias)
+            }
+        }
+    }
+
+    fn forward_(&self, x: &Tensor, train: bool) -> Result<Tensor> {
+        let (b_sz, c, h, w) = x.dims4()?;
+        if c != self.num_features {
+            candle::bail!(
+                "instance-norm input doesn't have the expected number of features ({:?} <> {})",
+                x.shape(),
+                self.num_features
+            )
+        }
+        let x_dtype = x.dtype();
+        let internal_dtype = match x_dtype {
+            DType::F16 | DType::BF16 => DType::F32,
+            d => d,
+        };
+        let x = x.to_dtype(internal_dtype)?.reshape((b_sz, c, h * w))?;
+        let x = match (&self.running_stats, train) {
+            (Some((running_mean, running_var)), false) => {
+                let mean = running_mean.as_detached_tensor().reshape((1, (), 1))?;
+                let var = running_var.as_detached_tensor().reshape((1, (), 1))?;
+                self.normalize(&x, &mean, &var)?
+            }
+            (running_stats, _) => {
+                let mean = x.mean_keepdim(2)?;
+                let var = x.broadcast_sub(&mean)?.sqr()?.mean_keepdim(2)?;
+                if let (Some((running_mean, running_var)), true) = (running_stats, train) {
+                    // The running variance uses the unbiased estimate, both stats are averaged
+                    // over the batch.
+                    let n = (h * w) as f64;
+                    let batch_mean = mean.mean(0)?.flatten_all()?;
+                    let batch_var = (var.mean(0)?.flatten_all()? * (n / (n - 1.)))?;
+                    let m = self.momentum;
+                    running_mean
+                        .set(&((running_mean.as_tensor() * (1. - m))? + (batch_mean * m)?)?)?;
+                    running_var
+                        .set(&((running_var.as_tensor() * (1. - m))? + (batch_var * m)?)?)?;
+                }
+                self.normalize(&x, &mean, &var)?
+            }
+        };
+        x.reshape((b_sz, c, h, w))?.to_dtype(x_dtype)
+    }
+}
+
+impl crate::Module for InstanceNorm2d {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.forward_(x, false)
+    }
+}
+
+pub fn instance_norm2d<C: Into<InstanceNormConfig>>(
+    num_features: usize,
+    config: C,
+    vb: crate::VarBuilder,
+) -> Result<InstanceNorm2d> {
+    use crate::Init;
+    let config = config.into();
+    if config.eps < 0. {
+        candle::bail!("instance-norm eps cannot be negative {}", config.eps)
+    }
+    let running_stats = if config.track_running_stats {
+        let running_mean = vb.get_with_hints(num_features, "running_mean", Init::Const(0.))?;
+        let running_var = vb.get_with_hints(num_features, "running_var", Init::Const(1.))?;
+        Some((
+            Var::from_tensor(&running_mean)?,
+            Var::from_tensor(&running_var)?,
+        ))
+    } else {
+        None
+    };
+    let weight_and_bias = if config.affine {
+        let weight = vb.get_with_hints(num_features, "weight", Init::Const(1.))?;
+        let bias = vb.get_with_hints(num_features, "bias", Init::Const(0.))?;
+        Some((weight, bias))
+    } else {
+        None
+    };
+    Ok(InstanceNorm2d {
+        running_stats,
+        weight_and_bias,
+        num_features,
+        eps: config.eps,
+        momentum: config.momentum,
+    })
+}
@@ -1,4 +1,31 @@
-use candle::{Result, Tensor};
+use candle::{DType, Result, Tensor, D};
+
+// The int8 values are stored as u8 with an offset of 128.
+const I8_OFFSET: f64 = 128.;
+
+// Symmetric int8 quantization over the last dimension, returns the quantized values and the
+// scales, the scales have the same shape as `xs` except for the last dimension which is 1.
+fn quantize_i8(xs: &Tensor) -> Result<(Tensor, Tensor)> {
+    let xs = xs.to_dtype(DType::F32)?;
+    let scales = (xs.abs()?.max_keepdim(D::Minus1)? / 127.)?;
+    // Avoid dividing by zero for rows that only contain zeros.
+    let inv_scales = scales.maximum(f32::MIN_POSITIVE)?.recip()?;
+    let qs = (xs.broadcast_mul(&inv_scales)?.round()? + I8_OFFSET)?.to_dtype(DType::U8)?;
+    Ok((qs, scales))
+}
+
+fn dequantize_i8(qs: &Tensor, scales: &Tensor, dtype: DType) -> Result<Tensor> {
+    let xs = (qs.to_dtype(DType::F32)? - I8_OFFSET)?;
+    xs.broadcast_mul(scales)?.to_dtype(dtype)
+}
+
+// The quantized storage, the int8 values are in `all_data` and the per-token scales are stored
+// here together with the dtype of the tensors that have been appended.
+#[derive(Debug, Clone)]
+struct I8State {
+    scales: Tensor,
+    dtype: DType,
+}
 
 #[derive(Debug, Clone)]
 pub struct Cache {
@@ -10,6 +37,8 @@ pub struct Cache {
     dim: usize,
     current_seq_len: usize,
     max_seq_len: usize,
+    quantized: bool,
+    i8_state: Option<I8State>,
 }
 
 impl Cache {
@@ -19,9 +48,25 @@ impl Cache {
             dim,
             current_seq_len: 0,
             max_seq_len,
+            quantized: false,
+            i8_state: None,
+        }
+    }
+
+    /// A cache storing the values as int8 with one scale per token, i.e. the values are quantized
+    /// over the last dimension which must be different from `dim`. The values are dequantized
+    /// back to the dtype of the appended tensors when read.
+    pub fn new_quantized(dim: usize, max_seq_len: usize) -> Self {
+        Self {
+            quantized: true,
+            ..Self::new(dim, max_seq_len)
         }
     }
 
+    pub fn is_quantized(&self) -> bool {
+        self.quantized
+    }
+
     pub fn dim(&self) -> usize {
         self.dim
     }
@@ -34,6 +79,8 @@ impl Cache {
         self.max_seq_len
     }
 
+    /// The underlying storage, for quantized caches this contains the int8 values offset by 128
+    /// and stored as u8.
     pub fn all_data(&self) -> &Option<Tensor> {
         &self.all_data
     }
@@ -41,35 +88,83 @@ impl Cache {
     pub fn current_data(&self) -> Result<Option<Tensor>> {
         let data = match self.all_data.as_ref() {
             None => None,
-            Some(d) => Some(d.narrow(self.dim, 0, self.current_seq_len)?),
+            Some(d) => {
+                let d = d.narrow(self.dim, 0, self.current_seq_len)?;
+                match self.i8_state.as_ref() {
+                    None => Some(d),
+                    Some(st) => {
+                        let scales = st.scales.narrow(self.dim, 0, self.current_seq_len)?;
+                        Some(dequantize_i8(&d, &scales, st.dtype)?)
+                    }
+                }
+            }
         };
         Ok(data)
     }
 
+    /// The number of bytes used by the allocated storage, including the scales for quantized
+    /// caches.
+    pub fn storage_size_in_bytes(&self) -> usize {
+        let size = |t: &Tensor| t.elem_count() * t.dtype().size_in_bytes();
+        let data = self.all_data.as_ref().map_or(0, size);
+        let scales = self.i8_state.as_ref().map_or(0, |st| size(&st.scales));
+        data + scales
+    }
+
     pub fn reset(&mut self) {
         self.current_seq_len = 0;
         self.all_data = None;
+        self.i8_state = None;
+    }
+
+    /// Discards the entries past the first `seq_len` positions, e.g. the draft tokens rejected
+    /// when using speculative decoding. This is a no-op if the cache holds at most `seq_len`
+    /// entries. The storage is kept and gets overwritten by the following appends.
+    pub fn truncate(&mut self, seq_len: usize) {
+        self.current_seq_len = self.current_seq_len.min(seq_len)
     }
 
     pub fn append(&mut self, src: &Tensor) -> Result<()> {
         let seq_len = src.dim(self.dim)?;
+        if self.quantized && self.dim + 1 == src.rank() {
+            candle::bail!(
+                "kv-cache: cannot quantize over the cache dimension {}",
+                self.dim
+            )
+        }
+        if self.current_seq_len + seq_len > self.max_seq_len {
+            candle::bail!(
+                "kv-cache: above max-seq-len {}+{seq_len}>{}",
+                self.current_seq_len,
+                self.max_seq_len
+            )
+        }
+        let dtype = src.dtype();
+        let (src, scales) = if self.quantized {
+            let (qs, scales) = quantize_i8(src)?;
+            (qs, Some(scales))
+        } else {
+            (src.clone(), None)
+        };
         // This doesn't seem very idiomatic but because the creation can fail, it's tricky to use
         // self.all_data.get_or_insert_with.
         if self.all_data.is_none() {
             let mut shape = src.dims().to_vec();
             shape[self.dim] = self.max_seq_len;
-            let ad = Tensor::zeros(shape, src.dtype(), src.device())?;
-            self.all_data = Some(ad)
+            let ad = Tensor::zeros(shape.as_slice(), src.dtype(), src.device())?;
+            self.all_data = Some(ad);
+            if let Some(scales) = scales.as_ref() {
+                *shape.last_mut().unwrap() = 1;
+                let scales = Tensor::zeros(shape, scales.dtype(), scales.device())?;
+                self.i8_state = Some(I8State { scales, dtype })
+            }
         };
         let ad = self.all_data.as_mut().unwrap();
-        if self.current_seq_len + seq_len > self.max_seq_len {
-            candle::bail!(
-                "kv-cache: above max-seq-len {}+{seq_len}>{}",
-                self.current_seq_len,
-                self.max_seq_len
-            )
+        ad.slice_set(&src, self.dim, self.current_seq_len)?;
+        if let (Some(st), Some(scales)) = (self.i8_state.as_mut(), scales) {
+            st.scales
+                .slice_set(&scales, self.dim, self.current_seq_len)?;
         }
-        ad.slice_set(src, self.dim, self.current_seq_len)?;
         self.current_seq_len += seq_len;
         Ok(())
     }
@@ -88,6 +183,22 @@ impl KvCache {
         Self { k, v }
     }
 
+    /// A cache storing the keys and values as int8, see [`Cache::new_quantized`].
+    pub fn new_quantized(dim: usize, max_seq_len: usize) -> Self {
+        let k = Cache::new_quantized(dim, max_seq_len);
+        let v = Cache::new_quantized(dim, max_seq_len);
+        Self { k, v }
+    }
+
+    pub fn is_quantized(&self) -> bool {
+        self.k.is_quantized()
+    }
+
+    /// The number of bytes used by the keys and values storage.
+    pub fn storage_size_in_bytes(&self) -> usize {
+        self.k.storage_size_in_bytes() + self.v.storage_size_in_bytes()
+    }
+
     pub fn k_cache(&self) -> &Cache {
         &self.k
     }
@@ -144,4 +255,10 @@ impl KvCache {
         self.k.reset();
         self.v.reset();
     }
+
+    /// Discards the keys and values past the first `seq_len` positions, see [`Cache::truncate`].
+    pub fn truncate(&mut self, seq_len: usize) {
+        self.k.truncate(seq_len);
+        self.v.truncate(seq_len);
+    }
 }
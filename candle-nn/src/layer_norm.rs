@@ -107,6 +107,19 @@ impl LayerNorm {
 
 impl Module for LayerNorm {
     fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        use candle::autocast::{cast, target_dtype, AutocastOp};
+        if target_dtype(AutocastOp::Norm, x.dtype()).is_some() {
+            let ln = Self {
+                weight: cast(&self.weight, AutocastOp::Norm)?,
+                bias: self
+                    .bias
+                    .as_ref()
+                    .map(|b| cast(b, AutocastOp::Norm))
+                    .transpose()?,
+                ..self.clone()
+            };
+            return ln.forward(&cast(x, AutocastOp::Norm)?);
+        }
         if x.is_contiguous() && self.remove_mean {
             if let Some(bias) = self.bias.as_ref() {
                 return crate::ops::layer_norm(x, &self.weight, bias, self.eps as f32);
@@ -176,7 +189,11 @@ impl RmsNorm {
 
 impl Module for RmsNorm {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
-        if xs.is_contiguous() {
+        if candle::autocast::target_dtype(candle::autocast::AutocastOp::Norm, xs.dtype()).is_some()
+        {
+            // The slow path handles the autocasting of both the input and the weights.
+            self.0.forward(xs)
+        } else if xs.is_contiguous() {
             crate::ops::rms_norm(xs, &self.0.weight, self.0.eps as f32)
         } else {
             self.0.forward(xs)
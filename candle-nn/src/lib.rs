@@ -1,40 +1,68 @@
 pub mod activation;
+pub mod attention;
 pub mod batch_norm;
 pub mod conv;
+pub mod ema;
 pub mod embedding;
 pub mod encoding;
 pub mod func;
 pub mod group_norm;
 pub mod init;
+pub mod instance_norm;
 pub mod kv_cache;
 pub mod layer_norm;
 pub mod linear;
+pub mod local_response_norm;
 pub mod loss;
 pub mod ops;
 pub mod optim;
+pub mod pool;
+pub mod quantized_linear;
 pub mod rnn;
 pub mod rotary_emb;
+pub mod schedule;
 pub mod sequential;
+pub mod tensor_parallel;
+pub mod upsample;
 pub mod var_builder;
 pub mod var_map;
 
 pub use activation::{prelu, Activation, PReLU};
+pub use attention::{multihead_attention, MultiheadAttention, MultiheadAttentionConfig};
 pub use batch_norm::{batch_norm, BatchNorm, BatchNormConfig};
 pub use conv::{
-    conv1d, conv1d_no_bias, conv2d, conv2d_no_bias, conv_transpose1d, conv_transpose1d_no_bias,
-    conv_transpose2d, conv_transpose2d_no_bias, Conv1d, Conv1dConfig, Conv2d, Conv2dConfig,
+    conv1d, conv1d_no_bias, conv2d, conv2d_no_bias, conv3d, conv3d_no_bias, conv_transpose1d,
+    conv_transpose1d_no_bias, conv_transpose2d, conv_transpose2d_no_bias, conv_transpose3d,
+    conv_transpose3d_no_bias, Conv1d, Conv1dConfig, Conv2d, Conv2dConfig, Conv3d, Conv3dConfig,
     ConvTranspose1d, ConvTranspose1dConfig, ConvTranspose2d, ConvTranspose2dConfig,
+    ConvTranspose3d, ConvTranspose3dConfig, PaddingMode,
 };
-pub use embedding::{embedding, Embedding};
+pub use ema::Ema;
+pub use embedding::{embedding, embedding_with_padding_idx, Embedding};
 pub use func::{func, func_t, Func, FuncT};
 pub use group_norm::{group_norm, GroupNorm};
 pub use init::Init;
+pub use instance_norm::{instance_norm2d, InstanceNorm2d, InstanceNormConfig};
 pub use layer_norm::{layer_norm, rms_norm, LayerNorm, LayerNormConfig, RmsNorm};
 pub use linear::{linear, linear_b, linear_no_bias, Linear};
+pub use local_response_norm::{LocalResponseNorm, LocalResponseNormConfig};
 pub use ops::Dropout;
-pub use optim::{AdamW, Optimizer, ParamsAdamW, SGD};
-pub use rnn::{gru, lstm, GRUConfig, LSTMConfig, GRU, LSTM, RNN};
+pub use optim::{
+    clip_grad_norm, clip_grad_value, Adafactor, Adagrad, Adam, AdamW, Lion, Optimizer,
+    ParamsAdafactor, ParamsAdagrad, ParamsAdam, ParamsAdamW, ParamsLion, ParamsRMSProp, RMSProp,
+    SGD,
+};
+pub use pool::{
+    AdaptiveAvgPool2d, AvgPool1d, AvgPool2d, AvgPoolConfig, MaxPool1d, MaxPool2d, MaxPoolConfig,
+};
+pub use quantized_linear::{Int8Scheme, QuantizedLinear};
+pub use rnn::{
+    gru, lstm, stacked_gru, stacked_lstm, Direction, GRUConfig, LSTMConfig, StackedGRU,
+    StackedLSTM, GRU, LSTM, RNN,
+};
 pub use sequential::{seq, Sequential};
+pub use tensor_parallel::{TensorParallelColumnLinear, TensorParallelRowLinear};
+pub use upsample::{Upsample, UpsampleMode, UpsampleSize};
 pub use var_builder::VarBuilder;
 pub use var_map::VarMap;
 
@@ -49,7 +49,10 @@ impl super::Module for Linear {
         let x = x.matmul(&w)?;
         match &self.bias {
             None => Ok(x),
-            Some(bias) => x.broadcast_add(bias),
+            Some(bias) => {
+                let bias = candle::autocast::cast(bias, candle::autocast::AutocastOp::Matmul)?;
+                x.broadcast_add(&bias)
+            }
         }
     }
 }
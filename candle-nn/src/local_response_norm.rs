@@ -0,0 +1,73 @@
+//! Local Response Normalization.
+//!
+//! This layer normalizes each element using the squared values of its neighbours over the
+//! channel dimension as introduced in AlexNet. The input should have at least three dimensions
+//! `(batch, channels, ...)`, the output for channel `c` is
+//!
+//! `x_c / (k + alpha / size * sum_{c'} x_{c'}^2) ^ beta`
+//!
+//! where the sum runs over the `size` channels centered on `c`, channels outside of the input
+//! being treated as zeros.
+use candle::{Result, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalResponseNormConfig {
+    pub alpha: f64,
+    pub beta: f64,
+    pub k: f64,
+}
+
+impl Default for LocalResponseNormConfig {
+    fn default() -> Self {
+        Self {
+            alpha: 1e-4,
+            beta: 0.75,
+            k: 1.,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalResponseNorm {
+    size: usize,
+    config: LocalResponseNormConfig,
+}
+
+impl LocalResponseNorm {
+    pub fn new(size: usize, config: LocalResponseNormConfig) -> Result<Self> {
+        if size == 0 {
+            candle::bail!("local-response-norm size should be positive")
+        }
+        Ok(Self { size, config })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn config(&self) -> &LocalResponseNormConfig {
+        &self.config
+    }
+}
+
+impl crate::Module for LocalResponseNorm {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        if x.rank() < 3 {
+            candle::bail!(
+                "local-response-norm input should have at least three dimensions {:?}",
+                x.shape()
+            )
+        }
+        let c = x.dim(1)?;
+        let sq = x
+            .sqr()?
+            .pad_with_zeros(1, self.size / 2, (self.size - 1) / 2)?;
+        let mut sum = sq.narrow(1, 0, c)?;
+        for i in 1..self.size {
+            sum = (sum + sq.narrow(1, i, c)?)?
+        }
+        let cfg = &self.config;
+        let div = ((sum * (cfg.alpha / self.size as f64))? + cfg.k)?.powf(cfg.beta)?;
+        x / div
+    }
+}
@@ -1,3 +1,4 @@
+use candle::autocast::{cast, AutocastOp};
 use candle::{Result, Tensor};
 
 /// The negative log likelihood loss.
@@ -22,6 +23,7 @@ pub fn nll(inp: &Tensor, target: &Tensor) -> Result<Tensor> {
         }
         dims => candle::bail!("the target tensor should have two dimensions ({dims:?})"),
     }
+    let inp = cast(inp, AutocastOp::Loss)?;
     inp.gather(&target.unsqueeze(1)?, 1)?
         .sum_all()?
         .affine(-1f64 / b_sz as f64, 0.)
@@ -46,6 +48,8 @@ pub fn cross_entropy(inp: &Tensor, target: &Tensor) -> Result<Tensor> {
 
 /// The mean squared error loss.
 pub fn mse(inp: &Tensor, target: &Tensor) -> Result<Tensor> {
+    let inp = cast(inp, AutocastOp::Loss)?;
+    let target = cast(target, AutocastOp::Loss)?;
     (inp - target)?.sqr()?.mean_all()
 }
 
@@ -60,7 +64,8 @@ pub fn mse(inp: &Tensor, target: &Tensor) -> Result<Tensor> {
 ///
 /// The resulting tensor is a scalar containing the average value over the batch.
 pub fn binary_cross_entropy_with_logit(inp: &Tensor, target: &Tensor) -> Result<Tensor> {
-    let inp = crate::ops::sigmoid(inp)?;
+    let inp = crate::ops::sigmoid(&cast(inp, AutocastOp::Loss)?)?;
+    let target = &cast(target, AutocastOp::Loss)?;
 
     let left_side = target * inp.log()?;
     let right_side = (target.affine(-1., 1.))? * inp.affine(-1., 1.)?.log()?;
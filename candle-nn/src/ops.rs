@@ -1,3 +1,4 @@
+use candle::autocast::{self, AutocastOp};
 use candle::{CpuStorage, DType, Layout, Module, Result, Shape, Tensor, D};
 use rayon::prelude::*;
 
@@ -17,6 +18,7 @@ use rayon::prelude::*;
 /// # Ok::<(), candle::Error>(())
 /// ```
 pub fn softmax<D: candle::shape::Dim>(xs: &Tensor, dim: D) -> Result<Tensor> {
+    let xs = &autocast::cast(xs, AutocastOp::Softmax)?;
     let dim = dim.to_index(xs.shape(), "softmax")?;
     let max = xs.max_keepdim(dim)?;
     let diff = xs.broadcast_sub(&max)?;
@@ -26,6 +28,7 @@ pub fn softmax<D: candle::shape::Dim>(xs: &Tensor, dim: D) -> Result<Tensor> {
 }
 
 pub fn log_softmax<D: candle::shape::Dim>(xs: &Tensor, d: D) -> Result<Tensor> {
+    let xs = &autocast::cast(xs, AutocastOp::Softmax)?;
     let d = d.to_index(xs.shape(), "log-softmax")?;
     let max = xs.max_keepdim(d)?;
     let diff = xs.broadcast_sub(&max)?;
@@ -229,6 +232,10 @@ impl candle::CustomOp1 for Sigmoid {
 }
 
 pub fn sigmoid(xs: &Tensor) -> Result<Tensor> {
+    // There is no specialized kernel for the wgpu backend.
+    if xs.device().is_wgpu() {
+        return (xs.neg()?.exp()? + 1.0)?.recip();
+    }
     xs.apply_op1(Sigmoid)
 }
 
@@ -425,10 +432,27 @@ impl candle::CustomOp1 for SoftmaxLastDim {
             candle::MetalStorage::new(output, device.clone(), elem_count, storage.dtype());
         Ok((newstorage, layout.shape().clone()))
     }
+
+    #[cfg(feature = "wgpu")]
+    fn wgpu_fwd(
+        &self,
+        storage: &candle::WgpuStorage,
+        layout: &Layout,
+    ) -> Result<(candle::WgpuStorage, Shape)> {
+        let storage = storage.softmax_last_dim(layout)?;
+        Ok((storage, layout.shape().clone()))
+    }
+
+    fn bwd(&self, _arg: &Tensor, res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        // d/dx_i softmax(x)_j = softmax(x)_j * (delta_ij - softmax(x)_i)
+        let dot = (grad_res * res)?.sum_keepdim(D::Minus1)?;
+        Ok(Some((grad_res.broadcast_sub(&dot)? * res)?))
+    }
 }
 
 pub fn softmax_last_dim(xs: &Tensor) -> Result<Tensor> {
-    xs.apply_op1_no_bwd(&SoftmaxLastDim)
+    let xs = autocast::cast(xs, AutocastOp::Softmax)?;
+    xs.apply_op1(SoftmaxLastDim)
 }
 
 #[derive(Debug, Clone)]
@@ -543,9 +567,12 @@ impl candle::CustomOp2 for RmsNorm {
                 let dim_m1 = dims[dims.len() - 1];
                 let (n_rows, n_cols) = (el / dim_m1, dim_m1);
 
+                // One block per row, a single warp is enough for small hidden sizes and avoids
+                // going through shared memory for the reduction.
+                let block_size = if n_cols < 1024 { 32 } else { 1024 };
                 let cfg = LaunchConfig {
                     grid_dim: (n_rows as u32, 1, 1),
-                    block_dim: (1024, 1, 1),
+                    block_dim: (block_size, 1, 1),
                     shared_mem_bytes: 0,
                 };
                 let func = dev.get_or_load_func(&kernel_name::<T>("rmsnorm"), kernels::REDUCE)?;
@@ -637,7 +664,406 @@ pub fn rms_norm(xs: &Tensor, alpha: &Tensor, eps: f32) -> Result<Tensor> {
             alpha.shape()
         )
     }
-    xs.apply_op2_no_bwd(alpha, &RmsNorm { eps })
+    let xs = autocast::cast(xs, AutocastOp::Norm)?;
+    let alpha = autocast::cast(alpha, AutocastOp::Norm)?;
+    if xs.device().is_wgpu() {
+        return rms_norm_slow(&xs, &alpha, eps);
+    }
+    xs.apply_op2_no_bwd(&alpha, &RmsNorm { eps })
+}
+
+/// Checks the shapes of the attention inputs and returns `(b_sz, n_heads, seq_len, kv_len)`.
+fn sdpa_dims(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    causal: bool,
+) -> Result<(usize, usize, usize, usize)> {
+    let (b_sz, n_heads, seq_len, head_dim) = q.dims4()?;
+    let (k_b_sz, k_heads, kv_len, k_head_dim) = k.dims4()?;
+    let (v_b_sz, v_heads, v_len, _v_head_dim) = v.dims4()?;
+    if (b_sz, n_heads, head_dim) != (k_b_sz, k_heads, k_head_dim)
+        || (k_b_sz, k_heads, kv_len) != (v_b_sz, v_heads, v_len)
+    {
+        candle::bail!(
+            "shape mismatch in scaled-dot-product-attention q: {:?} k: {:?} v: {:?}",
+            q.shape(),
+            k.shape(),
+            v.shape()
+        )
+    }
+    if causal && seq_len > kv_len {
+        candle::bail!(
+            "causal scaled-dot-product-attention requires seq_len {seq_len} <= kv_len {kv_len}"
+        )
+    }
+    Ok((b_sz, n_heads, seq_len, kv_len))
+}
+
+/// Computes scaled dot-product attention, `softmax(q k^T * scale + mask) v`.
+///
+/// The inputs use the `(batch, heads, seq_len, head_dim)` layout, `k` and `v` have the same
+/// sequence length `kv_len`. `mask` is an additive mask that is broadcasted to
+/// `(batch, heads, seq_len, kv_len)`, e.g. a tensor filled with `0` and `-inf`. `scale` defaults
+/// to `1 / sqrt(head_dim)`.
+///
+/// When `causal` is set, query `i` only attends to the keys `j <= i + kv_len - seq_len`, so the
+/// last query is aligned with the last key as is the case when decoding with a kv-cache.
+///
+/// On CUDA, this uses [`scaled_dot_product_attention_fused`] which does not materialize the
+/// attention scores when `q`, `k`, and `v` share the same head dimension of at most 256 and no
+/// gradient has to be tracked. Otherwise [`scaled_dot_product_attention_slow`] is used.
+pub fn scaled_dot_product_attention(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    mask: Option<&Tensor>,
+    scale: Option<f64>,
+    causal: bool,
+) -> Result<Tensor> {
+    sdpa_dims(q, k, v, causal)?;
+    let head_dim = q.dim(D::Minus1)?;
+    let use_fused = q.device().is_cuda()
+        && matches!(q.dtype(), DType::F32 | DType::F16 | DType::BF16)
+        && head_dim <= FUSED_ATTENTION_MAX_HEAD_DIM
+        && v.dim(D::Minus1)? == head_dim
+        && !(q.track_op() || k.track_op() || v.track_op() || mask.is_some_and(|m| m.track_op()));
+    if use_fused {
+        scaled_dot_product_attention_fused(q, k, v, mask, scale, causal)
+    } else {
+        scaled_dot_product_attention_slow(q, k, v, mask, scale, causal)
+    }
+}
+
+/// Scaled dot-product attention that materializes the `(batch, heads, seq_len, kv_len)`
+/// attention scores, this runs on all devices and supports backpropagation. See
+/// [`scaled_dot_product_attention`] for the arguments.
+///
+/// The scale is applied to the queries before the matmul and the causal mask is generated
+/// directly with the attention scores shape.
+pub fn scaled_dot_product_attention_slow(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    mask: Option<&Tensor>,
+    scale: Option<f64>,
+    causal: bool,
+) -> Result<Tensor> {
+    let (_b_sz, _n_heads, seq_len, kv_len) = sdpa_dims(q, k, v, causal)?;
+    let head_dim = q.dim(D::Minus1)?;
+    let scale = scale.unwrap_or_else(|| 1. / (head_dim as f64).sqrt());
+    let q = (q * scale)?;
+    let att = q.contiguous()?.matmul(&k.t()?.contiguous()?)?;
+    let att = if causal {
+        let offset = kv_len - seq_len;
+        let causal_mask: Vec<f32> = (0..seq_len)
+            .flat_map(|i| {
+                (0..kv_len).map(move |j| {
+                    if j > i + offset {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+            .collect();
+        let causal_mask = Tensor::from_vec(causal_mask, (seq_len, kv_len), att.device())?
+            .to_dtype(att.dtype())?;
+        att.broadcast_add(&causal_mask)?
+    } else {
+        att
+    };
+    let att = match mask {
+        None => att,
+        Some(mask) => att.broadcast_add(mask)?,
+    };
+    softmax_last_dim(&att)?.matmul(&v.contiguous()?)
+}
+
+/// The number of keys and values processed at once by the fused attention, the CUDA kernel
+/// loads them in shared memory. The online softmax rescales the accumulators once per tile.
+const FUSED_ATTENTION_BLOCK_KV: usize = 16;
+const FUSED_ATTENTION_MAX_HEAD_DIM: usize = 256;
+
+#[derive(Debug, Clone)]
+struct FusedAttention {
+    scale: f32,
+    causal: bool,
+    // The mask is broadcasted to (batch, heads, seq_len, kv_len) and has the dtype of q.
+    mask: Option<Tensor>,
+}
+
+impl candle::CustomOp3 for FusedAttention {
+    fn name(&self) -> &'static str {
+        "fused-attention"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+        s3: &CpuStorage,
+        l3: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        use candle::backend::BackendStorage;
+
+        #[allow(clippy::too_many_arguments)]
+        fn inner<T: candle::WithDType + num_traits::Float + num_traits::AsPrimitive<f32>>(
+            q: &[T],
+            q_l: &Layout,
+            k: &[T],
+            k_l: &Layout,
+            v: &[T],
+            v_l: &Layout,
+            mask: Option<(&CpuStorage, &Layout)>,
+            scale: f32,
+            causal: bool,
+        ) -> Result<(CpuStorage, Shape)> {
+            fn slice<'a, T>(xs: &'a [T], l: &Layout, name: &str) -> Result<&'a [T]> {
+                match l.contiguous_offsets() {
+                    None => candle::bail!("{name} has to be contiguous"),
+                    Some((o1, o2)) => Ok(&xs[o1..o2]),
+                }
+            }
+            let (q, k, v) = (
+                slice(q, q_l, "q")?,
+                slice(k, k_l, "k")?,
+                slice(v, v_l, "v")?,
+            );
+            let (_b_sz, n_heads, seq_len, head_dim) = q_l.shape().dims4()?;
+            let kv_len = k_l.dims()[2];
+            let mask = match mask {
+                None => None,
+                Some((storage, l)) => {
+                    Some((&storage.as_slice::<T>()?[l.start_offset()..], l.stride()))
+                }
+            };
+            let mut dst = vec![T::zero(); q_l.shape().elem_count()];
+            dst.par_chunks_mut(head_dim)
+                .enumerate()
+                .for_each(|(idx, dst)| {
+                    let (bh, row) = (idx / seq_len, idx % seq_len);
+                    let q = &q[idx * head_dim..(idx + 1) * head_dim];
+                    let k = &k[bh * kv_len * head_dim..(bh + 1) * kv_len * head_dim];
+                    let v = &v[bh * kv_len * head_dim..(bh + 1) * kv_len * head_dim];
+                    let mask = mask.map(|(m, s)| {
+                        let offset = (bh / n_heads) * s[0] + (bh % n_heads) * s[1] + row * s[2];
+                        (&m[offset..], s[3])
+                    });
+                    let kv_end = if causal {
+                        usize::min(kv_len, row + kv_len - seq_len + 1)
+                    } else {
+                        kv_len
+                    };
+                    let mut acc = vec![0f32; head_dim];
+                    let mut scores = [0f32; FUSED_ATTENTION_BLOCK_KV];
+                    let (mut m, mut l) = (f32::NEG_INFINITY, 0f32);
+                    for kv_start in (0..kv_end).step_by(FUSED_ATTENTION_BLOCK_KV) {
+                        let tile_len = usize::min(FUSED_ATTENTION_BLOCK_KV, kv_end - kv_start);
+                        let mut tile_max = f32::NEG_INFINITY;
+                        for (jj, score) in scores.iter_mut().take(tile_len).enumerate() {
+                            let j = kv_start + jj;
+                            let k = &k[j * head_dim..(j + 1) * head_dim];
+                            let mut s = q
+                                .iter()
+                                .zip(k)
+                                .map(|(&q, &k)| q.as_() * k.as_())
+                                .sum::<f32>()
+                                * scale;
+                            if let Some((mask, stride)) = mask {
+                                s += mask[j * stride].as_()
+                            }
+                            *score = s;
+                            tile_max = tile_max.max(s);
+                        }
+                        let m_new = m.max(tile_max);
+                        if m_new == f32::NEG_INFINITY {
+                            continue;
+                        }
+                        // Rescale the previous accumulators to the new maximum.
+                        let correction = (m - m_new).exp();
+                        l *= correction;
+                        acc.iter_mut().for_each(|a| *a *= correction);
+                        for (jj, score) in scores.iter().take(tile_len).enumerate() {
+                            let j = kv_start + jj;
+                            let p = (score - m_new).exp();
+                            l += p;
+                            let v = &v[j * head_dim..(j + 1) * head_dim];
+                            for (a, &v) in acc.iter_mut().zip(v) {
+                                *a += p * v.as_()
+                            }
+                        }
+                        m = m_new;
+                    }
+                    for (d, a) in dst.iter_mut().zip(acc) {
+                        *d = T::from(a / l).unwrap_or_else(T::nan)
+                    }
+                });
+            let storage = candle::WithDType::to_cpu_storage_owned(dst);
+            Ok((storage, q_l.shape().clone()))
+        }
+
+        let mask = self.mask.as_ref().map(|m| m.storage_and_layout());
+        let mask = match &mask {
+            None => None,
+            Some((storage, layout)) => match &**storage {
+                candle::Storage::Cpu(storage) => Some((storage, *layout)),
+                _ => candle::bail!("the mask must be on the cpu"),
+            },
+        };
+        let (scale, causal) = (self.scale, self.causal);
+        use CpuStorage as C;
+        match (s1, s2, s3) {
+            (C::BF16(q), C::BF16(k), C::BF16(v)) => {
+                inner::<half::bf16>(q, l1, k, l2, v, l3, mask, scale, causal)
+            }
+            (C::F16(q), C::F16(k), C::F16(v)) => {
+                inner::<half::f16>(q, l1, k, l2, v, l3, mask, scale, causal)
+            }
+            (C::F32(q), C::F32(k), C::F32(v)) => {
+                inner::<f32>(q, l1, k, l2, v, l3, mask, scale, causal)
+            }
+            _ => candle::bail!("unsupported dtype for fused-attention {:?}", s1.dtype()),
+        }
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        s1: &candle::CudaStorage,
+        l1: &Layout,
+        s2: &candle::CudaStorage,
+        l2: &Layout,
+        s3: &candle::CudaStorage,
+        l3: &Layout,
+    ) -> Result<(candle::CudaStorage, Shape)> {
+        use candle::backend::BackendStorage;
+        use candle::cuda_backend::cudarc::driver::{
+            CudaView, DeviceRepr, LaunchAsync, LaunchConfig,
+        };
+        use candle::cuda_backend::{kernel_name, kernels, CudaDType, WrapErr};
+        use candle::{CudaStorage, WithDType};
+
+        fn inner<T: CudaDType + DeviceRepr + WithDType>(
+            op: &FusedAttention,
+            q: &CudaStorage,
+            q_l: &Layout,
+            k: &CudaStorage,
+            k_l: &Layout,
+            v: &CudaStorage,
+            v_l: &Layout,
+        ) -> Result<CudaStorage> {
+            let dev = q.device();
+            fn slice<'a, T: CudaDType>(
+                xs: &'a CudaStorage,
+                l: &Layout,
+                name: &str,
+            ) -> Result<CudaView<'a, T>> {
+                let xs = xs.as_cuda_slice::<T>()?;
+                match l.contiguous_offsets() {
+                    None => candle::bail!("{name} has to be contiguous"),
+                    Some((o1, o2)) => Ok(xs.slice(o1..o2)),
+                }
+            }
+            // Placeholder for the mask argument when there is no mask, the kernel does not read it.
+            let no_mask = q.as_cuda_slice::<T>()?;
+            let (q, k, v) = (
+                slice::<T>(q, q_l, "q")?,
+                slice::<T>(k, k_l, "k")?,
+                slice::<T>(v, v_l, "v")?,
+            );
+            let (b_sz, n_heads, seq_len, head_dim) = q_l.shape().dims4()?;
+            let kv_len = k_l.dims()[2];
+
+            let mask = op.mask.as_ref().map(|m| m.storage_and_layout());
+            let (mask, has_mask, mask_strides) = match &mask {
+                None => (no_mask.slice(0..), 0, [0, 0, 0, 0]),
+                Some((storage, layout)) => {
+                    let storage = match &**storage {
+                        candle::Storage::Cuda(storage) => storage.as_cuda_slice::<T>()?,
+                        _ => candle::bail!("the mask must be on a cuda device"),
+                    };
+                    let s = layout.stride();
+                    let mask = storage.slice(layout.start_offset()..);
+                    (mask, 1, [s[0], s[1], s[2], s[3]])
+                }
+            };
+            let info = [n_heads, seq_len, kv_len, head_dim, has_mask]
+                .into_iter()
+                .chain(mask_strides)
+                .collect::<Vec<usize>>();
+            let info = dev.htod_copy(info).w()?;
+            let causal_offset = if op.causal {
+                (kv_len - seq_len) as i64
+            } else {
+                -1
+            };
+
+            let el = q_l.shape().elem_count();
+            let block_q = 8;
+            let cfg = LaunchConfig {
+                grid_dim: (seq_len.div_ceil(block_q) as u32, (b_sz * n_heads) as u32, 1),
+                block_dim: (32, block_q as u32, 1),
+                shared_mem_bytes: (2 * FUSED_ATTENTION_BLOCK_KV * head_dim * 4) as u32,
+            };
+            let func =
+                dev.get_or_load_func(&kernel_name::<T>("fused_attention"), kernels::ATTENTION)?;
+            // SAFETY: Set later by running the kernel.
+            let dst = unsafe { dev.alloc::<T>(el) }.w()?;
+            let params = (&q, &k, &v, &mask, &dst, &info, op.scale, causal_offset);
+            // SAFETY: ffi.
+            unsafe { func.launch(cfg, params) }.w()?;
+            Ok(CudaStorage::wrap_cuda_slice(dst, dev.clone()))
+        }
+
+        let dst = match s1.dtype() {
+            DType::BF16 => inner::<half::bf16>(self, s1, l1, s2, l2, s3, l3)?,
+            DType::F16 => inner::<half::f16>(self, s1, l1, s2, l2, s3, l3)?,
+            DType::F32 => inner::<f32>(self, s1, l1, s2, l2, s3, l3)?,
+            dt => candle::bail!("fused-attention is not implemented for {dt:?}"),
+        };
+        Ok((dst, l1.shape().clone()))
+    }
+}
+
+/// Scaled dot-product attention that processes the keys and values by tiles using an online
+/// softmax, so that the attention scores are never materialized. This is implemented on CPU
+/// and CUDA, `q`, `k`, and `v` must have the same head dimension of at most 256, and there is
+/// no backpropagation. See [`scaled_dot_product_attention`] for the arguments.
+pub fn scaled_dot_product_attention_fused(
+    q: &Tensor,
+    k: &Tensor,
+    v: &Tensor,
+    mask: Option<&Tensor>,
+    scale: Option<f64>,
+    causal: bool,
+) -> Result<Tensor> {
+    let (b_sz, n_heads, seq_len, kv_len) = sdpa_dims(q, k, v, causal)?;
+    let head_dim = q.dim(D::Minus1)?;
+    if v.dim(D::Minus1)? != head_dim || head_dim > FUSED_ATTENTION_MAX_HEAD_DIM {
+        candle::bail!(
+            "fused-attention requires q and v to have the same head dim, at most {FUSED_ATTENTION_MAX_HEAD_DIM} q: {:?} v: {:?}",
+            q.shape(),
+            v.shape()
+        )
+    }
+    let mask = match mask {
+        None => None,
+        Some(mask) => Some(
+            mask.to_dtype(q.dtype())?
+                .broadcast_as((b_sz, n_heads, seq_len, kv_len))?,
+        ),
+    };
+    let scale = scale.unwrap_or_else(|| 1. / (head_dim as f64).sqrt());
+    let op = FusedAttention {
+        scale: scale as f32,
+        causal,
+        mask,
+    };
+    q.contiguous()?
+        .apply_op3_no_bwd(&k.contiguous()?, &v.contiguous()?, &op)
 }
 
 #[derive(Debug, Clone)]
@@ -885,20 +1311,37 @@ pub fn layer_norm(xs: &Tensor, alpha: &Tensor, beta: &Tensor, eps: f32) -> Resul
             beta.shape()
         )
     }
-    xs.apply_op3_no_bwd(alpha, beta, &LayerNorm { eps })
+    let xs = autocast::cast(xs, AutocastOp::Norm)?;
+    let alpha = autocast::cast(alpha, AutocastOp::Norm)?;
+    let beta = autocast::cast(beta, AutocastOp::Norm)?;
+    if xs.device().is_wgpu() {
+        return layer_norm_slow(&xs, &alpha, &beta, eps);
+    }
+    xs.apply_op3_no_bwd(&alpha, &beta, &LayerNorm { eps })
 }
 
 // https://pytorch.org/docs/stable/generated/torch.nn.PixelShuffle.html
 pub fn pixel_shuffle(xs: &Tensor, upscale_factor: usize) -> Result<Tensor> {
     let (b_size, c, h, w) = xs.dims4()?;
+    if upscale_factor == 0 || c % (upscale_factor * upscale_factor) != 0 {
+        candle::bail!(
+            "pixel-shuffle: channels {c} should be divisible by the square of the upscale factor {upscale_factor}"
+        )
+    }
     let out_c = c / upscale_factor / upscale_factor;
     xs.reshape((b_size, out_c, upscale_factor, upscale_factor, h, w))?
         .permute((0, 1, 4, 2, 5, 3))?
         .reshape((b_size, out_c, h * upscale_factor, w * upscale_factor))
 }
 
+// https://pytorch.org/docs/stable/generated/torch.nn.PixelUnshuffle.html
 pub fn pixel_unshuffle(xs: &Tensor, downscale_factor: usize) -> Result<Tensor> {
     let (b_size, c, h, w) = xs.dims4()?;
+    if downscale_factor == 0 || h % downscale_factor != 0 || w % downscale_factor != 0 {
+        candle::bail!(
+            "pixel-unshuffle: spatial dims ({h}, {w}) should be divisible by the downscale factor {downscale_factor}"
+        )
+    }
     let out_c = c * downscale_factor * downscale_factor;
     xs.reshape((
         b_size,
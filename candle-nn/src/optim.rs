@@ -199,3 +199,746 @@ impl AdamW {
         self.params = params;
     }
 }
+
+#[derive(Clone, Debug)]
+pub struct ParamsAdam {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub eps: f64,
+    /// L2 penalty added to the gradients, contrary to `AdamW` the weight decay is not decoupled
+    /// from the gradient based update.
+    pub weight_decay: f64,
+}
+
+impl Default for ParamsAdam {
+    fn default() -> Self {
+        Self {
+            lr: 0.001,
+            beta1: 0.9,
+            beta2: 0.999,
+            eps: 1e-8,
+            weight_decay: 0.,
+        }
+    }
+}
+
+/// The Adam optimizer, following the PyTorch implementation.
+///
+/// <https://pytorch.org/docs/stable/generated/torch.optim.Adam.html>
+#[derive(Debug)]
+pub struct Adam {
+    vars: Vec<VarAdamW>,
+    step_t: usize,
+    params: ParamsAdam,
+}
+
+impl Optimizer for Adam {
+    type Config = ParamsAdam;
+
+    fn new(vars: Vec<Var>, params: ParamsAdam) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let first_moment = Var::zeros(shape, dtype, device)?;
+                let second_moment = Var::zeros(shape, dtype, device)?;
+                Ok(VarAdamW {
+                    var,
+                    first_moment,
+                    second_moment,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        self.step_t += 1;
+        let lr = self.params.lr;
+        let beta1 = self.params.beta1;
+        let beta2 = self.params.beta2;
+        let scale_m = 1f64 / (1f64 - beta1.powi(self.step_t as i32));
+        let scale_v = 1f64 / (1f64 - beta2.powi(self.step_t as i32));
+        for var in self.vars.iter() {
+            let theta = &var.var;
+            let m = &var.first_moment;
+            let v = &var.second_moment;
+            if let Some(g) = grads.get(theta) {
+                let g = if self.params.weight_decay != 0. {
+                    (g + (theta.as_tensor() * self.params.weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let next_m = ((m.as_tensor() * beta1)? + (&g * (1.0 - beta1))?)?;
+                let next_v = ((v.as_tensor() * beta2)? + (g.sqr()? * (1.0 - beta2))?)?;
+                let m_hat = (&next_m * scale_m)?;
+                let v_hat = (&next_v * scale_v)?;
+                let adjusted_grad = (m_hat / (v_hat.sqrt()? + self.params.eps)?)?;
+                let next_theta = (theta.as_tensor() - (adjusted_grad * lr)?)?;
+                m.set(&next_m)?;
+                v.set(&next_v)?;
+                theta.set(&next_theta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Adam {
+    pub fn new_lr(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsAdam {
+            lr: learning_rate,
+            ..ParamsAdam::default()
+        };
+        Self::new(vars, params)
+    }
+
+    pub fn params(&self) -> &ParamsAdam {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdam) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsRMSProp {
+    pub lr: f64,
+    /// The smoothing constant for the running average of the squared gradients.
+    pub alpha: f64,
+    pub eps: f64,
+    pub weight_decay: f64,
+    pub momentum: f64,
+    /// When set, the gradients are normalized by an estimate of their variance rather than by
+    /// their uncentered second moment.
+    pub centered: bool,
+}
+
+impl Default for ParamsRMSProp {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            alpha: 0.99,
+            eps: 1e-8,
+            weight_decay: 0.,
+            momentum: 0.,
+            centered: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VarRMSProp {
+    var: Var,
+    square_avg: Var,
+    grad_avg: Option<Var>,
+    momentum_buffer: Option<Var>,
+}
+
+/// The RMSProp optimizer, following the PyTorch implementation.
+///
+/// <https://pytorch.org/docs/stable/generated/torch.optim.RMSprop.html>
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub struct RMSProp {
+    vars: Vec<VarRMSProp>,
+    params: ParamsRMSProp,
+}
+
+impl Optimizer for RMSProp {
+    type Config = ParamsRMSProp;
+
+    fn new(vars: Vec<Var>, params: ParamsRMSProp) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let shape = var.shape();
+                let device = var.device();
+                let square_avg = Var::zeros(shape, dtype, device)?;
+                let grad_avg = if params.centered {
+                    Some(Var::zeros(shape, dtype, device)?)
+                } else {
+                    None
+                };
+                let momentum_buffer = if params.momentum > 0. {
+                    Some(Var::zeros(shape, dtype, device)?)
+                } else {
+                    None
+                };
+                Ok(VarRMSProp {
+                    var,
+                    square_avg,
+                    grad_avg,
+                    momentum_buffer,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        let lr = self.params.lr;
+        let alpha = self.params.alpha;
+        for var in self.vars.iter() {
+            let theta = &var.var;
+            if let Some(g) = grads.get(theta) {
+                let g = if self.params.weight_decay != 0. {
+                    (g + (theta.as_tensor() * self.params.weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let square_avg =
+                    ((var.square_avg.as_tensor() * alpha)? + (g.sqr()? * (1. - alpha))?)?;
+                let avg = match &var.grad_avg {
+                    None => square_avg.sqrt()?,
+                    Some(grad_avg) => {
+                        let next_grad_avg =
+                            ((grad_avg.as_tensor() * alpha)? + (&g * (1. - alpha))?)?;
+                        let avg = (&square_avg - next_grad_avg.sqr()?)?.sqrt()?;
+                        grad_avg.set(&next_grad_avg)?;
+                        avg
+                    }
+                };
+                let update = (g / (avg + self.params.eps)?)?;
+                let update = match &var.momentum_buffer {
+                    None => update,
+                    Some(buf) => {
+                        let next_buf = ((buf.as_tensor() * self.params.momentum)? + update)?;
+                        buf.set(&next_buf)?;
+                        next_buf
+                    }
+                };
+                var.square_avg.set(&square_avg)?;
+                theta.set(&(theta.as_tensor() - (update * lr)?)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RMSProp {
+    pub fn new_lr(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsRMSProp {
+            lr: learning_rate,
+            ..ParamsRMSProp::default()
+        };
+        Self::new(vars, params)
+    }
+
+    pub fn params(&self) -> &ParamsRMSProp {
+        &self.params
+    }
+
+    /// Sets the hyper-parameters, the `centered` and `momentum` state is only allocated when the
+    /// optimizer is created so these should not be enabled here.
+    pub fn set_params(&mut self, params: ParamsRMSProp) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsAdagrad {
+    pub lr: f64,
+    /// The learning rate used at step `t` is `lr / (1 + (t - 1) * lr_decay)`.
+    pub lr_decay: f64,
+    pub weight_decay: f64,
+    pub initial_accumulator_value: f64,
+    pub eps: f64,
+}
+
+impl Default for ParamsAdagrad {
+    fn default() -> Self {
+        Self {
+            lr: 0.01,
+            lr_decay: 0.,
+            weight_decay: 0.,
+            initial_accumulator_value: 0.,
+            eps: 1e-10,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VarAdagrad {
+    var: Var,
+    sum: Var,
+}
+
+/// The Adagrad optimizer, following the PyTorch implementation.
+///
+/// <https://pytorch.org/docs/stable/generated/torch.optim.Adagrad.html>
+#[derive(Debug)]
+pub struct Adagrad {
+    vars: Vec<VarAdagrad>,
+    step_t: usize,
+    params: ParamsAdagrad,
+}
+
+impl Optimizer for Adagrad {
+    type Config = ParamsAdagrad;
+
+    fn new(vars: Vec<Var>, params: ParamsAdagrad) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let sum =
+                    Tensor::full(params.initial_accumulator_value, var.shape(), var.device())?
+                        .to_dtype(var.dtype())?;
+                let sum = Var::from_tensor(&sum)?;
+                Ok(VarAdagrad { var, sum })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        self.step_t += 1;
+        let clr = self.params.lr / (1. + (self.step_t - 1) as f64 * self.params.lr_decay);
+        for var in self.vars.iter() {
+            let theta = &var.var;
+            if let Some(g) = grads.get(theta) {
+                let g = if self.params.weight_decay != 0. {
+                    (g + (theta.as_tensor() * self.params.weight_decay)?)?
+                } else {
+                    g.clone()
+                };
+                let sum = (var.sum.as_tensor() + g.sqr()?)?;
+                let update = (g / (sum.sqrt()? + self.params.eps)?)?;
+                var.sum.set(&sum)?;
+                theta.set(&(theta.as_tensor() - (update * clr)?)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Adagrad {
+    pub fn new_lr(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsAdagrad {
+            lr: learning_rate,
+            ..ParamsAdagrad::default()
+        };
+        Self::new(vars, params)
+    }
+
+    pub fn params(&self) -> &ParamsAdagrad {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdagrad) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsLion {
+    pub lr: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub weight_decay: f64,
+}
+
+impl Default for ParamsLion {
+    fn default() -> Self {
+        Self {
+            lr: 1e-4,
+            beta1: 0.9,
+            beta2: 0.99,
+            weight_decay: 0.,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct VarLion {
+    var: Var,
+    exp_avg: Var,
+}
+
+/// The Lion optimizer, the update is the sign of an interpolation between the momentum and the
+/// gradient so it only keeps a single moment per parameter.
+///
+/// <https://arxiv.org/abs/2302.06675>
+#[derive(Debug)]
+pub struct Lion {
+    vars: Vec<VarLion>,
+    params: ParamsLion,
+}
+
+impl Optimizer for Lion {
+    type Config = ParamsLion;
+
+    fn new(vars: Vec<Var>, params: ParamsLion) -> Result<Self> {
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let exp_avg = Var::zeros(var.shape(), var.dtype(), var.device())?;
+                Ok(VarLion { var, exp_avg })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { vars, params })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.params.lr
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = lr
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        let lr = self.params.lr;
+        let beta1 = self.params.beta1;
+        let beta2 = self.params.beta2;
+        for var in self.vars.iter() {
+            let theta = &var.var;
+            let m = &var.exp_avg;
+            if let Some(g) = grads.get(theta) {
+                let update = ((m.as_tensor() * beta1)? + (g * (1. - beta1))?)?.sign()?;
+                let next_theta = (theta.as_tensor() * (1. - lr * self.params.weight_decay))?;
+                let next_theta = (next_theta - (update * lr)?)?;
+                let next_m = ((m.as_tensor() * beta2)? + (g * (1. - beta2))?)?;
+                m.set(&next_m)?;
+                theta.set(&next_theta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lion {
+    pub fn new_lr(vars: Vec<Var>, learning_rate: f64) -> Result<Self> {
+        let params = ParamsLion {
+            lr: learning_rate,
+            ..ParamsLion::default()
+        };
+        Self::new(vars, params)
+    }
+
+    pub fn params(&self) -> &ParamsLion {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsLion) {
+        self.params = params;
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParamsAdafactor {
+    /// The external learning rate, this has to be set when `relative_step` is false.
+    pub lr: Option<f64>,
+    /// Regularization constants for the squared gradients and for the parameter scale.
+    pub eps: (f64, f64),
+    /// Threshold on the root mean square of the final update.
+    pub clip_threshold: f64,
+    /// Controls the running average of the squared gradients, `beta2_t = 1 - t ^ decay_rate`.
+    pub decay_rate: f64,
+    /// When set, a first moment is also kept for the updates.
+    pub beta1: Option<f64>,
+    pub weight_decay: f64,
+    /// Scale the learning rate by the root mean square of the parameter.
+    pub scale_parameter: bool,
+    /// Use a time-dependent learning rate `min(1e-2, 1 / sqrt(t))` rather than `lr`.
+    pub relative_step: bool,
+    /// With `relative_step`, use `min(1e-6 * t, 1 / sqrt(t))` as the time-dependent learning
+    /// rate.
+    pub warmup_init: bool,
+}
+
+impl Default for ParamsAdafactor {
+    fn default() -> Self {
+        Self {
+            lr: None,
+            eps: (1e-30, 1e-3),
+            clip_threshold: 1.,
+            decay_rate: -0.8,
+            beta1: None,
+            weight_decay: 0.,
+            scale_parameter: true,
+            relative_step: true,
+            warmup_init: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SecondMoment {
+    /// Running averages of the squared gradients over the last and second to last dimensions.
+    Factored {
+        row: Var,
+        col: Var,
+    },
+    Full(Var),
+}
+
+#[derive(Debug)]
+struct VarAdafactor {
+    var: Var,
+    exp_avg: Option<Var>,
+    exp_avg_sq: SecondMoment,
+}
+
+/// The Adafactor optimizer, the second moment of parameters with at least two dimensions is
+/// factored into per-row and per-column statistics so that the optimizer state is sub-linear in
+/// the number of elements.
+///
+/// <https://arxiv.org/abs/1804.04235>
+#[derive(Debug)]
+pub struct Adafactor {
+    vars: Vec<VarAdafactor>,
+    step_t: usize,
+    params: ParamsAdafactor,
+}
+
+fn rms(xs: &Tensor) -> Result<f64> {
+    xs.sqr()?
+        .mean_all()?
+        .sqrt()?
+        .to_dtype(candle::DType::F64)?
+        .to_scalar::<f64>()
+}
+
+impl Optimizer for Adafactor {
+    type Config = ParamsAdafactor;
+
+    fn new(vars: Vec<Var>, params: ParamsAdafactor) -> Result<Self> {
+        if !params.relative_step && params.lr.is_none() {
+            candle::bail!("adafactor requires a learning rate when relative_step is false")
+        }
+        let vars = vars
+            .into_iter()
+            .filter(|var| var.dtype().is_float())
+            .map(|var| {
+                let dtype = var.dtype();
+                let device = var.device();
+                let dims = var.dims();
+                let exp_avg_sq = if dims.len() >= 2 {
+                    let rank = dims.len();
+                    let row = Var::zeros(&dims[..rank - 1], dtype, device)?;
+                    let mut col_dims = dims[..rank - 2].to_vec();
+                    col_dims.push(dims[rank - 1]);
+                    let col = Var::zeros(col_dims, dtype, device)?;
+                    SecondMoment::Factored { row, col }
+                } else {
+                    SecondMoment::Full(Var::zeros(dims, dtype, device)?)
+                };
+                let exp_avg = match params.beta1 {
+                    None => None,
+                    Some(_) => Some(Var::zeros(dims, dtype, device)?),
+                };
+                Ok(VarAdafactor {
+                    var,
+                    exp_avg,
+                    exp_avg_sq,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            vars,
+            params,
+            step_t: 0,
+        })
+    }
+
+    fn learning_rate(&self) -> f64 {
+        match self.params.lr {
+            Some(lr) if !self.params.relative_step => lr,
+            _ => self.relative_step_size(usize::max(self.step_t, 1)),
+        }
+    }
+
+    fn set_learning_rate(&mut self, lr: f64) {
+        self.params.lr = Some(lr);
+        self.params.relative_step = false
+    }
+
+    fn step(&mut self, grads: &candle::backprop::GradStore) -> Result<()> {
+        use candle::D;
+        self.step_t += 1;
+        let p = &self.params;
+        let beta2_t = 1. - (self.step_t as f64).powf(p.decay_rate);
+        for var in self.vars.iter() {
+            let theta = &var.var;
+            if let Some(g) = grads.get(theta) {
+                let mut lr = match p.lr {
+                    Some(lr) if !p.relative_step => lr,
+                    _ => self.relative_step_size(self.step_t),
+                };
+                if p.scale_parameter {
+                    lr *= f64::max(p.eps.1, rms(theta.as_tensor())?)
+                }
+                let update = (g.sqr()? + p.eps.0)?;
+                let update = match &var.exp_avg_sq {
+                    SecondMoment::Factored { row, col } => {
+                        let next_row = ((row.as_tensor() * beta2_t)?
+                            + (update.mean(D::Minus1)? * (1. - beta2_t))?)?;
+                        let next_col = ((col.as_tensor() * beta2_t)?
+                            + (update.mean(D::Minus2)? * (1. - beta2_t))?)?;
+                        let r_factor = next_row
+                            .broadcast_div(&next_row.mean_keepdim(D::Minus1)?)?
+                            .sqrt()?
+                            .recip()?
+                            .unsqueeze(D::Minus1)?;
+                        let c_factor = next_col.sqrt()?.recip()?.unsqueeze(D::Minus2)?;
+                        row.set(&next_row)?;
+                        col.set(&next_col)?;
+                        r_factor.broadcast_mul(&c_factor)?.mul(g)?
+                    }
+                    SecondMoment::Full(v) => {
+                        let next_v = ((v.as_tensor() * beta2_t)? + (update * (1. - beta2_t))?)?;
+                        v.set(&next_v)?;
+                        (next_v.sqrt()?.recip()? * g)?
+                    }
+                };
+                let denom = f64::max(1., rms(&update)? / p.clip_threshold);
+                let update = (update * (lr / denom))?;
+                let update = match (&var.exp_avg, p.beta1) {
+                    (Some(m), Some(beta1)) => {
+                        let next_m = ((m.as_tensor() * beta1)? + (update * (1. - beta1))?)?;
+                        m.set(&next_m)?;
+                        next_m
+                    }
+                    _ => update,
+                };
+                let next_theta = (theta.as_tensor() * (1. - p.weight_decay * lr))?;
+                theta.set(&(next_theta - update)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Adafactor {
+    fn relative_step_size(&self, step_t: usize) -> f64 {
+        let min_step = if self.params.warmup_init {
+            1e-6 * step_t as f64
+        } else {
+            1e-2
+        };
+        f64::min(min_step, 1. / (step_t as f64).sqrt())
+    }
+
+    pub fn params(&self) -> &ParamsAdafactor {
+        &self.params
+    }
+
+    pub fn set_params(&mut self, params: ParamsAdafactor) {
+        self.params = params;
+    }
+
+    /// The total number of elements used by the optimizer state across all the parameters.
+    pub fn state_elem_count(&self) -> usize {
+        self.vars
+            .iter()
+            .map(|v| {
+                let exp_avg = v.exp_avg.as_ref().map_or(0, |m| m.elem_count());
+                let exp_avg_sq = match &v.exp_avg_sq {
+                    SecondMoment::Factored { row, col } => row.elem_count() + col.elem_count(),
+                    SecondMoment::Full(v) => v.elem_count(),
+                };
+                exp_avg + exp_avg_sq
+            })
+            .sum()
+    }
+}
+
+/// Computes the total `norm_type`-norm of all the gradients in `grads`, as if they were
+/// concatenated into a single vector, and rescales them in place so that this norm is at most
+/// `max_norm`. `norm_type` can be `f64::INFINITY` for the max norm. Returns the norm computed
+/// before clipping.
+///
+/// This should be called between `backward` and `Optimizer::step`.
+pub fn clip_grad_norm(
+    grads: &mut candle::backprop::GradStore,
+    max_norm: f64,
+    norm_type: f64,
+) -> Result<f64> {
+    if norm_type <= 0. || norm_type.is_nan() {
+        candle::bail!("clip_grad_norm: norm_type should be positive, got {norm_type}")
+    }
+    let ids: Vec<_> = grads.get_ids().copied().collect();
+    let mut total_norm = 0f64;
+    for &id in ids.iter() {
+        let grad = match grads.get_id(id) {
+            Some(grad) => grad.to_dtype(candle::DType::F64)?.abs()?,
+            None => continue,
+        };
+        if norm_type == f64::INFINITY {
+            if grad.elem_count() > 0 {
+                let max = grad.flatten_all()?.max(0)?.to_scalar::<f64>()?;
+                total_norm = total_norm.max(max)
+            }
+        } else {
+            total_norm += grad.powf(norm_type)?.sum_all()?.to_scalar::<f64>()?
+        }
+    }
+    if norm_type != f64::INFINITY {
+        total_norm = total_norm.powf(1. / norm_type)
+    }
+    if total_norm > max_norm {
+        let scale = max_norm / total_norm;
+        for &id in ids.iter() {
+            if let Some(grad) = grads.get_id_mut(id) {
+                *grad = (&*grad * scale)?
+            }
+        }
+    }
+    Ok(total_norm)
+}
+
+/// Clamps all the gradients in `grads` elementwise to `[-clip_value, clip_value]`.
+pub fn clip_grad_value(grads: &mut candle::backprop::GradStore, clip_value: f64) -> Result<()> {
+    if clip_value < 0. {
+        candle::bail!("clip_grad_value: clip_value cannot be negative, got {clip_value}")
+    }
+    let ids: Vec<_> = grads.get_ids().copied().collect();
+    for id in ids {
+        if let Some(grad) = grads.get_id_mut(id) {
+            *grad = grad.clamp(-clip_value, clip_value)?
+        }
+    }
+    Ok(())
+}
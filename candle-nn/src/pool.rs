@@ -0,0 +1,184 @@
+//! Pooling layers.
+use candle::{Result, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxPoolConfig {
+    /// The stride of the pooling window, defaults to the kernel size when `None`.
+    pub stride: Option<usize>,
+    pub padding: usize,
+    pub dilation: usize,
+}
+
+impl Default for MaxPoolConfig {
+    fn default() -> Self {
+        Self {
+            stride: None,
+            padding: 0,
+            dilation: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AvgPoolConfig {
+    /// The stride of the pooling window, defaults to the kernel size when `None`.
+    pub stride: Option<usize>,
+    pub padding: usize,
+    pub dilation: usize,
+    /// Whether the zero-padded positions are counted when computing the averages.
+    pub count_include_pad: bool,
+}
+
+impl Default for AvgPoolConfig {
+    fn default() -> Self {
+        Self {
+            stride: None,
+            padding: 0,
+            dilation: 1,
+            count_include_pad: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MaxPool1d {
+    kernel_size: usize,
+    config: MaxPoolConfig,
+}
+
+impl MaxPool1d {
+    pub fn new(kernel_size: usize, config: MaxPoolConfig) -> Self {
+        Self {
+            kernel_size,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &MaxPoolConfig {
+        &self.config
+    }
+}
+
+impl crate::Module for MaxPool1d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let cfg = &self.config;
+        xs.max_pool1d_with_params(
+            self.kernel_size,
+            cfg.stride.unwrap_or(self.kernel_size),
+            cfg.padding,
+            cfg.dilation,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MaxPool2d {
+    kernel_size: usize,
+    config: MaxPoolConfig,
+}
+
+impl MaxPool2d {
+    pub fn new(kernel_size: usize, config: MaxPoolConfig) -> Self {
+        Self {
+            kernel_size,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &MaxPoolConfig {
+        &self.config
+    }
+}
+
+impl crate::Module for MaxPool2d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let cfg = &self.config;
+        xs.max_pool2d_with_params(
+            self.kernel_size,
+            cfg.stride.unwrap_or(self.kernel_size),
+            cfg.padding,
+            cfg.dilation,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AvgPool1d {
+    kernel_size: usize,
+    config: AvgPoolConfig,
+}
+
+impl AvgPool1d {
+    pub fn new(kernel_size: usize, config: AvgPoolConfig) -> Self {
+        Self {
+            kernel_size,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &AvgPoolConfig {
+        &self.config
+    }
+}
+
+impl crate::Module for AvgPool1d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let cfg = &self.config;
+        xs.avg_pool1d_with_params(
+            self.kernel_size,
+            cfg.stride.unwrap_or(self.kernel_size),
+            cfg.padding,
+            cfg.dilation,
+            cfg.count_include_pad,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AvgPool2d {
+    kernel_size: usize,
+    config: AvgPoolConfig,
+}
+
+impl AvgPool2d {
+    pub fn new(kernel_size: usize, config: AvgPoolConfig) -> Self {
+        Self {
+            kernel_size,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &AvgPoolConfig {
+        &self.config
+    }
+}
+
+impl crate::Module for AvgPool2d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let cfg = &self.config;
+        xs.avg_pool2d_with_params(
+            self.kernel_size,
+            cfg.stride.unwrap_or(self.kernel_size),
+            cfg.padding,
+            cfg.dilation,
+            cfg.count_include_pad,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AdaptiveAvgPool2d {
+    output_size: (usize, usize),
+}
+
+impl AdaptiveAvgPool2d {
+    pub fn new(output_size: (usize, usize)) -> Self {
+        Self { output_size }
+    }
+}
+
+impl crate::Module for AdaptiveAvgPool2d {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        xs.adaptive_avg_pool2d(self.output_size)
+    }
+}
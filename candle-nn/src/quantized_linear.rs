@@ -0,0 +1,174 @@
+//! Linear layer using dynamic int8 quantization.
+//!
+//! The weights are quantized to int8 once when creating the layer, either with a single scale
+//! for the whole weight matrix or with one scale per output channel. The activations are
+//! quantized per row when running the forward pass, the matmul is done on int8 values with int32
+//! accumulation and the result is rescaled to f32.
+//!
+//! The int8 kernel is only available for f32 inputs on the cpu, other inputs fall back to a
+//! matmul with the dequantized weights.
+//!
+//! ```rust
+//! use candle::{Tensor, Device::Cpu};
+//! use candle_nn::{Int8Scheme, Linear, Module, QuantizedLinear};
+//! # fn main() -> candle::Result<()> {
+//!
+//! let w = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 6.]], &Cpu)?;
+//! let layer = QuantizedLinear::from_linear(&Linear::new(w, None), Int8Scheme::PerChannel)?;
+//! let xs = Tensor::new(&[[10f32, 100.]], &Cpu)?;
+//! let ys = layer.forward(&xs)?;
+//! assert_eq!(ys.dims(), &[1, 3]);
+//! # Ok(()) }
+//! ```
+use candle::{DType, Device, Result, Tensor};
+use std::sync::Arc;
+
+/// How the weights of a [`QuantizedLinear`] layer are quantized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Int8Scheme {
+    /// A single scale for the whole weight matrix.
+    PerTensor,
+    /// One scale for each output channel, i.e. for each row of the weight matrix.
+    PerChannel,
+}
+
+// Symmetric quantization of `xs` to int8 using the scale `amax / 127`, returns the scale.
+fn quantize_i8(xs: &[f32], dst: &mut [i8]) -> f32 {
+    let amax = xs.iter().fold(0f32, |acc, x| acc.max(x.abs()));
+    let scale = amax / 127.;
+    let inv_scale = if scale > 0. { 1. / scale } else { 0. };
+    for (d, x) in dst.iter_mut().zip(xs.iter()) {
+        *d = (x * inv_scale).round().clamp(-127., 127.) as i8
+    }
+    scale
+}
+
+#[derive(Clone, Debug)]
+pub struct QuantizedLinear {
+    weight: Arc<Vec<i8>>,
+    scales: Vec<f32>,
+    bias: Option<Tensor>,
+    in_dim: usize,
+    out_dim: usize,
+    scheme: Int8Scheme,
+}
+
+impl QuantizedLinear {
+    /// Quantizes the weights of a linear layer, the bias is kept as is.
+    pub fn from_linear(linear: &crate::Linear, scheme: Int8Scheme) -> Result<Self> {
+        let (out_dim, in_dim) = linear.weight().dims2()?;
+        let ws = linear
+            .weight()
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let mut weight = vec![0i8; ws.len()];
+        let scales = match scheme {
+            Int8Scheme::PerTensor => vec![quantize_i8(&ws, &mut weight)],
+            Int8Scheme::PerChannel if in_dim == 0 => vec![0.; out_dim],
+            Int8Scheme::PerChannel => weight
+                .chunks_mut(in_dim)
+                .zip(ws.chunks(in_dim))
+                .map(|(dst, ws)| quantize_i8(ws, dst))
+                .collect(),
+        };
+        Ok(Self {
+            weight: Arc::new(weight),
+            scales,
+            bias: linear.bias().cloned(),
+            in_dim,
+            out_dim,
+            scheme,
+        })
+    }
+
+    pub fn scheme(&self) -> Int8Scheme {
+        self.scheme
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.bias.as_ref()
+    }
+
+    fn weight_scale(&self, out_c: usize) -> f32 {
+        match self.scheme {
+            Int8Scheme::PerTensor => self.scales[0],
+            Int8Scheme::PerChannel => self.scales[out_c],
+        }
+    }
+
+    /// The weights after being dequantized, with shape `(out_dim, in_dim)`.
+    pub fn dequantized_weight(&self, device: &Device) -> Result<Tensor> {
+        let ws = self
+            .weight
+            .chunks(self.in_dim.max(1))
+            .enumerate()
+            .flat_map(|(out_c, ws)| {
+                let scale = self.weight_scale(out_c);
+                ws.iter().map(move |&w| w as f32 * scale)
+            })
+            .collect::<Vec<_>>();
+        Tensor::from_vec(ws, (self.out_dim, self.in_dim), device)
+    }
+
+    fn forward_i8(&self, x: &Tensor) -> Result<Tensor> {
+        let mut dims = x.dims().to_vec();
+        let k = self.in_dim;
+        let m = x.elem_count() / k.max(1);
+        let n = self.out_dim;
+        let xs = x.flatten_all()?.to_vec1::<f32>()?;
+        let mut lhs = vec![0i8; xs.len()];
+        let lhs_scales = if k == 0 {
+            vec![0.; m]
+        } else {
+            lhs.chunks_mut(k)
+                .zip(xs.chunks(k))
+                .map(|(dst, xs)| quantize_i8(xs, dst))
+                .collect::<Vec<_>>()
+        };
+        let mut acc = vec![0i32; m * n];
+        candle::cpu::i8_matmul((m, n, k), &lhs, &self.weight, &mut acc)?;
+        let ys = acc
+            .chunks(n.max(1))
+            .zip(lhs_scales.iter())
+            .flat_map(|(acc, &lhs_scale)| {
+                acc.iter()
+                    .enumerate()
+                    .map(move |(out_c, &v)| v as f32 * lhs_scale * self.weight_scale(out_c))
+            })
+            .collect::<Vec<_>>();
+        *dims.last_mut().unwrap() = n;
+        Tensor::from_vec(ys, dims, x.device())
+    }
+}
+
+impl crate::Module for QuantizedLinear {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let in_dim = match x.dims().last() {
+            Some(&in_dim) => in_dim,
+            None => candle::bail!("unexpected scalar input for QuantizedLinear"),
+        };
+        if in_dim != self.in_dim {
+            Err(candle::Error::ShapeMismatchBinaryOp {
+                lhs: x.shape().clone(),
+                rhs: (self.out_dim, self.in_dim).into(),
+                op: "quantized-linear",
+            }
+            .bt())?
+        }
+        if x.device().is_cpu() && x.dtype() == DType::F32 {
+            let ys = self.forward_i8(x)?;
+            match &self.bias {
+                None => Ok(ys),
+                Some(bias) => ys.broadcast_add(bias),
+            }
+        } else {
+            let weight = self.dequantized_weight(x.device())?.to_dtype(x.dtype())?;
+            let bias = match &self.bias {
+                None => None,
+                Some(bias) => Some(bias.to_dtype(x.dtype())?),
+            };
+            crate::Linear::new(weight, bias).forward(x)
+        }
+    }
+}
@@ -66,6 +66,25 @@ impl LSTMState {
     }
 }
 
+/// The direction in which a recurrent layer processes its input sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    #[default]
+    Forward,
+    /// The sequence is processed from the last step to the first one, the weight names get a
+    /// `_reverse` suffix as in PyTorch.
+    Backward,
+}
+
+impl Direction {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::Forward => "",
+            Self::Backward => "_reverse",
+        }
+    }
+}
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
 pub struct LSTMConfig {
@@ -74,6 +93,7 @@ pub struct LSTMConfig {
     pub b_ih_init: Option<super::Init>,
     pub b_hh_init: Option<super::Init>,
     pub layer_idx: usize,
+    pub direction: Direction,
 }
 
 impl Default for LSTMConfig {
@@ -84,6 +104,7 @@ impl Default for LSTMConfig {
             b_ih_init: Some(super::Init::Const(0.)),
             b_hh_init: Some(super::Init::Const(0.)),
             layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -96,8 +117,21 @@ impl LSTMConfig {
             b_ih_init: None,
             b_hh_init: None,
             layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
+
+    /// Sets the index of the layer used in the weight names, e.g. `weight_ih_l{layer_idx}`.
+    pub fn with_layer_idx(mut self, layer_idx: usize) -> Self {
+        self.layer_idx = layer_idx;
+        self
+    }
+
+    /// Sets the direction in which the input sequence is processed.
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
 }
 
 /// A Long Short-Term Memory (LSTM) layer.
@@ -124,26 +158,31 @@ pub fn lstm(
     vb: crate::VarBuilder,
 ) -> Result<LSTM> {
     let layer_idx = config.layer_idx;
+    let suffix = config.direction.suffix();
     let w_ih = vb.get_with_hints(
         (4 * hidden_dim, in_dim),
-        &format!("weight_ih_l{layer_idx}"), // Only a single layer is supported.
+        &format!("weight_ih_l{layer_idx}{suffix}"),
         config.w_ih_init,
     )?;
     let w_hh = vb.get_with_hints(
         (4 * hidden_dim, hidden_dim),
-        &format!("weight_hh_l{layer_idx}"), // Only a single layer is supported.
+        &format!("weight_hh_l{layer_idx}{suffix}"),
         config.w_hh_init,
     )?;
     let b_ih = match config.b_ih_init {
-        Some(init) => {
-            Some(vb.get_with_hints(4 * hidden_dim, &format!("bias_ih_l{layer_idx}"), init)?)
-        }
+        Some(init) => Some(vb.get_with_hints(
+            4 * hidden_dim,
+            &format!("bias_ih_l{layer_idx}{suffix}"),
+            init,
+        )?),
         None => None,
     };
     let b_hh = match config.b_hh_init {
-        Some(init) => {
-            Some(vb.get_with_hints(4 * hidden_dim, &format!("bias_hh_l{layer_idx}"), init)?)
-        }
+        Some(init) => Some(vb.get_with_hints(
+            4 * hidden_dim,
+            &format!("bias_hh_l{layer_idx}{suffix}"),
+            init,
+        )?),
         None => None,
     };
     Ok(LSTM {
@@ -222,6 +261,8 @@ pub struct GRUConfig {
     pub w_hh_init: super::Init,
     pub b_ih_init: Option<super::Init>,
     pub b_hh_init: Option<super::Init>,
+    pub layer_idx: usize,
+    pub direction: Direction,
 }
 
 impl Default for GRUConfig {
@@ -231,6 +272,8 @@ impl Default for GRUConfig {
             w_hh_init: super::init::DEFAULT_KAIMING_UNIFORM,
             b_ih_init: Some(super::Init::Const(0.)),
             b_hh_init: Some(super::Init::Const(0.)),
+            layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
 }
@@ -242,8 +285,22 @@ impl GRUConfig {
             w_hh_init: super::init::DEFAULT_KAIMING_UNIFORM,
             b_ih_init: None,
             b_hh_init: None,
+            layer_idx: 0,
+            direction: Direction::Forward,
         }
     }
+
+    /// Sets the index of the layer used in the weight names, e.g. `weight_ih_l{layer_idx}`.
+    pub fn with_layer_idx(mut self, layer_idx: usize) -> Self {
+        self.layer_idx = layer_idx;
+        self
+    }
+
+    /// Sets the direction in which the input sequence is processed.
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
 }
 
 /// A Gated Recurrent Unit (GRU) layer.
@@ -269,22 +326,32 @@ pub fn gru(
     config: GRUConfig,
     vb: crate::VarBuilder,
 ) -> Result<GRU> {
+    let layer_idx = config.layer_idx;
+    let suffix = config.direction.suffix();
     let w_ih = vb.get_with_hints(
         (3 * hidden_dim, in_dim),
-        "weight_ih_l0", // Only a single layer is supported.
+        &format!("weight_ih_l{layer_idx}{suffix}"),
         config.w_ih_init,
     )?;
     let w_hh = vb.get_with_hints(
         (3 * hidden_dim, hidden_dim),
-        "weight_hh_l0", // Only a single layer is supported.
+        &format!("weight_hh_l{layer_idx}{suffix}"),
         config.w_hh_init,
     )?;
     let b_ih = match config.b_ih_init {
-        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, "bias_ih_l0", init)?),
+        Some(init) => Some(vb.get_with_hints(
+            3 * hidden_dim,
+            &format!("bias_ih_l{layer_idx}{suffix}"),
+            init,
+        )?),
         None => None,
     };
     let b_hh = match config.b_hh_init {
-        Some(init) => Some(vb.get_with_hints(3 * hidden_dim, "bias_hh_l0", init)?),
+        Some(init) => Some(vb.get_with_hints(
+            3 * hidden_dim,
+            &format!("bias_hh_l{layer_idx}{suffix}"),
+            init,
+        )?),
         None => None,
     };
     Ok(GRU {
@@ -334,3 +401,150 @@ impl RNN for GRU {
         Tensor::cat(&states, 1)
     }
 }
+
+// Runs the layers one after the other, each layer contains one rnn per direction. The outputs
+// of the directions are concatenated on the feature dimension and fed to the next layer.
+fn stacked_forward<M: RNN>(
+    layers: &[Vec<M>],
+    input: &Tensor,
+    h: impl Fn(&M::State) -> &Tensor,
+) -> Result<(Tensor, Vec<M::State>)> {
+    let mut xs = input.clone();
+    let mut final_states = Vec::with_capacity(layers.len() * 2);
+    for layer in layers.iter() {
+        let mut outputs = Vec::with_capacity(layer.len());
+        for (dir_idx, rnn) in layer.iter().enumerate() {
+            let reverse = dir_idx == 1;
+            let input = if reverse { xs.flip(&[1])? } else { xs.clone() };
+            let states = rnn.seq(&input)?;
+            let mut hs = states.iter().map(|s| h(s).clone()).collect::<Vec<_>>();
+            if reverse {
+                hs.reverse()
+            }
+            outputs.push(Tensor::stack(&hs, 1)?);
+            match states.into_iter().last() {
+                None => candle::bail!("stacked rnn requires a non-empty sequence"),
+                Some(state) => final_states.push(state),
+            }
+        }
+        xs = Tensor::cat(&outputs, 2)?;
+    }
+    Ok((xs, final_states))
+}
+
+/// Multiple LSTM layers stacked on top of each other, optionally bidirectional.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug)]
+pub struct StackedLSTM {
+    layers: Vec<Vec<LSTM>>,
+}
+
+impl StackedLSTM {
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_bidirectional(&self) -> bool {
+        self.layers.iter().any(|l| l.len() == 2)
+    }
+
+    /// Applies the layers to an input of shape `(batch_size, seq_len, features)`.
+    ///
+    /// This returns the outputs of the last layer, of shape
+    /// `(batch_size, seq_len, num_directions * hidden_dim)`, the forward and backward hidden
+    /// states being concatenated. The final states of each layer and direction are also returned,
+    /// ordered as `layer_idx * num_directions + direction`, for the backward direction this is the
+    /// state after processing the first step of the sequence.
+    pub fn forward(&self, input: &Tensor) -> Result<(Tensor, Vec<LSTMState>)> {
+        stacked_forward(&self.layers, input, |s| &s.h)
+    }
+}
+
+/// Creates a stacked LSTM with `num_layers` layers, using the PyTorch weight names.
+pub fn stacked_lstm(
+    in_dim: usize,
+    hidden_dim: usize,
+    num_layers: usize,
+    bidirectional: bool,
+    config: LSTMConfig,
+    vb: crate::VarBuilder,
+) -> Result<StackedLSTM> {
+    let directions: &[Direction] = if bidirectional {
+        &[Direction::Forward, Direction::Backward]
+    } else {
+        &[Direction::Forward]
+    };
+    let mut layers = Vec::with_capacity(num_layers);
+    for layer_idx in 0..num_layers {
+        let in_dim = if layer_idx == 0 {
+            in_dim
+        } else {
+            hidden_dim * directions.len()
+        };
+        let layer = directions
+            .iter()
+            .map(|&direction| {
+                let config = config.with_layer_idx(layer_idx).with_direction(direction);
+                lstm(in_dim, hidden_dim, config, vb.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        layers.push(layer)
+    }
+    Ok(StackedLSTM { layers })
+}
+
+/// Multiple GRU layers stacked on top of each other, optionally bidirectional.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug)]
+pub struct StackedGRU {
+    layers: Vec<Vec<GRU>>,
+}
+
+impl StackedGRU {
+    pub fn num_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_bidirectional(&self) -> bool {
+        self.layers.iter().any(|l| l.len() == 2)
+    }
+
+    /// Applies the layers to an input of shape `(batch_size, seq_len, features)`, see
+    /// `StackedLSTM::forward` for the details on the returned values.
+    pub fn forward(&self, input: &Tensor) -> Result<(Tensor, Vec<GRUState>)> {
+        stacked_forward(&self.layers, input, |s| &s.h)
+    }
+}
+
+/// Creates a stacked GRU with `num_layers` layers, using the PyTorch weight names.
+pub fn stacked_gru(
+    in_dim: usize,
+    hidden_dim: usize,
+    num_layers: usize,
+    bidirectional: bool,
+    config: GRUConfig,
+    vb: crate::VarBuilder,
+) -> Result<StackedGRU> {
+    let directions: &[Direction] = if bidirectional {
+        &[Direction::Forward, Direction::Backward]
+    } else {
+        &[Direction::Forward]
+    };
+    let mut layers = Vec::with_capacity(num_layers);
+    for layer_idx in 0..num_layers {
+        let in_dim = if layer_idx == 0 {
+            in_dim
+        } else {
+            hidden_dim * directions.len()
+        };
+        let layer = directions
+            .iter()
+            .map(|&direction| {
+                let config = config.with_layer_idx(layer_idx).with_direction(direction);
+                gru(in_dim, hidden_dim, config, vb.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        layers.push(layer)
+    }
+    Ok(StackedGRU { layers })
+}
@@ -201,7 +201,7 @@ impl candle::CustomOp3 for RotaryEmbI {
 pub fn rope_i(xs: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
     let (_b_sz, _n_head, seq_len, n_embd) = xs.dims4()?;
     let (cos_seq_len, cos_n_embd) = cos.dims2()?;
-    let (sin_seq_len, sin_n_embd) = cos.dims2()?;
+    let (sin_seq_len, sin_n_embd) = sin.dims2()?;
     if cos_n_embd * 2 != n_embd
         || sin_n_embd * 2 != n_embd
         || seq_len > cos_seq_len
@@ -728,3 +728,585 @@ pub fn rope_thd(xs: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
     }
     xs.apply_op3_no_bwd(cos, sin, &RotaryEmbThd)
 }
+
+/// Rotary embeddings applied to both the queries and the keys in a single pass, the output is a
+/// flat buffer holding the rotated queries followed by the rotated keys.
+#[derive(Debug, Clone)]
+struct RotaryEmbQk {
+    cos: Tensor,
+    sin: Tensor,
+    interleaved: bool,
+}
+
+impl candle::CustomOp2 for RotaryEmbQk {
+    fn name(&self) -> &'static str {
+        "rotary-emb-qk"
+    }
+
+    fn cpu_fwd(
+        &self,
+        s1: &CpuStorage,
+        l1: &Layout,
+        s2: &CpuStorage,
+        l2: &Layout,
+    ) -> Result<(CpuStorage, Shape)> {
+        fn rotate<T: candle::WithDType + num_traits::Float>(
+            src: &[T],
+            dst: &mut [T],
+            cos: &[T],
+            sin: &[T],
+            (t, d): (usize, usize),
+            interleaved: bool,
+        ) {
+            src.par_chunks(t * d)
+                .zip(dst.par_chunks_mut(t * d))
+                .for_each(|(src, dst)| {
+                    for i_cs in 0..t * d / 2 {
+                        let (i1, i2) = if interleaved {
+                            (2 * i_cs, 2 * i_cs + 1)
+                        } else {
+                            let (i_t, i_d) = (i_cs / (d / 2), i_cs % (d / 2));
+                            (i_t * d + i_d, i_t * d + i_d + d / 2)
+                        };
+                        dst[i1] = src[i1] * cos[i_cs] - src[i2] * sin[i_cs];
+                        dst[i2] = src[i1] * sin[i_cs] + src[i2] * cos[i_cs];
+                    }
+                })
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn inner<T: candle::WithDType + num_traits::Float>(
+            q: &[T],
+            l_q: &Layout,
+            k: &[T],
+            l_k: &Layout,
+            cos: &[T],
+            l_cos: &Layout,
+            sin: &[T],
+            l_sin: &Layout,
+            interleaved: bool,
+        ) -> Result<(CpuStorage, Shape)> {
+            let q = match l_q.contiguous_offsets() {
+                None => candle::bail!("input q has to be contiguous"),
+                Some((o1, o2)) => &q[o1..o2],
+            };
+            let k = match l_k.contiguous_offsets() {
+                None => candle::bail!("input k has to be contiguous"),
+                Some((o1, o2)) => &k[o1..o2],
+            };
+            let cos = match l_cos.contiguous_offsets() {
+                None => candle::bail!("input cos has to be contiguous"),
+                Some((o1, o2)) => &cos[o1..o2],
+            };
+            let sin = match l_sin.contiguous_offsets() {
+                None => candle::bail!("input sin has to be contiguous"),
+                Some((o1, o2)) => &sin[o1..o2],
+            };
+            let (_, _, t, d) = l_q.shape().dims4()?;
+            let mut dst = vec![T::zero(); q.len() + k.len()];
+            let (dst_q, dst_k) = dst.split_at_mut(q.len());
+            rotate(q, dst_q, cos, sin, (t, d), interleaved);
+            rotate(k, dst_k, cos, sin, (t, d), interleaved);
+            let el_count = dst.len();
+            let storage = candle::WithDType::to_cpu_storage_owned(dst);
+            Ok((storage, el_count.into()))
+        }
+
+        use candle::backend::BackendStorage;
+        use CpuStorage::{BF16, F16, F32, F64};
+        let (cos, l_cos) = self.cos.storage_and_layout();
+        let (sin, l_sin) = self.sin.storage_and_layout();
+        let (cos, sin) = match (&*cos, &*sin) {
+            (candle::Storage::Cpu(cos), candle::Storage::Cpu(sin)) => (cos, sin),
+            _ => candle::bail!("cos and sin must be on the cpu"),
+        };
+        let il = self.interleaved;
+        match (s1, s2, cos, sin) {
+            (BF16(q), BF16(k), BF16(c), BF16(s)) => inner(q, l1, k, l2, c, l_cos, s, l_sin, il),
+            (F16(q), F16(k), F16(c), F16(s)) => inner(q, l1, k, l2, c, l_cos, s, l_sin, il),
+            (F32(q), F32(k), F32(c), F32(s)) => inner(q, l1, k, l2, c, l_cos, s, l_sin, il),
+            (F64(q), F64(k), F64(c), F64(s)) => inner(q, l1, k, l2, c, l_cos, s, l_sin, il),
+            _ => candle::bail!(
+                "unsupported dtype for rope {:?} {:?} {:?} {:?}",
+                s1.dtype(),
+                s2.dtype(),
+                cos.dtype(),
+                sin.dtype()
+            ),
+        }
+    }
+
+    #[cfg(feature = "cuda")]
+    fn cuda_fwd(
+        &self,
+        s1: &candle::CudaStorage,
+        l1: &Layout,
+        s2: &candle::CudaStorage,
+        l2: &Layout,
+    ) -> Result<(candle::CudaStorage, Shape)> {
+        use candle::backend::BackendStorage;
+        use candle::cuda_backend::cudarc::driver::{
+            CudaView, DeviceRepr, LaunchAsync, LaunchConfig,
+        };
+        use candle::cuda_backend::{kernel_name, kernels, CudaDType, WrapErr};
+        use candle::{CudaStorage, WithDType};
+
+        fn slice<'a, T: CudaDType>(
+            xs: &'a CudaStorage,
+            l: &Layout,
+            name: &str,
+        ) -> Result<CudaView<'a, T>> {
+            let xs = xs.as_cuda_slice::<T>()?;
+            match l.contiguous_offsets() {
+                None => candle::bail!("{name} input has to be contiguous"),
+                Some((o1, o2)) => Ok(xs.slice(o1..o2)),
+            }
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn inner<T: CudaDType + DeviceRepr + WithDType>(
+            q: &CudaStorage,
+            l_q: &Layout,
+            k: &CudaStorage,
+            l_k: &Layout,
+            cos: &CudaStorage,
+            l_cos: &Layout,
+            sin: &CudaStorage,
+            l_sin: &Layout,
+            interleaved: bool,
+        ) -> Result<CudaStorage> {
+            let dev = q.device();
+            let (b, h_q, t, d) = l_q.shape().dims4()?;
+            let h_k = l_k.dims()[1];
+            let el = l_q.shape().elem_count() + l_k.shape().elem_count();
+            let (q, k) = (slice::<T>(q, l_q, "q")?, slice::<T>(k, l_k, "k")?);
+            let cos = slice::<T>(cos, l_cos, "cos")?;
+            let sin = slice::<T>(sin, l_sin, "sin")?;
+            let cfg = LaunchConfig::for_num_elems((el / 2) as u32);
+            let func = dev.get_or_load_func(&kernel_name::<T>("rope_qk"), kernels::REDUCE)?;
+            // SAFETY: Set later by running the kernel.
+            let dst = unsafe { dev.alloc::<T>(el) }.w()?;
+            let params = (
+                &q,
+                &k,
+                &cos,
+                &sin,
+                &dst,
+                (b * h_q) as u32,
+                (b * h_k) as u32,
+                (t * d) as u32,
+                d as u32,
+                interleaved as u32,
+            );
+            // SAFETY: ffi.
+            unsafe { func.launch(cfg, params) }.w()?;
+            Ok(T::wrap_cuda_slice(dst, dev.clone()))
+        }
+
+        let (cos, l_cos) = self.cos.storage_and_layout();
+        let (sin, l_sin) = self.sin.storage_and_layout();
+        let (cos, sin) = match (&*cos, &*sin) {
+            (candle::Storage::Cuda(cos), candle::Storage::Cuda(sin)) => (cos, sin),
+            _ => candle::bail!("cos and sin must be on a cuda device"),
+        };
+        let il = self.interleaved;
+        let dst = match s1.dtype() {
+            candle::DType::BF16 => inner::<half::bf16>(s1, l1, s2, l2, cos, l_cos, sin, l_sin, il)?,
+            candle::DType::F16 => inner::<half::f16>(s1, l1, s2, l2, cos, l_cos, sin, l_sin, il)?,
+            candle::DType::F32 => inner::<f32>(s1, l1, s2, l2, cos, l_cos, sin, l_sin, il)?,
+            candle::DType::F64 => inner::<f64>(s1, l1, s2, l2, cos, l_cos, sin, l_sin, il)?,
+            dtype => candle::bail!("unsupported dtype for rope {dtype:?}"),
+        };
+        let el_count = l1.shape().elem_count() + l2.shape().elem_count();
+        Ok((dst, el_count.into()))
+    }
+}
+
+/// Applies the rotary embeddings to the queries and keys, `q` and `k` use the
+/// `(batch, heads, seq_len, head_dim)` layout and can have a different number of heads.
+///
+/// When `interleaved` is set, the rotation is applied to consecutive pairs of values as in
+/// [`rope_i`], otherwise the first half of the head dimension is rotated with the second half
+/// as in [`rope`]. Non-contiguous inputs, e.g. the output of a transpose, are copied first.
+///
+/// On cuda both tensors are rotated by a single fused kernel launch and the results are views
+/// on a shared buffer. On other accelerators, [`rope`] or [`rope_i`] are applied to each tensor
+/// separately.
+pub fn apply_rotary_emb_qk(
+    q: &Tensor,
+    k: &Tensor,
+    cos: &Tensor,
+    sin: &Tensor,
+    interleaved: bool,
+) -> Result<(Tensor, Tensor)> {
+    let (q_b_sz, _, q_seq_len, q_n_embd) = q.dims4()?;
+    let (k_b_sz, _, k_seq_len, k_n_embd) = k.dims4()?;
+    if q_b_sz != k_b_sz || q_seq_len != k_seq_len || q_n_embd != k_n_embd {
+        candle::bail!(
+            "inconsistent q and k shapes in rope {:?} {:?}",
+            q.shape(),
+            k.shape()
+        )
+    }
+    if !q.device().is_cpu() && !q.device().is_cuda() {
+        let rope_fn = if interleaved { rope_i } else { rope };
+        let q = rope_fn(&q.contiguous()?, cos, sin)?;
+        let k = rope_fn(&k.contiguous()?, cos, sin)?;
+        return Ok((q, k));
+    }
+    let (cos_seq_len, cos_n_embd) = cos.dims2()?;
+    let (sin_seq_len, sin_n_embd) = sin.dims2()?;
+    if cos_n_embd * 2 != q_n_embd
+        || sin_n_embd * 2 != q_n_embd
+        || q_seq_len > cos_seq_len
+        || q_seq_len > sin_seq_len
+    {
+        candle::bail!(
+            "inconsistent last dim size in rope {:?} {:?} {:?}",
+            q.shape(),
+            cos.shape(),
+            sin.shape()
+        )
+    }
+    if !cos.is_contiguous() {
+        candle::bail!("cos has to be contiguous in rope")
+    }
+    if !sin.is_contiguous() {
+        candle::bail!("sin has to be contiguous in rope")
+    }
+    let op = RotaryEmbQk {
+        cos: cos.clone(),
+        sin: sin.clone(),
+        interleaved,
+    };
+    let qk = q.contiguous()?.apply_op2_no_bwd(&k.contiguous()?, &op)?;
+    let q_el = q.elem_count();
+    let q_rot = qk.narrow(0, 0, q_el)?.reshape(q.shape())?;
+    let k_rot = qk.narrow(0, q_el, k.elem_count())?.reshape(k.shape())?;
+    Ok((q_rot, k_rot))
+}
+
+fn default_beta_fast() -> f64 {
+    32.
+}
+
+fn default_beta_slow() -> f64 {
+    1.
+}
+
+/// Scaling applied to the rotary embedding frequencies in order to extend the context length,
+/// this uses the same format as the `rope_scaling` field of the transformers configs. The scaling
+/// kind is read from either the `rope_type` or the legacy `type` field.
+///
+/// Deserializing a scaling kind that is not supported results in an error, model configs should
+/// use [`deserialize_rope_scaling`] to ignore these instead.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+#[serde(try_from = "RopeScalingConfig")]
+pub enum RopeScaling {
+    /// Position interpolation, the positions are divided by `factor`.
+    Linear { factor: f64 },
+    /// Dynamic NTK scaling, the base is increased when the sequence length exceeds
+    /// `original_max_position_embeddings`.
+    Dynamic {
+        factor: f64,
+        original_max_position_embeddings: usize,
+    },
+    /// YaRN, interpolates the low frequencies, keeps the high frequencies and blends the two
+    /// using a linear ramp between the `beta_fast` and `beta_slow` rotations.
+    Yarn {
+        factor: f64,
+        original_max_position_embeddings: usize,
+        beta_fast: f64,
+        beta_slow: f64,
+        /// Scaling applied to cos and sin, defaults to `0.1 * ln(factor) + 1`.
+        attention_factor: Option<f64>,
+    },
+    /// The Llama 3.1 scaling, the frequencies with a wavelength above
+    /// `original_max_position_embeddings / low_freq_factor` are divided by `factor`, the ones
+    /// with a wavelength below `original_max_position_embeddings / high_freq_factor` are kept
+    /// and the ones in between are smoothly interpolated.
+    Llama3 {
+        factor: f64,
+        low_freq_factor: f64,
+        high_freq_factor: f64,
+        original_max_position_embeddings: usize,
+    },
+}
+
+/// The raw `rope_scaling` field of a transformers config.
+#[derive(Debug, Clone, serde::Deserialize)]
+struct RopeScalingConfig {
+    rope_type: Option<String>,
+    #[serde(rename = "type")]
+    type_: Option<String>,
+    factor: Option<f64>,
+    original_max_position_embeddings: Option<usize>,
+    #[serde(default = "default_beta_fast")]
+    beta_fast: f64,
+    #[serde(default = "default_beta_slow")]
+    beta_slow: f64,
+    attention_factor: Option<f64>,
+    low_freq_factor: Option<f64>,
+    high_freq_factor: Option<f64>,
+}
+
+impl RopeScalingConfig {
+    /// Returns `None` for the scaling kinds that are not supported.
+    fn into_scaling(self) -> std::result::Result<Option<RopeScaling>, String> {
+        let kind = match self.rope_type.or(self.type_) {
+            Some(kind) => kind,
+            None => return Err("missing field `rope_type`".to_string()),
+        };
+        let required = |v: Option<f64>, name: &str| {
+            v.ok_or_else(|| format!("missing field `{name}` for {kind} rope scaling"))
+        };
+        let original_max_position_embeddings = || {
+            self.original_max_position_embeddings.ok_or_else(|| {
+                format!("missing field `original_max_position_embeddings` for {kind} rope scaling")
+            })
+        };
+        let scaling = match kind.as_str() {
+            "linear" => RopeScaling::Linear {
+                factor: required(self.factor, "factor")?,
+            },
+            "dynamic" => RopeScaling::Dynamic {
+                factor: required(self.factor, "factor")?,
+                original_max_position_embeddings: original_max_position_embeddings()?,
+            },
+            "yarn" => RopeScaling::Yarn {
+                factor: required(self.factor, "factor")?,
+                original_max_position_embeddings: original_max_position_embeddings()?,
+                beta_fast: self.beta_fast,
+                beta_slow: self.beta_slow,
+                attention_factor: self.attention_factor,
+            },
+            "llama3" => RopeScaling::Llama3 {
+                factor: required(self.factor, "factor")?,
+                low_freq_factor: required(self.low_freq_factor, "low_freq_factor")?,
+                high_freq_factor: required(self.high_freq_factor, "high_freq_factor")?,
+                original_max_position_embeddings: original_max_position_embeddings()?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(scaling))
+    }
+}
+
+impl TryFrom<RopeScalingConfig> for RopeScaling {
+    type Error = String;
+
+    fn try_from(config: RopeScalingConfig) -> std::result::Result<Self, Self::Error> {
+        let kind = config.rope_type.clone().or(config.type_.clone());
+        match config.into_scaling()? {
+            Some(scaling) => Ok(scaling),
+            None => Err(format!("unsupported rope scaling {kind:?}")),
+        }
+    }
+}
+
+/// Deserializes an optional `rope_scaling` config field, the scaling kinds that are not supported
+/// such as `default` are mapped to `None`, i.e. no scaling is applied.
+///
+/// ```ignore
+/// #[serde(default, deserialize_with = "candle_nn::rotary_emb::deserialize_rope_scaling")]
+/// pub rope_scaling: Option<RopeScaling>,
+/// ```
+pub fn deserialize_rope_scaling<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<RopeScaling>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    match Option::<RopeScalingConfig>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(config) => config.into_scaling().map_err(serde::de::Error::custom),
+    }
+}
+
+impl RopeScaling {
+    /// Returns the inverse frequencies for each pair of dimensions, together with the factor to
+    /// apply to the cos and sin tables. `seq_len` is only used by dynamic NTK scaling.
+    pub fn inv_freq(&self, head_dim: usize, base: f64, seq_len: usize) -> (Vec<f32>, f32) {
+        let freqs = |base: f64| -> Vec<f64> {
+            (0..head_dim)
+                .step_by(2)
+                .map(|i| 1. / base.powf(i as f64 / head_dim as f64))
+                .collect()
+        };
+        let to_f32 = |vs: Vec<f64>| vs.into_iter().map(|v| v as f32).collect();
+        match *self {
+            Self::Linear { factor } => {
+                let inv_freq = freqs(base).into_iter().map(|f| f / factor).collect();
+                (to_f32(inv_freq), 1.)
+            }
+            Self::Dynamic {
+                factor,
+                original_max_position_embeddings: max_pos,
+            } => {
+                let seq_len = seq_len.max(max_pos) as f64;
+                let dim = head_dim as f64;
+                let base = base
+                    * ((factor * seq_len / max_pos as f64) - (factor - 1.)).powf(dim / (dim - 2.));
+                (to_f32(freqs(base)), 1.)
+            }
+            Self::Yarn {
+                factor,
+                original_max_position_embeddings: max_pos,
+                beta_fast,
+                beta_slow,
+                attention_factor,
+            } => {
+                // The dimension at which a given number of rotations happens over max_pos.
+                let correction_dim = |n_rotations: f64| {
+                    head_dim as f64
+                        * (max_pos as f64 / (n_rotations * 2. * std::f64::consts::PI)).ln()
+                        / (2. * base.ln())
+                };
+                let low = correction_dim(beta_fast).floor().max(0.);
+                let high = correction_dim(beta_slow).ceil().min(head_dim as f64 - 1.);
+                let high = if low == high { high + 0.001 } else { high };
+                let inv_freq = freqs(base)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, f)| {
+                        let ramp = ((i as f64 - low) / (high - low)).clamp(0., 1.);
+                        let extrapolation_factor = 1. - ramp;
+                        f / factor * (1. - extrapolation_factor) + f * extrapolation_factor
+                    })
+                    .collect();
+                let attention_factor = match attention_factor {
+                    Some(attention_factor) => attention_factor,
+                    None if factor <= 1. => 1.,
+                    None => 0.1 * factor.ln() + 1.,
+                };
+                (to_f32(inv_freq), attention_factor as f32)
+            }
+            Self::Llama3 {
+                factor,
+                low_freq_factor,
+                high_freq_factor,
+                original_max_position_embeddings: max_pos,
+            } => {
+                let low_freq_wavelen = max_pos as f64 / low_freq_factor;
+                let high_freq_wavelen = max_pos as f64 / high_freq_factor;
+                let inv_freq = freqs(base)
+                    .into_iter()
+                    .map(|f| {
+                        let wavelen = 2. * std::f64::consts::PI / f;
+                        if wavelen < high_freq_wavelen {
+                            f
+                        } else if wavelen > low_freq_wavelen {
+                            f / factor
+                        } else {
+                            let smooth = (max_pos as f64 / wavelen - low_freq_factor)
+                                / (high_freq_factor - low_freq_factor);
+                            (1. - smooth) * f / factor + smooth * f
+                        }
+                    })
+                    .collect();
+                (to_f32(inv_freq), 1.)
+            }
+        }
+    }
+}
+
+/// Returns the cos and sin tables with shape `(max_seq_len, head_dim / 2)` to be used with
+/// [`rope`], [`rope_i`], or [`rope_thd`]. The frequencies are computed in f32 and the tables are
+/// converted to `dtype` at the end.
+///
+/// With dynamic NTK scaling, the frequencies are the ones for a sequence of `max_seq_len`
+/// positions, use [`RopeTables`] to get tables that follow the actual sequence length.
+pub fn rope_cos_sin(
+    head_dim: usize,
+    base: f64,
+    max_seq_len: usize,
+    scaling: Option<&RopeScaling>,
+    dtype: candle::DType,
+    device: &candle::Device,
+) -> Result<(Tensor, Tensor)> {
+    let (inv_freq, attention_factor) = match scaling {
+        Some(scaling) => scaling.inv_freq(head_dim, base, max_seq_len),
+        None => (
+            (0..head_dim)
+                .step_by(2)
+                .map(|i| 1f32 / (base as f32).powf(i as f32 / head_dim as f32))
+                .collect(),
+            1.,
+        ),
+    };
+    let inv_freq_len = inv_freq.len();
+    let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), device)?;
+    let t = Tensor::arange(0u32, max_seq_len as u32, device)?
+        .to_dtype(candle::DType::F32)?
+        .reshape((max_seq_len, 1))?;
+    let freqs = t.matmul(&inv_freq)?;
+    let cos = (freqs.cos()? * attention_factor as f64)?.to_dtype(dtype)?;
+    let sin = (freqs.sin()? * attention_factor as f64)?.to_dtype(dtype)?;
+    Ok((cos, sin))
+}
+
+/// Cos and sin tables precomputed for up to `max_seq_len` positions.
+///
+/// With dynamic NTK scaling, the frequencies depend on the length of the sequence being processed.
+/// As in transformers, the unscaled frequencies are used while the sequence fits in the original
+/// context and the tables are recomputed for the actual sequence length beyond that.
+#[derive(Debug, Clone)]
+pub struct RopeTables {
+    cos: Tensor,
+    sin: Tensor,
+    head_dim: usize,
+    base: f64,
+    scaling: Option<RopeScaling>,
+}
+
+impl RopeTables {
+    pub fn new(
+        head_dim: usize,
+        base: f64,
+        max_seq_len: usize,
+        scaling: Option<RopeScaling>,
+        dtype: candle::DType,
+        device: &candle::Device,
+    ) -> Result<Self> {
+        let table_scaling = match &scaling {
+            Some(RopeScaling::Dynamic { .. }) => None,
+            scaling => scaling.as_ref(),
+        };
+        let (cos, sin) = rope_cos_sin(head_dim, base, max_seq_len, table_scaling, dtype, device)?;
+        Ok(Self {
+            cos,
+            sin,
+            head_dim,
+            base,
+            scaling,
+        })
+    }
+
+    /// Returns the cos and sin tables for the positions `index_pos..index_pos + seq_len`.
+    pub fn narrow(&self, index_pos: usize, seq_len: usize) -> Result<(Tensor, Tensor)> {
+        if let Some(RopeScaling::Dynamic {
+            original_max_position_embeddings,
+            ..
+        }) = self.scaling
+        {
+            let total_len = index_pos + seq_len;
+            if total_len > original_max_position_embeddings {
+                let (cos, sin) = rope_cos_sin(
+                    self.head_dim,
+                    self.base,
+                    total_len,
+                    self.scaling.as_ref(),
+                    self.cos.dtype(),
+                    self.cos.device(),
+                )?;
+                let cos = cos.narrow(0, index_pos, seq_len)?;
+                let sin = sin.narrow(0, index_pos, seq_len)?;
+                return Ok((cos, sin));
+            }
+        }
+        let cos = self.cos.narrow(0, index_pos, seq_len)?;
+        let sin = self.sin.narrow(0, index_pos, seq_len)?;
+        Ok((cos, sin))
+    }
+}
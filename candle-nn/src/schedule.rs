@@ -0,0 +1,198 @@
+//! Learning rate schedules.
+//!
+//! A scheduler maps the current step, starting from 0, to a learning rate. The `apply` method
+//! can be used to update an optimizer before each of its steps.
+//!
+//! ```rust
+//! use candle_nn::schedule::{CosineWithWarmup, LrScheduler};
+//! let sched = CosineWithWarmup::new(1e-3, 1e-5, 10, 100);
+//! assert_eq!(sched.get_lr(0), 0.);
+//! assert_eq!(sched.get_lr(10), 1e-3);
+//! assert_eq!(sched.get_lr(100), 1e-5);
+//! ```
+use std::f64::consts::PI;
+
+use crate::Optimizer;
+
+/// The interface learning rate schedulers should implement.
+pub trait LrScheduler {
+    /// The learning rate to use at `step`.
+    fn get_lr(&self, step: usize) -> f64;
+
+    /// Sets the learning rate of `optimizer` to the one for `step`.
+    fn apply<O: Optimizer>(&self, step: usize, optimizer: &mut O) {
+        optimizer.set_learning_rate(self.get_lr(step))
+    }
+}
+
+// Interpolates from `start` at `pct = 0` to `end` at `pct = 1` following half a cosine.
+fn cosine_interpolate(start: f64, end: f64, pct: f64) -> f64 {
+    end + (start - end) * (1. + (PI * pct.clamp(0., 1.)).cos()) / 2.
+}
+
+/// Linearly increases the learning rate from 0 to `lr` over `warmup_steps` steps and then keeps
+/// it constant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinearWarmup {
+    pub lr: f64,
+    pub warmup_steps: usize,
+}
+
+impl LinearWarmup {
+    pub fn new(lr: f64, warmup_steps: usize) -> Self {
+        Self { lr, warmup_steps }
+    }
+}
+
+impl LrScheduler for LinearWarmup {
+    fn get_lr(&self, step: usize) -> f64 {
+        if step < self.warmup_steps {
+            self.lr * step as f64 / self.warmup_steps as f64
+        } else {
+            self.lr
+        }
+    }
+}
+
+/// Decreases the learning rate from `lr` to `min_lr` over `total_steps` steps following a
+/// cosine, the learning rate stays at `min_lr` afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CosineAnnealing {
+    pub lr: f64,
+    pub min_lr: f64,
+    pub total_steps: usize,
+}
+
+impl CosineAnnealing {
+    pub fn new(lr: f64, min_lr: f64, total_steps: usize) -> Self {
+        Self {
+            lr,
+            min_lr,
+            total_steps,
+        }
+    }
+}
+
+impl LrScheduler for CosineAnnealing {
+    fn get_lr(&self, step: usize) -> f64 {
+        if self.total_steps == 0 {
+            return self.min_lr;
+        }
+        let pct = step as f64 / self.total_steps as f64;
+        cosine_interpolate(self.lr, self.min_lr, pct)
+    }
+}
+
+/// A linear warmup from 0 to `lr` over `warmup_steps` followed by a cosine decay to `min_lr`
+/// that ends at `total_steps`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CosineWithWarmup {
+    pub lr: f64,
+    pub min_lr: f64,
+    pub warmup_steps: usize,
+    pub total_steps: usize,
+}
+
+impl CosineWithWarmup {
+    pub fn new(lr: f64, min_lr: f64, warmup_steps: usize, total_steps: usize) -> Self {
+        Self {
+            lr,
+            min_lr,
+            warmup_steps,
+            total_steps,
+        }
+    }
+}
+
+impl LrScheduler for CosineWithWarmup {
+    fn get_lr(&self, step: usize) -> f64 {
+        if step < self.warmup_steps {
+            return self.lr * step as f64 / self.warmup_steps as f64;
+        }
+        let decay_steps = self.total_steps.saturating_sub(self.warmup_steps);
+        if decay_steps == 0 {
+            return self.min_lr;
+        }
+        let pct = (step - self.warmup_steps) as f64 / decay_steps as f64;
+        cosine_interpolate(self.lr, self.min_lr, pct)
+    }
+}
+
+/// Multiplies the learning rate by `gamma` every `step_size` steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepDecay {
+    pub lr: f64,
+    pub step_size: usize,
+    pub gamma: f64,
+}
+
+impl StepDecay {
+    pub fn new(lr: f64, step_size: usize, gamma: f64) -> Self {
+        Self {
+            lr,
+            step_size,
+            gamma,
+        }
+    }
+}
+
+impl LrScheduler for StepDecay {
+    fn get_lr(&self, step: usize) -> f64 {
+        let n = step / usize::max(self.step_size, 1);
+        self.lr * self.gamma.powi(n as i32)
+    }
+}
+
+/// The one-cycle policy, the learning rate goes from `max_lr / div_factor` up to `max_lr` over
+/// the first `pct_start` fraction of `total_steps`, and then down to
+/// `max_lr / (div_factor * final_div_factor)` at the last step. Both phases use a cosine.
+///
+/// <https://pytorch.org/docs/stable/generated/torch.optim.lr_scheduler.OneCycleLR.html>
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneCycle {
+    pub max_lr: f64,
+    pub total_steps: usize,
+    pub pct_start: f64,
+    pub div_factor: f64,
+    pub final_div_factor: f64,
+}
+
+impl OneCycle {
+    /// Creates a one-cycle schedule using the PyTorch defaults, `pct_start = 0.3`,
+    /// `div_factor = 25` and `final_div_factor = 1e4`.
+    pub fn new(max_lr: f64, total_steps: usize) -> Self {
+        Self {
+            max_lr,
+            total_steps,
+            pct_start: 0.3,
+            div_factor: 25.,
+            final_div_factor: 1e4,
+        }
+    }
+}
+
+impl LrScheduler for OneCycle {
+    fn get_lr(&self, step: usize) -> f64 {
+        let initial_lr = self.max_lr / self.div_factor;
+        let min_lr = initial_lr / self.final_div_factor;
+        let warmup_end = self.pct_start * self.total_steps as f64 - 1.;
+        let end = self.total_steps as f64 - 1.;
+        let step = step as f64;
+        if step <= warmup_end {
+            // With a warmup phase of length zero, the first step is already at the peak.
+            let pct = if warmup_end > 0. {
+                step / warmup_end
+            } else {
+                1.
+            };
+            cosine_interpolate(initial_lr, self.max_lr, pct)
+        } else {
+            let pct = if end > warmup_end {
+                (step - warmup_end) / (end - warmup_end)
+            } else {
+                1.
+            };
+            cosine_interpolate(self.max_lr, min_lr, pct)
+        }
+    }
+}
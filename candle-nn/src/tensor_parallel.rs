@@ -0,0 +1,420 @@
+//! Tensor parallel linear layers.
+//!
+//! The weights of a linear layer are split between multiple ranks, each rank usually running on
+//! its own device. A [`TensorParallelColumnLinear`] splits the output features, each rank
+//! computing a slice of the output, and a [`TensorParallelRowLinear`] splits the input features,
+//! each rank computing partial sums that are all-reduced. Chaining the two, as in the MLP of a
+//! transformer block, only requires a single collective operation.
+//!
+//! ```rust
+//! use candle::{Device, Module, Tensor};
+//! use candle_nn::tensor_parallel::{Comm, LocalComm};
+//! use candle_nn::{Linear, TensorParallelColumnLinear, TensorParallelRowLinear};
+//! use std::sync::Arc;
+//! # fn main() -> candle::Result<()> {
+//! let dev = Device::Cpu;
+//! let w1 = Tensor::randn(0f32, 1., (8, 4), &dev)?;
+//! let w2 = Tensor::randn(0f32, 1., (4, 8), &dev)?;
+//! let xs = Tensor::randn(0f32, 1., (3, 4), &dev)?;
+//! let (l1, l2) = (Linear::new(w1, None), Linear::new(w2, None));
+//! let expected = l2.forward(&l1.forward(&xs)?)?;
+//!
+//! // Each rank runs in its own thread, the collective operations wait for all of them.
+//! let handles: Vec<_> = LocalComm::new(2)
+//!     .into_iter()
+//!     .map(|comm| {
+//!         let (l1, l2, xs) = (l1.clone(), l2.clone(), xs.clone());
+//!         std::thread::spawn(move || -> candle::Result<Tensor> {
+//!             let comm: Arc<dyn Comm + Send + Sync> = Arc::new(comm);
+//!             let l1 = TensorParallelColumnLinear::from_dense(&l1, comm.clone())?;
+//!             let l2 = TensorParallelRowLinear::from_dense(&l2, comm)?;
+//!             l2.forward(&l1.forward(&xs)?)
+//!         })
+//!     })
+//!     .collect();
+//! for handle in handles {
+//!     let ys = handle.join().unwrap()?;
+//!     let diff = (ys - &expected)?.abs()?.flatten_all()?.max(0)?.to_vec0::<f32>()?;
+//!     assert!(diff < 1e-4);
+//! }
+//! # Ok(()) }
+//! ```
+use crate::var_builder::{Shard, ShardedVarBuilder};
+use crate::Linear;
+use candle::{Module, Result, Tensor};
+use std::sync::{Arc, Barrier, Mutex};
+
+/// The collective operations between the ranks of a process group. All the ranks have to call
+/// the same operations in the same order, each call blocks until all the ranks have reached it.
+///
+/// The layers hold their process group as an `Arc<dyn Comm + Send + Sync>` so that they can be
+/// shared between threads, e.g. when each rank is driven by a thread of a single process.
+pub trait Comm {
+    fn rank(&self) -> usize;
+
+    fn world_size(&self) -> usize;
+
+    /// Returns the sum of `xs` over all the ranks, the shapes must be the same on all ranks.
+    fn all_reduce(&self, xs: &Tensor) -> Result<Tensor>;
+
+    /// Concatenates `xs` from all the ranks along `dim`, in rank order. The shapes must be the
+    /// same on all ranks.
+    fn all_gather(&self, xs: &Tensor, dim: usize) -> Result<Tensor>;
+}
+
+struct LocalCommState {
+    barrier: Barrier,
+    slots: Mutex<Vec<Option<Tensor>>>,
+}
+
+/// A process group where each rank runs in a thread of the current process, the tensors can be
+/// on any device and the results are moved to the device of the calling rank.
+#[derive(Clone)]
+pub struct LocalComm {
+    rank: usize,
+    world_size: usize,
+    state: Arc<LocalCommState>,
+}
+
+impl LocalComm {
+    /// Returns the handles for all the ranks of a new process group, the handle at index `i` has
+    /// rank `i`.
+    pub fn new(world_size: usize) -> Vec<Self> {
+        let state = Arc::new(LocalCommState {
+            barrier: Barrier::new(world_size),
+            slots: Mutex::new(vec![None; world_size]),
+        });
+        (0..world_size)
+            .map(|rank| Self {
+                rank,
+                world_size,
+                state: state.clone(),
+            })
+            .collect()
+    }
+
+    fn gather(&self, xs: &Tensor) -> Result<Vec<Tensor>> {
+        self.state.slots.lock().unwrap()[self.rank] = Some(xs.clone());
+        self.state.barrier.wait();
+        let all = self.state.slots.lock().unwrap().clone();
+        // Wait for all the ranks to have read the slots before they get overwritten by the next
+        // collective operation.
+        self.state.barrier.wait();
+        all.into_iter()
+            .map(|t| match t {
+                None => candle::bail!("missing tensor in collective operation"),
+                Some(t) => t.to_device(xs.device()),
+            })
+            .collect()
+    }
+}
+
+impl Comm for LocalComm {
+    fn rank(&self) -> usize {
+        self.rank
+    }
+
+    fn world_size(&self) -> usize {
+        self.world_size
+    }
+
+    fn all_reduce(&self, xs: &Tensor) -> Result<Tensor> {
+        let all = self.gather(xs)?;
+        let mut sum = all[0].clone();
+        for t in all[1..].iter() {
+            sum = (sum + t)?
+        }
+        Ok(sum)
+    }
+
+    fn all_gather(&self, xs: &Tensor, dim: usize) -> Result<Tensor> {
+        let all = self.gather(xs)?;
+        Tensor::cat(&all, dim)
+    }
+}
+
+fn shard_of(xs: &Tensor, dim: usize, comm: &dyn Comm) -> Result<Tensor> {
+    let (rank, world_size) = (comm.rank(), comm.world_size());
+    let size = xs.dim(dim)?;
+    if size % world_size != 0 {
+        return Err(candle::Error::ShapeMismatchSplit {
+            shape: xs.shape().clone(),
+            dim,
+            n_parts: world_size,
+        }
+        .bt());
+    }
+    let block_size = size / world_size;
+    xs.narrow(dim, rank * block_size, block_size)?.contiguous()
+}
+
+fn shard(dim: usize, comm: &dyn Comm) -> Shard {
+    Shard {
+        dim,
+        rank: comm.rank(),
+        world_size: comm.world_size(),
+    }
+}
+
+/// A linear layer where each rank holds a slice of the output features, the output of
+/// [`Module::forward`] only contains these features. This is usually followed by a
+/// [`TensorParallelRowLinear`], otherwise [`Self::forward_gathered`] returns all the features.
+#[derive(Clone)]
+pub struct TensorParallelColumnLinear {
+    linear: Linear,
+    comm: Arc<dyn Comm + Send + Sync>,
+}
+
+impl TensorParallelColumnLinear {
+    /// Creates the layer from the shard of this rank, the weight has shape
+    /// `(out_dim / world_size, in_dim)`.
+    pub fn new(linear: Linear, comm: Arc<dyn Comm + Send + Sync>) -> Self {
+        Self { linear, comm }
+    }
+
+    /// Shards a dense layer, only the output features of this rank are kept.
+    pub fn from_dense(linear: &Linear, comm: Arc<dyn Comm + Send + Sync>) -> Result<Self> {
+        let weight = shard_of(linear.weight(), 0, comm.as_ref())?;
+        let bias = match linear.bias() {
+            None => None,
+            Some(bias) => Some(shard_of(bias, 0, comm.as_ref())?),
+        };
+        Ok(Self::new(Linear::new(weight, bias), comm))
+    }
+
+    /// Loads the shard of this rank from weights that use the same names as [`crate::linear`].
+    pub fn load(
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        vb: ShardedVarBuilder,
+        comm: Arc<dyn Comm + Send + Sync>,
+    ) -> Result<Self> {
+        let hints = shard(0, comm.as_ref());
+        let weight = vb.get_with_hints((out_dim, in_dim), "weight", hints)?;
+        let bias = if bias {
+            Some(vb.get_with_hints(out_dim, "bias", hints)?)
+        } else {
+            None
+        };
+        Ok(Self::new(Linear::new(weight, bias), comm))
+    }
+
+    /// Returns all the output features by gathering the slices of all the ranks.
+    pub fn forward_gathered(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.forward(xs)?;
+        let last_dim = ys.rank() - 1;
+        self.comm.all_gather(&ys, last_dim)
+    }
+
+    pub fn linear(&self) -> &Linear {
+        &self.linear
+    }
+}
+
+impl Module for TensorParallelColumnLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        self.linear.forward(xs)
+    }
+}
+
+/// A linear layer where each rank holds a slice of the input features, the input is expected to
+/// only contain these features, e.g. the output of a [`TensorParallelColumnLinear`]. The partial
+/// results are all-reduced and the bias is added once.
+#[derive(Clone)]
+pub struct TensorParallelRowLinear {
+    linear: Linear,
+    bias: Option<Tensor>,
+    comm: Arc<dyn Comm + Send + Sync>,
+}
+
+impl TensorParallelRowLinear {
+    /// Creates the layer from the shard of this rank, the weight has shape
+    /// `(out_dim, in_dim / world_size)` and the bias, if any, is not sharded.
+    pub fn new(weight: Tensor, bias: Option<Tensor>, comm: Arc<dyn Comm + Send + Sync>) -> Self {
+        Self {
+            linear: Linear::new(weight, None),
+            bias,
+            comm,
+        }
+    }
+
+    /// Shards a dense layer, only the input features of this rank are kept.
+    pub fn from_dense(linear: &Linear, comm: Arc<dyn Comm + Send + Sync>) -> Result<Self> {
+        let weight = shard_of(linear.weight(), 1, comm.as_ref())?;
+        Ok(Self::new(weight, linear.bias().cloned(), comm))
+    }
+
+    /// Loads the shard of this rank from weights that use the same names as [`crate::linear`].
+    pub fn load(
+        in_dim: usize,
+        out_dim: usize,
+        bias: bool,
+        vb: ShardedVarBuilder,
+        comm: Arc<dyn Comm + Send + Sync>,
+    ) -> Result<Self> {
+        let hints = shard(1, comm.as_ref());
+        let weight = vb.get_with_hints((out_dim, in_dim), "weight", hints)?;
+        let bias = if bias {
+            Some(vb.get(out_dim, "bias")?)
+        } else {
+            None
+        };
+        Ok(Self::new(weight, bias, comm))
+    }
+}
+
+impl Module for TensorParallelRowLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.linear.forward(xs)?;
+        let ys = self.comm.all_reduce(&ys)?;
+        match &self.bias {
+            None => Ok(ys),
+            Some(bias) => ys.broadcast_add(bias),
+        }
+    }
+}
+
+#[cfg(feature = "nccl")]
+mod nccl {
+    use candle::{CpuStorage, Layout, Result, Shape, Tensor};
+    use cudarc::nccl::safe::Comm;
+    use std::sync::{Arc, Mutex};
+
+    // The communicator holds raw pointers so it is neither Send nor Sync.
+    struct SendComm(Comm);
+
+    // SAFETY: the communicator is only accessed through a mutex so a single thread uses it at a
+    // time, and the collective operations do not depend on the calling thread.
+    unsafe impl Send for SendComm {}
+
+    /// A process group using NCCL, each rank uses its own cuda device.
+    pub struct NcclComm {
+        comm: Arc<Mutex<SendComm>>,
+        rank: usize,
+        world_size: usize,
+    }
+
+    impl NcclComm {
+        pub fn new(comm: Comm) -> Self {
+            let (rank, world_size) = (comm.rank(), comm.world_size());
+            Self {
+                comm: Arc::new(Mutex::new(SendComm(comm))),
+                rank,
+                world_size,
+            }
+        }
+    }
+
+    struct NcclOp {
+        comm: Arc<Mutex<SendComm>>,
+        world_size: usize,
+        gather: bool,
+    }
+
+    impl candle::CustomOp1 for NcclOp {
+        fn name(&self) -> &'static str {
+            if self.gather {
+                "nccl-all-gather"
+            } else {
+                "nccl-all-reduce"
+            }
+        }
+
+        fn cpu_fwd(&self, _s: &CpuStorage, _l: &Layout) -> Result<(CpuStorage, Shape)> {
+            candle::bail!("nccl collective operations are never used on cpu")
+        }
+
+        fn cuda_fwd(
+            &self,
+            s: &candle::CudaStorage,
+            l: &Layout,
+        ) -> Result<(candle::CudaStorage, Shape)> {
+            use candle::backend::BackendStorage;
+            use candle::cuda_backend::{CudaDType, WrapErr};
+            use candle::{CudaStorage, DType};
+            use cudarc::driver::{DeviceRepr, DeviceSlice};
+            use cudarc::nccl::safe::ReduceOp;
+
+            fn inner<T: CudaDType + DeviceRepr + cudarc::nccl::NcclType>(
+                op: &NcclOp,
+                s: &CudaStorage,
+                l: &Layout,
+            ) -> Result<CudaStorage> {
+                let dev = s.device().clone();
+                let src = s.as_cuda_slice::<T>()?;
+                let src = match l.contiguous_offsets() {
+                    Some((0, len)) if len == src.len() => src,
+                    Some(_) | None => candle::bail!("input has to be contiguous"),
+                };
+                // A poisoned lock only means that another thread panicked during an operation.
+                let comm = op.comm.lock().unwrap_or_else(|e| e.into_inner());
+                let comm = &comm.0;
+                let dst = if op.gather {
+                    let el = l.shape().elem_count() * op.world_size;
+                    let mut dst = unsafe { dev.alloc::<T>(el) }.w()?;
+                    comm.all_gather(src, &mut dst)
+                        .map_err(candle::Error::debug)?;
+                    dst
+                } else {
+                    let mut dst = unsafe { dev.alloc::<T>(l.shape().elem_count()) }.w()?;
+                    comm.all_reduce(src, &mut dst, &ReduceOp::Sum)
+                        .map_err(candle::Error::debug)?;
+                    dst
+                };
+                Ok(CudaStorage::wrap_cuda_slice(dst, dev))
+            }
+
+            let dst = match s.dtype() {
+                DType::BF16 => inner::<half::bf16>(self, s, l)?,
+                DType::F16 => inner::<half::f16>(self, s, l)?,
+                DType::F32 => inner::<f32>(self, s, l)?,
+                dtype => candle::bail!("unsupported dtype {dtype:?} for {}", self.name()),
+            };
+            let shape = if self.gather {
+                let mut dims = vec![self.world_size];
+                dims.extend_from_slice(l.dims());
+                Shape::from(dims)
+            } else {
+                l.shape().clone()
+            };
+            Ok((dst, shape))
+        }
+    }
+
+    impl super::Comm for NcclComm {
+        fn rank(&self) -> usize {
+            self.rank
+        }
+
+        fn world_size(&self) -> usize {
+            self.world_size
+        }
+
+        fn all_reduce(&self, xs: &Tensor) -> Result<Tensor> {
+            let op = NcclOp {
+                comm: self.comm.clone(),
+                world_size: self.world_size,
+                gather: false,
+            };
+            xs.contiguous()?.apply_op1_no_bwd(&op)
+        }
+
+        fn all_gather(&self, xs: &Tensor, dim: usize) -> Result<Tensor> {
+            let op = NcclOp {
+                comm: self.comm.clone(),
+                world_size: self.world_size,
+                gather: true,
+            };
+            // The gathered tensor has shape (world_size, ..xs.dims()).
+            let all = xs.contiguous()?.apply_op1_no_bwd(&op)?;
+            let all = (0..self.world_size)
+                .map(|rank| all.get(rank))
+                .collect::<Result<Vec<_>>>()?;
+            Tensor::cat(&all, dim)
+        }
+    }
+}
+
+#[cfg(feature = "nccl")]
+pub use nccl::NcclComm;
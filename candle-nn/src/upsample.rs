@@ -0,0 +1,71 @@
+//! Upsampling layer.
+//!
+//! The input is expected to have four dimensions `(batch, channels, h, w)`, the two last
+//! dimensions are resized either to a fixed size or by a scale factor.
+use candle::{Result, Tensor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsampleMode {
+    Nearest,
+    Bilinear,
+}
+
+/// The target of the upsampling, for a scale factor the output size is obtained by rounding
+/// down the scaled input size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpsampleSize {
+    Size(usize, usize),
+    ScaleFactor(f64, f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Upsample {
+    size: UpsampleSize,
+    mode: UpsampleMode,
+    align_corners: bool,
+}
+
+impl Upsample {
+    /// Creates a new upsampling layer, `align_corners` can only be set for bilinear upsampling.
+    pub fn new(size: UpsampleSize, mode: UpsampleMode, align_corners: bool) -> Result<Self> {
+        if align_corners && mode == UpsampleMode::Nearest {
+            candle::bail!("align_corners can only be used with bilinear upsampling")
+        }
+        if let UpsampleSize::ScaleFactor(h, w) = size {
+            if h <= 0. || w <= 0. {
+                candle::bail!("upsample scale factors should be positive ({h}, {w})")
+            }
+        }
+        Ok(Self {
+            size,
+            mode,
+            align_corners,
+        })
+    }
+
+    pub fn mode(&self) -> UpsampleMode {
+        self.mode
+    }
+
+    pub fn size(&self) -> UpsampleSize {
+        self.size
+    }
+}
+
+impl crate::Module for Upsample {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (_b_size, _c, h, w) = xs.dims4()?;
+        let (target_h, target_w) = match self.size {
+            UpsampleSize::Size(h, w) => (h, w),
+            UpsampleSize::ScaleFactor(s_h, s_w) => {
+                ((h as f64 * s_h) as usize, (w as f64 * s_w) as usize)
+            }
+        };
+        match self.mode {
+            UpsampleMode::Nearest => xs.upsample_nearest2d(target_h, target_w),
+            UpsampleMode::Bilinear => {
+                xs.upsample_bilinear2d(target_h, target_w, self.align_corners)
+            }
+        }
+    }
+}
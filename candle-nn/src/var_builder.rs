@@ -71,6 +71,12 @@ pub trait SimpleBackend: Send + Sync {
     ) -> Result<Tensor>;
 
     fn contains_tensor(&self, name: &str) -> bool;
+
+    /// The names of all the tensors available in this backend, `None` if the backend cannot list
+    /// them, e.g. because the tensors are generated on the fly.
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        None
+    }
 }
 
 impl<'a> Backend for Box<dyn SimpleBackend + 'a> {
@@ -195,9 +201,13 @@ impl<'a, B: Backend> VarBuilderArgs<'a, B> {
         dtype: DType,
     ) -> Result<Tensor> {
         let path = self.path(name);
-        self.data
+        let tensor = self
+            .data
             .backend
-            .get(s.into(), &path, hints, dtype, &self.data.device)
+            .get(s.into(), &path, hints, dtype, &self.data.device)?;
+        // Naming the tensor after its path makes shape mismatch errors easier to track, this
+        // returns a new handle so the tensor held by the backend, e.g. a VarMap, is not modified.
+        Ok(tensor.with_name(&path))
     }
 }
 
@@ -245,6 +255,10 @@ impl SimpleBackend for HashMap<String, Tensor> {
     fn contains_tensor(&self, name: &str) -> bool {
         self.contains_key(name)
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.keys().cloned().collect())
+    }
 }
 
 impl SimpleBackend for VarMap {
@@ -262,6 +276,10 @@ impl SimpleBackend for VarMap {
     fn contains_tensor(&self, name: &str) -> bool {
         self.data().lock().unwrap().contains_key(name)
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.data().lock().unwrap().keys().cloned().collect())
+    }
 }
 
 pub struct SafeTensorWithRouting<'a> {
@@ -302,6 +320,10 @@ impl<'a> SimpleBackend for SafeTensorWithRouting<'a> {
     fn contains_tensor(&self, name: &str) -> bool {
         self.routing.contains_key(name)
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.routing.keys().cloned().collect())
+    }
 }
 
 impl SimpleBackend for candle::npy::NpzTensors {
@@ -335,6 +357,10 @@ impl SimpleBackend for candle::npy::NpzTensors {
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).map_or(false, |v| v.is_some())
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.names().into_iter().cloned().collect())
+    }
 }
 
 impl SimpleBackend for candle::pickle::PthTensors {
@@ -368,6 +394,10 @@ impl SimpleBackend for candle::pickle::PthTensors {
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).map_or(false, |v| v.is_some())
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.tensor_infos().keys().cloned().collect())
+    }
 }
 
 impl SimpleBackend for candle::safetensors::MmapedSafetensors {
@@ -394,6 +424,43 @@ impl SimpleBackend for candle::safetensors::MmapedSafetensors {
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).is_ok()
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.tensors().into_iter().map(|(name, _)| name).collect())
+    }
+}
+
+impl<R: std::io::Read + std::io::Seek + Send> SimpleBackend
+    for candle::safetensors::SafeTensorsReader<R>
+{
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        _: crate::Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Tensor> {
+        let tensor = self.load(name, dev)?.to_dtype(dtype)?;
+        if tensor.shape() != &s {
+            Err(candle::Error::UnexpectedShape {
+                msg: format!("shape mismatch for {name}"),
+                expected: s,
+                got: tensor.shape().clone(),
+            }
+            .bt())?
+        }
+        Ok(tensor)
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.tensor_info(name).is_ok()
+    }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        let names = candle::safetensors::SafeTensorsReader::tensor_names(self);
+        Some(names.into_iter().map(|name| name.to_string()).collect())
+    }
 }
 
 impl SimpleBackend for candle::safetensors::BufferedSafetensors {
@@ -420,6 +487,10 @@ impl SimpleBackend for candle::safetensors::BufferedSafetensors {
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).is_ok()
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.tensors().into_iter().map(|(name, _)| name).collect())
+    }
 }
 
 impl<'a> SimpleBackend for candle::safetensors::SliceSafetensors<'a> {
@@ -446,6 +517,10 @@ impl<'a> SimpleBackend for candle::safetensors::SliceSafetensors<'a> {
     fn contains_tensor(&self, name: &str) -> bool {
         self.get(name).is_ok()
     }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        Some(self.tensors().into_iter().map(|(name, _)| name).collect())
+    }
 }
 
 impl<'a> VarBuilder<'a> {
@@ -507,6 +582,33 @@ impl<'a> VarBuilder<'a> {
         Ok(Self::from_backend(Box::new(tensors), dtype, dev.clone()))
     }
 
+    /// Initializes a `VarBuilder` from the shards listed in a sharded safetensors index file,
+    /// e.g. `model.safetensors.index.json`, the shards get memory mapped.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn from_safetensors_index<P: AsRef<std::path::Path>>(
+        index_path: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let tensors = candle::safetensors::MmapedSafetensors::from_index(index_path)?;
+        Ok(Self::from_backend(Box::new(tensors), dtype, dev.clone()))
+    }
+
+    /// Initializes a `VarBuilder` that reads tensors on demand from a safetensors file, only
+    /// the header is read upfront and each tensor data is read from the file when requested.
+    /// Contrary to [`VarBuilder::from_mmaped_safetensors`] this does not require memory mapping.
+    pub fn from_safetensors_reader<P: AsRef<std::path::Path>>(
+        p: P,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Self> {
+        let tensors = candle::safetensors::SafeTensorsReader::from_file(p)?;
+        Ok(Self::from_backend(Box::new(tensors), dtype, dev.clone()))
+    }
+
     /// Initializes a `VarBuilder` from a binary buffer in the safetensor format.
     pub fn from_buffered_safetensors(data: Vec<u8>, dtype: DType, dev: &Device) -> Result<Self> {
         let tensors = candle::safetensors::BufferedSafetensors::new(data)?;
@@ -560,11 +662,72 @@ impl<'a> VarBuilder<'a> {
         self.rename(f)
     }
 
+    /// Same as `rename_f` but using a map from the queried names to the names in the inner
+    /// VarBuilder, names that are not in the map are left unchanged.
+    ///
+    /// ```rust
+    /// use candle::{Tensor, DType, Device};
+    ///
+    /// let a = Tensor::arange(0f32, 6f32, &Device::Cpu)?.reshape((2, 3))?;
+    /// let tensors = [("model.layers.0.q_proj.weight".to_string(), a)].into_iter().collect();
+    /// let vb = candle_nn::VarBuilder::from_tensors(tensors, DType::F32, &Device::Cpu);
+    /// let map = [("attn.q.weight", "model.layers.0.q_proj.weight")]
+    ///     .into_iter()
+    ///     .map(|(k, v)| (k.to_string(), v.to_string()))
+    ///     .collect();
+    /// let vb = vb.remap(map);
+    /// assert!(vb.pp("attn").get((2, 3), "q.weight").is_ok());
+    /// # Ok::<(), candle::Error>(())
+    /// ```
+    pub fn remap(self, map: HashMap<String, String>) -> Self {
+        self.rename(map)
+    }
+
+    /// Returns a non-strict `VarBuilder`: rather than failing, the tensors that are missing from
+    /// this `VarBuilder` get initialized using `init`, or using the hints provided by the caller
+    /// when `init` is `None`. The returned `LoadReport` tracks the missing tensors as well as the
+    /// unexpected ones, i.e. the tensors that are available but have not been retrieved, similar
+    /// to PyTorch's `load_state_dict(strict=False)`.
+    ///
+    /// The initialized tensors are variables stored in the report `VarMap` so that they can be
+    /// trained.
+    pub fn non_strict(self, init: Option<crate::Init>) -> (Self, LoadReport) {
+        let dtype = self.dtype();
+        let device = self.device().clone();
+        let path = self.path.clone();
+        let report = LoadReportData {
+            names: self.data.backend.tensor_names(),
+            requested: Default::default(),
+            missing: Default::default(),
+            var_map: VarMap::new(),
+        };
+        let report = LoadReport(Arc::new(std::sync::Mutex::new(report)));
+        let backend = NonStrict {
+            inner: self.root(),
+            init,
+            report: report.clone(),
+        };
+        let backend: Box<dyn SimpleBackend + 'a> = Box::new(backend);
+        let data = TensorData {
+            backend,
+            dtype,
+            device,
+        };
+        let vb = Self {
+            data: Arc::new(data),
+            path,
+            _phantom: std::marker::PhantomData,
+        };
+        (vb, report)
+    }
+
     pub fn rename<R: Renamer + Send + Sync + 'a>(self, renamer: R) -> Self {
         let dtype = self.dtype();
         let device = self.device().clone();
         let path = self.path.clone();
-        let backend = Rename::new(self, renamer);
+        // The renamer is applied on the full path so the inner VarBuilder should not add the
+        // prefix a second time.
+        let backend = Rename::new(self.root(), renamer);
         let backend: Box<dyn SimpleBackend + 'a> = Box::new(backend);
         let data = TensorData {
             backend,
@@ -743,3 +906,92 @@ impl Renamer for Box<dyn Fn(&str) -> String + Sync + Send> {
         std::borrow::Cow::Owned(self(v))
     }
 }
+
+impl Renamer for HashMap<String, String> {
+    fn rename(&self, v: &str) -> std::borrow::Cow<'_, str> {
+        match self.get(v) {
+            Some(v) => std::borrow::Cow::Borrowed(v.as_str()),
+            None => std::borrow::Cow::Owned(v.to_string()),
+        }
+    }
+}
+
+struct LoadReportData {
+    names: Option<Vec<String>>,
+    requested: std::collections::HashSet<String>,
+    missing: Vec<String>,
+    var_map: VarMap,
+}
+
+/// The missing and unexpected tensors for a non-strict `VarBuilder`, see
+/// `VarBuilder::non_strict`.
+#[derive(Clone)]
+pub struct LoadReport(Arc<std::sync::Mutex<LoadReportData>>);
+
+impl LoadReport {
+    /// The tensors that were requested but not available, in the order in which they have been
+    /// requested.
+    pub fn missing(&self) -> Vec<String> {
+        self.0.lock().unwrap().missing.clone()
+    }
+
+    /// The tensors that are available but have not been requested so far, sorted by name. This
+    /// is empty if the underlying backend cannot list its tensors.
+    pub fn unexpected(&self) -> Vec<String> {
+        let data = self.0.lock().unwrap();
+        let mut unexpected = data
+            .names
+            .iter()
+            .flatten()
+            .filter(|name| !data.requested.contains(name.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        unexpected.sort();
+        unexpected
+    }
+
+    /// The variables that have been initialized for the missing tensors.
+    pub fn var_map(&self) -> VarMap {
+        self.0.lock().unwrap().var_map.clone()
+    }
+}
+
+struct NonStrict<'a> {
+    inner: VarBuilder<'a>,
+    init: Option<crate::Init>,
+    report: LoadReport,
+}
+
+impl<'a> SimpleBackend for NonStrict<'a> {
+    fn get(
+        &self,
+        s: Shape,
+        name: &str,
+        h: crate::Init,
+        dtype: DType,
+        dev: &Device,
+    ) -> Result<Tensor> {
+        let mut report = self.report.0.lock().unwrap();
+        report.requested.insert(name.to_string());
+        if self.inner.contains_tensor(name) {
+            return self
+                .inner
+                .get_with_hints_dtype(s, name, h, dtype)?
+                .to_device(dev);
+        }
+        if !report.missing.iter().any(|n| n == name) {
+            report.missing.push(name.to_string())
+        }
+        report
+            .var_map
+            .get(s, name, self.init.unwrap_or(h), dtype, dev)
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.inner.contains_tensor(name)
+    }
+
+    fn tensor_names(&self) -> Option<Vec<String>> {
+        self.inner.data.backend.tensor_names()
+    }
+}
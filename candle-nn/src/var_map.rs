@@ -1,5 +1,5 @@
 use candle::{DType, Device, Result, Shape, Tensor, Var};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 /// A `VarMap` is a store that holds named variables. Variables can be retrieved from the stores
@@ -9,6 +9,7 @@ use std::sync::{Arc, Mutex};
 #[derive(Clone)]
 pub struct VarMap {
     data: Arc<Mutex<HashMap<String, Var>>>,
+    frozen: Arc<Mutex<HashSet<String>>>,
 }
 
 impl VarMap {
@@ -16,7 +17,8 @@ impl VarMap {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let data = Arc::new(Mutex::new(HashMap::new()));
-        Self { data }
+        let frozen = Arc::new(Mutex::new(HashSet::new()));
+        Self { data, frozen }
     }
 
     /// Retrieve all the variables currently stored in the map.
@@ -26,11 +28,49 @@ impl VarMap {
         tensor_data.values().map(|c| c.clone()).collect::<Vec<_>>()
     }
 
+    /// Retrieve the variables currently stored in the map that have not been frozen, this is
+    /// what should be passed to optimizers or to `Tensor::backward_for` when fine-tuning only
+    /// part of a model.
+    pub fn trainable_vars(&self) -> Vec<Var> {
+        let tensor_data = self.data.lock().unwrap();
+        let frozen = self.frozen.lock().unwrap();
+        tensor_data
+            .iter()
+            .filter(|(name, _)| !frozen.contains(name.as_str()))
+            .map(|(_, var)| var.clone())
+            .collect::<Vec<_>>()
+    }
+
+    /// Freeze or unfreeze the variables named `prefix` or with a name starting with `prefix.`.
+    ///
+    /// This only applies to the variables that are currently in the map, variables added later
+    /// on are trainable.
+    pub fn set_trainable(&self, prefix: &str, trainable: bool) {
+        let tensor_data = self.data.lock().unwrap();
+        let mut frozen = self.frozen.lock().unwrap();
+        let sub_prefix = format!("{prefix}.");
+        for name in tensor_data.keys() {
+            if name == prefix || name.starts_with(&sub_prefix) {
+                if trainable {
+                    frozen.remove(name);
+                } else {
+                    frozen.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    /// Returns true if the variable `name` has not been frozen.
+    pub fn is_trainable(&self, name: &str) -> bool {
+        !self.frozen.lock().unwrap().contains(name)
+    }
+
     /// Save the map in the safetensors format.
     pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
         let tensor_data = self.data.lock().unwrap();
         let data = tensor_data.iter().map(|(k, v)| (k, v.as_tensor()));
-        safetensors::tensor::serialize_to_file(data, &None, path.as_ref())?;
+        let data = candle::safetensors::serialize(data)?;
+        std::fs::write(path, data)?;
         Ok(())
     }
 
@@ -0,0 +1,97 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{test_utils, DType, Device, Module, Result, Tensor, D};
+use candle_nn::{kv_cache::KvCache, MultiheadAttentionConfig, VarBuilder, VarMap};
+
+#[test]
+fn multihead_attention_single_head() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mha = candle_nn::multihead_attention(4, Default::default(), vb.clone())?;
+    let xs = Tensor::randn(0f32, 1., (2, 3, 4), dev)?;
+    let ys = mha.forward(&xs, &xs, &xs, None)?;
+    assert_eq!(ys.dims(), [2, 3, 4]);
+
+    let linear = |name: &str| -> Result<candle_nn::Linear> { candle_nn::linear(4, 4, vb.pp(name)) };
+    let q = linear("q_proj")?.forward(&xs)?;
+    let k = linear("k_proj")?.forward(&xs)?;
+    let v = linear("v_proj")?.forward(&xs)?;
+    let att = (q.matmul(&k.t()?)? / 2.)?;
+    let att = candle_nn::ops::softmax(&att, D::Minus1)?;
+    let expected = linear("out_proj")?.forward(&att.matmul(&v)?)?;
+    test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn multihead_attention_fused_and_cross() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let cfg = MultiheadAttentionConfig {
+        num_heads: 2,
+        fused_qkv: true,
+        ..Default::default()
+    };
+    let mha = candle_nn::multihead_attention(8, cfg, vb.pp("fused"))?;
+    let xs = Tensor::randn(0f32, 1., (2, 5, 8), dev)?;
+    let ys = mha.forward(&xs, &xs, &xs, None)?;
+    assert_eq!(ys.dims(), [2, 5, 8]);
+    // Projecting with the split weights gives the same result as the single matmul.
+    let ys2 = mha.forward(&xs, &xs.copy()?, &xs.copy()?, None)?;
+    test_utils::assert_close(&ys, &ys2, 0., 1e-5);
+
+    let cfg = MultiheadAttentionConfig {
+        num_heads: 2,
+        kdim: Some(3),
+        vdim: Some(6),
+        ..Default::default()
+    };
+    let mha = candle_nn::multihead_attention(8, cfg, vb.pp("cross"))?;
+    let k = Tensor::randn(0f32, 1., (2, 7, 3), dev)?;
+    let v = Tensor::randn(0f32, 1., (2, 7, 6), dev)?;
+    let ys = mha.forward(&xs, &k, &v, None)?;
+    assert_eq!(ys.dims(), [2, 5, 8]);
+    let cfg = MultiheadAttentionConfig {
+        fused_qkv: true,
+        ..cfg
+    };
+    assert!(candle_nn::multihead_attention(8, cfg, vb.pp("bad")).is_err());
+    Ok(())
+}
+
+#[test]
+fn multihead_attention_kv_cache() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let cfg = MultiheadAttentionConfig {
+        num_heads: 2,
+        ..Default::default()
+    };
+    let mha = candle_nn::multihead_attention(8, cfg, vb)?;
+    let xs = Tensor::randn(0f32, 1., (1, 4, 8), dev)?;
+    let mask: Vec<f32> = (0..4)
+        .flat_map(|i| (0..4).map(move |j| if j > i { f32::NEG_INFINITY } else { 0. }))
+        .collect();
+    let mask = Tensor::from_vec(mask, (4, 4), dev)?;
+    let expected = mha.forward(&xs, &xs, &xs, Some(&mask))?;
+
+    // Decoding one position at a time with the cache matches the causal attention.
+    let mut kv_cache = KvCache::new(2, 16);
+    let ys = (0..4)
+        .map(|i| {
+            let x = xs.narrow(1, i, 1)?;
+            mha.forward_with_kv_cache(&x, &x, &x, None, Some(&mut kv_cache))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let ys = Tensor::cat(&ys, 1)?;
+    assert_eq!(kv_cache.current_seq_len(), 4);
+    test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    Ok(())
+}
@@ -0,0 +1,73 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::test_utils::to_vec1_round;
+use candle::{DType, Device, Result, Tensor};
+use candle_nn::{Ema, Init, VarMap};
+
+fn set(var_map: &VarMap, name: &str, value: &Tensor) -> Result<()> {
+    var_map.data().lock().unwrap()[name].set(value)
+}
+
+#[test]
+fn ema_converges() -> Result<()> {
+    let dev = &Device::Cpu;
+    let var_map = VarMap::new();
+    let w = var_map.get(3, "w", Init::Const(0.), DType::F32, dev)?;
+    let mut ema = Ema::new(&var_map, false)?;
+    let target = Tensor::new(&[1f32, -2., 3.], dev)?;
+    set(&var_map, "w", &target)?;
+    for _ in 0..1000 {
+        ema.update(0.99)?;
+    }
+    assert_eq!(ema.num_updates(), 1000);
+    assert_eq!(to_vec1_round(ema.get("w").unwrap(), 3)?, [1., -2., 3.]);
+
+    // Swap the averaged weights in, the live variable is updated in place.
+    set(&var_map, "w", &target.zeros_like()?)?;
+    ema.copy_to(&var_map)?;
+    assert_eq!(to_vec1_round(&w, 3)?, [1., -2., 3.]);
+    Ok(())
+}
+
+#[test]
+fn ema_no_decay() -> Result<()> {
+    let dev = &Device::Cpu;
+    let var_map = VarMap::new();
+    let w = var_map.get(
+        (2, 2),
+        "w",
+        Init::Randn {
+            mean: 0.,
+            stdev: 1.,
+        },
+        DType::F32,
+        dev,
+    )?;
+    let mut ema = Ema::new(&var_map, true)?;
+    for step in 0..5 {
+        let value = (w.as_ref() * 0.5)? + step as f64;
+        set(&var_map, "w", &value?)?;
+        ema.update(0.)?;
+        assert_eq!(ema.get("w").unwrap().to_vec2::<f32>()?, w.to_vec2::<f32>()?);
+    }
+    Ok(())
+}
+
+#[test]
+fn ema_warmup() -> Result<()> {
+    let dev = &Device::Cpu;
+    let var_map = VarMap::new();
+    var_map.get(1, "w", Init::Const(0.), DType::F32, dev)?;
+    let mut ema = Ema::new(&var_map, true)?;
+    assert_eq!(ema.effective_decay(0.999), 0.1);
+    set(&var_map, "w", &Tensor::new(&[1f32], dev)?)?;
+    ema.update(0.999)?;
+    assert_eq!(to_vec1_round(ema.get("w").unwrap(), 4)?, [0.9]);
+    assert_eq!(ema.effective_decay(0.999), 2. / 11.);
+    assert_eq!(ema.effective_decay(0.1), 0.1);
+    Ok(())
+}
@@ -0,0 +1,47 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, IndexOp, Tensor};
+use candle_nn::{Embedding, Module, Optimizer, VarBuilder, VarMap, SGD};
+
+#[test]
+fn embedding_padding_idx() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let emb = candle_nn::embedding_with_padding_idx(5, 3, Some(1), vb)?;
+    assert_eq!(emb.padding_idx(), Some(1));
+    assert_eq!(emb.embeddings().i(1)?.to_vec1::<f32>()?, [0., 0., 0.]);
+
+    let ids = Tensor::new(&[[1u32, 2, 1], [0, 1, 4]], dev)?;
+    let ys = emb.forward(&ids)?;
+    assert_eq!(ys.dims(), [2, 3, 3]);
+    assert_eq!(ys.i((0, 0))?.to_vec1::<f32>()?, [0., 0., 0.]);
+    assert_eq!(ys.i((1, 1))?.to_vec1::<f32>()?, [0., 0., 0.]);
+
+    let before = emb.embeddings().to_vec2::<f32>()?;
+    let mut sgd = SGD::new(varmap.all_vars(), 0.1)?;
+    let loss = (ys.sum_all()? + 1.)?.sqr()?;
+    sgd.backward_step(&loss)?;
+    let after = emb.embeddings().to_vec2::<f32>()?;
+    assert_eq!(after[1], [0., 0., 0.]);
+    // Rows that were looked up are updated, the unused row 3 is not.
+    assert_ne!(after[2], before[2]);
+    assert_eq!(after[3], before[3]);
+
+    // Existing weights are not modified but the lookups of the padding index are masked.
+    let ws = Tensor::arange(0f32, 6f32, dev)?.reshape((3, 2))?;
+    let emb = Embedding::new_with_padding_idx(ws.clone(), 2, Some(0))?;
+    let ys = emb.forward(&Tensor::new(&[0u32, 2], dev)?)?;
+    assert_eq!(ys.to_vec2::<f32>()?, [[0., 0.], [4., 5.]]);
+    assert!(Embedding::new_with_padding_idx(ws.clone(), 2, Some(3)).is_err());
+
+    let emb = Embedding::from_weights(ws)?;
+    assert_eq!(emb.hidden_size(), 2);
+    assert_eq!(emb.padding_idx(), None);
+    Ok(())
+}
@@ -0,0 +1,88 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{DType, Device, Tensor};
+use candle_nn::{InstanceNormConfig, Module, VarBuilder, VarMap};
+
+// Normalizes each (sample, channel) plane of a (n, c, h, w) tensor.
+fn reference(xs: &[f32], n: usize, c: usize, hw: usize, w: &[f32], b: &[f32]) -> Vec<f32> {
+    let mut out = Vec::with_capacity(xs.len());
+    for i in 0..n * c {
+        let plane = &xs[i * hw..(i + 1) * hw];
+        let mean = plane.iter().sum::<f32>() / hw as f32;
+        let var = plane.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / hw as f32;
+        for v in plane.iter() {
+            out.push((v - mean) / (var + 1e-5).sqrt() * w[i % c] + b[i % c])
+        }
+    }
+    out
+}
+
+fn max_diff(a: &[f32], b: &[f32]) -> f32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b).abs())
+        .fold(0., f32::max)
+}
+
+#[test]
+fn instance_norm() -> Result<()> {
+    let dev = &Device::Cpu;
+    let data: Vec<f32> = (0..48).map(|v| ((v * 7) % 11) as f32 - 3.).collect();
+    let xs = Tensor::from_vec(data.clone(), (2, 3, 2, 4), dev)?;
+
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let norm = candle_nn::instance_norm2d(3, 1e-5, vb.pp("plain"))?;
+    assert!(norm.weight_and_bias().is_none());
+    let ys = norm.forward(&xs)?.flatten_all()?.to_vec1::<f32>()?;
+    let expected = reference(&data, 2, 3, 8, &[1.; 3], &[0.; 3]);
+    assert!(max_diff(&ys, &expected) < 1e-5);
+
+    let mut tensors = std::collections::HashMap::new();
+    tensors.insert("weight".to_string(), Tensor::new(&[1f32, 2., -1.], dev)?);
+    tensors.insert("bias".to_string(), Tensor::new(&[0f32, 0.5, 3.], dev)?);
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, dev);
+    let cfg = InstanceNormConfig {
+        affine: true,
+        ..Default::default()
+    };
+    let norm = candle_nn::instance_norm2d(3, cfg, vb)?;
+    let ys = norm.forward(&xs)?.flatten_all()?.to_vec1::<f32>()?;
+    let expected = reference(&data, 2, 3, 8, &[1., 2., -1.], &[0., 0.5, 3.]);
+    assert!(max_diff(&ys, &expected) < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn instance_norm_running_stats() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let cfg = InstanceNormConfig {
+        track_running_stats: true,
+        momentum: 0.5,
+        ..Default::default()
+    };
+    let norm = candle_nn::instance_norm2d(1, cfg, vb)?;
+    // Two samples with means 1.5 and 5.5, and unbiased variances 5/3 for both.
+    let xs = Tensor::new(&[0f32, 1., 2., 3., 4., 5., 6., 7.], dev)?.reshape((2, 1, 2, 2))?;
+    let ys = norm.forward_train(&xs)?.flatten_all()?.to_vec1::<f32>()?;
+    let expected = reference(&[0., 1., 2., 3., 4., 5., 6., 7.], 2, 1, 4, &[1.], &[0.]);
+    assert!(max_diff(&ys, &expected) < 1e-5);
+    let running_mean = norm.running_mean().unwrap().to_vec1::<f32>()?;
+    let running_var = norm.running_var().unwrap().to_vec1::<f32>()?;
+    assert!(max_diff(&running_mean, &[1.75]) < 1e-6);
+    assert!(max_diff(&running_var, &[0.5 + 0.5 * 5. / 3.]) < 1e-6);
+
+    // In evaluation mode, the running stats are used.
+    let ys = norm.forward(&xs)?.flatten_all()?.to_vec1::<f32>()?;
+    let std = (running_var[0] + 1e-5).sqrt();
+    let expected: Vec<f32> = (0..8).map(|v| (v as f32 - 1.75) / std).collect();
+    assert!(max_diff(&ys, &expected) < 1e-5);
+    Ok(())
+}
@@ -0,0 +1,115 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{DType, Device, Result, Tensor, D};
+use candle_nn::kv_cache::KvCache;
+
+// A single attention layer decoding one token at a time using the kv cache.
+fn decode(cache: &mut KvCache, xs: &Tensor, ws: &[Tensor; 3]) -> Result<Tensor> {
+    let (_b, seq_len, _) = xs.dims3()?;
+    let mut ys = vec![];
+    for pos in 0..seq_len {
+        let x = xs.narrow(1, pos, 1)?;
+        let q = x.broadcast_matmul(&ws[0])?.unsqueeze(1)?;
+        let k = x.broadcast_matmul(&ws[1])?.unsqueeze(1)?;
+        let v = x.broadcast_matmul(&ws[2])?.unsqueeze(1)?;
+        let (k, v) = cache.append(&k.contiguous()?, &v.contiguous()?)?;
+        let head_dim = q.dim(D::Minus1)? as f64;
+        let att = (q.matmul(&k.t()?)? / head_dim.sqrt())?;
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+        ys.push(att.matmul(&v)?.squeeze(1)?)
+    }
+    Tensor::cat(&ys, 1)
+}
+
+#[test]
+fn quantized_kv_cache() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (seq_len, dim) = (12, 64);
+    let xs = Tensor::randn(0f32, 1., (2, seq_len, dim), dev)?;
+    let ws = [
+        (Tensor::randn(0f32, 1., (dim, dim), dev)? / 8.)?,
+        (Tensor::randn(0f32, 1., (dim, dim), dev)? / 8.)?,
+        (Tensor::randn(0f32, 1., (dim, dim), dev)? / 8.)?,
+    ];
+    let mut cache = KvCache::new(2, seq_len);
+    let mut qcache = KvCache::new_quantized(2, seq_len);
+    assert!(qcache.is_quantized());
+    let ys = decode(&mut cache, &xs, &ws)?;
+    let qys = decode(&mut qcache, &xs, &ws)?;
+    let diff = (&ys - &qys)?.sqr()?.sum_all()?.sqrt()?.to_vec0::<f32>()?;
+    let norm = ys.sqr()?.sum_all()?.sqrt()?.to_vec0::<f32>()?;
+    assert!(diff / norm < 0.02, "{diff} {norm}");
+    assert_eq!(qcache.current_seq_len(), seq_len);
+    assert_eq!(qcache.k()?.unwrap().dtype(), DType::F32);
+
+    qcache.reset();
+    assert_eq!(qcache.storage_size_in_bytes(), 0);
+    Ok(())
+}
+
+#[test]
+fn quantized_kv_cache_memory() -> Result<()> {
+    let dev = &Device::Cpu;
+    let k = Tensor::randn(0f32, 1., (1, 8, 4, 64), dev)?.to_dtype(DType::F16)?;
+    let mut cache = KvCache::new(2, 1024);
+    let mut qcache = KvCache::new_quantized(2, 1024);
+    let (k1, _) = cache.append(&k, &k)?;
+    let (qk, _) = qcache.append(&k, &k)?;
+    assert_eq!(qk.dtype(), DType::F16);
+    assert_eq!(qk.dims(), k1.dims());
+    let bytes = cache.storage_size_in_bytes();
+    let qbytes = qcache.storage_size_in_bytes();
+    assert_eq!(bytes, 2 * 8 * 1024 * 64 * 2);
+    // One byte per value plus one f32 scale per token and head.
+    assert_eq!(qbytes, 2 * (8 * 1024 * 64 + 8 * 1024 * 4));
+    assert!((qbytes as f64) < 0.6 * bytes as f64);
+
+    // Values are quantized per token, rows with only zeros are preserved.
+    let ks = Tensor::new(&[[[0f32, 0., 0.], [1., -2., 0.5]]], dev)?;
+    let mut qcache = KvCache::new_quantized(1, 4);
+    let (qk, _) = qcache.append(&ks, &ks)?;
+    let diff = (qk - &ks)?.abs()?.flatten_all()?.max(0)?.to_vec0::<f32>()?;
+    assert!(diff < 2. / 127.);
+
+    // Quantizing over the cache dimension is not supported.
+    let mut qcache = KvCache::new_quantized(2, 4);
+    assert!(qcache.append(&ks, &ks).is_err());
+    Ok(())
+}
+
+#[test]
+fn kv_cache_truncate() -> Result<()> {
+    let dev = &Device::Cpu;
+    let xs = Tensor::arange(0f32, 24., dev)?.reshape((1, 2, 6, 2))?;
+    let xs = |start, len| xs.narrow(2, start, len)?.contiguous();
+    for quantized in [false, true] {
+        let mut cache = if quantized {
+            KvCache::new_quantized(2, 8)
+        } else {
+            KvCache::new(2, 8)
+        };
+        cache.append(&xs(0, 4)?, &xs(0, 4)?)?;
+        // Roll back two entries and replace them with different values.
+        cache.truncate(2);
+        assert_eq!(cache.current_seq_len(), 2);
+        assert_eq!(cache.k()?.unwrap().dims(), [1, 2, 2, 2]);
+        let (k, v) = cache.append(&xs(3, 3)?, &xs(3, 3)?)?;
+        let expected = Tensor::cat(&[xs(0, 2)?, xs(3, 3)?], 2)?;
+        for t in [k, v] {
+            let diff = (t - &expected)?
+                .abs()?
+                .flatten_all()?
+                .max(0)?
+                .to_vec0::<f32>()?;
+            assert!(diff < 0.1, "{quantized} {diff}");
+        }
+        // Truncating past the current length does nothing.
+        cache.truncate(8);
+        assert_eq!(cache.current_seq_len(), 5);
+    }
+    Ok(())
+}
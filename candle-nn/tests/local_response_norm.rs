@@ -0,0 +1,45 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::{Device, Tensor};
+use candle_nn::{LocalResponseNorm, LocalResponseNormConfig, Module};
+
+#[test]
+fn local_response_norm() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (n, c, hw) = (2, 5, 3);
+    let data: Vec<f32> = (0..n * c * hw).map(|v| ((v * 5) % 7) as f32 - 2.).collect();
+    let xs = Tensor::from_vec(data.clone(), (n, c, 1, hw), dev)?;
+    for size in [1, 2, 3, 4] {
+        let cfg = LocalResponseNormConfig {
+            alpha: 0.5,
+            beta: 0.75,
+            k: 2.,
+        };
+        let lrn = LocalResponseNorm::new(size, cfg)?;
+        let ys = lrn.forward(&xs)?.flatten_all()?.to_vec1::<f32>()?;
+        // The window for channel i spans i - size / 2 ..= i + (size - 1) / 2.
+        for b in 0..n {
+            for i in 0..c {
+                for p in 0..hw {
+                    let lo = i.saturating_sub(size / 2);
+                    let hi = usize::min(i + (size - 1) / 2, c - 1);
+                    let sum: f32 = (lo..=hi).map(|j| data[(b * c + j) * hw + p].powi(2)).sum();
+                    let x = data[(b * c + i) * hw + p];
+                    let expected = x / (2. + 0.5 / size as f32 * sum).powf(0.75);
+                    let y = ys[(b * c + i) * hw + p];
+                    assert!(
+                        (y - expected).abs() < 1e-5,
+                        "{size} {b} {i} {p} {y} {expected}"
+                    );
+                }
+            }
+        }
+    }
+    assert!(LocalResponseNorm::new(0, Default::default()).is_err());
+    Ok(())
+}
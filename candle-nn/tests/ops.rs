@@ -4,7 +4,11 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use candle::{test_device, test_utils::to_vec3_round, Device, Result, Tensor};
+use candle::{
+    test_device,
+    test_utils::{self, to_vec3_round},
+    DType, Device, Result, Tensor,
+};
 
 fn softmax(device: &Device) -> Result<()> {
     let data = &[[[3f32, 1., 4.], [1., 5., 9.]], [[2., 1., 7.], [8., 2., 8.]]];
@@ -77,6 +81,93 @@ fn rms_norm(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn rms_norm_large(device: &Device) -> Result<()> {
+    // Typical (batch * seq_len, hidden_size) shapes, on both sides of the cuda block size.
+    for (n_rows, hidden_size) in [(7, 64), (13, 1000), (5, 4096)] {
+        let xs = Tensor::randn(0f32, 1., (n_rows, hidden_size), device)?;
+        let alpha = Tensor::randn(0f32, 1., hidden_size, device)?;
+        for dtype in [DType::F32, DType::F16, DType::BF16] {
+            let xs = xs.to_dtype(dtype)?;
+            let alpha = alpha.to_dtype(dtype)?;
+            let fused = candle_nn::ops::rms_norm(&xs, &alpha, 1e-5)?;
+            let slow = candle_nn::ops::rms_norm_slow(&xs, &alpha, 1e-5)?;
+            assert_eq!(fused.dims(), [n_rows, hidden_size]);
+            let slow = slow.to_dtype(DType::F32)?;
+            // Both results are rounded to the target dtype, use a relative error.
+            let diff = (fused.to_dtype(DType::F32)? - &slow)?
+                .abs()?
+                .div(&(slow.abs()? + 1.)?)?
+                .flatten_all()?
+                .max(0)?
+                .to_vec0::<f32>()?;
+            let tol = if dtype == DType::F32 { 1e-5 } else { 2e-2 };
+            assert!(diff < tol, "{dtype:?} {n_rows} {hidden_size} {diff}");
+        }
+    }
+    Ok(())
+}
+
+fn scaled_dot_product_attention_fused(device: &Device) -> Result<()> {
+    use candle_nn::ops::{scaled_dot_product_attention_fused, scaled_dot_product_attention_slow};
+
+    if device.is_metal() {
+        return Ok(());
+    }
+    // The keys and values are processed by tiles of 16, including lengths that are not a
+    // multiple of the tile size exercises the rescaling of the online softmax.
+    for (seq_len, kv_len, head_dim) in [(1, 1, 8), (5, 17, 32), (16, 16, 64), (33, 70, 80)] {
+        let q = Tensor::randn(0f32, 1., (2, 3, seq_len, head_dim), device)?;
+        let k = Tensor::randn(0f32, 1., (2, 3, kv_len, head_dim), device)?;
+        let v = Tensor::randn(0f32, 1., (2, 3, kv_len, head_dim), device)?;
+        // Large values so that the maximum changes a lot between tiles.
+        let q = (q * 4.)?;
+        // Some of the keys are masked out, the first key is kept so that no row is fully masked.
+        let mask = Tensor::randn(0f32, 1., (seq_len, kv_len), device)?;
+        let neg_inf = Tensor::new(f32::NEG_INFINITY, device)?.broadcast_as(mask.shape())?;
+        let keep_first = Tensor::arange(0u32, kv_len as u32, device)?
+            .gt(0u32)?
+            .broadcast_as(mask.shape())?;
+        let mask = (mask.ge(1.5)? * keep_first)?.where_cond(&neg_inf, &mask)?;
+        for dtype in [DType::F32, DType::F16, DType::BF16] {
+            // There is no bf16 matmul on cpu for the reference implementation.
+            if dtype == DType::BF16 && device.is_cpu() {
+                continue;
+            }
+            let (q, k, v) = (q.to_dtype(dtype)?, k.to_dtype(dtype)?, v.to_dtype(dtype)?);
+            let mask = mask.to_dtype(dtype)?;
+            let tol = match dtype {
+                DType::F32 => 1e-4,
+                DType::F16 => 3e-2,
+                _ => 5e-2,
+            };
+            for (mask, causal) in [(None, false), (None, true), (Some(&mask), false)] {
+                let causal = causal && seq_len <= kv_len;
+                let fused = scaled_dot_product_attention_fused(&q, &k, &v, mask, None, causal)?;
+                let slow = scaled_dot_product_attention_slow(&q, &k, &v, mask, None, causal)?;
+                assert_eq!(fused.dims(), [2, 3, seq_len, head_dim]);
+                assert!(
+                    test_utils::allclose(&fused, &slow, 0., tol)?,
+                    "{dtype:?} {seq_len} {kv_len} {causal}"
+                );
+            }
+        }
+    }
+
+    // Transposed inputs and a mask broadcasted over the batch.
+    let q = Tensor::randn(0f32, 1., (2, 7, 4, 16), device)?.transpose(1, 2)?;
+    let k = Tensor::randn(0f32, 1., (2, 20, 4, 16), device)?.transpose(1, 2)?;
+    let v = Tensor::randn(0f32, 1., (2, 20, 4, 16), device)?.transpose(1, 2)?;
+    let mask = Tensor::randn(0f32, 1., (4, 1, 20), device)?;
+    let fused = scaled_dot_product_attention_fused(&q, &k, &v, Some(&mask), Some(0.3), true)?;
+    let slow = scaled_dot_product_attention_slow(&q, &k, &v, Some(&mask), Some(0.3), true)?;
+    test_utils::assert_close(&fused, &slow, 0., 1e-4);
+
+    // The fused version requires the same head dim for q and v.
+    let v = Tensor::randn(0f32, 1., (2, 4, 20, 8), device)?;
+    assert!(scaled_dot_product_attention_fused(&q, &k, &v, None, None, false).is_err());
+    Ok(())
+}
+
 fn layer_norm(device: &Device) -> Result<()> {
     let data = &[[[3f32, 1., 4.], [1., 5., 9.]], [[2., 1., 7.], [8., 2., 8.]]];
     let tensor = Tensor::new(data, device)?;
@@ -103,6 +194,26 @@ fn layer_norm(device: &Device) -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn softmax_autocast() -> Result<()> {
+    use candle::autocast::autocast;
+    let dev = &Device::Cpu;
+    let xs = Tensor::new(&[[1f32, 2., 3.], [4., 5., 6.]], dev)?;
+    let ws = Tensor::new(&[[0.5f32, -1.], [2., 0.25], [-3., 1.5]], dev)?;
+    let (mm, sm, sm_f16) = autocast(DType::F16, || {
+        let mm = xs.matmul(&ws)?;
+        let sm = candle_nn::ops::softmax_last_dim(&xs)?;
+        let sm_f16 = candle_nn::ops::softmax(&xs.to_dtype(DType::F16)?, 1)?;
+        Ok::<_, candle::Error>((mm, sm, sm_f16))
+    })?;
+    assert_eq!(mm.dtype(), DType::F16);
+    assert_eq!(sm.dtype(), DType::F32);
+    assert_eq!(sm_f16.dtype(), DType::F32);
+    let expected = candle_nn::ops::softmax_last_dim(&xs)?;
+    assert_eq!(sm.to_vec2::<f32>()?, expected.to_vec2::<f32>()?);
+    Ok(())
+}
+
 #[test]
 fn softmax_numerical_stability() -> Result<()> {
     let dev = &Device::Cpu;
@@ -112,6 +223,38 @@ fn softmax_numerical_stability() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn softmax_last_dim_grad() -> Result<()> {
+    let dev = &Device::Cpu;
+    let xs = candle::Var::new(&[[1f32, 2., 3.], [4., -1., 0.5]], dev)?;
+    let ws = Tensor::new(&[[0.5f32, -1., 2.], [3., 0.25, -2.]], dev)?;
+    let sm = candle_nn::ops::softmax_last_dim(&xs)?;
+    let grads = (sm * &ws)?.sum_all()?.backward()?;
+    let sm = candle_nn::ops::softmax(&xs, 1)?;
+    let expected = (sm * &ws)?.sum_all()?.backward()?;
+    test_utils::assert_close(
+        grads.get(&xs).unwrap(),
+        expected.get(&xs).unwrap(),
+        0.,
+        1e-6,
+    );
+    Ok(())
+}
+
+#[cfg(feature = "wgpu")]
+#[test]
+fn wgpu_ops() -> Result<()> {
+    let device = match Device::new_wgpu(0) {
+        Ok(device) => device,
+        Err(_) => return Ok(()),
+    };
+    softmax(&device)?;
+    rms_norm(&device)?;
+    layer_norm(&device)?;
+    sigmoid(&device)?;
+    Ok(())
+}
+
 fn ropei(device: &Device) -> Result<()> {
     use rand::{rngs::StdRng, Rng, SeedableRng};
 
@@ -196,6 +339,207 @@ fn rope_thd(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn rope_qk(device: &Device) -> Result<()> {
+    use candle_nn::rotary_emb::{
+        apply_rotary_emb_qk, rope, rope_cos_sin, rope_i, rope_i_slow, rope_slow,
+    };
+
+    let (b_size, n_head, n_kv_head, seq_len) = (2, 4, 2, 7);
+    for head_dim in [2, 8, 64, 80, 128] {
+        // The queries and keys are built in the (batch, seq_len, heads, head_dim) layout and
+        // transposed as is the case in most models.
+        let q = Tensor::randn(0f32, 1., (b_size, seq_len, n_head, head_dim), device)?;
+        let k = Tensor::randn(0f32, 1., (b_size, seq_len, n_kv_head, head_dim), device)?;
+        let (q, k) = (q.transpose(1, 2)?, k.transpose(1, 2)?);
+        let (cos, sin) = rope_cos_sin(head_dim, 10000., 16, None, candle::DType::F32, device)?;
+        let cos = cos.narrow(0, 3, seq_len)?;
+        let sin = sin.narrow(0, 3, seq_len)?;
+        for interleaved in [false, true] {
+            let (q1, k1) = apply_rotary_emb_qk(&q, &k, &cos, &sin, interleaved)?;
+            let (q2, k2) = if interleaved {
+                (rope_i_slow(&q, &cos, &sin)?, rope_i_slow(&k, &cos, &sin)?)
+            } else {
+                (rope_slow(&q, &cos, &sin)?, rope_slow(&k, &cos, &sin)?)
+            };
+            assert_eq!(q1.dims(), [b_size, n_head, seq_len, head_dim]);
+            assert_eq!(k1.dims(), [b_size, n_kv_head, seq_len, head_dim]);
+            for (a, b) in [(&q1, q2), (&k1, k2)] {
+                let diff = (a - b)?.abs()?.flatten_all()?.max(0)?.to_vec0::<f32>()?;
+                assert!(diff < 1e-5, "{head_dim} {interleaved} {diff}");
+            }
+            // The fused kernel matches the per tensor kernels.
+            let rope_fn = if interleaved { rope_i } else { rope };
+            let q3 = rope_fn(&q.contiguous()?, &cos, &sin)?;
+            let k3 = rope_fn(&k.contiguous()?, &cos, &sin)?;
+            for (a, b) in [(q1, q3), (k1, k3)] {
+                let diff = (a - b)?.abs()?.flatten_all()?.max(0)?.to_vec0::<f32>()?;
+                assert!(diff < 1e-6, "{head_dim} {interleaved} {diff}");
+            }
+        }
+    }
+    // The sin table shape is checked too.
+    let q = Tensor::zeros((1, 2, 3, 8), candle::DType::F32, device)?;
+    let cos = Tensor::zeros((3, 4), candle::DType::F32, device)?;
+    let sin = Tensor::zeros((3, 2), candle::DType::F32, device)?;
+    assert!(apply_rotary_emb_qk(&q, &q, &cos, &sin, true).is_err());
+    assert!(apply_rotary_emb_qk(&q, &q, &cos, &sin, false).is_err());
+    Ok(())
+}
+
+#[test]
+fn rope_scaling_linear() -> Result<()> {
+    use candle_nn::rotary_emb::{rope_cos_sin, RopeScaling};
+
+    let (dev, dtype) = (&Device::Cpu, candle::DType::F32);
+    let (cos, sin) = rope_cos_sin(16, 10000., 32, None, dtype, dev)?;
+    let scaling = RopeScaling::Linear { factor: 1.0 };
+    let (cos1, sin1) = rope_cos_sin(16, 10000., 32, Some(&scaling), dtype, dev)?;
+    test_utils::assert_close(&cos, &cos1, 0., 1e-5);
+    test_utils::assert_close(&sin, &sin1, 0., 1e-5);
+
+    // With a factor of 2, the position 2p uses the same angles as the position p.
+    let scaling = RopeScaling::Linear { factor: 2.0 };
+    let (cos2, sin2) = rope_cos_sin(16, 10000., 32, Some(&scaling), dtype, dev)?;
+    let even = Tensor::arange_step(0u32, 32, 2, dev)?;
+    test_utils::assert_close(
+        &cos2.index_select(&even, 0)?,
+        &cos.narrow(0, 0, 16)?,
+        0.,
+        1e-5,
+    );
+    test_utils::assert_close(
+        &sin2.index_select(&even, 0)?,
+        &sin.narrow(0, 0, 16)?,
+        0.,
+        1e-5,
+    );
+    Ok(())
+}
+
+#[test]
+fn rope_scaling_reference() -> Result<()> {
+    use candle_nn::rotary_emb::{rope_cos_sin, RopeScaling};
+
+    let assert_close = |vs: &[f32], expected: &[f32]| -> Result<()> {
+        let vs = Tensor::new(vs, &Device::Cpu)?;
+        let expected = Tensor::new(expected, &Device::Cpu)?;
+        test_utils::assert_close(&vs, &expected, 1e-4, 0.);
+        Ok(())
+    };
+    // The reference values have been computed in python following the transformers rope
+    // initialization functions with head_dim 16, base 10000 and original_max_position_embeddings
+    // set to 64.
+    // For YaRN, the ramp goes from dimension 0 to dimension 3: the first frequency is left
+    // unchanged, the frequencies from dimension 3 are divided by the factor.
+    let yarn = RopeScaling::Yarn {
+        factor: 4.0,
+        original_max_position_embeddings: 64,
+        beta_fast: 32.,
+        beta_slow: 1.,
+        attention_factor: None,
+    };
+    let (inv_freq, attention_factor) = yarn.inv_freq(16, 10000., 256);
+    assert_close(
+        &inv_freq,
+        &[
+            1.00000e+00,
+            2.37171e-01,
+            5.00000e-02,
+            7.90569e-03,
+            2.50000e-03,
+            7.90569e-04,
+            2.50000e-04,
+            7.90569e-05,
+        ],
+    )?;
+    assert_close(&[attention_factor], &[1.138629])?;
+    let (cos, sin) = rope_cos_sin(16, 10000., 8, Some(&yarn), candle::DType::F32, &Device::Cpu)?;
+    assert_close(&cos.get(0)?.to_vec1::<f32>()?, &[1.138629; 8])?;
+    assert_eq!(sin.get(0)?.to_vec1::<f32>()?, [0.; 8]);
+
+    let dynamic = RopeScaling::Dynamic {
+        factor: 4.0,
+        original_max_position_embeddings: 64,
+    };
+    let (inv_freq, attention_factor) = dynamic.inv_freq(16, 10000., 128);
+    assert_close(
+        &inv_freq,
+        &[
+            1.00000e+00,
+            2.51274e-01,
+            6.31385e-02,
+            1.58650e-02,
+            3.98647e-03,
+            1.00170e-03,
+            2.51700e-04,
+            6.32456e-05,
+        ],
+    )?;
+    assert_eq!(attention_factor, 1.);
+    // Below the original context size, dynamic scaling does not change the frequencies.
+    let (inv_freq, _) = dynamic.inv_freq(16, 10000., 32);
+    let (expected, _) = RopeScaling::Linear { factor: 1.0 }.inv_freq(16, 10000., 32);
+    assert_close(&inv_freq, &expected)?;
+
+    // The wavelength of the second frequency is ~32.4, between 64 / 4 and 64 / 1 so it gets
+    // interpolated, the first one is kept and the others are divided by the factor.
+    let llama3 = RopeScaling::Llama3 {
+        factor: 8.0,
+        low_freq_factor: 1.0,
+        high_freq_factor: 4.0,
+        original_max_position_embeddings: 64,
+    };
+    let (inv_freq, attention_factor) = llama3.inv_freq(16, 500000., 256);
+    assert_close(
+        &inv_freq,
+        &[
+            1.00000e+00,
+            7.94030e-02,
+            4.70075e-03,
+            9.11583e-04,
+            1.76777e-04,
+            3.42810e-05,
+            6.64787e-06,
+            1.28917e-06,
+        ],
+    )?;
+    assert_eq!(attention_factor, 1.);
+    Ok(())
+}
+
+#[test]
+fn rope_tables_dynamic() -> Result<()> {
+    use candle_nn::rotary_emb::{rope_cos_sin, RopeScaling, RopeTables};
+
+    let (dev, dtype) = (&Device::Cpu, candle::DType::F32);
+    let dynamic = RopeScaling::Dynamic {
+        factor: 4.0,
+        original_max_position_embeddings: 16,
+    };
+    let tables = RopeTables::new(8, 10000., 64, Some(dynamic.clone()), dtype, dev)?;
+    let (unscaled_cos, unscaled_sin) = rope_cos_sin(8, 10000., 64, None, dtype, dev)?;
+    // While the sequence fits in the original context, the frequencies are not scaled.
+    let (cos, sin) = tables.narrow(10, 6)?;
+    test_utils::assert_close(&cos, &unscaled_cos.narrow(0, 10, 6)?, 0., 1e-6);
+    test_utils::assert_close(&sin, &unscaled_sin.narrow(0, 10, 6)?, 0., 1e-6);
+    // Past that, the frequencies follow the actual sequence length and not the table size.
+    for (index_pos, seq_len) in [(16, 1), (20, 4), (60, 4), (64, 8)] {
+        let total_len = index_pos + seq_len;
+        let (cos, sin) = tables.narrow(index_pos, seq_len)?;
+        let (e_cos, e_sin) = rope_cos_sin(8, 10000., total_len, Some(&dynamic), dtype, dev)?;
+        test_utils::assert_close(&cos, &e_cos.narrow(0, index_pos, seq_len)?, 0., 1e-6);
+        test_utils::assert_close(&sin, &e_sin.narrow(0, index_pos, seq_len)?, 0., 1e-6);
+    }
+    let (cos, _) = tables.narrow(20, 4)?;
+    assert!(!test_utils::allclose(
+        &cos,
+        &unscaled_cos.narrow(0, 20, 4)?,
+        0.,
+        1e-3
+    )?);
+    Ok(())
+}
+
 fn sigmoid(device: &Device) -> Result<()> {
     let data = &[[[3f32, 1., 4.], [1., 5., 9.]], [[2., 1., 7.], [8., 2., 8.]]];
     let tensor = Tensor::new(data, device)?;
@@ -206,10 +550,117 @@ fn sigmoid(device: &Device) -> Result<()> {
     Ok(())
 }
 
+fn scaled_dot_product_attention(device: &Device) -> Result<()> {
+    use candle::D;
+    let q = Tensor::randn(0f32, 1., (2, 3, 4, 8), device)?;
+    let k = Tensor::randn(0f32, 1., (2, 3, 6, 8), device)?;
+    let v = Tensor::randn(0f32, 1., (2, 3, 6, 5), device)?;
+    let manual = |mask: Option<&Tensor>, scale: f64| -> Result<Tensor> {
+        let att = (q.matmul(&k.t()?)? * scale)?;
+        let att = match mask {
+            None => att,
+            Some(mask) => att.broadcast_add(mask)?,
+        };
+        candle_nn::ops::softmax(&att, D::Minus1)?.matmul(&v)
+    };
+
+    let sdpa = candle_nn::ops::scaled_dot_product_attention(&q, &k, &v, None, None, false)?;
+    assert_eq!(sdpa.dims(), [2, 3, 4, 5]);
+    let expected = manual(None, 1. / 8f64.sqrt())?;
+    test_utils::assert_close(&sdpa, &expected, 0., 1e-5);
+
+    // The causal mask aligns the last query with the last key.
+    let causal_mask: Vec<f32> = (0..4)
+        .flat_map(|i| (0..6).map(move |j| if j > i + 2 { f32::NEG_INFINITY } else { 0. }))
+        .collect();
+    let causal_mask = Tensor::from_vec(causal_mask, (4, 6), device)?;
+    let sdpa = candle_nn::ops::scaled_dot_product_attention(&q, &k, &v, None, Some(0.5), true)?;
+    let expected = manual(Some(&causal_mask), 0.5)?;
+    test_utils::assert_close(&sdpa, &expected, 0., 1e-5);
+
+    // An additive mask that is broadcasted over the batch and heads, combined with causal.
+    let mask = Tensor::new(&[0f32, -1., 0., f32::NEG_INFINITY, 2., 0.], device)?;
+    let sdpa = candle_nn::ops::scaled_dot_product_attention(&q, &k, &v, Some(&mask), None, true)?;
+    let expected = manual(Some(&causal_mask.broadcast_add(&mask)?), 1. / 8f64.sqrt())?;
+    test_utils::assert_close(&sdpa, &expected, 0., 1e-5);
+    Ok(())
+}
+
+fn pixel_shuffle(device: &Device) -> Result<()> {
+    let xs = Tensor::arange(0f32, 8f32, device)?.reshape((1, 4, 1, 2))?;
+    let ys = candle_nn::ops::pixel_shuffle(&xs, 2)?;
+    assert_eq!(
+        ys.squeeze(0)?.squeeze(0)?.to_vec2::<f32>()?,
+        [[0., 2., 1., 3.], [4., 6., 5., 7.]]
+    );
+    let xs = Tensor::randn(0f32, 1., (2, 18, 4, 5), device)?;
+    let ys = candle_nn::ops::pixel_shuffle(&xs, 3)?;
+    assert_eq!(ys.dims(), [2, 2, 12, 15]);
+    let zs = candle_nn::ops::pixel_unshuffle(&ys, 3)?;
+    let diff = (zs - &xs)?.abs()?.sum_all()?.to_vec0::<f32>()?;
+    assert_eq!(diff, 0.);
+    assert!(candle_nn::ops::pixel_shuffle(&xs, 4).is_err());
+    assert!(candle_nn::ops::pixel_unshuffle(&xs, 2).is_err());
+    Ok(())
+}
+
+fn upsample(device: &Device) -> Result<()> {
+    use candle_nn::{Module, Upsample, UpsampleMode, UpsampleSize};
+    let xs = Tensor::new(&[[[[1f32, 2.], [3., 4.]]]], device)?;
+    let up = Upsample::new(
+        UpsampleSize::ScaleFactor(2., 2.),
+        UpsampleMode::Bilinear,
+        false,
+    )?;
+    let ys = up.forward(&xs)?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(
+        ys.to_vec2::<f32>()?,
+        [
+            [1.0, 1.25, 1.75, 2.0],
+            [1.5, 1.75, 2.25, 2.5],
+            [2.5, 2.75, 3.25, 3.5],
+            [3.0, 3.25, 3.75, 4.0]
+        ]
+    );
+    let up = Upsample::new(UpsampleSize::Size(3, 2), UpsampleMode::Bilinear, true)?;
+    let ys = up.forward(&xs)?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(ys.to_vec2::<f32>()?, [[1., 2.], [2., 3.], [3., 4.]]);
+    let up = Upsample::new(
+        UpsampleSize::ScaleFactor(1., 2.),
+        UpsampleMode::Nearest,
+        false,
+    )?;
+    let ys = up.forward(&xs)?.squeeze(0)?.squeeze(0)?;
+    assert_eq!(ys.to_vec2::<f32>()?, [[1., 1., 2., 2.], [3., 3., 4., 4.]]);
+    assert!(Upsample::new(UpsampleSize::Size(4, 4), UpsampleMode::Nearest, true).is_err());
+    Ok(())
+}
+
 test_device!(ropei, ropei_cpu, ropei_gpu, ropei_metal);
 test_device!(rope, rope_cpu, rope_gpu, rope_metal);
+test_device!(rope_qk, rope_qk_cpu, rope_qk_gpu, rope_qk_metal);
 test_device!(rope_thd, rope_thd_cpu, rope_thd_gpu, rope_thd_metal);
 test_device!(softmax, softmax_cpu, softmax_gpu, softmax_metal);
 test_device!(rms_norm, rms_norm_cpu, rms_norm_gpu, rms_norm_metal);
+test_device!(
+    rms_norm_large,
+    rms_norm_large_cpu,
+    rms_norm_large_gpu,
+    rms_norm_large_metal
+);
 test_device!(layer_norm, ln_cpu, ln_gpu, ln_metal);
 test_device!(sigmoid, sigmoid_cpu, sigmoid_gpu, sigmoid_metal);
+test_device!(scaled_dot_product_attention, sdpa_cpu, sdpa_gpu, sdpa_metal);
+test_device!(
+    scaled_dot_product_attention_fused,
+    sdpa_fused_cpu,
+    sdpa_fused_gpu,
+    sdpa_fused_metal
+);
+test_device!(
+    pixel_shuffle,
+    pixel_shuffle_cpu,
+    pixel_shuffle_gpu,
+    pixel_shuffle_metal
+);
+test_device!(upsample, upsample_cpu, upsample_gpu, upsample_metal);
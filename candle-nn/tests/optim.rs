@@ -8,7 +8,10 @@ use candle::test_utils::{to_vec0_round, to_vec2_round};
 
 use anyhow::Result;
 use candle::{DType, Device, Tensor, Var};
-use candle_nn::{AdamW, Linear, Module, Optimizer, ParamsAdamW, SGD};
+use candle_nn::{
+    Adafactor, Adagrad, Adam, AdamW, Linear, Lion, Module, Optimizer, ParamsAdafactor,
+    ParamsAdagrad, ParamsAdam, ParamsAdamW, ParamsLion, ParamsRMSProp, RMSProp, SGD,
+};
 
 #[test]
 fn sgd_optim() -> Result<()> {
@@ -158,3 +161,231 @@ fn adamw_linear_regression_varmap() -> Result<()> {
     assert_eq!(to_vec0_round(lin.bias().unwrap(), 4)?, 1.);
     Ok(())
 }
+
+#[test]
+fn sgd_frozen_varmap() -> Result<()> {
+    use candle_nn::Init::Const;
+
+    let dev = &Device::Cpu;
+    let var_map = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&var_map, DType::F32, dev);
+    let w1 = vb.get_with_hints((2, 2), "layer1.weight", Const(1.))?;
+    let w2 = vb.get_with_hints((1, 2), "layer2.weight", Const(1.))?;
+    var_map.set_trainable("layer1", false);
+    assert!(!var_map.is_trainable("layer1.weight"));
+    assert!(var_map.is_trainable("layer2.weight"));
+    let trainable = var_map.trainable_vars();
+    assert_eq!(trainable.len(), 1);
+    assert_eq!(var_map.all_vars().len(), 2);
+
+    let xs = Tensor::new(&[[2f32, 1.], [7., 4.]], dev)?;
+    let loss = xs.matmul(&w1.t()?)?.matmul(&w2.t()?)?.sqr()?.sum_all()?;
+    let grads = loss.backward_for(&trainable.iter().collect::<Vec<_>>())?;
+    assert!(grads.get(&w1).is_none());
+    assert!(grads.get(&w2).is_some());
+
+    let mut sgd = SGD::new(trainable, 0.01)?;
+    sgd.backward_step(&loss)?;
+    assert_eq!(w1.to_vec2::<f32>()?, &[[1., 1.], [1., 1.]]);
+    assert_ne!(w2.to_vec2::<f32>()?, &[[1., 1.]]);
+
+    var_map.set_trainable("layer1", true);
+    assert_eq!(var_map.trainable_vars().len(), 2);
+    Ok(())
+}
+
+// Runs `steps` optimizer steps on the quadratic `(x - 4.2)^2` starting from `x = 0`.
+fn quadratic_steps<O: Optimizer>(config: O::Config, steps: usize) -> Result<f32> {
+    let x = Var::new(0f32, &Device::Cpu)?;
+    let mut opt = O::new(vec![x.clone()], config)?;
+    let xt = x.as_tensor();
+    for _step in 0..steps {
+        let loss = ((xt - 4.2)? * (xt - 4.2)?)?;
+        opt.backward_step(&loss)?
+    }
+    Ok(x.to_scalar::<f32>()?)
+}
+
+fn round4(v: f32) -> f32 {
+    (v * 1e4).round() / 1e4
+}
+
+#[test]
+fn adam_optim() -> Result<()> {
+    let params = ParamsAdam {
+        lr: 0.1,
+        ..Default::default()
+    };
+    // The first step has a size of lr as the gradient is normalized by its magnitude.
+    assert_eq!(round4(quadratic_steps::<Adam>(params.clone(), 1)?), 0.1);
+    assert_eq!(round4(quadratic_steps::<Adam>(params.clone(), 3)?), 0.2997);
+    let with_decay = ParamsAdam {
+        weight_decay: 0.5,
+        ..params.clone()
+    };
+    assert_eq!(round4(quadratic_steps::<Adam>(with_decay, 2)?), 0.1999);
+    let x = quadratic_steps::<Adam>(params, 500)?;
+    assert!((x - 4.2).abs() < 1e-3, "{x}");
+    Ok(())
+}
+
+#[test]
+fn rmsprop_optim() -> Result<()> {
+    let params = ParamsRMSProp::default();
+    assert_eq!(round4(quadratic_steps::<RMSProp>(params.clone(), 1)?), 0.1);
+    assert_eq!(round4(quadratic_steps::<RMSProp>(params.clone(), 2)?), 0.17);
+    let centered = ParamsRMSProp {
+        centered: true,
+        ..params.clone()
+    };
+    assert_eq!(round4(quadratic_steps::<RMSProp>(centered, 2)?), 0.1712);
+    let momentum = ParamsRMSProp {
+        momentum: 0.9,
+        ..params.clone()
+    };
+    assert_eq!(round4(quadratic_steps::<RMSProp>(momentum, 2)?), 0.26);
+    let all = ParamsRMSProp {
+        centered: true,
+        momentum: 0.5,
+        weight_decay: 0.1,
+        ..params.clone()
+    };
+    assert_eq!(round4(quadratic_steps::<RMSProp>(all, 2)?), 0.2214);
+    let x = quadratic_steps::<RMSProp>(params, 1000)?;
+    assert!((x - 4.2).abs() < 1e-3, "{x}");
+    Ok(())
+}
+
+#[test]
+fn adagrad_optim() -> Result<()> {
+    let params = ParamsAdagrad {
+        lr: 0.1,
+        ..Default::default()
+    };
+    assert_eq!(round4(quadratic_steps::<Adagrad>(params.clone(), 1)?), 0.1);
+    assert_eq!(
+        round4(quadratic_steps::<Adagrad>(params.clone(), 3)?),
+        0.2265
+    );
+    let decay = ParamsAdagrad {
+        lr_decay: 0.5,
+        initial_accumulator_value: 0.1,
+        ..params
+    };
+    assert_eq!(round4(quadratic_steps::<Adagrad>(decay, 3)?), 0.1749);
+    let params = ParamsAdagrad {
+        lr: 1.,
+        ..Default::default()
+    };
+    let x = quadratic_steps::<Adagrad>(params, 500)?;
+    assert!((x - 4.2).abs() < 1e-3, "{x}");
+    Ok(())
+}
+
+#[test]
+fn lion_optim() -> Result<()> {
+    let params = ParamsLion {
+        lr: 0.01,
+        ..Default::default()
+    };
+    // The update is the sign of the gradient so the first steps have a size of lr.
+    assert_eq!(round4(quadratic_steps::<Lion>(params.clone(), 1)?), 0.01);
+    assert_eq!(round4(quadratic_steps::<Lion>(params.clone(), 3)?), 0.03);
+    let x = quadratic_steps::<Lion>(params, 1000)?;
+    assert!((x - 4.2).abs() < 0.05, "{x}");
+    Ok(())
+}
+
+#[test]
+fn adafactor_optim() -> Result<()> {
+    let params = ParamsAdafactor {
+        lr: Some(0.1),
+        relative_step: false,
+        scale_parameter: false,
+        ..Default::default()
+    };
+    // The first update is normalized and clipped to a root mean square of 1.
+    assert_eq!(
+        round4(quadratic_steps::<Adafactor>(params.clone(), 1)?),
+        0.1
+    );
+    let x = quadratic_steps::<Adafactor>(params.clone(), 500)?;
+    assert!((x - 4.2).abs() < 1e-2, "{x}");
+
+    // Fit a matrix parameter, the second moment is factored in this case.
+    let target = Tensor::new(&[[1f32, -2., 3.], [0.5, 4., -1.]], &Device::Cpu)?;
+    let w = Var::zeros((2, 3), DType::F32, &Device::Cpu)?;
+    let mut opt = Adafactor::new(vec![w.clone()], params)?;
+    assert_eq!(opt.state_elem_count(), 5);
+    for _step in 0..500 {
+        let loss = (w.as_tensor() - &target)?.sqr()?.sum_all()?;
+        opt.backward_step(&loss)?;
+    }
+    let diff = (w.as_tensor() - &target)?.abs()?.flatten_all()?.max(0)?;
+    assert!(diff.to_scalar::<f32>()? < 1e-2, "{w}");
+
+    // With the default relative step size the parameters are updated too.
+    let w = Var::ones((3, 2), DType::F32, &Device::Cpu)?;
+    let mut opt = Adafactor::new(vec![w.clone()], Default::default())?;
+    let loss = w.as_tensor().sqr()?.sum_all()?;
+    opt.backward_step(&loss)?;
+    assert!(w.as_tensor().flatten_all()?.max(0)?.to_scalar::<f32>()? < 1.);
+    assert!(Adafactor::new(
+        vec![],
+        ParamsAdafactor {
+            relative_step: false,
+            ..Default::default()
+        }
+    )
+    .is_err());
+    Ok(())
+}
+
+#[test]
+fn adafactor_state_size() -> Result<()> {
+    let w = Var::zeros((1000, 2000), DType::F32, &Device::Cpu)?;
+    let b = Var::zeros(2000, DType::F32, &Device::Cpu)?;
+    let opt = Adafactor::new(vec![w.clone(), b], Default::default())?;
+    // Factored rows and columns for the matrix, full second moment for the vector.
+    assert_eq!(opt.state_elem_count(), 1000 + 2000 + 2000);
+    assert!(opt.state_elem_count() < w.elem_count() / 100);
+    let adam = ParamsAdafactor {
+        beta1: Some(0.9),
+        ..Default::default()
+    };
+    let opt = Adafactor::new(vec![w.clone()], adam)?;
+    assert_eq!(opt.state_elem_count(), 1000 + 2000 + w.elem_count());
+    Ok(())
+}
+
+#[test]
+fn clip_grad() -> Result<()> {
+    let w1 = Var::new(&[3f32, 0.], &Device::Cpu)?;
+    let w2 = Var::new(&[[0f32, 4.]], &Device::Cpu)?;
+    // The gradients are (300, 0) and (0, 400), with a total 2-norm of 500.
+    let loss = ((w1.as_tensor().sqr()?.sum_all()? + w2.as_tensor().sqr()?.sum_all()?)? * 50.)?;
+    let mut grads = loss.backward()?;
+    let norm = candle_nn::clip_grad_norm(&mut grads, 1.0, 2.0)?;
+    assert_eq!(norm, 500.);
+    let g1 = grads.get(&w1).unwrap();
+    let g2 = grads.get(&w2).unwrap();
+    assert_eq!(g1.to_vec1::<f32>()?, [0.6, 0.]);
+    assert_eq!(g2.to_vec2::<f32>()?, [[0., 0.8]]);
+    let norm = (g1.sqr()?.sum_all()? + g2.sqr()?.sum_all()?)?.sqrt()?;
+    assert_eq!(to_vec0_round(&norm, 4)?, 1.0);
+    // Norms below the threshold leave the gradients untouched.
+    let norm = candle_nn::clip_grad_norm(&mut grads, 10.0, 2.0)?;
+    assert_eq!((norm * 1e4).round(), 1e4);
+    assert_eq!(grads.get(&w1).unwrap().to_vec1::<f32>()?, [0.6, 0.]);
+
+    let mut grads = loss.backward()?;
+    let norm = candle_nn::clip_grad_norm(&mut grads, 100.0, f64::INFINITY)?;
+    assert_eq!(norm, 400.);
+    assert_eq!(grads.get(&w1).unwrap().to_vec1::<f32>()?, [75., 0.]);
+
+    let mut grads = loss.backward()?;
+    candle_nn::clip_grad_value(&mut grads, 350.)?;
+    assert_eq!(grads.get(&w1).unwrap().to_vec1::<f32>()?, [300., 0.]);
+    assert_eq!(grads.get(&w2).unwrap().to_vec2::<f32>()?, [[0., 350.]]);
+    Ok(())
+}
@@ -0,0 +1,80 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{DType, Device, Result, Tensor, D};
+use candle_nn::{Int8Scheme, Linear, Module, QuantizedLinear};
+
+// The relative error between `ys` and the reference `expected`.
+fn rel_error(ys: &Tensor, expected: &Tensor) -> Result<f32> {
+    let diff = (ys - expected)?.sqr()?.sum_all()?.sqrt()?;
+    let norm = expected.sqr()?.sum_all()?.sqrt()?;
+    (diff / norm)?.to_vec0::<f32>()
+}
+
+#[test]
+fn i8_matmul() -> Result<()> {
+    let lhs = [1i8, -2, 3, 4, 5, -6];
+    let rhs = [7i8, 8, 9, -10, 11, 12, 127, -127, 127];
+    let mut dst = [0i32; 6];
+    candle::cpu::i8_matmul((2, 3, 3), &lhs, &rhs, &mut dst)?;
+    assert_eq!(dst, [18, 4, 762, 14, -57, -889]);
+    assert!(candle::cpu::i8_matmul((2, 3, 2), &lhs, &rhs, &mut dst).is_err());
+    Ok(())
+}
+
+#[test]
+fn quantized_linear() -> Result<()> {
+    let dev = &Device::Cpu;
+    let w = Tensor::randn(0f32, 1., (64, 128), dev)?;
+    let b = Tensor::randn(0f32, 1., 64, dev)?;
+    let linear = Linear::new(w, Some(b));
+    let xs = Tensor::randn(0f32, 1., (2, 5, 128), dev)?;
+    let expected = linear.forward(&xs)?;
+    for scheme in [Int8Scheme::PerTensor, Int8Scheme::PerChannel] {
+        let qlinear = QuantizedLinear::from_linear(&linear, scheme)?;
+        let ys = qlinear.forward(&xs)?;
+        assert_eq!(ys.dims(), [2, 5, 64]);
+        let err = rel_error(&ys, &expected)?;
+        assert!(err < 0.02, "{scheme:?} {err}");
+
+        // Other dtypes use the dequantized weights.
+        let ys64 = qlinear.forward(&xs.to_dtype(DType::F64)?)?;
+        assert_eq!(ys64.dtype(), DType::F64);
+        let err = rel_error(&ys64.to_dtype(DType::F32)?, &expected)?;
+        assert!(err < 0.02, "{scheme:?} {err}");
+    }
+    let qlinear = QuantizedLinear::from_linear(&linear, Int8Scheme::PerChannel)?;
+    assert!(qlinear.forward(&xs.narrow(D::Minus1, 0, 64)?).is_err());
+    Ok(())
+}
+
+#[test]
+fn quantized_linear_per_channel() -> Result<()> {
+    // The first output channel has much larger weights than the others, with a single scale the
+    // weights of the other channels mostly round to zero.
+    let dev = &Device::Cpu;
+    let w = Tensor::randn(0f32, 1., (16, 64), dev)?;
+    let channel_scales = Tensor::cat(
+        &[
+            Tensor::new(&[100f32], dev)?,
+            Tensor::ones(15, DType::F32, dev)?,
+        ],
+        0,
+    )?;
+    let w = w.broadcast_mul(&channel_scales.unsqueeze(1)?)?;
+    let linear = Linear::new(w, None);
+    let xs = Tensor::randn(0f32, 1., (8, 64), dev)?;
+    // Only consider the channels with small weights.
+    let expected = linear.forward(&xs)?.narrow(1, 1, 15)?;
+
+    let per_tensor = QuantizedLinear::from_linear(&linear, Int8Scheme::PerTensor)?;
+    let per_tensor = rel_error(&per_tensor.forward(&xs)?.narrow(1, 1, 15)?, &expected)?;
+    let per_channel = QuantizedLinear::from_linear(&linear, Int8Scheme::PerChannel)?;
+    let per_channel = rel_error(&per_channel.forward(&xs)?.narrow(1, 1, 15)?, &expected)?;
+    assert!(per_channel < 0.02, "{per_channel}");
+    assert!(per_tensor > 10. * per_channel, "{per_tensor} {per_channel}");
+    Ok(())
+}
@@ -4,7 +4,10 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
-use candle::{test_utils::to_vec2_round, DType, Device, Result, Tensor};
+use candle::{
+    test_utils::{self, to_vec2_round},
+    DType, Device, IndexOp, Result, Tensor,
+};
 use candle_nn::RNN;
 
 /* The following test can be verified against PyTorch using the following snippet.
@@ -99,3 +102,48 @@ fn gru() -> Result<()> {
     assert_eq!(to_vec2_round(h, 4)?, &[[0.0579, 0.8836, -0.9991]]);
     Ok(())
 }
+
+#[test]
+fn stacked_bidirectional_lstm() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, cpu);
+    let lstm = candle_nn::stacked_lstm(2, 3, 2, true, Default::default(), vb.clone())?;
+    assert_eq!(lstm.num_layers(), 2);
+    assert!(lstm.is_bidirectional());
+    let xs = Tensor::randn(0f32, 1., (4, 5, 2), cpu)?;
+    let (ys, states) = lstm.forward(&xs)?;
+    assert_eq!(ys.dims(), [4, 5, 6]);
+    assert_eq!(states.len(), 4);
+    for state in states.iter() {
+        assert_eq!(state.h().dims(), [4, 3]);
+        assert_eq!(state.c().dims(), [4, 3]);
+    }
+    // The last forward state is the last output step, the last backward state the first step.
+    test_utils::assert_close(&ys.i((.., 4, ..3))?, states[2].h(), 0., 0.);
+    test_utils::assert_close(&ys.i((.., 0, 3..))?, states[3].h(), 0., 0.);
+
+    // The backward direction of the first layer is a regular lstm applied on the reversed input.
+    let lstm = candle_nn::stacked_lstm(2, 3, 1, true, Default::default(), vb.clone())?;
+    let (ys, _) = lstm.forward(&xs)?;
+    let config = candle_nn::LSTMConfig::default().with_direction(candle_nn::Direction::Backward);
+    let backward = candle_nn::lstm(2, 3, config, vb)?;
+    let states = backward.seq(&xs.flip(&[1])?)?;
+    let expected = backward.states_to_tensor(&states)?.flip(&[1])?;
+    test_utils::assert_close(&ys.i((.., .., 3..))?, &expected, 0., 1e-6);
+    Ok(())
+}
+
+#[test]
+fn stacked_gru() -> Result<()> {
+    let cpu = &Device::Cpu;
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, cpu);
+    let gru = candle_nn::stacked_gru(2, 3, 3, false, Default::default(), vb)?;
+    assert!(!gru.is_bidirectional());
+    let xs = Tensor::randn(0f32, 1., (4, 5, 2), cpu)?;
+    let (ys, states) = gru.forward(&xs)?;
+    assert_eq!(ys.dims(), [4, 5, 3]);
+    assert_eq!(states.len(), 3);
+    Ok(())
+}
@@ -0,0 +1,85 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{Device, Result, Var};
+use candle_nn::schedule::{
+    CosineAnnealing, CosineWithWarmup, LinearWarmup, LrScheduler, OneCycle, StepDecay,
+};
+use candle_nn::{Optimizer, SGD};
+
+fn approx(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-12
+}
+
+#[test]
+fn cosine_with_warmup() {
+    let sched = CosineWithWarmup::new(1.0, 0.1, 4, 14);
+    let lrs: Vec<f64> = [0, 1, 2, 3, 4].iter().map(|&s| sched.get_lr(s)).collect();
+    assert_eq!(lrs, [0.0, 0.25, 0.5, 0.75, 1.0]);
+    // Halfway through the decay, the learning rate is midway between the peak and the floor.
+    assert!(approx(sched.get_lr(9), 0.55));
+    // A fifth of the decay: 0.1 + 0.9 * (1 + cos(pi / 5)) / 2
+    assert!(approx(sched.get_lr(6), 0.9140576474687264));
+    assert!(approx(sched.get_lr(14), 0.1));
+    assert!(approx(sched.get_lr(100), 0.1));
+}
+
+#[test]
+fn other_schedules() {
+    let sched = LinearWarmup::new(0.5, 5);
+    assert_eq!(sched.get_lr(0), 0.0);
+    assert_eq!(sched.get_lr(2), 0.2);
+    assert_eq!(sched.get_lr(5), 0.5);
+    assert_eq!(sched.get_lr(50), 0.5);
+
+    let sched = CosineAnnealing::new(1.0, 0.0, 10);
+    assert_eq!(sched.get_lr(0), 1.0);
+    assert!(approx(sched.get_lr(5), 0.5));
+    assert!(approx(sched.get_lr(10), 0.0));
+
+    let sched = StepDecay::new(1.0, 3, 0.5);
+    let lrs: Vec<f64> = (0..7).map(|s| sched.get_lr(s)).collect();
+    assert_eq!(lrs, [1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.25]);
+
+    let sched = OneCycle::new(1.0, 11);
+    assert!(approx(sched.get_lr(0), 0.04));
+    let peak = (0..11).map(|s| sched.get_lr(s)).fold(0f64, f64::max);
+    assert!(peak > 0.9 && peak <= 1.0);
+    assert!(approx(sched.get_lr(10), 4e-6));
+}
+
+#[test]
+fn one_cycle_short_warmup() {
+    // pct_start * total_steps - 1 == 0, the warmup phase has a length of zero.
+    let sched = OneCycle::new(1.0, 10);
+    let sched = OneCycle {
+        pct_start: 0.1,
+        ..sched
+    };
+    assert!(approx(sched.get_lr(0), 1.0));
+    assert!(approx(sched.get_lr(9), 4e-6));
+    // The whole schedule is the warmup phase.
+    let sched = OneCycle {
+        pct_start: 1.0,
+        ..sched
+    };
+    assert!(approx(sched.get_lr(9), 1.0));
+    assert!(approx(sched.get_lr(10), 4e-6));
+    for total_steps in 1..20 {
+        let sched = OneCycle::new(1.0, total_steps);
+        assert!((0..=total_steps).all(|s| sched.get_lr(s).is_finite()));
+    }
+}
+
+#[test]
+fn scheduler_drives_optimizer() -> Result<()> {
+    let w = Var::new(&[1f32], &Device::Cpu)?;
+    let mut sgd = SGD::new(vec![w], 1.0)?;
+    let sched = StepDecay::new(0.1, 2, 0.1);
+    sched.apply(2, &mut sgd);
+    assert!(approx(sgd.learning_rate(), 0.01));
+    Ok(())
+}
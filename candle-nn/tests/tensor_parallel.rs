@@ -0,0 +1,163 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{test_utils, Device, Module, Result, Tensor};
+use candle_nn::tensor_parallel::{Comm, LocalComm};
+use candle_nn::{Linear, TensorParallelColumnLinear, TensorParallelRowLinear};
+use std::sync::Arc;
+
+// Runs `f` for each rank of a process group in its own thread and returns the results in rank
+// order.
+fn run_ranks<F>(world_size: usize, f: F) -> Result<Vec<Tensor>>
+where
+    F: Fn(Arc<dyn Comm + Send + Sync>) -> Result<Tensor> + Send + Sync + Clone + 'static,
+{
+    let handles: Vec<_> = LocalComm::new(world_size)
+        .into_iter()
+        .map(|comm| {
+            let f = f.clone();
+            std::thread::spawn(move || f(Arc::new(comm)))
+        })
+        .collect();
+    handles.into_iter().map(|h| h.join().unwrap()).collect()
+}
+
+#[test]
+fn tensor_parallel_mlp() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (in_dim, hidden_dim, out_dim) = (6, 12, 5);
+    let l1 = Linear::new(
+        Tensor::randn(0f32, 1., (hidden_dim, in_dim), dev)?,
+        Some(Tensor::randn(0f32, 1., hidden_dim, dev)?),
+    );
+    let l2 = Linear::new(
+        Tensor::randn(0f32, 1., (out_dim, hidden_dim), dev)?,
+        Some(Tensor::randn(0f32, 1., out_dim, dev)?),
+    );
+    let xs = Tensor::randn(0f32, 1., (2, 3, in_dim), dev)?;
+    let hidden = l1.forward(&xs)?.relu()?;
+    let expected = l2.forward(&hidden)?;
+
+    for world_size in [1, 2, 3] {
+        let (l1, l2, xs) = (l1.clone(), l2.clone(), xs.clone());
+        let ys = run_ranks(world_size, move |comm| {
+            let l1 = TensorParallelColumnLinear::from_dense(&l1, comm.clone())?;
+            let l2 = TensorParallelRowLinear::from_dense(&l2, comm)?;
+            let hidden = l1.forward(&xs)?.relu()?;
+            assert_eq!(hidden.dims(), [2, 3, hidden_dim / world_size]);
+            l2.forward(&hidden)
+        })?;
+        for ys in ys {
+            assert_eq!(ys.dims(), [2, 3, out_dim]);
+            test_utils::assert_close(&ys, &expected, 0., 1e-4);
+        }
+    }
+
+    // The column slices can also be gathered on each rank.
+    let expected = l1.forward(&xs)?;
+    let ys = run_ranks(2, move |comm| {
+        let l1 = TensorParallelColumnLinear::from_dense(&l1, comm)?;
+        l1.forward_gathered(&xs)
+    })?;
+    for ys in ys {
+        test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    }
+    Ok(())
+}
+
+#[test]
+fn local_comm() -> Result<()> {
+    // Several collective operations in a row, the results are on the device of each rank.
+    let ys = run_ranks(3, |comm| {
+        let rank = comm.rank() as f32;
+        let xs = Tensor::new(&[[rank, 1.]], &Device::Cpu)?;
+        let sum = comm.all_reduce(&xs)?;
+        let all = comm.all_gather(&xs, 0)?;
+        let sum2 = comm.all_reduce(&(xs * 2.)?)?;
+        Tensor::cat(&[sum, all, sum2], 0)
+    })?;
+    for ys in ys {
+        assert_eq!(
+            ys.to_vec2::<f32>()?,
+            &[[3., 3.], [0., 1.], [1., 1.], [2., 1.], [6., 6.]]
+        );
+    }
+
+    // The sharded dimension has to be divisible by the world size.
+    let ys = run_ranks(2, |comm| {
+        let linear = Linear::new(
+            Tensor::zeros((3, 4), candle::DType::F32, &Device::Cpu)?,
+            None,
+        );
+        assert!(TensorParallelColumnLinear::from_dense(&linear, comm.clone()).is_err());
+        assert!(TensorParallelRowLinear::from_dense(&linear, comm.clone()).is_ok());
+        comm.all_reduce(&Tensor::new(1f32, &Device::Cpu)?)
+    })?;
+    assert_eq!(ys[1].to_vec0::<f32>()?, 2.);
+    Ok(())
+}
+
+#[test]
+fn layers_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<TensorParallelColumnLinear>();
+    assert_send_sync::<TensorParallelRowLinear>();
+}
+
+#[cfg(feature = "nccl")]
+#[test]
+fn nccl_comm() -> Result<()> {
+    use candle_nn::tensor_parallel::NcclComm;
+    use cudarc::driver::CudaDevice;
+    use cudarc::nccl::safe::{Comm as NcclRawComm, Id};
+
+    let world_size = CudaDevice::count().map_err(candle::Error::wrap)? as usize;
+    let cpu = &Device::Cpu;
+    let (in_dim, hidden_dim, out_dim) = (8, 4 * world_size, 6);
+    let l1 = Linear::new(Tensor::randn(0f32, 1., (hidden_dim, in_dim), cpu)?, None);
+    let l2 = Linear::new(
+        Tensor::randn(0f32, 1., (out_dim, hidden_dim), cpu)?,
+        Some(Tensor::randn(0f32, 1., out_dim, cpu)?),
+    );
+    let xs = Tensor::randn(0f32, 1., (3, in_dim), cpu)?;
+    let expected = l2.forward(&l1.forward(&xs)?)?;
+
+    // Each rank uses its own device and thread, the communicators have to be created
+    // concurrently.
+    let id = Id::new().map_err(candle::Error::debug)?;
+    let handles: Vec<_> = (0..world_size)
+        .map(|rank| {
+            let (l1, l2, xs) = (l1.clone(), l2.clone(), xs.clone());
+            std::thread::spawn(move || -> Result<(Tensor, Tensor)> {
+                let cuda = CudaDevice::new(rank).map_err(candle::Error::wrap)?;
+                let comm = NcclRawComm::from_rank(cuda, rank, world_size, id)
+                    .map_err(|e| candle::Error::debug(e.0))?;
+                let comm: Arc<dyn Comm + Send + Sync> = Arc::new(NcclComm::new(comm));
+                let device = Device::new_cuda(rank)?;
+                let move_linear = |l: &Linear| -> Result<Linear> {
+                    let bias = l.bias().map(|b| b.to_device(&device)).transpose()?;
+                    Ok(Linear::new(l.weight().to_device(&device)?, bias))
+                };
+                let l1 = TensorParallelColumnLinear::from_dense(&move_linear(&l1)?, comm.clone())?;
+                let l2 = TensorParallelRowLinear::from_dense(&move_linear(&l2)?, comm)?;
+                let xs = xs.to_device(&device)?;
+                let ys = l2.forward(&l1.forward(&xs)?)?;
+                let gathered = l1.forward_gathered(&xs)?;
+                Ok((
+                    ys.to_device(&Device::Cpu)?,
+                    gathered.to_device(&Device::Cpu)?,
+                ))
+            })
+        })
+        .collect();
+    let expected_gathered = l1.forward(&xs)?;
+    for handle in handles {
+        let (ys, gathered) = handle.join().unwrap()?;
+        test_utils::assert_close(&ys, &expected, 0., 1e-4);
+        test_utils::assert_close(&gathered, &expected_gathered, 0., 1e-4);
+    }
+    Ok(())
+}
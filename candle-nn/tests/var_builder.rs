@@ -0,0 +1,151 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{DType, Device, Result, Tensor};
+use candle_nn::{Module, VarBuilder};
+use std::collections::HashMap;
+
+// A checkpoint using the hf naming scheme for a single attention block.
+fn checkpoint(dev: &Device) -> Result<Vec<u8>> {
+    let q = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?;
+    let q_b = Tensor::new(&[0.5f32, -0.5], dev)?;
+    let k = Tensor::new(&[[-1f32, 0.], [0., 1.]], dev)?;
+    let data = [
+        ("model.layers.0.self_attn.q_proj.weight", &q),
+        ("model.layers.0.self_attn.q_proj.bias", &q_b),
+        ("model.layers.0.self_attn.k_proj.weight", &k),
+    ];
+    let data = safetensors::tensor::serialize(data, &None).map_err(candle::Error::wrap)?;
+    Ok(data)
+}
+
+// A module tree that does not match the checkpoint naming.
+fn query_key(vb: VarBuilder, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+    let vb = vb.pp("attn");
+    let q = candle_nn::linear(2, 2, vb.pp("query"))?;
+    let k = candle_nn::linear_no_bias(2, 2, vb.pp("key"))?;
+    Ok((q.forward(xs)?, k.forward(xs)?))
+}
+
+#[test]
+fn rename_f() -> Result<()> {
+    let dev = &Device::Cpu;
+    let vb = VarBuilder::from_buffered_safetensors(checkpoint(dev)?, DType::F32, dev)?;
+    let vb = vb.rename_f(|name: &str| {
+        name.replace("blocks.0.attn.query", "model.layers.0.self_attn.q_proj")
+            .replace("blocks.0.attn.key", "model.layers.0.self_attn.k_proj")
+    });
+    let xs = Tensor::new(&[[1f32, 1.]], dev)?;
+    // The renaming applies to the full path, including the prefixes pushed before and after.
+    let (q, k) = query_key(vb.pp("blocks").pp("0"), &xs)?;
+    assert_eq!(q.to_vec2::<f32>()?, &[[3.5, 6.5]]);
+    assert_eq!(k.to_vec2::<f32>()?, &[[-1., 1.]]);
+    Ok(())
+}
+
+#[test]
+fn remap() -> Result<()> {
+    let dev = &Device::Cpu;
+    let vb = VarBuilder::from_buffered_safetensors(checkpoint(dev)?, DType::F32, dev)?;
+    let map: HashMap<String, String> = [
+        ("attn.query.weight", "self_attn.q_proj.weight"),
+        ("attn.query.bias", "self_attn.q_proj.bias"),
+        ("attn.key.weight", "self_attn.k_proj.weight"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (format!("model.layers.0.{k}"), format!("model.layers.0.{v}")))
+    .collect();
+    let vb = vb.pp("model").pp("layers").pp("0").remap(map);
+    let xs = Tensor::new(&[[1f32, 1.]], dev)?;
+    let (q, k) = query_key(vb.clone(), &xs)?;
+    assert_eq!(q.to_vec2::<f32>()?, &[[3.5, 6.5]]);
+    assert_eq!(k.to_vec2::<f32>()?, &[[-1., 1.]]);
+    assert!(!vb.contains_tensor("attn.value.weight"));
+    Ok(())
+}
+
+#[test]
+fn non_strict() -> Result<()> {
+    let dev = &Device::Cpu;
+    let q = Tensor::new(&[[1f32, 2.], [3., 4.]], dev)?;
+    let extra = Tensor::new(&[1f32], dev)?;
+    let tensors: HashMap<String, Tensor> = [
+        ("attn.query.weight", &q),
+        ("attn.value.weight", &q),
+        ("attn.rotary.inv_freq", &extra),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.clone()))
+    .collect();
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, dev);
+    assert!(query_key(vb.clone(), &q).is_err());
+
+    let (vb, report) = vb.non_strict(Some(candle_nn::Init::Const(0.5)));
+    let xs = Tensor::new(&[[1f32, 1.]], dev)?;
+    let (q, k) = query_key(vb, &xs)?;
+    assert_eq!(q.to_vec2::<f32>()?, &[[3.5, 7.5]]);
+    assert_eq!(k.to_vec2::<f32>()?, &[[1., 1.]]);
+    assert_eq!(report.missing(), ["attn.query.bias", "attn.key.weight"]);
+    assert_eq!(
+        report.unexpected(),
+        ["attn.rotary.inv_freq", "attn.value.weight"]
+    );
+    assert_eq!(report.var_map().all_vars().len(), 2);
+    Ok(())
+}
+
+#[test]
+fn non_strict_safetensors_reader() -> Result<()> {
+    let dev = &Device::Cpu;
+    let path = std::env::temp_dir().join(format!("candle-vb-{}.safetensors", std::process::id()));
+    std::fs::write(&path, checkpoint(dev)?)?;
+    let vb = VarBuilder::from_safetensors_reader(&path, DType::F32, dev)?;
+    let (vb, report) = vb.non_strict(Some(candle_nn::Init::Const(0.5)));
+    let xs = Tensor::new(&[[1f32, 1.]], dev)?;
+    let (q, _k) = query_key(vb.pp("model.layers.0"), &xs)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(q.to_vec2::<f32>()?, &[[1.5, 1.5]]);
+    assert_eq!(
+        report.missing(),
+        [
+            "model.layers.0.attn.query.weight",
+            "model.layers.0.attn.query.bias",
+            "model.layers.0.attn.key.weight"
+        ]
+    );
+    // The tensors in the file are listed even though the reader only loaded the header.
+    assert_eq!(
+        report.unexpected(),
+        [
+            "model.layers.0.self_attn.k_proj.weight",
+            "model.layers.0.self_attn.q_proj.bias",
+            "model.layers.0.self_attn.q_proj.weight"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn shape_mismatch_reports_path() -> Result<()> {
+    let dev = &Device::Cpu;
+    let tensors: HashMap<String, Tensor> = [(
+        "proj.weight".to_string(),
+        Tensor::zeros((3, 4), DType::F32, dev)?,
+    )]
+    .into_iter()
+    .collect();
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, dev);
+    let proj = candle_nn::linear_no_bias(4, 3, vb.pp("proj"))?;
+    let xs = Tensor::zeros((2, 5), DType::F32, dev)?;
+    let err = proj.forward(&xs).unwrap_err().to_string();
+    assert!(
+        err.starts_with(
+            "shape mismatch in matmul, lhs: [2, 5], rhs: [4, 3], lhs name: <unnamed>, rhs name: proj.weight"
+        ),
+        "{err}"
+    );
+    Ok(())
+}
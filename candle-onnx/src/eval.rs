@@ -674,6 +674,9 @@ fn simple_eval_(
                     DType::F16 => arange_step!(f32),
                     DType::F32 => arange_step!(f32),
                     DType::F64 => arange_step!(f64),
+                    dt @ (DType::C64 | DType::C128) => {
+                        bail!("unsupported dtype {} for Range", dt.as_str())
+                    }
                 };
 
                 values.insert(node.output[0].clone(), output);
@@ -711,6 +714,36 @@ fn simple_eval_(
 
                 values.insert(node.output[0].clone(), output);
             }
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Max
+            "Max" => {
+                let mut output = get(&node.input[0])?.clone();
+                for input in node.input.iter() {
+                    let input = get(input)?;
+                    output = output.broadcast_maximum(input)?
+                }
+
+                values.insert(node.output[0].clone(), output);
+            }
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Expand
+            "Expand" => {
+                let input = get(&node.input[0])?;
+                let shape = get(&node.input[1])?.to_vec1::<i64>()?;
+                // The target shape and the input shape are broadcasted together, a dimension of 1
+                // in the target shape keeps the input dimension.
+                let rank = usize::max(shape.len(), input.rank());
+                let mut dims = vec![1usize; rank];
+                for (i, &d) in shape.iter().rev().enumerate() {
+                    dims[rank - 1 - i] = d as usize
+                }
+                for (i, &d) in input.dims().iter().rev().enumerate() {
+                    let idx = rank - 1 - i;
+                    if dims[idx] == 1 {
+                        dims[idx] = d
+                    }
+                }
+                let output = input.broadcast_as(dims)?;
+                values.insert(node.output[0].clone(), output);
+            }
             // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Where
             "Where" => {
                 let cond = get(&node.input[0])?;
@@ -1156,6 +1189,42 @@ fn simple_eval_(
                 };
                 values.insert(node.output[0].clone(), output);
             }
+            // https://onnx.ai/onnx/operators/onnx__ReduceSum.html#reducesum-13
+            // https://onnx.ai/onnx/operators/onnx__ReduceMax.html#reducemax-13
+            // ReduceSum takes the axes as an optional input, ReduceMax and ReduceMin use an
+            // attribute up to version 13.
+            op @ ("ReduceSum" | "ReduceMax" | "ReduceMin") => {
+                let input = get(&node.input[0])?;
+                let axes = match node.input.get(1) {
+                    Some(axes) if !axes.is_empty() => Some(get(axes)?.to_vec1::<i64>()?),
+                    _ => get_attr_opt::<[i64]>(node, "axes")?.map(|v| v.to_vec()),
+                };
+                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
+                let n_dims = input.dims().len();
+                let axes: Vec<usize> = match axes {
+                    Some(axes) if !axes.is_empty() => axes
+                        .iter()
+                        .map(|e| (if e < &0 { (n_dims as i64) + *e } else { *e }) as usize)
+                        .collect(),
+                    _ => (0..n_dims).collect(),
+                };
+                let mut output = input.clone();
+                for &axis in axes.iter() {
+                    output = match op {
+                        "ReduceSum" => output.sum_keepdim(axis)?,
+                        "ReduceMax" => output.max_keepdim(axis)?,
+                        _ => output.min_keepdim(axis)?,
+                    }
+                }
+                if keepdims != 1 {
+                    let mut axes = axes;
+                    axes.sort();
+                    for &axis in axes.iter().rev() {
+                        output = output.squeeze(axis)?
+                    }
+                }
+                values.insert(node.output[0].clone(), output);
+            }
             random_type @ ("RandomUniform" | "RandomNormal") => {
                 let dt: i64 = get_attr_opt(node, "dtype")?.copied().unwrap_or(1); // 1 is float
                                                                                   // type by
@@ -1262,7 +1331,7 @@ fn simple_eval_(
                 let input = get(&node.input[0])?;
                 let dt = input.dtype();
                 match dt {
-                    DType::U8 | DType::U32 | DType::I64 => {
+                    DType::U8 | DType::U32 | DType::I64 | DType::C64 | DType::C128 => {
                         bail!(
                             "unsupported dtype {}, only float types are allowed for LeakyRelu",
                             dt.as_str()
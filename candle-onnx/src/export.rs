@@ -0,0 +1,520 @@
+//! Export of candle computations to ONNX.
+//!
+//! The graph is recovered by walking the operations recorded for backpropagation, starting from
+//! the outputs. The inputs are turned into variables during tracing so that all the operations
+//! depending on them get recorded. Tensors that do not depend on the inputs, e.g. the model
+//! weights, are stored as initializers.
+use crate::onnx::attribute_proto::AttributeType;
+use crate::onnx::tensor_proto::DataType;
+use crate::onnx::tensor_shape_proto::{dimension, Dimension};
+use crate::onnx::{self, type_proto, AttributeProto, NodeProto, TensorProto, ValueInfoProto};
+use candle::op::{BinaryOp, Op, ReduceOp, UnaryOp};
+use candle::{bail, DType, Result, Tensor, TensorId, Var};
+use prost::Message;
+use std::collections::HashMap;
+
+// Reductions use the axes attribute so the exported models target this opset version.
+const OPSET_VERSION: i64 = 17;
+
+fn data_type(dtype: DType) -> Result<DataType> {
+    let dt = match dtype {
+        DType::U8 => DataType::Uint8,
+        DType::U32 => DataType::Uint32,
+        DType::I64 => DataType::Int64,
+        DType::BF16 => DataType::Bfloat16,
+        DType::F16 => DataType::Float16,
+        DType::F32 => DataType::Float,
+        DType::F64 => DataType::Double,
+        dtype => bail!("unsupported dtype for onnx export {dtype:?}"),
+    };
+    Ok(dt)
+}
+
+fn tensor_proto(name: String, t: &Tensor) -> Result<TensorProto> {
+    let dims = t.dims().iter().map(|&d| d as i64).collect();
+    let data_type = data_type(t.dtype())?;
+    let flat = t.flatten_all()?;
+    let mut proto = TensorProto {
+        name,
+        dims,
+        data_type: data_type.into(),
+        ..TensorProto::default()
+    };
+    match t.dtype() {
+        DType::F32 => proto.float_data = flat.to_vec1::<f32>()?,
+        DType::F64 => proto.double_data = flat.to_vec1::<f64>()?,
+        DType::I64 => proto.int64_data = flat.to_vec1::<i64>()?,
+        DType::U8 => proto.raw_data = flat.to_vec1::<u8>()?,
+        DType::U32 => {
+            proto.raw_data = flat
+                .to_vec1::<u32>()?
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect()
+        }
+        DType::F16 => {
+            proto.raw_data = flat
+                .to_vec1::<half::f16>()?
+                .iter()
+                .flat_map(|v| v.to_bits().to_le_bytes())
+                .collect()
+        }
+        DType::BF16 => {
+            proto.raw_data = flat
+                .to_vec1::<half::bf16>()?
+                .iter()
+                .flat_map(|v| v.to_bits().to_le_bytes())
+                .collect()
+        }
+        dtype => bail!("unsupported dtype for onnx export {dtype:?}"),
+    }
+    Ok(proto)
+}
+
+fn value_info(name: &str, t: &Tensor) -> Result<ValueInfoProto> {
+    let dim = t
+        .dims()
+        .iter()
+        .map(|&d| Dimension {
+            denotation: "".to_string(),
+            value: Some(dimension::Value::DimValue(d as i64)),
+        })
+        .collect();
+    let tensor_type = type_proto::Tensor {
+        elem_type: data_type(t.dtype())?.into(),
+        shape: Some(onnx::TensorShapeProto { dim }),
+    };
+    Ok(ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(onnx::TypeProto {
+            value: Some(type_proto::Value::TensorType(tensor_type)),
+            denotation: "".to_string(),
+        }),
+        doc_string: "".to_string(),
+    })
+}
+
+fn attr_int(name: &str, i: i64) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Int.into(),
+        i,
+        ..AttributeProto::default()
+    }
+}
+
+fn attr_ints(name: &str, ints: Vec<i64>) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Ints.into(),
+        ints,
+        ..AttributeProto::default()
+    }
+}
+
+#[derive(Default)]
+struct Exporter {
+    // The name of the onnx value for each tensor that has already been processed.
+    names: HashMap<TensorId, String>,
+    nodes: Vec<NodeProto>,
+    initializers: Vec<TensorProto>,
+    // Tensors with an id above this one were created while running the traced function.
+    traced_from: Option<TensorId>,
+}
+
+impl Exporter {
+    fn fresh_name(&self, prefix: &str) -> String {
+        format!("{prefix}_{}", self.nodes.len() + self.initializers.len())
+    }
+
+    fn node(
+        &mut self,
+        op_type: &str,
+        input: Vec<String>,
+        attribute: Vec<AttributeProto>,
+    ) -> String {
+        let output = self.fresh_name(op_type);
+        self.nodes.push(NodeProto {
+            op_type: op_type.to_string(),
+            name: output.clone(),
+            input,
+            output: vec![output.clone()],
+            attribute,
+            ..NodeProto::default()
+        });
+        output
+    }
+
+    fn initializer(&mut self, prefix: &str, t: &Tensor) -> Result<String> {
+        let name = self.fresh_name(prefix);
+        self.initializers.push(tensor_proto(name.clone(), t)?);
+        Ok(name)
+    }
+
+    // A scalar constant with the same dtype as `like`.
+    fn scalar(&mut self, v: f64, like: &Tensor) -> Result<String> {
+        let t = Tensor::new(v, like.device())?.to_dtype(like.dtype())?;
+        self.initializer("const", &t)
+    }
+
+    fn ints(&mut self, vs: Vec<i64>) -> Result<String> {
+        let len = vs.len();
+        let t = Tensor::from_vec(vs, len, &candle::Device::Cpu)?;
+        self.initializer("const", &t)
+    }
+
+    fn cast(&mut self, input: String, dt: DataType) -> String {
+        self.node("Cast", vec![input], vec![attr_int("to", dt as i64)])
+    }
+
+    fn value(&mut self, t: &Tensor) -> Result<String> {
+        if let Some(name) = self.names.get(&t.id()) {
+            return Ok(name.clone());
+        }
+        let name = match t.op() {
+            None if t.is_variable() => self.initializer("param", t)?,
+            None => {
+                if self.traced_from.is_some_and(|id| t.id() > id) {
+                    // Exporting such a tensor as a constant would silently drop the part of the
+                    // graph that produced it.
+                    bail!(
+                        "onnx export: tensor {:?} was created while tracing without a recorded op, \
+                         e.g. by a custom op applied with apply_op*_no_bwd",
+                        t.id()
+                    )
+                }
+                self.initializer("const", t)?
+            }
+            Some(op) => self.op(t, op)?,
+        };
+        self.names.insert(t.id(), name.clone());
+        Ok(name)
+    }
+
+    fn op(&mut self, t: &Tensor, op: &Op) -> Result<String> {
+        let name = match op {
+            Op::Binary(lhs, rhs, op) => {
+                let op_type = match op {
+                    BinaryOp::Add => "Add",
+                    BinaryOp::Sub => "Sub",
+                    BinaryOp::Mul => "Mul",
+                    BinaryOp::Div => "Div",
+                    BinaryOp::Maximum => "Max",
+                    BinaryOp::Minimum => "Min",
+                };
+                let lhs = self.value(lhs)?;
+                let rhs = self.value(rhs)?;
+                self.node(op_type, vec![lhs, rhs], vec![])
+            }
+            Op::Unary(arg, op) => {
+                let x = self.value(arg)?;
+                let op_type = match op {
+                    UnaryOp::Exp => "Exp",
+                    UnaryOp::Log => "Log",
+                    UnaryOp::Sin => "Sin",
+                    UnaryOp::Cos => "Cos",
+                    UnaryOp::Abs => "Abs",
+                    UnaryOp::Neg => "Neg",
+                    UnaryOp::Sqrt => "Sqrt",
+                    UnaryOp::Erf => "Erf",
+                    UnaryOp::Relu => "Relu",
+                    UnaryOp::Tanh => "Tanh",
+                    UnaryOp::Floor => "Floor",
+                    UnaryOp::Ceil => "Ceil",
+                    UnaryOp::Sqr => return Ok(self.node("Mul", vec![x.clone(), x], vec![])),
+                    UnaryOp::Recip => {
+                        let one = self.scalar(1., arg)?;
+                        return Ok(self.node("Div", vec![one, x], vec![]));
+                    }
+                    UnaryOp::Silu => {
+                        let sigmoid = self.node("Sigmoid", vec![x.clone()], vec![]);
+                        return Ok(self.node("Mul", vec![x, sigmoid], vec![]));
+                    }
+                    UnaryOp::GeluErf => {
+                        // 0.5 * x * (1 + erf(x / sqrt(2)))
+                        let c = self.scalar(std::f64::consts::FRAC_1_SQRT_2, arg)?;
+                        let xs = self.node("Mul", vec![x.clone(), c], vec![]);
+                        let erf = self.node("Erf", vec![xs], vec![]);
+                        let one = self.scalar(1., arg)?;
+                        let erf = self.node("Add", vec![erf, one], vec![]);
+                        let half = self.scalar(0.5, arg)?;
+                        let xs = self.node("Mul", vec![x, half], vec![]);
+                        return Ok(self.node("Mul", vec![xs, erf], vec![]));
+                    }
+                    UnaryOp::Gelu => {
+                        // 0.5 * x * (1 + tanh(sqrt(2 / pi) * (x + 0.044715 * x^3)))
+                        let three = self.scalar(3., arg)?;
+                        let cube = self.node("Pow", vec![x.clone(), three], vec![]);
+                        let c = self.scalar(0.044715, arg)?;
+                        let cube = self.node("Mul", vec![cube, c], vec![]);
+                        let inner = self.node("Add", vec![x.clone(), cube], vec![]);
+                        let c = self.scalar((2. / std::f64::consts::PI).sqrt(), arg)?;
+                        let inner = self.node("Mul", vec![inner, c], vec![]);
+                        let tanh = self.node("Tanh", vec![inner], vec![]);
+                        let one = self.scalar(1., arg)?;
+                        let tanh = self.node("Add", vec![tanh, one], vec![]);
+                        let half = self.scalar(0.5, arg)?;
+                        let xs = self.node("Mul", vec![x, half], vec![]);
+                        return Ok(self.node("Mul", vec![xs, tanh], vec![]));
+                    }
+                    UnaryOp::Round | UnaryOp::Sign => {
+                        bail!("unsupported op for onnx export unary {op:?}")
+                    }
+                };
+                self.node(op_type, vec![x], vec![])
+            }
+            Op::Matmul(lhs, rhs) => {
+                let lhs = self.value(lhs)?;
+                let rhs = self.value(rhs)?;
+                self.node("MatMul", vec![lhs, rhs], vec![])
+            }
+            Op::Reduce(arg, op, _) => {
+                let x = self.value(arg)?;
+                // The reduced dimensions are kept in the recorded op.
+                let axes: Vec<i64> = arg
+                    .dims()
+                    .iter()
+                    .zip(t.dims().iter())
+                    .enumerate()
+                    .filter(|(_, (d1, d2))| d1 != d2)
+                    .map(|(i, _)| i as i64)
+                    .collect();
+                if axes.is_empty() {
+                    return Ok(self.node("Identity", vec![x], vec![]));
+                }
+                match op {
+                    ReduceOp::Sum => {
+                        let axes = self.ints(axes)?;
+                        self.node("ReduceSum", vec![x, axes], vec![attr_int("keepdims", 1)])
+                    }
+                    ReduceOp::Max | ReduceOp::Min => {
+                        let op_type = if *op == ReduceOp::Max {
+                            "ReduceMax"
+                        } else {
+                            "ReduceMin"
+                        };
+                        let attrs = vec![attr_ints("axes", axes), attr_int("keepdims", 1)];
+                        self.node(op_type, vec![x], attrs)
+                    }
+                    ReduceOp::ArgMin | ReduceOp::ArgMax => {
+                        bail!("unsupported op for onnx export reduce {op:?}")
+                    }
+                }
+            }
+            Op::Affine { arg, mul, add } => {
+                let x = self.value(arg)?;
+                let mul = self.scalar(*mul, arg)?;
+                let x = self.node("Mul", vec![x, mul], vec![]);
+                let add = self.scalar(*add, arg)?;
+                self.node("Add", vec![x, add], vec![])
+            }
+            Op::Powf(arg, e) => {
+                let x = self.value(arg)?;
+                let e = self.scalar(*e, arg)?;
+                self.node("Pow", vec![x, e], vec![])
+            }
+            Op::Broadcast(arg) => {
+                let x = self.value(arg)?;
+                let shape = self.ints(t.dims().iter().map(|&d| d as i64).collect())?;
+                self.node("Expand", vec![x, shape], vec![])
+            }
+            Op::Reshape(arg) => {
+                let x = self.value(arg)?;
+                let shape = self.ints(t.dims().iter().map(|&d| d as i64).collect())?;
+                self.node("Reshape", vec![x, shape], vec![])
+            }
+            Op::Transpose(arg, dim1, dim2) => {
+                let x = self.value(arg)?;
+                let mut perm: Vec<i64> = (0..arg.rank() as i64).collect();
+                perm.swap(*dim1, *dim2);
+                self.node("Transpose", vec![x], vec![attr_ints("perm", perm)])
+            }
+            Op::Permute(arg, dims) => {
+                let x = self.value(arg)?;
+                let perm = dims.iter().map(|&d| d as i64).collect();
+                self.node("Transpose", vec![x], vec![attr_ints("perm", perm)])
+            }
+            Op::Copy(arg) | Op::ToDevice(arg) => {
+                let x = self.value(arg)?;
+                self.node("Identity", vec![x], vec![])
+            }
+            Op::ToDType(arg) => {
+                let x = self.value(arg)?;
+                self.cast(x, data_type(t.dtype())?)
+            }
+            Op::Narrow(arg, dim, start, len) => {
+                let x = self.value(arg)?;
+                let starts = self.ints(vec![*start as i64])?;
+                let ends = self.ints(vec![(start + len) as i64])?;
+                let axes = self.ints(vec![*dim as i64])?;
+                self.node("Slice", vec![x, starts, ends, axes], vec![])
+            }
+            Op::Cat(args, dim) => {
+                let xs = args
+                    .iter()
+                    .map(|arg| self.value(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.node("Concat", xs, vec![attr_int("axis", *dim as i64)])
+            }
+            Op::WhereCond(pred, on_true, on_false) => {
+                let pred = self.value(pred)?;
+                let pred = self.cast(pred, DataType::Bool);
+                let on_true = self.value(on_true)?;
+                let on_false = self.value(on_false)?;
+                self.node("Where", vec![pred, on_true, on_false], vec![])
+            }
+            Op::IndexSelect(arg, ids, dim) => {
+                let x = self.value(arg)?;
+                let ids = self.value(ids)?;
+                let ids = self.cast(ids, DataType::Int64);
+                self.node("Gather", vec![x, ids], vec![attr_int("axis", *dim as i64)])
+            }
+            Op::Conv2D {
+                arg,
+                kernel,
+                padding,
+                stride,
+                dilation,
+            } => {
+                let x = self.value(arg)?;
+                let k = self.value(kernel)?;
+                let (p, s, d) = (*padding as i64, *stride as i64, *dilation as i64);
+                let (_, _, k_h, k_w) = kernel.dims4()?;
+                let attrs = vec![
+                    attr_ints("kernel_shape", vec![k_h as i64, k_w as i64]),
+                    attr_ints("pads", vec![p, p, p, p]),
+                    attr_ints("strides", vec![s, s]),
+                    attr_ints("dilations", vec![d, d]),
+                ];
+                self.node("Conv", vec![x, k], attrs)
+            }
+            Op::MaxPool2D {
+                arg,
+                kernel_size,
+                stride,
+            }
+            | Op::AvgPool2D {
+                arg,
+                kernel_size,
+                stride,
+            } => {
+                let op_type = match op {
+                    Op::MaxPool2D { .. } => "MaxPool",
+                    _ => "AveragePool",
+                };
+                let x = self.value(arg)?;
+                let attrs = vec![
+                    attr_ints(
+                        "kernel_shape",
+                        vec![kernel_size.0 as i64, kernel_size.1 as i64],
+                    ),
+                    attr_ints("strides", vec![stride.0 as i64, stride.1 as i64]),
+                ];
+                self.node(op_type, vec![x], attrs)
+            }
+            Op::CustomOp1(arg, c) => {
+                let x = self.value(arg)?;
+                match c.name() {
+                    "softmax-last-dim" => self.node("Softmax", vec![x], vec![attr_int("axis", -1)]),
+                    "sigmoid" => self.node("Sigmoid", vec![x], vec![]),
+                    name => bail!("unsupported op for onnx export custom-op {name}"),
+                }
+            }
+            Op::CustomOp2(_, _, c) => {
+                bail!("unsupported op for onnx export custom-op {}", c.name())
+            }
+            Op::CustomOp3(_, _, _, c) => {
+                bail!("unsupported op for onnx export custom-op {}", c.name())
+            }
+            Op::Cmp(..) => bail!("unsupported op for onnx export cmp"),
+            Op::Gather(..) => bail!("unsupported op for onnx export gather"),
+            Op::ScatterAdd(..) => bail!("unsupported op for onnx export scatter-add"),
+            Op::IndexAdd(..) => bail!("unsupported op for onnx export index-add"),
+            Op::Conv1D { .. } => bail!("unsupported op for onnx export conv1d"),
+            Op::ConvTranspose1D { .. } => bail!("unsupported op for onnx export conv-transpose1d"),
+            Op::ConvTranspose2D { .. } => bail!("unsupported op for onnx export conv-transpose2d"),
+            Op::UpsampleNearest1D { .. } | Op::UpsampleNearest2D { .. } => {
+                bail!("unsupported op for onnx export upsample-nearest")
+            }
+            Op::SliceScatter0(..) => bail!("unsupported op for onnx export slice-scatter"),
+            Op::Elu(..) => bail!("unsupported op for onnx export elu"),
+            Op::Checkpoint { .. } => bail!("unsupported op for onnx export checkpoint"),
+        };
+        Ok(name)
+    }
+}
+
+/// Traces `f` on `inputs` and returns the resulting ONNX model, the outputs of `f` are named
+/// using `output_names`.
+///
+/// The inputs are copied into fresh variables before calling `f` so that all the operations
+/// using them are recorded. Tensors that do not depend on the inputs end up as initializers,
+/// these have to be created before calling `trace`: a tensor created by `f` without a recorded
+/// op, e.g. the output of `rms_norm` or of a constructor such as `Tensor::new`, results in an
+/// error.
+///
+/// ```ignore
+/// let model = candle_onnx::trace(|xs| Ok(vec![mlp.forward(&xs[0])?]), &[("x", &xs)], &["y"])?;
+/// candle_onnx::export(&model, "mlp.onnx")?;
+/// ```
+pub fn trace<F>(f: F, inputs: &[(&str, &Tensor)], output_names: &[&str]) -> Result<onnx::ModelProto>
+where
+    F: FnOnce(&[Tensor]) -> Result<Vec<Tensor>>,
+{
+    let mut exporter = Exporter {
+        traced_from: Some(Tensor::zeros((), DType::U8, &candle::Device::Cpu)?.id()),
+        ..Exporter::default()
+    };
+    let mut graph_inputs = Vec::with_capacity(inputs.len());
+    let mut vars = Vec::with_capacity(inputs.len());
+    for &(name, t) in inputs.iter() {
+        let var = Var::from_tensor(&t.detach())?.as_tensor().clone();
+        exporter.names.insert(var.id(), name.to_string());
+        graph_inputs.push(value_info(name, t)?);
+        vars.push(var)
+    }
+    let outputs = f(&vars)?;
+    if outputs.len() != output_names.len() {
+        bail!(
+            "onnx export: got {} outputs but {} output names",
+            outputs.len(),
+            output_names.len()
+        )
+    }
+    let mut graph_outputs = Vec::with_capacity(outputs.len());
+    for (t, &name) in outputs.iter().zip(output_names.iter()) {
+        let value = exporter.value(t)?;
+        exporter.nodes.push(NodeProto {
+            op_type: "Identity".to_string(),
+            name: name.to_string(),
+            input: vec![value],
+            output: vec![name.to_string()],
+            ..NodeProto::default()
+        });
+        graph_outputs.push(value_info(name, t)?);
+    }
+    let graph = onnx::GraphProto {
+        name: "candle".to_string(),
+        node: exporter.nodes,
+        initializer: exporter.initializers,
+        input: graph_inputs,
+        output: graph_outputs,
+        ..onnx::GraphProto::default()
+    };
+    Ok(onnx::ModelProto {
+        ir_version: onnx::Version::IrVersion as i64,
+        opset_import: vec![onnx::OperatorSetIdProto {
+            domain: "".to_string(),
+            version: OPSET_VERSION,
+        }],
+        producer_name: "candle".to_string(),
+        graph: Some(graph),
+        ..onnx::ModelProto::default()
+    })
+}
+
+/// Writes `model` to `path` in the ONNX protobuf format.
+pub fn export<P: AsRef<std::path::Path>>(model: &onnx::ModelProto, path: P) -> Result<()> {
+    std::fs::write(path, model.encode_to_vec())?;
+    Ok(())
+}
@@ -1,14 +1,72 @@
-use candle::Result;
+use candle::{Result, Tensor};
 use prost::Message;
+use std::collections::HashMap;
 
 pub mod onnx {
     include!(concat!(env!("OUT_DIR"), "/onnx.rs"));
 }
 
 pub mod eval;
+pub mod export;
 pub use eval::{dtype, simple_eval};
+pub use export::{export, trace};
 
 pub fn read_file<P: AsRef<std::path::Path>>(p: P) -> Result<onnx::ModelProto> {
     let buf = std::fs::read(p)?;
     onnx::ModelProto::decode(buf.as_slice()).map_err(candle::Error::wrap)
 }
+
+/// An ONNX model, the graph gets evaluated node by node using candle ops on each call to `run`.
+#[derive(Debug, Clone)]
+pub struct Model {
+    proto: onnx::ModelProto,
+}
+
+impl Model {
+    /// Loads a model from a `.onnx` protobuf file.
+    pub fn load<P: AsRef<std::path::Path>>(p: P) -> Result<Self> {
+        Self::from_proto(read_file(p)?)
+    }
+
+    pub fn from_proto(proto: onnx::ModelProto) -> Result<Self> {
+        if proto.graph.is_none() {
+            candle::bail!("no graph defined in proto")
+        }
+        Ok(Self { proto })
+    }
+
+    pub fn proto(&self) -> &onnx::ModelProto {
+        &self.proto
+    }
+
+    fn graph(&self) -> &onnx::GraphProto {
+        // The graph presence is checked when creating the model.
+        self.proto.graph.as_ref().unwrap()
+    }
+
+    /// The names of the inputs that have to be provided to `run`, the graph inputs that have an
+    /// initializer are excluded.
+    pub fn input_names(&self) -> Vec<&str> {
+        let graph = self.graph();
+        graph
+            .input
+            .iter()
+            .filter(|i| !graph.initializer.iter().any(|t| t.name == i.name))
+            .map(|i| i.name.as_str())
+            .collect()
+    }
+
+    pub fn output_names(&self) -> Vec<&str> {
+        self.graph()
+            .output
+            .iter()
+            .map(|o| o.name.as_str())
+            .collect()
+    }
+
+    /// Evaluates the graph on the given inputs and returns the graph outputs keyed by name.
+    /// An error naming the operator is returned if the graph uses an unsupported operator.
+    pub fn run(&self, inputs: HashMap<String, Tensor>) -> Result<HashMap<String, Tensor>> {
+        simple_eval(&self.proto, inputs)
+    }
+}
@@ -0,0 +1,110 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use candle::{test_utils, DType, Device, Module, Result, Tensor, D};
+use candle_nn::{VarBuilder, VarMap};
+use std::collections::HashMap;
+
+fn round_trip(model: &candle_onnx::onnx::ModelProto, xs: &Tensor) -> Result<Tensor> {
+    let path = std::env::temp_dir().join(format!(
+        "candle-onnx-export-{}-{}.onnx",
+        std::process::id(),
+        xs.dims().len()
+    ));
+    candle_onnx::export(model, &path)?;
+    let model = candle_onnx::Model::load(&path);
+    std::fs::remove_file(&path)?;
+    let model = model?;
+    assert_eq!(model.input_names(), ["x"]);
+    assert_eq!(model.output_names(), ["y"]);
+    let inputs = HashMap::from_iter([("x".to_string(), xs.clone())]);
+    let mut outputs = model.run(inputs)?;
+    Ok(outputs.remove("y").expect("missing output"))
+}
+
+#[test]
+fn export_mlp() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let fc1 = candle_nn::linear(4, 8, vb.pp("fc1"))?;
+    let fc2 = candle_nn::linear(8, 3, vb.pp("fc2"))?;
+    let forward = |xs: &Tensor| -> Result<Tensor> {
+        let xs = fc1.forward(xs)?.gelu_erf()?;
+        let xs = fc2.forward(&xs)?;
+        candle_nn::ops::softmax_last_dim(&(xs * 2.)?)
+    };
+    let xs = Tensor::randn(0f32, 1., (5, 4), dev)?;
+    let model = candle_onnx::trace(|xs| Ok(vec![forward(&xs[0])?]), &[("x", &xs)], &["y"])?;
+    let graph = model.graph.as_ref().unwrap();
+    // The weights are stored as initializers rather than graph inputs.
+    assert_eq!(graph.input.len(), 1);
+    assert!(graph.initializer.len() >= 4);
+    assert!(graph.node.iter().any(|node| node.op_type == "Softmax"));
+
+    // The traced model applies to other inputs with the same shape.
+    let xs = Tensor::randn(0f32, 1., (5, 4), dev)?;
+    let ys = round_trip(&model, &xs)?;
+    let expected = forward(&xs)?;
+    assert_eq!(ys.dims(), [5, 3]);
+    test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn export_convnet() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let cfg = candle_nn::Conv2dConfig {
+        padding: 1,
+        ..Default::default()
+    };
+    let conv = candle_nn::conv2d(2, 4, 3, cfg, vb.pp("conv"))?;
+    let fc = candle_nn::linear(4 * 3 * 3, 2, vb.pp("fc"))?;
+    let forward = |xs: &Tensor| -> Result<Tensor> {
+        let xs = conv.forward(xs)?.relu()?.max_pool2d(2)?;
+        let xs = xs.flatten_from(1)?;
+        let xs = fc.forward(&xs)?;
+        xs.broadcast_sub(&xs.max_keepdim(D::Minus1)?)?.exp()
+    };
+    let xs = Tensor::randn(0f32, 1., (3, 2, 6, 6), dev)?;
+    let model = candle_onnx::trace(|xs| Ok(vec![forward(&xs[0])?]), &[("x", &xs)], &["y"])?;
+    let xs = Tensor::randn(0f32, 1., (3, 2, 6, 6), dev)?;
+    let ys = round_trip(&model, &xs)?;
+    let expected = forward(&xs)?;
+    assert_eq!(ys.dims(), [3, 2]);
+    test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn export_unsupported_op() -> Result<()> {
+    let xs = Tensor::new(&[1f32, 2., 3.], &Device::Cpu)?;
+    let err = candle_onnx::trace(|xs| Ok(vec![xs[0].elu(1.)?]), &[("x", &xs)], &["y"]);
+    match err {
+        Err(err) => assert!(err.to_string().contains("elu")),
+        Ok(_) => panic!("expected an error for an unsupported op"),
+    }
+    Ok(())
+}
+
+#[test]
+fn export_untracked_op() -> Result<()> {
+    let xs = Tensor::new(&[[1f32, 2., 3.]], &Device::Cpu)?;
+    let alpha = Tensor::ones(3, DType::F32, &Device::Cpu)?;
+    // rms_norm does not record an op, exporting its output as a constant would drop the graph.
+    let err = candle_onnx::trace(
+        |xs| Ok(vec![candle_nn::ops::rms_norm(&(&xs[0] * 2.)?, &alpha, 1e-5)?]),
+        &[("x", &xs)],
+        &["y"],
+    );
+    match err {
+        Err(err) => assert!(err.to_string().contains("without a recorded op")),
+        Ok(_) => panic!("expected an error for an untracked op"),
+    }
+    Ok(())
+}
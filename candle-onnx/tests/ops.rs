@@ -3407,3 +3407,104 @@ fn test_slice() -> Result<()> {
 
     Ok(())
 }
+
+// A hand-built MLP laid out like the graphs produced by `torch.onnx.export` for `nn.Linear`
+// layers: Gemm with transB, Relu and a final Gemm, with the weights stored as initializers and
+// one of them also listed in the graph inputs as done by older opsets.
+#[test]
+fn test_model_load_and_run_mlp() -> Result<()> {
+    use prost::Message;
+
+    let w1: Vec<f32> = vec![0.5, -1.0, 0.3, 0.2, 0.8, -0.6];
+    let b1: Vec<f32> = vec![0.1, -0.2];
+    let w2: Vec<f32> = vec![1.5, -0.7];
+    let b2: Vec<f32> = vec![0.05];
+    let initializer = |name: &str, dims: Vec<i64>, data: &[f32]| TensorProto {
+        name: name.to_string(),
+        dims,
+        float_data: data.to_vec(),
+        data_type: DataType::Float.into(),
+        ..TensorProto::default()
+    };
+    let gemm = |inputs: [&str; 3], output: &str| NodeProto {
+        op_type: "Gemm".to_string(),
+        input: inputs.iter().map(|s| s.to_string()).collect(),
+        output: vec![output.to_string()],
+        attribute: vec![AttributeProto {
+            name: "transB".to_string(),
+            r#type: AttributeType::Int.into(),
+            i: 1,
+            ..AttributeProto::default()
+        }],
+        ..NodeProto::default()
+    };
+    let value_info = |name: &str| ValueInfoProto {
+        name: name.to_string(),
+        doc_string: "".to_string(),
+        r#type: None,
+    };
+    let model = create_model_proto_with_graph(Some(GraphProto {
+        node: vec![
+            gemm(["input", "fc1.weight", "fc1.bias"], "h"),
+            NodeProto {
+                op_type: "Relu".to_string(),
+                input: vec!["h".to_string()],
+                output: vec!["h_relu".to_string()],
+                ..NodeProto::default()
+            },
+            gemm(["h_relu", "fc2.weight", "fc2.bias"], "output"),
+        ],
+        initializer: vec![
+            initializer("fc1.weight", vec![2, 3], &w1),
+            initializer("fc1.bias", vec![2], &b1),
+            initializer("fc2.weight", vec![1, 2], &w2),
+            initializer("fc2.bias", vec![1], &b2),
+        ],
+        input: vec![value_info("input"), value_info("fc1.weight")],
+        output: vec![value_info("output")],
+        ..GraphProto::default()
+    }));
+    let path = std::env::temp_dir().join(format!("candle-onnx-mlp-{}.onnx", std::process::id()));
+    std::fs::write(&path, model.encode_to_vec())?;
+    let model = candle_onnx::Model::load(&path);
+    std::fs::remove_file(&path)?;
+    let model = model?;
+    assert_eq!(model.input_names(), ["input"]);
+    assert_eq!(model.output_names(), ["output"]);
+
+    let dev = &Device::Cpu;
+    let xs = Tensor::new(&[[1f32, 2., 3.], [-1., 0.5, 2.]], dev)?;
+    let inputs = HashMap::from_iter([("input".to_string(), xs.clone())]);
+    let outputs = model.run(inputs)?;
+    let ys = outputs.get("output").expect("missing output");
+
+    let w1 = Tensor::from_vec(w1, (2, 3), dev)?;
+    let b1 = Tensor::new(b1.as_slice(), dev)?;
+    let w2 = Tensor::from_vec(w2, (1, 2), dev)?;
+    let b2 = Tensor::new(b2.as_slice(), dev)?;
+    let expected = xs.matmul(&w1.t()?)?.broadcast_add(&b1)?.relu()?;
+    let expected = expected.matmul(&w2.t()?)?.broadcast_add(&b2)?;
+    assert_eq!(to_vec2_round(ys, 4)?, to_vec2_round(&expected, 4)?);
+    Ok(())
+}
+
+#[test]
+fn test_model_unsupported_op() -> Result<()> {
+    let model = create_model_proto_with_graph(Some(GraphProto {
+        node: vec![NodeProto {
+            op_type: "NotAnOnnxOp".to_string(),
+            input: vec![INPUT_X.to_string()],
+            output: vec![OUTPUT_Z.to_string()],
+            ..NodeProto::default()
+        }],
+        ..GraphProto::default()
+    }));
+    let model = candle_onnx::Model::from_proto(model)?;
+    let inputs = HashMap::from_iter([(INPUT_X.to_string(), Tensor::new(&[1f32], &Device::Cpu)?)]);
+    match model.run(inputs) {
+        Err(err) => assert!(err.to_string().contains("unsupported op_type NotAnOnnxOp")),
+        Ok(_) => panic!("expected an error for an unsupported op"),
+    }
+    assert!(candle_onnx::Model::from_proto(create_model_proto_with_graph(None)).is_err());
+    Ok(())
+}
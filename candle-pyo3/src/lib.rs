@@ -72,12 +72,14 @@ impl PyDType {
 
 static CUDA_DEVICE: std::sync::Mutex<Option<Device>> = std::sync::Mutex::new(None);
 static METAL_DEVICE: std::sync::Mutex<Option<Device>> = std::sync::Mutex::new(None);
+static WGPU_DEVICE: std::sync::Mutex<Option<Device>> = std::sync::Mutex::new(None);
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum PyDevice {
     Cpu,
     Cuda,
     Metal,
+    Wgpu,
 }
 
 impl PyDevice {
@@ -86,6 +88,7 @@ impl PyDevice {
             Device::Cpu => Self::Cpu,
             Device::Cuda(_) => Self::Cuda,
             Device::Metal(_) => Self::Metal,
+            Device::Wgpu(_) => Self::Wgpu,
         }
     }
 
@@ -110,6 +113,15 @@ impl PyDevice {
                 *device = Some(d.clone());
                 Ok(d)
             }
+            Self::Wgpu => {
+                let mut device = WGPU_DEVICE.lock().unwrap();
+                if let Some(device) = device.as_ref() {
+                    return Ok(device.clone());
+                };
+                let d = Device::new_wgpu(0).map_err(wrap_err)?;
+                *device = Some(d.clone());
+                Ok(d)
+            }
         }
     }
 }
@@ -120,6 +132,7 @@ impl<'source> FromPyObject<'source> for PyDevice {
         let device = match device.as_str() {
             "cpu" => PyDevice::Cpu,
             "cuda" => PyDevice::Cuda,
+            "wgpu" => PyDevice::Wgpu,
             _ => Err(PyTypeError::new_err(format!("invalid device '{device}'")))?,
         };
         Ok(device)
@@ -132,6 +145,7 @@ impl ToPyObject for PyDevice {
             PyDevice::Cpu => "cpu",
             PyDevice::Cuda => "cuda",
             PyDevice::Metal => "metal",
+            PyDevice::Wgpu => "wgpu",
         };
         str.to_object(py)
     }
@@ -205,6 +219,10 @@ trait MapDType {
             DType::F16 => self.f::<f16>(t),
             DType::F32 => self.f::<f32>(t),
             DType::F64 => self.f::<f64>(t),
+            dtype @ (DType::C64 | DType::C128) => Err(PyTypeError::new_err(format!(
+                "unsupported dtype {}",
+                dtype.as_str()
+            ))),
         }
     }
 }
@@ -0,0 +1,150 @@
+use super::LogitsProcessor;
+use candle::{Device, Result, Tensor};
+
+/// A model that can be driven by a [`BatchGenerator`].
+///
+/// - `input_ids` has shape `(batch, seq_len)`, prompts are left-padded.
+/// - `attention_mask` is a u8 tensor with shape `(batch, seqlen_offset + seq_len)`, set to 1 for
+///   the actual tokens and to 0 for the padding, this includes the tokens from the kv cache.
+///   [`crate::utils::padded_causal_mask`] can be used to build the additive attention mask.
+/// - `position_ids` is a u32 tensor with shape `(batch, seq_len)`, the position of each token
+///   within its own sequence, i.e. ignoring the padding.
+///
+/// The returned logits can either be for the last position only or for all the positions, i.e.
+/// with shape `(batch, vocab)` or `(batch, seq_len, vocab)`.
+pub trait BatchedGenerationModel {
+    fn forward(
+        &mut self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        position_ids: &Tensor,
+        seqlen_offset: usize,
+    ) -> Result<Tensor>;
+}
+
+/// Generates tokens for a batch of prompts with different lengths.
+///
+/// The prompts are left-padded so that all the sequences end at the same position, each
+/// sequence uses its own [`LogitsProcessor`]. Once a sequence has produced the end of sequence
+/// token or reached the maximum number of tokens, the padding token is used as its input and
+/// masked out until all the sequences are done.
+pub struct BatchGenerator<M> {
+    model: M,
+    logits_processors: Vec<LogitsProcessor>,
+    pad_token: u32,
+    eos_token: Option<u32>,
+    max_new_tokens: usize,
+    device: Device,
+}
+
+impl<M: BatchedGenerationModel> BatchGenerator<M> {
+    pub fn new(
+        model: M,
+        logits_processors: Vec<LogitsProcessor>,
+        pad_token: u32,
+        max_new_tokens: usize,
+        device: &Device,
+    ) -> Self {
+        Self {
+            model,
+            logits_processors,
+            pad_token,
+            eos_token: None,
+            max_new_tokens,
+            device: device.clone(),
+        }
+    }
+
+    pub fn with_eos_token(mut self, eos_token: u32) -> Self {
+        self.eos_token = Some(eos_token);
+        self
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn into_inner(self) -> M {
+        self.model
+    }
+
+    /// Returns the generated tokens for each prompt, the end of sequence token is not included.
+    pub fn generate(&mut self, prompts: &[Vec<u32>]) -> Result<Vec<Vec<u32>>> {
+        let b_size = prompts.len();
+        if b_size != self.logits_processors.len() {
+            candle::bail!(
+                "got {b_size} prompts but {} logits processors",
+                self.logits_processors.len()
+            )
+        }
+        if prompts.iter().any(|p| p.is_empty()) {
+            candle::bail!("batched generation requires non-empty prompts")
+        }
+        let max_len = prompts.iter().map(|p| p.len()).max().unwrap_or(0);
+        let mut input_ids = Vec::with_capacity(b_size * max_len);
+        let mut position_ids = Vec::with_capacity(b_size * max_len);
+        // The attention mask for each sequence, including the tokens from the kv cache.
+        let mut mask = vec![Vec::with_capacity(max_len + self.max_new_tokens); b_size];
+        for (prompt, mask) in prompts.iter().zip(mask.iter_mut()) {
+            let n_pad = max_len - prompt.len();
+            input_ids.extend_from_slice(&vec![self.pad_token; n_pad]);
+            input_ids.extend_from_slice(prompt);
+            position_ids.extend_from_slice(&vec![0; n_pad]);
+            position_ids.extend(0..prompt.len() as u32);
+            mask.extend_from_slice(&vec![0u8; n_pad]);
+            mask.extend_from_slice(&vec![1u8; prompt.len()]);
+        }
+        let mut input_ids = Tensor::from_vec(input_ids, (b_size, max_len), &self.device)?;
+        let mut position_ids = Tensor::from_vec(position_ids, (b_size, max_len), &self.device)?;
+
+        let mut outputs = vec![vec![]; b_size];
+        let mut done = vec![self.max_new_tokens == 0; b_size];
+        let mut seqlen_offset = 0;
+        while done.iter().any(|d| !d) {
+            let seq_len = input_ids.dim(1)?;
+            let attention_mask = mask.iter().flatten().copied().collect::<Vec<_>>();
+            let kv_len = seqlen_offset + seq_len;
+            let attention_mask = Tensor::from_vec(attention_mask, (b_size, kv_len), &self.device)?;
+            let logits =
+                self.model
+                    .forward(&input_ids, &attention_mask, &position_ids, seqlen_offset)?;
+            let logits = match logits.rank() {
+                2 => logits,
+                3 => logits.narrow(1, logits.dim(1)? - 1, 1)?.squeeze(1)?,
+                _ => candle::bail!("unexpected logits shape {:?}", logits.shape()),
+            };
+            seqlen_offset = kv_len;
+
+            let mut next_tokens = Vec::with_capacity(b_size);
+            let mut next_positions = Vec::with_capacity(b_size);
+            for (i, ((output, done), mask)) in outputs
+                .iter_mut()
+                .zip(done.iter_mut())
+                .zip(mask.iter_mut())
+                .enumerate()
+            {
+                if !*done {
+                    let token = self.logits_processors[i].sample(&logits.get(i)?)?;
+                    if Some(token) == self.eos_token {
+                        *done = true
+                    } else {
+                        output.push(token);
+                        *done = output.len() >= self.max_new_tokens;
+                    }
+                    if !*done {
+                        next_tokens.push(token);
+                        next_positions.push((prompts[i].len() + output.len() - 1) as u32);
+                        mask.push(1);
+                        continue;
+                    }
+                }
+                next_tokens.push(self.pad_token);
+                next_positions.push(0);
+                mask.push(0);
+            }
+            input_ids = Tensor::from_vec(next_tokens, (b_size, 1), &self.device)?;
+            position_ids = Tensor::from_vec(next_positions, (b_size, 1), &self.device)?;
+        }
+        Ok(outputs)
+    }
+}
@@ -0,0 +1,171 @@
+use candle::{DType, Result, Tensor, D};
+
+/// A completed hypothesis returned by [`BeamSearch::run`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamHypothesis {
+    /// The generated tokens, including the end of sequence token if it has been sampled.
+    pub tokens: Vec<u32>,
+    /// The sum of the log probabilities of the tokens divided by `len ^ length_penalty`.
+    pub score: f64,
+}
+
+/// Beam search decoding.
+///
+/// At each step, every beam is expanded with all the possible next tokens, the `num_beams`
+/// candidates with the highest total log probability are kept. Beams ending with the end of
+/// sequence token are moved to the set of completed hypotheses and scored using their total log
+/// probability divided by `len ^ length_penalty`, a length penalty above 1 favors longer
+/// sequences.
+#[derive(Debug, Clone)]
+pub struct BeamSearch {
+    num_beams: usize,
+    max_new_tokens: usize,
+    eos_token: Option<u32>,
+    length_penalty: f64,
+    early_stopping: bool,
+    num_return_sequences: usize,
+}
+
+impl BeamSearch {
+    pub fn new(num_beams: usize, max_new_tokens: usize) -> Self {
+        Self {
+            num_beams,
+            max_new_tokens,
+            eos_token: None,
+            length_penalty: 1.0,
+            early_stopping: false,
+            num_return_sequences: 1,
+        }
+    }
+
+    pub fn with_eos_token(mut self, eos_token: u32) -> Self {
+        self.eos_token = Some(eos_token);
+        self
+    }
+
+    pub fn with_length_penalty(mut self, length_penalty: f64) -> Self {
+        self.length_penalty = length_penalty;
+        self
+    }
+
+    /// When enabled, the search stops as soon as `num_beams` hypotheses have been completed.
+    /// Otherwise it stops once none of the running beams can beat the completed hypotheses.
+    pub fn with_early_stopping(mut self, early_stopping: bool) -> Self {
+        self.early_stopping = early_stopping;
+        self
+    }
+
+    pub fn with_num_return_sequences(mut self, num_return_sequences: usize) -> Self {
+        self.num_return_sequences = num_return_sequences;
+        self
+    }
+
+    fn score(&self, sum_logprobs: f64, len: usize) -> f64 {
+        sum_logprobs / (len.max(1) as f64).powf(self.length_penalty)
+    }
+
+    fn add_hypothesis(&self, finished: &mut Vec<BeamHypothesis>, tokens: Vec<u32>, sum: f64) {
+        let score = self.score(sum, tokens.len());
+        finished.push(BeamHypothesis { tokens, score });
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        finished.truncate(self.num_beams);
+    }
+
+    /// Runs the search, `f` is called with the tokens generated so far for each running beam and
+    /// should return the next token logits with shape `(n_beams, vocab)`.
+    ///
+    /// The returned hypotheses are sorted by decreasing score.
+    pub fn run<F>(&self, mut f: F) -> Result<Vec<BeamHypothesis>>
+    where
+        F: FnMut(&[Vec<u32>]) -> Result<Tensor>,
+    {
+        if self.num_beams == 0 {
+            candle::bail!("beam search requires at least one beam")
+        }
+        if self.num_return_sequences == 0 || self.num_return_sequences > self.num_beams {
+            candle::bail!(
+                "num_return_sequences {} should be between 1 and num_beams {}",
+                self.num_return_sequences,
+                self.num_beams
+            )
+        }
+        // The running beams, with the sum of the log probabilities of their tokens.
+        let mut beams: Vec<(Vec<u32>, f64)> = vec![(vec![], 0.)];
+        let mut finished: Vec<BeamHypothesis> = vec![];
+        let mut done = false;
+        for _step in 0..self.max_new_tokens {
+            let tokens = beams.iter().map(|(t, _)| t.clone()).collect::<Vec<_>>();
+            let logits = f(&tokens)?.to_dtype(DType::F32)?;
+            let (n_beams, _vocab) = logits.dims2()?;
+            if n_beams != beams.len() {
+                candle::bail!("expected logits for {} beams, got {n_beams}", beams.len())
+            }
+            let logprobs = candle_nn::ops::log_softmax(&logits, D::Minus1)?.to_vec2::<f32>()?;
+            let mut candidates = Vec::new();
+            for (beam_idx, logprobs) in logprobs.iter().enumerate() {
+                let sum = beams[beam_idx].1;
+                candidates.extend(
+                    logprobs
+                        .iter()
+                        .enumerate()
+                        .map(|(token, &lp)| (sum + lp as f64, beam_idx, token as u32)),
+                );
+            }
+            // Only 2 * num_beams candidates are needed: even if num_beams of them end with eos,
+            // there are enough candidates left to fill the running beams.
+            let n_candidates = (2 * self.num_beams).min(candidates.len());
+            if n_candidates < candidates.len() {
+                candidates.select_nth_unstable_by(n_candidates, |a, b| b.0.total_cmp(&a.0));
+                candidates.truncate(n_candidates);
+            }
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            let mut next_beams = Vec::with_capacity(self.num_beams);
+            for (rank, (sum, beam_idx, token)) in candidates.into_iter().enumerate() {
+                let mut tokens = beams[beam_idx].0.clone();
+                tokens.push(token);
+                if Some(token) == self.eos_token {
+                    // Completed hypotheses that would not have been part of the top beams are
+                    // discarded.
+                    if rank < self.num_beams {
+                        self.add_hypothesis(&mut finished, tokens, sum)
+                    }
+                } else {
+                    next_beams.push((tokens, sum));
+                    if next_beams.len() == self.num_beams {
+                        break;
+                    }
+                }
+            }
+            beams = next_beams;
+            if beams.is_empty() {
+                break;
+            }
+
+            if finished.len() >= self.num_beams {
+                if self.early_stopping {
+                    done = true;
+                    break;
+                }
+                let worst_finished = finished[finished.len() - 1].score;
+                let best_running = beams
+                    .iter()
+                    .map(|(tokens, sum)| self.score(*sum, tokens.len()))
+                    .fold(f64::NEG_INFINITY, f64::max);
+                if best_running <= worst_finished {
+                    done = true;
+                    break;
+                }
+            }
+        }
+        // Beams still running when reaching max_new_tokens are scored as if they were complete,
+        // this ensures that enough sequences are returned when the eos token has not been sampled.
+        if !done {
+            for (tokens, sum) in beams {
+                self.add_hypothesis(&mut finished, tokens, sum)
+            }
+        }
+        finished.truncate(self.num_return_sequences);
+        Ok(finished)
+    }
+}
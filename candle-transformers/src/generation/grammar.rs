@@ -0,0 +1,850 @@
+use candle::{Result, Tensor, D};
+use std::collections::HashMap;
+
+/// A GBNF grammar for JSON values, the root rule only accepts objects.
+pub const JSON_GRAMMAR: &str = r#"
+root   ::= object
+value  ::= object | array | string | number | ("true" | "false" | "null") ws
+
+object ::=
+  "{" ws (
+    string ":" ws value
+    ("," ws string ":" ws value)*
+  )? "}" ws
+
+array  ::=
+  "[" ws (
+    value
+    ("," ws value)*
+  )? "]" ws
+
+string ::=
+  "\"" (
+    [^"\\\x7F\x00-\x1F] |
+    "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4})
+  )* "\"" ws
+
+number ::= "-"? ("0" | [1-9] [0-9]*) ("." [0-9]+)? ([eE] [-+]? [0-9]+)? ws
+
+ws ::= [ \t\n]*
+"#;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    // Matches a single character, `negated` classes match the characters outside of the ranges.
+    Chars {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Rule(usize),
+}
+
+impl Element {
+    fn char(c: char) -> Self {
+        Self::Chars {
+            ranges: vec![(c, c)],
+            negated: false,
+        }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Chars { ranges, negated } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+            Self::Rule(_) => false,
+        }
+    }
+
+    // Whether some character with a code point in `lo..=hi` can be matched. For negated classes
+    // this is an approximation that only checks whether a single range covers `lo..=hi`.
+    fn matches_range(&self, lo: u32, hi: u32) -> bool {
+        match self {
+            Self::Chars { ranges, negated } => {
+                if *negated {
+                    !ranges
+                        .iter()
+                        .any(|&(l, h)| l as u32 <= lo && hi <= h as u32)
+                } else {
+                    ranges
+                        .iter()
+                        .any(|&(l, h)| l as u32 <= hi && lo <= h as u32)
+                }
+            }
+            Self::Rule(_) => false,
+        }
+    }
+}
+
+// Each rule is a list of alternatives, each alternative being a sequence of elements.
+type Rule = Vec<Vec<Element>>;
+
+struct GrammarParser {
+    chars: Vec<char>,
+    pos: usize,
+    rules: Vec<Rule>,
+    names: Vec<String>,
+    defined: Vec<bool>,
+    ids: HashMap<String, usize>,
+    current: String,
+}
+
+impl GrammarParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.pos += 1
+                }
+            } else if c.is_whitespace() {
+                self.pos += 1
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        for expected in s.chars() {
+            match self.peek() {
+                Some(c) if c == expected => self.pos += 1,
+                c => candle::bail!(
+                    "grammar: expected {s:?} at position {}, got {c:?}",
+                    self.pos
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            self.pos += 1
+        }
+        (self.pos > start).then(|| self.chars[start..self.pos].iter().collect())
+    }
+
+    // Checks whether the next tokens are the start of a new rule definition, i.e. `name ::=`.
+    fn at_rule_definition(&mut self) -> bool {
+        let start = self.pos;
+        let is_definition = self.name().is_some() && {
+            self.skip_whitespace();
+            self.chars[self.pos..].starts_with(&[':', ':', '='])
+        };
+        self.pos = start;
+        is_definition
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.rules.len();
+        self.rules.push(vec![]);
+        self.names.push(name.to_string());
+        self.defined.push(false);
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn anonymous_rule(&mut self, alternatives: Rule) -> usize {
+        let id = self.rules.len();
+        self.rules.push(alternatives);
+        self.names.push(format!("{}_{id}", self.current));
+        self.defined.push(true);
+        id
+    }
+
+    fn escaped_char(&mut self) -> Result<char> {
+        let c = match self.peek() {
+            None => candle::bail!("grammar: unexpected end of input"),
+            Some(c) => c,
+        };
+        self.pos += 1;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let c = match self.peek() {
+            None => candle::bail!("grammar: unexpected end of input"),
+            Some(c) => c,
+        };
+        self.pos += 1;
+        let n_digits = match c {
+            'n' => return Ok('\n'),
+            't' => return Ok('\t'),
+            'r' => return Ok('\r'),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            c => return Ok(c),
+        };
+        let start = self.pos;
+        let end = (start + n_digits).min(self.chars.len());
+        let digits: String = self.chars[start..end].iter().collect();
+        self.pos = end;
+        match u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) if digits.len() == n_digits => Ok(c),
+            _ => candle::bail!("grammar: invalid escape sequence \\{c}{digits}"),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Vec<Element>> {
+        self.expect("\"")?;
+        let mut elements = vec![];
+        while self.peek() != Some('"') {
+            elements.push(Element::char(self.escaped_char()?))
+        }
+        self.pos += 1;
+        Ok(elements)
+    }
+
+    fn char_class(&mut self) -> Result<Element> {
+        self.expect("[")?;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1
+        }
+        let mut ranges = vec![];
+        while self.peek() != Some(']') {
+            let lo = self.escaped_char()?;
+            let hi = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.pos += 1;
+                self.escaped_char()?
+            } else {
+                lo
+            };
+            ranges.push((lo, hi))
+        }
+        self.pos += 1;
+        Ok(Element::Chars { ranges, negated })
+    }
+
+    fn number(&mut self) -> Result<usize> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| candle::Error::Msg(format!("grammar: expected a number at {start}")))
+    }
+
+    // Parses the `{m}`, `{m,}` and `{m,n}` repetition bounds.
+    fn bounds(&mut self) -> Result<(usize, Option<usize>)> {
+        self.expect("{")?;
+        let min = self.number()?;
+        self.skip_whitespace();
+        let max = if self.peek() == Some(',') {
+            self.pos += 1;
+            self.skip_whitespace();
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.number()?)
+            }
+        } else {
+            Some(min)
+        };
+        self.skip_whitespace();
+        self.expect("}")?;
+        if max.is_some_and(|max| max < min) {
+            candle::bail!("grammar: invalid repetition bounds {{{min},{max:?}}}")
+        }
+        Ok((min, max))
+    }
+
+    fn repeat(&mut self, item: Vec<Element>, min: usize, max: Option<usize>) -> Vec<Element> {
+        let mut elements = vec![];
+        for _ in 0..min {
+            elements.extend_from_slice(&item)
+        }
+        match max {
+            None => {
+                // rep ::= item rep | ""
+                let id = self.anonymous_rule(vec![]);
+                let mut alternative = item;
+                alternative.push(Element::Rule(id));
+                self.rules[id] = vec![alternative, vec![]];
+                elements.push(Element::Rule(id))
+            }
+            Some(max) => {
+                // Nested optional items: opt_k ::= item opt_{k-1} | ""
+                let mut last = None;
+                for _ in min..max {
+                    let mut alternative = item.clone();
+                    alternative.extend(last.map(Element::Rule));
+                    last = Some(self.anonymous_rule(vec![alternative, vec![]]));
+                }
+                elements.extend(last.map(Element::Rule))
+            }
+        }
+        elements
+    }
+
+    fn sequence(&mut self, nested: bool) -> Result<Vec<Element>> {
+        let mut elements = vec![];
+        loop {
+            self.skip_whitespace();
+            let item = match self.peek() {
+                None | Some('|') => break,
+                Some(')') if nested => break,
+                Some('"') => self.literal()?,
+                Some('[') => vec![self.char_class()?],
+                Some('.') => {
+                    self.pos += 1;
+                    vec![Element::Chars {
+                        ranges: vec![],
+                        negated: true,
+                    }]
+                }
+                Some('(') => {
+                    self.pos += 1;
+                    let alternatives = self.alternatives(true)?;
+                    self.skip_whitespace();
+                    self.expect(")")?;
+                    vec![Element::Rule(self.anonymous_rule(alternatives))]
+                }
+                Some(_) if !nested && self.at_rule_definition() => break,
+                Some(c) => match self.name() {
+                    Some(name) => vec![Element::Rule(self.rule_id(&name))],
+                    None => candle::bail!("grammar: unexpected {c:?} at position {}", self.pos),
+                },
+            };
+            let item = match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    self.repeat(item, 0, None)
+                }
+                Some('+') => {
+                    self.pos += 1;
+                    self.repeat(item, 1, None)
+                }
+                Some('?') => {
+                    self.pos += 1;
+                    self.repeat(item, 0, Some(1))
+                }
+                Some('{') => {
+                    let (min, max) = self.bounds()?;
+                    self.repeat(item, min, max)
+                }
+                _ => item,
+            };
+            elements.extend(item)
+        }
+        Ok(elements)
+    }
+
+    fn alternatives(&mut self, nested: bool) -> Result<Rule> {
+        let mut alternatives = vec![self.sequence(nested)?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence(nested)?)
+        }
+        Ok(alternatives)
+    }
+
+    fn parse(mut self) -> Result<Grammar> {
+        loop {
+            self.skip_whitespace();
+            if self.peek().is_none() {
+                break;
+            }
+            let name = match self.name() {
+                Some(name) => name,
+                None => candle::bail!("grammar: expected a rule name at position {}", self.pos),
+            };
+            self.skip_whitespace();
+            self.expect("::=")?;
+            self.current = name.clone();
+            let alternatives = self.alternatives(false)?;
+            let id = self.rule_id(&name);
+            if self.defined[id] {
+                candle::bail!("grammar: rule {name} is defined more than once")
+            }
+            self.rules[id] = alternatives;
+            self.defined[id] = true;
+        }
+        if let Some(id) = self.defined.iter().position(|d| !d) {
+            candle::bail!("grammar: rule {} is not defined", self.names[id])
+        }
+        let root = match self.ids.get("root") {
+            Some(&root) => root,
+            None => candle::bail!("grammar: no root rule"),
+        };
+        let grammar = Grammar {
+            rules: self.rules,
+            names: self.names,
+            root,
+        };
+        grammar.check_left_recursion()?;
+        Ok(grammar)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Grammar {
+    rules: Vec<Rule>,
+    names: Vec<String>,
+    root: usize,
+}
+
+impl Grammar {
+    fn parse(src: &str) -> Result<Self> {
+        let parser = GrammarParser {
+            chars: src.chars().collect(),
+            pos: 0,
+            rules: vec![],
+            names: vec![],
+            defined: vec![],
+            ids: HashMap::new(),
+            current: String::new(),
+        };
+        parser.parse()
+    }
+
+    // Left recursive rules would make the expansion of the parser stacks loop forever.
+    fn check_left_recursion(&self) -> Result<()> {
+        let n_rules = self.rules.len();
+        let mut nullable = vec![false; n_rules];
+        let is_nullable = |e: &Element, nullable: &[bool]| match e {
+            Element::Chars { .. } => false,
+            Element::Rule(id) => nullable[*id],
+        };
+        loop {
+            let mut changed = false;
+            for (id, rule) in self.rules.iter().enumerate() {
+                if !nullable[id]
+                    && rule
+                        .iter()
+                        .any(|a| a.iter().all(|e| is_nullable(e, &nullable)))
+                {
+                    nullable[id] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        // The rules that can appear first when expanding each rule.
+        let mut edges = vec![vec![]; n_rules];
+        for (id, rule) in self.rules.iter().enumerate() {
+            for alternative in rule.iter() {
+                for e in alternative.iter() {
+                    if let Element::Rule(child) = e {
+                        edges[id].push(*child)
+                    }
+                    if !is_nullable(e, &nullable) {
+                        break;
+                    }
+                }
+            }
+        }
+        // 0: not visited, 1: in progress, 2: done.
+        fn visit(id: usize, edges: &[Vec<usize>], state: &mut [u8]) -> Option<usize> {
+            state[id] = 1;
+            for &child in edges[id].iter() {
+                match state[child] {
+                    0 => {
+                        if let Some(id) = visit(child, edges, state) {
+                            return Some(id);
+                        }
+                    }
+                    1 => return Some(child),
+                    _ => {}
+                }
+            }
+            state[id] = 2;
+            None
+        }
+        let mut state = vec![0u8; n_rules];
+        for id in 0..n_rules {
+            if state[id] == 0 {
+                if let Some(id) = visit(id, &edges, &mut state) {
+                    candle::bail!("grammar: rule {} is left recursive", self.names[id])
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn element(&self, pos: &Position) -> &Element {
+        &self.rules[pos.rule][pos.alternative][pos.element]
+    }
+
+    // Replaces rule references at the top of `stack` with the start of their alternatives until
+    // the top of each resulting stack is a character element, or the stack is empty.
+    fn expand(&self, mut stack: Stack, out: &mut Vec<Stack>) {
+        let top = match stack.last() {
+            None => {
+                out.push(stack);
+                return;
+            }
+            Some(top) => *top,
+        };
+        let id = match self.element(&top) {
+            Element::Chars { .. } => {
+                out.push(stack);
+                return;
+            }
+            Element::Rule(id) => *id,
+        };
+        self.advance(&mut stack);
+        for (alternative, elements) in self.rules[id].iter().enumerate() {
+            let mut stack = stack.clone();
+            if !elements.is_empty() {
+                stack.push(Position {
+                    rule: id,
+                    alternative,
+                    element: 0,
+                })
+            }
+            self.expand(stack, out)
+        }
+    }
+
+    // Moves the top of the stack past its current element.
+    fn advance(&self, stack: &mut Stack) {
+        if let Some(mut top) = stack.pop() {
+            top.element += 1;
+            if top.element < self.rules[top.rule][top.alternative].len() {
+                stack.push(top)
+            }
+        }
+    }
+
+    fn initial_stacks(&self) -> Vec<Stack> {
+        let mut stacks = vec![];
+        for (alternative, elements) in self.rules[self.root].iter().enumerate() {
+            let stack = if elements.is_empty() {
+                vec![]
+            } else {
+                vec![Position {
+                    rule: self.root,
+                    alternative,
+                    element: 0,
+                }]
+            };
+            self.expand(stack, &mut stacks)
+        }
+        stacks.sort();
+        stacks.dedup();
+        stacks
+    }
+
+    // Returns the stacks obtained after accepting `c`, this is empty if `c` is not allowed.
+    fn accept_char(&self, stacks: &[Stack], c: char) -> Vec<Stack> {
+        let mut next = vec![];
+        for stack in stacks.iter() {
+            if let Some(top) = stack.last() {
+                if self.element(top).matches(c) {
+                    let mut stack = stack.clone();
+                    self.advance(&mut stack);
+                    self.expand(stack, &mut next)
+                }
+            }
+        }
+        next.sort();
+        next.dedup();
+        next
+    }
+
+    // Returns the stacks and the partial character obtained after accepting the utf-8 byte `b`,
+    // the stacks are empty if `b` is not allowed. While a character is incomplete, only the
+    // stacks that can match a character starting with the bytes seen so far are kept.
+    fn accept_byte(
+        &self,
+        stacks: &[Stack],
+        partial: PartialChar,
+        b: u8,
+    ) -> (Vec<Stack>, PartialChar) {
+        let partial = match partial.push(b) {
+            None => return (vec![], partial),
+            Some(partial) => partial,
+        };
+        if partial.remaining == 0 {
+            match char::from_u32(partial.value) {
+                None => (vec![], partial),
+                Some(c) => (self.accept_char(stacks, c), partial),
+            }
+        } else {
+            let bits = 6 * partial.remaining;
+            let lo = partial.value << bits;
+            let hi = lo | ((1 << bits) - 1);
+            let stacks = stacks
+                .iter()
+                .filter(|s| {
+                    s.last()
+                        .is_some_and(|top| self.element(top).matches_range(lo, hi))
+                })
+                .cloned()
+                .collect();
+            (stacks, partial)
+        }
+    }
+}
+
+// A character being decoded from its utf-8 bytes, `value` contains the bits decoded so far and
+// `remaining` is the number of continuation bytes that are still expected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct PartialChar {
+    value: u32,
+    remaining: u32,
+}
+
+impl PartialChar {
+    // Returns `None` if `b` cannot follow the bytes seen so far in valid utf-8.
+    fn push(self, b: u8) -> Option<Self> {
+        let (value, remaining) = if self.remaining == 0 {
+            match b {
+                0x00..=0x7f => (b as u32, 0),
+                0xc2..=0xdf => ((b & 0x1f) as u32, 1),
+                0xe0..=0xef => ((b & 0x0f) as u32, 2),
+                0xf0..=0xf4 => ((b & 0x07) as u32, 3),
+                _ => return None,
+            }
+        } else if b & 0xc0 == 0x80 {
+            ((self.value << 6) | (b & 0x3f) as u32, self.remaining - 1)
+        } else {
+            return None;
+        };
+        Some(Self { value, remaining })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    rule: usize,
+    alternative: usize,
+    element: usize,
+}
+
+// A parser stack, the last position is the next element to match. An empty stack means that the
+// input matched the whole grammar.
+type Stack = Vec<Position>;
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    tokens: Vec<u32>,
+}
+
+/// Constrains the generated tokens so that the output matches a GBNF grammar.
+///
+/// The grammar uses the GBNF format from llama.cpp: rules are defined with `name ::= ...` and
+/// can use string literals, character classes such as `[a-z]` or `[^"]`, `.`, groups, the
+/// alternative operator `|` and the `*`, `+`, `?`, `{m,n}` repetition operators. The generation
+/// starts from the `root` rule.
+///
+/// `vocab` contains the text of each token, indexed by token id. Tokens can span several grammar
+/// terminals or only part of one, a token is allowed when all its characters can be accepted by
+/// the grammar. Tokens with an empty text, e.g. special tokens, are never allowed, the end of
+/// sequence token is handled separately with [`GrammarConstraint::with_eos_token`] and only
+/// allowed once the output matches the whole grammar. The tokens are matched on their utf-8
+/// bytes so a token can stop in the middle of a character, e.g. byte fallback tokens, the
+/// following tokens then have to complete the character. Use
+/// [`GrammarConstraint::from_tokenizer`] to get the token texts from a tokenizer.
+///
+/// On each generation step, [`GrammarConstraint::apply`] masks the logits of the tokens that are
+/// not allowed, then [`GrammarConstraint::accept_token`] updates the parser state with the
+/// sampled token. [`LogitsProcessor::sample_with_grammar`](super::LogitsProcessor::sample_with_grammar)
+/// does both.
+#[derive(Debug, Clone)]
+pub struct GrammarConstraint {
+    grammar: Grammar,
+    vocab: Vec<Vec<u8>>,
+    trie: Vec<TrieNode>,
+    stacks: Vec<Stack>,
+    partial: PartialChar,
+    eos_token: Option<u32>,
+    finished: bool,
+}
+
+impl GrammarConstraint {
+    pub fn new(grammar: &str, vocab: Vec<String>) -> Result<Self> {
+        let vocab = vocab.into_iter().map(String::into_bytes).collect();
+        Self::from_bytes(grammar, vocab)
+    }
+
+    /// Uses the vocabulary of a sentencepiece style `tokenizer`: `▁` is replaced with a space
+    /// and the byte fallback tokens `<0xNN>` stand for the byte `NN`. Special tokens are never
+    /// allowed.
+    pub fn from_tokenizer(grammar: &str, tokenizer: &tokenizers::Tokenizer) -> Result<Self> {
+        let added_tokens = tokenizer.get_added_tokens_decoder();
+        let vocab_size = tokenizer
+            .get_vocab(true)
+            .values()
+            .max()
+            .map_or(0, |&id| id as usize + 1);
+        let vocab = (0..vocab_size as u32)
+            .map(|id| match tokenizer.id_to_token(id) {
+                Some(_) if added_tokens.get(&id).is_some_and(|t| t.special) => vec![],
+                Some(token) => token_bytes(&token),
+                None => vec![],
+            })
+            .collect();
+        Self::from_bytes(grammar, vocab)
+    }
+
+    fn from_bytes(grammar: &str, vocab: Vec<Vec<u8>>) -> Result<Self> {
+        let grammar = Grammar::parse(grammar)?;
+        let mut trie = vec![TrieNode::default()];
+        for (token, text) in vocab.iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            let mut node = 0;
+            for &c in text.iter() {
+                node = match trie[node].children.iter().find(|(k, _)| *k == c) {
+                    Some(&(_, child)) => child,
+                    None => {
+                        trie.push(TrieNode::default());
+                        let child = trie.len() - 1;
+                        trie[node].children.push((c, child));
+                        child
+                    }
+                }
+            }
+            trie[node].tokens.push(token as u32)
+        }
+        let stacks = grammar.initial_stacks();
+        Ok(Self {
+            grammar,
+            vocab,
+            trie,
+            stacks,
+            partial: PartialChar::default(),
+            eos_token: None,
+            finished: false,
+        })
+    }
+
+    pub fn with_eos_token(mut self, eos_token: u32) -> Self {
+        self.eos_token = Some(eos_token);
+        self
+    }
+
+    /// Returns true if the tokens accepted so far match the whole grammar.
+    pub fn is_complete(&self) -> bool {
+        self.stacks.iter().any(|s| s.is_empty())
+    }
+
+    /// Returns true once the end of sequence token has been accepted.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Restarts the parsing from the root rule.
+    pub fn reset(&mut self) {
+        self.stacks = self.grammar.initial_stacks();
+        self.partial = PartialChar::default();
+        self.finished = false;
+    }
+
+    fn collect_allowed(
+        &self,
+        node: usize,
+        stacks: &[Stack],
+        partial: PartialChar,
+        allowed: &mut Vec<u32>,
+    ) {
+        for &(b, child) in self.trie[node].children.iter() {
+            let (stacks, partial) = self.grammar.accept_byte(stacks, partial, b);
+            if !stacks.is_empty() {
+                allowed.extend_from_slice(&self.trie[child].tokens);
+                self.collect_allowed(child, &stacks, partial, allowed)
+            }
+        }
+    }
+
+    /// The tokens that can be generated next, sorted by id.
+    pub fn allowed_tokens(&self) -> Vec<u32> {
+        let mut allowed = vec![];
+        if self.finished {
+            return allowed;
+        }
+        self.collect_allowed(0, &self.stacks, self.partial, &mut allowed);
+        if let Some(eos_token) = self.eos_token {
+            if self.is_complete() {
+                allowed.push(eos_token)
+            }
+        }
+        allowed.sort();
+        allowed.dedup();
+        allowed
+    }
+
+    /// Sets the logits of the tokens that are not allowed to minus infinity. `logits` can have
+    /// more entries than the vocabulary, e.g. when the embeddings are padded, these are always
+    /// masked.
+    pub fn apply(&self, logits: &Tensor) -> Result<Tensor> {
+        let allowed = self.allowed_tokens();
+        if allowed.is_empty() {
+            candle::bail!("no token is allowed by the grammar")
+        }
+        let vocab_size = logits.dim(D::Minus1)?;
+        let mut bias = vec![f32::NEG_INFINITY; vocab_size];
+        for &token in allowed.iter() {
+            if let Some(b) = bias.get_mut(token as usize) {
+                *b = 0.
+            }
+        }
+        let bias = Tensor::from_vec(bias, vocab_size, logits.device())?.to_dtype(logits.dtype())?;
+        logits.broadcast_add(&bias)
+    }
+
+    /// Updates the parser state with `token`, returns an error if the token is not allowed.
+    pub fn accept_token(&mut self, token: u32) -> Result<()> {
+        if self.finished {
+            candle::bail!("the grammar constraint has already accepted the end of sequence token")
+        }
+        if Some(token) == self.eos_token {
+            if !self.is_complete() {
+                candle::bail!("the end of sequence token is not allowed by the grammar")
+            }
+            self.finished = true;
+            return Ok(());
+        }
+        let text = match self.vocab.get(token as usize) {
+            Some(text) if !text.is_empty() => text,
+            _ => candle::bail!("token {token} is not allowed by the grammar"),
+        };
+        let (mut stacks, mut partial) = (self.stacks.clone(), self.partial);
+        for &b in text.iter() {
+            (stacks, partial) = self.grammar.accept_byte(&stacks, partial, b);
+            if stacks.is_empty() {
+                let text = String::from_utf8_lossy(text);
+                candle::bail!("token {token} {text:?} is not allowed by the grammar")
+            }
+        }
+        self.stacks = stacks;
+        self.partial = partial;
+        Ok(())
+    }
+}
+
+// The bytes for a sentencepiece token, byte fallback tokens are written as `<0xNN>`.
+fn token_bytes(token: &str) -> Vec<u8> {
+    let byte = token
+        .strip_prefix("<0x")
+        .and_then(|t| t.strip_suffix('>'))
+        .filter(|hex| hex.len() == 2)
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match byte {
+        Some(byte) => vec![byte],
+        None => token.replace('▁', " ").into_bytes(),
+    }
+}
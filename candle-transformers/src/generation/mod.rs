@@ -1,6 +1,17 @@
 use candle::{DType, Error, Result, Tensor};
 use rand::{distributions::Distribution, SeedableRng};
 
+mod batched;
+mod beam_search;
+mod grammar;
+mod speculative;
+mod token_stream;
+pub use batched::{BatchGenerator, BatchedGenerationModel};
+pub use beam_search::{BeamHypothesis, BeamSearch};
+pub use grammar::{GrammarConstraint, JSON_GRAMMAR};
+pub use speculative::{accept_draft_tokens, SpeculativeDecoder, SpeculativeModel};
+pub use token_stream::{GenerationModel, StopReason, TokenStream};
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Sampling {
     ArgMax,
@@ -8,17 +19,104 @@ pub enum Sampling {
     TopK { k: usize, temperature: f64 },
     TopP { p: f64, temperature: f64 },
     TopKThenTopP { k: usize, p: f64, temperature: f64 },
+    MinP { p: f64, temperature: f64 },
+    Typical { p: f64, temperature: f64 },
+}
+
+/// Penalties applied to the logits of the tokens that have already been generated.
+///
+/// - `repetition_penalty` divides the positive logits and multiplies the negative logits of the
+///   tokens from the history, 1.0 means no penalty.
+/// - `frequency_penalty` is subtracted from the logits once per occurrence of the token in the
+///   history.
+/// - `presence_penalty` is subtracted once from the logits of the tokens that appear in the
+///   history.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Penalties {
+    pub repetition_penalty: f32,
+    pub frequency_penalty: f32,
+    pub presence_penalty: f32,
+}
+
+impl Default for Penalties {
+    fn default() -> Self {
+        Self {
+            repetition_penalty: 1.0,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+        }
+    }
+}
+
+impl Penalties {
+    pub fn is_noop(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Applies the penalties to `logits`, a tensor of shape `(vocab,)`, using the tokens from
+    /// `history`. The result is returned as f32.
+    pub fn apply(&self, logits: &Tensor, history: &[u32]) -> Result<Tensor> {
+        if self.is_noop() || history.is_empty() {
+            return logits.to_dtype(DType::F32);
+        }
+        let mut counts = std::collections::HashMap::new();
+        for &token in history {
+            *counts.entry(token).or_insert(0usize) += 1
+        }
+        let mut logits_v = logits.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+        for (&token, &count) in counts.iter() {
+            if let Some(logit) = logits_v.get_mut(token as usize) {
+                if *logit >= 0. {
+                    *logit /= self.repetition_penalty
+                } else {
+                    *logit *= self.repetition_penalty
+                }
+                *logit -= count as f32 * self.frequency_penalty + self.presence_penalty
+            }
+        }
+        let len = logits_v.len();
+        Tensor::from_vec(logits_v, len, logits.device())
+    }
 }
 
 pub struct LogitsProcessor {
     rng: rand::rngs::StdRng,
     sampling: Sampling,
+    penalties: Penalties,
 }
 
 impl LogitsProcessor {
     pub fn from_sampling(seed: u64, sampling: Sampling) -> Self {
         let rng = rand::rngs::StdRng::seed_from_u64(seed);
-        Self { rng, sampling }
+        Self {
+            rng,
+            sampling,
+            penalties: Penalties::default(),
+        }
+    }
+
+    pub fn with_penalties(mut self, penalties: Penalties) -> Self {
+        self.penalties = penalties;
+        self
+    }
+
+    pub fn with_repetition_penalty(mut self, repetition_penalty: f32) -> Self {
+        self.penalties.repetition_penalty = repetition_penalty;
+        self
+    }
+
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.penalties.frequency_penalty = frequency_penalty;
+        self
+    }
+
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.penalties.presence_penalty = presence_penalty;
+        self
+    }
+
+    pub fn penalties(&self) -> &Penalties {
+        &self.penalties
     }
 
     pub fn new(seed: u64, temperature: Option<f64>, top_p: Option<f64>) -> Self {
@@ -106,10 +204,67 @@ impl LogitsProcessor {
         }
     }
 
+    /// min-p sampling only keeps the tokens with a probability of at least `min_p` times the
+    /// probability of the most likely token.
+    fn sample_min_p(&mut self, prs: &mut Vec<f32>, min_p: f32) -> Result<u32> {
+        let max_p = prs.iter().copied().fold(0f32, f32::max);
+        let threshold = min_p * max_p;
+        for p in prs.iter_mut() {
+            if *p < threshold {
+                *p = 0.0
+            }
+        }
+        self.sample_multinomial(prs)
+    }
+
+    /// Locally typical sampling keeps the tokens whose surprisal `-log p` is the closest to the
+    /// entropy of the distribution, until their cumulative probability reaches `mass`.
+    fn sample_typical(&mut self, prs: &mut Vec<f32>, mass: f32) -> Result<u32> {
+        let entropy = -prs
+            .iter()
+            .filter(|&&p| p > 0.)
+            .map(|&p| p * p.ln())
+            .sum::<f32>();
+        let shifted = prs
+            .iter()
+            .map(|&p| (-p.ln() - entropy).abs())
+            .collect::<Vec<_>>();
+        let mut argsort_indices = (0..prs.len()).collect::<Vec<_>>();
+        argsort_indices.sort_by(|&i, &j| shifted[i].total_cmp(&shifted[j]));
+        let mut cumsum = 0.;
+        for index in &argsort_indices {
+            if cumsum >= mass {
+                prs[*index] = 0.0;
+            } else {
+                cumsum += prs[*index];
+            }
+        }
+        self.sample_multinomial(prs)
+    }
+
     pub fn sample(&mut self, logits: &Tensor) -> Result<u32> {
         self.sample_f(logits, |_| {})
     }
 
+    /// Applies the penalties against the previously generated tokens in `history`, typically the
+    /// last few generated tokens, then samples the next token.
+    pub fn sample_with_history(&mut self, logits: &Tensor, history: &[u32]) -> Result<u32> {
+        let logits = self.penalties.apply(logits, history)?;
+        self.sample(&logits)
+    }
+
+    /// Samples a token among the ones allowed by `grammar` and updates the grammar state.
+    pub fn sample_with_grammar(
+        &mut self,
+        logits: &Tensor,
+        grammar: &mut GrammarConstraint,
+    ) -> Result<u32> {
+        let logits = grammar.apply(logits)?;
+        let next_token = self.sample(&logits)?;
+        grammar.accept_token(next_token)?;
+        Ok(next_token)
+    }
+
     pub fn sample_f(&mut self, logits: &Tensor, f: impl FnOnce(&mut [f32])) -> Result<u32> {
         let logits = logits.to_dtype(DType::F32)?;
         let prs = |temperature: f64| -> Result<Vec<f32>> {
@@ -144,6 +299,18 @@ impl LogitsProcessor {
                 let mut prs = prs(*temperature)?;
                 self.sample_topk_topp(&mut prs, *k, *p as f32)?
             }
+            Sampling::MinP { p, temperature } => {
+                let mut prs = prs(*temperature)?;
+                self.sample_min_p(&mut prs, *p as f32)?
+            }
+            Sampling::Typical { p, temperature } => {
+                let mut prs = prs(*temperature)?;
+                if *p <= 0.0 || *p >= 1.0 {
+                    self.sample_multinomial(&prs)?
+                } else {
+                    self.sample_typical(&mut prs, *p as f32)?
+                }
+            }
         };
         Ok(next_token)
     }
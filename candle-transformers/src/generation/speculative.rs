@@ -0,0 +1,274 @@
+use super::GenerationModel;
+use candle::{DType, Device, Error, Result, Tensor};
+use rand::{distributions::Distribution, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+fn sample_from(prs: &[f32], rng: &mut impl Rng) -> Result<u32> {
+    let distr = rand::distributions::WeightedIndex::new(prs).map_err(Error::wrap)?;
+    Ok(distr.sample(rng) as u32)
+}
+
+/// The rejection sampling step of speculative decoding.
+///
+/// `draft_tokens` have been sampled from the draft distributions `draft_probs`, and
+/// `target_probs` contains the target model distributions for the same positions plus one
+/// additional position. Each draft token `x` is accepted with probability
+/// `min(1, p(x) / q(x))`. On the first rejection, the replacement token is sampled from the
+/// normalized residual distribution `max(0, p - q)`. If all the draft tokens are accepted, an
+/// extra token is sampled from the last target distribution. This ensures that the returned
+/// tokens follow the target distribution.
+///
+/// Returns the number of accepted draft tokens and the token to append after them.
+pub fn accept_draft_tokens(
+    draft_tokens: &[u32],
+    draft_probs: &[Vec<f32>],
+    target_probs: &[Vec<f32>],
+    rng: &mut impl Rng,
+) -> Result<(usize, u32)> {
+    let gamma = draft_tokens.len();
+    if draft_probs.len() != gamma || target_probs.len() != gamma + 1 {
+        candle::bail!(
+            "unexpected number of distributions, draft {} target {} for {gamma} draft tokens",
+            draft_probs.len(),
+            target_probs.len()
+        )
+    }
+    for (i, &token) in draft_tokens.iter().enumerate() {
+        let (p, q) = (&target_probs[i], &draft_probs[i]);
+        let (p_x, q_x) = (p[token as usize], q[token as usize]);
+        // Accepting when r < p(x) / q(x), written so that q(x) = 0 is handled.
+        let r: f32 = rng.gen();
+        if r * q_x < p_x {
+            continue;
+        }
+        let residual = p
+            .iter()
+            .zip(q.iter())
+            .map(|(p, q)| (p - q).max(0.))
+            .collect::<Vec<_>>();
+        let token = if residual.iter().any(|&v| v > 0.) {
+            sample_from(&residual, rng)?
+        } else {
+            sample_from(p, rng)?
+        };
+        return Ok((i, token));
+    }
+    Ok((gamma, sample_from(&target_probs[gamma], rng)?))
+}
+
+/// A model that can be used with a [`SpeculativeDecoder`]. On top of [`GenerationModel`], the
+/// model has to be able to roll back its kv cache as draft tokens can be rejected.
+pub trait SpeculativeModel: GenerationModel {
+    /// Discards the kv cache entries past the first `seq_len` positions.
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()>;
+}
+
+/// Speculative decoding: a draft model proposes `gamma` tokens that are verified by the target
+/// model in a single forward pass.
+///
+/// The target model must return the logits for all the input positions, i.e. with shape
+/// `(1, seq_len, vocab)` or `(seq_len, vocab)`, the draft model can also only return the logits
+/// for the last position. After each verification, the kv cache of both models is truncated
+/// so that it only contains the accepted tokens.
+///
+/// When `temperature` is `None`, both models are decoded greedily and the output is the same as
+/// greedy decoding with the target model.
+pub struct SpeculativeDecoder<T, D> {
+    target: T,
+    draft: D,
+    gamma: usize,
+    temperature: Option<f64>,
+    rng: rand::rngs::StdRng,
+    device: Device,
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    target_processed: usize,
+    draft_processed: usize,
+    pending: VecDeque<u32>,
+    eos_token: Option<u32>,
+    max_new_tokens: Option<usize>,
+    done: bool,
+    num_drafted: usize,
+    num_accepted: usize,
+}
+
+impl<T: SpeculativeModel, D: SpeculativeModel> SpeculativeDecoder<T, D> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        target: T,
+        draft: D,
+        gamma: usize,
+        seed: u64,
+        temperature: Option<f64>,
+        prompt: &[u32],
+        device: &Device,
+    ) -> Self {
+        let temperature = temperature.filter(|&t| t >= 1e-7);
+        Self {
+            target,
+            draft,
+            gamma,
+            temperature,
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            device: device.clone(),
+            tokens: prompt.to_vec(),
+            prompt_len: prompt.len(),
+            target_processed: 0,
+            draft_processed: 0,
+            pending: VecDeque::new(),
+            eos_token: None,
+            max_new_tokens: None,
+            done: false,
+            num_drafted: 0,
+            num_accepted: 0,
+        }
+    }
+
+    pub fn with_eos_token(mut self, eos_token: u32) -> Self {
+        self.eos_token = Some(eos_token);
+        self
+    }
+
+    pub fn with_max_new_tokens(mut self, max_new_tokens: usize) -> Self {
+        self.max_new_tokens = Some(max_new_tokens);
+        self
+    }
+
+    /// The prompt followed by the generated tokens, this can include tokens that have not been
+    /// returned by the iterator yet.
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// The number of tokens proposed by the draft model so far.
+    pub fn num_drafted(&self) -> usize {
+        self.num_drafted
+    }
+
+    /// The number of draft tokens accepted by the target model so far.
+    pub fn num_accepted(&self) -> usize {
+        self.num_accepted
+    }
+
+    pub fn into_inner(self) -> (T, D) {
+        (self.target, self.draft)
+    }
+
+    fn probs(&self, logits: &Tensor) -> Result<Vec<f32>> {
+        let logits = logits.to_dtype(DType::F32)?;
+        match self.temperature {
+            None => {
+                let logits = logits.to_vec1::<f32>()?;
+                let argmax = logits
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, u), (_, v)| u.total_cmp(v))
+                    .map_or(0, |(i, _)| i);
+                let mut prs = vec![0f32; logits.len()];
+                prs[argmax] = 1.;
+                Ok(prs)
+            }
+            Some(temperature) => {
+                let prs = candle_nn::ops::softmax_last_dim(&(logits / temperature)?)?;
+                prs.to_vec1()
+            }
+        }
+    }
+
+    // Returns the logits as a (seq_len, vocab) tensor.
+    fn logits_2d(logits: Tensor) -> Result<Tensor> {
+        match logits.rank() {
+            2 => Ok(logits),
+            3 => logits.squeeze(0),
+            _ => candle::bail!("unexpected logits shape {:?}", logits.shape()),
+        }
+    }
+
+    // Runs one round of drafting and verification, returns the newly generated tokens.
+    fn step(&mut self) -> Result<Vec<u32>> {
+        let n = self.tokens.len();
+        if n == 0 {
+            candle::bail!("speculative decoding requires a non-empty prompt")
+        }
+        let mut draft_seq = self.tokens.clone();
+        let mut draft_tokens = Vec::with_capacity(self.gamma);
+        let mut draft_probs = Vec::with_capacity(self.gamma);
+        for _ in 0..self.gamma {
+            let input = &draft_seq[self.draft_processed..];
+            let input = Tensor::new(input, &self.device)?.unsqueeze(0)?;
+            let logits = self.draft.forward(&input, self.draft_processed)?;
+            let logits = Self::logits_2d(logits)?;
+            let logits = logits.get(logits.dim(0)? - 1)?;
+            self.draft_processed = draft_seq.len();
+            let prs = self.probs(&logits)?;
+            let token = sample_from(&prs, &mut self.rng)?;
+            draft_seq.push(token);
+            draft_tokens.push(token);
+            draft_probs.push(prs);
+        }
+
+        let input = &draft_seq[self.target_processed..];
+        let input = Tensor::new(input, &self.device)?.unsqueeze(0)?;
+        let logits = self.target.forward(&input, self.target_processed)?;
+        let logits = Self::logits_2d(logits)?;
+        let seq_len = logits.dim(0)?;
+        if seq_len != draft_seq.len() - self.target_processed {
+            candle::bail!(
+                "the target model should return logits for all positions, got {seq_len} for {}",
+                draft_seq.len() - self.target_processed
+            )
+        }
+        let logits = logits.narrow(0, seq_len - self.gamma - 1, self.gamma + 1)?;
+        let target_probs = (0..=self.gamma)
+            .map(|i| self.probs(&logits.get(i)?))
+            .collect::<Result<Vec<_>>>()?;
+
+        let (accepted, token) =
+            accept_draft_tokens(&draft_tokens, &draft_probs, &target_probs, &mut self.rng)?;
+        self.num_drafted += self.gamma;
+        self.num_accepted += accepted;
+        self.target_processed = n + accepted;
+        self.draft_processed = self.draft_processed.min(n + accepted);
+        self.target.truncate_kv_cache(self.target_processed)?;
+        self.draft.truncate_kv_cache(self.draft_processed)?;
+        let mut new_tokens = draft_tokens[..accepted].to_vec();
+        new_tokens.push(token);
+        Ok(new_tokens)
+    }
+
+    fn next_token(&mut self) -> Result<Option<u32>> {
+        while self.pending.is_empty() && !self.done {
+            for token in self.step()? {
+                if Some(token) == self.eos_token {
+                    self.done = true;
+                    break;
+                }
+                self.tokens.push(token);
+                self.pending.push_back(token);
+                if Some(self.tokens.len() - self.prompt_len) == self.max_new_tokens {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<T: SpeculativeModel, D: SpeculativeModel> Iterator for SpeculativeDecoder<T, D> {
+    type Item = Result<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.max_new_tokens == Some(0) {
+            return None;
+        }
+        match self.next_token() {
+            Ok(token) => token.map(Ok),
+            Err(err) => {
+                self.done = true;
+                self.pending.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use super::LogitsProcessor;
+use candle::{Device, Result, Tensor};
+
+/// A model that can be driven by a [`TokenStream`]. The model is expected to handle its own kv
+/// cache, `input_ids` has shape `(1, seq_len)` and `seqlen_offset` is the number of tokens that
+/// have already been processed. The returned logits can either be for the last position only
+/// or for all the positions, i.e. with shape `(vocab,)`, `(1, vocab)` or `(1, seq_len, vocab)`.
+pub trait GenerationModel {
+    fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor>;
+}
+
+impl<F: FnMut(&Tensor, usize) -> Result<Tensor>> GenerationModel for F {
+    fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        self(input_ids, seqlen_offset)
+    }
+}
+
+/// Why a [`TokenStream`] stopped producing tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The end of sequence token has been sampled, this token is not returned by the stream.
+    Eos,
+    /// The maximum number of new tokens has been reached.
+    MaxNewTokens,
+    /// One of the stop sequences has been generated, the tokens from the stop sequence are
+    /// returned by the stream.
+    StopSequence,
+}
+
+/// An iterator returning generated tokens one at a time.
+///
+/// The first call to `next` runs the model on the whole prompt, the following calls only run it
+/// on the last sampled token.
+///
+/// ```rust
+/// use candle::{Device, Tensor};
+/// use candle_transformers::generation::{LogitsProcessor, StopReason, TokenStream};
+/// # fn main() -> candle::Result<()> {
+/// // A "model" always predicting the token following the last input token.
+/// let model = |xs: &Tensor, _: usize| {
+///     let last = xs.flatten_all()?.to_vec1::<u32>()?.last().copied().unwrap_or(0);
+///     Tensor::arange(0u32, 8, &Device::Cpu)?.eq(last + 1)?.to_dtype(candle::DType::F32)
+/// };
+/// let logits_processor = LogitsProcessor::new(0, None, None);
+/// let mut stream = TokenStream::new(model, logits_processor, &[1, 2], &Device::Cpu).with_eos_token(6);
+/// let tokens = stream.by_ref().collect::<candle::Result<Vec<_>>>()?;
+/// assert_eq!(tokens, [3, 4, 5]);
+/// assert_eq!(stream.stop_reason(), Some(StopReason::Eos));
+/// # Ok(()) }
+/// ```
+pub struct TokenStream<M> {
+    model: M,
+    logits_processor: LogitsProcessor,
+    device: Device,
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    processed: usize,
+    eos_token: Option<u32>,
+    max_new_tokens: Option<usize>,
+    stop_sequences: Vec<Vec<u32>>,
+    stop_reason: Option<StopReason>,
+    failed: bool,
+}
+
+impl<M: GenerationModel> TokenStream<M> {
+    pub fn new(
+        model: M,
+        logits_processor: LogitsProcessor,
+        prompt: &[u32],
+        device: &Device,
+    ) -> Self {
+        Self {
+            model,
+            logits_processor,
+            device: device.clone(),
+            tokens: prompt.to_vec(),
+            prompt_len: prompt.len(),
+            processed: 0,
+            eos_token: None,
+            max_new_tokens: None,
+            stop_sequences: vec![],
+            stop_reason: None,
+            failed: false,
+        }
+    }
+
+    pub fn with_eos_token(mut self, eos_token: u32) -> Self {
+        self.eos_token = Some(eos_token);
+        self
+    }
+
+    pub fn with_max_new_tokens(mut self, max_new_tokens: usize) -> Self {
+        self.max_new_tokens = Some(max_new_tokens);
+        self
+    }
+
+    /// Stops the generation once the generated tokens end with `stop_sequence`.
+    pub fn with_stop_sequence(mut self, stop_sequence: Vec<u32>) -> Self {
+        if !stop_sequence.is_empty() {
+            self.stop_sequences.push(stop_sequence)
+        }
+        self
+    }
+
+    /// The prompt followed by the tokens generated so far.
+    pub fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    /// The tokens generated so far.
+    pub fn generated_tokens(&self) -> &[u32] {
+        &self.tokens[self.prompt_len..]
+    }
+
+    /// Returns `None` while the generation is still in progress.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn into_inner(self) -> M {
+        self.model
+    }
+
+    fn step(&mut self) -> Result<Option<u32>> {
+        if self.stop_reason.is_some() {
+            return Ok(None);
+        }
+        if let Some(max_new_tokens) = self.max_new_tokens {
+            if self.tokens.len() - self.prompt_len >= max_new_tokens {
+                self.stop_reason = Some(StopReason::MaxNewTokens);
+                return Ok(None);
+            }
+        }
+        let input = &self.tokens[self.processed..];
+        if input.is_empty() {
+            candle::bail!("token stream requires a non-empty prompt")
+        }
+        let input = Tensor::new(input, &self.device)?.unsqueeze(0)?;
+        let logits = self.model.forward(&input, self.processed)?;
+        let vocab_size = match logits.dims().last() {
+            Some(&vocab_size) => vocab_size,
+            None => candle::bail!("unexpected scalar logits"),
+        };
+        let logits = logits.reshape(((), vocab_size))?;
+        let logits = logits.get(logits.dim(0)? - 1)?;
+        let token = self.logits_processor.sample(&logits)?;
+        self.processed = self.tokens.len();
+        if Some(token) == self.eos_token {
+            self.stop_reason = Some(StopReason::Eos);
+            return Ok(None);
+        }
+        self.tokens.push(token);
+        let generated = &self.tokens[self.prompt_len..];
+        if self.stop_sequences.iter().any(|s| generated.ends_with(s)) {
+            self.stop_reason = Some(StopReason::StopSequence);
+        }
+        Ok(Some(token))
+    }
+}
+
+impl<M: GenerationModel> Iterator for TokenStream<M> {
+    type Item = Result<u32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.step() {
+            Ok(token) => token.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Linear layers with GPTQ quantized weights.
+//!
+//! GPTQ checkpoints, e.g. the ones exported by AutoGPTQ, store each linear layer using the
+//! following tensors:
+//! - `qweight`, int32 with shape `(in_dim * bits / 32, out_dim)`. Each value packs `32 / bits`
+//!   consecutive input features, the first feature being stored in the lowest bits.
+//! - `qzeros`, int32 with shape `(n_groups, out_dim * bits / 32)`. The zero points are packed
+//!   along the output dimension and are stored minus one.
+//! - `scales`, with shape `(n_groups, out_dim)`.
+//! - `g_idx`, int32 with shape `(in_dim,)`, the group of each input feature. Groups are made of
+//!   consecutive input features unless the model has been quantized with `desc_act`.
+//!
+//! The weight for input `i` and output `o` is `scales[g, o] * (q[i, o] - (zeros[g, o] + 1))`
+//! where `g = g_idx[i]`.
+use candle::{DType, Device, Module, Result, Tensor};
+use candle_nn::VarBuilder;
+
+/// The quantization parameters, as found in `quantize_config.json`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct GptqConfig {
+    pub bits: usize,
+    /// The number of input features sharing the same scale and zero point, -1 means that all the
+    /// input features use a single group.
+    pub group_size: i64,
+    #[serde(default)]
+    pub desc_act: bool,
+}
+
+impl GptqConfig {
+    fn group_size(&self, in_dim: usize) -> usize {
+        if self.group_size <= 0 {
+            in_dim
+        } else {
+            self.group_size as usize
+        }
+    }
+}
+
+fn to_u32s(t: &Tensor) -> Result<Vec<u32>> {
+    // Safetensors int32 values are loaded as i64, only the lower 32 bits are meaningful.
+    let vs = t.flatten_all()?.to_dtype(DType::I64)?.to_vec1::<i64>()?;
+    Ok(vs.into_iter().map(|v| v as u32).collect())
+}
+
+/// A linear layer using GPTQ weights. The weights are unpacked once when the layer is created,
+/// on the device and with the dtype of `scales`.
+#[derive(Debug, Clone)]
+pub struct GptqLinear {
+    linear: candle_nn::Linear,
+    bits: usize,
+}
+
+impl GptqLinear {
+    pub fn new(
+        qweight: &Tensor,
+        qzeros: &Tensor,
+        scales: &Tensor,
+        g_idx: &Tensor,
+        bias: Option<Tensor>,
+        bits: usize,
+    ) -> Result<Self> {
+        if ![2, 4, 8].contains(&bits) {
+            candle::bail!("unsupported number of bits for gptq {bits}")
+        }
+        let pack = 32 / bits;
+        let (packed_in, out_dim) = qweight.dims2()?;
+        let in_dim = packed_in * pack;
+        let (n_groups, packed_out) = qzeros.dims2()?;
+        if packed_out * pack != out_dim {
+            candle::bail!(
+                "unexpected qzeros shape {:?} for qweight {:?}",
+                qzeros.shape(),
+                qweight.shape()
+            )
+        }
+        if scales.dims2()? != (n_groups, out_dim) {
+            candle::bail!(
+                "unexpected scales shape {:?}, expected {:?}",
+                scales.shape(),
+                (n_groups, out_dim)
+            )
+        }
+        if g_idx.dims1()? != in_dim {
+            candle::bail!(
+                "unexpected g_idx shape {:?}, expected {in_dim}",
+                g_idx.shape()
+            )
+        }
+        let g_idx = to_u32s(g_idx)?;
+        if let Some(&g) = g_idx.iter().find(|&&g| g as usize >= n_groups) {
+            candle::bail!("g_idx contains group {g} but there are only {n_groups} groups")
+        }
+        let qweight_ = to_u32s(qweight)?;
+        let qzeros = to_u32s(qzeros)?;
+        let scales_ = scales
+            .flatten_all()?
+            .to_dtype(DType::F32)?
+            .to_vec1::<f32>()?;
+
+        let mask = (1u32 << bits) - 1;
+        let zero_cols = out_dim / pack;
+        let mut ws = vec![0f32; in_dim * out_dim];
+        for (i, &g) in g_idx.iter().enumerate() {
+            let g = g as usize;
+            let qweight = &qweight_[(i / pack) * out_dim..(i / pack + 1) * out_dim];
+            let qzeros = &qzeros[g * zero_cols..(g + 1) * zero_cols];
+            let scales = &scales_[g * out_dim..(g + 1) * out_dim];
+            let shift = (i % pack) * bits;
+            for o in 0..out_dim {
+                let q = (qweight[o] >> shift) & mask;
+                let z = ((qzeros[o / pack] >> ((o % pack) * bits)) & mask) + 1;
+                ws[o * in_dim + i] = scales[o] * (q as f32 - z as f32)
+            }
+        }
+        let weight =
+            Tensor::from_vec(ws, (out_dim, in_dim), scales.device())?.to_dtype(scales.dtype())?;
+        Ok(Self {
+            linear: candle_nn::Linear::new(weight, bias),
+            bits,
+        })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.linear.bias()
+    }
+
+    /// The dequantized weights, with shape `(out_dim, in_dim)`.
+    pub fn weight(&self) -> &Tensor {
+        self.linear.weight()
+    }
+}
+
+impl Module for GptqLinear {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.linear.forward(x)
+    }
+}
+
+/// Loads a GPTQ linear layer, `g_idx` is optional and defaults to groups of consecutive input
+/// features.
+pub fn gptq_linear(
+    in_dim: usize,
+    out_dim: usize,
+    bias: bool,
+    cfg: &GptqConfig,
+    vb: VarBuilder,
+) -> Result<GptqLinear> {
+    let bits = cfg.bits;
+    if bits == 0 || 32 % bits != 0 || in_dim % (32 / bits) != 0 || out_dim % (32 / bits) != 0 {
+        candle::bail!("unsupported gptq layer {in_dim}x{out_dim} with {bits} bits")
+    }
+    let pack = 32 / bits;
+    let group_size = cfg.group_size(in_dim);
+    let n_groups = in_dim.div_ceil(group_size);
+    let qweight = vb.get_with_hints_dtype(
+        (in_dim / pack, out_dim),
+        "qweight",
+        Default::default(),
+        DType::I64,
+    )?;
+    let qzeros = vb.get_with_hints_dtype(
+        (n_groups, out_dim / pack),
+        "qzeros",
+        Default::default(),
+        DType::I64,
+    )?;
+    let scales = vb.get((n_groups, out_dim), "scales")?;
+    let g_idx = if vb.contains_tensor("g_idx") {
+        vb.get_with_hints_dtype(in_dim, "g_idx", Default::default(), DType::I64)?
+    } else {
+        let g_idx = (0..in_dim).map(|i| (i / group_size) as i64).collect();
+        Tensor::from_vec(g_idx, in_dim, &Device::Cpu)?
+    };
+    let bias = if bias {
+        Some(vb.get(out_dim, "bias")?)
+    } else {
+        None
+    };
+    GptqLinear::new(&qweight, &qzeros, &scales, &g_idx, bias, bits)
+}
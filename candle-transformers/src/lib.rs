@@ -1,5 +1,8 @@
 pub mod generation;
+pub mod gptq;
+pub mod lora;
 pub mod models;
+pub mod moe;
 pub mod object_detection;
 pub mod pipelines;
 pub mod quantized_nn;
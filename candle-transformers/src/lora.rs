@@ -0,0 +1,216 @@
+//! Low-rank adapters (LoRA) for linear layers.
+//!
+//! LoRA: Low-Rank Adaptation of Large Language Models https://arxiv.org/abs/2106.09685
+//!
+//! An adapted linear layer computes `base(x) + scale * x @ A^T @ B^T` where `A` has shape
+//! `(rank, in_dim)`, `B` has shape `(out_dim, rank)` and `scale` is `alpha / rank`. The adapters
+//! exported by peft (`adapter_model.safetensors`) store these matrices using keys such as
+//! `base_model.model.model.layers.0.self_attn.q_proj.lora_A.weight`, the hyper-parameters can
+//! be found in `adapter_config.json`.
+use candle::{DType, Device, Module, Result, Tensor};
+use std::collections::HashMap;
+
+/// The adapter hyper-parameters, as found in `adapter_config.json`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct LoraConfig {
+    pub r: usize,
+    pub lora_alpha: f64,
+    /// The names of the adapted modules, e.g. `q_proj`, an empty list matches all the modules.
+    #[serde(default)]
+    pub target_modules: Vec<String>,
+    /// Use `alpha / sqrt(rank)` as the scale rather than `alpha / rank`.
+    #[serde(default)]
+    pub use_rslora: bool,
+}
+
+impl LoraConfig {
+    pub fn scale(&self) -> f64 {
+        if self.use_rslora {
+            self.lora_alpha / (self.r as f64).sqrt()
+        } else {
+            self.lora_alpha / self.r as f64
+        }
+    }
+
+    fn is_target(&self, module: &str) -> bool {
+        let name = module.rsplit('.').next().unwrap_or(module);
+        self.target_modules.is_empty() || self.target_modules.iter().any(|t| t == name)
+    }
+}
+
+/// A linear layer with a low-rank adapter.
+#[derive(Debug, Clone)]
+pub struct LoraLinear {
+    base: candle_nn::Linear,
+    // The A and B matrices, this is None once the adapter has been merged.
+    lora: Option<(Tensor, Tensor)>,
+    scale: f64,
+}
+
+impl LoraLinear {
+    /// `lora_a` has shape `(rank, in_dim)` and `lora_b` has shape `(out_dim, rank)`.
+    pub fn new(
+        base: candle_nn::Linear,
+        lora_a: Tensor,
+        lora_b: Tensor,
+        scale: f64,
+    ) -> Result<Self> {
+        let (out_dim, in_dim) = base.weight().dims2()?;
+        let (rank, a_in_dim) = lora_a.dims2()?;
+        let (b_out_dim, b_rank) = lora_b.dims2()?;
+        if a_in_dim != in_dim || b_out_dim != out_dim || b_rank != rank {
+            candle::bail!(
+                "unexpected lora shapes A {:?} B {:?} for a {out_dim}x{in_dim} linear layer",
+                lora_a.shape(),
+                lora_b.shape()
+            )
+        }
+        Ok(Self {
+            base,
+            lora: Some((lora_a, lora_b)),
+            scale,
+        })
+    }
+
+    pub fn base(&self) -> &candle_nn::Linear {
+        &self.base
+    }
+
+    pub fn is_merged(&self) -> bool {
+        self.lora.is_none()
+    }
+
+    /// Folds the adapter into the base weights so that the forward pass has the same cost as
+    /// the base layer. This is a no-op if the adapter has already been merged.
+    pub fn merge(&mut self) -> Result<()> {
+        if let Some((a, b)) = self.lora.take() {
+            let weight = merge_weight(self.base.weight(), &a, &b, self.scale)?;
+            self.base = candle_nn::Linear::new(weight, self.base.bias().cloned());
+        }
+        Ok(())
+    }
+}
+
+impl Module for LoraLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let ys = self.base.forward(xs)?;
+        match &self.lora {
+            None => Ok(ys),
+            Some((a, b)) => {
+                let a = candle_nn::Linear::new(a.to_dtype(xs.dtype())?, None);
+                let b = candle_nn::Linear::new(b.to_dtype(xs.dtype())?, None);
+                ys + (b.forward(&a.forward(xs)?)? * self.scale)?
+            }
+        }
+    }
+}
+
+// Returns `weight + scale * b @ a`, the delta is computed in f32.
+fn merge_weight(weight: &Tensor, a: &Tensor, b: &Tensor, scale: f64) -> Result<Tensor> {
+    let delta = b
+        .to_dtype(DType::F32)?
+        .matmul(&a.to_dtype(DType::F32)?)?
+        .affine(scale, 0.)?;
+    (weight.to_dtype(DType::F32)? + delta)?.to_dtype(weight.dtype())
+}
+
+/// The A and B matrices of an adapter, indexed by the path of the module they apply to, e.g.
+/// `model.layers.0.self_attn.q_proj`.
+#[derive(Debug, Clone)]
+pub struct LoraAdapter {
+    layers: HashMap<String, (Tensor, Tensor)>,
+    config: LoraConfig,
+}
+
+impl LoraAdapter {
+    /// Builds an adapter from the tensors of `adapter_model.safetensors`. The peft
+    /// `base_model.model.` prefix is removed from the module paths, only the modules listed in
+    /// `target_modules` are kept.
+    pub fn from_tensors(tensors: HashMap<String, Tensor>, config: LoraConfig) -> Result<Self> {
+        let mut lora_a = HashMap::new();
+        let mut lora_b = HashMap::new();
+        for (name, tensor) in tensors.into_iter() {
+            let name = name.strip_prefix("base_model.model.").unwrap_or(&name);
+            // Some exports include the adapter name, e.g. `lora_A.default.weight`.
+            let name = name.replace(".default.weight", ".weight");
+            if let Some(module) = name.strip_suffix(".lora_A.weight") {
+                lora_a.insert(module.to_string(), tensor);
+            } else if let Some(module) = name.strip_suffix(".lora_B.weight") {
+                lora_b.insert(module.to_string(), tensor);
+            }
+        }
+        let mut layers = HashMap::new();
+        for (module, a) in lora_a.into_iter() {
+            let b = match lora_b.remove(&module) {
+                Some(b) => b,
+                None => candle::bail!("missing lora_B for {module}"),
+            };
+            if config.is_target(&module) {
+                layers.insert(module, (a, b));
+            }
+        }
+        if let Some(module) = lora_b.keys().next() {
+            candle::bail!("missing lora_A for {module}")
+        }
+        Ok(Self { layers, config })
+    }
+
+    /// Loads an adapter from a safetensors file, usually `adapter_model.safetensors`.
+    pub fn load<P: AsRef<std::path::Path>>(
+        p: P,
+        config: LoraConfig,
+        device: &Device,
+    ) -> Result<Self> {
+        let tensors = candle::safetensors::load(p, device)?;
+        Self::from_tensors(tensors, config)
+    }
+
+    pub fn config(&self) -> &LoraConfig {
+        &self.config
+    }
+
+    /// The paths of the adapted modules.
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.layers.keys().map(|k| k.as_str())
+    }
+
+    /// The A and B matrices for the module at `path`.
+    pub fn get(&self, path: &str) -> Option<(&Tensor, &Tensor)> {
+        self.layers.get(path).map(|(a, b)| (a, b))
+    }
+
+    /// Wraps `base`, the linear layer at `path`, with its adapter. The layer is returned without
+    /// any adapter if `path` is not adapted.
+    pub fn linear(&self, base: candle_nn::Linear, path: &str) -> Result<LoraLinear> {
+        match self.layers.get(path) {
+            Some((a, b)) => {
+                let device = base.weight().device();
+                let (a, b) = (a.to_device(device)?, b.to_device(device)?);
+                LoraLinear::new(base, a, b, self.config.scale())
+            }
+            None => Ok(LoraLinear {
+                base,
+                lora: None,
+                scale: self.config.scale(),
+            }),
+        }
+    }
+
+    /// Folds the adapter into the weights of a base model, indexed by their full names, e.g.
+    /// `model.layers.0.self_attn.q_proj.weight`. The resulting weights can be used with
+    /// `VarBuilder::from_tensors` to run the adapted model without any overhead.
+    pub fn merge_into(&self, weights: &mut HashMap<String, Tensor>) -> Result<()> {
+        for (module, (a, b)) in self.layers.iter() {
+            let name = format!("{module}.weight");
+            let weight = match weights.get(&name) {
+                Some(weight) => weight,
+                None => candle::bail!("cannot find {name} in the base weights"),
+            };
+            let a = a.to_device(weight.device())?;
+            let b = b.to_device(weight.device())?;
+            let merged = merge_weight(weight, &a, &b, self.config.scale())?;
+            weights.insert(name, merged);
+        }
+        Ok(())
+    }
+}
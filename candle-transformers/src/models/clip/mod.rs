@@ -135,13 +135,28 @@ impl ClipModel {
             .apply(&self.visual_projection)
     }
 
+    /// Returns the L2 normalized text embeddings with shape `(batch, projection_dim)`, the cosine
+    /// similarity with the image embeddings is a simple dot product.
+    pub fn encode_text(&self, input_ids: &Tensor) -> Result<Tensor> {
+        div_l2_norm(&self.get_text_features(input_ids)?)
+    }
+
+    /// Returns the L2 normalized image embeddings with shape `(batch, projection_dim)`.
+    pub fn encode_image(&self, pixel_values: &Tensor) -> Result<Tensor> {
+        div_l2_norm(&self.get_image_features(pixel_values)?)
+    }
+
+    /// The scale applied to the cosine similarities to get the logits, this is the exponential
+    /// of the learned `logit_scale` parameter.
+    pub fn logit_scale(&self) -> Result<Tensor> {
+        self.logit_scale.exp()
+    }
+
     pub fn forward(&self, pixel_values: &Tensor, input_ids: &Tensor) -> Result<(Tensor, Tensor)> {
-        let image_features = self.get_image_features(pixel_values)?;
-        let text_features = self.get_text_features(input_ids)?;
-        let image_features_normalized = div_l2_norm(&image_features)?;
-        let text_features_normalized = div_l2_norm(&text_features)?;
+        let image_features_normalized = self.encode_image(pixel_values)?;
+        let text_features_normalized = self.encode_text(input_ids)?;
         let logits_per_text = text_features_normalized.matmul(&image_features_normalized.t()?)?;
-        let logit_scale = self.logit_scale.exp()?;
+        let logit_scale = self.logit_scale()?;
         let logits_per_text = logits_per_text.broadcast_mul(&logit_scale)?;
         let logits_per_image = logits_per_text.t()?;
         Ok((logits_per_text, logits_per_image))
@@ -116,6 +116,7 @@ impl ResidualConvUnit {
             stride: 1,
             dilation: 1,
             groups: 1,
+            ..Default::default()
         };
         let conv1 = conv2d(
             conf.num_features,
@@ -200,6 +201,7 @@ impl FeatureFusionBlock {
             stride: 1,
             dilation: 1,
             groups: 1,
+            ..Default::default()
         };
         let output_conv = conv2d(
             conf.num_features,
@@ -250,6 +252,7 @@ impl Scratch {
             stride: 1,
             dilation: 1,
             groups: 1,
+            ..Default::default()
         };
 
         let layer1_rn = conv2d_no_bias(
@@ -311,6 +314,7 @@ impl Scratch {
             stride: 1,
             dilation: 1,
             groups: 1,
+            ..Default::default()
         };
         let output_conv1 = conv2d(
             conf.num_features,
@@ -415,6 +419,7 @@ impl<'a> DPTHead<'a> {
                     stride: 2,
                     dilation: 1,
                     groups: 1,
+                    ..Default::default()
                 },
                 vb.pp("resize_layers").pp("3"),
             )?),
@@ -28,7 +28,7 @@ pub struct Config {
 }
 
 impl Config {
-    fn hidden_act(&self) -> Result<Activation> {
+    pub(crate) fn hidden_act(&self) -> Result<Activation> {
         match (self.hidden_act, self.hidden_activation) {
             (None, Some(act)) | (Some(act), None) => Ok(act),
             (Some(_), Some(_)) => candle::bail!("both hidden_act and hidden_activation are set"),
@@ -38,7 +38,7 @@ impl Config {
 }
 
 #[derive(Debug, Clone)]
-struct RmsNorm {
+pub(crate) struct RmsNorm {
     weight: Tensor,
     eps: f64,
 }
@@ -48,6 +48,10 @@ impl RmsNorm {
         let weight = vb.get(dim, "weight")?;
         Ok(Self { weight, eps })
     }
+
+    pub(crate) fn from_weight(weight: Tensor, eps: f64) -> Self {
+        Self { weight, eps }
+    }
 }
 
 impl Module for RmsNorm {
@@ -68,13 +72,13 @@ impl Module for RmsNorm {
 }
 
 #[derive(Debug, Clone)]
-struct RotaryEmbedding {
+pub(crate) struct RotaryEmbedding {
     sin: Tensor,
     cos: Tensor,
 }
 
 impl RotaryEmbedding {
-    fn new(dtype: DType, cfg: &Config, dev: &Device) -> Result<Self> {
+    pub(crate) fn new(dtype: DType, cfg: &Config, dev: &Device) -> Result<Self> {
         let dim = cfg.head_dim;
         let max_seq_len = cfg.max_position_embeddings;
         let inv_freq: Vec<_> = (0..dim)
@@ -93,7 +97,7 @@ impl RotaryEmbedding {
         })
     }
 
-    fn apply_rotary_emb_qkv(
+    pub(crate) fn apply_rotary_emb_qkv(
         &self,
         q: &Tensor,
         k: &Tensor,
@@ -1,6 +1,10 @@
 use super::with_tracing::{linear_no_bias as linear, Linear, RmsNorm};
 use candle::{DType, Device, IndexOp, Result, Tensor, D};
-use candle_nn::{embedding, Embedding, Module, VarBuilder};
+use candle_nn::{
+    embedding,
+    rotary_emb::{RopeScaling, RopeTables},
+    Embedding, Module, VarBuilder,
+};
 use std::collections::HashMap;
 
 pub const MAX_SEQ_LEN: usize = 4096;
@@ -16,6 +20,11 @@ pub struct LlamaConfig {
     pub rms_norm_eps: f64,
     #[serde(default = "default_rope")]
     pub rope_theta: f32,
+    #[serde(
+        default,
+        deserialize_with = "candle_nn::rotary_emb::deserialize_rope_scaling"
+    )]
+    pub rope_scaling: Option<RopeScaling>,
     pub bos_token_id: Option<u32>,
     pub eos_token_id: Option<u32>,
 }
@@ -41,6 +50,7 @@ impl LlamaConfig {
             num_key_value_heads: self.num_key_value_heads(),
             rms_norm_eps: self.rms_norm_eps,
             rope_theta: self.rope_theta,
+            rope_scaling: self.rope_scaling,
             use_flash_attn,
             bos_token_id: self.bos_token_id,
             eos_token_id: self.eos_token_id,
@@ -59,6 +69,7 @@ pub struct Config {
     pub use_flash_attn: bool,
     pub rms_norm_eps: f64,
     pub rope_theta: f32,
+    pub rope_scaling: Option<RopeScaling>,
     pub bos_token_id: Option<u32>,
     pub eos_token_id: Option<u32>,
 }
@@ -75,6 +86,7 @@ impl Config {
             use_flash_attn,
             rms_norm_eps: 1e-6,
             rope_theta: 10_000.0,
+            rope_scaling: None,
             bos_token_id: None,
             eos_token_id: None,
         }
@@ -91,6 +103,7 @@ impl Config {
             use_flash_attn,
             rms_norm_eps: 1e-5,
             rope_theta: 10_000.0,
+            rope_scaling: None,
             bos_token_id: None,
             eos_token_id: None,
         }
@@ -102,8 +115,7 @@ pub struct Cache {
     masks: HashMap<usize, Tensor>,
     pub use_kv_cache: bool,
     kvs: Vec<Option<(Tensor, Tensor)>>,
-    cos: Tensor,
-    sin: Tensor,
+    rope: RopeTables,
     device: Device,
 }
 
@@ -111,38 +123,51 @@ impl Cache {
     pub fn new(use_kv_cache: bool, dtype: DType, config: &Config, device: &Device) -> Result<Self> {
         // precompute freqs_cis
         let n_elem = config.hidden_size / config.num_attention_heads;
-        let theta: Vec<_> = (0..n_elem)
-            .step_by(2)
-            .map(|i| 1f32 / config.rope_theta.powf(i as f32 / n_elem as f32))
-            .collect();
-        let theta = Tensor::new(theta.as_slice(), device)?;
-        let idx_theta = Tensor::arange(0, MAX_SEQ_LEN as u32, device)?
-            .to_dtype(DType::F32)?
-            .reshape((MAX_SEQ_LEN, 1))?
-            .matmul(&theta.reshape((1, theta.elem_count()))?)?;
         // This is different from the paper, see:
         // https://github.com/huggingface/transformers/blob/6112b1c6442aaf7affd2b0676a1cd4eee30c45cf/src/transformers/models/llama/modeling_llama.py#L112
-        let cos = idx_theta.cos()?.to_dtype(dtype)?;
-        let sin = idx_theta.sin()?.to_dtype(dtype)?;
+        let rope = RopeTables::new(
+            n_elem,
+            config.rope_theta as f64,
+            MAX_SEQ_LEN,
+            config.rope_scaling.clone(),
+            dtype,
+            device,
+        )?;
         Ok(Self {
             masks: HashMap::new(),
             use_kv_cache,
             kvs: vec![None; config.num_hidden_layers],
             device: device.clone(),
-            cos,
-            sin,
+            rope,
         })
     }
 
-    fn mask(&mut self, t: usize) -> Result<Tensor> {
-        if let Some(mask) = self.masks.get(&t) {
+    /// Discards the keys and values past the first `seq_len` positions, e.g. the draft tokens
+    /// rejected in speculative decoding.
+    pub fn truncate(&mut self, seq_len: usize) -> Result<()> {
+        for (k, v) in self.kvs.iter_mut().flatten() {
+            if k.dim(2)? > seq_len {
+                *k = k.narrow(2, 0, seq_len)?;
+                *v = v.narrow(2, 0, seq_len)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The causal mask for `t` queries attending to `kv_len` keys, the queries being the last
+    // positions. Only the masks without a kv cache offset are memoized.
+    fn mask(&mut self, t: usize, kv_len: usize) -> Result<Tensor> {
+        if let Some(mask) = self.masks.get(&t).filter(|_| t == kv_len) {
             Ok(mask.clone())
         } else {
+            let offset = kv_len - t;
             let mask: Vec<_> = (0..t)
-                .flat_map(|i| (0..t).map(move |j| u8::from(j > i)))
+                .flat_map(|i| (0..kv_len).map(move |j| u8::from(j > i + offset)))
                 .collect();
-            let mask = Tensor::from_slice(&mask, (t, t), &self.device)?;
-            self.masks.insert(t, mask.clone());
+            let mask = Tensor::from_slice(&mask, (t, kv_len), &self.device)?;
+            if t == kv_len {
+                self.masks.insert(t, mask.clone());
+            }
             Ok(mask)
         }
     }
@@ -182,8 +207,7 @@ impl CausalSelfAttention {
     fn apply_rotary_emb(&self, x: &Tensor, index_pos: usize, cache: &Cache) -> Result<Tensor> {
         let _enter = self.span_rot.enter();
         let (_b_sz, _, seq_len, _hidden_size) = x.dims4()?;
-        let cos = cache.cos.narrow(0, index_pos, seq_len)?;
-        let sin = cache.sin.narrow(0, index_pos, seq_len)?;
+        let (cos, sin) = cache.rope.narrow(index_pos, seq_len)?;
         candle_nn::rotary_emb::rope(x, &cos, &sin)
     }
 
@@ -254,7 +278,8 @@ impl CausalSelfAttention {
             let att = if seq_len == 1 {
                 att
             } else {
-                let mask = cache.mask(seq_len)?.broadcast_as(att.shape())?;
+                let kv_len = att.dim(D::Minus1)?;
+                let mask = cache.mask(seq_len, kv_len)?.broadcast_as(att.shape())?;
                 masked_fill(&att, &mask, f32::NEG_INFINITY)?
             };
             let att = candle_nn::ops::softmax(&att, D::Minus1)?;
@@ -72,6 +72,7 @@ impl LLaVAConfig {
             num_key_value_heads: self.num_key_value_heads,
             rms_norm_eps: self.rms_norm_eps as f64,
             rope_theta: self.rope_theta,
+            rope_scaling: None,
             bos_token_id: Some(self.bos_token_id as u32),
             eos_token_id: Some(self.eos_token_id as u32),
             use_flash_attn: false,
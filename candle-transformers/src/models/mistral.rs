@@ -1,7 +1,10 @@
 use crate::models::with_tracing::{linear_no_bias, Linear, RmsNorm};
 /// Mistral LLM, https://github.com/mistralai/mistral-src
-use candle::{DType, Device, Module, Result, Tensor, D};
-use candle_nn::{Activation, VarBuilder};
+use candle::{DType, Device, Module, Result, Tensor};
+use candle_nn::{
+    rotary_emb::{RopeScaling, RopeTables},
+    Activation, VarBuilder,
+};
 use std::sync::Arc;
 
 fn default_use_flash_attn() -> bool {
@@ -20,6 +23,11 @@ pub struct Config {
     pub max_position_embeddings: usize,
     pub rms_norm_eps: f64,
     pub rope_theta: f64,
+    #[serde(
+        default,
+        deserialize_with = "candle_nn::rotary_emb::deserialize_rope_scaling"
+    )]
+    pub rope_scaling: Option<RopeScaling>,
     pub sliding_window: Option<usize>,
     #[serde(default = "default_use_flash_attn")]
     pub use_flash_attn: bool,
@@ -39,6 +47,7 @@ impl Config {
             max_position_embeddings: 32768,
             rms_norm_eps: 1e-5,
             rope_theta: 10_000.,
+            rope_scaling: None,
             sliding_window: Some(4096),
             use_flash_attn,
         }
@@ -58,6 +67,7 @@ impl Config {
             max_position_embeddings: 32768,
             rms_norm_eps: 1e-5,
             rope_theta: 10_000.,
+            rope_scaling: None,
             sliding_window: Some(4096),
             use_flash_attn,
         }
@@ -76,6 +86,7 @@ impl Config {
             max_position_embeddings: 32768,
             rms_norm_eps: 1e-5,
             rope_theta: 10_000.,
+            rope_scaling: None,
             sliding_window: Some(4096),
             use_flash_attn,
         }
@@ -84,29 +95,21 @@ impl Config {
 
 #[derive(Debug, Clone)]
 struct RotaryEmbedding {
-    sin: Tensor,
-    cos: Tensor,
+    tables: RopeTables,
 }
 
 impl RotaryEmbedding {
     fn new(dtype: DType, cfg: &Config, dev: &Device) -> Result<Self> {
-        let rope_theta = cfg.rope_theta as f32;
         let dim = cfg.hidden_size / cfg.num_attention_heads;
-        let max_seq_len = cfg.max_position_embeddings;
-        let inv_freq: Vec<_> = (0..dim)
-            .step_by(2)
-            .map(|i| 1f32 / rope_theta.powf(i as f32 / dim as f32))
-            .collect();
-        let inv_freq_len = inv_freq.len();
-        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), dev)?.to_dtype(dtype)?;
-        let t = Tensor::arange(0u32, max_seq_len as u32, dev)?
-            .to_dtype(dtype)?
-            .reshape((max_seq_len, 1))?;
-        let freqs = t.matmul(&inv_freq)?;
-        Ok(Self {
-            sin: freqs.sin()?,
-            cos: freqs.cos()?,
-        })
+        let tables = RopeTables::new(
+            dim,
+            cfg.rope_theta,
+            cfg.max_position_embeddings,
+            cfg.rope_scaling.clone(),
+            dtype,
+            dev,
+        )?;
+        Ok(Self { tables })
     }
 
     fn apply_rotary_emb_qkv(
@@ -116,8 +119,7 @@ impl RotaryEmbedding {
         seqlen_offset: usize,
     ) -> Result<(Tensor, Tensor)> {
         let (_b_sz, _h, seq_len, _n_embd) = q.dims4()?;
-        let cos = self.cos.narrow(0, seqlen_offset, seq_len)?;
-        let sin = self.sin.narrow(0, seqlen_offset, seq_len)?;
+        let (cos, sin) = self.tables.narrow(seqlen_offset, seq_len)?;
         let q_embed = candle_nn::rotary_emb::rope(q, &cos, &sin)?;
         let k_embed = candle_nn::rotary_emb::rope(k, &cos, &sin)?;
         Ok((q_embed, k_embed))
@@ -284,6 +286,16 @@ impl Attention {
     fn clear_kv_cache(&mut self) {
         self.kv_cache = None
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        if let Some((k, v)) = self.kv_cache.as_mut() {
+            if k.dim(2)? > seq_len {
+                *k = k.narrow(2, 0, seq_len)?;
+                *v = v.narrow(2, 0, seq_len)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -331,6 +343,10 @@ impl DecoderLayer {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache()
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.self_attn.truncate_kv_cache(seq_len)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -374,49 +390,61 @@ impl Model {
         tgt_len: usize,
         seqlen_offset: usize,
     ) -> Result<Tensor> {
-        let sliding_window = self.sliding_window.unwrap_or(tgt_len + 1);
-        let mask: Vec<_> = (0..tgt_len)
-            .flat_map(|i| {
-                (0..tgt_len).map(move |j| {
-                    if i < j || j + sliding_window < i {
-                        f32::NEG_INFINITY
-                    } else {
-                        0.
-                    }
-                })
-            })
-            .collect();
-        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.device)?;
-        let mask = if seqlen_offset > 0 {
-            let mask0 = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.device)?;
-            Tensor::cat(&[&mask0, &mask], D::Minus1)?
-        } else {
-            mask
-        };
+        let mask = crate::utils::sliding_window_causal_mask(
+            tgt_len,
+            seqlen_offset,
+            self.sliding_window,
+            DType::F32,
+            &self.device,
+        )?;
         mask.expand((1, 1, tgt_len, tgt_len + seqlen_offset))?
             .to_dtype(self.dtype)
     }
 
-    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+    fn forward_hidden(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
         let (_b_size, seq_len) = input_ids.dims2()?;
-        let attention_mask = if seq_len <= 1 {
-            None
-        } else {
-            let mask = self.prepare_decoder_attention_mask(seq_len, seqlen_offset)?;
-            Some(mask)
-        };
+        let attention_mask =
+            if seq_len <= 1 && !matches!(self.sliding_window, Some(w) if seqlen_offset > w) {
+                None
+            } else {
+                let mask = self.prepare_decoder_attention_mask(seq_len, seqlen_offset)?;
+                Some(mask)
+            };
         let mut xs = self.embed_tokens.forward(input_ids)?;
         for layer in self.layers.iter_mut() {
             xs = layer.forward(&xs, attention_mask.as_ref(), seqlen_offset)?
         }
+        Ok(xs)
+    }
+
+    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let (_b_size, seq_len) = input_ids.dims2()?;
+        let xs = self.forward_hidden(input_ids, seqlen_offset)?;
         xs.narrow(1, seq_len - 1, 1)?
             .apply(&self.norm)?
             .apply(&self.lm_head)
     }
 
+    /// Same as `forward` but returns the logits for all the positions, with shape
+    /// `(b_size, seq_len, vocab_size)`, e.g. to verify draft tokens in speculative decoding.
+    pub fn forward_all(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        self.forward_hidden(input_ids, seqlen_offset)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)
+    }
+
     pub fn clear_kv_cache(&mut self) {
         for layer in self.layers.iter_mut() {
             layer.clear_kv_cache()
         }
     }
+
+    /// Discards the kv cache entries past the first `seq_len` positions, e.g. the draft tokens
+    /// rejected in speculative decoding.
+    pub fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        for layer in self.layers.iter_mut() {
+            layer.truncate_kv_cache(seq_len)?
+        }
+        Ok(())
+    }
 }
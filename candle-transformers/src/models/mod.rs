@@ -36,6 +36,7 @@ pub mod phi;
 pub mod phi3;
 pub mod quantized_blip;
 pub mod quantized_blip_text;
+pub mod quantized_gemma;
 pub mod quantized_llama;
 pub mod quantized_llama2_c;
 pub mod quantized_metavoice;
@@ -45,6 +46,7 @@ pub mod quantized_moondream;
 pub mod quantized_mpt;
 pub mod quantized_phi;
 pub mod quantized_phi3;
+pub mod quantized_qwen2;
 pub mod quantized_recurrent_gemma;
 pub mod quantized_rwkv_v5;
 pub mod quantized_rwkv_v6;
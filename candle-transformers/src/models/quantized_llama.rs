@@ -4,7 +4,7 @@ use crate::quantized_nn::RmsNorm;
 use candle::quantized::QTensor;
 use candle::quantized::{ggml_file, gguf_file};
 use candle::{DType, Device, IndexOp, Result, Tensor};
-use candle_nn::{Embedding, Module};
+use candle_nn::{kv_cache::KvCache, Embedding, Module};
 
 pub const MAX_SEQ_LEN: usize = 4096;
 
@@ -21,7 +21,9 @@ impl QMatMul {
         let span = tracing::span!(tracing::Level::TRACE, "qmatmul");
         Ok(Self { inner, span })
     }
+}
 
+impl Module for QMatMul {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         let _enter = self.span.enter();
         self.inner.forward(xs)
@@ -47,78 +49,13 @@ impl Module for Mlp {
 #[derive(Debug, Clone)]
 enum MlpOrMoe {
     Mlp(Mlp),
-    MoE {
-        n_expert_used: usize,
-        feed_forward_gate_inp: QMatMul,
-        experts: Vec<Mlp>,
-    },
+    MoE(crate::moe::SparseMoeBlock<QMatMul, Mlp>),
 }
 
 impl Module for MlpOrMoe {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         match self {
-            Self::MoE {
-                feed_forward_gate_inp,
-                experts,
-                n_expert_used,
-            } => {
-                let (b_size, seq_len, hidden_dim) = xs.dims3()?;
-                let xs = xs.reshape(((), hidden_dim))?;
-                let router_logits = feed_forward_gate_inp.forward(&xs)?;
-                let routing_weights = candle_nn::ops::softmax_last_dim(&router_logits)?;
-
-                // In order to extract topk, we extract the data from the tensor and manipulate it
-                // directly. Maybe we will want to use some custom ops instead at some point.
-                let routing_weights = routing_weights.to_dtype(DType::F32)?.to_vec2::<f32>()?;
-
-                // routing_weights, selected_experts = torch.topk(routing_weights, self.top_k, dim=-1)
-                // top_x contains the row indexes to evaluate for each expert.
-                let mut top_x = vec![vec![]; experts.len()];
-                let mut selected_rws = vec![vec![]; experts.len()];
-                for (row_idx, rw) in routing_weights.iter().enumerate() {
-                    let mut dst = (0..rw.len() as u32).collect::<Vec<u32>>();
-                    dst.sort_by(|&i, &j| rw[j as usize].total_cmp(&rw[i as usize]));
-                    let mut sum_routing_weights = 0f32;
-                    for &expert_idx in dst.iter().take(*n_expert_used) {
-                        let expert_idx = expert_idx as usize;
-                        let routing_weight = rw[expert_idx];
-                        sum_routing_weights += routing_weight;
-                        top_x[expert_idx].push(row_idx as u32);
-                    }
-                    for &expert_idx in dst.iter().take(*n_expert_used) {
-                        let expert_idx = expert_idx as usize;
-                        let routing_weight = rw[expert_idx];
-                        selected_rws[expert_idx].push(routing_weight / sum_routing_weights)
-                    }
-                }
-
-                // routing_weights /= routing_weights.sum(dim=-1, keepdim=True)
-                // expert_mask = torch.nn.functional.one_hot(selected_experts, num_classes=self.num_experts).permute(2, 1, 0)
-
-                let mut ys = xs.zeros_like()?;
-                for (expert_idx, expert_layer) in experts.iter().enumerate() {
-                    let top_x = &top_x[expert_idx];
-                    if top_x.is_empty() {
-                        continue;
-                    }
-                    let top_x = Tensor::new(top_x.as_slice(), xs.device())?;
-                    let selected_rws =
-                        Tensor::new(selected_rws[expert_idx].as_slice(), xs.device())?
-                            .reshape(((), 1))?;
-                    // Index the correct hidden states and compute the expert hidden state for
-                    // the current expert. We need to make sure to multiply the output hidden
-                    // states by `routing_weights` on the corresponding tokens (top-1 and top-2)
-                    let current_state = xs.index_select(&top_x, 0)?.reshape(((), hidden_dim))?;
-                    // current_hidden_states = expert_layer(current_state, routing_weights[top_x_list, idx_list, None])
-                    let current_hidden_states = expert_layer.forward(&current_state)?;
-                    let current_hidden_states =
-                        current_hidden_states.broadcast_mul(&selected_rws)?;
-                    ys = ys.index_add(&top_x, &current_hidden_states, 0)?;
-                }
-
-                let ys = ys.reshape((b_size, seq_len, hidden_dim))?;
-                Ok(ys)
-            }
+            Self::MoE(moe) => moe.forward(xs),
             Self::Mlp(mlp) => mlp.forward(xs),
         }
     }
@@ -140,6 +77,8 @@ struct LayerWeights {
     sin: Tensor,
     neg_inf: Tensor,
     kv_cache: Option<(Tensor, Tensor)>,
+    // Used in place of kv_cache when the keys and values are stored as int8.
+    quantized_kv_cache: Option<KvCache>,
     span_attn: tracing::Span,
     span_rot: tracing::Span,
     span_mlp: tracing::Span,
@@ -191,19 +130,30 @@ impl LayerWeights {
         let q = self.apply_rotary_emb(&q, index_pos)?;
         let k = self.apply_rotary_emb(&k, index_pos)?;
 
-        let (k, v) = match &self.kv_cache {
-            None => (k, v),
-            Some((k_cache, v_cache)) => {
+        let (k, v) = match self.quantized_kv_cache.as_mut() {
+            Some(kv_cache) => {
                 if index_pos == 0 {
-                    (k, v)
-                } else {
-                    let k = Tensor::cat(&[k_cache, &k], 2)?;
-                    let v = Tensor::cat(&[v_cache, &v], 2)?;
-                    (k, v)
+                    kv_cache.reset()
                 }
+                kv_cache.append(&k, &v)?
+            }
+            None => {
+                let (k, v) = match &self.kv_cache {
+                    None => (k, v),
+                    Some((k_cache, v_cache)) => {
+                        if index_pos == 0 {
+                            (k, v)
+                        } else {
+                            let k = Tensor::cat(&[k_cache, &k], 2)?;
+                            let v = Tensor::cat(&[v_cache, &v], 2)?;
+                            (k, v)
+                        }
+                    }
+                };
+                self.kv_cache = Some((k.clone(), v.clone()));
+                (k, v)
             }
         };
-        self.kv_cache = Some((k.clone(), v.clone()));
 
         // Support for MQA, useful for 70B models and mistral.
         let k = crate::utils::repeat_kv(k, self.n_head / self.n_kv_head)?;
@@ -302,6 +252,7 @@ impl ModelWeights {
                 sin: sin.clone(),
                 neg_inf: neg_inf.clone(),
                 kv_cache: None,
+                quantized_kv_cache: None,
                 span_attn,
                 span_rot,
                 span_mlp,
@@ -395,11 +346,12 @@ impl ModelWeights {
                         feed_forward_w3: QMatMul::from_qtensor(feed_forward_w3)?,
                     })
                 }
-                MlpOrMoe::MoE {
-                    n_expert_used,
-                    feed_forward_gate_inp: QMatMul::from_qtensor(feed_forward_gate_inp)?,
+                MlpOrMoe::MoE(crate::moe::SparseMoeBlock::new(
+                    QMatMul::from_qtensor(feed_forward_gate_inp)?,
                     experts,
-                }
+                    n_expert_used,
+                    true,
+                )?)
             };
             let attention_norm =
                 ct.tensor(reader, &format!("{prefix}.attn_norm.weight"), device)?;
@@ -422,6 +374,7 @@ impl ModelWeights {
                 sin: sin.clone(),
                 neg_inf: neg_inf.clone(),
                 kv_cache: None,
+                quantized_kv_cache: None,
                 span_attn,
                 span_rot,
                 span_mlp,
@@ -440,15 +393,45 @@ impl ModelWeights {
         })
     }
 
-    fn mask(&mut self, t: usize, device: &Device) -> Result<Tensor> {
-        if let Some(mask) = self.masks.get(&t) {
+    /// Stores the keys and values of the kv cache as int8 rather than f32, this divides the memory
+    /// used by the cache by roughly four. The current content of the cache is discarded.
+    pub fn use_quantized_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            layer.kv_cache = None;
+            layer.quantized_kv_cache = Some(KvCache::new_quantized(2, MAX_SEQ_LEN))
+        }
+    }
+
+    /// Discards the kv cache entries past the first `seq_len` positions, e.g. the draft tokens
+    /// rejected in speculative decoding.
+    pub fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        for layer in self.layers.iter_mut() {
+            if let Some(kv_cache) = layer.quantized_kv_cache.as_mut() {
+                kv_cache.truncate(seq_len)
+            }
+            if let Some((k, v)) = layer.kv_cache.as_mut() {
+                if k.dim(2)? > seq_len {
+                    *k = k.narrow(2, 0, seq_len)?;
+                    *v = v.narrow(2, 0, seq_len)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The causal mask for `t` queries following `index_pos` cached positions. Only the masks
+    // without a kv cache offset are memoized.
+    fn mask(&mut self, t: usize, index_pos: usize, device: &Device) -> Result<Tensor> {
+        if let Some(mask) = self.masks.get(&t).filter(|_| index_pos == 0) {
             Ok(mask.clone())
         } else {
             let mask: Vec<_> = (0..t)
-                .flat_map(|i| (0..t).map(move |j| u8::from(j > i)))
+                .flat_map(|i| (0..t + index_pos).map(move |j| u8::from(j > i + index_pos)))
                 .collect();
-            let mask = Tensor::from_slice(&mask, (t, t), device)?;
-            self.masks.insert(t, mask.clone());
+            let mask = Tensor::from_slice(&mask, (t, t + index_pos), device)?;
+            if index_pos == 0 {
+                self.masks.insert(t, mask.clone());
+            }
             Ok(mask)
         }
     }
@@ -458,7 +441,7 @@ impl ModelWeights {
         let mask = if seq_len == 1 {
             None
         } else {
-            Some(self.mask(seq_len, x.device())?)
+            Some(self.mask(seq_len, index_pos, x.device())?)
         };
         let _enter = self.span.enter();
         let mut layer_in = self.tok_embeddings.forward(x)?;
@@ -1,6 +1,6 @@
 use crate::quantized_nn::{linear_no_bias, Embedding, Linear, RmsNorm};
 pub use crate::quantized_var_builder::VarBuilder;
-use candle::{DType, Device, Module, Result, Tensor, D};
+use candle::{DType, Device, Module, Result, Tensor};
 use candle_nn::Activation;
 use std::sync::Arc;
 
@@ -183,6 +183,16 @@ impl Attention {
     fn clear_kv_cache(&mut self) {
         self.kv_cache = None
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        if let Some((k, v)) = self.kv_cache.as_mut() {
+            if k.dim(2)? > seq_len {
+                *k = k.narrow(2, 0, seq_len)?;
+                *v = v.narrow(2, 0, seq_len)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -230,6 +240,10 @@ impl DecoderLayer {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache()
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.self_attn.truncate_kv_cache(seq_len)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -271,50 +285,62 @@ impl Model {
         tgt_len: usize,
         seqlen_offset: usize,
     ) -> Result<Tensor> {
-        let sliding_window = self.sliding_window.unwrap_or(tgt_len + 1);
-        let mask: Vec<_> = (0..tgt_len)
-            .flat_map(|i| {
-                (0..tgt_len).map(move |j| {
-                    if i < j || j + sliding_window < i {
-                        f32::NEG_INFINITY
-                    } else {
-                        0.
-                    }
-                })
-            })
-            .collect();
-        let mask = Tensor::from_slice(&mask, (tgt_len, tgt_len), &self.device)?;
-        let mask = if seqlen_offset > 0 {
-            let mask0 = Tensor::zeros((tgt_len, seqlen_offset), DType::F32, &self.device)?;
-            Tensor::cat(&[&mask0, &mask], D::Minus1)?
-        } else {
-            mask
-        };
+        let mask = crate::utils::sliding_window_causal_mask(
+            tgt_len,
+            seqlen_offset,
+            self.sliding_window,
+            DType::F32,
+            &self.device,
+        )?;
         mask.expand((1, 1, tgt_len, tgt_len + seqlen_offset))?
             .to_dtype(DType::F32)
     }
 
-    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+    fn forward_hidden(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
         let (_b_size, seq_len) = input_ids.dims2()?;
-        let attention_mask = if seq_len <= 1 {
-            None
-        } else {
-            let mask = self.prepare_decoder_attention_mask(seq_len, seqlen_offset)?;
-            Some(mask)
-        };
+        let attention_mask =
+            if seq_len <= 1 && !matches!(self.sliding_window, Some(w) if seqlen_offset > w) {
+                None
+            } else {
+                let mask = self.prepare_decoder_attention_mask(seq_len, seqlen_offset)?;
+                Some(mask)
+            };
         let mut xs = self.embed_tokens.forward(input_ids)?;
         for layer in self.layers.iter_mut() {
             xs = layer.forward(&xs, attention_mask.as_ref(), seqlen_offset)?
         }
+        Ok(xs)
+    }
+
+    pub fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        let (_b_size, seq_len) = input_ids.dims2()?;
+        let xs = self.forward_hidden(input_ids, seqlen_offset)?;
         xs.narrow(1, seq_len - 1, 1)?
             .contiguous()?
             .apply(&self.norm)?
             .apply(&self.lm_head)
     }
 
+    /// Same as `forward` but returns the logits for all the positions, with shape
+    /// `(b_size, seq_len, vocab_size)`, e.g. to verify draft tokens in speculative decoding.
+    pub fn forward_all(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        self.forward_hidden(input_ids, seqlen_offset)?
+            .apply(&self.norm)?
+            .apply(&self.lm_head)
+    }
+
     pub fn clear_kv_cache(&mut self) {
         for layer in self.layers.iter_mut() {
             layer.clear_kv_cache()
         }
     }
+
+    /// Discards the kv cache entries past the first `seq_len` positions, e.g. the draft tokens
+    /// rejected in speculative decoding.
+    pub fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        for layer in self.layers.iter_mut() {
+            layer.truncate_kv_cache(seq_len)?
+        }
+        Ok(())
+    }
 }
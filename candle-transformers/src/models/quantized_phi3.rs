@@ -296,6 +296,15 @@ impl ModelWeights {
         }
     }
 
+    /// Stores the keys and values of the kv cache as int8, this roughly halves the memory used by
+    /// the cache. The current content of the cache is discarded.
+    pub fn use_quantized_kv_cache(&mut self) {
+        for layer in self.layers.iter_mut() {
+            let max_seq_len = layer.kv_cache.k_cache().max_seq_len();
+            layer.kv_cache = KvCache::new_quantized(2, max_seq_len)
+        }
+    }
+
     pub fn forward(&mut self, xs: &Tensor, index_pos: usize) -> Result<Tensor> {
         let (_b_sz, seq_len) = xs.dims2()?;
         let mask = if seq_len == 1 {
@@ -5,6 +5,7 @@ use crate::{
 use candle::{IndexOp, Result, Tensor};
 use candle_nn::{GroupNorm, LayerNorm, Module};
 
+use crate::models::rwkv_v5::token_shift;
 pub use crate::models::rwkv_v5::{Config, State, Tokenizer};
 
 #[derive(Debug, Clone)]
@@ -90,17 +91,12 @@ impl SelfAttention {
         let s = s / h;
         let (receptance, key, value, gate) = {
             // extract key-value
-            let shifted = state.per_layer[self.layer_id].extract_key_value.clone();
-            let shifted = if shifted.rank() == 2 {
-                shifted.unsqueeze(1)?
-            } else {
-                shifted
-            };
-            let key = ((xs * &self.time_mix_key)? + &shifted * (1.0 - &self.time_mix_key)?)?;
-            let value = ((xs * &self.time_mix_value)? + &shifted * (1.0 - &self.time_mix_value)?)?;
-            let receptance = ((xs * &self.time_mix_receptance)?
-                + &shifted * (1.0 - &self.time_mix_receptance)?)?;
-            let gate = ((xs * &self.time_mix_gate)? + &shifted * (1.0 - &self.time_mix_gate)?)?;
+            let shifted = token_shift(xs, &state.per_layer[self.layer_id].extract_key_value)?;
+            let mix = |m: &Tensor| xs.broadcast_mul(m)? + shifted.broadcast_mul(&(1.0 - m)?)?;
+            let key = mix(&self.time_mix_key)?;
+            let value = mix(&self.time_mix_value)?;
+            let receptance = mix(&self.time_mix_receptance)?;
+            let gate = mix(&self.time_mix_gate)?;
 
             let key = self.key.forward(&key)?;
             let value = self.value.forward(&value)?;
@@ -138,7 +134,7 @@ impl SelfAttention {
             state_ = (&at + time_decay.broadcast_mul(&state_))?;
             out.push(out_)
         }
-        let out = Tensor::cat(&out, 1)?.reshape((b * t, h * s, 1))?;
+        let out = Tensor::stack(&out, 1)?.reshape((b * t, h * s, 1))?;
         let out = out.apply(&self.ln_x)?.reshape((b, t, h * s))?;
         let out = (out * gate)?.apply(&self.output)?;
         state.per_layer[self.layer_id].linear_attention = state_;
@@ -181,7 +177,7 @@ impl FeedForward {
     }
 
     fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let shifted = &state.per_layer[self.layer_id].feed_forward;
+        let shifted = token_shift(xs, &state.per_layer[self.layer_id].feed_forward)?;
         let key = (xs.broadcast_mul(&self.time_mix_key)?
             + shifted.broadcast_mul(&(1.0 - &self.time_mix_key)?)?)?;
         let receptance = (xs.broadcast_mul(&self.time_mix_receptance)?
@@ -271,7 +267,7 @@ impl Model {
     }
 
     pub fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let (_b_size, _seq_len) = xs.dims2()?;
+        let (_b_size, seq_len) = xs.dims2()?;
         let mut xs = xs.apply(&self.embeddings)?;
         for (block_idx, block) in self.blocks.iter().enumerate() {
             xs = block.forward(&xs, state)?;
@@ -280,7 +276,7 @@ impl Model {
             }
         }
         let xs = xs.apply(&self.ln_out)?.apply(&self.head)?;
-        state.pos += 1;
+        state.pos += seq_len;
         Ok(xs)
     }
 }
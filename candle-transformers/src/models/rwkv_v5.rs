@@ -22,14 +22,22 @@ pub struct Config {
     pub rescale_every: usize,
 }
 
+/// The recurrent state of a layer, this is all that is needed to process the next tokens so it
+/// can be stored and restored between calls.
+#[derive(Debug, Clone)]
 pub struct StatePerLayer {
+    /// The last input of the time mixing block, used for the token shift.
     pub extract_key_value: Tensor,
+    /// The wkv state of the linear attention, with shape `(batch, heads, head_size, head_size)`.
     pub linear_attention: Tensor,
+    /// The last input of the channel mixing block, used for the token shift.
     pub feed_forward: Tensor,
 }
 
+#[derive(Debug, Clone)]
 pub struct State {
     pub per_layer: Vec<StatePerLayer>,
+    /// The number of tokens processed so far.
     pub pos: usize,
 }
 
@@ -61,6 +69,18 @@ impl State {
     }
 }
 
+// Shifts `xs`, with shape `(batch, seq_len, hidden_size)`, by one position along the sequence,
+// `prev` is the last position of the previous call and has shape `(batch, hidden_size)`.
+pub(crate) fn token_shift(xs: &Tensor, prev: &Tensor) -> Result<Tensor> {
+    let seq_len = xs.dim(1)?;
+    let prev = prev.unsqueeze(1)?;
+    if seq_len == 1 {
+        Ok(prev)
+    } else {
+        Tensor::cat(&[&prev, &xs.narrow(1, 0, seq_len - 1)?], 1)
+    }
+}
+
 #[derive(Debug, Clone)]
 struct SelfAttention {
     key: Linear,
@@ -125,17 +145,12 @@ impl SelfAttention {
         let s = s / h;
         let (receptance, key, value, gate) = {
             // extract key-value
-            let shifted = state.per_layer[self.layer_id].extract_key_value.clone();
-            let shifted = if shifted.rank() == 2 {
-                shifted.unsqueeze(1)?
-            } else {
-                shifted
-            };
-            let key = ((xs * &self.time_mix_key)? + &shifted * (1.0 - &self.time_mix_key)?)?;
-            let value = ((xs * &self.time_mix_value)? + &shifted * (1.0 - &self.time_mix_value)?)?;
-            let receptance = ((xs * &self.time_mix_receptance)?
-                + &shifted * (1.0 - &self.time_mix_receptance)?)?;
-            let gate = ((xs * &self.time_mix_gate)? + &shifted * (1.0 - &self.time_mix_gate)?)?;
+            let shifted = token_shift(xs, &state.per_layer[self.layer_id].extract_key_value)?;
+            let mix = |m: &Tensor| xs.broadcast_mul(m)? + shifted.broadcast_mul(&(1.0 - m)?)?;
+            let key = mix(&self.time_mix_key)?;
+            let value = mix(&self.time_mix_value)?;
+            let receptance = mix(&self.time_mix_receptance)?;
+            let gate = mix(&self.time_mix_gate)?;
 
             let key = self.key.forward(&key)?;
             let value = self.value.forward(&value)?;
@@ -173,7 +188,7 @@ impl SelfAttention {
             state_ = (&at + time_decay.broadcast_mul(&state_))?;
             out.push(out_)
         }
-        let out = Tensor::cat(&out, 1)?.reshape((b * t, h * s, 1))?;
+        let out = Tensor::stack(&out, 1)?.reshape((b * t, h * s, 1))?;
         let out = out.apply(&self.ln_x)?.reshape((b, t, h * s))?;
         let out = (out * gate)?.apply(&self.output)?;
         state.per_layer[self.layer_id].linear_attention = state_;
@@ -212,7 +227,7 @@ impl FeedForward {
     }
 
     pub fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let shifted = &state.per_layer[self.layer_id].feed_forward;
+        let shifted = token_shift(xs, &state.per_layer[self.layer_id].feed_forward)?;
         let key = (xs.broadcast_mul(&self.time_mix_key)?
             + shifted.broadcast_mul(&(1.0 - &self.time_mix_key)?)?)?;
         let receptance = (xs.broadcast_mul(&self.time_mix_receptance)?
@@ -302,7 +317,7 @@ impl Model {
     }
 
     pub fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let (_b_size, _seq_len) = xs.dims2()?;
+        let (_b_size, seq_len) = xs.dims2()?;
         let mut xs = xs.apply(&self.embeddings)?;
         for (block_idx, block) in self.blocks.iter().enumerate() {
             xs = block.forward(&xs, state)?;
@@ -311,7 +326,7 @@ impl Model {
             }
         }
         let xs = xs.apply(&self.ln_out)?.apply(&self.head)?;
-        state.pos += 1;
+        state.pos += seq_len;
         Ok(xs)
     }
 }
@@ -2,6 +2,7 @@ use super::with_tracing::{layer_norm, linear_no_bias as linear, LayerNorm, Linea
 use candle::{IndexOp, Result, Tensor};
 use candle_nn::{embedding, Embedding, Module, VarBuilder};
 
+use crate::models::rwkv_v5::token_shift;
 pub use crate::models::rwkv_v5::{Config, State, Tokenizer};
 
 #[derive(Debug, Clone)]
@@ -87,15 +88,10 @@ impl SelfAttention {
         let s = s / h;
         let (receptance, key, value, gate, w) = {
             // extract key-value
-            let shifted = state.per_layer[self.layer_id].extract_key_value.clone();
-            let shifted = if shifted.rank() == 2 {
-                shifted.unsqueeze(1)?
-            } else {
-                shifted
-            };
+            let shifted = token_shift(xs, &state.per_layer[self.layer_id].extract_key_value)?;
 
             let sx = (&shifted - xs)?;
-            let xxx = (xs + &sx * &self.time_mix_x)?;
+            let xxx = (xs + sx.broadcast_mul(&self.time_mix_x)?)?;
             let xxx = xxx
                 .broadcast_matmul(&self.time_mix_w1)?
                 .tanh()?
@@ -106,18 +102,19 @@ impl SelfAttention {
 
             let (mw, mk, mv, mr, mg) = (xxx.i(0)?, xxx.i(1)?, xxx.i(2)?, xxx.i(3)?, xxx.i(4)?);
 
-            let xw = (xs + &sx * (&self.time_mix_w + &mw)?)?;
-            let xk = (xs + &sx * (&self.time_mix_key + &mk)?)?;
-            let xv = (xs + &sx * (&self.time_mix_value + &mv)?)?;
-            let xr = (xs + &sx * (&self.time_mix_receptance + &mr)?)?;
-            let xg = (xs + &sx * (&self.time_mix_gate + &mg)?)?;
+            let xw = (xs + &sx * self.time_mix_w.broadcast_add(&mw)?)?;
+            let xk = (xs + &sx * self.time_mix_key.broadcast_add(&mk)?)?;
+            let xv = (xs + &sx * self.time_mix_value.broadcast_add(&mv)?)?;
+            let xr = (xs + &sx * self.time_mix_receptance.broadcast_add(&mr)?)?;
+            let xg = (xs + &sx * self.time_mix_gate.broadcast_add(&mg)?)?;
 
-            let w = (&self.time_decay
-                + xw.broadcast_matmul(&self.time_decay_w1)?
-                    .tanh()?
-                    .broadcast_matmul(&self.time_decay_w2)?)?
-            .reshape(((), 1, 1))?
-            .reshape((self.n_attn_heads, (), 1))?;
+            // The decay depends on the input so there is one value per time step.
+            let w = xw
+                .broadcast_matmul(&self.time_decay_w1)?
+                .tanh()?
+                .broadcast_matmul(&self.time_decay_w2)?
+                .broadcast_add(&self.time_decay)?
+                .reshape((b, t, h, s))?;
 
             let key = self.key.forward(&xk)?;
             let value = self.value.forward(&xv)?;
@@ -148,10 +145,11 @@ impl SelfAttention {
             let at = kt.matmul(&vt)?;
             let rhs = (time_faaaa.broadcast_mul(&at)? + &state_)?;
             let out_ = rt.matmul(&rhs)?.squeeze(2)?;
-            state_ = (&at + w.broadcast_mul(&state_))?;
+            let wt = w.i((.., t_))?.unsqueeze(3)?;
+            state_ = (&at + wt.broadcast_mul(&state_))?;
             out.push(out_)
         }
-        let out = Tensor::cat(&out, 1)?.reshape((b * t, h * s, 1))?;
+        let out = Tensor::stack(&out, 1)?.reshape((b * t, h * s, 1))?;
         let out = out.apply(&self.ln_x)?.reshape((b, t, h * s))?;
         let out = (out * gate)?.apply(&self.output)?;
         state.per_layer[self.layer_id].linear_attention = state_;
@@ -190,9 +188,7 @@ impl FeedForward {
     }
 
     fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let shifted = state.per_layer[self.layer_id]
-            .feed_forward
-            .broadcast_sub(xs)?;
+        let shifted = (token_shift(xs, &state.per_layer[self.layer_id].feed_forward)? - xs)?;
         let key = (xs + shifted.broadcast_mul(&self.time_mix_key)?)?;
         let receptance = (xs + shifted.broadcast_mul(&self.time_mix_receptance)?)?;
         let key = key.apply(&self.key)?.relu()?.sqr()?;
@@ -280,7 +276,7 @@ impl Model {
     }
 
     pub fn forward(&self, xs: &Tensor, state: &mut State) -> Result<Tensor> {
-        let (_b_size, _seq_len) = xs.dims2()?;
+        let (_b_size, seq_len) = xs.dims2()?;
         let mut xs = xs.apply(&self.embeddings)?;
         for (block_idx, block) in self.blocks.iter().enumerate() {
             xs = block.forward(&xs, state)?;
@@ -289,7 +285,7 @@ impl Model {
             }
         }
         let xs = xs.apply(&self.ln_out)?.apply(&self.head)?;
-        state.pos += 1;
+        state.pos += seq_len;
         Ok(xs)
     }
 }
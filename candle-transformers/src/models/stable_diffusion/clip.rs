@@ -27,17 +27,16 @@ impl Module for Activation {
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    vocab_size: usize,
-    embed_dim: usize,       // aka config.hidden_size
-    activation: Activation, // aka config.hidden_act
-    intermediate_size: usize,
+    pub vocab_size: usize,
+    pub embed_dim: usize,       // aka config.hidden_size
+    pub activation: Activation, // aka config.hidden_act
+    pub intermediate_size: usize,
     pub max_position_embeddings: usize,
     // The character to use for padding, use EOS when not set.
     pub pad_with: Option<String>,
-    num_hidden_layers: usize,
-    num_attention_heads: usize,
-    #[allow(dead_code)]
-    projection_dim: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub projection_dim: usize,
 }
 
 impl Config {
@@ -388,6 +387,29 @@ impl ClipTextTransformer {
         let xs = self.encoder.forward(&xs, &causal_attention_mask)?;
         self.final_layer_norm.forward(&xs)
     }
+
+    /// Returns the hidden states of the penultimate layer, as used by SDXL, together with the
+    /// output of the final layer norm.
+    pub fn forward_with_penultimate(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (bsz, seq_len) = xs.dims2()?;
+        let xs = self.embeddings.forward(xs)?;
+        let causal_attention_mask =
+            Self::build_causal_attention_mask(bsz, seq_len, usize::MAX, xs.device())?;
+        let n_layers = self.encoder.layers.len();
+        let mut xs = xs;
+        let mut penultimate = None;
+        for (i, layer) in self.encoder.layers.iter().enumerate() {
+            if i + 1 == n_layers {
+                penultimate = Some(xs.clone())
+            }
+            xs = layer.forward(&xs, &causal_attention_mask)?;
+        }
+        let penultimate = match penultimate {
+            Some(penultimate) => penultimate,
+            None => candle::bail!("the penultimate layer requires at least one layer"),
+        };
+        Ok((penultimate, self.final_layer_norm.forward(&xs)?))
+    }
 }
 
 impl Module for ClipTextTransformer {
@@ -395,3 +417,40 @@ impl Module for ClipTextTransformer {
         self.forward_with_mask(xs, usize::MAX)
     }
 }
+
+/// A CLIP text transformer followed by a projection of the pooled output, the second SDXL text
+/// encoder uses this to produce the pooled text embeddings.
+#[derive(Debug)]
+pub struct ClipTextModelWithProjection {
+    text_model: ClipTextTransformer,
+    text_projection: nn::Linear,
+}
+
+impl ClipTextModelWithProjection {
+    pub fn new(vs: candle_nn::VarBuilder, c: &Config) -> Result<Self> {
+        let text_model = ClipTextTransformer::new(vs.clone(), c)?;
+        let text_projection =
+            nn::linear_no_bias(c.embed_dim, c.projection_dim, vs.pp("text_projection"))?;
+        Ok(Self {
+            text_model,
+            text_projection,
+        })
+    }
+
+    /// Returns the hidden states of the penultimate layer, with shape
+    /// `(batch, seq_len, embed_dim)`, and the pooled text embeddings with shape
+    /// `(batch, projection_dim)`. The pooled output is taken at the end of text token, i.e. the
+    /// token with the largest id.
+    pub fn forward(&self, xs: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (penultimate, last) = self.text_model.forward_with_penultimate(xs)?;
+        let eos_positions = xs.argmax(D::Minus1)?.to_vec1::<u32>()?;
+        let pooled = eos_positions
+            .iter()
+            .enumerate()
+            .map(|(b, &p)| last.get(b)?.get(p as usize))
+            .collect::<Result<Vec<_>>>()?;
+        let pooled = Tensor::stack(&pooled, 0)?;
+        let text_embeds = self.text_projection.forward(&pooled)?;
+        Ok((penultimate, text_embeds))
+    }
+}
@@ -0,0 +1,235 @@
+//! ControlNet
+//!
+//! Adding Conditional Control to Text-to-Image Diffusion Models.
+//! https://arxiv.org/abs/2302.05543
+//!
+//! A ControlNet is a copy of the UNet down and mid blocks that takes an additional conditioning
+//! image, e.g. edges, depth or pose. Its outputs go through zero-initialized convolutions and
+//! are added to the skip connections and to the mid block output of the UNet, see
+//! [`UNet2DConditionModel::forward_with_additional_residuals`]. The weights follow the diffusers
+//! `ControlNetModel` layout.
+//!
+//! [`UNet2DConditionModel::forward_with_additional_residuals`]: super::unet_2d::UNet2DConditionModel::forward_with_additional_residuals
+use super::embeddings::{TimestepEmbedding, Timesteps};
+use super::unet_2d::{unet_down_blocks, unet_mid_block, UNet2DConditionModelConfig, UNetDownBlock};
+use super::unet_2d_blocks::UNetMidBlock2DCrossAttn;
+use crate::models::with_tracing::{conv2d, Conv2d};
+use candle::{Result, Tensor};
+use candle_nn as nn;
+use candle_nn::Module;
+
+#[derive(Debug, Clone)]
+pub struct ControlNetConfig {
+    /// The number of channels of the conditioning image.
+    pub conditioning_channels: usize,
+    /// The channels of the convolutions embedding the conditioning image, each additional
+    /// element halves the spatial resolution.
+    pub conditioning_embedding_out_channels: Vec<usize>,
+}
+
+impl Default for ControlNetConfig {
+    fn default() -> Self {
+        Self {
+            conditioning_channels: 3,
+            conditioning_embedding_out_channels: vec![16, 32, 96, 256],
+        }
+    }
+}
+
+// Embeds the conditioning image in the latent space, the image has a resolution eight times
+// larger than the latents with the default config.
+#[derive(Debug)]
+struct ControlNetConditioningEmbedding {
+    conv_in: Conv2d,
+    blocks: Vec<Conv2d>,
+    conv_out: Conv2d,
+}
+
+impl ControlNetConditioningEmbedding {
+    fn new(
+        vs: nn::VarBuilder,
+        conditioning_channels: usize,
+        block_out_channels: &[usize],
+        out_channels: usize,
+    ) -> Result<Self> {
+        let conv_cfg = nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let stride_cfg = nn::Conv2dConfig {
+            padding: 1,
+            stride: 2,
+            ..Default::default()
+        };
+        let b_channels = block_out_channels[0];
+        let conv_in = conv2d(
+            conditioning_channels,
+            b_channels,
+            3,
+            conv_cfg,
+            vs.pp("conv_in"),
+        )?;
+        let vs_b = vs.pp("blocks");
+        let mut blocks = Vec::with_capacity(2 * block_out_channels.len());
+        for (i, w) in block_out_channels.windows(2).enumerate() {
+            let (c_in, c_out) = (w[0], w[1]);
+            blocks.push(conv2d(c_in, c_in, 3, conv_cfg, vs_b.pp(2 * i))?);
+            blocks.push(conv2d(c_in, c_out, 3, stride_cfg, vs_b.pp(2 * i + 1))?);
+        }
+        let bl_channels = *block_out_channels.last().unwrap();
+        let conv_out = conv2d(bl_channels, out_channels, 3, conv_cfg, vs.pp("conv_out"))?;
+        Ok(Self {
+            conv_in,
+            blocks,
+            conv_out,
+        })
+    }
+}
+
+impl Module for ControlNetConditioningEmbedding {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut xs = nn::ops::silu(&self.conv_in.forward(xs)?)?;
+        for block in self.blocks.iter() {
+            xs = nn::ops::silu(&block.forward(&xs)?)?;
+        }
+        self.conv_out.forward(&xs)
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlNet {
+    conv_in: Conv2d,
+    time_proj: Timesteps,
+    time_embedding: TimestepEmbedding,
+    controlnet_cond_embedding: ControlNetConditioningEmbedding,
+    down_blocks: Vec<UNetDownBlock>,
+    controlnet_down_blocks: Vec<Conv2d>,
+    mid_block: UNetMidBlock2DCrossAttn,
+    controlnet_mid_block: Conv2d,
+    span: tracing::Span,
+}
+
+impl ControlNet {
+    /// `unet_config` is the config of the UNet the ControlNet has been trained for, only its
+    /// down and mid blocks are used.
+    pub fn new(
+        vs: nn::VarBuilder,
+        in_channels: usize,
+        use_flash_attn: bool,
+        unet_config: &UNet2DConditionModelConfig,
+        config: &ControlNetConfig,
+    ) -> Result<Self> {
+        let b_channels = unet_config.blocks[0].out_channels;
+        let bl_channels = unet_config.blocks.last().unwrap().out_channels;
+        let time_embed_dim = b_channels * 4;
+        let conv_cfg = nn::Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let conv_in = conv2d(in_channels, b_channels, 3, conv_cfg, vs.pp("conv_in"))?;
+        let time_proj = Timesteps::new(
+            b_channels,
+            unet_config.flip_sin_to_cos,
+            unet_config.freq_shift,
+        );
+        let time_embedding =
+            TimestepEmbedding::new(vs.pp("time_embedding"), b_channels, time_embed_dim)?;
+        let controlnet_cond_embedding = ControlNetConditioningEmbedding::new(
+            vs.pp("controlnet_cond_embedding"),
+            config.conditioning_channels,
+            &config.conditioning_embedding_out_channels,
+            b_channels,
+        )?;
+        let down_blocks = unet_down_blocks(
+            vs.pp("down_blocks"),
+            unet_config,
+            time_embed_dim,
+            use_flash_attn,
+        )?;
+        let mid_block = unet_mid_block(
+            vs.pp("mid_block"),
+            unet_config,
+            time_embed_dim,
+            use_flash_attn,
+        )?;
+
+        // One 1x1 convolution per UNet skip connection: the conv_in output, the output of each
+        // resnet and the output of each downsampler.
+        let n_blocks = unet_config.blocks.len();
+        let mut channels = vec![b_channels];
+        for (i, block) in unet_config.blocks.iter().enumerate() {
+            let n_layers = unet_config.layers_per_block + usize::from(i < n_blocks - 1);
+            channels.extend_from_slice(&vec![block.out_channels; n_layers]);
+        }
+        let vs_cdb = vs.pp("controlnet_down_blocks");
+        let controlnet_down_blocks = channels
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| conv2d(c, c, 1, Default::default(), vs_cdb.pp(i)))
+            .collect::<Result<Vec<_>>>()?;
+        let controlnet_mid_block = conv2d(
+            bl_channels,
+            bl_channels,
+            1,
+            Default::default(),
+            vs.pp("controlnet_mid_block"),
+        )?;
+        let span = tracing::span!(tracing::Level::TRACE, "controlnet");
+        Ok(Self {
+            conv_in,
+            time_proj,
+            time_embedding,
+            controlnet_cond_embedding,
+            down_blocks,
+            controlnet_down_blocks,
+            mid_block,
+            controlnet_mid_block,
+            span,
+        })
+    }
+
+    /// Returns the residuals for the UNet skip connections and for the mid block output, both
+    /// scaled by `conditioning_scale`.
+    ///
+    /// `controlnet_cond` is the conditioning image with values between 0 and 1, its resolution
+    /// is eight times the latent resolution with the default config.
+    pub fn forward(
+        &self,
+        xs: &Tensor,
+        timestep: f64,
+        encoder_hidden_states: &Tensor,
+        controlnet_cond: &Tensor,
+        conditioning_scale: f64,
+    ) -> Result<(Vec<Tensor>, Tensor)> {
+        let _enter = self.span.enter();
+        let bsize = xs.dim(0)?;
+        // 1. time
+        let emb = (Tensor::ones(bsize, xs.dtype(), xs.device())? * timestep)?;
+        let emb = self.time_proj.forward(&emb)?;
+        let emb = self.time_embedding.forward(&emb)?;
+        // 2. pre-process
+        let xs = self.conv_in.forward(xs)?;
+        let cond = self.controlnet_cond_embedding.forward(controlnet_cond)?;
+        let xs = (xs + cond)?;
+        // 3. down
+        let mut down_block_res_xs = vec![xs.clone()];
+        let mut xs = xs;
+        for down_block in self.down_blocks.iter() {
+            let (_xs, res_xs) = down_block.forward(&xs, &emb, encoder_hidden_states)?;
+            down_block_res_xs.extend(res_xs);
+            xs = _xs;
+        }
+        // 4. mid
+        let xs = self
+            .mid_block
+            .forward(&xs, Some(&emb), Some(encoder_hidden_states))?;
+        // 5. control net blocks
+        let down_block_res_xs = down_block_res_xs
+            .iter()
+            .zip(self.controlnet_down_blocks.iter())
+            .map(|(xs, block)| block.forward(xs)? * conditioning_scale)
+            .collect::<Result<Vec<_>>>()?;
+        let mid_block_res_xs = (self.controlnet_mid_block.forward(&xs)? * conditioning_scale)?;
+        Ok((down_block_res_xs, mid_block_res_xs))
+    }
+}
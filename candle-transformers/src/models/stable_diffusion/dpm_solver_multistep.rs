@@ -0,0 +1,232 @@
+//! DPM-Solver++ multistep scheduler.
+//!
+//! DPM-Solver++: Fast Solver for Guided Sampling of Diffusion Probabilistic Models.
+//! https://arxiv.org/abs/2211.01095
+//!
+//! This follows the diffusers `DPMSolverMultistepScheduler` with the `dpmsolver++` algorithm,
+//! the `midpoint` solver type, `lower_order_final` and a final sigma of zero.
+use super::{
+    schedulers::{
+        betas_for_alpha_bar, BetaSchedule, PredictionType, Scheduler, SchedulerConfig,
+        TimestepSpacing,
+    },
+    utils::interp,
+};
+use candle::{bail, Result, Tensor};
+use std::sync::Mutex;
+
+/// The configuration for the DPM-Solver++ multistep scheduler.
+#[derive(Debug, Clone, Copy)]
+pub struct DPMSolverMultistepSchedulerConfig {
+    /// The value of beta at the beginning of training.
+    pub beta_start: f64,
+    /// The value of beta at the end of training.
+    pub beta_end: f64,
+    /// How beta evolved during training.
+    pub beta_schedule: BetaSchedule,
+    /// Adjust the indexes of the inference schedule by this value.
+    pub steps_offset: usize,
+    /// prediction type of the scheduler function, one of `epsilon` (predicting
+    /// the noise of the diffusion process), `sample` (directly predicting the noisy sample`)
+    /// or `v_prediction` (see section 2.4 https://imagen.research.google/video/paper.pdf)
+    pub prediction_type: PredictionType,
+    /// number of diffusion steps used to train the model
+    pub train_timesteps: usize,
+    /// time step spacing for the diffusion process
+    pub timestep_spacing: TimestepSpacing,
+    /// The order of the solver, either 1 or 2.
+    pub solver_order: usize,
+}
+
+impl Default for DPMSolverMultistepSchedulerConfig {
+    fn default() -> Self {
+        Self {
+            beta_start: 0.00085f64,
+            beta_end: 0.012f64,
+            beta_schedule: BetaSchedule::ScaledLinear,
+            steps_offset: 1,
+            prediction_type: PredictionType::Epsilon,
+            train_timesteps: 1000,
+            timestep_spacing: TimestepSpacing::Leading,
+            solver_order: 2,
+        }
+    }
+}
+
+impl SchedulerConfig for DPMSolverMultistepSchedulerConfig {
+    fn build(&self, inference_steps: usize) -> Result<Box<dyn Scheduler>> {
+        Ok(Box::new(DPMSolverMultistepScheduler::new(
+            inference_steps,
+            *self,
+        )?))
+    }
+}
+
+/// The DPM-Solver++ multistep scheduler.
+///
+/// The second order updates use the data prediction from the previous step, this is stored in
+/// the scheduler so `step` has to be called on consecutive timesteps. When the previous step is
+/// not available, e.g. at the start of an img2img run, a first order update is used.
+#[derive(Debug)]
+pub struct DPMSolverMultistepScheduler {
+    timesteps: Vec<usize>,
+    sigmas: Vec<f64>,
+    // The step index and the predicted original sample of the last call to `step`.
+    last_output: Mutex<Option<(usize, Tensor)>>,
+    pub config: DPMSolverMultistepSchedulerConfig,
+}
+
+impl DPMSolverMultistepScheduler {
+    pub fn new(inference_steps: usize, config: DPMSolverMultistepSchedulerConfig) -> Result<Self> {
+        if !(1..=2).contains(&config.solver_order) {
+            bail!("unsupported solver order {}", config.solver_order)
+        }
+        let train_timesteps = config.train_timesteps;
+        let timesteps: Vec<usize> = match config.timestep_spacing {
+            TimestepSpacing::Leading => {
+                let step_ratio = train_timesteps / (inference_steps + 1);
+                (1..=inference_steps)
+                    .rev()
+                    .map(|s| s * step_ratio + config.steps_offset)
+                    .collect()
+            }
+            TimestepSpacing::Trailing => {
+                let step_ratio = train_timesteps as f64 / inference_steps as f64;
+                (0..inference_steps)
+                    .map(|s| (train_timesteps as f64 - s as f64 * step_ratio).round() as usize - 1)
+                    .collect()
+            }
+            TimestepSpacing::Linspace => {
+                super::utils::linspace(0.0, (train_timesteps - 1) as f64, inference_steps + 1)?
+                    .to_vec1::<f64>()?
+                    .iter()
+                    .skip(1)
+                    .map(|&f| f.round_ties_even() as usize)
+                    .rev()
+                    .collect()
+            }
+        };
+
+        let betas = match config.beta_schedule {
+            BetaSchedule::ScaledLinear => super::utils::linspace(
+                config.beta_start.sqrt(),
+                config.beta_end.sqrt(),
+                train_timesteps,
+            )?
+            .sqr()?,
+            BetaSchedule::Linear => {
+                super::utils::linspace(config.beta_start, config.beta_end, train_timesteps)?
+            }
+            BetaSchedule::SquaredcosCapV2 => betas_for_alpha_bar(train_timesteps, 0.999)?,
+        };
+        let betas = betas.to_vec1::<f64>()?;
+        let mut alphas_cumprod = Vec::with_capacity(betas.len());
+        for &beta in betas.iter() {
+            let alpha = 1.0 - beta;
+            alphas_cumprod.push(alpha * *alphas_cumprod.last().unwrap_or(&1f64))
+        }
+        let sigmas: Vec<f64> = alphas_cumprod
+            .iter()
+            .map(|&f| ((1. - f) / f).sqrt())
+            .collect();
+        let sigmas_xa: Vec<_> = (0..sigmas.len()).map(|i| i as f64).collect();
+        let mut sigmas = interp(
+            &timesteps.iter().map(|&t| t as f64).collect::<Vec<_>>(),
+            &sigmas_xa,
+            &sigmas,
+        );
+        sigmas.push(0.0);
+
+        Ok(Self {
+            timesteps,
+            sigmas,
+            last_output: Mutex::new(None),
+            config,
+        })
+    }
+
+    /// The sigmas for each timestep, followed by the final sigma of zero.
+    pub fn sigmas(&self) -> &[f64] {
+        &self.sigmas
+    }
+
+    // Returns (alpha_t, sigma_t) such that x_t = alpha_t * x_0 + sigma_t * noise.
+    fn alpha_sigma_t(sigma: f64) -> (f64, f64) {
+        let alpha_t = 1. / (sigma * sigma + 1.).sqrt();
+        (alpha_t, sigma * alpha_t)
+    }
+
+    fn lambda(sigma: f64) -> f64 {
+        let (alpha_t, sigma_t) = Self::alpha_sigma_t(sigma);
+        alpha_t.ln() - sigma_t.ln()
+    }
+}
+
+impl Scheduler for DPMSolverMultistepScheduler {
+    fn timesteps(&self) -> &[usize] {
+        self.timesteps.as_slice()
+    }
+
+    fn scale_model_input(&self, sample: Tensor, _timestep: usize) -> Result<Tensor> {
+        Ok(sample)
+    }
+
+    fn step(&self, model_output: &Tensor, timestep: usize, sample: &Tensor) -> Result<Tensor> {
+        let step_index = match self.timesteps.iter().position(|&t| t == timestep) {
+            Some(i) => i,
+            None => bail!("timestep out of this schedulers bounds: {timestep}"),
+        };
+        let sigma_s0 = self.sigmas[step_index];
+        let sigma_t = self.sigmas[step_index + 1];
+        let (alpha_s0, sigma_s0_t) = Self::alpha_sigma_t(sigma_s0);
+
+        // Convert the model output to a prediction of the original sample.
+        let x0_pred = match self.config.prediction_type {
+            PredictionType::Epsilon => ((sample - (model_output * sigma_s0_t)?)? / alpha_s0)?,
+            PredictionType::VPrediction => ((sample * alpha_s0)? - (model_output * sigma_s0_t)?)?,
+            PredictionType::Sample => model_output.clone(),
+        };
+
+        let (alpha_t, sigma_t_t) = Self::alpha_sigma_t(sigma_t);
+        // With a final sigma of zero, lambda_t is infinite and the update returns x0_pred.
+        let h = Self::lambda(sigma_t) - Self::lambda(sigma_s0);
+        let coef = alpha_t * ((-h).exp() - 1.0);
+        let sample_coef = sigma_t_t / sigma_s0_t;
+
+        let mut last_output = match self.last_output.lock() {
+            Ok(last_output) => last_output,
+            Err(_) => bail!("the scheduler state has been poisoned"),
+        };
+        let is_last = step_index == self.timesteps.len() - 1;
+        let previous = match last_output.as_ref() {
+            Some((index, x0)) if *index + 1 == step_index => Some(x0),
+            _ => None,
+        };
+        let prev_sample = match previous {
+            Some(m1) if self.config.solver_order == 2 && !is_last => {
+                let sigma_s1 = self.sigmas[step_index - 1];
+                let h_0 = Self::lambda(sigma_s0) - Self::lambda(sigma_s1);
+                let r0 = h_0 / h;
+                // D1 = (m0 - m1) / r0
+                let d1 = ((&x0_pred - m1)? / r0)?;
+                ((sample * sample_coef)? - (&x0_pred * coef)? - (d1 * (0.5 * coef))?)?
+            }
+            _ => ((sample * sample_coef)? - (&x0_pred * coef)?)?,
+        };
+        *last_output = Some((step_index, x0_pred));
+        Ok(prev_sample)
+    }
+
+    fn add_noise(&self, original: &Tensor, noise: Tensor, timestep: usize) -> Result<Tensor> {
+        let step_index = match self.timesteps.iter().position(|&t| t == timestep) {
+            Some(i) => i,
+            None => bail!("timestep out of this schedulers bounds: {timestep}"),
+        };
+        let (alpha_t, sigma_t) = Self::alpha_sigma_t(self.sigmas[step_index]);
+        (original * alpha_t)? + (noise * sigma_t)?
+    }
+
+    fn init_noise_sigma(&self) -> f64 {
+        1.0
+    }
+}
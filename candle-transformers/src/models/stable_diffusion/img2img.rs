@@ -0,0 +1,134 @@
+//! Image to image generation and inpainting.
+//!
+//! For image to image, the initial image is encoded with the VAE and noised up to the timestep
+//! corresponding to `strength`, the denoising loop then only runs the remaining timesteps. For
+//! inpainting, the known regions of the image are blended back into the latents after each step
+//! so that only the masked regions are generated.
+use super::schedulers::Scheduler;
+use super::vae::AutoEncoderKL;
+use candle::{DType, Result, Tensor};
+
+/// Returns the index of the first timestep to run for a given `strength`, a strength of 1 runs
+/// all the timesteps and a strength of 0 runs none of them.
+pub fn start_timestep_index(n_steps: usize, strength: f64) -> usize {
+    let init_timestep = ((n_steps as f64 * strength) as usize).min(n_steps);
+    n_steps - init_timestep
+}
+
+/// Encodes `image`, with values between -1 and 1 and shape `(batch, 3, height, width)`, into
+/// scaled latents.
+pub fn encode_image(vae: &AutoEncoderKL, image: &Tensor, vae_scale: f64) -> Result<Tensor> {
+    vae.encode(image)?.sample()? * vae_scale
+}
+
+/// Returns the latents to start the denoising loop from, together with the index of the first
+/// timestep to run.
+///
+/// `init_latents` are noised with `noise` up to the starting timestep. When `strength` is 1, the
+/// initial latents are discarded and the scaled `noise` is returned, the same as for text to
+/// image generation.
+pub fn img2img_latents(
+    scheduler: &dyn Scheduler,
+    init_latents: &Tensor,
+    noise: &Tensor,
+    strength: f64,
+) -> Result<(Tensor, usize)> {
+    if !(0. ..=1.).contains(&strength) {
+        candle::bail!("strength should be between 0 and 1, got {strength}")
+    }
+    let timesteps = scheduler.timesteps();
+    let t_start = start_timestep_index(timesteps.len(), strength);
+    let latents = if strength >= 1. {
+        (noise * scheduler.init_noise_sigma())?
+    } else if t_start < timesteps.len() {
+        scheduler.add_noise(init_latents, noise.clone(), timesteps[t_start])?
+    } else {
+        init_latents.clone()
+    };
+    Ok((latents, t_start))
+}
+
+/// Downsamples an image mask with shape `(batch, 1, height, width)` to the latent resolution.
+/// Values of 1 mark the regions to generate and values of 0 the regions to keep.
+pub fn latent_mask(mask: &Tensor, latent_height: usize, latent_width: usize) -> Result<Tensor> {
+    mask.to_dtype(DType::F32)?
+        .interpolate2d(latent_height, latent_width)?
+        .ge(0.5)?
+        .to_dtype(DType::F32)
+}
+
+/// The state used to keep the unmasked regions of the initial image when inpainting.
+#[derive(Debug, Clone)]
+pub struct Inpaint {
+    mask: Tensor,
+    init_latents: Tensor,
+    noise: Tensor,
+}
+
+impl Inpaint {
+    /// `mask` has shape `(batch, 1, latent_height, latent_width)`, see [`latent_mask`], and
+    /// `noise` should be the same noise as the one used to build the starting latents.
+    pub fn new(mask: Tensor, init_latents: Tensor, noise: Tensor) -> Result<Self> {
+        let (b_size, _, h, w) = init_latents.dims4()?;
+        let (m_b_size, m_c, m_h, m_w) = mask.dims4()?;
+        if (m_b_size != b_size && m_b_size != 1) || m_c != 1 || (m_h, m_w) != (h, w) {
+            candle::bail!(
+                "unexpected mask shape {:?} for latents {:?}",
+                mask.shape(),
+                init_latents.shape()
+            )
+        }
+        let mask = mask.to_dtype(init_latents.dtype())?;
+        Ok(Self {
+            mask,
+            init_latents,
+            noise,
+        })
+    }
+
+    /// Replaces the unmasked regions of `latents` with the initial latents noised at
+    /// `next_timestep`, or with the initial latents themselves after the last step.
+    pub fn blend(
+        &self,
+        scheduler: &dyn Scheduler,
+        latents: &Tensor,
+        next_timestep: Option<usize>,
+    ) -> Result<Tensor> {
+        let init_latents = match next_timestep {
+            Some(t) => scheduler.add_noise(&self.init_latents, self.noise.clone(), t)?,
+            None => self.init_latents.clone(),
+        };
+        let keep = self.mask.affine(-1., 1.)?;
+        latents
+            .broadcast_mul(&self.mask)?
+            .add(&init_latents.broadcast_mul(&keep)?)
+    }
+}
+
+/// Runs the denoising loop from the timestep at index `t_start`.
+///
+/// `noise_pred` is called with the scaled model input and the timestep and returns the predicted
+/// noise, classifier free guidance can be applied there. When `inpaint` is set, the unmasked
+/// regions are blended back after each step.
+pub fn denoise<F>(
+    scheduler: &dyn Scheduler,
+    latents: Tensor,
+    t_start: usize,
+    inpaint: Option<&Inpaint>,
+    mut noise_pred: F,
+) -> Result<Tensor>
+where
+    F: FnMut(&Tensor, usize) -> Result<Tensor>,
+{
+    let timesteps = scheduler.timesteps().to_vec();
+    let mut latents = latents;
+    for (index, &timestep) in timesteps.iter().enumerate().skip(t_start) {
+        let model_input = scheduler.scale_model_input(latents.clone(), timestep)?;
+        let pred = noise_pred(&model_input, timestep)?;
+        latents = scheduler.step(&pred, timestep, &latents)?;
+        if let Some(inpaint) = inpaint {
+            latents = inpaint.blend(scheduler, &latents, timesteps.get(index + 1).copied())?;
+        }
+    }
+    Ok(latents)
+}
@@ -1,11 +1,16 @@
 pub mod attention;
 pub mod clip;
+pub mod controlnet;
 pub mod ddim;
 pub mod ddpm;
+pub mod dpm_solver_multistep;
 pub mod embeddings;
 pub mod euler_ancestral_discrete;
+pub mod img2img;
+pub mod prompt_weighting;
 pub mod resnet;
 pub mod schedulers;
+pub mod sdxl;
 pub mod unet_2d;
 pub mod unet_2d_blocks;
 pub mod utils;
@@ -59,6 +64,7 @@ impl StableDiffusionConfig {
             norm_num_groups: 32,
             sliced_attention_size,
             use_linear_projection: false,
+            addition_embed: None,
         };
         let autoencoder = vae::AutoEncoderKLConfig {
             block_out_channels: vec![128, 256, 512, 512],
@@ -126,6 +132,7 @@ impl StableDiffusionConfig {
             norm_num_groups: 32,
             sliced_attention_size,
             use_linear_projection: true,
+            addition_embed: None,
         };
         // https://huggingface.co/stabilityai/stable-diffusion-2-1/blob/main/vae/config.json
         let autoencoder = vae::AutoEncoderKLConfig {
@@ -207,6 +214,10 @@ impl StableDiffusionConfig {
             norm_num_groups: 32,
             sliced_attention_size,
             use_linear_projection: true,
+            addition_embed: Some(unet_2d::AdditionEmbedConfig {
+                addition_time_embed_dim: 256,
+                projection_class_embeddings_input_dim: 2816,
+            }),
         };
         // https://huggingface.co/stabilityai/stable-diffusion-xl-base-1.0/blob/main/vae/config.json
         let autoencoder = vae::AutoEncoderKLConfig {
@@ -274,6 +285,10 @@ impl StableDiffusionConfig {
             norm_num_groups: 32,
             sliced_attention_size,
             use_linear_projection: true,
+            addition_embed: Some(unet_2d::AdditionEmbedConfig {
+                addition_time_embed_dim: 256,
+                projection_class_embeddings_input_dim: 2816,
+            }),
         };
         // https://huggingface.co/stabilityai/sdxl-turbo/blob/main/vae/config.json
         let autoencoder = vae::AutoEncoderKLConfig {
@@ -371,6 +386,10 @@ impl StableDiffusionConfig {
             norm_num_groups: 32,
             sliced_attention_size,
             use_linear_projection: true,
+            addition_embed: Some(unet_2d::AdditionEmbedConfig {
+                addition_time_embed_dim: 256,
+                projection_class_embeddings_input_dim: 2816,
+            }),
         };
         // https://huggingface.co/stabilityai/stable-diffusion-xl-base-1.0/blob/main/vae/config.json
         let autoencoder = vae::AutoEncoderKLConfig {
@@ -441,6 +460,25 @@ impl StableDiffusionConfig {
         Ok(unet)
     }
 
+    /// Builds the two text encoders used by SDXL, this requires `clip2` to be set.
+    pub fn build_sdxl_text_encoder<P: AsRef<std::path::Path>>(
+        &self,
+        clip_weights: P,
+        clip2_weights: P,
+        device: &Device,
+        dtype: DType,
+    ) -> Result<sdxl::SdxlTextEncoder> {
+        let clip2 = match &self.clip2 {
+            Some(clip2) => clip2,
+            None => candle::bail!("this model does not use a second text encoder"),
+        };
+        let vs =
+            unsafe { nn::VarBuilder::from_mmaped_safetensors(&[clip_weights], dtype, device)? };
+        let vs2 =
+            unsafe { nn::VarBuilder::from_mmaped_safetensors(&[clip2_weights], dtype, device)? };
+        sdxl::SdxlTextEncoder::new(vs, vs2, &self.clip, clip2)
+    }
+
     pub fn build_scheduler(&self, n_steps: usize) -> Result<Box<dyn Scheduler>> {
         self.scheduler.build(n_steps)
     }
@@ -456,3 +494,14 @@ pub fn build_clip_transformer<P: AsRef<std::path::Path>>(
     let text_model = clip::ClipTextTransformer::new(vs, clip)?;
     Ok(text_model)
 }
+
+pub fn build_clip_transformer_with_projection<P: AsRef<std::path::Path>>(
+    clip: &clip::Config,
+    clip_weights: P,
+    device: &Device,
+    dtype: DType,
+) -> Result<clip::ClipTextModelWithProjection> {
+    let vs = unsafe { nn::VarBuilder::from_mmaped_safetensors(&[clip_weights], dtype, device)? };
+    let text_model = clip::ClipTextModelWithProjection::new(vs, clip)?;
+    Ok(text_model)
+}
@@ -0,0 +1,185 @@
+//! Prompt weighting, a.k.a. attention or emphasis syntax.
+//!
+//! This uses the same syntax as the AUTOMATIC1111 web ui:
+//! - `(text)` multiplies the weight of `text` by 1.1.
+//! - `[text]` divides the weight of `text` by 1.1.
+//! - `(text:1.3)` multiplies the weight of `text` by 1.3.
+//! - `\(`, `\)`, `\[`, `\]` and `\\` are used for literal brackets and backslashes.
+//!
+//! Brackets can be nested, the weights get multiplied, and unclosed brackets apply up to the end
+//! of the prompt. The weights are applied to the CLIP text embeddings by scaling each token
+//! embedding around the mean embedding of the sequence, the result is rescaled so that the
+//! overall norm of the embeddings is preserved.
+use candle::{DType, Result, Tensor};
+
+const ROUND_BRACKET_MULTIPLIER: f64 = 1.1;
+const SQUARE_BRACKET_MULTIPLIER: f64 = 1. / 1.1;
+
+// Parses `\s*[+-]?[.\d]+\s*\)` starting at `pos`, returns the weight and the position of the
+// closing bracket.
+fn parse_weight(chars: &[char], pos: usize) -> Option<(f64, usize)> {
+    let skip_ws = |mut pos: usize| {
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1
+        }
+        pos
+    };
+    let start = skip_ws(pos);
+    let mut end = start;
+    if end < chars.len() && (chars[end] == '+' || chars[end] == '-') {
+        end += 1
+    }
+    while end < chars.len() && (chars[end] == '.' || chars[end].is_ascii_digit()) {
+        end += 1
+    }
+    let weight = chars[start..end].iter().collect::<String>().parse::<f64>();
+    let close = skip_ws(end);
+    match weight {
+        Ok(weight) if close < chars.len() && chars[close] == ')' => Some((weight, close)),
+        _ => None,
+    }
+}
+
+/// Splits `prompt` in chunks of text with their weights, the brackets and weights are removed
+/// from the text and consecutive chunks with the same weight are merged.
+///
+/// ```rust
+/// use candle_transformers::models::stable_diffusion::prompt_weighting::parse_prompt_weights;
+/// let chunks = parse_prompt_weights("a (red:1.5) car, [blurry]");
+/// assert_eq!(chunks.len(), 4);
+/// assert_eq!(chunks[0], ("a ".to_string(), 1.0));
+/// assert_eq!(chunks[1], ("red".to_string(), 1.5));
+/// assert_eq!(chunks[2], (" car, ".to_string(), 1.0));
+/// assert_eq!(chunks[3].0, "blurry");
+/// assert!((chunks[3].1 - 1. / 1.1).abs() < 1e-9);
+/// ```
+pub fn parse_prompt_weights(prompt: &str) -> Vec<(String, f64)> {
+    let chars = prompt.chars().collect::<Vec<_>>();
+    let mut res: Vec<(String, f64)> = vec![];
+    let mut round_brackets = vec![];
+    let mut square_brackets = vec![];
+    // Text is appended to the last chunk unless a bracket has been seen since it was created.
+    let mut new_chunk = true;
+    let multiply = |res: &mut Vec<(String, f64)>, start: usize, m: f64| {
+        for (_, w) in res[start..].iter_mut() {
+            *w *= m
+        }
+    };
+    let mut pos = 0;
+    while pos < chars.len() {
+        let mut c = chars[pos];
+        match c {
+            '\\' if pos + 1 < chars.len() && "()[]\\".contains(chars[pos + 1]) => {
+                pos += 1;
+                c = chars[pos];
+            }
+            '(' => {
+                round_brackets.push(res.len());
+                new_chunk = true;
+                pos += 1;
+                continue;
+            }
+            '[' => {
+                square_brackets.push(res.len());
+                new_chunk = true;
+                pos += 1;
+                continue;
+            }
+            ':' if !round_brackets.is_empty() => {
+                if let Some((weight, close)) = parse_weight(&chars, pos + 1) {
+                    if let Some(start) = round_brackets.pop() {
+                        multiply(&mut res, start, weight)
+                    }
+                    new_chunk = true;
+                    pos = close + 1;
+                    continue;
+                }
+            }
+            ')' if !round_brackets.is_empty() => {
+                if let Some(start) = round_brackets.pop() {
+                    multiply(&mut res, start, ROUND_BRACKET_MULTIPLIER)
+                }
+                new_chunk = true;
+                pos += 1;
+                continue;
+            }
+            ']' if !square_brackets.is_empty() => {
+                if let Some(start) = square_brackets.pop() {
+                    multiply(&mut res, start, SQUARE_BRACKET_MULTIPLIER)
+                }
+                new_chunk = true;
+                pos += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match res.last_mut() {
+            Some((text, _)) if !new_chunk => text.push(c),
+            _ => res.push((c.to_string(), 1.0)),
+        }
+        new_chunk = false;
+        pos += 1;
+    }
+    for start in round_brackets {
+        multiply(&mut res, start, ROUND_BRACKET_MULTIPLIER)
+    }
+    for start in square_brackets {
+        multiply(&mut res, start, SQUARE_BRACKET_MULTIPLIER)
+    }
+
+    let mut merged: Vec<(String, f64)> = Vec::with_capacity(res.len());
+    for (text, weight) in res {
+        match merged.last_mut() {
+            Some((prev, prev_weight)) if *prev_weight == weight => prev.push_str(&text),
+            _ => merged.push((text, weight)),
+        }
+    }
+    if merged.is_empty() {
+        merged.push((String::new(), 1.0))
+    }
+    merged
+}
+
+/// Parses `prompt` and tokenizes each chunk with `tokenize`, returns the tokens together with
+/// the weight of each token. `tokenize` should not add any special token.
+pub fn tokenize_weighted<F>(prompt: &str, mut tokenize: F) -> Result<(Vec<u32>, Vec<f64>)>
+where
+    F: FnMut(&str) -> Result<Vec<u32>>,
+{
+    let mut tokens = vec![];
+    let mut weights = vec![];
+    for (text, weight) in parse_prompt_weights(prompt) {
+        let chunk_tokens = tokenize(&text)?;
+        weights.extend_from_slice(&vec![weight; chunk_tokens.len()]);
+        tokens.extend_from_slice(&chunk_tokens);
+    }
+    Ok((tokens, weights))
+}
+
+/// Applies the token weights to text embeddings with shape `(batch, seq_len, hidden_size)`.
+///
+/// Each token embedding `e` becomes `mean + w * (e - mean)` where `mean` is the average
+/// embedding over the sequence, the result is then rescaled to the norm of the original
+/// embeddings. `weights` can be shorter than the sequence, e.g. when the padding has been
+/// omitted, the missing weights default to 1.
+pub fn apply_prompt_weights(embeddings: &Tensor, weights: &[f64]) -> Result<Tensor> {
+    let (_b_size, seq_len, _hidden_size) = embeddings.dims3()?;
+    if weights.len() > seq_len {
+        candle::bail!("got {} weights for {seq_len} tokens", weights.len())
+    }
+    if weights.iter().all(|&w| w == 1.0) {
+        return Ok(embeddings.clone());
+    }
+    let mut ws = weights.iter().map(|&w| w as f32).collect::<Vec<_>>();
+    ws.resize(seq_len, 1.0);
+    let ws = Tensor::from_vec(ws, (1, seq_len, 1), embeddings.device())?;
+    let xs = embeddings.to_dtype(DType::F32)?;
+    let mean = xs.mean_keepdim(1)?;
+    let ys = xs
+        .broadcast_sub(&mean)?
+        .broadcast_mul(&ws)?
+        .broadcast_add(&mean)?;
+    let norm = |xs: &Tensor| xs.sqr()?.sum_keepdim((1, 2))?.sqrt();
+    let scale = (norm(&xs)? / norm(&ys)?)?;
+    ys.broadcast_mul(&scale)?.to_dtype(embeddings.dtype())
+}
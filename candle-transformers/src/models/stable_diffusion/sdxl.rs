@@ -0,0 +1,64 @@
+//! Stable Diffusion XL specific conditioning.
+//!
+//! SDXL uses two CLIP text encoders, the penultimate hidden states of both encoders are
+//! concatenated to get the token embeddings used by the cross-attention layers. The second
+//! encoder also produces a pooled text embedding that is passed to the UNet together with the
+//! size and crop conditioning, the so called time ids.
+//!
+//! https://arxiv.org/abs/2307.01952
+use super::clip::{ClipTextModelWithProjection, ClipTextTransformer, Config};
+use candle::{Device, Result, Tensor, D};
+use candle_nn as nn;
+
+/// The two SDXL text encoders.
+#[derive(Debug)]
+pub struct SdxlTextEncoder {
+    clip: ClipTextTransformer,
+    clip2: ClipTextModelWithProjection,
+}
+
+impl SdxlTextEncoder {
+    /// `vs` and `vs2` are the weights of the first and second text encoders, e.g. from the
+    /// `text_encoder` and `text_encoder_2` directories of the diffusers checkpoints.
+    pub fn new(
+        vs: nn::VarBuilder,
+        vs2: nn::VarBuilder,
+        config: &Config,
+        config2: &Config,
+    ) -> Result<Self> {
+        let clip = ClipTextTransformer::new(vs, config)?;
+        let clip2 = ClipTextModelWithProjection::new(vs2, config2)?;
+        Ok(Self { clip, clip2 })
+    }
+
+    /// Encodes the tokens for both tokenizers, each with shape `(batch, seq_len)`. Returns the
+    /// token embeddings with shape `(batch, seq_len, embed_dim + embed_dim2)` and the pooled
+    /// embeddings with shape `(batch, projection_dim2)`.
+    pub fn encode(&self, tokens: &Tensor, tokens2: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (embeds, _) = self.clip.forward_with_penultimate(tokens)?;
+        let (embeds2, pooled) = self.clip2.forward(tokens2)?;
+        let embeds = Tensor::cat(&[embeds, embeds2], D::Minus1)?;
+        Ok((embeds, pooled))
+    }
+}
+
+/// The time ids conditioning the UNet on the size of the original image, on the top-left crop
+/// coordinates and on the target size, all of them as `(height, width)`. The returned tensor has
+/// shape `(1, 6)`.
+pub fn add_time_ids(
+    original_size: (usize, usize),
+    crop_coords_top_left: (usize, usize),
+    target_size: (usize, usize),
+    device: &Device,
+) -> Result<Tensor> {
+    let time_ids = [
+        original_size.0,
+        original_size.1,
+        crop_coords_top_left.0,
+        crop_coords_top_left.1,
+        target_size.0,
+        target_size.1,
+    ];
+    let time_ids = time_ids.iter().map(|&v| v as f32).collect::<Vec<_>>();
+    Tensor::from_vec(time_ids, (1, 6), device)
+}
@@ -18,6 +18,16 @@ pub struct BlockConfig {
     pub attention_head_dim: usize,
 }
 
+/// The `text_time` additional conditioning used by SDXL, the pooled text embeddings and the
+/// embedded time ids are projected and added to the timestep embedding.
+#[derive(Debug, Clone, Copy)]
+pub struct AdditionEmbedConfig {
+    /// The number of channels of the sinusoidal embedding of each time id.
+    pub addition_time_embed_dim: usize,
+    /// The size of the pooled text embeddings concatenated with the embedded time ids.
+    pub projection_class_embeddings_input_dim: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct UNet2DConditionModelConfig {
     pub center_input_sample: bool,
@@ -32,6 +42,7 @@ pub struct UNet2DConditionModelConfig {
     pub cross_attention_dim: usize,
     pub sliced_attention_size: Option<usize>,
     pub use_linear_projection: bool,
+    pub addition_embed: Option<AdditionEmbedConfig>,
 }
 
 impl Default for UNet2DConditionModelConfig {
@@ -70,6 +81,7 @@ impl Default for UNet2DConditionModelConfig {
             cross_attention_dim: 1280,
             sliced_attention_size: None,
             use_linear_projection: false,
+            addition_embed: None,
         }
     }
 }
@@ -80,6 +92,121 @@ pub(crate) enum UNetDownBlock {
     CrossAttn(CrossAttnDownBlock2D),
 }
 
+impl UNetDownBlock {
+    pub(crate) fn forward(
+        &self,
+        xs: &Tensor,
+        emb: &Tensor,
+        encoder_hidden_states: &Tensor,
+    ) -> Result<(Tensor, Vec<Tensor>)> {
+        match self {
+            Self::Basic(b) => b.forward(xs, Some(emb)),
+            Self::CrossAttn(b) => b.forward(xs, Some(emb), Some(encoder_hidden_states)),
+        }
+    }
+}
+
+// The down blocks, shared between the UNet and the ControlNet.
+pub(crate) fn unet_down_blocks(
+    vs_db: nn::VarBuilder,
+    config: &UNet2DConditionModelConfig,
+    time_embed_dim: usize,
+    use_flash_attn: bool,
+) -> Result<Vec<UNetDownBlock>> {
+    let n_blocks = config.blocks.len();
+    let b_channels = config.blocks[0].out_channels;
+    (0..n_blocks)
+        .map(|i| {
+            let BlockConfig {
+                out_channels,
+                use_cross_attn,
+                attention_head_dim,
+            } = config.blocks[i];
+
+            // Enable automatic attention slicing if the config sliced_attention_size is set to 0.
+            let sliced_attention_size = match config.sliced_attention_size {
+                Some(0) => Some(attention_head_dim / 2),
+                _ => config.sliced_attention_size,
+            };
+
+            let in_channels = if i > 0 {
+                config.blocks[i - 1].out_channels
+            } else {
+                b_channels
+            };
+            let db_cfg = DownBlock2DConfig {
+                num_layers: config.layers_per_block,
+                resnet_eps: config.norm_eps,
+                resnet_groups: config.norm_num_groups,
+                add_downsample: i < n_blocks - 1,
+                downsample_padding: config.downsample_padding,
+                ..Default::default()
+            };
+            if let Some(transformer_layers_per_block) = use_cross_attn {
+                let config = CrossAttnDownBlock2DConfig {
+                    downblock: db_cfg,
+                    attn_num_head_channels: attention_head_dim,
+                    cross_attention_dim: config.cross_attention_dim,
+                    sliced_attention_size,
+                    use_linear_projection: config.use_linear_projection,
+                    transformer_layers_per_block,
+                };
+                let block = CrossAttnDownBlock2D::new(
+                    vs_db.pp(i.to_string()),
+                    in_channels,
+                    out_channels,
+                    Some(time_embed_dim),
+                    use_flash_attn,
+                    config,
+                )?;
+                Ok(UNetDownBlock::CrossAttn(block))
+            } else {
+                let block = DownBlock2D::new(
+                    vs_db.pp(i.to_string()),
+                    in_channels,
+                    out_channels,
+                    Some(time_embed_dim),
+                    db_cfg,
+                )?;
+                Ok(UNetDownBlock::Basic(block))
+            }
+        })
+        .collect::<Result<Vec<_>>>()
+}
+
+// The mid block, shared between the UNet and the ControlNet.
+pub(crate) fn unet_mid_block(
+    vs: nn::VarBuilder,
+    config: &UNet2DConditionModelConfig,
+    time_embed_dim: usize,
+    use_flash_attn: bool,
+) -> Result<UNetMidBlock2DCrossAttn> {
+    let bl_channels = config.blocks.last().unwrap().out_channels;
+    let bl_attention_head_dim = config.blocks.last().unwrap().attention_head_dim;
+    // https://github.com/huggingface/diffusers/blob/a76f2ad538e73b34d5fe7be08c8eb8ab38c7e90c/src/diffusers/models/unet_2d_condition.py#L462
+    let mid_transformer_layers_per_block = match config.blocks.last() {
+        None => 1,
+        Some(block) => block.use_cross_attn.unwrap_or(1),
+    };
+    let mid_cfg = UNetMidBlock2DCrossAttnConfig {
+        resnet_eps: config.norm_eps,
+        output_scale_factor: config.mid_block_scale_factor,
+        cross_attn_dim: config.cross_attention_dim,
+        attn_num_head_channels: bl_attention_head_dim,
+        resnet_groups: Some(config.norm_num_groups),
+        use_linear_projection: config.use_linear_projection,
+        transformer_layers_per_block: mid_transformer_layers_per_block,
+        ..Default::default()
+    };
+    UNetMidBlock2DCrossAttn::new(
+        vs,
+        bl_channels,
+        Some(time_embed_dim),
+        use_flash_attn,
+        mid_cfg,
+    )
+}
+
 #[derive(Debug)]
 enum UNetUpBlock {
     Basic(UpBlock2D),
@@ -91,6 +218,8 @@ pub struct UNet2DConditionModel {
     conv_in: Conv2d,
     time_proj: Timesteps,
     time_embedding: TimestepEmbedding,
+    // The time ids projection and the embedding of the additional conditioning.
+    add_embedding: Option<(Timesteps, TimestepEmbedding)>,
     down_blocks: Vec<UNetDownBlock>,
     mid_block: UNetMidBlock2DCrossAttn,
     up_blocks: Vec<UNetUpBlock>,
@@ -111,7 +240,6 @@ impl UNet2DConditionModel {
         let n_blocks = config.blocks.len();
         let b_channels = config.blocks[0].out_channels;
         let bl_channels = config.blocks.last().unwrap().out_channels;
-        let bl_attention_head_dim = config.blocks.last().unwrap().attention_head_dim;
         let time_embed_dim = b_channels * 4;
         let conv_cfg = nn::Conv2dConfig {
             padding: 1,
@@ -122,89 +250,31 @@ impl UNet2DConditionModel {
         let time_proj = Timesteps::new(b_channels, config.flip_sin_to_cos, config.freq_shift);
         let time_embedding =
             TimestepEmbedding::new(vs.pp("time_embedding"), b_channels, time_embed_dim)?;
-
-        let vs_db = vs.pp("down_blocks");
-        let down_blocks = (0..n_blocks)
-            .map(|i| {
-                let BlockConfig {
-                    out_channels,
-                    use_cross_attn,
-                    attention_head_dim,
-                } = config.blocks[i];
-
-                // Enable automatic attention slicing if the config sliced_attention_size is set to 0.
-                let sliced_attention_size = match config.sliced_attention_size {
-                    Some(0) => Some(attention_head_dim / 2),
-                    _ => config.sliced_attention_size,
-                };
-
-                let in_channels = if i > 0 {
-                    config.blocks[i - 1].out_channels
-                } else {
-                    b_channels
-                };
-                let db_cfg = DownBlock2DConfig {
-                    num_layers: config.layers_per_block,
-                    resnet_eps: config.norm_eps,
-                    resnet_groups: config.norm_num_groups,
-                    add_downsample: i < n_blocks - 1,
-                    downsample_padding: config.downsample_padding,
-                    ..Default::default()
-                };
-                if let Some(transformer_layers_per_block) = use_cross_attn {
-                    let config = CrossAttnDownBlock2DConfig {
-                        downblock: db_cfg,
-                        attn_num_head_channels: attention_head_dim,
-                        cross_attention_dim: config.cross_attention_dim,
-                        sliced_attention_size,
-                        use_linear_projection: config.use_linear_projection,
-                        transformer_layers_per_block,
-                    };
-                    let block = CrossAttnDownBlock2D::new(
-                        vs_db.pp(&i.to_string()),
-                        in_channels,
-                        out_channels,
-                        Some(time_embed_dim),
-                        use_flash_attn,
-                        config,
-                    )?;
-                    Ok(UNetDownBlock::CrossAttn(block))
-                } else {
-                    let block = DownBlock2D::new(
-                        vs_db.pp(&i.to_string()),
-                        in_channels,
-                        out_channels,
-                        Some(time_embed_dim),
-                        db_cfg,
-                    )?;
-                    Ok(UNetDownBlock::Basic(block))
-                }
-            })
-            .collect::<Result<Vec<_>>>()?;
-
-        // https://github.com/huggingface/diffusers/blob/a76f2ad538e73b34d5fe7be08c8eb8ab38c7e90c/src/diffusers/models/unet_2d_condition.py#L462
-        let mid_transformer_layers_per_block = match config.blocks.last() {
-            None => 1,
-            Some(block) => block.use_cross_attn.unwrap_or(1),
-        };
-        let mid_cfg = UNetMidBlock2DCrossAttnConfig {
-            resnet_eps: config.norm_eps,
-            output_scale_factor: config.mid_block_scale_factor,
-            cross_attn_dim: config.cross_attention_dim,
-            attn_num_head_channels: bl_attention_head_dim,
-            resnet_groups: Some(config.norm_num_groups),
-            use_linear_projection: config.use_linear_projection,
-            transformer_layers_per_block: mid_transformer_layers_per_block,
-            ..Default::default()
+        let add_embedding = match config.addition_embed {
+            None => None,
+            Some(cfg) => {
+                let add_time_proj = Timesteps::new(
+                    cfg.addition_time_embed_dim,
+                    config.flip_sin_to_cos,
+                    config.freq_shift,
+                );
+                let add_embedding = TimestepEmbedding::new(
+                    vs.pp("add_embedding"),
+                    cfg.projection_class_embeddings_input_dim,
+                    time_embed_dim,
+                )?;
+                Some((add_time_proj, add_embedding))
+            }
         };
 
-        let mid_block = UNetMidBlock2DCrossAttn::new(
-            vs.pp("mid_block"),
-            bl_channels,
-            Some(time_embed_dim),
+        let down_blocks = unet_down_blocks(
+            vs.pp("down_blocks"),
+            &config,
+            time_embed_dim,
             use_flash_attn,
-            mid_cfg,
         )?;
+        let mid_block =
+            unet_mid_block(vs.pp("mid_block"), &config, time_embed_dim, use_flash_attn)?;
 
         let vs_ub = vs.pp("up_blocks");
         let up_blocks = (0..n_blocks)
@@ -286,6 +356,7 @@ impl UNet2DConditionModel {
             conv_in,
             time_proj,
             time_embedding,
+            add_embedding,
             down_blocks,
             mid_block,
             up_blocks,
@@ -313,6 +384,28 @@ impl UNet2DConditionModel {
         encoder_hidden_states: &Tensor,
         down_block_additional_residuals: Option<&[Tensor]>,
         mid_block_additional_residual: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        self.forward_with_added_cond(
+            xs,
+            timestep,
+            encoder_hidden_states,
+            None,
+            down_block_additional_residuals,
+            mid_block_additional_residual,
+        )
+    }
+
+    /// The forward pass for models using the SDXL additional conditioning, `added_cond` contains
+    /// the pooled text embeddings with shape `(batch, pooled_dim)` and the time ids with shape
+    /// `(batch, n_time_ids)`, see [`super::sdxl::add_time_ids`].
+    pub fn forward_with_added_cond(
+        &self,
+        xs: &Tensor,
+        timestep: f64,
+        encoder_hidden_states: &Tensor,
+        added_cond: Option<(&Tensor, &Tensor)>,
+        down_block_additional_residuals: Option<&[Tensor]>,
+        mid_block_additional_residual: Option<&Tensor>,
     ) -> Result<Tensor> {
         let (bsize, _channels, height, width) = xs.dims4()?;
         let device = xs.device();
@@ -331,24 +424,40 @@ impl UNet2DConditionModel {
         let emb = (Tensor::ones(bsize, xs.dtype(), device)? * timestep)?;
         let emb = self.time_proj.forward(&emb)?;
         let emb = self.time_embedding.forward(&emb)?;
+        let emb = match (&self.add_embedding, added_cond) {
+            (Some((add_time_proj, add_embedding)), Some((text_embeds, time_ids))) => {
+                let time_ids = time_ids.flatten_all()?.to_dtype(emb.dtype())?;
+                let time_embeds = add_time_proj.forward(&time_ids)?.reshape((bsize, ()))?;
+                let text_embeds = text_embeds.to_dtype(emb.dtype())?;
+                let add_embeds = Tensor::cat(&[text_embeds, time_embeds], 1)?;
+                (emb + add_embedding.forward(&add_embeds)?)?
+            }
+            (None, None) => emb,
+            (Some(_), None) => {
+                candle::bail!("this model requires the pooled text embeddings and time ids")
+            }
+            (None, Some(_)) => candle::bail!("this model does not use additional conditioning"),
+        };
         // 2. pre-process
         let xs = self.conv_in.forward(&xs)?;
         // 3. down
         let mut down_block_res_xs = vec![xs.clone()];
         let mut xs = xs;
         for down_block in self.down_blocks.iter() {
-            let (_xs, res_xs) = match down_block {
-                UNetDownBlock::Basic(b) => b.forward(&xs, Some(&emb))?,
-                UNetDownBlock::CrossAttn(b) => {
-                    b.forward(&xs, Some(&emb), Some(encoder_hidden_states))?
-                }
-            };
+            let (_xs, res_xs) = down_block.forward(&xs, &emb, encoder_hidden_states)?;
             down_block_res_xs.extend(res_xs);
             xs = _xs;
         }
 
         let new_down_block_res_xs =
             if let Some(down_block_additional_residuals) = down_block_additional_residuals {
+                if down_block_additional_residuals.len() != down_block_res_xs.len() {
+                    candle::bail!(
+                        "expected {} down block additional residuals, got {}",
+                        down_block_res_xs.len(),
+                        down_block_additional_residuals.len()
+                    )
+                }
                 let mut v = vec![];
                 // A previous version of this code had a bug because of the addition being made
                 // in place via += hence modifying the input of the mid block.
@@ -398,4 +507,8 @@ impl UNet2DConditionModel {
         let xs = nn::ops::silu(&xs)?;
         self.conv_out.forward(&xs)
     }
+
+    pub fn config(&self) -> &UNet2DConditionModelConfig {
+        &self.config
+    }
 }
@@ -1,6 +1,8 @@
 pub mod audio;
 pub mod model;
 pub mod quantized_model;
+pub mod timestamps;
+pub mod transcribe;
 
 use serde::Deserialize;
 
@@ -39,6 +41,7 @@ pub const COMPRESSION_RATIO_THRESHOLD: f64 = 2.4;
 
 // Tokenizer dependent bits.
 pub const SOT_TOKEN: &str = "<|startoftranscript|>";
+pub const SOT_PREV_TOKEN: &str = "<|startofprev|>";
 pub const TRANSCRIBE_TOKEN: &str = "<|transcribe|>";
 pub const TRANSLATE_TOKEN: &str = "<|translate|>";
 pub const NO_TIMESTAMPS_TOKEN: &str = "<|notimestamps|>";
@@ -60,7 +60,7 @@ impl MultiHeadAttention {
         xa: Option<&Tensor>,
         mask: Option<&Tensor>,
         flush_cache: bool,
-    ) -> Result<Tensor> {
+    ) -> Result<(Tensor, Tensor)> {
         let _enter = self.span.enter();
         let q = self.query.forward(x)?;
         let (k, v) = match xa {
@@ -83,9 +83,9 @@ impl MultiHeadAttention {
                 }
             }
         };
-        let wv = self.qkv_attention(&q, &k, &v, mask)?;
+        let (wv, qk) = self.qkv_attention(&q, &k, &v, mask)?;
         let out = self.out.forward(&wv)?;
-        Ok(out)
+        Ok((out, qk))
     }
 
     fn reshape_head(&self, x: &Tensor) -> Result<Tensor> {
@@ -100,7 +100,7 @@ impl MultiHeadAttention {
         k: &Tensor,
         v: &Tensor,
         mask: Option<&Tensor>,
-    ) -> Result<Tensor> {
+    ) -> Result<(Tensor, Tensor)> {
         let (_, n_ctx, n_state) = q.dims3()?;
         let scale = ((n_state / self.n_head) as f64).powf(-0.25);
         let q = (self.reshape_head(q)? * scale)?;
@@ -124,7 +124,7 @@ impl MultiHeadAttention {
         }
         .transpose(1, 2)?
         .flatten_from(2)?;
-        Ok(wv)
+        Ok((wv, qk))
     }
 
     fn reset_kv_cache(&mut self) {
@@ -177,21 +177,24 @@ impl ResidualAttentionBlock {
         xa: Option<&Tensor>,
         mask: Option<&Tensor>,
         flush_kv_cache: bool,
-    ) -> Result<Tensor> {
+    ) -> Result<(Tensor, Option<Tensor>)> {
         let _enter = self.span.enter();
-        let attn = self
+        let (attn, _) = self
             .attn
             .forward(&self.attn_ln.forward(x)?, None, mask, flush_kv_cache)?;
         let mut x = (x + attn)?;
+        let mut cross_qk = None;
         if let Some((attn, ln)) = &mut self.cross_attn {
-            x = (&x + attn.forward(&ln.forward(&x)?, xa, None, flush_kv_cache)?)?;
+            let (cross_attn, qk) = attn.forward(&ln.forward(&x)?, xa, None, flush_kv_cache)?;
+            x = (&x + cross_attn)?;
+            cross_qk = Some(qk);
         }
         let mlp = x
             .apply(&self.mlp_ln)?
             .apply(&self.mlp_linear1)?
             .gelu()?
             .apply(&self.mlp_linear2)?;
-        x + mlp
+        Ok(((x + mlp)?, cross_qk))
     }
 
     fn reset_kv_cache(&mut self) {
@@ -287,7 +290,7 @@ impl AudioEncoder {
         let positional_embedding = self.positional_embedding.narrow(0, 0, seq_len)?;
         let mut x = x.broadcast_add(&positional_embedding)?;
         for block in self.blocks.iter_mut() {
-            x = block.forward(&x, None, None, flush_kv_cache)?
+            (x, _) = block.forward(&x, None, None, flush_kv_cache)?
         }
         let x = self.ln_post.forward(&x)?;
         Ok(x)
@@ -345,15 +348,31 @@ impl TextDecoder {
     }
 
     pub fn forward(&mut self, x: &Tensor, xa: &Tensor, flush_kv_cache: bool) -> Result<Tensor> {
+        let (x, _) = self.forward_with_cross_attentions(x, xa, flush_kv_cache)?;
+        Ok(x)
+    }
+
+    /// Same as `forward` but also returns the cross-attention scores of each layer, these are
+    /// taken before the softmax and have shape `(batch, n_head, seq_len, n_audio_ctx)`. They can
+    /// be used to align the text tokens with the audio frames, see [`super::timestamps`].
+    pub fn forward_with_cross_attentions(
+        &mut self,
+        x: &Tensor,
+        xa: &Tensor,
+        flush_kv_cache: bool,
+    ) -> Result<(Tensor, Vec<Tensor>)> {
         let _enter = self.span.enter();
         let last = x.dim(D::Minus1)?;
         let token_embedding = self.token_embedding.forward(x)?;
         let positional_embedding = self.positional_embedding.narrow(0, 0, last)?;
         let mut x = token_embedding.broadcast_add(&positional_embedding)?;
+        let mut cross_attentions = Vec::with_capacity(self.blocks.len());
         for block in self.blocks.iter_mut() {
-            x = block.forward(&x, Some(xa), Some(&self.mask), flush_kv_cache)?;
+            let (ys, qk) = block.forward(&x, Some(xa), Some(&self.mask), flush_kv_cache)?;
+            x = ys;
+            cross_attentions.extend(qk);
         }
-        self.ln.forward(&x)
+        Ok((self.ln.forward(&x)?, cross_attentions))
     }
 
     pub fn final_linear(&self, x: &Tensor) -> Result<Tensor> {
@@ -0,0 +1,197 @@
+//! Word level timestamps.
+//!
+//! Some of the decoder cross-attention heads closely follow the timing of the speech. Their
+//! attention scores over the audio frames are normalized, smoothed with a median filter and
+//! averaged, and dynamic time warping (DTW) then aligns the text tokens with the audio frames.
+//! https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/timing.py
+//!
+//! The alignment requires running the decoder on the whole token sequence, i.e. the start of
+//! transcript tokens, the text tokens and the end of text token, using
+//! [`super::model::TextDecoder::forward_with_cross_attentions`].
+use super::{Config, HOP_LENGTH, SAMPLE_RATE};
+use candle::{IndexOp, Result, Tensor, D};
+
+/// The duration of an audio frame in the output of the encoder, in seconds.
+pub const SECONDS_PER_FRAME: f64 = (HOP_LENGTH * 2) as f64 / SAMPLE_RATE as f64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordTiming {
+    pub word: String,
+    pub tokens: Vec<u32>,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// The `(layer, head)` pairs used for the alignment when the model specific ones are not known,
+/// these are all the heads in the second half of the decoder layers.
+pub fn default_alignment_heads(cfg: &Config) -> Vec<(usize, usize)> {
+    let n_layers = cfg.decoder_layers;
+    (n_layers / 2..n_layers)
+        .flat_map(|l| (0..cfg.decoder_attention_heads).map(move |h| (l, h)))
+        .collect()
+}
+
+/// Returns the path with the minimal total cost through the `(n, m)` cost matrix, going from
+/// `(0, 0)` to `(n - 1, m - 1)`. The path is returned as the row and column indexes of each
+/// step, both indexes are non-decreasing.
+pub fn dtw(cost: &Tensor) -> Result<(Vec<usize>, Vec<usize>)> {
+    let cost = cost.to_dtype(candle::DType::F32)?.to_vec2::<f32>()?;
+    let n = cost.len();
+    let m = cost.first().map_or(0, |c| c.len());
+    if n == 0 || m == 0 {
+        candle::bail!("dtw requires a non-empty cost matrix")
+    }
+    let mut acc = vec![vec![f32::INFINITY; m + 1]; n + 1];
+    let mut trace = vec![vec![2u8; m + 1]; n + 1];
+    acc[0][0] = 0.;
+    // On the borders the path can only move along the border towards the origin.
+    for row in trace.iter_mut().skip(1) {
+        row[0] = 1;
+    }
+    for j in 1..=m {
+        for i in 1..=n {
+            let c0 = acc[i - 1][j - 1];
+            let c1 = acc[i - 1][j];
+            let c2 = acc[i][j - 1];
+            let (c, t) = if c0 < c1 && c0 < c2 {
+                (c0, 0)
+            } else if c1 < c0 && c1 < c2 {
+                (c1, 1)
+            } else {
+                (c2, 2)
+            };
+            acc[i][j] = cost[i - 1][j - 1] + c;
+            trace[i][j] = t;
+        }
+    }
+    let (mut i, mut j) = (n, m);
+    let mut text_indices = vec![];
+    let mut time_indices = vec![];
+    while i > 0 || j > 0 {
+        text_indices.push(i.saturating_sub(1));
+        time_indices.push(j.saturating_sub(1));
+        match trace[i][j] {
+            0 => {
+                i -= 1;
+                j -= 1;
+            }
+            1 => i -= 1,
+            _ => j -= 1,
+        }
+    }
+    text_indices.reverse();
+    time_indices.reverse();
+    Ok((text_indices, time_indices))
+}
+
+/// Applies a median filter of width `filter_width` over the last dimension, the input is padded
+/// by reflection on both sides.
+pub fn median_filter(xs: &Tensor, filter_width: usize) -> Result<Tensor> {
+    if filter_width % 2 != 1 {
+        candle::bail!("the median filter width should be odd, got {filter_width}")
+    }
+    let pad = filter_width / 2;
+    let dims = xs.dims().to_vec();
+    let len = xs.dim(D::Minus1)?;
+    if len <= pad {
+        return Ok(xs.clone());
+    }
+    let rows = xs
+        .to_dtype(candle::DType::F32)?
+        .reshape(((), len))?
+        .to_vec2::<f32>()?;
+    let mut out = Vec::with_capacity(rows.len() * len);
+    let mut window = Vec::with_capacity(filter_width);
+    for row in rows.iter() {
+        let reflect = |i: isize| {
+            let i = if i < 0 { -i } else { i };
+            let i = i as usize;
+            if i >= len {
+                row[2 * (len - 1) - i]
+            } else {
+                row[i]
+            }
+        };
+        for i in 0..len {
+            window.clear();
+            window
+                .extend((0..filter_width).map(|k| reflect(i as isize + k as isize - pad as isize)));
+            window.sort_by(|a, b| a.total_cmp(b));
+            out.push(window[pad])
+        }
+    }
+    Tensor::from_vec(out, dims, xs.device())?.to_dtype(xs.dtype())
+}
+
+/// Builds the alignment matrix between the decoded tokens and the audio frames.
+///
+/// `cross_attentions` contains the pre-softmax cross-attention scores of each decoder layer for
+/// a single sequence, each with shape `(1, n_head, n_tokens, n_audio_ctx)`. `num_frames` is the
+/// number of mel frames of the actual audio, the encoder halves this resolution. The result
+/// has shape `(n_tokens, num_frames / 2)`.
+pub fn alignment_matrix(
+    cross_attentions: &[Tensor],
+    alignment_heads: &[(usize, usize)],
+    num_frames: usize,
+    medfilt_width: usize,
+) -> Result<Tensor> {
+    if alignment_heads.is_empty() {
+        candle::bail!("no alignment heads")
+    }
+    let weights = alignment_heads
+        .iter()
+        .map(|&(layer, head)| match cross_attentions.get(layer) {
+            None => candle::bail!("no cross-attention for layer {layer}"),
+            Some(qk) => qk.i((0, head))?.narrow(D::Minus1, 0, num_frames / 2),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let weights = Tensor::stack(&weights, 0)?
+        .to_dtype(candle::DType::F32)?
+        .contiguous()?;
+    let weights = candle_nn::ops::softmax_last_dim(&weights)?;
+    // Standardize each head over the tokens.
+    let mean = weights.mean_keepdim(1)?;
+    let weights = weights.broadcast_sub(&mean)?;
+    let std = weights.sqr()?.mean_keepdim(1)?.sqrt()?;
+    let weights = weights.broadcast_div(&std)?;
+    let weights = median_filter(&weights, medfilt_width)?;
+    weights.mean(0)
+}
+
+/// Returns the start and end times of each word, in seconds.
+///
+/// `words` contains the text of each word with its tokens. The rows of `matrix` are the
+/// decoder positions that predict each of these tokens, followed by the position predicting
+/// the end of text token, so there should be one more row than there are tokens. The words
+/// times are monotonic and consecutive words do not overlap.
+pub fn word_timings(matrix: &Tensor, words: &[(String, Vec<u32>)]) -> Result<Vec<WordTiming>> {
+    let n_tokens: usize = words.iter().map(|(_, tokens)| tokens.len()).sum();
+    let (n_rows, _n_frames) = matrix.dims2()?;
+    if n_rows != n_tokens + 1 {
+        candle::bail!(
+            "expected {} rows in the alignment matrix, got {n_rows}",
+            n_tokens + 1
+        )
+    }
+    let (text_indices, time_indices) = dtw(&matrix.neg()?)?;
+    // The time at which the alignment path reaches each row.
+    let mut jump_times = Vec::with_capacity(n_rows);
+    for (idx, (&text_idx, &time_idx)) in text_indices.iter().zip(time_indices.iter()).enumerate() {
+        if idx == 0 || text_indices[idx - 1] != text_idx {
+            jump_times.push(time_idx as f64 * SECONDS_PER_FRAME)
+        }
+    }
+    let mut timings = Vec::with_capacity(words.len());
+    let mut boundary = 0;
+    for (word, tokens) in words.iter() {
+        let next_boundary = boundary + tokens.len();
+        timings.push(WordTiming {
+            word: word.clone(),
+            tokens: tokens.clone(),
+            start: jump_times[boundary],
+            end: jump_times[next_boundary],
+        });
+        boundary = next_boundary
+    }
+    Ok(timings)
+}
@@ -0,0 +1,212 @@
+//! Long-form transcription.
+//!
+//! The model only processes 30 seconds of audio at a time. Longer inputs are transcribed by
+//! sliding a window over the mel spectrogram, the window is advanced using the timestamp tokens
+//! predicted by the model so that a window starts right after the last complete segment of the
+//! previous one. Each window is conditioned on the text of the previous windows.
+//! https://github.com/openai/whisper/blob/f572f2161ba831bae131364c3bffdead7af6d210/whisper/transcribe.py
+use super::{
+    COMPRESSION_RATIO_THRESHOLD, HOP_LENGTH, LOGPROB_THRESHOLD, NO_SPEECH_THRESHOLD, N_FRAMES,
+    SAMPLE_RATE, TEMPERATURES,
+};
+use candle::{Result, Tensor};
+
+/// The number of mel frames per audio frame in the output of the encoder.
+const INPUT_STRIDE: usize = 2;
+
+/// The duration between two consecutive timestamp tokens, in seconds.
+pub const TIME_PRECISION: f64 = (INPUT_STRIDE * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+
+#[derive(Debug, Clone)]
+pub struct DecodingResult {
+    /// The sampled tokens, including the timestamp tokens but excluding the initial special
+    /// tokens and the end of text token.
+    pub tokens: Vec<u32>,
+    pub avg_logprob: f64,
+    pub no_speech_prob: f64,
+    pub temperature: f64,
+    /// Large values indicate some repetitions in the text, can be NaN if not computed.
+    pub compression_ratio: f64,
+}
+
+/// Decodes a single window of audio, this is implemented on top of the model and tokenizer.
+pub trait WindowDecoder {
+    /// `mel` has shape `(1, n_mels, n_frames)` with at most `N_FRAMES` frames, `prompt` contains
+    /// tokens from the previous windows that the decoding should be conditioned on.
+    fn decode(&mut self, mel: &Tensor, prompt: &[u32], temperature: f64) -> Result<DecodingResult>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// The first mel frame of the window that this segment was decoded from.
+    pub seek: usize,
+    /// The absolute start and end times, in seconds.
+    pub start: f64,
+    pub end: f64,
+    pub tokens: Vec<u32>,
+    pub temperature: f64,
+    pub avg_logprob: f64,
+    pub no_speech_prob: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscribeOptions {
+    /// The token id for the first timestamp, `<|0.00|>`, all the tokens from this one onwards are
+    /// timestamp tokens.
+    pub timestamp_begin: u32,
+    /// The temperatures to try in order, the next one is used when the decoding fails, has a
+    /// low average log probability, or has too many repetitions.
+    pub temperatures: Vec<f64>,
+    pub compression_ratio_threshold: f64,
+    pub logprob_threshold: f64,
+    pub no_speech_threshold: f64,
+    /// Use the text of the previous windows as a prompt. The prompt is reset when the decoding
+    /// had to use a high temperature, as it is then likely to have repetitions.
+    pub condition_on_previous_text: bool,
+    pub max_prompt_len: usize,
+}
+
+impl TranscribeOptions {
+    pub fn new(timestamp_begin: u32, cfg: &super::Config) -> Self {
+        Self {
+            timestamp_begin,
+            temperatures: TEMPERATURES.to_vec(),
+            compression_ratio_threshold: COMPRESSION_RATIO_THRESHOLD,
+            logprob_threshold: LOGPROB_THRESHOLD,
+            no_speech_threshold: NO_SPEECH_THRESHOLD,
+            condition_on_previous_text: true,
+            max_prompt_len: cfg.max_target_positions / 2 - 1,
+        }
+    }
+}
+
+/// Splits the tokens decoded for a window of `window_frames` mel frames into segments using the
+/// timestamp tokens. The segments are returned with their start and end times relative to the
+/// window start, together with the number of mel frames the window should be advanced by.
+///
+/// Each pair of consecutive timestamp tokens marks the end of a segment and the start of the
+/// next one. When the tokens do not end with a single timestamp token, the last segment is
+/// incomplete and the next window starts at the end of the last complete segment.
+pub fn split_segments(
+    tokens: &[u32],
+    timestamp_begin: u32,
+    window_frames: usize,
+) -> (Vec<(f64, f64, Vec<u32>)>, usize) {
+    let is_timestamp = |t: u32| t >= timestamp_begin;
+    let time = |t: u32| (t - timestamp_begin) as f64 * TIME_PRECISION;
+    let single_timestamp_ending = match tokens {
+        [.., t1, t2] => !is_timestamp(*t1) && is_timestamp(*t2),
+        _ => false,
+    };
+    let mut slices: Vec<usize> = (1..tokens.len())
+        .filter(|&i| is_timestamp(tokens[i - 1]) && is_timestamp(tokens[i]))
+        .collect();
+    if slices.is_empty() {
+        let window_duration = (window_frames * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+        let duration = match tokens.iter().rev().find(|&&t| is_timestamp(t)) {
+            Some(&t) if t != timestamp_begin => time(t),
+            _ => window_duration,
+        };
+        return (vec![(0., duration, tokens.to_vec())], window_frames);
+    }
+    if single_timestamp_ending {
+        slices.push(tokens.len())
+    }
+    let mut segments = Vec::with_capacity(slices.len());
+    let mut last_slice = 0;
+    for &slice in slices.iter() {
+        let sliced = &tokens[last_slice..slice];
+        let start = if is_timestamp(sliced[0]) {
+            time(sliced[0])
+        } else {
+            0.
+        };
+        let end = time(sliced[sliced.len() - 1]);
+        segments.push((start, end, sliced.to_vec()));
+        last_slice = slice
+    }
+    let advance = if single_timestamp_ending {
+        window_frames
+    } else {
+        // Resume from the timestamp that ends the last complete segment, a window that does
+        // not move forward would loop forever so the whole window is skipped in this case.
+        let advance = (tokens[last_slice - 1] - timestamp_begin) as usize * INPUT_STRIDE;
+        if advance == 0 {
+            window_frames
+        } else {
+            usize::min(advance, window_frames)
+        }
+    };
+    (segments, advance)
+}
+
+fn decode_with_fallback<D: WindowDecoder>(
+    decoder: &mut D,
+    mel: &Tensor,
+    prompt: &[u32],
+    options: &TranscribeOptions,
+) -> Result<DecodingResult> {
+    let n_temperatures = options.temperatures.len();
+    for (i, &t) in options.temperatures.iter().enumerate() {
+        let dr = decoder.decode(mel, prompt, t);
+        if i == n_temperatures - 1 {
+            return dr;
+        }
+        // On errors, we try again with a different temperature.
+        if let Ok(dr) = dr {
+            let needs_fallback = dr.compression_ratio > options.compression_ratio_threshold
+                || dr.avg_logprob < options.logprob_threshold;
+            if !needs_fallback || dr.no_speech_prob > options.no_speech_threshold {
+                return Ok(dr);
+            }
+        }
+    }
+    candle::bail!("no temperatures to decode with")
+}
+
+/// Transcribes a mel spectrogram of arbitrary length with shape `(1, n_mels, n_frames)`, the
+/// returned segments have absolute timestamps.
+pub fn transcribe_long<D: WindowDecoder>(
+    decoder: &mut D,
+    mel: &Tensor,
+    options: &TranscribeOptions,
+) -> Result<Vec<Segment>> {
+    let (_, _, content_frames) = mel.dims3()?;
+    let mut seek = 0;
+    let mut segments = vec![];
+    let mut all_tokens: Vec<u32> = vec![];
+    let mut prompt_reset_since = 0;
+    while seek < content_frames {
+        let time_offset = (seek * HOP_LENGTH) as f64 / SAMPLE_RATE as f64;
+        let window_frames = usize::min(content_frames - seek, N_FRAMES);
+        let mel_window = mel.narrow(2, seek, window_frames)?;
+        let prompt = &all_tokens[prompt_reset_since..];
+        let prompt = &prompt[prompt.len().saturating_sub(options.max_prompt_len)..];
+        let dr = decode_with_fallback(decoder, &mel_window, prompt, options)?;
+        if dr.no_speech_prob > options.no_speech_threshold
+            && dr.avg_logprob < options.logprob_threshold
+        {
+            seek += window_frames;
+            continue;
+        }
+        let (window_segments, advance) =
+            split_segments(&dr.tokens, options.timestamp_begin, window_frames);
+        for (start, end, tokens) in window_segments {
+            all_tokens.extend_from_slice(&tokens);
+            segments.push(Segment {
+                seek,
+                start: time_offset + start,
+                end: time_offset + end,
+                tokens,
+                temperature: dr.temperature,
+                avg_logprob: dr.avg_logprob,
+                no_speech_prob: dr.no_speech_prob,
+            })
+        }
+        if !options.condition_on_previous_text || dr.temperature > 0.5 {
+            prompt_reset_since = all_tokens.len()
+        }
+        seek += advance
+    }
+    Ok(segments)
+}
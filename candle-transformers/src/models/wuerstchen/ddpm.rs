@@ -2,8 +2,12 @@ use candle::{Result, Tensor};
 
 #[derive(Debug, Clone)]
 pub struct DDPMWSchedulerConfig {
-    scaler: f64,
-    s: f64,
+    pub scaler: f64,
+    pub s: f64,
+    /// The denoised prior latents are converted to image embeddings for the decoder using
+    /// `latents * latent_mean - latent_std`.
+    pub latent_mean: f64,
+    pub latent_std: f64,
 }
 
 impl Default for DDPMWSchedulerConfig {
@@ -11,6 +15,8 @@ impl Default for DDPMWSchedulerConfig {
         Self {
             scaler: 1f64,
             s: 0.008f64,
+            latent_mean: 42f64,
+            latent_std: 1f64,
         }
     }
 }
@@ -100,4 +106,24 @@ impl DDPMWScheduler {
     pub fn init_noise_sigma(&self) -> f64 {
         self.init_noise_sigma
     }
+
+    /// Runs the denoising loop starting from `latents`. For each timestep, `model` is called
+    /// with the current latents and the timestep ratio, which is used as the `r` conditioning of
+    /// the prior and decoder models, and returns the predicted noise.
+    pub fn denoise<F>(&self, latents: Tensor, mut model: F) -> Result<Tensor>
+    where
+        F: FnMut(&Tensor, f64) -> Result<Tensor>,
+    {
+        let mut latents = latents;
+        for &t in self.timesteps[..self.timesteps.len() - 1].iter() {
+            let noise_pred = model(&latents, t)?;
+            latents = self.step(&noise_pred, t, &latents)?;
+        }
+        Ok(latents)
+    }
+
+    /// Converts the denoised prior latents to the image embeddings used by the decoder.
+    pub fn unscale_latents(&self, latents: &Tensor) -> Result<Tensor> {
+        latents.affine(self.config.latent_mean, -self.config.latent_std)
+    }
 }
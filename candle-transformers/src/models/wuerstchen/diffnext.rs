@@ -87,6 +87,28 @@ pub struct WDiffNeXt {
     patch_size: usize,
 }
 
+/// The sizes of the decoder levels, the default matches the released Würstchen decoder.
+#[derive(Debug, Clone)]
+pub struct WDiffNeXtConfig {
+    pub c_hidden: [usize; 4],
+    pub blocks: [usize; 4],
+    pub nhead: [usize; 4],
+    pub inject_effnet: [bool; 4],
+    pub effnet_embd: usize,
+}
+
+impl Default for WDiffNeXtConfig {
+    fn default() -> Self {
+        Self {
+            c_hidden: [320, 640, 1280, 1280],
+            blocks: [4, 4, 14, 4],
+            nhead: [1, 10, 20, 20],
+            inject_effnet: [false, true, true, true],
+            effnet_embd: 16,
+        }
+    }
+}
+
 impl WDiffNeXt {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
@@ -99,19 +121,39 @@ impl WDiffNeXt {
         use_flash_attn: bool,
         vb: VarBuilder,
     ) -> Result<Self> {
-        const C_HIDDEN: [usize; 4] = [320, 640, 1280, 1280];
-        const BLOCKS: [usize; 4] = [4, 4, 14, 4];
-        const NHEAD: [usize; 4] = [1, 10, 20, 20];
-        const INJECT_EFFNET: [bool; 4] = [false, true, true, true];
-        const EFFNET_EMBD: usize = 16;
+        let cfg = WDiffNeXtConfig::default();
+        Self::new_with_config(
+            c_in,
+            c_out,
+            c_r,
+            c_cond,
+            clip_embd,
+            patch_size,
+            use_flash_attn,
+            &cfg,
+            vb,
+        )
+    }
 
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_config(
+        c_in: usize,
+        c_out: usize,
+        c_r: usize,
+        c_cond: usize,
+        clip_embd: usize,
+        patch_size: usize,
+        use_flash_attn: bool,
+        cfg: &WDiffNeXtConfig,
+        vb: VarBuilder,
+    ) -> Result<Self> {
         let clip_mapper = candle_nn::linear(clip_embd, c_cond, vb.pp("clip_mapper"))?;
-        let mut effnet_mappers = Vec::with_capacity(2 * INJECT_EFFNET.len());
+        let mut effnet_mappers = Vec::with_capacity(2 * cfg.inject_effnet.len());
         let vb_e = vb.pp("effnet_mappers");
-        for (i, &inject) in INJECT_EFFNET.iter().enumerate() {
+        for (i, &inject) in cfg.inject_effnet.iter().enumerate() {
             let c = if inject {
                 Some(candle_nn::conv2d(
-                    EFFNET_EMBD,
+                    cfg.effnet_embd,
                     c_cond,
                     1,
                     Default::default(),
@@ -122,14 +164,14 @@ impl WDiffNeXt {
             };
             effnet_mappers.push(c)
         }
-        for (i, &inject) in INJECT_EFFNET.iter().rev().enumerate() {
+        for (i, &inject) in cfg.inject_effnet.iter().rev().enumerate() {
             let c = if inject {
                 Some(candle_nn::conv2d(
-                    EFFNET_EMBD,
+                    cfg.effnet_embd,
                     c_cond,
                     1,
                     Default::default(),
-                    vb_e.pp(i + INJECT_EFFNET.len()),
+                    vb_e.pp(i + cfg.inject_effnet.len()),
                 )?)
             } else {
                 None
@@ -137,33 +179,34 @@ impl WDiffNeXt {
             effnet_mappers.push(c)
         }
         let seq_norm = LayerNormNoWeights::new(c_cond)?;
-        let embedding_ln = WLayerNorm::new(C_HIDDEN[0])?;
+        let embedding_ln = WLayerNorm::new(cfg.c_hidden[0])?;
         let embedding_conv = candle_nn::conv2d(
             c_in * patch_size * patch_size,
-            C_HIDDEN[0],
+            cfg.c_hidden[0],
             1,
             Default::default(),
             vb.pp("embedding.1"),
         )?;
 
-        let mut down_blocks = Vec::with_capacity(C_HIDDEN.len());
-        for (i, &c_hidden) in C_HIDDEN.iter().enumerate() {
+        let mut down_blocks = Vec::with_capacity(cfg.c_hidden.len());
+        for (i, &c_hidden) in cfg.c_hidden.iter().enumerate() {
             let vb = vb.pp("down_blocks").pp(i);
             let (layer_norm, conv, start_layer_i) = if i > 0 {
-                let layer_norm = WLayerNorm::new(C_HIDDEN[i - 1])?;
-                let cfg = candle_nn::Conv2dConfig {
+                let layer_norm = WLayerNorm::new(cfg.c_hidden[i - 1])?;
+                let conv_cfg = candle_nn::Conv2dConfig {
                     stride: 2,
                     ..Default::default()
                 };
-                let conv = candle_nn::conv2d(C_HIDDEN[i - 1], c_hidden, 2, cfg, vb.pp("0.1"))?;
+                let conv =
+                    candle_nn::conv2d(cfg.c_hidden[i - 1], c_hidden, 2, conv_cfg, vb.pp("0.1"))?;
                 (Some(layer_norm), Some(conv), 1)
             } else {
                 (None, None, 0)
             };
-            let mut sub_blocks = Vec::with_capacity(BLOCKS[i]);
+            let mut sub_blocks = Vec::with_capacity(cfg.blocks[i]);
             let mut layer_i = start_layer_i;
-            for _j in 0..BLOCKS[i] {
-                let c_skip = if INJECT_EFFNET[i] { c_cond } else { 0 };
+            for _j in 0..cfg.blocks[i] {
+                let c_skip = if cfg.inject_effnet[i] { c_cond } else { 0 };
                 let res_block = ResBlockStageB::new(c_hidden, c_skip, 3, vb.pp(layer_i))?;
                 layer_i += 1;
                 let ts_block = TimestepBlock::new(c_hidden, c_r, vb.pp(layer_i))?;
@@ -174,7 +217,7 @@ impl WDiffNeXt {
                     let attn_block = AttnBlock::new(
                         c_hidden,
                         c_cond,
-                        NHEAD[i],
+                        cfg.nhead[i],
                         true,
                         use_flash_attn,
                         vb.pp(layer_i),
@@ -197,14 +240,14 @@ impl WDiffNeXt {
             down_blocks.push(down_block)
         }
 
-        let mut up_blocks = Vec::with_capacity(C_HIDDEN.len());
-        for (i, &c_hidden) in C_HIDDEN.iter().enumerate().rev() {
-            let vb = vb.pp("up_blocks").pp(C_HIDDEN.len() - 1 - i);
-            let mut sub_blocks = Vec::with_capacity(BLOCKS[i]);
+        let mut up_blocks = Vec::with_capacity(cfg.c_hidden.len());
+        for (i, &c_hidden) in cfg.c_hidden.iter().enumerate().rev() {
+            let vb = vb.pp("up_blocks").pp(cfg.c_hidden.len() - 1 - i);
+            let mut sub_blocks = Vec::with_capacity(cfg.blocks[i]);
             let mut layer_i = 0;
-            for j in 0..BLOCKS[i] {
-                let c_skip = if INJECT_EFFNET[i] { c_cond } else { 0 };
-                let c_skip_res = if i < BLOCKS.len() - 1 && j == 0 {
+            for j in 0..cfg.blocks[i] {
+                let c_skip = if cfg.inject_effnet[i] { c_cond } else { 0 };
+                let c_skip_res = if i < cfg.blocks.len() - 1 && j == 0 {
                     c_hidden + c_skip
                 } else {
                     c_skip
@@ -219,7 +262,7 @@ impl WDiffNeXt {
                     let attn_block = AttnBlock::new(
                         c_hidden,
                         c_cond,
-                        NHEAD[i],
+                        cfg.nhead[i],
                         true,
                         use_flash_attn,
                         vb.pp(layer_i),
@@ -235,16 +278,16 @@ impl WDiffNeXt {
                 sub_blocks.push(sub_block)
             }
             let (layer_norm, conv) = if i > 0 {
-                let layer_norm = WLayerNorm::new(C_HIDDEN[i - 1])?;
-                let cfg = candle_nn::ConvTranspose2dConfig {
+                let layer_norm = WLayerNorm::new(cfg.c_hidden[i - 1])?;
+                let conv_cfg = candle_nn::ConvTranspose2dConfig {
                     stride: 2,
                     ..Default::default()
                 };
                 let conv = candle_nn::conv_transpose2d(
                     c_hidden,
-                    C_HIDDEN[i - 1],
+                    cfg.c_hidden[i - 1],
                     2,
-                    cfg,
+                    conv_cfg,
                     vb.pp(layer_i).pp(1),
                 )?;
                 (Some(layer_norm), Some(conv))
@@ -259,9 +302,9 @@ impl WDiffNeXt {
             up_blocks.push(up_block)
         }
 
-        let clf_ln = WLayerNorm::new(C_HIDDEN[0])?;
+        let clf_ln = WLayerNorm::new(cfg.c_hidden[0])?;
         let clf_conv = candle_nn::conv2d(
-            C_HIDDEN[0],
+            cfg.c_hidden[0],
             2 * c_out * patch_size * patch_size,
             1,
             Default::default(),
@@ -70,6 +70,7 @@ pub struct PaellaVQ {
     down_blocks_bn: candle_nn::BatchNorm,
     up_blocks_conv: candle_nn::Conv2d,
     up_blocks: Vec<(Vec<MixingResidualBlock>, Option<candle_nn::ConvTranspose2d>)>,
+    codebook: Tensor,
 }
 
 impl PaellaVQ {
@@ -80,6 +81,7 @@ impl PaellaVQ {
         const EMBED_DIM: usize = 384;
         const BOTTLENECK_BLOCKS: usize = 12;
         const C_LEVELS: [usize; 2] = [EMBED_DIM / 2, EMBED_DIM];
+        const NUM_VQ_EMBEDDINGS: usize = 8192;
 
         let in_block_conv = candle_nn::conv2d(
             IN_CHANNELS * 4,
@@ -165,6 +167,10 @@ impl PaellaVQ {
             };
             up_blocks.push((res_blocks, conv_block))
         }
+        let codebook = vb.get(
+            (NUM_VQ_EMBEDDINGS, LATENT_CHANNELS),
+            "vquantizer.embedding.weight",
+        )?;
         Ok(Self {
             in_block_conv,
             down_blocks,
@@ -173,10 +179,35 @@ impl PaellaVQ {
             up_blocks,
             up_blocks_conv,
             out_block_conv,
+            codebook,
         })
     }
 
+    /// Encodes an image with shape `(batch, 3, height, width)` and returns the quantized latents
+    /// with shape `(batch, 4, height / 4, width / 4)`.
     pub fn encode(&self, xs: &Tensor) -> Result<Tensor> {
+        self.quantize(&self.encode_unquantized(xs)?)
+    }
+
+    /// Replaces each latent vector with the closest entry from the codebook.
+    pub fn quantize(&self, xs: &Tensor) -> Result<Tensor> {
+        let (b_size, c, h, w) = xs.dims4()?;
+        let flat = xs.permute((0, 2, 3, 1))?.reshape((b_size * h * w, c))?;
+        let codebook = self.codebook.to_dtype(xs.dtype())?;
+        // |x - e|^2 = |x|^2 - 2 x.e + |e|^2, the |x|^2 term does not depend on the entry.
+        let dists = flat
+            .matmul(&codebook.t()?)?
+            .affine(-2., 0.)?
+            .broadcast_add(&codebook.sqr()?.sum_keepdim(1)?.t()?)?;
+        let indexes = dists.argmin(1)?;
+        codebook
+            .index_select(&indexes, 0)?
+            .reshape((b_size, h, w, c))?
+            .permute((0, 3, 1, 2))
+    }
+
+    /// Encodes an image without the codebook quantization step.
+    pub fn encode_unquantized(&self, xs: &Tensor) -> Result<Tensor> {
         let mut xs = candle_nn::ops::pixel_unshuffle(xs, 2)?.apply(&self.in_block_conv)?;
         for down_block in self.down_blocks.iter() {
             if let Some(conv) = &down_block.0 {
@@ -0,0 +1,131 @@
+//! Sparse mixture of experts.
+//!
+//! Mixtral of Experts https://arxiv.org/abs/2401.04088
+//!
+//! A router linear layer scores each token against each expert, the token is only processed by
+//! the `top_k` experts with the highest scores and their outputs are combined using the softmax
+//! of the router logits as weights.
+use candle::{DType, Module, Result, Tensor, D};
+
+/// Returns the `top_k` experts for each token together with their routing weights, both with
+/// shape `(num_tokens, top_k)` and sorted by decreasing weights. `router_logits` has shape
+/// `(num_tokens, num_experts)`.
+///
+/// The weights are the softmax of the router logits over all the experts, when `norm_topk_prob`
+/// is set they are renormalized so that the weights of the selected experts sum to one. The
+/// weights are returned as f32 whatever the dtype of the logits.
+pub fn top_k_routing(
+    router_logits: &Tensor,
+    top_k: usize,
+    norm_topk_prob: bool,
+) -> Result<(Tensor, Tensor)> {
+    let (_num_tokens, num_experts) = router_logits.dims2()?;
+    if top_k == 0 || top_k > num_experts {
+        candle::bail!("unexpected top_k {top_k} for {num_experts} experts")
+    }
+    let routing_weights = router_logits.to_dtype(DType::F32)?;
+    let routing_weights = candle_nn::ops::softmax_last_dim(&routing_weights)?;
+    let selected_experts = routing_weights
+        .arg_sort_last_dim(false)?
+        .narrow(D::Minus1, 0, top_k)?
+        .contiguous()?;
+    let routing_weights = routing_weights.gather(&selected_experts, D::Minus1)?;
+    let routing_weights = if norm_topk_prob {
+        routing_weights.broadcast_div(&routing_weights.sum_keepdim(D::Minus1)?)?
+    } else {
+        routing_weights
+    };
+    Ok((selected_experts, routing_weights))
+}
+
+/// A sparse mixture of experts block, `gate` is the router that maps the hidden states to one
+/// logit per expert.
+#[derive(Debug, Clone)]
+pub struct SparseMoeBlock<G, E> {
+    gate: G,
+    experts: Vec<E>,
+    num_experts_per_tok: usize,
+    norm_topk_prob: bool,
+    span: tracing::Span,
+}
+
+impl<G: Module, E: Module> SparseMoeBlock<G, E> {
+    pub fn new(
+        gate: G,
+        experts: Vec<E>,
+        num_experts_per_tok: usize,
+        norm_topk_prob: bool,
+    ) -> Result<Self> {
+        if num_experts_per_tok == 0 || num_experts_per_tok > experts.len() {
+            candle::bail!(
+                "unexpected num_experts_per_tok {num_experts_per_tok} for {} experts",
+                experts.len()
+            )
+        }
+        let span = tracing::span!(tracing::Level::TRACE, "sparse-moe");
+        Ok(Self {
+            gate,
+            experts,
+            num_experts_per_tok,
+            norm_topk_prob,
+            span,
+        })
+    }
+
+    pub fn experts(&self) -> &[E] {
+        &self.experts
+    }
+
+    pub fn num_experts_per_tok(&self) -> usize {
+        self.num_experts_per_tok
+    }
+}
+
+impl<G: Module, E: Module> Module for SparseMoeBlock<G, E> {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let (b_size, seq_len, hidden_dim) = xs.dims3()?;
+        let xs = xs.reshape(((), hidden_dim))?;
+        let router_logits = xs.apply(&self.gate)?;
+        let (selected_experts, routing_weights) = top_k_routing(
+            &router_logits,
+            self.num_experts_per_tok,
+            self.norm_topk_prob,
+        )?;
+
+        // The routing is done on the cpu, top_x contains the tokens to evaluate for each expert
+        // and rws the matching routing weights.
+        let selected_experts = selected_experts.to_vec2::<u32>()?;
+        let routing_weights = routing_weights.to_vec2::<f32>()?;
+        let mut top_x = vec![vec![]; self.experts.len()];
+        let mut rws = vec![vec![]; self.experts.len()];
+        for (row_idx, (expert_idxs, rw)) in selected_experts
+            .iter()
+            .zip(routing_weights.iter())
+            .enumerate()
+        {
+            for (&expert_idx, &rw) in expert_idxs.iter().zip(rw.iter()) {
+                top_x[expert_idx as usize].push(row_idx as u32);
+                rws[expert_idx as usize].push(rw)
+            }
+        }
+
+        // Each expert only processes the tokens routed to it, the weighted outputs are then
+        // scattered back to the positions of these tokens.
+        let mut ys = xs.zeros_like()?;
+        for (expert_idx, expert) in self.experts.iter().enumerate() {
+            let top_x = &top_x[expert_idx];
+            if top_x.is_empty() {
+                continue;
+            }
+            let top_x = Tensor::new(top_x.as_slice(), xs.device())?;
+            let rws = Tensor::new(rws[expert_idx].as_slice(), xs.device())?
+                .reshape(((), 1))?
+                .to_dtype(xs.dtype())?;
+            let current_state = xs.index_select(&top_x, 0)?;
+            let current_hidden_states = expert.forward(&current_state)?.broadcast_mul(&rws)?;
+            ys = ys.index_add(&top_x, &current_hidden_states, 0)?;
+        }
+        ys.reshape((b_size, seq_len, hidden_dim))
+    }
+}
@@ -34,3 +34,68 @@ pub fn repeat_kv(xs: Tensor, n_rep: usize) -> Result<Tensor> {
         Tensor::cat(&vec![&xs; n_rep], 2)?.reshape((b_sz, n_kv_head * n_rep, seq_len, head_dim))
     }
 }
+
+/// Additive causal attention mask with shape `(seq_len, seqlen_offset + seq_len)`, allowed
+/// positions are set to 0 and masked positions to minus infinity.
+///
+/// The queries are at positions `seqlen_offset..seqlen_offset + seq_len` and the keys at positions
+/// `0..seqlen_offset + seq_len`, `seqlen_offset` being the number of tokens in the kv cache. When
+/// `sliding_window` is set, a query at position `i` only attends to the keys at positions `j`
+/// with `i - sliding_window <= j <= i`.
+pub fn sliding_window_causal_mask(
+    seq_len: usize,
+    seqlen_offset: usize,
+    sliding_window: Option<usize>,
+    dtype: candle::DType,
+    device: &candle::Device,
+) -> Result<Tensor> {
+    let kv_len = seqlen_offset + seq_len;
+    let mask: Vec<_> = (seqlen_offset..kv_len)
+        .flat_map(|i| {
+            (0..kv_len).map(move |j| {
+                let outside_window = sliding_window.is_some_and(|w| j + w < i);
+                if i < j || outside_window {
+                    f32::NEG_INFINITY
+                } else {
+                    0.
+                }
+            })
+        })
+        .collect();
+    Tensor::from_slice(&mask, (seq_len, kv_len), device)?.to_dtype(dtype)
+}
+
+/// Additive attention mask with shape `(batch, 1, seq_len, kv_len)` for left-padded sequences,
+/// `attention_mask` has shape `(batch, kv_len)` and is set to 0 for padding positions.
+///
+/// The queries are the last `seq_len` positions, each query attends to the previous non-padding
+/// positions. Padding queries attend to themselves so that no row of the mask is fully masked.
+pub fn padded_causal_mask(
+    attention_mask: &Tensor,
+    seq_len: usize,
+    dtype: candle::DType,
+) -> Result<Tensor> {
+    let (b_size, kv_len) = attention_mask.dims2()?;
+    if seq_len > kv_len {
+        candle::bail!("seq_len {seq_len} is larger than the attention mask length {kv_len}")
+    }
+    let offset = kv_len - seq_len;
+    let rows = attention_mask
+        .to_dtype(candle::DType::U8)?
+        .to_vec2::<u8>()?;
+    let mask: Vec<f32> = rows
+        .iter()
+        .flat_map(|row| {
+            (offset..kv_len).flat_map(move |i| {
+                row.iter().enumerate().map(move |(j, &m)| {
+                    if j > i || (m == 0 && j != i) {
+                        f32::NEG_INFINITY
+                    } else {
+                        0.
+                    }
+                })
+            })
+        })
+        .collect();
+    Tensor::from_vec(mask, (b_size, 1, seq_len, kv_len), attention_mask.device())?.to_dtype(dtype)
+}
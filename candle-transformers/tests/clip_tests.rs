@@ -0,0 +1,95 @@
+use candle::{test_utils, DType, Device, Result, Tensor, D};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::clip::{
+    text_model::{Activation, ClipTextConfig},
+    vision_model::ClipVisionConfig,
+    ClipConfig, ClipModel,
+};
+
+fn tiny_config() -> ClipConfig {
+    let text_config = ClipTextConfig {
+        vocab_size: 32,
+        embed_dim: 16,
+        activation: Activation::QuickGelu,
+        intermediate_size: 32,
+        max_position_embeddings: 8,
+        pad_with: None,
+        num_hidden_layers: 2,
+        num_attention_heads: 2,
+        projection_dim: 8,
+    };
+    let vision_config = ClipVisionConfig {
+        embed_dim: 16,
+        activation: Activation::QuickGelu,
+        intermediate_size: 32,
+        num_hidden_layers: 2,
+        num_attention_heads: 2,
+        projection_dim: 8,
+        num_channels: 3,
+        image_size: 16,
+        patch_size: 8,
+    };
+    ClipConfig {
+        text_config,
+        vision_config,
+        logit_scale_init_value: 2.6592,
+        image_size: 16,
+    }
+}
+
+#[test]
+fn clip_embeddings() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let model = ClipModel::new(vb, &tiny_config())?;
+
+    let images = Tensor::rand(-1f32, 1., (3, 3, 16, 16), dev)?;
+    let input_ids = Tensor::new(&[[1u32, 4, 7, 31, 0], [2, 5, 31, 0, 0]], dev)?;
+    let image_embeds = model.encode_image(&images)?;
+    let text_embeds = model.encode_text(&input_ids)?;
+    assert_eq!(image_embeds.dims(), [3, 8]);
+    assert_eq!(text_embeds.dims(), [2, 8]);
+    for embeds in [&image_embeds, &text_embeds] {
+        let norms = embeds.sqr()?.sum(D::Minus1)?.sqrt()?;
+        let ones = norms.ones_like()?;
+        test_utils::assert_close(&norms, &ones, 0., 1e-5);
+    }
+
+    let logit_scale = model.logit_scale()?.flatten_all()?.to_vec1::<f32>()?;
+    assert!((logit_scale[0] - 2.6592f32.exp()).abs() < 1e-4);
+    let (logits_per_text, logits_per_image) = model.forward(&images, &input_ids)?;
+    assert_eq!(logits_per_text.dims(), [2, 3]);
+    assert_eq!(logits_per_image.dims(), [3, 2]);
+    let expected = (text_embeds.matmul(&image_embeds.t()?)? * logit_scale[0] as f64)?;
+    test_utils::assert_close(&logits_per_text, &expected, 0., 1e-4);
+    test_utils::assert_close(&logits_per_image, &expected.t()?, 0., 1e-4);
+    Ok(())
+}
+
+#[test]
+fn clip_similarities() -> Result<()> {
+    // With random weights there is no meaningful image/text alignment, so the known pairs are
+    // identical inputs: the matching pairs have a similarity of one, above any other pair.
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let model = ClipModel::new(vb, &tiny_config())?;
+
+    let images = Tensor::rand(-1f32, 1., (3, 3, 16, 16), dev)?;
+    let image_embeds = model.encode_image(&images)?;
+    let query = model.encode_image(&images.narrow(0, 1, 1)?)?;
+    let sims = query
+        .matmul(&image_embeds.t()?)?
+        .squeeze(0)?
+        .to_vec1::<f32>()?;
+    assert!((sims[1] - 1.).abs() < 1e-5, "{sims:?}");
+    assert!(sims[0] < sims[1] && sims[2] < sims[1], "{sims:?}");
+
+    let input_ids = Tensor::new(&[[1u32, 4, 7, 31], [2, 5, 9, 31], [3, 3, 31, 0]], dev)?;
+    let text_embeds = model.encode_text(&input_ids)?;
+    let query = model.encode_text(&input_ids.narrow(0, 2, 1)?)?;
+    let sims = query.matmul(&text_embeds.t()?)?.squeeze(0)?;
+    assert_eq!(sims.argmax(0)?.to_scalar::<u32>()?, 2);
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use candle::quantized::{gguf_file, GgmlDType, QTensor};
+use candle::Result;
+use candle_nn::VarMap;
+
+/// Serializes the variables of a var map as an in-memory gguf file using f32 tensors, so that a
+/// quantized model can be loaded with the same weights as its non-quantized counterpart.
+pub fn varmap_to_gguf(varmap: &VarMap) -> Result<Vec<u8>> {
+    let data = varmap.data().lock().unwrap();
+    let tensors = data
+        .iter()
+        .map(|(name, var)| {
+            Ok((
+                name.as_str(),
+                QTensor::quantize(var.as_tensor(), GgmlDType::F32)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let tensors = tensors.iter().map(|(n, t)| (*n, t)).collect::<Vec<_>>();
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write(&mut buffer, &[], &tensors)?;
+    Ok(buffer.into_inner())
+}
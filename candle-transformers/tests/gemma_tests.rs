@@ -0,0 +1,97 @@
+mod common;
+
+use candle::{test_utils, DType, Device, Result, Tensor, D};
+use candle_nn::{Activation, VarBuilder, VarMap};
+use candle_transformers::models::{gemma, quantized_gemma};
+use common::varmap_to_gguf;
+
+fn tiny_config(rms_norm_eps: f64) -> gemma::Config {
+    // head_dim is decoupled from hidden_size / num_attention_heads as in the 7b model.
+    gemma::Config {
+        attention_bias: false,
+        head_dim: 6,
+        hidden_act: None,
+        hidden_activation: Some(Activation::GeluPytorchTanh),
+        hidden_size: 8,
+        intermediate_size: 16,
+        num_attention_heads: 4,
+        num_hidden_layers: 2,
+        num_key_value_heads: 2,
+        rms_norm_eps,
+        rope_theta: 10000.,
+        vocab_size: 32,
+        max_position_embeddings: 64,
+    }
+}
+
+#[test]
+fn gemma_forward() -> Result<()> {
+    let dev = &Device::Cpu;
+    let cfg = tiny_config(1e-6);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = gemma::Model::new(false, &cfg, vb)?;
+    let input_ids = Tensor::new(&[[1u32, 5, 7, 2]], dev)?;
+    let logits = model.forward(&input_ids, 0)?;
+    assert_eq!(logits.dims(), [1, 1, 32]);
+    let logits = model.forward(&Tensor::new(&[[3u32]], dev)?, 4)?;
+    assert_eq!(logits.dims(), [1, 1, 32]);
+    Ok(())
+}
+
+#[test]
+fn gemma_embedding_and_norm() -> Result<()> {
+    let dev = &Device::Cpu;
+    // A large epsilon so that the final norm is sensitive to the embedding scaling.
+    let cfg = tiny_config(1.);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = gemma::Model::new(false, &cfg, vb)?;
+    // The model shares its weights with the var map, zero the layer outputs so that the
+    // decoder layers are the identity.
+    let embeddings = Tensor::randn(0f32, 1., (32, 8), dev)?;
+    let norm_weight = Tensor::randn(0f32, 1., 8, dev)?;
+    for (name, var) in varmap.data().lock().unwrap().iter() {
+        if name == "model.embed_tokens.weight" {
+            var.set(&embeddings)?
+        } else if name == "model.norm.weight" {
+            var.set(&norm_weight)?
+        } else if name.ends_with("o_proj.weight") || name.ends_with("down_proj.weight") {
+            var.set(&var.zeros_like()?)?
+        }
+    }
+    let logits = model.forward(&Tensor::new(&[[4u32, 9]], dev)?, 0)?;
+    // The embeddings are scaled by sqrt(hidden_size) and the norm uses (1 + weight).
+    let xs = (embeddings.get(9)? * 8f64.sqrt())?;
+    let rms = ((xs.sqr()?.mean_keepdim(D::Minus1)? + 1.)?).sqrt()?;
+    let xs = xs.broadcast_div(&rms)?.mul(&(norm_weight + 1.)?)?;
+    let expected = embeddings.matmul(&xs.unsqueeze(1)?)?.squeeze(1)?;
+    test_utils::assert_close(&logits.flatten_all()?, &expected, 0., 1e-4);
+    Ok(())
+}
+
+#[test]
+fn quantized_gemma() -> Result<()> {
+    let dev = &Device::Cpu;
+    let cfg = tiny_config(1e-6);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = gemma::Model::new(false, &cfg, vb)?;
+    // The rms norm weights are zero initialized by the var map.
+    for (name, var) in varmap.data().lock().unwrap().iter() {
+        if name.ends_with("norm.weight") {
+            var.set(&var.rand_like(-1., 1.)?)?;
+        }
+    }
+    let vb = quantized_gemma::VarBuilder::from_gguf_buffer(&varmap_to_gguf(&varmap)?, dev)?;
+    let mut qmodel = quantized_gemma::Model::new(&cfg, vb.pp("model"))?;
+
+    for (input_ids, seqlen_offset) in [(vec![1u32, 5, 7, 2], 0), (vec![3u32], 4)] {
+        let input_ids = Tensor::new(input_ids, dev)?.unsqueeze(0)?;
+        let logits = model.forward(&input_ids, seqlen_offset)?;
+        let qlogits = qmodel.forward(&input_ids, seqlen_offset)?;
+        assert_eq!(qlogits.dims(), [1, 1, 32]);
+        test_utils::assert_close(&logits, &qlogits, 0., 1e-4);
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use candle::{Device, Result, Tensor};
+use candle::{DType, Device, Result, Tensor};
 use candle_transformers::generation::LogitsProcessor;
 
 #[test]
@@ -54,3 +54,904 @@ fn sample_with_top_k() -> Result<()> {
     assert_eq!(token, 2);
     Ok(())
 }
+
+#[test]
+fn sample_with_min_p() -> Result<()> {
+    use candle_transformers::generation::Sampling;
+
+    let prs = [0.5f32, 0.3, 0.15, 0.05];
+    let logits = Tensor::new(&prs, &Device::Cpu)?.log()?;
+    let sample_counts = |sampling| -> Result<[usize; 4]> {
+        let mut logits_process = LogitsProcessor::from_sampling(42, sampling);
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            counts[logits_process.sample(&logits)? as usize] += 1
+        }
+        Ok(counts)
+    };
+    // The threshold is 0.2 * 0.5 = 0.1 so only the last token is removed.
+    let counts = sample_counts(Sampling::MinP {
+        p: 0.2,
+        temperature: 1.0,
+    })?;
+    assert!(counts[..3].iter().all(|&c| c > 0), "{counts:?}");
+    assert_eq!(counts[3], 0);
+    // Only the most likely token is kept for a peaked distribution.
+    let counts = sample_counts(Sampling::MinP {
+        p: 0.7,
+        temperature: 1.0,
+    })?;
+    assert_eq!(counts, [1000, 0, 0, 0]);
+    // min-p of 0 samples from the whole distribution.
+    let counts = sample_counts(Sampling::MinP {
+        p: 0.0,
+        temperature: 1.0,
+    })?;
+    assert!(counts.iter().all(|&c| c > 0), "{counts:?}");
+    Ok(())
+}
+
+#[test]
+fn sample_with_typical() -> Result<()> {
+    use candle_transformers::generation::Sampling;
+
+    // The entropy is ~1.28, the surprisals are ~0.92, 1.20, 1.61 and 2.30 so the tokens sorted
+    // by typicality are 1, 2, 0, 3.
+    let prs = [0.4f32, 0.3, 0.2, 0.1];
+    let logits = Tensor::new(&prs, &Device::Cpu)?.log()?;
+    let sampled_tokens = |p| -> Result<Vec<u32>> {
+        let mut logits_process = LogitsProcessor::from_sampling(
+            42,
+            Sampling::Typical {
+                p,
+                temperature: 1.0,
+            },
+        );
+        let mut tokens = (0..1000)
+            .map(|_| logits_process.sample(&logits))
+            .collect::<Result<Vec<_>>>()?;
+        tokens.sort();
+        tokens.dedup();
+        Ok(tokens)
+    };
+    assert_eq!(sampled_tokens(0.25)?, [1]);
+    assert_eq!(sampled_tokens(0.45)?, [1, 2]);
+    assert_eq!(sampled_tokens(0.8)?, [0, 1, 2]);
+    assert_eq!(sampled_tokens(1.0)?, [0, 1, 2, 3]);
+    Ok(())
+}
+
+#[test]
+fn sample_with_penalties() -> Result<()> {
+    use candle_transformers::generation::Penalties;
+
+    let logits = Tensor::new(&[1.0f32, 4.0, -2.0, 3.9], &Device::Cpu)?;
+    let history = [1, 1, 2];
+
+    // A high repetition penalty suppresses the previously sampled token.
+    let mut logits_process = LogitsProcessor::new(1337, None, None);
+    assert_eq!(logits_process.sample_with_history(&logits, &history)?, 1);
+    let mut logits_process = LogitsProcessor::new(1337, None, None).with_repetition_penalty(2.0);
+    assert_eq!(logits_process.sample_with_history(&logits, &history)?, 3);
+    let penalized = logits_process.penalties().apply(&logits, &history)?;
+    assert_eq!(penalized.to_vec1::<f32>()?, [1.0, 2.0, -4.0, 3.9]);
+
+    // The frequency penalty scales with the number of occurrences, the presence one does not.
+    let penalties = Penalties {
+        repetition_penalty: 1.0,
+        frequency_penalty: 0.5,
+        presence_penalty: 0.25,
+    };
+    let penalized = penalties.apply(&logits, &history)?;
+    assert_eq!(penalized.to_vec1::<f32>()?, [1.0, 2.75, -2.75, 3.9]);
+    let mut logits_process = LogitsProcessor::new(1337, None, None).with_penalties(penalties);
+    assert_eq!(logits_process.sample_with_history(&logits, &history)?, 3);
+
+    // Penalties of 1.0 and 0.0 leave the logits unchanged.
+    let penalties = *LogitsProcessor::new(1337, Some(1.0), None)
+        .with_repetition_penalty(1.0)
+        .with_frequency_penalty(0.0)
+        .with_presence_penalty(0.0)
+        .penalties();
+    assert!(penalties.is_noop());
+    let penalized = penalties.apply(&logits, &history)?;
+    assert_eq!(penalized.to_vec1::<f32>()?, logits.to_vec1::<f32>()?);
+    let mut p1 = LogitsProcessor::new(42, Some(1.0), None).with_penalties(penalties);
+    let mut p2 = LogitsProcessor::new(42, Some(1.0), None);
+    for _ in 0..10 {
+        assert_eq!(
+            p1.sample_with_history(&logits, &history)?,
+            p2.sample(&logits)?
+        );
+    }
+
+    // The penalized logits are returned as f32, including when no penalty applies.
+    let logits = logits.to_dtype(DType::BF16)?;
+    for history in [&history[..], &[]] {
+        let penalized = penalties.apply(&logits, history)?;
+        assert_eq!(penalized.dtype(), DType::F32);
+    }
+    let penalties = Penalties {
+        repetition_penalty: 2.0,
+        ..Default::default()
+    };
+    let penalized = penalties.apply(&logits, &history)?;
+    assert_eq!(penalized.dtype(), DType::F32);
+    assert_eq!(penalized.to_vec1::<f32>()?, [1.0, 2.0, -4.0, 3.90625]);
+    Ok(())
+}
+
+// A tiny model with a "kv cache" storing the processed tokens, the next token is the sum of all
+// the tokens so far modulo the vocabulary size.
+struct SumModel {
+    cache: Vec<u32>,
+    calls: Vec<(usize, usize)>,
+}
+
+impl candle_transformers::generation::GenerationModel for SumModel {
+    fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        assert_eq!(seqlen_offset, self.cache.len());
+        let (_, seq_len) = input_ids.dims2()?;
+        self.calls.push((seq_len, seqlen_offset));
+        self.cache
+            .extend(input_ids.flatten_all()?.to_vec1::<u32>()?);
+        let next = self.cache.iter().sum::<u32>() % 16;
+        // Return logits for all the positions, only the last one should be used.
+        let last = Tensor::arange(0u32, 16, &Device::Cpu)?
+            .eq(next)?
+            .to_dtype(candle::DType::F32)?;
+        let logits = Tensor::zeros((seq_len - 1, 16), candle::DType::F32, &Device::Cpu)?;
+        Tensor::cat(&[logits, last.unsqueeze(0)?], 0)?.unsqueeze(0)
+    }
+}
+
+#[test]
+fn token_stream() -> Result<()> {
+    use candle_transformers::generation::{StopReason, TokenStream};
+
+    let new_stream = |prompt: &[u32]| {
+        let model = SumModel {
+            cache: vec![],
+            calls: vec![],
+        };
+        TokenStream::new(
+            model,
+            LogitsProcessor::new(0, None, None),
+            prompt,
+            &Device::Cpu,
+        )
+    };
+
+    let mut stream = new_stream(&[1, 2]).with_max_new_tokens(5);
+    let tokens = stream.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens, [3, 6, 12, 8, 0]);
+    assert_eq!(stream.stop_reason(), Some(StopReason::MaxNewTokens));
+    assert_eq!(stream.tokens(), [1, 2, 3, 6, 12, 8, 0]);
+    assert!(stream.next().is_none());
+    // The prompt is processed in a single step, then one token at a time.
+    let calls = &stream.model().calls;
+    assert_eq!(calls, &[(2, 0), (1, 2), (1, 3), (1, 4), (1, 5)]);
+
+    let mut stream = new_stream(&[1, 2]).with_eos_token(8);
+    let tokens = stream.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens, [3, 6, 12]);
+    assert_eq!(stream.stop_reason(), Some(StopReason::Eos));
+
+    let mut stream = new_stream(&[1, 2])
+        .with_stop_sequence(vec![6, 3])
+        .with_stop_sequence(vec![12, 8]);
+    let tokens = stream.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens, [3, 6, 12, 8]);
+    assert_eq!(stream.stop_reason(), Some(StopReason::StopSequence));
+    assert_eq!(stream.generated_tokens(), [3, 6, 12, 8]);
+
+    let mut stream = new_stream(&[]);
+    assert!(stream.next().unwrap().is_err());
+    assert!(stream.next().is_none());
+    Ok(())
+}
+
+// Returns the logits for each of the sequences using a fixed table of next token probabilities,
+// token 0 is the end of sequence token.
+fn table_logits(seqs: &[Vec<u32>]) -> Result<Tensor> {
+    let prs = seqs
+        .iter()
+        .map(|seq| {
+            match seq.as_slice() {
+                [] => [1e-6f32, 0.6, 0.4, 1e-6],
+                [1] => [1e-6, 0.3, 0.3, 0.4],
+                [2] => [1e-6, 0.05, 0.05, 0.9],
+                _ => [1.0, 1e-6, 1e-6, 1e-6],
+            }
+            .to_vec()
+        })
+        .collect::<Vec<_>>();
+    Tensor::new(prs, &Device::Cpu)?.log()
+}
+
+#[test]
+fn beam_search_finds_better_sequence() -> Result<()> {
+    use candle_transformers::generation::BeamSearch;
+
+    // Greedy decoding picks 1, then 3 with a probability of 0.6 * 0.4 = 0.24.
+    let greedy = BeamSearch::new(1, 10).with_eos_token(0).run(table_logits)?;
+    assert_eq!(greedy.len(), 1);
+    assert_eq!(greedy[0].tokens, [1, 3, 0]);
+    // The sequence 2, 3 has a probability of 0.4 * 0.9 = 0.36.
+    let beams = BeamSearch::new(2, 10)
+        .with_eos_token(0)
+        .with_num_return_sequences(2)
+        .run(table_logits)?;
+    assert_eq!(beams.len(), 2);
+    assert_eq!(beams[0].tokens, [2, 3, 0]);
+    assert_eq!(beams[1].tokens, [1, 3, 0]);
+    assert!((beams[0].score - 0.36f64.ln() / 3.).abs() < 1e-4);
+    assert!((beams[1].score - 0.24f64.ln() / 3.).abs() < 1e-4);
+
+    // Without an eos token, the search stops at max_new_tokens.
+    let beams = BeamSearch::new(2, 2).run(table_logits)?;
+    assert_eq!(beams[0].tokens, [2, 3]);
+    assert!(BeamSearch::new(2, 2)
+        .with_num_return_sequences(3)
+        .run(table_logits)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn beam_search_length_penalty() -> Result<()> {
+    use candle_transformers::generation::BeamSearch;
+
+    // Stopping right away has probability 0.5, the longer sequence 1, 1, 1 has probability
+    // 0.5 * 0.9 * 0.9 ~ 0.4.
+    let logits = |seqs: &[Vec<u32>]| {
+        let prs = seqs
+            .iter()
+            .map(|seq| {
+                match seq.len() {
+                    0 => [0.5f32, 0.5],
+                    1 | 2 => [0.1, 0.9],
+                    _ => [1.0, 1e-6],
+                }
+                .to_vec()
+            })
+            .collect::<Vec<_>>();
+        Tensor::new(prs, &Device::Cpu)?.log()
+    };
+    let beams = BeamSearch::new(2, 10)
+        .with_eos_token(0)
+        .with_length_penalty(0.0)
+        .run(logits)?;
+    assert_eq!(beams[0].tokens, [0]);
+    let beams = BeamSearch::new(2, 10)
+        .with_eos_token(0)
+        .with_length_penalty(1.0)
+        .run(logits)?;
+    assert_eq!(beams[0].tokens, [1, 1, 1, 0]);
+    // With early stopping the search ends once two hypotheses are complete.
+    let beams = BeamSearch::new(2, 10)
+        .with_eos_token(0)
+        .with_early_stopping(true)
+        .with_num_return_sequences(2)
+        .run(logits)?;
+    assert_eq!(beams[0].tokens, [0]);
+    assert_eq!(beams[1].tokens, [1, 0]);
+    Ok(())
+}
+
+#[test]
+fn beam_search_width_one_is_greedy() -> Result<()> {
+    use candle_transformers::generation::BeamSearch;
+
+    // Pseudo random logits depending on the whole sequence.
+    let logits_for = |seq: &[u32]| {
+        let mut h = 0x9e3779b97f4a7c15u64;
+        for &t in seq {
+            h = (h ^ t as u64).wrapping_mul(0x100000001b3);
+        }
+        (0..32)
+            .map(|i| {
+                let h = (h ^ i).wrapping_mul(0x2545f4914f6cdd1d);
+                (h >> 40) as f32 / (1 << 24) as f32 * 10.
+            })
+            .collect::<Vec<f32>>()
+    };
+    let beams = BeamSearch::new(1, 20).with_eos_token(5).run(|seqs| {
+        let logits = seqs.iter().map(|s| logits_for(s)).collect::<Vec<_>>();
+        Tensor::new(logits, &Device::Cpu)
+    })?;
+
+    let mut logits_processor = LogitsProcessor::new(0, None, None);
+    let mut greedy = vec![];
+    while greedy.len() < 20 {
+        let logits = Tensor::new(logits_for(&greedy), &Device::Cpu)?;
+        let token = logits_processor.sample(&logits)?;
+        greedy.push(token);
+        if token == 5 {
+            break;
+        }
+    }
+    assert_eq!(beams[0].tokens, greedy);
+    Ok(())
+}
+
+// A tiny model returning logits for all positions, the logits only depend on the prefix and on
+// the seed. The "kv cache" has to be truncated by the speculative decoder after rejections.
+struct HashModel {
+    seed: u64,
+    cache: Vec<u32>,
+    n_forward: usize,
+}
+
+impl HashModel {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            cache: vec![],
+            n_forward: 0,
+        }
+    }
+
+    fn logits(&self, prefix: &[u32]) -> Vec<f32> {
+        let mut h = self.seed;
+        for &t in prefix {
+            h = (h ^ t as u64).wrapping_mul(0x100000001b3);
+        }
+        (0..8u64)
+            .map(|i| {
+                let h = (h ^ i).wrapping_mul(0x2545f4914f6cdd1d);
+                (h >> 40) as f32 / (1 << 24) as f32 * 4.
+            })
+            .collect()
+    }
+}
+
+impl candle_transformers::generation::GenerationModel for HashModel {
+    fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        assert_eq!(seqlen_offset, self.cache.len());
+        self.n_forward += 1;
+        let mut logits = vec![];
+        for t in input_ids.flatten_all()?.to_vec1::<u32>()? {
+            self.cache.push(t);
+            logits.push(self.logits(&self.cache))
+        }
+        Tensor::new(logits, &Device::Cpu)?.unsqueeze(0)
+    }
+}
+
+impl candle_transformers::generation::SpeculativeModel for HashModel {
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.cache.truncate(seq_len);
+        Ok(())
+    }
+}
+
+#[test]
+fn speculative_same_model() -> Result<()> {
+    use candle_transformers::generation::{SpeculativeDecoder, TokenStream};
+
+    // With draft == target, all the draft tokens are accepted.
+    let decoder = SpeculativeDecoder::new(
+        HashModel::new(1),
+        HashModel::new(1),
+        4,
+        42,
+        Some(1.0),
+        &[3, 1],
+        &Device::Cpu,
+    );
+    let mut decoder = decoder.with_max_new_tokens(20);
+    let tokens = decoder.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens.len(), 20);
+    assert_eq!(decoder.num_drafted(), decoder.num_accepted());
+    // Each step generates gamma + 1 = 5 tokens.
+    assert_eq!(decoder.num_drafted(), 16);
+    let (target, _draft) = decoder.into_inner();
+    assert_eq!(target.n_forward, 4);
+
+    // With greedy decoding, the output matches the target model output.
+    let lp = LogitsProcessor::new(0, None, None);
+    let expected = TokenStream::new(HashModel::new(1), lp, &[3, 1], &Device::Cpu)
+        .with_max_new_tokens(20)
+        .collect::<Result<Vec<_>>>()?;
+    for gamma in [0, 1, 3] {
+        let decoder = SpeculativeDecoder::new(
+            HashModel::new(1),
+            HashModel::new(2),
+            gamma,
+            42,
+            None,
+            &[3, 1],
+            &Device::Cpu,
+        );
+        let mut decoder = decoder.with_max_new_tokens(20);
+        let tokens = decoder.by_ref().collect::<Result<Vec<_>>>()?;
+        assert_eq!(tokens, expected, "{gamma}");
+        assert!(decoder.num_accepted() <= decoder.num_drafted());
+    }
+    // Use an eos token that does not appear earlier in the output.
+    let eos_idx = (1..20)
+        .rev()
+        .find(|&i| !expected[..i].contains(&expected[i]))
+        .unwrap();
+    let decoder = SpeculativeDecoder::new(
+        HashModel::new(1),
+        HashModel::new(2),
+        3,
+        42,
+        None,
+        &[3, 1],
+        &Device::Cpu,
+    );
+    let tokens = decoder
+        .with_eos_token(expected[eos_idx])
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens, expected[..eos_idx]);
+    Ok(())
+}
+
+// A mistral model with random weights, this relies on the model kv cache being rolled back.
+#[derive(Clone)]
+struct Mistral(candle_transformers::models::mistral::Model);
+
+impl Mistral {
+    fn new() -> Result<Self> {
+        use candle_transformers::models::mistral::{Config, Model};
+        let cfg = Config {
+            vocab_size: 32,
+            hidden_size: 16,
+            intermediate_size: 32,
+            num_hidden_layers: 2,
+            num_attention_heads: 4,
+            num_key_value_heads: 2,
+            hidden_act: candle_nn::Activation::Silu,
+            max_position_embeddings: 128,
+            rms_norm_eps: 1e-5,
+            rope_theta: 10_000.,
+            rope_scaling: None,
+            sliding_window: None,
+            use_flash_attn: false,
+        };
+        let varmap = candle_nn::VarMap::new();
+        let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        Ok(Self(Model::new(&cfg, vb)?))
+    }
+}
+
+impl candle_transformers::generation::GenerationModel for Mistral {
+    fn forward(&mut self, input_ids: &Tensor, seqlen_offset: usize) -> Result<Tensor> {
+        self.0.forward_all(input_ids, seqlen_offset)
+    }
+}
+
+impl candle_transformers::generation::SpeculativeModel for Mistral {
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.0.truncate_kv_cache(seq_len)
+    }
+}
+
+#[test]
+fn speculative_kv_cache() -> Result<()> {
+    use candle_transformers::generation::{SpeculativeDecoder, TokenStream};
+
+    let target = Mistral::new()?;
+    let prompt = [3, 1, 4, 1, 5];
+    let lp = LogitsProcessor::new(0, None, None);
+    let expected = TokenStream::new(target.clone(), lp, &prompt, &Device::Cpu)
+        .with_max_new_tokens(20)
+        .collect::<Result<Vec<_>>>()?;
+
+    // With a different draft model, most draft tokens get rejected and the kv caches have to be
+    // rolled back for the output to match greedy decoding with the target model.
+    let decoder = SpeculativeDecoder::new(
+        target.clone(),
+        Mistral::new()?,
+        3,
+        42,
+        None,
+        &prompt,
+        &Device::Cpu,
+    );
+    let mut decoder = decoder.with_max_new_tokens(20);
+    let tokens = decoder.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens, expected);
+    assert!(decoder.num_accepted() < decoder.num_drafted());
+
+    let decoder = SpeculativeDecoder::new(
+        target.clone(),
+        target,
+        3,
+        42,
+        Some(1.0),
+        &prompt,
+        &Device::Cpu,
+    );
+    let mut decoder = decoder.with_max_new_tokens(20);
+    let tokens = decoder.by_ref().collect::<Result<Vec<_>>>()?;
+    assert_eq!(tokens.len(), 20);
+    assert_eq!(decoder.num_drafted(), decoder.num_accepted());
+    Ok(())
+}
+
+#[test]
+fn speculative_acceptance() -> Result<()> {
+    use candle_transformers::generation::accept_draft_tokens;
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    // A draft token with zero target probability is always rejected and replaced by a token
+    // sampled from max(0, p - q).
+    let p = vec![0., 0.5, 0.5, 0.];
+    let q = vec![0.5, 0.5, 0., 0.];
+    for _ in 0..10 {
+        let (accepted, token) = accept_draft_tokens(
+            &[0],
+            std::slice::from_ref(&q),
+            &[p.clone(), p.clone()],
+            &mut rng,
+        )?;
+        assert_eq!((accepted, token), (0, 2));
+    }
+    // Identical distributions always accept, the extra token comes from the last distribution.
+    let last = vec![0., 0., 0., 1.];
+    let (accepted, token) = accept_draft_tokens(
+        &[1, 2],
+        &[p.clone(), p.clone()],
+        &[p.clone(), p.clone(), last],
+        &mut rng,
+    )?;
+    assert_eq!((accepted, token), (2, 3));
+    assert!(accept_draft_tokens(
+        &[1],
+        std::slice::from_ref(&p),
+        std::slice::from_ref(&p),
+        &mut rng
+    )
+    .is_err());
+
+    // The first returned token follows the target distribution whatever the draft distribution.
+    let p = vec![0.1, 0.2, 0.3, 0.4];
+    let q = vec![0.4, 0.3, 0.2, 0.1];
+    let q_distr = rand::distributions::WeightedIndex::new(&q).unwrap();
+    let n = 100_000;
+    let mut counts = [0usize; 4];
+    for _ in 0..n {
+        let draft = rand::distributions::Distribution::sample(&q_distr, &mut rng) as u32;
+        let (accepted, token) = accept_draft_tokens(
+            &[draft],
+            std::slice::from_ref(&q),
+            &[p.clone(), p.clone()],
+            &mut rng,
+        )?;
+        let first = if accepted == 1 { draft } else { token };
+        counts[first as usize] += 1;
+    }
+    for (c, p) in counts.iter().zip(p.iter()) {
+        let freq = *c as f32 / n as f32;
+        assert!((freq - p).abs() < 0.01, "{counts:?}");
+    }
+    Ok(())
+}
+
+// A single attention layer model supporting left-padded batches.
+#[derive(Clone)]
+struct TinyAttention {
+    emb: Tensor,
+    ws: [Tensor; 4],
+    kv: Option<(Tensor, Tensor)>,
+}
+
+impl TinyAttention {
+    fn new() -> Result<Self> {
+        use rand::{Rng, SeedableRng};
+
+        let dev = &Device::Cpu;
+        let (vocab, dim) = (16, 8);
+        // Use a local rng rather than the device one so that the weights, and hence the eos
+        // token picked in the test, do not depend on the other tests running concurrently.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(299792458);
+        let mut w = |d1, d2| {
+            let ws: Vec<f32> = (0..d1 * d2).map(|_| rng.gen_range(-1.7..1.7)).collect();
+            Tensor::from_vec(ws, (d1, d2), dev)
+        };
+        Ok(Self {
+            emb: w(vocab, dim)?,
+            ws: [w(dim, dim)?, w(dim, dim)?, w(dim, dim)?, w(dim, vocab)?],
+            kv: None,
+        })
+    }
+}
+
+impl candle_transformers::generation::BatchedGenerationModel for TinyAttention {
+    fn forward(
+        &mut self,
+        input_ids: &Tensor,
+        attention_mask: &Tensor,
+        position_ids: &Tensor,
+        seqlen_offset: usize,
+    ) -> Result<Tensor> {
+        let (b_size, seq_len) = input_ids.dims2()?;
+        let dim = self.emb.dim(1)?;
+        assert_eq!(attention_mask.dims2()?, (b_size, seqlen_offset + seq_len));
+        let xs = self.emb.index_select(&input_ids.flatten_all()?, 0)?;
+        let xs = xs.reshape((b_size, seq_len, dim))?;
+        let freqs = Tensor::arange(1f32, dim as f32 + 1., &Device::Cpu)?.reshape((1, 1, dim))?;
+        let pos = position_ids.to_dtype(candle::DType::F32)?.unsqueeze(2)?;
+        let xs = (xs + pos.broadcast_mul(&freqs)?.sin()?)?;
+        let q = xs.broadcast_matmul(&self.ws[0])?;
+        let k = xs.broadcast_matmul(&self.ws[1])?;
+        let v = xs.broadcast_matmul(&self.ws[2])?;
+        let (k, v) = match &self.kv {
+            None => (k, v),
+            Some((pk, pv)) => (Tensor::cat(&[pk, &k], 1)?, Tensor::cat(&[pv, &v], 1)?),
+        };
+        self.kv = Some((k.clone(), v.clone()));
+        let mask = candle_transformers::utils::padded_causal_mask(
+            attention_mask,
+            seq_len,
+            candle::DType::F32,
+        )?;
+        let att = (q.matmul(&k.t()?)? / (dim as f64).sqrt())?;
+        let att = att.broadcast_add(&mask.squeeze(1)?)?;
+        let att = candle_nn::ops::softmax_last_dim(&att)?;
+        let ys = (xs + att.matmul(&v)?)?;
+        ys.broadcast_matmul(&self.ws[3])
+    }
+}
+
+#[test]
+fn batched_generation() -> Result<()> {
+    use candle_transformers::generation::BatchGenerator;
+
+    let dev = &Device::Cpu;
+    let model = TinyAttention::new()?;
+    let prompts = vec![vec![3, 1, 4, 1, 5], vec![9, 2]];
+    let single = |prompt: &[u32], lp: LogitsProcessor, eos: Option<u32>| {
+        let mut g = BatchGenerator::new(model.clone(), vec![lp], 0, 10, dev);
+        if let Some(eos) = eos {
+            g = g.with_eos_token(eos)
+        }
+        g.generate(&[prompt.to_vec()]).map(|mut v| v.remove(0))
+    };
+
+    // Pick an eos token so that the second sequence finishes early.
+    let greedy = || LogitsProcessor::new(0, None, None);
+    let out1 = single(&prompts[1], greedy(), None)?;
+    assert_eq!(out1.len(), 10);
+    let eos = out1[(1..10).find(|&i| !out1[..i].contains(&out1[i])).unwrap()];
+    let expected = [
+        single(&prompts[0], greedy(), Some(eos))?,
+        single(&prompts[1], greedy(), Some(eos))?,
+    ];
+    assert!(expected[1].len() < 10);
+    let mut g = BatchGenerator::new(model.clone(), vec![greedy(), greedy()], 0, 10, dev)
+        .with_eos_token(eos);
+    let outputs = g.generate(&prompts)?;
+    assert_eq!(outputs, expected);
+
+    // Each sequence uses its own sampling state.
+    let lp = |seed| LogitsProcessor::new(seed, Some(1.0), None);
+    let expected = [
+        single(&prompts[0], lp(1), None)?,
+        single(&prompts[1], lp(2), None)?,
+    ];
+    let mut g = BatchGenerator::new(model.clone(), vec![lp(1), lp(2)], 0, 10, dev);
+    assert_eq!(g.generate(&prompts)?, expected);
+
+    let mut g = BatchGenerator::new(model, vec![greedy()], 0, 10, dev);
+    assert!(g.generate(&prompts).is_err());
+    Ok(())
+}
+
+// Single character tokens for the printable ascii characters followed by some multi-character
+// tokens.
+fn grammar_vocab() -> Vec<String> {
+    let mut vocab: Vec<String> = (32u8..127).map(|c| (c as char).to_string()).collect();
+    vocab.push("\n".to_string());
+    for token in [
+        "{\"", "\":", "\": \"", "\",", "key", "12", "1a", ".5", "e+", "true", "}\n",
+    ] {
+        vocab.push(token.to_string())
+    }
+    // The end of sequence token.
+    vocab.push(String::new());
+    vocab
+}
+
+#[test]
+fn grammar_number() -> Result<()> {
+    use candle_transformers::generation::GrammarConstraint;
+
+    let vocab = grammar_vocab();
+    let id = |s: &str| vocab.iter().position(|v| v == s).unwrap() as u32;
+    let grammar = r#"root ::= "-"? [0-9]+ ("." [0-9]+)?"#;
+    let mut constraint = GrammarConstraint::new(grammar, vocab.clone())?;
+    let text = |tokens: Vec<u32>| {
+        tokens
+            .iter()
+            .map(|&t| vocab[t as usize].as_str())
+            .collect::<Vec<_>>()
+    };
+    let mut expected = vec!["-", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "12"];
+    expected.sort();
+    let mut allowed = text(constraint.allowed_tokens());
+    allowed.sort();
+    assert_eq!(allowed, expected);
+    assert!(!constraint.is_complete());
+
+    // Letters are rejected, including when they are part of a multi-character token.
+    assert!(constraint.accept_token(id("a")).is_err());
+    assert!(constraint.accept_token(id("1a")).is_err());
+    constraint.accept_token(id("12"))?;
+    assert!(constraint.is_complete());
+    let allowed = text(constraint.allowed_tokens());
+    assert!(allowed.contains(&".") && allowed.contains(&".5"));
+    assert!(allowed
+        .iter()
+        .all(|t| t.chars().all(|c| c.is_ascii_digit() || c == '.')));
+    constraint.accept_token(id("."))?;
+    assert!(!constraint.is_complete());
+    assert!(constraint.accept_token(id(".5")).is_err());
+    assert!(constraint.accept_token(id("e")).is_err());
+    constraint.accept_token(id("5"))?;
+    assert!(constraint.is_complete());
+
+    // Masking the logits always results in a number.
+    let eos = vocab.len() as u32 - 1;
+    let mut constraint = GrammarConstraint::new(grammar, vocab.clone())?.with_eos_token(eos);
+    let mut logits_processor = LogitsProcessor::new(42, Some(1.0), None);
+    let mut output = String::new();
+    while !constraint.is_finished() {
+        let logits = Tensor::randn(0f32, 1., vocab.len(), &Device::Cpu)?;
+        let token = logits_processor.sample_with_grammar(&logits, &mut constraint)?;
+        output.push_str(&vocab[token as usize]);
+    }
+    assert!(output.parse::<f64>().is_ok(), "{output}");
+    Ok(())
+}
+
+#[test]
+fn grammar_json_object() -> Result<()> {
+    use candle_transformers::generation::GrammarConstraint;
+
+    let vocab = grammar_vocab();
+    let eos = vocab.len() as u32 - 1;
+    let grammar = r#"
+        root ::= "{" ws (pair ("," ws pair)*)? "}"
+        pair ::= string ":" ws value ws
+        value ::= string | "0" | [1-9] [0-9]* | "true" | "false"
+        string ::= "\"" [a-z]* "\""
+        ws ::= [ \n]*  # optional whitespace
+    "#;
+    let constraint = GrammarConstraint::new(grammar, vocab.clone())?.with_eos_token(eos);
+    let allowed = constraint.allowed_tokens();
+    let first = allowed.iter().map(|&t| vocab[t as usize].as_str());
+    assert_eq!(first.collect::<Vec<_>>(), ["{", "{\""]);
+
+    let valid_chars = "{}\":, \nabcdefghijklmnopqrstuvwxyz0123456789";
+    for seed in 0..20 {
+        let mut constraint = constraint.clone();
+        let mut logits_processor = LogitsProcessor::new(seed, Some(1.0), None);
+        let mut output = String::new();
+        let mut logits = Tensor::randn(0f32, 1., vocab.len(), &Device::Cpu)?.to_vec1::<f32>()?;
+        // Favor the end of sequence token so that the generation terminates quickly.
+        logits[eos as usize] = 10.;
+        for _ in 0..200 {
+            if constraint.is_finished() {
+                break;
+            }
+            let logits = (Tensor::new(logits.as_slice(), &Device::Cpu)?
+                + Tensor::randn(0f32, 1., vocab.len(), &Device::Cpu)?)?;
+            let token = logits_processor.sample_with_grammar(&logits, &mut constraint)?;
+            output.push_str(&vocab[token as usize]);
+            assert!(output.chars().all(|c| valid_chars.contains(c)), "{output}");
+        }
+        if constraint.is_finished() {
+            let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+            assert!(value.is_object(), "{output}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn grammar_json() -> Result<()> {
+    use candle_transformers::generation::{GrammarConstraint, JSON_GRAMMAR};
+
+    let vocab = grammar_vocab();
+    let id = |c: char| vocab.iter().position(|v| v == &c.to_string()).unwrap() as u32;
+    let json =
+        "{\"a\": [1, -2.5e3, \"x\\n\\u00e9\"],\n  \"b\": {\"c\": null, \"d\": true}, \"e\": {}}";
+    let mut constraint = GrammarConstraint::new(JSON_GRAMMAR, vocab.clone())?;
+    for c in json.chars() {
+        assert!(!constraint.is_complete());
+        constraint.accept_token(id(c))?;
+    }
+    assert!(constraint.is_complete());
+
+    for invalid in ["[1]", "{\"a\" 1}", "{\"a\": 01}", "{a: 1}", "{\"a\": tru}"] {
+        let mut constraint = GrammarConstraint::new(JSON_GRAMMAR, vocab.clone())?;
+        let accepted = invalid
+            .chars()
+            .try_for_each(|c| constraint.accept_token(id(c)))
+            .is_ok();
+        assert!(!accepted || !constraint.is_complete(), "{invalid}");
+    }
+
+    for invalid in [
+        "root ::= value",
+        "root ::= root \"a\" | \"b\"",
+        "root ::= a\na ::= b? root",
+        "root ::= \"a\"\nroot ::= \"b\"",
+        "value ::= \"a\"",
+        "root ::= [0-9]{3,1}",
+    ] {
+        assert!(
+            GrammarConstraint::new(invalid, vocab.clone()).is_err(),
+            "{invalid}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn grammar_from_tokenizer() -> Result<()> {
+    use candle_transformers::generation::GrammarConstraint;
+    use tokenizers::{models::wordlevel::WordLevel, AddedToken, Tokenizer};
+
+    // A sentencepiece style vocabulary, "€" is only available through the byte fallback tokens
+    // e2 82 ac.
+    let tokens = [
+        "<unk>", "<s>", "</s>", "<0x0A>", "<0x41>", "<0xE2>", "<0x82>", "<0xAC>", "<0xC3>",
+        "<0xA9>", "▁price", "▁is", "▁", "5",
+    ];
+    let vocab = tokens
+        .iter()
+        .enumerate()
+        .map(|(id, t)| (t.to_string(), id as u32))
+        .collect();
+    let model = WordLevel::builder()
+        .vocab(vocab)
+        .unk_token("<unk>".to_string())
+        .build()
+        .map_err(|e| candle::Error::Msg(e.to_string()))?;
+    let mut tokenizer = Tokenizer::new(model);
+    tokenizer.add_special_tokens(&[
+        AddedToken::from("<s>", true),
+        AddedToken::from("</s>", true),
+    ]);
+    let id = |s: &str| tokens.iter().position(|t| *t == s).unwrap() as u32;
+
+    let grammar = r#"root ::= " price is" " "? [0-9]+ ("€" | "A") "\n""#;
+    let mut constraint = GrammarConstraint::from_tokenizer(grammar, &tokenizer)?;
+    let mut constraint_eos = constraint.clone().with_eos_token(id("</s>"));
+    assert_eq!(constraint.allowed_tokens(), [id("▁price"), id("▁")]);
+    for token in ["▁price", "▁is", "▁", "5"] {
+        constraint.accept_token(id(token))?;
+    }
+    // "é" is c3 a9 so the c3 byte is rejected, e2 can start "€".
+    assert_eq!(
+        constraint.allowed_tokens(),
+        [id("<0x41>"), id("<0xE2>"), id("5")]
+    );
+    constraint.accept_token(id("<0xE2>"))?;
+    assert_eq!(constraint.allowed_tokens(), [id("<0x82>")]);
+    assert!(constraint.clone().accept_token(id("<0xAC>")).is_err());
+    constraint.accept_token(id("<0x82>"))?;
+    constraint.accept_token(id("<0xAC>"))?;
+    assert!(!constraint.is_complete());
+    assert_eq!(constraint.allowed_tokens(), [id("<0x0A>")]);
+    constraint.accept_token(id("<0x0A>"))?;
+    assert!(constraint.is_complete());
+
+    // The special tokens are only allowed as the end of sequence token.
+    for token in ["▁price", "▁is", "5", "<0x41>", "<0x0A>"] {
+        assert!(!constraint_eos.allowed_tokens().contains(&id("</s>")));
+        constraint_eos.accept_token(id(token))?;
+    }
+    assert_eq!(constraint_eos.allowed_tokens(), [id("</s>")]);
+    assert!(constraint_eos.accept_token(id("<s>")).is_err());
+    constraint_eos.accept_token(id("</s>"))?;
+    assert!(constraint_eos.is_finished());
+    Ok(())
+}
@@ -0,0 +1,108 @@
+# Generates gptq_layer.safetensors, a 4 bits GPTQ linear layer with desc_act and the tensors
+# that AutoGPTQ exports for it.
+#
+# The packing follows QuantLinear.pack from auto_gptq/nn_modules/qlinear/qlinear_cuda_old.py
+# step by step, using plain python lists rather than torch/numpy so that the only requirement is
+# the python standard library.
+import json
+import random
+import struct
+
+BITS = 4
+GROUP_SIZE = 16
+IN_FEATURES = 64
+OUT_FEATURES = 32
+N_GROUPS = IN_FEATURES // GROUP_SIZE
+MAX_Q = 2**BITS - 1
+
+rng = random.Random(1234)
+
+
+def f16(v):
+    return struct.unpack("<e", struct.pack("<e", v))[0]
+
+
+# The quantization parameters as produced by the GPTQ algorithm, scales are stored in float16.
+scales = [[f16(rng.uniform(0.001, 0.05)) for _ in range(OUT_FEATURES)] for _ in range(N_GROUPS)]
+zeros = [[rng.randint(1, MAX_Q) for _ in range(OUT_FEATURES)] for _ in range(N_GROUPS)]
+# With desc_act, the columns are quantized by decreasing hessian diagonal so the groups are
+# assigned through a permutation: g_idx[perm[i]] = i // group_size.
+perm = list(range(IN_FEATURES))
+rng.shuffle(perm)
+g_idx = [0] * IN_FEATURES
+for i, p in enumerate(perm):
+    g_idx[p] = i // GROUP_SIZE
+# The weight of the linear layer, already on the quantization grid as after running GPTQ.
+weight = [
+    [scales[g_idx[i]][o] * (rng.randint(0, MAX_Q) - zeros[g_idx[i]][o]) for i in range(IN_FEATURES)]
+    for o in range(OUT_FEATURES)
+]
+bias = [f16(rng.uniform(-1, 1)) for _ in range(OUT_FEATURES)]
+
+# QuantLinear.pack, this requires in and out features to be multiples of 32.
+scale_zeros = [[z * s for z, s in zip(zs, ss)] for zs, ss in zip(zeros, scales)]
+intweight = []
+for idx in range(IN_FEATURES):
+    g = g_idx[idx]
+    intweight.append(
+        [round((weight[o][idx] + scale_zeros[g][o]) / scales[g][o]) for o in range(OUT_FEATURES)]
+    )
+# intweight has shape (infeatures, outfeatures) at this point, i.e. after intweight.t().
+qweight = [[0] * OUT_FEATURES for _ in range(IN_FEATURES // 32 * BITS)]
+i = 0
+row = 0
+while row < len(qweight):
+    for j in range(i, i + (32 // BITS)):
+        for o in range(OUT_FEATURES):
+            qweight[row][o] |= intweight[j][o] << (BITS * (j - i))
+    i += 32 // BITS
+    row += 1
+
+zeros_m1 = [[z - 1 for z in zs] for zs in zeros]
+qzeros = [[0] * (OUT_FEATURES // 32 * BITS) for _ in range(N_GROUPS)]
+i = 0
+col = 0
+while col < len(qzeros[0]):
+    for j in range(i, i + (32 // BITS)):
+        for g in range(N_GROUPS):
+            qzeros[g][col] |= zeros_m1[g][j] << (BITS * (j - i))
+    i += 32 // BITS
+    col += 1
+
+
+def flatten(vs):
+    return [v for row in vs for v in row]
+
+
+def i32(vs):
+    # np.uint32 -> np.int32
+    return struct.pack(f"<{len(vs)}i", *[v - 2**32 if v >= 2**31 else v for v in vs])
+
+
+xs = [[rng.uniform(-1, 1) for _ in range(IN_FEATURES)] for _ in range(3)]
+ys = [
+    [sum(x[i] * weight[o][i] for i in range(IN_FEATURES)) + bias[o] for o in range(OUT_FEATURES)]
+    for x in xs
+]
+
+tensors = {
+    "layer.qweight": ("I32", [len(qweight), OUT_FEATURES], i32(flatten(qweight))),
+    "layer.qzeros": ("I32", [N_GROUPS, len(qzeros[0])], i32(flatten(qzeros))),
+    "layer.scales": ("F16", [N_GROUPS, OUT_FEATURES], struct.pack(f"<{N_GROUPS * OUT_FEATURES}e", *flatten(scales))),
+    "layer.g_idx": ("I32", [IN_FEATURES], i32(g_idx)),
+    "layer.bias": ("F16", [OUT_FEATURES], struct.pack(f"<{OUT_FEATURES}e", *bias)),
+    # The expected dequantized weight, input and output, these are not part of the checkpoint.
+    "weight": ("F32", [OUT_FEATURES, IN_FEATURES], struct.pack(f"<{OUT_FEATURES * IN_FEATURES}f", *flatten(weight))),
+    "xs": ("F32", [3, IN_FEATURES], struct.pack(f"<{3 * IN_FEATURES}f", *flatten(xs))),
+    "ys": ("F32", [3, OUT_FEATURES], struct.pack(f"<{3 * OUT_FEATURES}f", *flatten(ys))),
+}
+
+# Write the safetensors file by hand to avoid depending on the safetensors package.
+header, data = {}, b""
+for name, (dtype, shape, values) in tensors.items():
+    header[name] = {"dtype": dtype, "shape": shape, "data_offsets": [len(data), len(data) + len(values)]}
+    data += values
+header = json.dumps(header, separators=(",", ":")).encode()
+header += b" " * (-len(header) % 8)
+with open("gptq_layer.safetensors", "wb") as f:
+    f.write(struct.pack("<Q", len(header)) + header + data)
@@ -0,0 +1,172 @@
+use candle::{DType, Device, Module, Result, Tensor};
+use candle_transformers::gptq::{gptq_linear, GptqConfig, GptqLinear};
+use std::collections::HashMap;
+
+// Simple deterministic generator so that the tests do not depend on a random seed.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (self.0 >> 33) as u32
+    }
+}
+
+// Packs the weights the same way as AutoGPTQ `QuantLinear.pack`, `q` has shape (in_dim, out_dim)
+// and `zeros` has shape (n_groups, out_dim). Values are returned as i32 similar to the exported
+// checkpoints.
+fn pack(q: &[Vec<u32>], zeros: &[Vec<u32>], bits: usize) -> (Vec<i64>, Vec<i64>) {
+    let pack = 32 / bits;
+    let (in_dim, out_dim) = (q.len(), q[0].len());
+    let mut qweight = vec![0u32; in_dim / pack * out_dim];
+    for (i, row) in q.iter().enumerate() {
+        for (o, &v) in row.iter().enumerate() {
+            qweight[(i / pack) * out_dim + o] |= v << ((i % pack) * bits)
+        }
+    }
+    let mut qzeros = vec![0u32; zeros.len() * out_dim / pack];
+    for (g, row) in zeros.iter().enumerate() {
+        for (o, &z) in row.iter().enumerate() {
+            qzeros[g * out_dim / pack + o / pack] |= (z - 1) << ((o % pack) * bits)
+        }
+    }
+    let to_i64 = |vs: Vec<u32>| vs.into_iter().map(|v| v as i32 as i64).collect();
+    (to_i64(qweight), to_i64(qzeros))
+}
+
+#[test]
+fn gptq_packing_order() -> Result<()> {
+    let dev = &Device::Cpu;
+    // Input feature i is stored in the bits 4i..4i+4 so q[i, o] = i, the zero point for output o
+    // is stored as o so the actual zero point is o + 1.
+    let qweight = Tensor::new(&[[0x76543210i64; 8]], dev)?;
+    let qzeros = Tensor::new(&[[0x76543210i64]], dev)?;
+    let scales = Tensor::new(&[[0.5f32; 8]], dev)?;
+    let g_idx = Tensor::zeros(8, DType::I64, dev)?;
+    let layer = GptqLinear::new(&qweight, &qzeros, &scales, &g_idx, None, 4)?;
+    let ws = layer.weight().to_vec2::<f32>()?;
+    for (o, row) in ws.iter().enumerate() {
+        for (i, &w) in row.iter().enumerate() {
+            assert_eq!(w, 0.5 * (i as f32 - o as f32 - 1.), "{o} {i}")
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn gptq_linear_layer() -> Result<()> {
+    let dev = &Device::Cpu;
+    let (in_dim, out_dim, group_size) = (128, 64, 32);
+    let n_groups = in_dim / group_size;
+    let mut rng = Lcg(42);
+    for (bits, desc_act) in [(4, false), (4, true), (8, true), (2, false)] {
+        let max_q = (1u32 << bits) - 1;
+        let q: Vec<Vec<u32>> = (0..in_dim)
+            .map(|_| (0..out_dim).map(|_| rng.next() % (max_q + 1)).collect())
+            .collect();
+        let zeros: Vec<Vec<u32>> = (0..n_groups)
+            .map(|_| (0..out_dim).map(|_| 1 + rng.next() % max_q).collect())
+            .collect();
+        let scales: Vec<Vec<f32>> = (0..n_groups)
+            .map(|_| {
+                (0..out_dim)
+                    .map(|_| (1 + rng.next() % 100) as f32 / 1000.)
+                    .collect()
+            })
+            .collect();
+        // With desc_act, the input features are assigned to groups in a permuted order.
+        let g_idx: Vec<usize> = if desc_act {
+            (0..in_dim)
+                .map(|i| (i * 37 % in_dim) / group_size)
+                .collect()
+        } else {
+            (0..in_dim).map(|i| i / group_size).collect()
+        };
+        let expected_w: Vec<f32> = (0..out_dim)
+            .flat_map(|o| {
+                let (q, zeros, scales, g_idx) = (&q, &zeros, &scales, &g_idx);
+                (0..in_dim).map(move |i| {
+                    let g = g_idx[i];
+                    scales[g][o] * (q[i][o] as f32 - zeros[g][o] as f32)
+                })
+            })
+            .collect();
+        let expected_w = Tensor::from_vec(expected_w, (out_dim, in_dim), dev)?;
+        let bias = Tensor::arange(0f32, out_dim as f32, dev)?;
+
+        let (qweight, qzeros) = pack(&q, &zeros, bits);
+        let pack = 32 / bits;
+        let mut ts = HashMap::new();
+        ts.insert(
+            "qweight".to_string(),
+            Tensor::from_vec(qweight, (in_dim / pack, out_dim), dev)?,
+        );
+        ts.insert(
+            "qzeros".to_string(),
+            Tensor::from_vec(qzeros, (n_groups, out_dim / pack), dev)?,
+        );
+        ts.insert("scales".to_string(), Tensor::new(scales, dev)?);
+        ts.insert("bias".to_string(), bias.clone());
+        if desc_act {
+            let g_idx = g_idx.iter().map(|&g| g as i64).collect();
+            ts.insert("g_idx".to_string(), Tensor::from_vec(g_idx, in_dim, dev)?);
+        }
+        let vb = candle_nn::VarBuilder::from_tensors(ts, DType::F32, dev);
+        let cfg = GptqConfig {
+            bits,
+            group_size: group_size as i64,
+            desc_act,
+        };
+        let layer = gptq_linear(in_dim, out_dim, true, &cfg, vb)?;
+
+        let diff = (layer.weight() - &expected_w)?.abs()?;
+        assert_eq!(diff.max_keepdim(0)?.max(1)?.to_vec1::<f32>()?, [0.]);
+
+        let xs = Tensor::randn(0f32, 1., (2, 3, in_dim), dev)?;
+        let ys = layer.forward(&xs)?;
+        let expected = candle_nn::Linear::new(expected_w, Some(bias)).forward(&xs)?;
+        assert_eq!(ys.dims(), [2, 3, out_dim]);
+        let diff = (ys - expected)?.abs()?.flatten_all()?.max(0)?;
+        assert!(diff.to_vec0::<f32>()? < 1e-4);
+    }
+    Ok(())
+}
+
+#[test]
+fn gptq_autogptq_layer() -> Result<()> {
+    // A 4 bits layer with desc_act, stored with the tensors and dtypes exported by AutoGPTQ, see
+    // gptq_layer.py.
+    let dev = &Device::Cpu;
+    let ts = candle::safetensors::load("tests/gptq_layer.safetensors", dev)?;
+    let (weight, xs, ys) = (ts["weight"].clone(), ts["xs"].clone(), ts["ys"].clone());
+    let vb = candle_nn::VarBuilder::from_tensors(ts, DType::F32, dev);
+    let cfg = GptqConfig {
+        bits: 4,
+        group_size: 16,
+        desc_act: true,
+    };
+    let layer = gptq_linear(64, 32, true, &cfg, vb.pp("layer"))?;
+    assert_eq!(layer.weight().to_vec2::<f32>()?, weight.to_vec2::<f32>()?);
+    candle::test_utils::assert_close(&layer.forward(&xs)?, &ys, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn gptq_config() -> Result<()> {
+    let cfg: GptqConfig =
+        serde_json::from_str(r#"{"bits": 4, "group_size": 128, "desc_act": true, "sym": true}"#)
+            .map_err(candle::Error::wrap)?;
+    assert_eq!(cfg.bits, 4);
+    assert_eq!(cfg.group_size, 128);
+    assert!(cfg.desc_act);
+
+    // Layers with an odd number of bits are not supported.
+    let cfg = GptqConfig {
+        bits: 3,
+        group_size: -1,
+        desc_act: false,
+    };
+    let vb = candle_nn::VarBuilder::zeros(DType::F32, &Device::Cpu);
+    assert!(gptq_linear(64, 64, false, &cfg, vb).is_err());
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use candle_nn::rotary_emb::RopeScaling;
+use candle_transformers::models::{llama, mistral};
+
+fn llama_config(rope_scaling: &str) -> serde_json::Result<llama::LlamaConfig> {
+    let config = format!(
+        r#"{{
+            "hidden_size": 64,
+            "intermediate_size": 128,
+            "vocab_size": 256,
+            "num_hidden_layers": 2,
+            "num_attention_heads": 4,
+            "num_key_value_heads": 2,
+            "rms_norm_eps": 1e-5,
+            "rope_theta": 500000.0,
+            "rope_scaling": {rope_scaling},
+            "bos_token_id": 128000,
+            "eos_token_id": 128001
+        }}"#
+    );
+    serde_json::from_str(&config)
+}
+
+#[test]
+fn rope_scaling_config() -> serde_json::Result<()> {
+    // The rope_scaling field from the Llama 3.1 and 3.2 configs.
+    let config = llama_config(
+        r#"{
+            "factor": 8.0,
+            "low_freq_factor": 1.0,
+            "high_freq_factor": 4.0,
+            "original_max_position_embeddings": 8192,
+            "rope_type": "llama3"
+        }"#,
+    )?;
+    assert_eq!(
+        config.rope_scaling,
+        Some(RopeScaling::Llama3 {
+            factor: 8.0,
+            low_freq_factor: 1.0,
+            high_freq_factor: 4.0,
+            original_max_position_embeddings: 8192,
+        })
+    );
+
+    // Older configs use `type` rather than `rope_type`, some configs set both.
+    let config = llama_config(r#"{"type": "linear", "factor": 2.0}"#)?;
+    assert_eq!(
+        config.rope_scaling,
+        Some(RopeScaling::Linear { factor: 2.0 })
+    );
+    let config = llama_config(r#"{"type": "linear", "rope_type": "linear", "factor": 2.0}"#)?;
+    assert_eq!(
+        config.rope_scaling,
+        Some(RopeScaling::Linear { factor: 2.0 })
+    );
+
+    // Scaling kinds that are not supported result in no scaling rather than an error.
+    for rope_scaling in [
+        "null",
+        r#"{"rope_type": "default"}"#,
+        r#"{"rope_type": "longrope", "factor": 2.0}"#,
+    ] {
+        let config = llama_config(rope_scaling)?;
+        assert_eq!(config.rope_scaling, None);
+    }
+
+    // Missing parameters for a supported kind are still reported.
+    let err = llama_config(r#"{"rope_type": "llama3", "factor": 8.0}"#).unwrap_err();
+    assert!(err.to_string().contains("low_freq_factor"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn rope_scaling_direct() {
+    // Deserializing a `RopeScaling` directly rejects unsupported kinds.
+    let scaling: RopeScaling = serde_json::from_str(
+        r#"{"type": "yarn", "factor": 4.0, "original_max_position_embeddings": 4096}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        scaling,
+        RopeScaling::Yarn {
+            factor: 4.0,
+            original_max_position_embeddings: 4096,
+            beta_fast: 32.,
+            beta_slow: 1.,
+            attention_factor: None,
+        }
+    );
+    let err = serde_json::from_str::<RopeScaling>(r#"{"rope_type": "longrope"}"#).unwrap_err();
+    assert!(
+        err.to_string().contains("unsupported rope scaling"),
+        "{err}"
+    );
+}
+
+#[test]
+fn mistral_rope_scaling_config() -> serde_json::Result<()> {
+    let config: mistral::Config = serde_json::from_str(
+        r#"{
+            "vocab_size": 256,
+            "hidden_size": 64,
+            "intermediate_size": 128,
+            "num_hidden_layers": 2,
+            "num_attention_heads": 4,
+            "num_key_value_heads": 2,
+            "hidden_act": "silu",
+            "max_position_embeddings": 4096,
+            "rms_norm_eps": 1e-5,
+            "rope_theta": 10000.0,
+            "rope_scaling": {"rope_type": "dynamic", "factor": 2.0,
+                             "original_max_position_embeddings": 2048},
+            "sliding_window": null
+        }"#,
+    )?;
+    assert_eq!(
+        config.rope_scaling,
+        Some(RopeScaling::Dynamic {
+            factor: 2.0,
+            original_max_position_embeddings: 2048,
+        })
+    );
+    Ok(())
+}
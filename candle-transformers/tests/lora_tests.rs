@@ -0,0 +1,86 @@
+use candle::{test_utils, DType, Device, Module, Result, Tensor};
+use candle_transformers::lora::{LoraAdapter, LoraConfig, LoraLinear};
+use std::collections::HashMap;
+
+fn config(target_modules: &[&str]) -> LoraConfig {
+    LoraConfig {
+        r: 2,
+        lora_alpha: 4.,
+        target_modules: target_modules.iter().map(|s| s.to_string()).collect(),
+        use_rslora: false,
+    }
+}
+
+#[test]
+fn lora_zero_b() -> Result<()> {
+    let dev = &Device::Cpu;
+    let weight = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    let bias = Tensor::randn(0f32, 1., 3, dev)?;
+    let base = candle_nn::Linear::new(weight, Some(bias));
+    let lora_a = Tensor::randn(0f32, 1., (2, 4), dev)?;
+    let lora_b = Tensor::zeros((3, 2), DType::F32, dev)?;
+    let lora = LoraLinear::new(base.clone(), lora_a, lora_b, 2.)?;
+    let xs = Tensor::randn(0f32, 1., (2, 5, 4), dev)?;
+    test_utils::assert_close(&lora.forward(&xs)?, &base.forward(&xs)?, 0., 0.);
+    Ok(())
+}
+
+#[test]
+fn lora_merge() -> Result<()> {
+    let dev = &Device::Cpu;
+    let weight = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    let base = candle_nn::Linear::new(weight, None);
+    let lora_a = Tensor::randn(0f32, 1., (2, 4), dev)?;
+    let lora_b = Tensor::randn(0f32, 1., (3, 2), dev)?;
+    let mut lora = LoraLinear::new(base.clone(), lora_a, lora_b, 2.)?;
+    let xs = Tensor::randn(0f32, 1., (2, 5, 4), dev)?;
+    let ys = lora.forward(&xs)?;
+    assert!(!test_utils::allclose(&ys, &base.forward(&xs)?, 0., 1e-3)?);
+    lora.merge()?;
+    assert!(lora.is_merged());
+    test_utils::assert_close(&lora.forward(&xs)?, &ys, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn lora_adapter() -> Result<()> {
+    let dev = &Device::Cpu;
+    let q = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    let v = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    let mut tensors = HashMap::new();
+    for module in ["q_proj", "v_proj"] {
+        let prefix = format!("base_model.model.model.layers.0.self_attn.{module}");
+        let lora_a = Tensor::randn(0f32, 1., (2, 4), dev)?;
+        let lora_b = Tensor::randn(0f32, 1., (3, 2), dev)?;
+        tensors.insert(format!("{prefix}.lora_A.weight"), lora_a);
+        tensors.insert(format!("{prefix}.lora_B.weight"), lora_b);
+    }
+    // Only q_proj is a target module.
+    let adapter = LoraAdapter::from_tensors(tensors.clone(), config(&["q_proj"]))?;
+    assert_eq!(
+        adapter.modules().collect::<Vec<_>>(),
+        ["model.layers.0.self_attn.q_proj"]
+    );
+    assert_eq!(adapter.config().scale(), 2.);
+
+    let xs = Tensor::randn(0f32, 1., (5, 4), dev)?;
+    let q_path = "model.layers.0.self_attn.q_proj";
+    let v_path = "model.layers.0.self_attn.v_proj";
+    let q_lora = adapter.linear(candle_nn::Linear::new(q.clone(), None), q_path)?;
+    let v_lora = adapter.linear(candle_nn::Linear::new(v.clone(), None), v_path)?;
+    assert!(!q_lora.is_merged());
+    assert!(v_lora.is_merged());
+
+    let mut weights = HashMap::new();
+    weights.insert(format!("{q_path}.weight"), q);
+    weights.insert(format!("{v_path}.weight"), v.clone());
+    adapter.merge_into(&mut weights)?;
+    let q_merged = candle_nn::Linear::new(weights[&format!("{q_path}.weight")].clone(), None);
+    test_utils::assert_close(&q_merged.forward(&xs)?, &q_lora.forward(&xs)?, 0., 1e-5);
+    test_utils::assert_close(&weights[&format!("{v_path}.weight")], &v, 0., 0.);
+
+    // An incomplete adapter is rejected.
+    tensors.remove("base_model.model.model.layers.0.self_attn.v_proj.lora_B.weight");
+    assert!(LoraAdapter::from_tensors(tensors, config(&[])).is_err());
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use candle::{test_utils, Device, Module, Result, Tensor, D};
+use candle_nn::Linear;
+use candle_transformers::moe::{top_k_routing, SparseMoeBlock};
+
+fn linear(in_dim: usize, out_dim: usize, dev: &Device) -> Result<Linear> {
+    let weight = Tensor::randn(0f32, 1., (out_dim, in_dim), dev)?;
+    Ok(Linear::new(weight, None))
+}
+
+#[test]
+fn moe_top_k_routing() -> Result<()> {
+    let dev = &Device::Cpu;
+    let logits = Tensor::new(&[[0f32, 1., 3., 2.], [5., 0., 0., 1.]], dev)?;
+    let (experts, weights) = top_k_routing(&logits, 2, false)?;
+    assert_eq!(experts.to_vec2::<u32>()?, [[2, 3], [0, 3]]);
+    let probs = candle_nn::ops::softmax_last_dim(&logits)?.to_vec2::<f32>()?;
+    let weights = weights.to_vec2::<f32>()?;
+    assert_eq!(
+        weights,
+        [[probs[0][2], probs[0][3]], [probs[1][0], probs[1][3]]]
+    );
+
+    let (_, weights) = top_k_routing(&logits, 2, true)?;
+    let sums = weights.sum(D::Minus1)?.to_vec1::<f32>()?;
+    assert!(sums.iter().all(|s| (s - 1.).abs() < 1e-6));
+    let weights = weights.to_vec2::<f32>()?;
+    assert!((weights[0][0] - 1f32.exp() / (1f32.exp() + 1.)).abs() < 1e-6);
+
+    assert!(top_k_routing(&logits, 0, true).is_err());
+    assert!(top_k_routing(&logits, 5, true).is_err());
+    Ok(())
+}
+
+#[test]
+fn moe_dense() -> Result<()> {
+    // Routing to all the experts gives the weighted sum of all the expert outputs.
+    let dev = &Device::Cpu;
+    let (hidden, num_experts) = (6, 4);
+    let gate = linear(hidden, num_experts, dev)?;
+    let experts = (0..num_experts)
+        .map(|_| linear(hidden, hidden, dev))
+        .collect::<Result<Vec<_>>>()?;
+    let moe = SparseMoeBlock::new(gate.clone(), experts.clone(), num_experts, true)?;
+    let xs = Tensor::randn(0f32, 1., (2, 3, hidden), dev)?;
+    let ys = moe.forward(&xs)?;
+    assert_eq!(ys.dims(), [2, 3, hidden]);
+
+    let probs = candle_nn::ops::softmax_last_dim(&gate.forward(&xs)?)?;
+    let mut expected = xs.zeros_like()?;
+    for (idx, expert) in experts.iter().enumerate() {
+        let p = probs.narrow(D::Minus1, idx, 1)?;
+        expected = (expected + expert.forward(&xs)?.broadcast_mul(&p)?)?;
+    }
+    test_utils::assert_close(&ys, &expected, 0., 1e-5);
+    Ok(())
+}
+
+#[test]
+fn moe_argmax_routing() -> Result<()> {
+    // With a peaked router output, each token only goes through the expert with the largest
+    // logit and the normalized top-1 weight is one.
+    let dev = &Device::Cpu;
+    let (hidden, num_experts) = (4, 3);
+    // The router picks the expert matching the position of the largest input coordinate.
+    let gate = Tensor::new(
+        &[[100f32, 0., 0., 0.], [0., 100., 0., 0.], [0., 0., 100., 0.]],
+        dev,
+    )?;
+    let gate = Linear::new(gate, None);
+    let experts = (0..num_experts)
+        .map(|_| linear(hidden, hidden, dev))
+        .collect::<Result<Vec<_>>>()?;
+    let moe = SparseMoeBlock::new(gate, experts.clone(), 1, true)?;
+    let xs = Tensor::new(
+        &[[
+            [1f32, 0., 0., 0.5],
+            [0., 0., 1., 0.5],
+            [0., 1., 0., 0.5],
+            [0., 0., 1., -0.5],
+        ]],
+        dev,
+    )?;
+    let ys = moe.forward(&xs)?;
+    for (token, expert) in [0, 2, 1, 2].into_iter().enumerate() {
+        let x = xs.narrow(1, token, 1)?;
+        let y = ys.narrow(1, token, 1)?;
+        test_utils::assert_close(&y, &experts[expert].forward(&x)?, 0., 1e-5);
+    }
+    Ok(())
+}
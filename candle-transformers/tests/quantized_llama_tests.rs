@@ -0,0 +1,134 @@
+use candle::quantized::{gguf_file, GgmlDType, QTensor};
+use candle::{test_utils, Device, Result, Tensor, D};
+use candle_transformers::models::quantized_llama::ModelWeights;
+
+const VOCAB_SIZE: usize = 32;
+const HIDDEN_SIZE: usize = 32;
+const INTERMEDIATE_SIZE: usize = 64;
+const NUM_HEADS: usize = 4;
+const NUM_KV_HEADS: usize = 2;
+const NUM_LAYERS: usize = 2;
+
+fn tiny_model(dev: &Device) -> Result<Vec<u8>> {
+    use gguf_file::Value;
+
+    let head_dim = HIDDEN_SIZE / NUM_HEADS;
+    let kv_dim = NUM_KV_HEADS * head_dim;
+    let metadata = [
+        ("llama.attention.head_count", Value::U32(NUM_HEADS as u32)),
+        (
+            "llama.attention.head_count_kv",
+            Value::U32(NUM_KV_HEADS as u32),
+        ),
+        ("llama.block_count", Value::U32(NUM_LAYERS as u32)),
+        ("llama.embedding_length", Value::U32(HIDDEN_SIZE as u32)),
+        ("llama.rope.dimension_count", Value::U32(head_dim as u32)),
+        ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-5)),
+    ];
+    let mut shapes = vec![
+        ("token_embd.weight".to_string(), (VOCAB_SIZE, HIDDEN_SIZE)),
+        ("output.weight".to_string(), (VOCAB_SIZE, HIDDEN_SIZE)),
+    ];
+    for i in 0..NUM_LAYERS {
+        for (name, shape) in [
+            ("attn_q", (HIDDEN_SIZE, HIDDEN_SIZE)),
+            ("attn_k", (kv_dim, HIDDEN_SIZE)),
+            ("attn_v", (kv_dim, HIDDEN_SIZE)),
+            ("attn_output", (HIDDEN_SIZE, HIDDEN_SIZE)),
+            ("ffn_gate", (INTERMEDIATE_SIZE, HIDDEN_SIZE)),
+            ("ffn_up", (INTERMEDIATE_SIZE, HIDDEN_SIZE)),
+            ("ffn_down", (HIDDEN_SIZE, INTERMEDIATE_SIZE)),
+        ] {
+            shapes.push((format!("blk.{i}.{name}.weight"), shape))
+        }
+    }
+    let mut tensors = shapes
+        .into_iter()
+        .map(|(name, shape)| {
+            // Small weights avoid saturating the attention softmax, as with trained models.
+            let t = Tensor::randn(0f32, 0.15, shape, dev)?;
+            Ok((name, QTensor::quantize(&t, GgmlDType::F32)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut norms = vec!["output_norm.weight".to_string()];
+    for i in 0..NUM_LAYERS {
+        norms.push(format!("blk.{i}.attn_norm.weight"));
+        norms.push(format!("blk.{i}.ffn_norm.weight"));
+    }
+    for name in norms {
+        let t = Tensor::ones(HIDDEN_SIZE, candle::DType::F32, dev)?;
+        tensors.push((name, QTensor::quantize(&t, GgmlDType::F32)?))
+    }
+    let metadata = metadata.iter().map(|(k, v)| (*k, v)).collect::<Vec<_>>();
+    let tensors = tensors
+        .iter()
+        .map(|(n, t)| (n.as_str(), t))
+        .collect::<Vec<_>>();
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write(&mut buffer, &metadata, &tensors)?;
+    Ok(buffer.into_inner())
+}
+
+fn load(gguf: &[u8], dev: &Device) -> Result<ModelWeights> {
+    let mut reader = std::io::Cursor::new(gguf);
+    let content = gguf_file::Content::read(&mut reader)?;
+    ModelWeights::from_gguf(content, &mut reader, dev)
+}
+
+#[test]
+fn quantized_kv_cache_generation() -> Result<()> {
+    let dev = &Device::Cpu;
+    dev.set_seed(42)?;
+    let gguf = tiny_model(dev)?;
+    let mut model = load(&gguf, dev)?;
+    let mut qmodel = load(&gguf, dev)?;
+    qmodel.use_quantized_kv_cache();
+
+    // Greedy generation with the full precision cache, the model with the int8 cache is fed the
+    // same tokens so that the logits can be compared at each step.
+    let prompt = [1u32, 5, 7, 2, 9];
+    let mut tokens = prompt.to_vec();
+    let mut qtokens = prompt.to_vec();
+    let mut index_pos = 0;
+    for _ in 0..20 {
+        let context = &tokens[index_pos..];
+        let input = Tensor::new(context, dev)?.unsqueeze(0)?;
+        let logits = model.forward(&input, index_pos)?;
+        let qlogits = qmodel.forward(&input, index_pos)?;
+        test_utils::assert_close(&logits, &qlogits, 0., 5e-2);
+        index_pos += context.len();
+        tokens.push(logits.argmax(D::Minus1)?.squeeze(0)?.to_scalar::<u32>()?);
+        qtokens.push(qlogits.argmax(D::Minus1)?.squeeze(0)?.to_scalar::<u32>()?);
+    }
+    assert_eq!(tokens, qtokens);
+
+    // Starting again from position 0 resets the cache.
+    let input = Tensor::new(&prompt, dev)?.unsqueeze(0)?;
+    let logits = model.forward(&input, 0)?;
+    let qlogits = qmodel.forward(&input, 0)?;
+    test_utils::assert_close(&logits, &qlogits, 1e-2, 5e-2);
+    Ok(())
+}
+
+#[test]
+fn truncate_kv_cache() -> Result<()> {
+    let dev = &Device::Cpu;
+    dev.set_seed(42)?;
+    let gguf = tiny_model(dev)?;
+    let tensor = |tokens: &[u32]| Tensor::new(tokens, dev)?.unsqueeze(0);
+    for quantized in [false, true] {
+        let mut model = load(&gguf, dev)?;
+        if quantized {
+            model.use_quantized_kv_cache();
+        }
+        let expected = model.forward(&tensor(&[1, 5, 7, 2, 9, 4])?, 0)?;
+        // Process some tokens that get rolled back, then several tokens at once on top of the
+        // truncated cache.
+        model.forward(&tensor(&[1, 5, 7])?, 0)?;
+        model.forward(&tensor(&[3, 3, 3])?, 3)?;
+        model.truncate_kv_cache(3)?;
+        let logits = model.forward(&tensor(&[2, 9, 4])?, 3)?;
+        test_utils::assert_close(&logits, &expected, 0., 1e-4);
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use candle::{test_utils, DType, Device, Result, Tensor};
+use candle_nn::{Activation, VarBuilder, VarMap};
+use candle_transformers::models::{quantized_qwen2, qwen2};
+use common::varmap_to_gguf;
+
+fn tiny_config(tie_word_embeddings: bool) -> qwen2::Config {
+    qwen2::Config {
+        vocab_size: 32,
+        hidden_size: 16,
+        intermediate_size: 24,
+        num_hidden_layers: 2,
+        num_attention_heads: 4,
+        num_key_value_heads: 2,
+        max_position_embeddings: 64,
+        sliding_window: 64,
+        max_window_layers: 2,
+        tie_word_embeddings,
+        rope_theta: 1000000.,
+        rms_norm_eps: 1e-6,
+        use_sliding_window: false,
+        hidden_act: Activation::Silu,
+    }
+}
+
+#[test]
+fn qwen2_forward() -> Result<()> {
+    let dev = &Device::Cpu;
+    let input_ids = Tensor::new(&[[1u32, 5, 7, 2], [3, 4, 8, 9]], dev)?;
+    for tie_word_embeddings in [true, false] {
+        let cfg = tiny_config(tie_word_embeddings);
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+        let mut model = qwen2::ModelForCausalLM::new(&cfg, vb)?;
+        let logits = model.forward(&input_ids, 0)?;
+        assert_eq!(logits.dims(), [2, 1, 32]);
+        let logits = model.forward(&Tensor::new(&[[3u32], [4]], dev)?, 4)?;
+        assert_eq!(logits.dims(), [2, 1, 32]);
+        let has_lm_head = varmap.data().lock().unwrap().contains_key("lm_head.weight");
+        assert_eq!(has_lm_head, !tie_word_embeddings);
+    }
+    Ok(())
+}
+
+#[test]
+fn qwen2_qkv_bias() -> Result<()> {
+    let dev = &Device::Cpu;
+    let cfg = tiny_config(true);
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = qwen2::ModelForCausalLM::new(&cfg, vb)?;
+    let input_ids = Tensor::new(&[[1u32, 5, 7, 2]], dev)?;
+    let forward = |model: &mut qwen2::ModelForCausalLM| {
+        model.clear_kv_cache();
+        model.forward(&input_ids, 0)
+    };
+    let set_biases = |proj: &str, value: f64| -> Result<()> {
+        for (name, var) in varmap.data().lock().unwrap().iter() {
+            if name.ends_with(&format!("self_attn.{proj}.bias")) {
+                var.set(&(var.ones_like()? * value)?)?
+            }
+        }
+        Ok(())
+    };
+    for proj in ["q_proj", "k_proj", "v_proj"] {
+        set_biases(proj, 0.)?
+    }
+    let logits = forward(&mut model)?;
+    // The model shares its weights with the var map so updating the biases in place changes
+    // the model outputs.
+    for proj in ["q_proj", "k_proj", "v_proj"] {
+        set_biases(proj, 0.5)?;
+        let logits_with_bias = forward(&mut model)?;
+        assert!(
+            !test_utils::allclose(&logits, &logits_with_bias, 0., 1e-4)?,
+            "{proj}"
+        );
+        set_biases(proj, 0.)?;
+        let logits_without_bias = forward(&mut model)?;
+        test_utils::assert_close(&logits, &logits_without_bias, 0., 0.);
+    }
+    Ok(())
+}
+
+#[test]
+fn quantized_qwen2() -> Result<()> {
+    let dev = &Device::Cpu;
+    for tie_word_embeddings in [true, false] {
+        let cfg = tiny_config(tie_word_embeddings);
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+        let mut model = qwen2::ModelForCausalLM::new(&cfg, vb)?;
+        let vb = quantized_qwen2::VarBuilder::from_gguf_buffer(&varmap_to_gguf(&varmap)?, dev)?;
+        let mut qmodel = quantized_qwen2::Model::new(&cfg, vb)?;
+
+        for (input_ids, seqlen_offset) in [(vec![1u32, 5, 7, 2], 0), (vec![3u32], 4)] {
+            let input_ids = Tensor::new(input_ids, dev)?.unsqueeze(0)?;
+            let logits = model.forward(&input_ids, seqlen_offset)?;
+            let qlogits = qmodel.forward(&input_ids, seqlen_offset)?;
+            assert_eq!(qlogits.dims(), [1, 1, 32]);
+            test_utils::assert_close(&logits, &qlogits, 0., 1e-4);
+        }
+    }
+    Ok(())
+}
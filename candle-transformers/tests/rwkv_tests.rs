@@ -0,0 +1,114 @@
+mod common;
+
+use candle::{test_utils, DType, Device, Result, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::{quantized_rwkv_v5, quantized_rwkv_v6, rwkv_v5, rwkv_v6};
+use candle_transformers::quantized_var_builder::VarBuilder as QVarBuilder;
+use common::varmap_to_gguf;
+use rwkv_v5::{Config, State};
+
+const VOCAB_SIZE: usize = 32;
+
+fn tiny_config() -> Config {
+    Config {
+        vocab_size: VOCAB_SIZE,
+        hidden_size: 16,
+        num_hidden_layers: 2,
+        attention_hidden_size: 16,
+        num_attention_heads: 4,
+        head_size: 4,
+        intermediate_size: Some(32),
+        layer_norm_epsilon: 1e-5,
+        rescale_every: 6,
+    }
+}
+
+// The var map is zero initialized for most of the weights, use some random values instead.
+fn random_var_map<F: Fn(VarBuilder) -> Result<()>>(f: F) -> Result<VarMap> {
+    let varmap = VarMap::new();
+    f(VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu))?;
+    for var in varmap.all_vars() {
+        var.set(&var.randn_like(0., 0.5)?)?
+    }
+    Ok(varmap)
+}
+
+// Checks that processing the whole sequence in one call, token by token, or in two chunks
+// results in the same logits and states.
+fn check_recurrence<F>(forward: F) -> Result<()>
+where
+    F: Fn(&Tensor, &mut State) -> Result<Tensor>,
+{
+    let dev = &Device::Cpu;
+    let cfg = tiny_config();
+    let tokens = Tensor::new(&[[1u32, 5, 7, 2, 9], [3, 4, 8, 0, 6]], dev)?;
+    let seq_len = tokens.dim(1)?;
+
+    let mut state = State::new(2, &cfg, dev)?;
+    let logits = forward(&tokens, &mut state)?;
+    assert_eq!(logits.dims(), [2, seq_len, VOCAB_SIZE]);
+    assert_eq!(state.pos, seq_len);
+
+    let mut rec_state = State::new(2, &cfg, dev)?;
+    let mut rec_logits = vec![];
+    for idx in 0..seq_len {
+        rec_logits.push(forward(&tokens.narrow(1, idx, 1)?, &mut rec_state)?);
+    }
+    let rec_logits = Tensor::cat(&rec_logits, 1)?;
+    test_utils::assert_close(&logits, &rec_logits, 0., 1e-4);
+    assert_eq!(rec_state.pos, seq_len);
+    for (l, r) in state.per_layer.iter().zip(rec_state.per_layer.iter()) {
+        test_utils::assert_close(&l.linear_attention, &r.linear_attention, 0., 1e-4);
+        test_utils::assert_close(&l.extract_key_value, &r.extract_key_value, 0., 1e-4);
+        test_utils::assert_close(&l.feed_forward, &r.feed_forward, 0., 1e-4);
+    }
+
+    // The state carries over between calls and can be stored to resume from it later.
+    let mut chunk_state = State::new(2, &cfg, dev)?;
+    forward(&tokens.narrow(1, 0, 3)?, &mut chunk_state)?;
+    let saved_state = chunk_state.clone();
+    let chunk_logits = forward(&tokens.narrow(1, 3, 2)?, &mut chunk_state)?;
+    test_utils::assert_close(&logits.narrow(1, 3, 2)?, &chunk_logits, 0., 1e-4);
+    let mut saved_state = saved_state;
+    let resumed_logits = forward(&tokens.narrow(1, 3, 2)?, &mut saved_state)?;
+    test_utils::assert_close(&chunk_logits, &resumed_logits, 0., 0.);
+
+    // Using a fresh state for the second chunk gives different results.
+    let mut fresh_state = State::new(2, &cfg, dev)?;
+    let fresh_logits = forward(&tokens.narrow(1, 3, 2)?, &mut fresh_state)?;
+    assert!(!test_utils::allclose(
+        &chunk_logits,
+        &fresh_logits,
+        0.,
+        1e-3
+    )?);
+    Ok(())
+}
+
+#[test]
+fn rwkv_v5_recurrence() -> Result<()> {
+    let cfg = tiny_config();
+    let varmap = random_var_map(|vb| rwkv_v5::Model::new(&cfg, vb).map(|_| ()))?;
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    let model = rwkv_v5::Model::new(&cfg, vb)?;
+    check_recurrence(|xs, state| model.forward(xs, state))?;
+
+    let vb = QVarBuilder::from_gguf_buffer(&varmap_to_gguf(&varmap)?, &Device::Cpu)?;
+    let qmodel = quantized_rwkv_v5::Model::new(&cfg, vb)?;
+    check_recurrence(|xs, state| qmodel.forward(xs, state))?;
+    Ok(())
+}
+
+#[test]
+fn rwkv_v6_recurrence() -> Result<()> {
+    let cfg = tiny_config();
+    let varmap = random_var_map(|vb| rwkv_v6::Model::new(&cfg, vb).map(|_| ()))?;
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    let model = rwkv_v6::Model::new(&cfg, vb)?;
+    check_recurrence(|xs, state| model.forward(xs, state))?;
+
+    let vb = QVarBuilder::from_gguf_buffer(&varmap_to_gguf(&varmap)?, &Device::Cpu)?;
+    let qmodel = quantized_rwkv_v6::Model::new(&cfg, vb)?;
+    check_recurrence(|xs, state| qmodel.forward(xs, state))?;
+    Ok(())
+}
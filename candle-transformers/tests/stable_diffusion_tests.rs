@@ -0,0 +1,435 @@
+use candle::{Device, Result, Tensor};
+use candle_transformers::models::stable_diffusion::{
+    ddim::DDIMSchedulerConfig, img2img, schedulers::SchedulerConfig,
+};
+
+// A stand-in for the unet, predicting a fraction of its input as the noise.
+fn noise_pred(xs: &Tensor, _timestep: usize) -> Result<Tensor> {
+    xs * 0.1
+}
+
+fn max_abs_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    (a - b)?.abs()?.flatten_all()?.max(0)?.to_vec0()
+}
+
+#[test]
+fn img2img_start_timestep() {
+    assert_eq!(img2img::start_timestep_index(30, 1.0), 0);
+    assert_eq!(img2img::start_timestep_index(30, 0.8), 6);
+    assert_eq!(img2img::start_timestep_index(30, 0.5), 15);
+    assert_eq!(img2img::start_timestep_index(30, 0.0), 30);
+    assert_eq!(img2img::start_timestep_index(10, 0.25), 8);
+}
+
+#[test]
+fn img2img_full_strength() -> Result<()> {
+    let dev = &Device::Cpu;
+    let scheduler = DDIMSchedulerConfig::default().build(10)?;
+    let init_latents = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+    let noise = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+
+    // With a strength of 1, the initial image is discarded and the process is the same as text
+    // to image.
+    let (latents, t_start) = img2img::img2img_latents(&*scheduler, &init_latents, &noise, 1.0)?;
+    assert_eq!(t_start, 0);
+    let txt2img_latents = (&noise * scheduler.init_noise_sigma())?;
+    assert_eq!(max_abs_diff(&latents, &txt2img_latents)?, 0.);
+    let latents = img2img::denoise(&*scheduler, latents, t_start, None, noise_pred)?;
+    let txt2img = img2img::denoise(&*scheduler, txt2img_latents, 0, None, noise_pred)?;
+    assert_eq!(max_abs_diff(&latents, &txt2img)?, 0.);
+
+    // With a lower strength, the initial latents are noised at the starting timestep and only
+    // the remaining timesteps are run.
+    let (latents, t_start) = img2img::img2img_latents(&*scheduler, &init_latents, &noise, 0.3)?;
+    assert_eq!(t_start, 7);
+    let timestep = scheduler.timesteps()[7];
+    let expected = scheduler.add_noise(&init_latents, noise.clone(), timestep)?;
+    assert_eq!(max_abs_diff(&latents, &expected)?, 0.);
+    let mut timesteps = vec![];
+    img2img::denoise(&*scheduler, latents, t_start, None, |xs, t| {
+        timesteps.push(t);
+        noise_pred(xs, t)
+    })?;
+    assert_eq!(timesteps, &scheduler.timesteps()[7..]);
+
+    // With a strength of 0, the initial latents are returned unchanged.
+    let (latents, t_start) = img2img::img2img_latents(&*scheduler, &init_latents, &noise, 0.0)?;
+    assert_eq!(t_start, 10);
+    assert_eq!(max_abs_diff(&latents, &init_latents)?, 0.);
+    assert!(img2img::img2img_latents(&*scheduler, &init_latents, &noise, 1.5).is_err());
+    Ok(())
+}
+
+#[test]
+fn inpaint_preserves_unmasked_latents() -> Result<()> {
+    let dev = &Device::Cpu;
+    let scheduler = DDIMSchedulerConfig::default().build(10)?;
+    let init_latents = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+    let noise = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+
+    // Generate the right half of a 64x64 image.
+    let mask = Tensor::cat(
+        &[
+            Tensor::zeros((1, 1, 64, 32), candle::DType::F32, dev)?,
+            Tensor::ones((1, 1, 64, 32), candle::DType::F32, dev)?,
+        ],
+        3,
+    )?;
+    let mask = img2img::latent_mask(&mask, 8, 8)?;
+    assert_eq!(mask.dims(), [1, 1, 8, 8]);
+    assert_eq!(mask.sum_all()?.to_vec0::<f32>()?, 32.);
+
+    let inpaint = img2img::Inpaint::new(mask, init_latents.clone(), noise.clone())?;
+    let (latents, t_start) = img2img::img2img_latents(&*scheduler, &init_latents, &noise, 1.0)?;
+    let latents = img2img::denoise(&*scheduler, latents, t_start, Some(&inpaint), noise_pred)?;
+    let left = |xs: &Tensor| xs.narrow(3, 0, 4);
+    let right = |xs: &Tensor| xs.narrow(3, 4, 4);
+    assert_eq!(max_abs_diff(&left(&latents)?, &left(&init_latents)?)?, 0.);
+    assert!(max_abs_diff(&right(&latents)?, &right(&init_latents)?)? > 0.1);
+
+    // As the fake model works pixel-wise, the masked region is the same as without inpainting.
+    let txt2img_latents = (&noise * scheduler.init_noise_sigma())?;
+    let txt2img = img2img::denoise(&*scheduler, txt2img_latents, 0, None, noise_pred)?;
+    assert_eq!(max_abs_diff(&right(&latents)?, &right(&txt2img)?)?, 0.);
+
+    let bad_mask = Tensor::ones((1, 1, 4, 4), candle::DType::F32, dev)?;
+    assert!(img2img::Inpaint::new(bad_mask, init_latents, noise).is_err());
+    Ok(())
+}
+
+#[test]
+fn dpm_solver_multistep() -> Result<()> {
+    use candle_transformers::models::stable_diffusion::dpm_solver_multistep::{
+        DPMSolverMultistepScheduler, DPMSolverMultistepSchedulerConfig,
+    };
+    use candle_transformers::models::stable_diffusion::schedulers::{Scheduler, TimestepSpacing};
+
+    let dev = &Device::Cpu;
+    let config = DPMSolverMultistepSchedulerConfig::default();
+    let scheduler = DPMSolverMultistepScheduler::new(10, config)?;
+    assert_eq!(
+        scheduler.timesteps(),
+        [901, 811, 721, 631, 541, 451, 361, 271, 181, 91]
+    );
+    let config = DPMSolverMultistepSchedulerConfig {
+        timestep_spacing: TimestepSpacing::Trailing,
+        ..config
+    };
+    let scheduler = DPMSolverMultistepScheduler::new(10, config)?;
+    assert_eq!(
+        scheduler.timesteps(),
+        [999, 899, 799, 699, 599, 499, 399, 299, 199, 99]
+    );
+    let config = DPMSolverMultistepSchedulerConfig {
+        timestep_spacing: TimestepSpacing::Linspace,
+        ..config
+    };
+    let scheduler = DPMSolverMultistepScheduler::new(10, config)?;
+    assert_eq!(
+        scheduler.timesteps(),
+        [999, 899, 799, 699, 599, 500, 400, 300, 200, 100]
+    );
+    // The sigma values for the stable diffusion beta schedule, 14.6146 is the well known maximum
+    // sigma, the final sigma is zero.
+    let sigmas = scheduler.sigmas();
+    assert_eq!(sigmas.len(), 11);
+    for (s, e) in sigmas[..3].iter().zip([14.614641, 8.302803, 5.087763]) {
+        assert!((s - e).abs() < 1e-4, "{sigmas:?}");
+    }
+    assert_eq!(sigmas[10], 0.);
+
+    // A first order step followed by a second order one.
+    let timesteps = scheduler.timesteps().to_vec();
+    let sample = Tensor::ones((1, 4, 2, 2), candle::DType::F32, dev)?;
+    let eps = Tensor::full(0.5f32, (1, 4, 2, 2), dev)?;
+    let sample = scheduler.step(&eps, timesteps[0], &sample)?;
+    let vs = sample.flatten_all()?.to_vec1::<f32>()?;
+    assert!(vs.iter().all(|v| (v - 1.374287).abs() < 1e-4), "{vs:?}");
+    let eps = Tensor::full(0.25f32, (1, 4, 2, 2), dev)?;
+    let sample = scheduler.step(&eps, timesteps[1], &sample)?;
+    let vs = sample.flatten_all()?.to_vec1::<f32>()?;
+    assert!(vs.iter().all(|v| (v - 2.128_64).abs() < 1e-4), "{vs:?}");
+
+    // The last step returns the predicted original sample.
+    let sample = Tensor::full(0.3f32, (1, 4, 2, 2), dev)?;
+    let prev = scheduler.step(&eps, timesteps[9], &sample)?;
+    let (alpha, sigma) = {
+        let s = sigmas[9];
+        let alpha = 1. / (s * s + 1.).sqrt();
+        (alpha, s * alpha)
+    };
+    let expected = ((0.3 - sigma * 0.25) / alpha) as f32;
+    let vs = prev.flatten_all()?.to_vec1::<f32>()?;
+    assert!(vs.iter().all(|v| (v - expected).abs() < 1e-5), "{vs:?}");
+    Ok(())
+}
+
+#[test]
+fn euler_ancestral_sigmas() -> Result<()> {
+    use candle_transformers::models::stable_diffusion::euler_ancestral_discrete::{
+        EulerAncestralDiscreteScheduler, EulerAncestralDiscreteSchedulerConfig,
+    };
+    use candle_transformers::models::stable_diffusion::schedulers::{Scheduler, TimestepSpacing};
+
+    let config = EulerAncestralDiscreteSchedulerConfig {
+        timestep_spacing: TimestepSpacing::Linspace,
+        ..Default::default()
+    };
+    let scheduler = EulerAncestralDiscreteScheduler::new(10, config)?;
+    assert_eq!(scheduler.timesteps()[0], 999);
+    assert!((scheduler.init_noise_sigma() - 14.614641).abs() < 1e-4);
+    Ok(())
+}
+
+#[test]
+fn controlnet_residuals() -> Result<()> {
+    use candle::DType;
+    use candle_transformers::models::stable_diffusion::controlnet::{ControlNet, ControlNetConfig};
+    use candle_transformers::models::stable_diffusion::unet_2d::{
+        BlockConfig, UNet2DConditionModel, UNet2DConditionModelConfig,
+    };
+
+    let dev = &Device::Cpu;
+    let unet_config = UNet2DConditionModelConfig {
+        blocks: vec![
+            BlockConfig {
+                out_channels: 32,
+                use_cross_attn: Some(1),
+                attention_head_dim: 8,
+            },
+            BlockConfig {
+                out_channels: 64,
+                use_cross_attn: None,
+                attention_head_dim: 8,
+            },
+        ],
+        layers_per_block: 1,
+        cross_attention_dim: 16,
+        ..Default::default()
+    };
+    let config = ControlNetConfig {
+        conditioning_channels: 3,
+        conditioning_embedding_out_channels: vec![16, 32],
+    };
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let unet = UNet2DConditionModel::new(vb.pp("unet"), 4, 4, false, unet_config.clone())?;
+    let controlnet = ControlNet::new(vb.pp("controlnet"), 4, false, &unet_config, &config)?;
+
+    let latents = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+    let text_embeddings = Tensor::randn(0f32, 1., (1, 5, 16), dev)?;
+    // The conditioning embedding downsamples the image once with this config.
+    let cond = Tensor::rand(0f32, 1., (1, 3, 16, 16), dev)?;
+    let (down_res, mid_res) = controlnet.forward(&latents, 10., &text_embeddings, &cond, 1.)?;
+    // One residual for conv_in, one per resnet and one for the downsampler of the first block.
+    let shapes = down_res
+        .iter()
+        .map(|t| t.dims().to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        shapes,
+        [[1, 32, 8, 8], [1, 32, 8, 8], [1, 32, 4, 4], [1, 64, 4, 4]]
+    );
+    assert_eq!(mid_res.dims(), [1, 64, 4, 4]);
+
+    let ys = unet.forward(&latents, 10., &text_embeddings)?;
+    let zeros = down_res
+        .iter()
+        .map(|t| t.zeros_like())
+        .collect::<Result<Vec<_>>>()?;
+    let ys_zeros = unet.forward_with_additional_residuals(
+        &latents,
+        10.,
+        &text_embeddings,
+        Some(&zeros),
+        Some(&mid_res.zeros_like()?),
+    )?;
+    assert_eq!(max_abs_diff(&ys, &ys_zeros)?, 0.);
+    let ys_control = unet.forward_with_additional_residuals(
+        &latents,
+        10.,
+        &text_embeddings,
+        Some(&down_res),
+        Some(&mid_res),
+    )?;
+    assert_eq!(ys_control.dims(), ys.dims());
+    assert!(max_abs_diff(&ys, &ys_control)? > 1e-4);
+
+    // The number of residuals has to match the number of skip connections.
+    let res = unet.forward_with_additional_residuals(
+        &latents,
+        10.,
+        &text_embeddings,
+        Some(&down_res[1..]),
+        Some(&mid_res),
+    );
+    assert!(res.is_err());
+    Ok(())
+}
+
+#[test]
+fn prompt_weights_parsing() {
+    use candle_transformers::models::stable_diffusion::prompt_weighting::parse_prompt_weights;
+
+    let chunks = |p: &str| {
+        parse_prompt_weights(p)
+            .into_iter()
+            .map(|(t, w)| (t, (w * 1000.).round() / 1000.))
+            .collect::<Vec<_>>()
+    };
+    let c = |t: &str, w: f64| (t.to_string(), w);
+    assert_eq!(chunks("a cat"), [c("a cat", 1.)]);
+    assert_eq!(chunks(""), [c("", 1.)]);
+    assert_eq!(chunks("a (cat)"), [c("a ", 1.), c("cat", 1.1)]);
+    assert_eq!(chunks("a ((cat))"), [c("a ", 1.), c("cat", 1.21)]);
+    assert_eq!(chunks("a [cat]"), [c("a ", 1.), c("cat", 0.909)]);
+    assert_eq!(
+        chunks("(a (cat:2) dog:0.5)"),
+        [c("a ", 0.5), c("cat", 1.), c(" dog", 0.5)]
+    );
+    assert_eq!(
+        chunks("a (cat: 1.5 ) dog"),
+        [c("a ", 1.), c("cat", 1.5), c(" dog", 1.)]
+    );
+    // Unclosed brackets apply to the end of the prompt, escaped brackets are literals.
+    assert_eq!(chunks("a (cat"), [c("a ", 1.), c("cat", 1.1)]);
+    assert_eq!(chunks(r"a \(cat\) 2:1"), [c("a (cat) 2:1", 1.)]);
+    assert_eq!(chunks("a cat) dog]"), [c("a cat) dog]", 1.)]);
+}
+
+#[test]
+fn prompt_weights_embeddings() -> Result<()> {
+    use candle_transformers::models::stable_diffusion::prompt_weighting::{
+        apply_prompt_weights, tokenize_weighted,
+    };
+
+    let dev = &Device::Cpu;
+    let (tokens, weights) = tokenize_weighted("a (red:1.5) car", |text| {
+        Ok(text.split_whitespace().map(|w| w.len() as u32).collect())
+    })?;
+    assert_eq!(tokens, [1, 3, 3]);
+    assert_eq!(weights, [1.0, 1.5, 1.0]);
+
+    // Positive values so that the sums used to recover the scale are far from zero.
+    let xs = Tensor::rand(0.5f32, 1.5, (2, 6, 8), dev)?;
+    // A weight of 1 leaves the embeddings unchanged.
+    let ys = apply_prompt_weights(&xs, &[1.0, 1.0, 1.0])?;
+    assert_eq!(max_abs_diff(&xs, &ys)?, 0.);
+
+    let norm = |t: &Tensor| -> Result<Vec<f32>> { t.sqr()?.sum((1, 2))?.sqrt()?.to_vec1() };
+    let ys = apply_prompt_weights(&xs, &[1.0, 2.0])?;
+    // The overall magnitude is preserved.
+    let (n_xs, n_ys) = (norm(&xs)?, norm(&ys)?);
+    for (n_xs, n_ys) in n_xs.iter().zip(n_ys.iter()) {
+        assert!((n_xs - n_ys).abs() < 1e-4 * n_xs, "{n_xs} {n_ys}");
+    }
+    // The tokens with a weight of 1 are only rescaled, the emphasized token moves away from the
+    // mean: ys[1] = s * (xs[1] + (w - 1) * (xs[1] - mean)).
+    let scale = (ys.narrow(1, 0, 1)?.sum((1, 2))? / xs.narrow(1, 0, 1)?.sum((1, 2))?)?;
+    let scale = scale.reshape((2, 1, 1))?;
+    let unweighted = xs.narrow(1, 2, 4)?.broadcast_mul(&scale)?;
+    assert!(max_abs_diff(&unweighted, &ys.narrow(1, 2, 4)?)? < 1e-4);
+    let x1 = xs.narrow(1, 1, 1)?;
+    let expected = (&x1 + x1.broadcast_sub(&xs.mean_keepdim(1)?)?)?.broadcast_mul(&scale)?;
+    assert!(max_abs_diff(&expected, &ys.narrow(1, 1, 1)?)? < 1e-4);
+
+    assert!(apply_prompt_weights(&xs, &[1.0; 7]).is_err());
+    Ok(())
+}
+
+#[test]
+fn sdxl_conditioning() -> Result<()> {
+    use candle::DType;
+    use candle_transformers::models::stable_diffusion::{
+        clip,
+        sdxl::{add_time_ids, SdxlTextEncoder},
+        unet_2d::UNet2DConditionModelConfig,
+        unet_2d::{AdditionEmbedConfig, BlockConfig, UNet2DConditionModel},
+    };
+
+    let dev = &Device::Cpu;
+    let clip_config = |embed_dim, projection_dim| clip::Config {
+        vocab_size: 100,
+        embed_dim,
+        activation: clip::Activation::QuickGelu,
+        intermediate_size: 2 * embed_dim,
+        max_position_embeddings: 8,
+        pad_with: Some("!".to_string()),
+        num_hidden_layers: 2,
+        num_attention_heads: 2,
+        projection_dim,
+    };
+    let (config, config2) = (clip_config(8, 8), clip_config(16, 12));
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let encoder = SdxlTextEncoder::new(vb.pp("te"), vb.pp("te2"), &config, &config2)?;
+    assert!(varmap
+        .data()
+        .lock()
+        .unwrap()
+        .contains_key("te2.text_projection.weight"));
+    let tokens = Tensor::new(
+        &[[49u32, 3, 7, 99, 0, 0, 0, 0], [49, 5, 99, 0, 0, 0, 0, 0]],
+        dev,
+    )?;
+    let (embeds, pooled) = encoder.encode(&tokens, &tokens)?;
+    // The token embeddings of both encoders are concatenated.
+    assert_eq!(embeds.dims(), [2, 8, 8 + 16]);
+    assert_eq!(pooled.dims(), [2, 12]);
+
+    let time_ids = add_time_ids((1024, 768), (0, 16), (512, 384), dev)?;
+    assert_eq!(time_ids.dims(), [1, 6]);
+    assert_eq!(
+        time_ids.to_vec2::<f32>()?,
+        [[1024., 768., 0., 16., 512., 384.]]
+    );
+
+    // Each time id is embedded with 4 channels, 12 + 6 * 4 = 36.
+    let unet_config = UNet2DConditionModelConfig {
+        blocks: vec![
+            BlockConfig {
+                out_channels: 32,
+                use_cross_attn: None,
+                attention_head_dim: 8,
+            },
+            BlockConfig {
+                out_channels: 32,
+                use_cross_attn: Some(1),
+                attention_head_dim: 8,
+            },
+        ],
+        layers_per_block: 1,
+        cross_attention_dim: 24,
+        use_linear_projection: true,
+        addition_embed: Some(AdditionEmbedConfig {
+            addition_time_embed_dim: 4,
+            projection_class_embeddings_input_dim: 36,
+        }),
+        ..Default::default()
+    };
+    let unet = UNet2DConditionModel::new(vb.pp("unet"), 4, 4, false, unet_config)?;
+    let latents = Tensor::randn(0f32, 1., (2, 4, 8, 8), dev)?;
+    let time_ids = time_ids.repeat((2, 1))?;
+    let ys = unet.forward_with_added_cond(
+        &latents,
+        10.,
+        &embeds,
+        Some((&pooled, &time_ids)),
+        None,
+        None,
+    )?;
+    assert_eq!(ys.dims(), [2, 4, 8, 8]);
+    // The conditioning changes the prediction and is required by models using it.
+    let other_ids = add_time_ids((512, 512), (0, 0), (512, 512), dev)?.repeat((2, 1))?;
+    let ys2 = unet.forward_with_added_cond(
+        &latents,
+        10.,
+        &embeds,
+        Some((&pooled, &other_ids)),
+        None,
+        None,
+    )?;
+    assert!(max_abs_diff(&ys, &ys2)? > 0.);
+    assert!(unet.forward(&latents, 10., &embeds).is_err());
+    Ok(())
+}
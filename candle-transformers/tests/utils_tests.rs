@@ -0,0 +1,64 @@
+use candle::{DType, Device, Result};
+use candle_transformers::utils::sliding_window_causal_mask;
+
+// Builds the mask from a string representation, `x` for allowed positions and `.` for masked
+// positions.
+fn reference_mask(rows: &[&str]) -> Vec<Vec<f32>> {
+    rows.iter()
+        .map(|row| {
+            row.chars()
+                .map(|c| if c == 'x' { 0. } else { f32::NEG_INFINITY })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn sliding_window_mask() -> Result<()> {
+    let dev = &Device::Cpu;
+    let mask = sliding_window_causal_mask(5, 0, None, DType::F32, dev)?;
+    let expected = reference_mask(&["x....", "xx...", "xxx..", "xxxx.", "xxxxx"]);
+    assert_eq!(mask.to_vec2::<f32>()?, expected);
+
+    let mask = sliding_window_causal_mask(5, 0, Some(2), DType::F32, dev)?;
+    let expected = reference_mask(&["x....", "xx...", "xxx..", ".xxx.", "..xxx"]);
+    assert_eq!(mask.to_vec2::<f32>()?, expected);
+
+    // With a kv cache of 3 tokens, the queries are at positions 3 and 4.
+    let mask = sliding_window_causal_mask(2, 3, Some(2), DType::F32, dev)?;
+    let expected = reference_mask(&[".xxx.", "..xxx"]);
+    assert_eq!(mask.to_vec2::<f32>()?, expected);
+    let mask = sliding_window_causal_mask(2, 3, None, DType::F32, dev)?;
+    let expected = reference_mask(&["xxxx.", "xxxxx"]);
+    assert_eq!(mask.to_vec2::<f32>()?, expected);
+
+    // Single token decoding step.
+    let mask = sliding_window_causal_mask(1, 6, Some(3), DType::F16, dev)?;
+    let expected = reference_mask(&["...xxxx"]);
+    assert_eq!(mask.to_dtype(DType::F32)?.to_vec2::<f32>()?, expected);
+    Ok(())
+}
+
+#[test]
+fn padded_mask() -> Result<()> {
+    use candle::Tensor;
+    use candle_transformers::utils::padded_causal_mask;
+
+    let dev = &Device::Cpu;
+    // The first sequence has two padding tokens, the second one has none.
+    let attention_mask = Tensor::new(&[[0u8, 0, 1, 1], [1, 1, 1, 1]], dev)?;
+    let mask = padded_causal_mask(&attention_mask, 4, DType::F32)?;
+    assert_eq!(mask.dims(), [2, 1, 4, 4]);
+    let expected = [
+        reference_mask(&["x...", ".x..", "..x.", "..xx"]),
+        reference_mask(&["x...", "xx..", "xxx.", "xxxx"]),
+    ];
+    assert_eq!(mask.squeeze(1)?.to_vec3::<f32>()?, expected);
+
+    // Decoding step after the first sequence has finished and been padded.
+    let attention_mask = Tensor::new(&[[0u8, 0, 1, 1, 0], [1, 1, 1, 1, 1]], dev)?;
+    let mask = padded_causal_mask(&attention_mask, 1, DType::F32)?;
+    let expected = [reference_mask(&["..xxx"]), reference_mask(&["xxxxx"])];
+    assert_eq!(mask.squeeze(1)?.to_vec3::<f32>()?, expected);
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use candle::{DType, Device, Result, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+use candle_transformers::models::whisper::{self as m, timestamps};
+
+fn tiny_config() -> m::Config {
+    m::Config {
+        num_mel_bins: 4,
+        max_source_positions: 8,
+        d_model: 8,
+        encoder_attention_heads: 2,
+        encoder_layers: 1,
+        vocab_size: 16,
+        max_target_positions: 8,
+        decoder_attention_heads: 2,
+        decoder_layers: 2,
+        suppress_tokens: vec![],
+    }
+}
+
+#[test]
+fn whisper_dtw() -> Result<()> {
+    let dev = &Device::Cpu;
+    // The cheapest path goes through the zeros: down the first column, then along the last row.
+    let cost = Tensor::new(
+        &[[0f32, 9., 9., 9.], [0., 9., 9., 9.], [0., 0., 0., 0.]],
+        dev,
+    )?;
+    let (text_indices, time_indices) = timestamps::dtw(&cost)?;
+    assert_eq!(text_indices, [0, 1, 2, 2, 2, 2]);
+    assert_eq!(time_indices, [0, 0, 0, 1, 2, 3]);
+
+    let xs = Tensor::new(&[[1f32, 9., 2., 3., 8., 4.]], dev)?;
+    let ys = timestamps::median_filter(&xs, 3)?;
+    assert_eq!(ys.to_vec2::<f32>()?, [[9., 2., 3., 3., 4., 8.]]);
+    Ok(())
+}
+
+#[test]
+fn whisper_word_timings() -> Result<()> {
+    let dev = &Device::Cpu;
+    // Synthetic cross-attention scores: for the alignment head, the decoder position k attends
+    // to the audio frames 4k..4k+4, the other heads attend to random frames.
+    let (n_rows, frames_per_row) = (7, 4);
+    let n_frames = n_rows * frames_per_row;
+    let cfg = tiny_config();
+    let mut diag = vec![0f32; n_rows * n_frames];
+    for row in 0..n_rows {
+        for frame in row * frames_per_row..(row + 1) * frames_per_row {
+            diag[row * n_frames + frame] = 10.
+        }
+    }
+    let diag = Tensor::from_vec(diag, (1, 1, n_rows, n_frames), dev)?;
+    let noise = Tensor::randn(0f32, 1., (1, 1, n_rows, n_frames), dev)?;
+    let head_layer = Tensor::cat(&[&diag, &noise], 1)?;
+    let cross_attentions = [
+        Tensor::randn(0f32, 1., (1, 2, n_rows, n_frames), dev)?,
+        head_layer,
+    ];
+    // The audio is padded, only the first half of the encoder frames are actual audio.
+    let cross_attentions = cross_attentions
+        .iter()
+        .map(|qk| Tensor::cat(&[qk, &qk.zeros_like()?], 3))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(timestamps::default_alignment_heads(&cfg), [(1, 0), (1, 1)]);
+    let matrix = timestamps::alignment_matrix(&cross_attentions, &[(1, 0)], 2 * n_frames, 1)?;
+    assert_eq!(matrix.dims(), [n_rows, n_frames]);
+
+    let words = vec![
+        (" the".to_string(), vec![1]),
+        (" quick".to_string(), vec![2, 3]),
+        (" brown".to_string(), vec![4]),
+        (" fox".to_string(), vec![5, 6]),
+    ];
+    let timings = timestamps::word_timings(&matrix, &words)?;
+    assert_eq!(timings.len(), words.len());
+    let row_time = frames_per_row as f64 * timestamps::SECONDS_PER_FRAME;
+    let mut boundary = 0;
+    let mut prev_end = 0f64;
+    for (timing, (word, tokens)) in timings.iter().zip(words.iter()) {
+        assert_eq!(&timing.word, word);
+        assert!(
+            timing.start <= timing.end && prev_end <= timing.start,
+            "{timings:?}"
+        );
+        let start = boundary as f64 * row_time;
+        boundary += tokens.len();
+        let end = boundary as f64 * row_time;
+        assert!((timing.start - start).abs() < 1e-6, "{timings:?}");
+        assert!((timing.end - end).abs() < 1e-6, "{timings:?}");
+        prev_end = timing.end
+    }
+    assert!(timestamps::word_timings(&matrix.narrow(0, 1, n_rows - 1)?, &words).is_err());
+    Ok(())
+}
+
+#[test]
+fn whisper_cross_attentions() -> Result<()> {
+    let dev = &Device::Cpu;
+    let cfg = tiny_config();
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let mut model = m::model::Whisper::load(&vb, cfg.clone())?;
+    let mel = Tensor::randn(0f32, 1., (1, cfg.num_mel_bins, 16), dev)?;
+    let audio_features = model.encoder.forward(&mel, true)?;
+    let tokens = Tensor::new(&[[1u32, 2, 3, 4, 5]], dev)?;
+    let (ys, cross_attentions) =
+        model
+            .decoder
+            .forward_with_cross_attentions(&tokens, &audio_features, true)?;
+    assert_eq!(cross_attentions.len(), cfg.decoder_layers);
+    for qk in cross_attentions.iter() {
+        assert_eq!(qk.dims(), [1, cfg.decoder_attention_heads, 5, 8]);
+    }
+    let ys2 = model.decoder.forward(&tokens, &audio_features, false)?;
+    let diff = (ys - ys2)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?
+        .to_scalar::<f32>()?;
+    assert_eq!(diff, 0.);
+
+    let heads = timestamps::default_alignment_heads(&cfg);
+    let matrix = timestamps::alignment_matrix(&cross_attentions, &heads, 16, 3)?;
+    assert_eq!(matrix.dims(), [5, 8]);
+    Ok(())
+}
+
+#[test]
+fn whisper_split_segments() {
+    use m::transcribe::split_segments;
+    let tb = 100;
+    // The last segment is incomplete, the next window starts at its beginning, i.e. at 2s.
+    let tokens = [tb, 5, 6, tb + 50, tb + 50, 7, 8, tb + 100, tb + 100, 9];
+    let (segments, advance) = split_segments(&tokens, tb, 3000);
+    assert_eq!(
+        segments,
+        [
+            (0., 1., vec![tb, 5, 6, tb + 50]),
+            (1., 2., vec![tb + 50, 7, 8, tb + 100])
+        ]
+    );
+    assert_eq!(advance, 200);
+
+    // A single timestamp at the end means that the whole window has been consumed.
+    let tokens = [tb, 5, tb + 50, tb + 50, 6, tb + 150];
+    let (segments, advance) = split_segments(&tokens, tb, 3000);
+    assert_eq!(
+        segments,
+        [
+            (0., 1., vec![tb, 5, tb + 50]),
+            (1., 3., vec![tb + 50, 6, tb + 150])
+        ]
+    );
+    assert_eq!(advance, 3000);
+
+    // Without timestamps the window is a single segment.
+    let (segments, advance) = split_segments(&[5, 6, 7], tb, 1000);
+    assert_eq!(segments, [(0., 10., vec![5, 6, 7])]);
+    assert_eq!(advance, 1000);
+    let (segments, _) = split_segments(&[tb, 5, tb + 150], tb, 1000);
+    assert_eq!(segments, [(0., 3., vec![tb, 5, tb + 150])]);
+
+    // A window that would not move forward is skipped entirely.
+    let (_, advance) = split_segments(&[tb, tb, 5], tb, 3000);
+    assert_eq!(advance, 3000);
+}
+
+#[test]
+fn whisper_transcribe_long() -> Result<()> {
+    use m::transcribe::{transcribe_long, DecodingResult, TranscribeOptions, WindowDecoder};
+    const TB: u32 = 1000;
+
+    // The mel values encode the frame index so that the decoder knows where the window starts.
+    #[derive(Default)]
+    struct Scripted {
+        calls: Vec<(usize, usize, Vec<u32>, f64)>,
+    }
+
+    impl WindowDecoder for Scripted {
+        fn decode(&mut self, mel: &Tensor, prompt: &[u32], t: f64) -> Result<DecodingResult> {
+            let (_, _, frames) = mel.dims3()?;
+            let seek = mel.get(0)?.get(0)?.get(0)?.to_scalar::<f32>()? as usize;
+            self.calls.push((seek, frames, prompt.to_vec(), t));
+            let (tokens, avg_logprob, no_speech_prob, compression_ratio) = match seek {
+                // Only the first two segments are complete, the window is advanced by 20s.
+                0 => (
+                    vec![TB, 10, TB + 500, TB + 500, 11, TB + 1000, TB + 1000, 12],
+                    -0.5,
+                    0.1,
+                    if t == 0. { 3. } else { 1. },
+                ),
+                // No speech, the whole window is skipped.
+                2000 => (vec![13, 14], -2., 0.9, 1.),
+                _ => (vec![TB, 15, TB + 300], -0.5, 0.1, 1.),
+            };
+            Ok(DecodingResult {
+                tokens,
+                avg_logprob,
+                no_speech_prob,
+                temperature: t,
+                compression_ratio,
+            })
+        }
+    }
+
+    let dev = &Device::Cpu;
+    let cfg = tiny_config();
+    let mel = Tensor::arange(0f32, 7000., dev)?
+        .reshape((1, 1, 7000))?
+        .repeat((1, cfg.num_mel_bins, 1))?;
+    let mut decoder = Scripted::default();
+    let options = TranscribeOptions {
+        max_prompt_len: 16,
+        ..TranscribeOptions::new(TB, &cfg)
+    };
+    let segments = transcribe_long(&mut decoder, &mel, &options)?;
+
+    let calls: Vec<_> = decoder.calls.iter().map(|c| (c.0, c.1, c.3)).collect();
+    assert_eq!(
+        calls,
+        [
+            (0, 3000, 0.),
+            (0, 3000, 0.2),
+            (2000, 3000, 0.),
+            (5000, 2000, 0.)
+        ]
+    );
+    let first_window_tokens = [TB, 10, TB + 500, TB + 500, 11, TB + 1000];
+    assert!(decoder.calls[0].2.is_empty());
+    assert_eq!(decoder.calls[2].2, first_window_tokens);
+    assert_eq!(decoder.calls[3].2, first_window_tokens);
+
+    let segments: Vec<_> = segments
+        .iter()
+        .map(|s| (s.seek, s.start, s.end, s.tokens.clone(), s.temperature))
+        .collect();
+    assert_eq!(
+        segments,
+        [
+            (0, 0., 10., vec![TB, 10, TB + 500], 0.2),
+            (0, 10., 20., vec![TB + 500, 11, TB + 1000], 0.2),
+            (5000, 50., 56., vec![TB, 15, TB + 300], 0.),
+        ]
+    );
+
+    // With the default options for this config, only the last three tokens are kept.
+    let mut decoder = Scripted::default();
+    transcribe_long(&mut decoder, &mel, &TranscribeOptions::new(TB, &cfg))?;
+    assert_eq!(decoder.calls[3].2, [TB + 500, 11, TB + 1000]);
+
+    let options = TranscribeOptions {
+        condition_on_previous_text: false,
+        ..options
+    };
+    let mut decoder = Scripted::default();
+    transcribe_long(&mut decoder, &mel, &options)?;
+    assert!(decoder.calls.iter().all(|c| c.2.is_empty()));
+    Ok(())
+}
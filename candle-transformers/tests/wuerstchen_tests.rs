@@ -0,0 +1,115 @@
+use candle::{DType, Device, Result, Tensor};
+use candle_transformers::models::wuerstchen;
+
+// Runs the prior, decoder and vqgan on tiny randomly initialized models to check that the shapes
+// flow through the whole pipeline.
+#[test]
+fn wuerstchen_pipeline() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let (prior_cin, c_r, clip_embd) = (16, 8, 24);
+    let prior = wuerstchen::prior::WPrior::new(
+        /* c_in */ prior_cin,
+        /* c */ 32,
+        /* c_cond */ clip_embd,
+        c_r,
+        /* depth */ 1,
+        /* nhead */ 2,
+        false,
+        vb.pp("prior"),
+    )?;
+    let decoder_cfg = wuerstchen::diffnext::WDiffNeXtConfig {
+        c_hidden: [8, 16, 16, 16],
+        blocks: [1, 1, 1, 1],
+        nhead: [1, 2, 2, 2],
+        inject_effnet: [false, true, true, true],
+        effnet_embd: prior_cin,
+    };
+    let decoder = wuerstchen::diffnext::WDiffNeXt::new_with_config(
+        /* c_in */ 4,
+        /* c_out */ 4,
+        c_r,
+        /* c_cond */ 16,
+        clip_embd,
+        /* patch_size */ 1,
+        false,
+        &decoder_cfg,
+        vb.pp("decoder"),
+    )?;
+    let vqgan = wuerstchen::paella_vq::PaellaVQ::new(vb.pp("vqgan"))?;
+
+    // The conditional and unconditional text embeddings.
+    let prior_text_embeddings = Tensor::randn(0f32, 1., (2, 5, clip_embd), dev)?;
+    let scheduler = wuerstchen::ddpm::DDPMWScheduler::new(2, Default::default())?;
+    let latents = Tensor::randn(0f32, 1., (1, prior_cin, 3, 3), dev)?;
+    let mut ratios = vec![];
+    let latents = scheduler.denoise(latents, |latents, t| {
+        ratios.push(t);
+        let latents = Tensor::cat(&[latents, latents], 0)?;
+        let ratio = (Tensor::ones(2, DType::F32, dev)? * t)?;
+        let noise_pred = prior.forward(&latents, &ratio, &prior_text_embeddings)?;
+        let noise_pred = noise_pred.chunk(2, 0)?;
+        let (text, uncond) = (&noise_pred[0], &noise_pred[1]);
+        uncond + ((text - uncond)? * 4.0)?
+    })?;
+    assert_eq!(ratios, [1.0, 0.5]);
+    assert_eq!(latents.dims(), [1, prior_cin, 3, 3]);
+    let image_embeddings = scheduler.unscale_latents(&latents)?;
+    let expected = ((&latents * 42.)? - 1.)?;
+    let diff = (image_embeddings.clone() - expected)?
+        .abs()?
+        .flatten_all()?
+        .max(0)?;
+    assert!(diff.to_vec0::<f32>()? < 1e-4);
+
+    let text_embeddings = Tensor::randn(0f32, 1., (1, 5, clip_embd), dev)?;
+    let latents = Tensor::randn(0f32, 1., (1, 4, 8, 8), dev)?;
+    let latents = scheduler.denoise(latents, |latents, t| {
+        let ratio = (Tensor::ones(1, DType::F32, dev)? * t)?;
+        decoder.forward(latents, &ratio, &image_embeddings, Some(&text_embeddings))
+    })?;
+    assert_eq!(latents.dims(), [1, 4, 8, 8]);
+    let image = vqgan.decode(&(&latents * 0.3764)?)?;
+    assert_eq!(image.dims(), [1, 3, 32, 32]);
+    let image = image.flatten_all()?.to_vec1::<f32>()?;
+    assert!(image.iter().all(|v| v.is_finite()));
+    Ok(())
+}
+
+#[test]
+fn paella_vq_encode() -> Result<()> {
+    let dev = &Device::Cpu;
+    let mut varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let vqgan = wuerstchen::paella_vq::PaellaVQ::new(vb)?;
+    let codebook = Tensor::randn(0f32, 1., (8192, 4), dev)?;
+    varmap.set_one("vquantizer.embedding.weight", &codebook)?;
+
+    let image = Tensor::rand(0f32, 1., (1, 3, 16, 16), dev)?;
+    let latents = vqgan.encode_unquantized(&image)?;
+    assert_eq!(latents.dims(), [1, 4, 4, 4]);
+    let quantized = vqgan.encode(&image)?;
+    assert_eq!(quantized.dims(), [1, 4, 4, 4]);
+
+    // Each quantized vector is the closest codebook entry to the unquantized one.
+    let codebook = codebook.to_vec2::<f32>()?;
+    let dist = |u: &[f32], v: &[f32]| u.iter().zip(v).map(|(u, v)| (u - v).powi(2)).sum::<f32>();
+    let latents = latents
+        .permute((0, 2, 3, 1))?
+        .reshape(((), 4))?
+        .to_vec2::<f32>()?;
+    let quantized_v = quantized.permute((0, 2, 3, 1))?.reshape(((), 4))?;
+    for (latent, quantized) in latents.iter().zip(quantized_v.to_vec2::<f32>()?) {
+        let best = codebook
+            .iter()
+            .map(|e| dist(latent, e))
+            .fold(f32::INFINITY, f32::min);
+        assert!(codebook.contains(&quantized));
+        assert!((dist(latent, &quantized) - best).abs() < 1e-4);
+    }
+
+    let reconstruction = vqgan.decode(&quantized)?;
+    assert_eq!(reconstruction.dims(), image.dims());
+    Ok(())
+}